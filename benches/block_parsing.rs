@@ -0,0 +1,104 @@
+//! Parsing a large `getBlock` response: in full through a `Value`, as `RpcClient` does, in full
+//! straight into the block types, and with borrowed data, keeping one account's transactions.
+//!
+//! Run with `cargo bench --bench block_parsing`.
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use solana_data_aggregator::blocks::transactions_touching;
+use solana_sdk::pubkey::Pubkey;
+use solana_transaction_status::UiConfirmedBlock;
+
+use std::{collections::HashSet, hint::black_box};
+
+const FIXTURE: &str = include_str!("../fixtures/rpc/transfer.json");
+
+/// Transactions in the benchmarked block, about as many as a busy mainnet block has.
+const TRANSACTIONS_PER_BLOCK: usize = 2000;
+
+#[derive(Deserialize)]
+struct Response {
+    result: UiConfirmedBlock,
+}
+
+/// A `getBlock` response of copies of the fixture transfer, each between its own pair of
+/// accounts, and the accounts of the first one.
+fn block() -> (Vec<u8>, HashSet<String>) {
+    let exchanges: Vec<Value> = serde_json::from_str(FIXTURE).unwrap();
+    let mut exchange = exchanges
+        .into_iter()
+        .find(|exchange| exchange["method"] == "getTransaction")
+        .unwrap();
+    let mut template = exchange["result"].take();
+    let fields = template.as_object_mut().unwrap();
+    fields.remove("slot");
+    fields.remove("blockTime");
+
+    let mut accounts = HashSet::new();
+    let transactions = (0..TRANSACTIONS_PER_BLOCK)
+        .map(|i| {
+            let mut transaction = template.clone();
+
+            for key in transaction["transaction"]["message"]["accountKeys"]
+                .as_array_mut()
+                .unwrap()
+            {
+                let pubkey = Pubkey::new_unique().to_string();
+                if i == 0 {
+                    accounts.insert(pubkey.clone());
+                }
+                key["pubkey"] = json!(pubkey);
+            }
+
+            transaction
+        })
+        .collect::<Vec<_>>();
+
+    let body = serde_json::to_vec(&json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "result": {
+            "blockHeight": 300000000,
+            "blockTime": 1720000000,
+            "blockhash": "14khGK3iuJX28TsTNCm2M7HHaJN13BuhqBgANFJJtLrx",
+            "parentSlot": 312000041,
+            "previousBlockhash": "4sZ76MsNd8y3WSw2L1nfd3AqLoYxdmC98sERoMRbHV14",
+            "transactions": transactions,
+        },
+    }))
+    .unwrap();
+
+    (body, accounts)
+}
+
+fn block_parsing(c: &mut Criterion) {
+    let (body, accounts) = block();
+
+    let mut group = c.benchmark_group("get_block");
+    group.throughput(Throughput::Bytes(body.len() as u64));
+
+    group.bench_function("value", |b| {
+        b.iter(|| {
+            let mut response: Value = serde_json::from_slice(black_box(&body)).unwrap();
+            serde_json::from_value::<UiConfirmedBlock>(response["result"].take()).unwrap()
+        })
+    });
+
+    group.bench_function("owned", |b| {
+        b.iter(|| {
+            serde_json::from_slice::<Response>(black_box(&body))
+                .unwrap()
+                .result
+        })
+    });
+
+    group.bench_function("borrowed", |b| {
+        b.iter(|| transactions_touching(312000042, black_box(&body), &accounts).unwrap())
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, block_parsing);
+criterion_main!(benches);
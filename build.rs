@@ -0,0 +1,7 @@
+fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // the gRPC service is generated from its protobuf definition; needs `protoc`
+    #[cfg(feature = "grpc")]
+    tonic_build::compile_protos("proto/aggregator.proto")?;
+
+    Ok(())
+}
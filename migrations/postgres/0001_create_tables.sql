@@ -0,0 +1,89 @@
+-- Baseline schema, as previously created at startup. `IF NOT EXISTS` lets it run against
+-- databases created before migrations were introduced.
+
+CREATE TABLE IF NOT EXISTS transactions (
+    id SERIAL PRIMARY KEY,
+    signature VARCHAR NOT NULL,
+    sender VARCHAR NOT NULL,
+    receiver VARCHAR NOT NULL,
+    sol_amount BIGINT NOT NULL,
+    fee BIGINT NOT NULL,
+    timestamp BIGINT NOT NULL,
+    prev_blockhash VARCHAR NOT NULL
+);
+
+-- per-address history looks transactions up by either side
+CREATE INDEX IF NOT EXISTS transactions_sender_id_idx ON transactions (sender, id);
+CREATE INDEX IF NOT EXISTS transactions_receiver_id_idx ON transactions (receiver, id);
+
+CREATE TABLE IF NOT EXISTS fee_burns (
+    signature VARCHAR PRIMARY KEY,
+    epoch BIGINT NOT NULL,
+    base_fee BIGINT NOT NULL,
+    priority_fee BIGINT NOT NULL,
+    burned BIGINT NOT NULL,
+    to_validator BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS zstd_dictionaries (
+    id SERIAL PRIMARY KEY,
+    dictionary BYTEA NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS raw_transactions (
+    signature VARCHAR PRIMARY KEY,
+    dictionary_id INT REFERENCES zstd_dictionaries (id),
+    payload BYTEA NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS balances (
+    id SERIAL PRIMARY KEY,
+    pubkey VARCHAR NOT NULL,
+    lamports BIGINT NOT NULL,
+    token_mint VARCHAR,
+    token_amount BIGINT,
+    recorded_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS balances_pubkey_recorded_at_idx ON balances (pubkey, recorded_at);
+
+CREATE TABLE IF NOT EXISTS account_snapshots (
+    id SERIAL PRIMARY KEY,
+    pubkey VARCHAR NOT NULL,
+    owner VARCHAR NOT NULL,
+    lamports BIGINT NOT NULL,
+    data BYTEA NOT NULL,
+    slot BIGINT NOT NULL,
+    recorded_at BIGINT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS account_snapshots_pubkey_id_idx ON account_snapshots (pubkey, id);
+
+CREATE TABLE IF NOT EXISTS account_layouts (
+    program VARCHAR NOT NULL,
+    name VARCHAR NOT NULL,
+    definition JSONB NOT NULL,
+    PRIMARY KEY (program, name)
+);
+
+CREATE TABLE IF NOT EXISTS transaction_rollups (
+    granularity VARCHAR NOT NULL,
+    bucket BIGINT NOT NULL,
+    address VARCHAR NOT NULL,
+    transaction_count BIGINT NOT NULL,
+    sol_volume BIGINT NOT NULL,
+    fee_sum BIGINT NOT NULL,
+    PRIMARY KEY (granularity, address, bucket)
+);
+
+CREATE TABLE IF NOT EXISTS watermarks (
+    name VARCHAR PRIMARY KEY,
+    last_id BIGINT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS ad_hoc_transactions (
+    pubkey VARCHAR NOT NULL,
+    signature VARCHAR NOT NULL,
+    analyzed_at BIGINT NOT NULL,
+    PRIMARY KEY (pubkey, signature)
+);
@@ -0,0 +1,12 @@
+-- Every poll used to re-insert the latest transactions of each address, so keep only the first
+-- copy of each before making signatures unique.
+DELETE FROM transactions duplicate
+USING transactions original
+WHERE duplicate.signature = original.signature
+    AND duplicate.id > original.id;
+
+CREATE UNIQUE INDEX transactions_signature_idx ON transactions (signature);
+
+-- time range queries and rollups; sender and receiver lookups use the (sender, id) and
+-- (receiver, id) indexes
+CREATE INDEX transactions_timestamp_idx ON transactions (timestamp);
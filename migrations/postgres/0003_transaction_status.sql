@@ -0,0 +1,8 @@
+-- Rows stored before these columns existed get slot 0 and no logs or compute units.
+ALTER TABLE transactions
+    ADD COLUMN slot BIGINT NOT NULL DEFAULT 0,
+    ADD COLUMN error VARCHAR,
+    ADD COLUMN log_messages JSONB NOT NULL DEFAULT '[]',
+    ADD COLUMN compute_units_consumed BIGINT;
+
+CREATE INDEX transactions_slot_idx ON transactions (slot);
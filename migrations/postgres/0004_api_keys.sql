@@ -0,0 +1,11 @@
+-- Only a SHA-256 of each key is stored; the prefix identifies keys in listings.
+CREATE TABLE api_keys (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    prefix VARCHAR NOT NULL,
+    key_hash VARCHAR NOT NULL UNIQUE,
+    -- NULL uses the configured default
+    rate_limit_per_minute INTEGER,
+    created_at BIGINT NOT NULL,
+    revoked_at BIGINT
+);
@@ -0,0 +1,6 @@
+-- Where a warm standby is in each primary's transactions, as the primary's opaque `id` cursor.
+CREATE TABLE replication_cursors (
+    primary_url VARCHAR PRIMARY KEY,
+    cursor VARCHAR NOT NULL,
+    updated_at BIGINT NOT NULL
+);
@@ -0,0 +1,75 @@
+-- Counters kept up to date in the same database transaction as every insert, so statistics are
+-- read rather than aggregated. Address '*' holds the totals over all transactions.
+CREATE TABLE address_stats (
+    address VARCHAR PRIMARY KEY,
+    transaction_count BIGINT NOT NULL,
+    total_sol_amount BIGINT NOT NULL,
+    total_fees BIGINT NOT NULL,
+    first_activity BIGINT,
+    last_activity BIGINT
+);
+
+-- most active addresses overall
+CREATE INDEX address_stats_activity_idx ON address_stats (transaction_count DESC, total_sol_amount DESC);
+
+-- how much each address transacted with each of its counterparties
+CREATE TABLE counterparty_stats (
+    address VARCHAR NOT NULL,
+    counterparty VARCHAR NOT NULL,
+    transaction_count BIGINT NOT NULL,
+    sol_amount BIGINT NOT NULL,
+    PRIMARY KEY (address, counterparty)
+);
+
+-- an account's top counterparties
+CREATE INDEX counterparty_stats_activity_idx
+    ON counterparty_stats (address, transaction_count DESC, sol_amount DESC);
+
+WITH sides AS (
+    SELECT sender AS address, sol_amount, fee, timestamp FROM transactions
+    UNION ALL
+    SELECT receiver, sol_amount, fee, timestamp FROM transactions WHERE receiver <> sender
+    UNION ALL
+    SELECT '*', sol_amount, fee, timestamp FROM transactions
+)
+INSERT INTO address_stats
+    (address, transaction_count, total_sol_amount, total_fees, first_activity, last_activity)
+SELECT address, COUNT(*), SUM(sol_amount), SUM(fee), MIN(timestamp), MAX(timestamp)
+FROM sides
+GROUP BY address;
+
+WITH pairs AS (
+    SELECT sender AS address, receiver AS counterparty, sol_amount FROM transactions
+    UNION ALL
+    SELECT receiver, sender, sol_amount FROM transactions WHERE receiver <> sender
+)
+INSERT INTO counterparty_stats (address, counterparty, transaction_count, sol_amount)
+SELECT address, counterparty, COUNT(*), SUM(sol_amount)
+FROM pairs
+GROUP BY address, counterparty;
+
+-- Rollups are now also updated on insert. Fold in what the periodic refresh hadn't reached yet
+-- and retire its watermark.
+WITH new AS (
+    SELECT sender, receiver, sol_amount, fee, timestamp FROM transactions
+    WHERE id > COALESCE((SELECT last_id FROM watermarks WHERE name = 'transaction_rollups'), 0)
+), sides AS (
+    SELECT sender AS address, sol_amount, fee, timestamp FROM new
+    UNION ALL
+    SELECT receiver, sol_amount, fee, timestamp FROM new WHERE receiver <> sender
+    UNION ALL
+    SELECT '*', sol_amount, fee, timestamp FROM new
+), granularities (granularity, bucket_secs) AS (
+    VALUES ('hour', 3600::BIGINT), ('day', 86400::BIGINT)
+)
+INSERT INTO transaction_rollups
+    (granularity, bucket, address, transaction_count, sol_volume, fee_sum)
+SELECT granularity, timestamp - timestamp % bucket_secs, address, COUNT(*), SUM(sol_amount), SUM(fee)
+FROM sides CROSS JOIN granularities
+GROUP BY 1, 2, 3
+ON CONFLICT (granularity, address, bucket) DO UPDATE SET
+    transaction_count = transaction_rollups.transaction_count + EXCLUDED.transaction_count,
+    sol_volume = transaction_rollups.sol_volume + EXCLUDED.sol_volume,
+    fee_sum = transaction_rollups.fee_sum + EXCLUDED.fee_sum;
+
+DELETE FROM watermarks WHERE name = 'transaction_rollups';
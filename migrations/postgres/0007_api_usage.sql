@@ -0,0 +1,14 @@
+-- What each API key used per calendar month (UTC), for billing. Counts are added in batches, so
+-- the current month lags by up to the configured flush interval.
+CREATE TABLE api_usage (
+    api_key_id INTEGER NOT NULL REFERENCES api_keys (id),
+    -- YYYY-MM
+    month VARCHAR NOT NULL,
+    requests BIGINT NOT NULL DEFAULT 0,
+    rows_returned BIGINT NOT NULL DEFAULT 0,
+    webhook_deliveries BIGINT NOT NULL DEFAULT 0,
+    PRIMARY KEY (api_key_id, month)
+);
+
+-- a month's usage across all keys
+CREATE INDEX api_usage_month_idx ON api_usage (month);
@@ -0,0 +1,15 @@
+-- Boundaries of the epochs seen while the aggregator was running.
+CREATE TABLE epochs (
+    epoch BIGINT PRIMARY KEY,
+    first_slot BIGINT NOT NULL,
+    last_slot BIGINT NOT NULL,
+    -- unix seconds the epoch was first and last seen as the current one
+    first_seen_at BIGINT NOT NULL,
+    last_seen_at BIGINT NOT NULL
+);
+
+-- Filled in by the epoch tracker after insert; `NULL` until then, and for rows without a slot.
+ALTER TABLE transactions ADD COLUMN epoch BIGINT;
+
+-- an epoch's transactions, and the untagged ones still to fill in
+CREATE INDEX transactions_epoch_id_idx ON transactions (epoch, id);
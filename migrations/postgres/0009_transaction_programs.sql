@@ -0,0 +1,6 @@
+-- First watched program each transaction invokes; `NULL` for transactions invoking none, and for
+-- those stored before the program was watched.
+ALTER TABLE transactions ADD COLUMN program VARCHAR;
+
+-- a program's transactions, in insertion order
+CREATE INDEX transactions_program_id_idx ON transactions (program, id);
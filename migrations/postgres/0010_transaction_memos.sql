@@ -0,0 +1,3 @@
+-- Text of each transaction's SPL Memo instructions; `NULL` for transactions without one, and for
+-- those stored before memos were extracted.
+ALTER TABLE transactions ADD COLUMN memo TEXT;
@@ -0,0 +1,5 @@
+-- What each transaction's compute budget instructions set, and the priority fee that follows in
+-- lamports; `NULL` for transactions stored before compute budgets were parsed.
+ALTER TABLE transactions ADD COLUMN compute_unit_limit BIGINT;
+ALTER TABLE transactions ADD COLUMN compute_unit_price BIGINT;
+ALTER TABLE transactions ADD COLUMN priority_fee BIGINT;
@@ -0,0 +1,31 @@
+-- Metaplex metadata of the NFTs (supply 1, decimals 0) moved by stored token transfers.
+CREATE TABLE nfts (
+    mint VARCHAR PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    symbol VARCHAR NOT NULL,
+    uri VARCHAR,
+    -- the collection NFT's mint, and whether the collection's authority verified membership
+    collection VARCHAR,
+    collection_verified BOOLEAN NOT NULL DEFAULT FALSE,
+    -- unix seconds
+    fetched_at BIGINT NOT NULL
+);
+
+-- Token balance changes of stored transactions, one row per token account whose balance changed.
+CREATE TABLE token_transfers (
+    id BIGSERIAL PRIMARY KEY,
+    signature VARCHAR NOT NULL,
+    account_index SMALLINT NOT NULL,
+    mint VARCHAR NOT NULL,
+    -- owner of the token account, if the RPC node reported it
+    owner VARCHAR,
+    -- in the mint's smallest unit, saturated at the BIGINT range
+    change BIGINT NOT NULL,
+    decimals SMALLINT NOT NULL,
+    nft BOOLEAN NOT NULL DEFAULT FALSE,
+    UNIQUE (signature, account_index)
+);
+
+CREATE INDEX token_transfers_mint_id_idx ON token_transfers (mint, id);
+CREATE INDEX token_transfers_owner_id_idx ON token_transfers (owner, id);
+CREATE INDEX token_transfers_nft_id_idx ON token_transfers (id) WHERE nft;
@@ -0,0 +1,9 @@
+-- Human-readable labels and tags of addresses, registered through the API.
+CREATE TABLE labels (
+    pubkey VARCHAR PRIMARY KEY,
+    label VARCHAR NOT NULL,
+    -- lowercase
+    tags VARCHAR[] NOT NULL DEFAULT '{}',
+    -- unix seconds
+    updated_at BIGINT NOT NULL
+);
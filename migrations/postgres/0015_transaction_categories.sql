@@ -0,0 +1,6 @@
+-- Categories from the directory of known addresses (`dex_swap`, `cex_deposit`, ...); empty for
+-- transactions stored before they were categorized.
+ALTER TABLE transactions ADD COLUMN categories JSONB NOT NULL DEFAULT '[]';
+
+-- a category's transactions, through containment queries
+CREATE INDEX transactions_categories_idx ON transactions USING GIN (categories jsonb_path_ops);
@@ -0,0 +1,12 @@
+-- Optional storage modes set up on this database, e.g. `timescale` once `transactions` is a
+-- TimescaleDB hypertable with continuous aggregates for the rollups.
+CREATE TABLE storage_features (
+    feature VARCHAR PRIMARY KEY,
+    enabled_at BIGINT NOT NULL
+);
+
+-- Volume rollups as served: `transaction_rollups` on plain Postgres, replaced by a view over the
+-- continuous aggregates in TimescaleDB mode.
+CREATE VIEW volume_rollups AS
+SELECT granularity, bucket, address, transaction_count, sol_volume, fee_sum
+FROM transaction_rollups;
@@ -0,0 +1,12 @@
+-- Transactions that failed validation, with every rule each broke, for auditing. Only the latest
+-- rejection of a signature is kept.
+CREATE TABLE rejected_transactions (
+    id BIGSERIAL PRIMARY KEY,
+    signature VARCHAR NOT NULL UNIQUE,
+    -- `ValidationError`s, tagged by `rule`
+    reasons JSONB NOT NULL,
+    -- the processed `TransactionData`
+    transaction JSONB NOT NULL,
+    -- unix seconds
+    rejected_at BIGINT NOT NULL
+);
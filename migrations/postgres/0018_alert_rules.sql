@@ -0,0 +1,14 @@
+-- Alert rules on ingested activity, registered through the API.
+CREATE TABLE alert_rules (
+    id SERIAL PRIMARY KEY,
+    name VARCHAR NOT NULL,
+    -- `Condition`, tagged by `kind`
+    condition JSONB NOT NULL,
+    -- `Channel`s, tagged by `type`
+    channels JSONB NOT NULL,
+    -- key the rule was created with, whose usage its webhook deliveries count towards
+    api_key_id INTEGER REFERENCES api_keys (id),
+    -- unix seconds
+    created_at BIGINT NOT NULL,
+    last_fired_at BIGINT
+);
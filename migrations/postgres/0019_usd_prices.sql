@@ -0,0 +1,14 @@
+-- USD prices at block time, from the configured price feed; NULL without one, or for transactions
+-- and transfers that couldn't be priced.
+ALTER TABLE transactions ADD COLUMN sol_usd_price DOUBLE PRECISION;
+ALTER TABLE token_transfers ADD COLUMN usd_price DOUBLE PRECISION;
+
+-- SOL moved, valued at each transaction's price; unpriced transactions count as 0
+ALTER TABLE address_stats ADD COLUMN total_usd_volume DOUBLE PRECISION NOT NULL DEFAULT 0;
+ALTER TABLE transaction_rollups ADD COLUMN usd_volume DOUBLE PRECISION NOT NULL DEFAULT 0;
+
+-- replaced by a view over the continuous aggregates in TimescaleDB mode, which is recreated with
+-- the new column at startup
+CREATE OR REPLACE VIEW volume_rollups AS
+SELECT granularity, bucket, address, transaction_count, sol_volume, fee_sum, usd_volume
+FROM transaction_rollups;
@@ -0,0 +1,8 @@
+-- How final a stored transaction is: 'confirmed' when stored, then 'finalized' once rooted, or
+-- 'dropped' if the fork it landed on was abandoned. Rows stored before tracking began are taken as
+-- finalized; new ones start out confirmed.
+ALTER TABLE transactions ADD COLUMN commitment_status VARCHAR NOT NULL DEFAULT 'finalized';
+ALTER TABLE transactions ALTER COLUMN commitment_status SET DEFAULT 'confirmed';
+
+-- the finality checker only ever looks for the rows still waiting to be finalized
+CREATE INDEX idx_transactions_unfinalized ON transactions (id) WHERE commitment_status = 'confirmed';
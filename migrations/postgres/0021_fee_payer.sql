@@ -0,0 +1,4 @@
+-- The account that paid a transaction's fee, and the SOL transfers its instructions made. Rows
+-- stored before they were recorded have no fee payer and no transfers; their sender paid the fee.
+ALTER TABLE transactions ADD COLUMN fee_payer VARCHAR;
+ALTER TABLE transactions ADD COLUMN transfers JSONB NOT NULL DEFAULT '[]';
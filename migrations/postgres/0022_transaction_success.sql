@@ -0,0 +1,7 @@
+-- Whether a transaction executed successfully, so failed ones can be told apart without parsing
+-- their error. Rows stored before it was recorded succeeded unless they have an error.
+ALTER TABLE transactions ADD COLUMN success BOOLEAN NOT NULL DEFAULT TRUE;
+UPDATE transactions SET success = FALSE WHERE error IS NOT NULL;
+
+-- failed transactions are a small share, listed with `?status=failed`
+CREATE INDEX idx_transactions_failed ON transactions (id) WHERE NOT success;
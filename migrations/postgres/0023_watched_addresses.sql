@@ -0,0 +1,6 @@
+-- Addresses imported through the API, watched alongside the configured ones across restarts.
+CREATE TABLE watched_addresses (
+    pubkey VARCHAR PRIMARY KEY,
+    -- unix seconds
+    added_at BIGINT NOT NULL
+);
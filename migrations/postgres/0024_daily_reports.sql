@@ -0,0 +1,17 @@
+-- Daily activity summaries of the watched addresses, one row per address and UTC day.
+CREATE TABLE daily_reports (
+    -- YYYY-MM-DD
+    date VARCHAR NOT NULL,
+    pubkey VARCHAR NOT NULL,
+    transaction_count BIGINT NOT NULL,
+    -- lamports received from and sent to other addresses
+    inflow BIGINT NOT NULL,
+    outflow BIGINT NOT NULL,
+    -- lamports paid in fees
+    fees BIGINT NOT NULL,
+    -- counterparty summaries, most transactions first
+    top_counterparties JSONB NOT NULL DEFAULT '[]',
+    -- unix seconds
+    generated_at BIGINT NOT NULL,
+    PRIMARY KEY (date, pubkey)
+);
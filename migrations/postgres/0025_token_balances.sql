@@ -0,0 +1,16 @@
+-- SPL token balances of the watched addresses, a row whenever a token account's balance changes.
+CREATE TABLE token_balances (
+    id BIGSERIAL PRIMARY KEY,
+    -- the watched address owning the token account
+    owner VARCHAR NOT NULL,
+    account VARCHAR NOT NULL,
+    mint VARCHAR NOT NULL,
+    -- raw amount, in the mint's smallest unit; 0 once the account is closed
+    amount BIGINT NOT NULL,
+    decimals SMALLINT NOT NULL,
+    -- unix seconds
+    recorded_at BIGINT NOT NULL
+);
+
+CREATE INDEX token_balances_owner_account_idx ON token_balances (owner, account, id);
+CREATE INDEX token_balances_owner_recorded_at_idx ON token_balances (owner, recorded_at);
@@ -0,0 +1,7 @@
+-- Indexes behind GET /search: trigram indexes for case-insensitive substring matches of memos and
+-- labels, and a pattern index for address prefixes.
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX transactions_memo_trgm_idx ON transactions USING GIN (memo gin_trgm_ops);
+CREATE INDEX labels_label_trgm_idx ON labels USING GIN (label gin_trgm_ops);
+CREATE INDEX address_stats_address_prefix_idx ON address_stats (address varchar_pattern_ops);
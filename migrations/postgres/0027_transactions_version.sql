@@ -0,0 +1,7 @@
+-- Version of the stored transactions, bumped whenever they're stored, updated or deleted or a
+-- label changes. The API derives its ETags from it.
+CREATE TABLE transactions_version (
+    version BIGINT NOT NULL
+);
+
+INSERT INTO transactions_version (version) VALUES (0);
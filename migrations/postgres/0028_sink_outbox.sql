@@ -0,0 +1,7 @@
+-- Transactions waiting to be published to Kafka with `exactly_once`, written in the same database
+-- transaction that stores them and deleted once the topic has them.
+CREATE TABLE sink_outbox (
+    id BIGSERIAL PRIMARY KEY,
+    signature VARCHAR NOT NULL,
+    payload BYTEA NOT NULL
+);
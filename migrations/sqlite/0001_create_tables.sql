@@ -0,0 +1,17 @@
+-- Baseline schema, as previously created at startup. `IF NOT EXISTS` lets it run against
+-- databases created before migrations were introduced.
+
+CREATE TABLE IF NOT EXISTS transactions (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    signature TEXT NOT NULL,
+    sender TEXT NOT NULL,
+    receiver TEXT NOT NULL,
+    sol_amount INTEGER NOT NULL,
+    fee INTEGER NOT NULL,
+    timestamp INTEGER NOT NULL,
+    prev_blockhash TEXT NOT NULL
+);
+
+CREATE INDEX IF NOT EXISTS transactions_signature_idx ON transactions (signature);
+CREATE INDEX IF NOT EXISTS transactions_sender_id_idx ON transactions (sender, id);
+CREATE INDEX IF NOT EXISTS transactions_receiver_id_idx ON transactions (receiver, id);
@@ -0,0 +1,10 @@
+-- Every poll used to re-insert the latest transactions of each address, so keep only the first
+-- copy of each before making signatures unique.
+DELETE FROM transactions
+WHERE id NOT IN (SELECT MIN(id) FROM transactions GROUP BY signature);
+
+DROP INDEX IF EXISTS transactions_signature_idx;
+CREATE UNIQUE INDEX transactions_signature_idx ON transactions (signature);
+
+-- sender and receiver lookups use the (sender, id) and (receiver, id) indexes
+CREATE INDEX transactions_timestamp_idx ON transactions (timestamp);
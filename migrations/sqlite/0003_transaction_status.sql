@@ -0,0 +1,7 @@
+-- Rows stored before these columns existed get slot 0 and no logs or compute units.
+ALTER TABLE transactions ADD COLUMN slot INTEGER NOT NULL DEFAULT 0;
+ALTER TABLE transactions ADD COLUMN error TEXT;
+ALTER TABLE transactions ADD COLUMN log_messages TEXT NOT NULL DEFAULT '[]';
+ALTER TABLE transactions ADD COLUMN compute_units_consumed INTEGER;
+
+CREATE INDEX transactions_slot_idx ON transactions (slot);
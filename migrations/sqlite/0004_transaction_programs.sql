@@ -0,0 +1,5 @@
+-- First watched program each transaction invokes; `NULL` for transactions invoking none, and for
+-- those stored before the program was watched.
+ALTER TABLE transactions ADD COLUMN program TEXT;
+
+CREATE INDEX transactions_program_id_idx ON transactions (program, id);
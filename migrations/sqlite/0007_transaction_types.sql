@@ -0,0 +1,7 @@
+-- What each transaction does, by the programs its instructions call; `NULL` for transactions
+-- stored before they were classified.
+ALTER TABLE transactions ADD COLUMN txn_type TEXT
+    CHECK (txn_type IN ('vote', 'stake', 'token', 'transfer', 'other'));
+
+-- a type's transactions, in insertion order
+CREATE INDEX transactions_txn_type_id_idx ON transactions (txn_type, id);
@@ -0,0 +1,3 @@
+-- Categories from the directory of known addresses (`dex_swap`, `cex_deposit`, ...), as a JSON
+-- array; empty for transactions stored before they were categorized.
+ALTER TABLE transactions ADD COLUMN categories TEXT NOT NULL DEFAULT '[]';
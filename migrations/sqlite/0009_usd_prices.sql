@@ -0,0 +1,3 @@
+-- SOL/USD price at the transaction's block time; NULL without a price feed, or if it couldn't be
+-- priced.
+ALTER TABLE transactions ADD COLUMN sol_usd_price REAL;
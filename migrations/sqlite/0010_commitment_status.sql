@@ -0,0 +1,2 @@
+-- Finality is only tracked with Postgres storage, so SQLite rows stay 'confirmed'.
+ALTER TABLE transactions ADD COLUMN commitment_status TEXT NOT NULL DEFAULT 'confirmed';
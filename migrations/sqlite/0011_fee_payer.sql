@@ -0,0 +1,3 @@
+-- Transfers are stored as a JSON array, like categories.
+ALTER TABLE transactions ADD COLUMN fee_payer TEXT;
+ALTER TABLE transactions ADD COLUMN transfers TEXT NOT NULL DEFAULT '[]';
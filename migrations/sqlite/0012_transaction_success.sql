@@ -0,0 +1,3 @@
+-- Rows stored before it was recorded succeeded unless they have an error.
+ALTER TABLE transactions ADD COLUMN success INTEGER NOT NULL DEFAULT 1;
+UPDATE transactions SET success = 0 WHERE error IS NOT NULL;
@@ -0,0 +1,109 @@
+// Serves the current state of accounts, read live from the RPC node with a short-lived cache
+
+// Responsibilities:
+// * Answer `/accounts/{pubkey}` with an account's lamports, owner program, executable flag, rent
+//   epoch and, for token accounts, mint and amount, whether the account is watched or not.
+// * Keep repeated requests for the same account from each costing an RPC call.
+
+// Implementation:
+// * Accounts are read with `getAccountInfo` through the `SolanaClient`, with its fallbacks and
+//   rate limits, and cached per account for `cache_ttl_secs`, accounts that don't exist included.
+// * Expired entries are dropped whenever a fresh result is stored, so the cache stays small.
+
+use crate::{
+    config::AccountDetailsConfig, data_processing::AccountDetails, data_retrieval::SolanaClient,
+};
+
+use solana_sdk::pubkey::Pubkey;
+use tokio::time::{Duration, Instant};
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+pub struct AccountCache {
+    solana_client: Arc<SolanaClient>,
+    ttl: Duration,
+    /// Keyed by account, `None` for accounts that don't exist.
+    entries: Mutex<HashMap<Pubkey, (Instant, Option<AccountDetails>)>>,
+}
+
+impl AccountCache {
+    pub fn new(config: &AccountDetailsConfig, solana_client: Arc<SolanaClient>) -> Self {
+        AccountCache {
+            solana_client,
+            ttl: Duration::from_secs(config.cache_ttl_secs),
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The current state of `address`, or `None` if the account doesn't exist.
+    pub async fn get(&self, address: &Pubkey) -> anyhow::Result<Option<AccountDetails>> {
+        if let Some(details) = self.cached(address) {
+            return Ok(details);
+        }
+
+        let details = self.solana_client.fetch_account_details(address).await?;
+        self.store(*address, details.clone());
+
+        Ok(details)
+    }
+
+    fn cached(&self, address: &Pubkey) -> Option<Option<AccountDetails>> {
+        let entries = self.entries.lock().expect("account cache lock poisoned");
+
+        entries
+            .get(address)
+            .filter(|(read_at, _)| read_at.elapsed() < self.ttl)
+            .map(|(_, details)| details.clone())
+    }
+
+    fn store(&self, address: Pubkey, details: Option<AccountDetails>) {
+        let mut entries = self.entries.lock().expect("account cache lock poisoned");
+
+        entries.retain(|_, (read_at, _)| read_at.elapsed() < self.ttl);
+        entries.insert(address, (Instant::now(), details));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn details(address: &Pubkey, lamports: u64) -> AccountDetails {
+        AccountDetails {
+            pubkey: address.to_string(),
+            lamports,
+            owner: "11111111111111111111111111111111".to_string(),
+            executable: false,
+            rent_epoch: u64::MAX,
+            data_len: 0,
+            token_mint: None,
+            token_amount: None,
+            slot: 42,
+        }
+    }
+
+    #[test]
+    fn test_cache_keeps_missing_accounts_and_expires() {
+        let solana_client = Arc::new(SolanaClient::new("http://localhost:8899"));
+        let cache = AccountCache::new(
+            &AccountDetailsConfig { cache_ttl_secs: 60 },
+            Arc::clone(&solana_client),
+        );
+        let (funded, missing) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+        cache.store(funded, Some(details(&funded, 1_000)));
+        cache.store(missing, None);
+
+        assert_eq!(cache.cached(&funded), Some(Some(details(&funded, 1_000))));
+        assert_eq!(cache.cached(&missing), Some(None));
+        assert_eq!(cache.cached(&Pubkey::new_unique()), None);
+
+        let expired = AccountCache::new(&AccountDetailsConfig { cache_ttl_secs: 0 }, solana_client);
+        expired.store(funded, Some(details(&funded, 1_000)));
+
+        assert_eq!(expired.cached(&funded), None);
+    }
+}
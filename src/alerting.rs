@@ -0,0 +1,61 @@
+// Operational alerting for the aggregator itself
+
+// Responsibilities:
+// * Surface problems with the running service (not with the watched addresses) to operators.
+
+// Implementation:
+// * Always log the alert at `error` level.
+// * Optionally POST the alert as JSON to a webhook (e.g. Slack, PagerDuty, Alertmanager).
+
+use log::error;
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+struct AlertPayload<'a> {
+    source: &'a str,
+    subject: &'a str,
+    detail: &'a str,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Alerter {
+    webhook_url: Option<String>,
+    http: reqwest::Client,
+}
+
+impl Alerter {
+    pub fn new(webhook_url: Option<String>) -> Self {
+        Alerter {
+            webhook_url,
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Raise an operational alert.
+    ///
+    /// The alert is always logged; if a webhook is configured it is also posted there.
+    pub async fn raise(&self, subject: &str, detail: &str) {
+        error!("ALERT: {subject}: {detail}");
+
+        let Some(webhook_url) = &self.webhook_url else {
+            return;
+        };
+
+        let payload = AlertPayload {
+            source: "solana-data-aggregator",
+            subject,
+            detail,
+        };
+
+        if let Err(e) = self
+            .http
+            .post(webhook_url)
+            .json(&payload)
+            .send()
+            .await
+            .and_then(|res| res.error_for_status())
+        {
+            error!("Failed to deliver alert to webhook: {e:?}");
+        }
+    }
+}
@@ -0,0 +1,867 @@
+// Alert rules on the activity of addresses, with notifications to webhooks, Slack and email
+
+// Responsibilities:
+// * Hold alert rules registered through `POST /alerts` and persisted in `alert_rules`: large
+//   transfers, transfers with a new counterparty of an address, and addresses going quiet.
+// * Evaluate the transfer rules against every newly stored transaction, and the inactivity rules
+//   every `check_interval_secs`.
+// * Send the monitor's transactions that may fire a transfer rule down a priority lane, so their
+//   notifications don't wait for the batched insert.
+// * Notify each rule's channels when it fires: a JSON webhook, a Slack incoming webhook or an
+//   email sent through the configured SMTP server.
+
+// Implementation:
+// * Unlike `alerting`, which reports problems with the aggregator itself to operators, these
+//   alerts are about the watched chain activity and configured by API users.
+// * Rules are loaded from Postgres at startup and kept in memory, like labels, so evaluating a
+//   batch costs no query unless a new-counterparty rule concerns one of its transactions.
+// * A counterparty is new when no stored transaction between it and the address predates the
+//   batch it was stored in.
+// * An inactivity rule fires once per quiet spell: again only after the address is active and then
+//   quiet for as long once more. An address without stored transactions is quiet since the rule
+//   was created.
+// * Notifications are sent in the background, so a slow channel never holds up ingestion. Failed
+//   deliveries are logged, not retried. Webhook and Slack deliveries count towards the usage of
+//   the API key the rule was created with.
+// * Email needs a build with the `email` feature.
+// * The lane is a bounded channel of up to `lane_capacity` batches, fed by the monitor pipeline
+//   right after processing and evaluated by its own task. Only transactions a rule may fire on and
+//   that aren't stored yet are sent; the signatures sent are remembered (the latest
+//   `DISPATCHED_CAPACITY`), so the insert that follows, or another watched address's batch
+//   holding the same transaction, doesn't evaluate them again. When the lane is full, or storage
+//   can't be checked, they're evaluated once stored, as everything else is.
+// * Alerts sent down the lane may fire for a transaction whose insert then fails.
+
+use crate::{
+    config::{AlertsConfig, SmtpConfig},
+    data_processing::TransactionData,
+    data_storage::{
+        delete_alert_rule, get_alert_rules, get_known_counterparties, get_last_activity,
+        insert_alert_rule, set_alert_rule_fired, Storage,
+    },
+    metering::UsageMeter,
+    schedules::Job,
+};
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use std::{
+    collections::{BTreeMap, HashSet, VecDeque},
+    str::FromStr,
+    sync::{Arc, Mutex, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Longest rule name accepted, in bytes.
+const MAX_NAME_LEN: usize = 200;
+
+const MAX_CHANNELS: usize = 10;
+
+/// Shortest quiet spell an inactivity rule can watch for, in seconds.
+const MIN_INACTIVITY_SECS: i64 = 60;
+
+/// Signatures sent down the priority lane that are remembered, so they aren't evaluated again.
+const DISPATCHED_CAPACITY: usize = 10_000;
+
+/// What makes a rule fire.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Condition {
+    /// A transaction moving more than `lamports`, optionally only to or from `address`.
+    AmountAbove {
+        lamports: u64,
+        #[serde(default)]
+        address: Option<String>,
+    },
+    /// A transaction between `address` and an address it never transacted with before.
+    NewCounterparty { address: String },
+    /// No transaction sent or received by `address` for `secs` seconds.
+    Inactivity { address: String, secs: i64 },
+}
+
+/// Where a rule's notifications go.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Channel {
+    /// POST the alert as JSON.
+    Webhook {
+        url: String,
+    },
+    /// Post the alert to a Slack incoming webhook.
+    Slack {
+        webhook_url: String,
+    },
+    Email {
+        to: String,
+    },
+}
+
+/// A rule as registered through the API.
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NewAlertRule {
+    pub name: String,
+    pub condition: Condition,
+    pub channels: Vec<Channel>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AlertRule {
+    pub id: i32,
+    pub name: String,
+    pub condition: Condition,
+    pub channels: Vec<Channel>,
+    #[serde(skip_serializing)]
+    pub api_key_id: Option<i32>,
+    /// Unix seconds.
+    pub created_at: i64,
+    pub last_fired_at: Option<i64>,
+}
+
+/// A fired rule, as sent to webhooks.
+#[derive(Debug, Clone, Serialize)]
+pub struct Alert {
+    pub rule_id: i32,
+    pub rule: String,
+    pub subject: String,
+    pub detail: String,
+    /// Of the transaction that fired the rule, if one did.
+    pub signature: Option<String>,
+    /// Unix seconds.
+    pub fired_at: i64,
+}
+
+#[derive(Serialize)]
+struct SlackMessage {
+    text: String,
+}
+
+/// The latest signatures sent down the priority lane, oldest first.
+#[derive(Debug, Default)]
+struct Dispatched {
+    signatures: HashSet<String>,
+    order: VecDeque<String>,
+}
+
+impl Dispatched {
+    /// Remember `signature`, returning whether it's new.
+    fn insert(&mut self, signature: &str) -> bool {
+        if !self.signatures.insert(signature.to_string()) {
+            return false;
+        }
+
+        self.order.push_back(signature.to_string());
+
+        while self.order.len() > DISPATCHED_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.signatures.remove(&oldest);
+            }
+        }
+
+        true
+    }
+}
+
+pub struct AlertEngine {
+    db: Arc<PgPool>,
+    smtp: Option<SmtpConfig>,
+    http: reqwest::Client,
+    /// Only set when API keys are used.
+    meter: Option<Arc<UsageMeter>>,
+    /// Keyed by id.
+    rules: RwLock<BTreeMap<i32, AlertRule>>,
+    lane: mpsc::Sender<Vec<TransactionData>>,
+    /// Taken by the task evaluating the lane.
+    lane_receiver: Mutex<Option<mpsc::Receiver<Vec<TransactionData>>>>,
+    dispatched: Mutex<Dispatched>,
+}
+
+impl AlertEngine {
+    /// The engine with the rules stored in `db`.
+    pub async fn load(
+        config: &AlertsConfig,
+        db: Arc<PgPool>,
+        meter: Option<Arc<UsageMeter>>,
+    ) -> anyhow::Result<Self> {
+        let rules = get_alert_rules(&db)
+            .await?
+            .into_iter()
+            .map(|rule| (rule.id, rule))
+            .collect();
+
+        Ok(Self::new(config, db, meter, rules))
+    }
+
+    fn new(
+        config: &AlertsConfig,
+        db: Arc<PgPool>,
+        meter: Option<Arc<UsageMeter>>,
+        rules: BTreeMap<i32, AlertRule>,
+    ) -> Self {
+        let (lane, lane_receiver) = mpsc::channel(config.lane_capacity.max(1));
+
+        AlertEngine {
+            db,
+            smtp: config.smtp.clone(),
+            http: reqwest::Client::new(),
+            meter,
+            rules: RwLock::new(rules),
+            lane,
+            lane_receiver: Mutex::new(Some(lane_receiver)),
+            dispatched: Mutex::new(Dispatched::default()),
+        }
+    }
+
+    /// Check a rule before it's created, normalizing its addresses.
+    pub fn check(&self, rule: NewAlertRule) -> anyhow::Result<NewAlertRule> {
+        let name = rule.name.trim();
+
+        if name.is_empty() || name.len() > MAX_NAME_LEN {
+            anyhow::bail!("Rule names must be between 1 and {MAX_NAME_LEN} bytes");
+        }
+
+        let condition = match rule.condition {
+            Condition::AmountAbove { lamports, address } => Condition::AmountAbove {
+                lamports,
+                address: address.as_deref().map(normalize_pubkey).transpose()?,
+            },
+            Condition::NewCounterparty { address } => Condition::NewCounterparty {
+                address: normalize_pubkey(&address)?,
+            },
+            Condition::Inactivity { address, secs } => {
+                if secs < MIN_INACTIVITY_SECS {
+                    anyhow::bail!("Inactivity rules need `secs` of at least {MIN_INACTIVITY_SECS}");
+                }
+
+                Condition::Inactivity {
+                    address: normalize_pubkey(&address)?,
+                    secs,
+                }
+            }
+        };
+
+        if rule.channels.is_empty() || rule.channels.len() > MAX_CHANNELS {
+            anyhow::bail!("Rules need between 1 and {MAX_CHANNELS} channels");
+        }
+
+        for channel in &rule.channels {
+            match channel {
+                Channel::Webhook { url } | Channel::Slack { webhook_url: url } => {
+                    let url = reqwest::Url::parse(url)
+                        .map_err(|e| anyhow::anyhow!("Invalid URL `{url}`: {e}"))?;
+
+                    if !matches!(url.scheme(), "http" | "https") {
+                        anyhow::bail!("Webhook URLs must be http or https");
+                    }
+                }
+                Channel::Email { to } => {
+                    if self.smtp.is_none() {
+                        anyhow::bail!("Email alerts need an `[alerts.smtp]` server");
+                    }
+
+                    check_email(to)?;
+                }
+            }
+        }
+
+        Ok(NewAlertRule {
+            name: name.to_string(),
+            condition,
+            channels: rule.channels,
+        })
+    }
+
+    /// Store a rule checked by `check`, created with the API key `api_key_id`, if any.
+    pub async fn create(
+        &self,
+        rule: NewAlertRule,
+        api_key_id: Option<i32>,
+    ) -> anyhow::Result<AlertRule> {
+        let created_at = unix_now();
+        let id = insert_alert_rule(&self.db, &rule, api_key_id, created_at).await?;
+
+        let rule = AlertRule {
+            id,
+            name: rule.name,
+            condition: rule.condition,
+            channels: rule.channels,
+            api_key_id,
+            created_at,
+            last_fired_at: None,
+        };
+
+        self.rules
+            .write()
+            .expect("alert rules lock poisoned")
+            .insert(id, rule.clone());
+
+        Ok(rule)
+    }
+
+    /// Delete a rule, returning whether there was one.
+    pub async fn remove(&self, id: i32) -> anyhow::Result<bool> {
+        let deleted = delete_alert_rule(&self.db, id).await?;
+
+        // also forgotten if the row was already gone
+        let removed = self
+            .rules
+            .write()
+            .expect("alert rules lock poisoned")
+            .remove(&id)
+            .is_some();
+
+        Ok(deleted || removed)
+    }
+
+    /// Every rule, by id.
+    pub fn rules(&self) -> Vec<AlertRule> {
+        self.rules
+            .read()
+            .expect("alert rules lock poisoned")
+            .values()
+            .cloned()
+            .collect()
+    }
+
+    /// Send the transactions a transfer rule may fire on that aren't in `storage` yet down the
+    /// priority lane, to be evaluated ahead of their insert. Those the lane has no room for are
+    /// left to be evaluated once stored.
+    pub async fn dispatch(&self, txns: &[TransactionData], storage: &Arc<dyn Storage>) {
+        let rules = self.rules();
+        let candidates = txns
+            .iter()
+            .filter(|txn| rules.iter().any(|rule| may_fire(&rule.condition, txn)))
+            .collect::<Vec<_>>();
+
+        if candidates.is_empty() {
+            return;
+        }
+
+        let signatures = candidates
+            .iter()
+            .map(|txn| txn.signature.clone())
+            .collect::<Vec<_>>();
+
+        let stored = match storage.stored_signatures(&signatures).await {
+            Ok(stored) => stored,
+            Err(e) => {
+                error!("Failed to check transactions for the alert lane: {e:?}");
+                return;
+            }
+        };
+
+        let Ok(permit) = self.lane.try_reserve() else {
+            warn!(
+                "The alert lane is full, evaluating {} transactions once stored",
+                candidates.len()
+            );
+            return;
+        };
+
+        let fresh = {
+            let mut dispatched = self.dispatched.lock().expect("alert lane lock poisoned");
+
+            candidates
+                .into_iter()
+                .filter(|txn| !stored.contains(&txn.signature) && dispatched.insert(&txn.signature))
+                .cloned()
+                .collect::<Vec<_>>()
+        };
+
+        if !fresh.is_empty() {
+            permit.send(fresh);
+        }
+    }
+
+    /// Whether a transaction was sent down the priority lane, and so is already evaluated.
+    pub fn was_dispatched(&self, signature: &str) -> bool {
+        self.dispatched
+            .lock()
+            .expect("alert lane lock poisoned")
+            .signatures
+            .contains(signature)
+    }
+
+    /// Evaluate what's sent down the priority lane until `shutdown` is cancelled.
+    pub async fn run_lane(&self, shutdown: &CancellationToken) {
+        let Some(mut lane) = self
+            .lane_receiver
+            .lock()
+            .expect("alert lane lock poisoned")
+            .take()
+        else {
+            return;
+        };
+
+        loop {
+            let txns = tokio::select! {
+                _ = shutdown.cancelled() => break,
+                txns = lane.recv() => match txns {
+                    Some(txns) => txns,
+                    None => break,
+                },
+            };
+
+            self.evaluate(&txns.iter().collect::<Vec<_>>()).await;
+        }
+    }
+
+    /// Fire the transfer rules matching newly stored transactions.
+    pub async fn evaluate(&self, txns: &[&TransactionData]) {
+        if txns.is_empty() {
+            return;
+        }
+
+        let signatures = txns
+            .iter()
+            .map(|txn| txn.signature.clone())
+            .collect::<Vec<_>>();
+
+        for rule in self.rules() {
+            match &rule.condition {
+                Condition::AmountAbove { lamports, address } => {
+                    for txn in txns
+                        .iter()
+                        .filter(|txn| exceeds(txn, *lamports, address.as_deref()))
+                    {
+                        let subject = format!("{} SOL transferred", txn.sol_amount as f64 / 1e9);
+                        let detail = format!(
+                            "{} sent {} lamports to {} in {}",
+                            txn.sender, txn.sol_amount, txn.receiver, txn.signature
+                        );
+
+                        self.fire(&rule, subject, detail, Some(&txn.signature))
+                            .await;
+                    }
+                }
+                Condition::NewCounterparty { address } => {
+                    if let Err(e) = self
+                        .fire_new_counterparties(&rule, address, txns, &signatures)
+                        .await
+                    {
+                        error!("Failed to evaluate alert rule {}: {e:?}", rule.id);
+                    }
+                }
+                Condition::Inactivity { .. } => {}
+            }
+        }
+    }
+
+    async fn fire_new_counterparties(
+        &self,
+        rule: &AlertRule,
+        address: &str,
+        txns: &[&TransactionData],
+        signatures: &[String],
+    ) -> anyhow::Result<()> {
+        let candidates = counterparties(address, txns);
+
+        if candidates.is_empty() {
+            return Ok(());
+        }
+
+        let keys = candidates.keys().cloned().collect::<Vec<_>>();
+        let known = get_known_counterparties(&self.db, address, &keys, signatures).await?;
+
+        for (counterparty, txn) in candidates {
+            if !known.contains(&counterparty) {
+                let subject = format!("New counterparty for {address}");
+                let detail = format!(
+                    "{address} transacted with {counterparty} for the first time in {}",
+                    txn.signature
+                );
+
+                self.fire(rule, subject, detail, Some(&txn.signature)).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check the inactivity rules every time `job` is due, until `shutdown` is cancelled.
+    pub async fn run(&self, job: &Job, shutdown: &CancellationToken) {
+        while job.tick(shutdown).await {
+            let _ = job.run(self.check_inactivity()).await;
+        }
+    }
+
+    async fn check_inactivity(&self) -> anyhow::Result<()> {
+        let now = unix_now();
+
+        for rule in self.rules() {
+            let Condition::Inactivity { address, secs } = &rule.condition else {
+                continue;
+            };
+
+            let last_activity = get_last_activity(&self.db, address).await?;
+
+            if inactivity_due(&rule, *secs, last_activity, now) {
+                let subject = format!("No activity for {address}");
+                let detail = match last_activity {
+                    Some(timestamp) => format!(
+                        "{address} hasn't sent or received a transaction since {timestamp} \
+                        (unix seconds)"
+                    ),
+                    None => format!("{address} hasn't sent or received a transaction yet"),
+                };
+
+                self.fire(&rule, subject, detail, None).await;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Record that `rule` fired and notify its channels in the background.
+    async fn fire(
+        &self,
+        rule: &AlertRule,
+        subject: String,
+        detail: String,
+        signature: Option<&str>,
+    ) {
+        let fired_at = unix_now();
+
+        info!("Alert rule {} ({}) fired: {subject}", rule.id, rule.name);
+
+        if let Some(stored) = self
+            .rules
+            .write()
+            .expect("alert rules lock poisoned")
+            .get_mut(&rule.id)
+        {
+            stored.last_fired_at = Some(fired_at);
+        }
+
+        if let Err(e) = set_alert_rule_fired(&self.db, rule.id, fired_at).await {
+            error!("Failed to record that alert rule {} fired: {e:?}", rule.id);
+        }
+
+        let alert = Alert {
+            rule_id: rule.id,
+            rule: rule.name.clone(),
+            subject,
+            detail,
+            signature: signature.map(str::to_string),
+            fired_at,
+        };
+        let notifier = Notifier {
+            http: self.http.clone(),
+            smtp: self.smtp.clone(),
+            meter: self.meter.clone(),
+            api_key_id: rule.api_key_id,
+        };
+        let channels = rule.channels.clone();
+
+        tokio::spawn(async move {
+            for channel in &channels {
+                notifier.notify(channel, &alert).await;
+            }
+        });
+    }
+}
+
+/// Sends a rule's alerts.
+struct Notifier {
+    http: reqwest::Client,
+    smtp: Option<SmtpConfig>,
+    meter: Option<Arc<UsageMeter>>,
+    api_key_id: Option<i32>,
+}
+
+impl Notifier {
+    async fn notify(&self, channel: &Channel, alert: &Alert) {
+        let sent = match channel {
+            Channel::Webhook { url } => self.post(url, alert).await,
+            Channel::Slack { webhook_url } => {
+                let message = SlackMessage {
+                    text: format!("*{}*: {}\n{}", alert.rule, alert.subject, alert.detail),
+                };
+
+                self.post(webhook_url, &message).await
+            }
+            Channel::Email { to } => match &self.smtp {
+                Some(smtp) => {
+                    let subject = format!("[{}] {}", alert.rule, alert.subject);
+                    send_email(smtp, to, subject, alert.detail.clone()).await
+                }
+                None => Err(anyhow::anyhow!("No SMTP server is configured")),
+            },
+        };
+
+        if let Err(e) = sent {
+            error!("Failed to deliver alert of rule {}: {e:?}", alert.rule_id);
+            return;
+        }
+
+        // emails aren't metered
+        if let (Some(meter), Some(key_id), Channel::Webhook { .. } | Channel::Slack { .. }) =
+            (&self.meter, self.api_key_id, channel)
+        {
+            meter.record_webhook_delivery(key_id);
+        }
+    }
+
+    async fn post(&self, url: &str, body: &impl Serialize) -> anyhow::Result<()> {
+        self.http
+            .post(url)
+            .json(body)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+/// Send a plain text email through `smtp`. Also used for the daily reports.
+#[cfg(feature = "email")]
+pub async fn send_email(
+    smtp: &SmtpConfig,
+    to: &str,
+    subject: String,
+    body: String,
+) -> anyhow::Result<()> {
+    use lettre::{
+        transport::smtp::authentication::Credentials, AsyncSmtpTransport, AsyncTransport, Message,
+        Tokio1Executor,
+    };
+
+    let message = Message::builder()
+        .from(smtp.from.parse()?)
+        .to(to.parse()?)
+        .subject(subject)
+        .body(body)?;
+
+    let mut transport =
+        AsyncSmtpTransport::<Tokio1Executor>::starttls_relay(&smtp.host)?.port(smtp.port);
+
+    if let (Some(username), Some(password)) = (&smtp.username, &smtp.password) {
+        transport = transport.credentials(Credentials::new(username.clone(), password.clone()));
+    }
+
+    transport.build().send(message).await?;
+
+    Ok(())
+}
+
+#[cfg(not(feature = "email"))]
+pub async fn send_email(
+    smtp: &SmtpConfig,
+    _to: &str,
+    _subject: String,
+    _body: String,
+) -> anyhow::Result<()> {
+    anyhow::bail!(
+        "Emails through {} are configured, but this build lacks the `email` feature",
+        smtp.host
+    )
+}
+
+#[cfg(feature = "email")]
+pub fn check_email(to: &str) -> anyhow::Result<()> {
+    to.parse::<lettre::message::Mailbox>()
+        .map_err(|e| anyhow::anyhow!("Invalid email address `{to}`: {e}"))?;
+
+    Ok(())
+}
+
+#[cfg(not(feature = "email"))]
+pub fn check_email(_to: &str) -> anyhow::Result<()> {
+    anyhow::bail!("Emails need a build with the `email` feature")
+}
+
+fn normalize_pubkey(pubkey: &str) -> anyhow::Result<String> {
+    Pubkey::from_str(pubkey)
+        .map(|pubkey| pubkey.to_string())
+        .map_err(|e| anyhow::anyhow!("Invalid public key `{pubkey}`: {e}"))
+}
+
+/// Whether `txn` moves more than `lamports`, to or from `address` if given.
+fn exceeds(txn: &TransactionData, lamports: u64, address: Option<&str>) -> bool {
+    txn.sol_amount > lamports
+        && address.map_or(true, |address| {
+            txn.sender == address || txn.receiver == address
+        })
+}
+
+/// Whether a transfer rule with `condition` may fire on `txn`. New counterparties still need
+/// checking against the stored transactions.
+fn may_fire(condition: &Condition, txn: &TransactionData) -> bool {
+    match condition {
+        Condition::AmountAbove { lamports, address } => exceeds(txn, *lamports, address.as_deref()),
+        Condition::NewCounterparty { address } => {
+            txn.sender != txn.receiver && (txn.sender == *address || txn.receiver == *address)
+        }
+        Condition::Inactivity { .. } => false,
+    }
+}
+
+/// The other side of each transaction of `address`, with the first transaction with it.
+fn counterparties<'a>(
+    address: &str,
+    txns: &[&'a TransactionData],
+) -> BTreeMap<String, &'a TransactionData> {
+    let mut counterparties = BTreeMap::new();
+
+    for txn in txns {
+        let counterparty = if txn.sender == address {
+            &txn.receiver
+        } else if txn.receiver == address {
+            &txn.sender
+        } else {
+            continue;
+        };
+
+        if counterparty != address {
+            counterparties.entry(counterparty.clone()).or_insert(*txn);
+        }
+    }
+
+    counterparties
+}
+
+/// Whether an inactivity rule watching for `secs` of silence should fire at `now`.
+fn inactivity_due(rule: &AlertRule, secs: i64, last_activity: Option<i64>, now: i64) -> bool {
+    let quiet_since = last_activity.unwrap_or(rule.created_at);
+
+    now - quiet_since >= secs
+        && rule
+            .last_fired_at
+            .map_or(true, |fired_at| fired_at < quiet_since)
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data_storage::InMemoryStorage;
+
+    use solana_sdk::signature::Signature;
+
+    fn transfer(sender: &str, receiver: &str, sol_amount: u64) -> TransactionData {
+        TransactionData {
+            signature: Signature::new_unique().to_string(),
+            sender: sender.to_string(),
+            receiver: receiver.to_string(),
+            sol_amount,
+            fee: 5000,
+            timestamp: 1625077743,
+            prev_blockhash: "4sZ76MsNd8y3WSw2L1nfd3AqLoYxdmC98sERoMRbHV14".to_string(),
+            slot: 42,
+            ..TransactionData::default()
+        }
+    }
+
+    #[test]
+    fn test_conditions_match_and_inactivity_fires_once() {
+        let [a, b, c] = [(); 3].map(|_| Pubkey::new_unique().to_string());
+
+        let large = transfer(&a, &b, 200_000_000_000);
+        let small = transfer(&c, &a, 1_000);
+        assert!(exceeds(&large, 100_000_000_000, None));
+        assert!(exceeds(&large, 100_000_000_000, Some(&b)));
+        assert!(!exceeds(&large, 100_000_000_000, Some(&c)));
+        assert!(!exceeds(&small, 100_000_000_000, None));
+
+        let again = transfer(&b, &a, 5);
+        let found = counterparties(&a, &[&large, &small, &again]);
+        assert_eq!(found.len(), 2);
+        assert_eq!(found[&b].signature, large.signature);
+        assert_eq!(found[&c].signature, small.signature);
+
+        let mut rule = AlertRule {
+            id: 1,
+            name: "quiet".to_string(),
+            condition: Condition::Inactivity {
+                address: a.clone(),
+                secs: 3600,
+            },
+            channels: vec![],
+            api_key_id: None,
+            created_at: 1000,
+            last_fired_at: None,
+        };
+        assert!(!inactivity_due(&rule, 3600, None, 2000));
+        assert!(inactivity_due(&rule, 3600, None, 5000));
+        assert!(inactivity_due(&rule, 3600, Some(2000), 6000));
+
+        // fires once per quiet spell
+        rule.last_fired_at = Some(6000);
+        assert!(!inactivity_due(&rule, 3600, Some(2000), 20_000));
+        assert!(inactivity_due(&rule, 3600, Some(7000), 20_000));
+
+        let rule = serde_json::from_value::<NewAlertRule>(serde_json::json!({
+            "name": "whales",
+            "condition": { "kind": "amount_above", "lamports": 100_000_000_000u64 },
+            "channels": [{ "type": "slack", "webhook_url": "https://hooks.slack.com/services/x" }],
+        }))
+        .unwrap();
+        assert_eq!(
+            rule.condition,
+            Condition::AmountAbove {
+                lamports: 100_000_000_000,
+                address: None
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lane_sends_unstored_matches_once() -> anyhow::Result<()> {
+        let [a, b] = [(); 2].map(|_| Pubkey::new_unique().to_string());
+
+        let rule = AlertRule {
+            id: 1,
+            name: "whales".to_string(),
+            condition: Condition::AmountAbove {
+                lamports: 100_000_000_000,
+                address: None,
+            },
+            channels: vec![],
+            api_key_id: None,
+            created_at: 1000,
+            last_fired_at: None,
+        };
+        let engine = AlertEngine::new(
+            &AlertsConfig::default(),
+            Arc::new(PgPool::connect_lazy("postgres://localhost/unused")?),
+            None,
+            BTreeMap::from([(rule.id, rule)]),
+        );
+        let mut lane = engine.lane_receiver.lock().unwrap().take().unwrap();
+
+        let stored = transfer(&a, &b, 200_000_000_000);
+        let large = transfer(&a, &b, 300_000_000_000);
+        let small = transfer(&b, &a, 1_000);
+
+        let storage: Arc<dyn Storage> = Arc::new(InMemoryStorage::new());
+        storage.insert(&stored).await?;
+
+        let batch = [stored.clone(), large.clone(), small.clone()];
+        engine.dispatch(&batch, &storage).await;
+
+        let sent = lane.try_recv()?;
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0].signature, large.signature);
+        assert!(engine.was_dispatched(&large.signature));
+        assert!(!engine.was_dispatched(&stored.signature));
+        assert!(!engine.was_dispatched(&small.signature));
+
+        // the same transaction in another address's batch
+        engine.dispatch(&batch, &storage).await;
+        assert!(lane.try_recv().is_err());
+
+        Ok(())
+    }
+}
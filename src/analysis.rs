@@ -0,0 +1,314 @@
+// Analyzes addresses on demand, without adding them to the watchlist
+
+// Responsibilities:
+// * Backfill a bounded number of an address's most recent transactions in one shot.
+// * Run them through the same processing and enrichment as monitored transactions.
+// * Summarize the address's activity for investigators.
+// * Expire ad hoc data after a TTL, so investigations don't grow the database without bound.
+
+// Implementation:
+// * Transactions that are already stored are left alone; the others are stored and flagged in
+//   `ad_hoc_transactions`, so they can be told apart from monitored ones.
+// * The summary covers every valid fetched transaction, stored before or not.
+// * Watched addresses are rejected, since the monitor already covers them.
+// * Expiry runs in the background. Data of addresses that have since been added to the watchlist
+//   is kept and loses its ad hoc flag instead.
+
+use crate::{
+    config::AnalysisConfig,
+    data_processing::TransactionData,
+    data_retrieval::SolanaClient,
+    data_storage::{expire_ad_hoc_transactions, insert_ad_hoc_transaction, AdHocCleanup, Storage},
+    schedules::Job,
+};
+
+use log::{error, info};
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+use sqlx::PgPool;
+use tokio_util::sync::CancellationToken;
+
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Outcome of an on-demand analysis of an address.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct AnalysisSummary {
+    pub pubkey: String,
+    /// Unix seconds.
+    pub analyzed_at: i64,
+    /// Signatures returned by the RPC node.
+    pub fetched: usize,
+    /// Fetched transactions that parsed and passed validation.
+    pub transactions: usize,
+    /// Transactions that weren't stored yet, now stored and flagged as ad hoc.
+    pub stored: usize,
+    pub sent: usize,
+    pub received: usize,
+    pub sol_sent: u64,
+    pub sol_received: u64,
+    /// Fees of the transactions the address sent.
+    pub fees_paid: u64,
+    pub first_activity: Option<i64>,
+    pub last_activity: Option<i64>,
+    /// Distinct addresses on the other side of the address's transactions.
+    pub counterparties: usize,
+}
+
+/// Rows reclaimed by expiring ad hoc data.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CleanupReport {
+    /// `None` if ad hoc data never expires.
+    pub ttl_secs: Option<u64>,
+    /// Unix seconds.
+    pub last_run_at: Option<i64>,
+    pub last_run: AdHocCleanup,
+    /// Totals since startup.
+    pub reclaimed: AdHocCleanup,
+}
+
+pub struct Analyzer {
+    solana_client: Arc<SolanaClient>,
+    watched: HashSet<Pubkey>,
+    max_transactions: usize,
+    ttl_secs: u64,
+    cleanup: Mutex<CleanupReport>,
+}
+
+impl Analyzer {
+    pub fn new(
+        config: &AnalysisConfig,
+        solana_client: Arc<SolanaClient>,
+        watched: &[Pubkey],
+    ) -> Self {
+        Analyzer {
+            solana_client,
+            watched: watched.iter().copied().collect(),
+            max_transactions: config.max_transactions,
+            ttl_secs: config.ttl_secs,
+            cleanup: Mutex::new(CleanupReport {
+                ttl_secs: (config.ttl_secs > 0).then_some(config.ttl_secs),
+                ..CleanupReport::default()
+            }),
+        }
+    }
+
+    pub fn is_watched(&self, address: &Pubkey) -> bool {
+        self.watched.contains(address)
+    }
+
+    /// Backfill up to `limit` (capped at `max_transactions`) of the address's most recent
+    /// transactions, store the new ones and summarize them all.
+    pub async fn analyze(
+        &self,
+        address: &Pubkey,
+        limit: Option<usize>,
+        storage: &Arc<dyn Storage>,
+        db: &Arc<PgPool>,
+    ) -> anyhow::Result<AnalysisSummary> {
+        let limit = limit
+            .unwrap_or(self.max_transactions)
+            .clamp(1, self.max_transactions.max(1));
+
+        self.solana_client.wait_for_memory().await;
+
+        let signatures = self
+            .solana_client
+            .fetch_recent_signatures(address, limit)
+            .await?;
+        let txns = self.solana_client.fetch_transactions(&signatures).await?;
+
+        let fetched = self.solana_client.charge_fetched(&txns);
+        let batch = self.solana_client.process(txns, None);
+        drop(fetched);
+        let _processed = self.solana_client.charge_processed(&batch);
+
+        let pubkey = address.to_string();
+        let analyzed_at = unix_now();
+        let stored = self.solana_client.store(&batch, storage, Some(db)).await?;
+
+        for signature in &stored {
+            insert_ad_hoc_transaction(db, &pubkey, signature, analyzed_at).await?;
+        }
+
+        Ok(AnalysisSummary {
+            analyzed_at,
+            fetched: signatures.len(),
+            stored: stored.len(),
+            ..summarize(&pubkey, &batch.transactions)
+        })
+    }
+
+    /// Periodically delete ad hoc data older than the TTL until shut down.
+    pub async fn expire_ad_hoc_data(
+        &self,
+        db: &Arc<PgPool>,
+        job: &Job,
+        shutdown: &CancellationToken,
+    ) {
+        if self.ttl_secs == 0 {
+            return;
+        }
+
+        let watched = self
+            .watched
+            .iter()
+            .map(Pubkey::to_string)
+            .collect::<Vec<_>>();
+
+        while job.tick(shutdown).await {
+            let now = unix_now();
+            let cutoff = now - self.ttl_secs as i64;
+
+            let result = job.run(expire_ad_hoc_transactions(db, cutoff, &watched));
+
+            match result.await {
+                Ok(run) => {
+                    if run != AdHocCleanup::default() {
+                        info!("Expired ad hoc analysis data: {run:?}");
+                    }
+
+                    self.record_cleanup(now, run);
+                }
+                Err(e) => error!("Failed to expire ad hoc analysis data: {e:?}"),
+            }
+        }
+    }
+
+    fn record_cleanup(&self, now: i64, run: AdHocCleanup) {
+        let mut report = self.cleanup.lock().expect("cleanup report lock poisoned");
+
+        report.last_run_at = Some(now);
+        report.last_run = run;
+        report.reclaimed.transactions += run.transactions;
+        report.reclaimed.fee_burns += run.fee_burns;
+        report.reclaimed.raw_transactions += run.raw_transactions;
+        report.reclaimed.promoted += run.promoted;
+    }
+
+    pub fn cleanup_report(&self) -> CleanupReport {
+        self.cleanup
+            .lock()
+            .expect("cleanup report lock poisoned")
+            .clone()
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+/// Summarize the activity of `pubkey` over the given transactions.
+fn summarize(pubkey: &str, transactions: &[TransactionData]) -> AnalysisSummary {
+    let mut summary = AnalysisSummary {
+        pubkey: pubkey.to_string(),
+        transactions: transactions.len(),
+        ..AnalysisSummary::default()
+    };
+    let mut counterparties = HashSet::new();
+
+    for txn in transactions {
+        if txn.sender == pubkey {
+            summary.sent += 1;
+            summary.sol_sent += txn.sol_amount;
+            summary.fees_paid += txn.fee;
+            counterparties.insert(txn.receiver.as_str());
+        }
+
+        if txn.receiver == pubkey {
+            summary.received += 1;
+            summary.sol_received += txn.sol_amount;
+            counterparties.insert(txn.sender.as_str());
+        }
+
+        summary.first_activity = Some(
+            summary
+                .first_activity
+                .map_or(txn.timestamp, |first| first.min(txn.timestamp)),
+        );
+        summary.last_activity = Some(
+            summary
+                .last_activity
+                .map_or(txn.timestamp, |last| last.max(txn.timestamp)),
+        );
+    }
+
+    counterparties.remove(pubkey);
+    summary.counterparties = counterparties.len();
+
+    summary
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(
+        sender: &str,
+        receiver: &str,
+        sol_amount: u64,
+        timestamp: i64,
+    ) -> TransactionData {
+        TransactionData {
+            signature: format!("{sender}-{receiver}-{timestamp}"),
+            sender: sender.to_string(),
+            receiver: receiver.to_string(),
+            sol_amount,
+            fee: 5000,
+            timestamp,
+            ..TransactionData::default()
+        }
+    }
+
+    #[test]
+    fn test_summarize() {
+        let transactions = [
+            transaction("me", "alice", 100, 20),
+            transaction("bob", "me", 40, 10),
+            transaction("me", "alice", 1, 30),
+        ];
+
+        let summary = summarize("me", &transactions);
+
+        assert_eq!(summary.transactions, 3);
+        assert_eq!((summary.sent, summary.received), (2, 1));
+        assert_eq!((summary.sol_sent, summary.sol_received), (101, 40));
+        assert_eq!(summary.fees_paid, 10_000);
+        assert_eq!(summary.first_activity, Some(10));
+        assert_eq!(summary.last_activity, Some(30));
+        assert_eq!(summary.counterparties, 2);
+
+        assert_eq!(summarize("me", &[]).first_activity, None);
+    }
+
+    #[test]
+    fn test_cleanup_totals() {
+        let analyzer = Analyzer::new(
+            &AnalysisConfig::default(),
+            Arc::new(SolanaClient::new("http://127.0.0.1:8899")),
+            &[],
+        );
+
+        let run = AdHocCleanup {
+            transactions: 3,
+            fee_burns: 2,
+            raw_transactions: 3,
+            promoted: 1,
+        };
+        analyzer.record_cleanup(100, run);
+        analyzer.record_cleanup(200, AdHocCleanup::default());
+        analyzer.record_cleanup(300, run);
+
+        let report = analyzer.cleanup_report();
+        assert_eq!(report.ttl_secs, Some(AnalysisConfig::default().ttl_secs));
+        assert_eq!(report.last_run_at, Some(300));
+        assert_eq!(report.last_run, run);
+        assert_eq!(report.reclaimed.transactions, 6);
+        assert_eq!(report.reclaimed.promoted, 2);
+    }
+}
@@ -0,0 +1,186 @@
+// Time-bucketed volume analytics over the stored transactions
+
+// Responsibilities:
+// * Define the hourly and daily rollups (transaction count, SOL volume, fee sum) kept per address
+//   and over all transactions.
+// * Serve them for charting without aggregating the raw transactions on every request.
+// * Lay out an address's activity as heatmaps: weekday by hour of the day, or a calendar of days.
+
+// Implementation:
+// * Rollups live in the `transaction_rollups` table, read through the `volume_rollups` view. New
+//   transactions are folded in by the same database transaction that stores them (see
+//   `data_storage::insert_transactions_batch`), so they are never behind or counted twice.
+// * In TimescaleDB mode the view reads continuous aggregates instead (see
+//   `data_storage::enable_timescale`).
+// * Buckets are aligned to unix time, so daily buckets start at midnight UTC.
+// * Heatmaps aren't rolled up: their cells are aggregated in SQL from the address's transactions
+//   on request, since they're bucketed by weekday. Weeks start on Monday.
+
+use crate::{data_storage::ActivityCell, metering::civil_date};
+
+use serde::{Deserialize, Serialize};
+
+const DAY_SECS: i64 = 86_400;
+
+const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+
+/// Bucket size of a rollup.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RollupInterval {
+    #[default]
+    Hour,
+    Day,
+}
+
+impl RollupInterval {
+    pub const ALL: [RollupInterval; 2] = [RollupInterval::Hour, RollupInterval::Day];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RollupInterval::Hour => "hour",
+            RollupInterval::Day => "day",
+        }
+    }
+
+    pub fn seconds(self) -> i64 {
+        match self {
+            RollupInterval::Hour => 3600,
+            RollupInterval::Day => DAY_SECS,
+        }
+    }
+}
+
+/// An address's transaction counts and SOL volume as matrices, ready to render as a heatmap.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct ActivityHeatmap {
+    pub granularity: RollupInterval,
+    /// Range covered, in unix seconds, `to` excluded.
+    pub from: i64,
+    pub to: i64,
+    /// Row labels: weekdays by hour, or the Monday (`YYYY-MM-DD`) of each week by day.
+    pub rows: Vec<String>,
+    /// Column labels: hours of the day (UTC) by hour, or weekdays by day.
+    pub columns: Vec<String>,
+    /// Transactions per cell, indexed by row, then column.
+    pub transaction_counts: Vec<Vec<i64>>,
+    /// Lamports sent and received per cell, likewise.
+    pub sol_volumes: Vec<Vec<i64>>,
+}
+
+impl ActivityHeatmap {
+    /// Lay out the cells aggregated by `data_storage::get_activity_cells` over `[from, to)`.
+    pub fn new(granularity: RollupInterval, from: i64, to: i64, cells: &[ActivityCell]) -> Self {
+        let weekdays = WEEKDAYS.map(String::from).to_vec();
+        let first_week = week_start(from.div_euclid(DAY_SECS));
+
+        let (rows, columns) = match granularity {
+            RollupInterval::Hour => (weekdays, (0..24).map(|hour| format!("{hour:02}")).collect()),
+            RollupInterval::Day => {
+                let last_week = week_start((to - 1).div_euclid(DAY_SECS));
+                let mondays = (first_week..=last_week)
+                    .step_by(7)
+                    .map(|day| {
+                        let (year, month, day) = civil_date(day * DAY_SECS);
+                        format!("{year:04}-{month:02}-{day:02}")
+                    })
+                    .collect();
+
+                (mondays, weekdays)
+            }
+        };
+
+        let mut transaction_counts = vec![vec![0; columns.len()]; rows.len()];
+        let mut sol_volumes = transaction_counts.clone();
+
+        for cell in cells {
+            let (row, column) = match granularity {
+                RollupInterval::Hour => (cell.weekday, cell.bucket),
+                RollupInterval::Day => ((week_start(cell.bucket) - first_week) / 7, cell.weekday),
+            };
+            let (Ok(row), Ok(column)) = (usize::try_from(row), usize::try_from(column)) else {
+                continue;
+            };
+
+            if let Some(count) = transaction_counts
+                .get_mut(row)
+                .and_then(|counts| counts.get_mut(column))
+            {
+                *count += cell.transaction_count;
+                sol_volumes[row][column] += cell.sol_volume;
+            }
+        }
+
+        ActivityHeatmap {
+            granularity,
+            from,
+            to,
+            rows,
+            columns,
+            transaction_counts,
+            sol_volumes,
+        }
+    }
+}
+
+/// The Monday of the week of `day`, both in days since the unix epoch (a Thursday).
+fn week_start(day: i64) -> i64 {
+    day - (day + 3).rem_euclid(7)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_interval_from_query() {
+        #[derive(Deserialize)]
+        struct Params {
+            interval: RollupInterval,
+        }
+
+        let params: Params = serde_json::from_str(r#"{"interval":"day"}"#).unwrap();
+
+        assert_eq!(params.interval, RollupInterval::Day);
+        assert_eq!(params.interval.seconds(), 86_400);
+        assert!(serde_json::from_str::<Params>(r#"{"interval":"week"}"#).is_err());
+    }
+
+    #[test]
+    fn test_activity_heatmap_layout() {
+        let cell = |weekday, bucket, transaction_count| ActivityCell {
+            weekday,
+            bucket,
+            transaction_count,
+            sol_volume: transaction_count * 1_000,
+        };
+
+        // Wednesday 2024-01-03 to Tuesday 2024-01-16, UTC
+        let (from, to) = (1_704_240_000, 1_705_449_600);
+
+        let heatmap = ActivityHeatmap::new(
+            RollupInterval::Hour,
+            from,
+            to,
+            &[cell(2, 13, 4), cell(6, 0, 1)],
+        );
+        assert_eq!((heatmap.rows.len(), heatmap.columns.len()), (7, 24));
+        assert_eq!(heatmap.rows[2], "Wed");
+        assert_eq!(heatmap.columns[13], "13");
+        assert_eq!(heatmap.transaction_counts[2][13], 4);
+        assert_eq!(heatmap.sol_volumes[6][0], 1_000);
+
+        // 2024-01-03 is day 19_725 since the epoch
+        let heatmap = ActivityHeatmap::new(
+            RollupInterval::Day,
+            from,
+            to,
+            &[cell(2, 19_725, 3), cell(1, 19_738, 2)],
+        );
+        assert_eq!(heatmap.rows, ["2024-01-01", "2024-01-08", "2024-01-15"]);
+        assert_eq!(heatmap.columns[0], "Mon");
+        assert_eq!(heatmap.transaction_counts[0][2], 3);
+        assert_eq!(heatmap.transaction_counts[2][1], 2);
+        assert_eq!(heatmap.transaction_counts[1], [0; 7]);
+    }
+}
@@ -7,29 +7,2743 @@
 // Implementation:
 // * Use `actix-web` to create a RESTful API server.
 
-use crate::{data_processing::TransactionData, data_storage::get_all_transactions};
+use crate::{
+    accounts::AccountCache,
+    alerts::{AlertEngine, NewAlertRule},
+    analysis::Analyzer,
+    analytics::{ActivityHeatmap, RollupInterval},
+    archive::RawArchive,
+    auth::{Authenticator, Verdict},
+    cache::CachedStorage,
+    config::{CorsConfig, TlsConfig},
+    dashboards::{DashboardSources, Dashboards},
+    data_processing::{diff_account_data, ByteChange, TransactionData, TransactionType},
+    data_retrieval::SolanaClient,
+    data_storage::{
+        delete_label, get_account_snapshots, get_activity_cells, get_balance_history,
+        get_counterparties, get_current_token_balances, get_daily_reports, get_epoch_transactions,
+        get_fee_burn_by_epoch, get_latest_balance, get_nft, get_priority_fee_percentiles,
+        get_rejected_transactions, get_token_balance_history, get_token_transfers,
+        get_volume_rollups, upsert_account_layout, upsert_label, AddressMatch, CommitmentStatus,
+        CounterpartySummary, Storage, StoredRejection, StoredTokenTransfer, StoredTransaction,
+        TokenBalance, TransactionQuery, ALL_ADDRESSES,
+    },
+    directory::{Directory, EntryKind},
+    dlq::{DeadLetterQueue, REPLAY_JOB},
+    encoding::Encoding,
+    epochs::EpochTracker,
+    export::{self, ExportFormat},
+    feed::TransactionFeed,
+    finality::FinalityTracker,
+    gaps::GapRepair,
+    graph::{self, GraphScope},
+    graphql::{self, AggregatorSchema},
+    ids::IdCodec,
+    imports::{self, ListFormat},
+    labels::{Label, LabelRegistry},
+    layouts::{AccountLayout, DecodedAccount, LayoutRegistry},
+    leaderboards::{Dimension, Leaderboards, Window},
+    maintenance::MaintenanceScheduler,
+    memory::MemoryBudget,
+    metering::{current_month, is_month, usage_csv, Tenant},
+    metrics::IngestMetrics,
+    monitor::MonitorControl,
+    portfolio::Portfolios,
+    replication::Follower,
+    reports::{self, ReportFormat},
+    rpc_proxy::RpcProxy,
+    schedules::Schedules,
+    search::{self, QueryShape, MIN_QUERY},
+    slo::SloTracker,
+    stats::StatsCache,
+    watchlist::Watchlist,
+};
 
-use actix_web::{web, App, HttpServer, Responder};
+use actix_cors::Cors;
+use actix_web::{
+    body::MessageBody,
+    dev::{Server, Service, ServiceRequest, ServiceResponse},
+    http::header,
+    middleware::{self, Condition, Next},
+    web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer,
+};
+use async_graphql::http::GraphiQLSource;
+use async_graphql_actix_web::{GraphQLRequest, GraphQLResponse};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
 use sqlx::PgPool;
+use tokio::{runtime::Handle, time::Instant};
 
-use std::sync::Arc;
+use std::{
+    collections::HashMap,
+    fs::File,
+    future::Future,
+    io::BufReader,
+    path::Path,
+    str::FromStr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
 
-/// Handler to get all transactions.
-async fn get_transactions(db: web::Data<Arc<PgPool>>) -> impl Responder {
-    match get_all_transactions(&db).await {
-        Ok(transactions) => web::Json(transactions),
-        Err(_) => web::Json(Vec::<TransactionData>::new()),
+/// Everything the API server shares between its workers.
+#[derive(Clone)]
+pub struct ApiContext {
+    pub storage: Arc<dyn Storage>,
+    pub ids: IdCodec,
+    pub slo: Arc<SloTracker>,
+    pub ingest: Arc<IngestMetrics>,
+    pub monitor: Arc<MonitorControl>,
+    pub feed: Arc<TransactionFeed>,
+    pub leaderboards: Arc<Leaderboards>,
+    pub memory: Arc<MemoryBudget>,
+    pub schedules: Arc<Schedules>,
+    /// Empty without the Postgres backend, where labels are stored.
+    pub labels: Arc<LabelRegistry>,
+    pub directory: Arc<Directory>,
+    pub accounts: Arc<AccountCache>,
+    pub watchlist: Arc<Watchlist>,
+    /// Only set with the Postgres backend, where API keys are stored.
+    pub auth: Option<Arc<Authenticator>>,
+    /// Only set on a warm standby.
+    pub follower: Option<Arc<Follower>>,
+    /// Only set when gap repair is enabled and transactions are stored.
+    pub gaps: Option<Arc<GapRepair>>,
+    /// Only set when the RPC proxy is enabled.
+    pub rpc_proxy: Option<Arc<RpcProxy>>,
+    /// Only set when the dead-letter queue is enabled and transactions are stored.
+    pub dead_letters: Option<Arc<DeadLetterQueue>>,
+    /// Only set when the query cache is enabled; `storage` is then the same cache.
+    pub cache: Option<Arc<CachedStorage>>,
+    /// Only set when transactions are stored; lookups store the transactions they fetch.
+    pub solana_client: Option<Arc<SolanaClient>>,
+    /// The main runtime, where handlers make their RPC calls: the blocking `RpcClient` panics on
+    /// the single-threaded runtimes of the actix workers.
+    pub runtime: Handle,
+    /// Only set with the Postgres backend; its routes are not served otherwise.
+    pub postgres: Option<PostgresContext>,
+}
+
+/// Which routes a listener serves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exposure {
+    /// Every route, including admin and write endpoints.
+    Private,
+    /// Only read endpoints that are safe to expose publicly. The other routes are not
+    /// registered at all, so they can't be reached whatever the auth setup.
+    Public,
+}
+
+/// State of the routes that query Postgres-only tables.
+#[derive(Clone)]
+pub struct PostgresContext {
+    pub db: Arc<PgPool>,
+    pub layouts: Arc<LayoutRegistry>,
+    pub stats: Arc<StatsCache>,
+    pub archive: Arc<RawArchive>,
+    pub maintenance: Arc<MaintenanceScheduler>,
+    /// `None` when on-demand analysis is disabled.
+    pub analyzer: Option<Arc<Analyzer>>,
+    pub portfolios: Arc<Portfolios>,
+    pub dashboards: Arc<Dashboards>,
+    /// `None` when epoch tracking is disabled.
+    pub epochs: Option<Arc<EpochTracker>>,
+    /// `None` when alert rules are disabled.
+    pub alerts: Option<Arc<AlertEngine>>,
+    /// `None` when finality tracking is disabled or transactions aren't stored.
+    pub finality: Option<Arc<FinalityTracker>>,
+}
+
+/// A stored transaction as exposed by the API, with its public id.
+#[derive(Debug, Serialize)]
+struct ApiTransaction {
+    id: String,
+    #[serde(flatten)]
+    transaction: TransactionData,
+    /// Registered labels of the sender and receiver, if any.
+    sender_label: Option<String>,
+    receiver_label: Option<String>,
+    commitment_status: CommitmentStatus,
+}
+
+impl ApiTransaction {
+    fn new(stored: StoredTransaction, ids: &IdCodec, labels: &LabelRegistry) -> Self {
+        ApiTransaction {
+            id: ids.encode(stored.id),
+            sender_label: labels.label(&stored.transaction.sender),
+            receiver_label: labels.label(&stored.transaction.receiver),
+            transaction: stored.transaction,
+            commitment_status: stored.commitment_status,
+        }
+    }
+}
+
+/// Addresses carrying `tag`, or an empty response if none do, so the query can be skipped.
+fn tagged_addresses(
+    labels: &LabelRegistry,
+    tag: Option<&str>,
+    encoding: Encoding,
+) -> Result<Option<Vec<String>>, HttpResponse> {
+    let Some(tag) = tag else {
+        return Ok(None);
+    };
+
+    match labels.tagged(tag) {
+        addresses if addresses.is_empty() => {
+            Err(encoding.respond(HttpResponse::Ok(), &Vec::<ApiTransaction>::new()))
+        }
+        addresses => Ok(Some(addresses)),
+    }
+}
+
+/// Cursor pagination: pass the `id` of the last transaction received as `after`.
+#[derive(Debug, Deserialize)]
+struct PageParams {
+    after: Option<String>,
+    limit: Option<i64>,
+}
+
+/// Decode an `after` cursor, defaulting to the start.
+fn parse_cursor(ids: &IdCodec, after: Option<&str>) -> Result<i64, HttpResponse> {
+    match after {
+        Some(cursor) => ids
+            .decode(cursor)
+            .ok_or_else(|| HttpResponse::BadRequest().body("Invalid cursor")),
+        None => Ok(0),
+    }
+}
+
+/// Meter rows returned to the API key a request was made with, if any.
+fn record_rows(tenant: Option<web::ReqData<Tenant>>, rows: usize) {
+    if let Some(tenant) = tenant {
+        tenant.record_rows(rows);
+    }
+}
+
+/// Whether transactions executed successfully on chain, as `?status=` of transaction listings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum ExecutionStatus {
+    Success,
+    Failed,
+}
+
+impl ExecutionStatus {
+    fn is_success(self) -> bool {
+        self == ExecutionStatus::Success
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct TransactionsParams {
+    after: Option<String>,
+    limit: Option<i64>,
+    /// Only transactions invoking this watched program.
+    program: Option<String>,
+    /// Only transactions whose memo contains this text.
+    memo_contains: Option<String>,
+    #[serde(rename = "type")]
+    txn_type: Option<TransactionType>,
+    /// Only transactions sent or received by an address with this label tag.
+    tag: Option<String>,
+    /// Only transactions auto-tagged with this category, e.g. `dex_swap`.
+    category: Option<String>,
+    /// Also return transactions dropped by a fork.
+    #[serde(default)]
+    include_dropped: bool,
+    status: Option<ExecutionStatus>,
+}
+
+/// Whether the client asked for newline-delimited JSON rather than a JSON array.
+fn accepts_ndjson(req: &HttpRequest) -> bool {
+    req.headers()
+        .get(header::ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .is_some_and(|accept| accept.contains(ExportFormat::Ndjson.content_type()))
+}
+
+/// The format to stream transactions in, if the client asked for newline-delimited JSON,
+/// MessagePack or CBOR rather than a JSON array.
+fn streamed_format(req: &HttpRequest) -> Option<ExportFormat> {
+    if accepts_ndjson(req) {
+        return Some(ExportFormat::Ndjson);
+    }
+
+    match Encoding::negotiate(req) {
+        Encoding::Json => None,
+        Encoding::MessagePack => Some(ExportFormat::Msgpack),
+        Encoding::Cbor => Some(ExportFormat::Cbor),
+    }
+}
+
+/// Weak ETag of the stored transactions in `encoding`, derived from their version, so it changes
+/// whenever a transaction is stored, updated or deleted or a label changes. The encoding is part
+/// of it, as the same page in another encoding is a different response.
+async fn transactions_etag(
+    storage: &Arc<dyn Storage>,
+    encoding: Encoding,
+) -> anyhow::Result<header::EntityTag> {
+    let mut hasher = Sha256::new();
+    hasher.update(storage.version().await?.to_be_bytes());
+    hasher.update(encoding.content_type().as_bytes());
+
+    Ok(header::EntityTag::new_weak(hex::encode(
+        &hasher.finalize()[..16],
+    )))
+}
+
+/// The ETag to answer a request for transactions in `encoding` with, unless it can't be computed,
+/// and whether the client already has the response it tags.
+async fn check_etag(
+    req: &HttpRequest,
+    storage: &Arc<dyn Storage>,
+    encoding: Encoding,
+) -> (Option<header::EntityTag>, bool) {
+    let etag = match transactions_etag(storage, encoding).await {
+        Ok(etag) => etag,
+        Err(e) => {
+            error!("Failed to compute the transactions ETag: {e:?}");
+            return (None, false);
+        }
+    };
+
+    let fresh = match req.get_header::<header::IfNoneMatch>() {
+        Some(header::IfNoneMatch::Any) => true,
+        Some(header::IfNoneMatch::Items(tags)) => tags.iter().any(|tag| tag.weak_eq(&etag)),
+        None => false,
+    };
+
+    (Some(etag), fresh)
+}
+
+/// Handler to get all transactions, streamed as newline-delimited JSON, MessagePack or CBOR if the
+/// client accepts it.
+async fn get_transactions(
+    req: HttpRequest,
+    storage: web::Data<Arc<dyn Storage>>,
+    ids: web::Data<IdCodec>,
+    labels: web::Data<Arc<LabelRegistry>>,
+    params: web::Query<TransactionsParams>,
+    tenant: Option<web::ReqData<Tenant>>,
+) -> HttpResponse {
+    let streamed = streamed_format(&req);
+
+    // backends disagree on what a non-positive limit means, so none reaches them
+    if params.limit.is_some_and(|limit| limit < 1) {
+        return HttpResponse::BadRequest().body("`limit` must be at least 1");
+    }
+
+    let after = match parse_cursor(&ids, params.after.as_deref()) {
+        Ok(after) => after,
+        Err(res) => return res,
+    };
+
+    let program = match params.program.as_deref().map(parse_pubkey).transpose() {
+        Ok(program) => program,
+        Err(res) => return res,
+    };
+
+    let addresses = match tagged_addresses(&labels, params.tag.as_deref(), Encoding::Json) {
+        Ok(addresses) => addresses,
+        Err(res) => {
+            return match streamed {
+                Some(format) => HttpResponse::Ok()
+                    .content_type(format.content_type())
+                    .finish(),
+                None => res,
+            }
+        }
+    };
+
+    let query = TransactionQuery {
+        addresses,
+        after,
+        program,
+        memo_contains: params.memo_contains.clone(),
+        txn_type: params.txn_type,
+        category: params.category.clone(),
+        include_dropped: params.include_dropped,
+        success: params.status.map(ExecutionStatus::is_success),
+        limit: params.limit,
+        ..TransactionQuery::default()
+    };
+
+    if let Some(format) = streamed {
+        return HttpResponse::Ok()
+            .content_type(format.content_type())
+            .insert_header((header::VARY, "Accept"))
+            .streaming(export::export(
+                Arc::clone(&storage),
+                IdCodec::clone(&ids),
+                Arc::clone(&labels),
+                query,
+                format,
+                tenant.map(web::ReqData::into_inner),
+            ));
+    }
+
+    // anything not streamed is JSON
+    let (etag, fresh) = check_etag(&req, &storage, Encoding::Json).await;
+
+    if let Some(etag) = etag.clone().filter(|_| fresh) {
+        return HttpResponse::NotModified()
+            .insert_header(header::ETag(etag))
+            .insert_header((header::VARY, "Accept"))
+            .finish();
+    }
+
+    match storage.query(&query).await {
+        Ok(transactions) => {
+            record_rows(tenant, transactions.len());
+
+            let mut res = HttpResponse::Ok();
+            res.insert_header((header::VARY, "Accept"));
+            if let Some(etag) = etag {
+                res.insert_header(header::ETag(etag));
+            }
+
+            res.json(
+                transactions
+                    .into_iter()
+                    .map(|stored| ApiTransaction::new(stored, &ids, &labels))
+                    .collect::<Vec<_>>(),
+            )
+        }
+        Err(_) => HttpResponse::Ok().json(Vec::<ApiTransaction>::new()),
+    }
+}
+
+/// Handler to get a single transaction by its signature.
+async fn get_transaction(
+    req: HttpRequest,
+    storage: web::Data<Arc<dyn Storage>>,
+    ids: web::Data<IdCodec>,
+    labels: web::Data<Arc<LabelRegistry>>,
+    path: web::Path<String>,
+    tenant: Option<web::ReqData<Tenant>>,
+) -> HttpResponse {
+    match storage.get_by_signature(&path.into_inner()).await {
+        Ok(Some(stored)) => {
+            record_rows(tenant, 1);
+            Encoding::negotiate(&req).respond(
+                HttpResponse::Ok(),
+                &ApiTransaction::new(stored, &ids, &labels),
+            )
+        }
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            error!("Failed to fetch transaction: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Run `task` on the main runtime rather than the actix worker's, for handlers that make RPC
+/// calls.
+async fn on_main_runtime<T: Send + 'static>(
+    runtime: &Handle,
+    task: impl Future<Output = T> + Send + 'static,
+) -> anyhow::Result<T> {
+    Ok(runtime.spawn(task).await?)
+}
+
+/// What became of a transaction looked up on chain.
+enum Lookup {
+    NotFound,
+    /// Stored, unless it failed validation or the pipeline settings left it out.
+    Processed,
+    FetchFailed(anyhow::Error),
+    StoreFailed(anyhow::Error),
+}
+
+/// Fetch a transaction from the RPC node, then process and store it.
+async fn fetch_and_store(
+    solana_client: Arc<SolanaClient>,
+    storage: Arc<dyn Storage>,
+    db: Option<Arc<PgPool>>,
+    signature: Signature,
+) -> Lookup {
+    solana_client.wait_for_memory().await;
+
+    let txns = match solana_client.fetch_transactions(&[signature]).await {
+        Ok(txns) if txns.is_empty() => return Lookup::NotFound,
+        Ok(txns) => txns,
+        Err(e) => return Lookup::FetchFailed(e),
+    };
+
+    let fetched = solana_client.charge_fetched(&txns);
+    let batch = solana_client.process(txns, None);
+    drop(fetched);
+    let _processed = solana_client.charge_processed(&batch);
+
+    match solana_client.store(&batch, &storage, db.as_ref()).await {
+        Ok(_) => Lookup::Processed,
+        Err(e) => Lookup::StoreFailed(e),
+    }
+}
+
+/// Handler to get a transaction, fetching it from the RPC node, then processing and storing it,
+/// if it isn't stored yet.
+#[allow(clippy::too_many_arguments)]
+async fn lookup_transaction(
+    req: HttpRequest,
+    storage: web::Data<Arc<dyn Storage>>,
+    ids: web::Data<IdCodec>,
+    labels: web::Data<Arc<LabelRegistry>>,
+    solana_client: web::Data<Arc<SolanaClient>>,
+    runtime: web::Data<Handle>,
+    db: Option<web::Data<Arc<PgPool>>>,
+    path: web::Path<String>,
+    tenant: Option<web::ReqData<Tenant>>,
+) -> HttpResponse {
+    let signature = path.into_inner();
+    let encoding = Encoding::negotiate(&req);
+
+    let Ok(parsed) = Signature::from_str(&signature) else {
+        return HttpResponse::BadRequest().body("Invalid signature");
+    };
+
+    match storage.get_by_signature(&signature).await {
+        Ok(Some(stored)) => {
+            record_rows(tenant, 1);
+            return encoding.respond(
+                HttpResponse::Ok(),
+                &ApiTransaction::new(stored, &ids, &labels),
+            );
+        }
+        Ok(None) => {}
+        Err(e) => {
+            error!("Failed to fetch transaction: {e:?}");
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    let lookup = fetch_and_store(
+        Arc::clone(&solana_client),
+        Arc::clone(&storage),
+        db.map(|db| Arc::clone(&db)),
+        parsed,
+    );
+
+    match on_main_runtime(&runtime, lookup).await {
+        Ok(Lookup::Processed) => {}
+        Ok(Lookup::NotFound) => {
+            return HttpResponse::NotFound().body("Transaction not found on chain")
+        }
+        Ok(Lookup::FetchFailed(e)) => {
+            error!("Failed to look up transaction {signature}: {e:?}");
+            return HttpResponse::BadGateway().finish();
+        }
+        Ok(Lookup::StoreFailed(e)) | Err(e) => {
+            error!("Failed to store looked up transaction {signature}: {e:?}");
+            return HttpResponse::InternalServerError().finish();
+        }
+    }
+
+    match storage.get_by_signature(&signature).await {
+        Ok(Some(stored)) => {
+            record_rows(tenant, 1);
+            encoding.respond(
+                HttpResponse::Ok(),
+                &ApiTransaction::new(stored, &ids, &labels),
+            )
+        }
+        Ok(None) => HttpResponse::UnprocessableEntity()
+            .body("Transaction failed validation or was left out by the pipeline settings"),
+        Err(e) => {
+            error!("Failed to fetch transaction: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Handler to get the current state of an account, read live from the RPC node.
+async fn get_account_details(
+    accounts: web::Data<Arc<AccountCache>>,
+    runtime: web::Data<Handle>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let Ok(address) = Pubkey::from_str(&path) else {
+        return HttpResponse::BadRequest().body("Invalid public key");
+    };
+
+    let accounts = Arc::clone(&accounts);
+    let details = on_main_runtime(&runtime, async move { accounts.get(&address).await }).await;
+
+    match details.and_then(|details| details) {
+        Ok(Some(details)) => HttpResponse::Ok().json(details),
+        Ok(None) => HttpResponse::NotFound().body("Account not found"),
+        Err(e) => {
+            error!("Failed to fetch account {address}: {e:?}");
+            HttpResponse::BadGateway().finish()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct GraphParams {
+    address: String,
+    depth: Option<usize>,
+    /// Time range, in unix seconds.
+    from: Option<i64>,
+    to: Option<i64>,
+}
+
+/// Handler for the directed graph of SOL and token flows around an address.
+async fn get_transfer_graph(
+    storage: web::Data<Arc<dyn Storage>>,
+    labels: web::Data<Arc<LabelRegistry>>,
+    db: Option<web::Data<Arc<PgPool>>>,
+    params: web::Query<GraphParams>,
+) -> HttpResponse {
+    let address = match parse_pubkey(&params.address) {
+        Ok(address) => address,
+        Err(res) => return res,
+    };
+
+    let depth = params.depth.unwrap_or(2);
+
+    if !(1..=graph::MAX_DEPTH).contains(&depth) {
+        return HttpResponse::BadRequest().body(format!(
+            "`depth` must be between 1 and {}",
+            graph::MAX_DEPTH
+        ));
+    }
+
+    let scope = GraphScope {
+        from: params.from,
+        to: params.to,
+    };
+
+    match graph::transfer_graph(
+        &storage,
+        db.as_ref().map(|db| db.get_ref()),
+        &labels,
+        &address,
+        depth,
+        scope,
+    )
+    .await
+    {
+        Ok(graph) => HttpResponse::Ok().json(graph),
+        Err(e) => {
+            error!("Failed to build transfer graph of {address}: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Handler to get the archived raw RPC payload of a transaction.
+async fn get_raw_transaction(
+    db: web::Data<Arc<PgPool>>,
+    archive: web::Data<Arc<RawArchive>>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    match archive.fetch(&db, &path.into_inner()).await {
+        Ok(Some(json)) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(json),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            error!("Failed to fetch raw transaction: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Handler to execute a GraphQL query.
+async fn post_graphql(
+    schema: web::Data<AggregatorSchema>,
+    request: GraphQLRequest,
+) -> GraphQLResponse {
+    schema.execute(request.into_inner()).await.into()
+}
+
+/// Handler to serve GraphiQL, for exploring the GraphQL schema from a browser.
+async fn get_graphiql() -> HttpResponse {
+    HttpResponse::Ok()
+        .content_type("text/html; charset=utf-8")
+        .body(GraphiQLSource::build().endpoint("/graphql").finish())
+}
+
+/// Validate a pubkey taken from the request path.
+fn parse_pubkey(pubkey: &str) -> Result<String, HttpResponse> {
+    Pubkey::from_str(pubkey)
+        .map(|pubkey| pubkey.to_string())
+        .map_err(|_| HttpResponse::BadRequest().body("Invalid public key"))
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+/// Handler to get statistics over all stored transactions.
+async fn get_stats(db: web::Data<Arc<PgPool>>, stats: web::Data<Arc<StatsCache>>) -> HttpResponse {
+    match stats.get(&db, None).await {
+        Ok(stats) => HttpResponse::Ok().json(stats),
+        Err(e) => {
+            error!("Failed to compute statistics: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Handler to get statistics over the transactions an account sent or received.
+async fn get_account_stats(
+    db: web::Data<Arc<PgPool>>,
+    stats: web::Data<Arc<StatsCache>>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    let pubkey = match parse_pubkey(&path) {
+        Ok(pubkey) => pubkey,
+        Err(res) => return res,
+    };
+
+    match stats.get(&db, Some(&pubkey)).await {
+        Ok(stats) => HttpResponse::Ok().json(stats),
+        Err(e) => {
+            error!("Failed to compute account statistics: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Time range (unix seconds) and page of an account's counterparties.
+#[derive(Debug, Deserialize)]
+struct CounterpartiesParams {
+    from: Option<i64>,
+    to: Option<i64>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// A counterparty with its label, if it has one.
+#[derive(Debug, Serialize)]
+struct ApiCounterparty {
+    #[serde(flatten)]
+    counterparty: CounterpartySummary,
+    label: Option<String>,
+}
+
+/// Handler to get the addresses an account transacted with, and what it exchanged with each.
+async fn get_account_counterparties(
+    db: web::Data<Arc<PgPool>>,
+    labels: web::Data<Arc<LabelRegistry>>,
+    path: web::Path<String>,
+    params: web::Query<CounterpartiesParams>,
+    tenant: Option<web::ReqData<Tenant>>,
+) -> HttpResponse {
+    let pubkey = match parse_pubkey(&path) {
+        Ok(pubkey) => pubkey,
+        Err(res) => return res,
+    };
+
+    let limit = params.limit.unwrap_or(100).clamp(1, 1000);
+    let offset = params.offset.unwrap_or(0).max(0);
+
+    match get_counterparties(&db, &pubkey, params.from, params.to, limit, offset).await {
+        Ok(counterparties) => {
+            record_rows(tenant, counterparties.len());
+
+            HttpResponse::Ok().json(
+                counterparties
+                    .into_iter()
+                    .map(|counterparty| ApiCounterparty {
+                        label: labels.label(&counterparty.address),
+                        counterparty,
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        }
+        Err(e) => {
+            error!("Failed to fetch counterparties: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Granularity and time range (unix seconds) of an account's activity heatmap.
+#[derive(Debug, Deserialize)]
+struct ActivityParams {
+    #[serde(default)]
+    granularity: RollupInterval,
+    from: Option<i64>,
+    to: Option<i64>,
+}
+
+/// Days an activity heatmap covers when no `from` is given, by hour (whole weeks, so every weekday
+/// counts alike) and by day, and at most.
+const DEFAULT_HOURLY_ACTIVITY_DAYS: i64 = 28;
+const DEFAULT_DAILY_ACTIVITY_DAYS: i64 = 364;
+const MAX_ACTIVITY_DAYS: i64 = 366;
+
+/// Handler to get an account's transaction counts and volume as a weekday by hour, or calendar,
+/// heatmap.
+async fn get_account_activity(
+    db: web::Data<Arc<PgPool>>,
+    path: web::Path<String>,
+    params: web::Query<ActivityParams>,
+) -> HttpResponse {
+    let pubkey = match parse_pubkey(&path) {
+        Ok(pubkey) => pubkey,
+        Err(res) => return res,
+    };
+
+    let default_days = match params.granularity {
+        RollupInterval::Hour => DEFAULT_HOURLY_ACTIVITY_DAYS,
+        RollupInterval::Day => DEFAULT_DAILY_ACTIVITY_DAYS,
+    };
+    let to = params.to.unwrap_or_else(unix_now);
+
+    let Some(from) = activity_from(params.from, to, default_days) else {
+        return HttpResponse::BadRequest().body(format!(
+            "`from` must be after 1970 and before `to`, at most {MAX_ACTIVITY_DAYS} days apart"
+        ));
+    };
+
+    let by_hour = params.granularity == RollupInterval::Hour;
+
+    match get_activity_cells(&db, &pubkey, by_hour, from, to).await {
+        Ok(cells) => {
+            HttpResponse::Ok().json(ActivityHeatmap::new(params.granularity, from, to, &cells))
+        }
+        Err(e) => {
+            error!("Failed to compute account activity: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// The start of an activity range ending at `to`, `default_days` before it unless given. `None`
+/// unless it's after the epoch, before `to` and at most `MAX_ACTIVITY_DAYS` apart, including for
+/// bounds so far apart their span overflows.
+fn activity_from(from: Option<i64>, to: i64, default_days: i64) -> Option<i64> {
+    let from = match from {
+        Some(from) => from,
+        None => to.checked_sub(default_days * 86_400)?,
+    };
+    let span = to.checked_sub(from)?;
+
+    (from >= 0 && span > 0 && span <= MAX_ACTIVITY_DAYS * 86_400).then_some(from)
+}
+
+/// Handler to get the latest recorded balance of an account.
+async fn get_account_balance(db: web::Data<Arc<PgPool>>, path: web::Path<String>) -> HttpResponse {
+    let pubkey = match parse_pubkey(&path) {
+        Ok(pubkey) => pubkey,
+        Err(res) => return res,
+    };
+
+    match get_latest_balance(&db, &pubkey).await {
+        Ok(Some(balance)) => HttpResponse::Ok().json(balance),
+        Ok(None) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            error!("Failed to fetch balance: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct PortfolioParams {
+    /// Also list token accounts with a zero balance.
+    include_zero: Option<bool>,
+}
+
+/// Handler to get an overview of an account's balances and recent activity.
+async fn get_account_portfolio(
+    db: web::Data<Arc<PgPool>>,
+    portfolios: web::Data<Arc<Portfolios>>,
+    runtime: web::Data<Handle>,
+    path: web::Path<String>,
+    params: web::Query<PortfolioParams>,
+) -> HttpResponse {
+    let Ok(address) = Pubkey::from_str(&path) else {
+        return HttpResponse::BadRequest().body("Invalid public key");
+    };
+
+    let (portfolios, db) = (Arc::clone(&portfolios), Arc::clone(&db));
+    let include_zero = params.include_zero.unwrap_or(false);
+    let portfolio = on_main_runtime(&runtime, async move {
+        portfolios.get(&address, include_zero, &db).await
+    })
+    .await;
+
+    match portfolio.and_then(|portfolio| portfolio) {
+        Ok(portfolio) => HttpResponse::Ok().json(portfolio),
+        Err(e) => {
+            error!("Failed to build the portfolio of {address}: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Time range (unix seconds) and page size for history queries.
+#[derive(Debug, Deserialize)]
+struct HistoryParams {
+    from: Option<i64>,
+    to: Option<i64>,
+    limit: Option<i64>,
+}
+
+/// Handler to get the recorded balance history of an account.
+async fn get_account_balance_history(
+    db: web::Data<Arc<PgPool>>,
+    path: web::Path<String>,
+    params: web::Query<HistoryParams>,
+    tenant: Option<web::ReqData<Tenant>>,
+) -> HttpResponse {
+    let pubkey = match parse_pubkey(&path) {
+        Ok(pubkey) => pubkey,
+        Err(res) => return res,
+    };
+
+    match get_balance_history(&db, &pubkey, params.from, params.to, params.limit).await {
+        Ok(history) => {
+            record_rows(tenant, history.len());
+            HttpResponse::Ok().json(history)
+        }
+        Err(e) => {
+            error!("Failed to fetch balance history: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Mint, time range (unix seconds) and size of an account's token balance history.
+#[derive(Debug, Deserialize)]
+struct TokenBalanceParams {
+    mint: Option<String>,
+    from: Option<i64>,
+    to: Option<i64>,
+    limit: Option<i64>,
+}
+
+/// A token account's current balance, also in whole tokens.
+#[derive(Debug, Serialize)]
+struct ApiTokenHolding {
+    #[serde(flatten)]
+    balance: TokenBalance,
+    ui_amount: f64,
+}
+
+/// Recorded balances of one token account, oldest first.
+#[derive(Debug, Serialize)]
+struct TokenSeries {
+    account: String,
+    mint: String,
+    decimals: i16,
+    points: Vec<TokenPoint>,
+}
+
+#[derive(Debug, Serialize)]
+struct TokenPoint {
+    recorded_at: i64,
+    amount: i64,
+}
+
+/// An account's current token holdings and their history, as snapshotted by balance tracking.
+#[derive(Debug, Serialize)]
+struct ApiTokenBalances {
+    holdings: Vec<ApiTokenHolding>,
+    history: Vec<TokenSeries>,
+}
+
+/// Group balance changes into a series per token account, in order of their first change.
+fn token_series(history: Vec<TokenBalance>) -> Vec<TokenSeries> {
+    let mut series = Vec::<TokenSeries>::new();
+    let mut positions = HashMap::new();
+
+    for balance in history {
+        let position = *positions.entry(balance.account.clone()).or_insert_with(|| {
+            series.push(TokenSeries {
+                account: balance.account.clone(),
+                mint: balance.mint.clone(),
+                decimals: balance.decimals,
+                points: Vec::new(),
+            });
+            series.len() - 1
+        });
+
+        series[position].points.push(TokenPoint {
+            recorded_at: balance.recorded_at,
+            amount: balance.amount,
+        });
+    }
+
+    series
+}
+
+/// Handler to get the token holdings of a watched account, and how their balances changed.
+async fn get_account_tokens(
+    db: web::Data<Arc<PgPool>>,
+    path: web::Path<String>,
+    params: web::Query<TokenBalanceParams>,
+    tenant: Option<web::ReqData<Tenant>>,
+) -> HttpResponse {
+    let pubkey = match parse_pubkey(&path) {
+        Ok(pubkey) => pubkey,
+        Err(res) => return res,
+    };
+
+    let mint = match params.mint.as_deref().map(parse_pubkey).transpose() {
+        Ok(mint) => mint,
+        Err(res) => return res,
+    };
+
+    let limit = params.limit.unwrap_or(1000).clamp(1, 10_000);
+
+    let holdings = match get_current_token_balances(&db, &pubkey).await {
+        Ok(holdings) => holdings,
+        Err(e) => {
+            error!("Failed to fetch token balances: {e:?}");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let history = match get_token_balance_history(
+        &db,
+        &pubkey,
+        mint.as_deref(),
+        params.from,
+        params.to,
+        Some(limit),
+    )
+    .await
+    {
+        Ok(history) => history,
+        Err(e) => {
+            error!("Failed to fetch token balance history: {e:?}");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let holdings = holdings
+        .into_iter()
+        .filter(|balance| mint.as_ref().map_or(true, |mint| &balance.mint == mint))
+        .map(|balance| ApiTokenHolding {
+            ui_amount: balance.amount as f64 / 10f64.powi(i32::from(balance.decimals)),
+            balance,
+        })
+        .collect::<Vec<_>>();
+
+    record_rows(tenant, holdings.len() + history.len());
+
+    HttpResponse::Ok().json(ApiTokenBalances {
+        holdings,
+        history: token_series(history),
+    })
+}
+
+/// Cursor pagination plus a time range (unix seconds), slot range, type, counterparty tag and
+/// execution status for an account's transactions.
+#[derive(Debug, Deserialize)]
+struct AccountTransactionParams {
+    after: Option<String>,
+    from: Option<i64>,
+    to: Option<i64>,
+    from_slot: Option<i64>,
+    to_slot: Option<i64>,
+    #[serde(rename = "type")]
+    txn_type: Option<TransactionType>,
+    /// Only transactions with an address carrying this label tag.
+    tag: Option<String>,
+    category: Option<String>,
+    #[serde(default)]
+    include_dropped: bool,
+    status: Option<ExecutionStatus>,
+    limit: Option<i64>,
+}
+
+/// Handler to get the transactions an account sent or received.
+async fn get_account_transactions(
+    req: HttpRequest,
+    storage: web::Data<Arc<dyn Storage>>,
+    ids: web::Data<IdCodec>,
+    labels: web::Data<Arc<LabelRegistry>>,
+    path: web::Path<String>,
+    params: web::Query<AccountTransactionParams>,
+    tenant: Option<web::ReqData<Tenant>>,
+) -> HttpResponse {
+    let encoding = Encoding::negotiate(&req);
+
+    let pubkey = match parse_pubkey(&path) {
+        Ok(pubkey) => pubkey,
+        Err(res) => return res,
+    };
+
+    let after = match parse_cursor(&ids, params.after.as_deref()) {
+        Ok(after) => after,
+        Err(res) => return res,
+    };
+
+    let addresses = match tagged_addresses(&labels, params.tag.as_deref(), encoding) {
+        Ok(addresses) => addresses,
+        Err(res) => return res,
+    };
+
+    let query = TransactionQuery {
+        address: Some(pubkey),
+        addresses,
+        after,
+        from: params.from,
+        to: params.to,
+        from_slot: params.from_slot,
+        to_slot: params.to_slot,
+        program: None,
+        memo_contains: None,
+        txn_type: params.txn_type,
+        category: params.category.clone(),
+        include_dropped: params.include_dropped,
+        success: params.status.map(ExecutionStatus::is_success),
+        limit: Some(params.limit.unwrap_or(100).clamp(1, 1000)),
+    };
+
+    let (etag, fresh) = check_etag(&req, &storage, encoding).await;
+
+    if let Some(etag) = etag.clone().filter(|_| fresh) {
+        return HttpResponse::NotModified()
+            .insert_header(header::ETag(etag))
+            .insert_header((header::VARY, "Accept"))
+            .finish();
+    }
+
+    match storage.query(&query).await {
+        Ok(transactions) => {
+            record_rows(tenant, transactions.len());
+
+            let mut res = HttpResponse::Ok();
+            if let Some(etag) = etag {
+                res.insert_header(header::ETag(etag));
+            }
+
+            encoding.respond(
+                res,
+                &transactions
+                    .into_iter()
+                    .map(|stored| ApiTransaction::new(stored, &ids, &labels))
+                    .collect::<Vec<_>>(),
+            )
+        }
+        Err(e) => {
+            error!("Failed to fetch account transactions: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// One stored version of a watched account's data.
+#[derive(Debug, Serialize)]
+struct AccountVersion {
+    slot: u64,
+    recorded_at: i64,
+    owner: String,
+    lamports: u64,
+    /// Base64-encoded account data.
+    data: String,
+    /// Data decoded with a registered layout of the owner program, if one matches.
+    decoded: Option<DecodedAccount>,
+    /// Changed byte ranges relative to the previous version; `None` for the first version.
+    changes: Option<Vec<ByteChange>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LimitParams {
+    limit: Option<i64>,
+}
+
+/// Handler to get the change history of a watched account's data, newest first.
+async fn get_account_changes(
+    db: web::Data<Arc<PgPool>>,
+    layouts: web::Data<Arc<LayoutRegistry>>,
+    path: web::Path<String>,
+    params: web::Query<LimitParams>,
+    tenant: Option<web::ReqData<Tenant>>,
+) -> HttpResponse {
+    let pubkey = match parse_pubkey(&path) {
+        Ok(pubkey) => pubkey,
+        Err(res) => return res,
+    };
+
+    let limit = params.limit.unwrap_or(20).clamp(1, 1000);
+
+    // one extra version so the oldest one returned can still be diffed
+    let snapshots = match get_account_snapshots(&db, &pubkey, limit + 1).await {
+        Ok(snapshots) => snapshots,
+        Err(e) => {
+            error!("Failed to fetch account snapshots: {e:?}");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let versions = snapshots
+        .iter()
+        .enumerate()
+        .take(limit as usize)
+        .map(|(i, snapshot)| AccountVersion {
+            slot: snapshot.slot,
+            recorded_at: snapshot.recorded_at,
+            owner: snapshot.owner.clone(),
+            lamports: snapshot.lamports,
+            data: BASE64.encode(&snapshot.data),
+            decoded: layouts.decode(&snapshot.owner, &snapshot.data),
+            changes: snapshots
+                .get(i + 1)
+                .map(|previous| diff_account_data(&previous.data, &snapshot.data)),
+        })
+        .collect::<Vec<_>>();
+
+    record_rows(tenant, versions.len());
+    HttpResponse::Ok().json(versions)
+}
+
+/// Handler to list the registered account data layouts.
+async fn get_layouts(layouts: web::Data<Arc<LayoutRegistry>>) -> HttpResponse {
+    HttpResponse::Ok().json(layouts.layouts())
+}
+
+/// Handler to register an account data layout, persisting it across restarts.
+async fn post_layout(
+    db: web::Data<Arc<PgPool>>,
+    layouts: web::Data<Arc<LayoutRegistry>>,
+    layout: web::Json<AccountLayout>,
+) -> HttpResponse {
+    let layout = layout.into_inner();
+
+    if let Err(e) = layouts.register(layout.clone()) {
+        return HttpResponse::BadRequest().body(e.to_string());
+    }
+
+    match upsert_account_layout(&db, &layout).await {
+        Ok(()) => HttpResponse::Created().json(layout),
+        Err(e) => {
+            error!("Failed to store account layout: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LabelsParams {
+    tag: Option<String>,
+}
+
+/// Handler to list the registered address labels, optionally only those with a tag.
+async fn get_labels(
+    labels: web::Data<Arc<LabelRegistry>>,
+    params: web::Query<LabelsParams>,
+) -> HttpResponse {
+    let mut labels = labels.labels();
+
+    if let Some(tag) = &params.tag {
+        let tag = tag.trim().to_lowercase();
+        labels.retain(|label| label.tags.contains(&tag));
+    }
+
+    HttpResponse::Ok().json(labels)
+}
+
+#[derive(Debug, Deserialize)]
+struct DirectoryParams {
+    kind: Option<EntryKind>,
+}
+
+/// Handler to list the directory of well-known programs and exchange wallets.
+async fn get_directory(
+    directory: web::Data<Arc<Directory>>,
+    params: web::Query<DirectoryParams>,
+) -> HttpResponse {
+    let entries = directory
+        .entries()
+        .into_iter()
+        .filter(|entry| params.kind.map_or(true, |kind| entry.kind == kind))
+        .collect::<Vec<_>>();
+
+    HttpResponse::Ok().json(entries)
+}
+
+/// Handler to label an address, persisting the label across restarts.
+async fn post_label(
+    db: web::Data<Arc<PgPool>>,
+    labels: web::Data<Arc<LabelRegistry>>,
+    label: web::Json<Label>,
+) -> HttpResponse {
+    let label = match label.into_inner().normalized() {
+        Ok(label) => label,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+
+    // stored first, so a label is never served that a restart would lose
+    if let Err(e) = upsert_label(&db, &label, unix_now()).await {
+        error!("Failed to store label: {e:?}");
+        return HttpResponse::InternalServerError().finish();
+    }
+
+    match labels.register(label) {
+        Ok(label) => HttpResponse::Created().json(label),
+        Err(e) => HttpResponse::BadRequest().body(e.to_string()),
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportParams {
+    /// Most recent transactions to backfill per newly watched address, if any.
+    backfill: Option<usize>,
+}
+
+/// Handler to watch a JSON or CSV list of addresses with optional labels, optionally
+/// backfilling the recent transactions of those that weren't watched yet in the background.
+#[allow(clippy::too_many_arguments)]
+async fn import_addresses(
+    req: HttpRequest,
+    body: web::Bytes,
+    storage: web::Data<Arc<dyn Storage>>,
+    db: web::Data<Arc<PgPool>>,
+    watchlist: web::Data<Arc<Watchlist>>,
+    labels: web::Data<Arc<LabelRegistry>>,
+    solana_client: Option<web::Data<Arc<SolanaClient>>>,
+    runtime: web::Data<Handle>,
+    params: web::Query<ImportParams>,
+) -> HttpResponse {
+    if params.backfill.is_some() && solana_client.is_none() {
+        return HttpResponse::BadRequest().body("Backfills need transactions to be stored");
+    }
+
+    let format = ListFormat::from_content_type(req.content_type());
+    let entries = match imports::parse(&body, format) {
+        Ok(entries) => entries,
+        Err(e) => return HttpResponse::BadRequest().body(format!("Invalid address list: {e}")),
+    };
+
+    let mut report = match imports::import(entries, &watchlist, &labels, &db).await {
+        Ok(report) => report,
+        Err(e) => {
+            error!("Failed to import addresses: {e:?}");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    if let (Some(limit), Some(solana_client)) = (params.backfill, solana_client) {
+        let added = report
+            .added
+            .iter()
+            .filter_map(|pubkey| Pubkey::from_str(pubkey).ok())
+            .collect::<Vec<_>>();
+        report.backfilling = Some(added.len());
+
+        // on the main runtime, like other RPC calls, which also outlives this worker
+        if !added.is_empty() {
+            runtime.spawn(imports::backfill(
+                Arc::clone(&solana_client),
+                Arc::clone(&storage),
+                Arc::clone(&db),
+                added,
+                limit,
+            ));
+        }
+    }
+
+    HttpResponse::Ok().json(report)
+}
+
+#[derive(Debug, Deserialize)]
+struct AddressExportParams {
+    #[serde(default)]
+    format: ListFormat,
+}
+
+/// Handler to download the watched addresses with their labels, as JSON or CSV.
+async fn export_addresses(
+    watchlist: web::Data<Arc<Watchlist>>,
+    labels: web::Data<Arc<LabelRegistry>>,
+    params: web::Query<AddressExportParams>,
+) -> HttpResponse {
+    match imports::write(&imports::watched(&watchlist, &labels), params.format) {
+        Ok(body) => HttpResponse::Ok()
+            .content_type(params.format.content_type())
+            .body(body),
+        Err(e) => {
+            error!("Failed to export addresses: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Handler to list the alert rules.
+async fn list_alert_rules(alerts: web::Data<Arc<AlertEngine>>) -> HttpResponse {
+    HttpResponse::Ok().json(alerts.rules())
+}
+
+/// Handler to create an alert rule. Webhook deliveries of rules created with an API key count
+/// towards its usage.
+async fn create_alert_rule(
+    alerts: web::Data<Arc<AlertEngine>>,
+    rule: web::Json<NewAlertRule>,
+    tenant: Option<web::ReqData<Tenant>>,
+) -> HttpResponse {
+    let rule = match alerts.check(rule.into_inner()) {
+        Ok(rule) => rule,
+        Err(e) => return HttpResponse::BadRequest().body(e.to_string()),
+    };
+
+    match alerts
+        .create(rule, tenant.map(|tenant| tenant.key_id()))
+        .await
+    {
+        Ok(rule) => HttpResponse::Created().json(rule),
+        Err(e) => {
+            error!("Failed to store alert rule: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Handler to delete an alert rule.
+async fn remove_alert_rule(
+    alerts: web::Data<Arc<AlertEngine>>,
+    path: web::Path<i32>,
+) -> HttpResponse {
+    match alerts.remove(path.into_inner()).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().finish(),
+        Err(e) => {
+            error!("Failed to delete alert rule: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
     }
 }
 
-#[actix_web::main]
-pub async fn main(db: Arc<PgPool>) -> std::io::Result<()> {
-    HttpServer::new(move || {
-        App::new()
-            .app_data(web::Data::new(db.clone()))
-            .route("/transactions", web::get().to(get_transactions))
+/// Handler to remove the label of an address.
+async fn remove_label(
+    db: web::Data<Arc<PgPool>>,
+    labels: web::Data<Arc<LabelRegistry>>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    match delete_label(&db, &path).await {
+        Ok(deleted) => {
+            // also forgotten if the row was already gone
+            if labels.remove(&path) || deleted {
+                HttpResponse::NoContent().finish()
+            } else {
+                HttpResponse::NotFound().finish()
+            }
+        }
+        Err(e) => {
+            error!("Failed to delete label: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct DailyReportParams {
+    /// `YYYY-MM-DD`, the previous UTC day by default.
+    date: Option<String>,
+    #[serde(default)]
+    format: ReportFormat,
+}
+
+/// Handler to download the watched addresses' activity reports of a day, as JSON or CSV.
+async fn get_daily_report(
+    db: web::Data<Arc<PgPool>>,
+    params: web::Query<DailyReportParams>,
+) -> HttpResponse {
+    let date = params.date.clone().unwrap_or_else(reports::previous_day);
+
+    if reports::day_start(&date).is_none() {
+        return HttpResponse::BadRequest().body("Invalid date: expected YYYY-MM-DD");
+    }
+
+    let daily_reports = match get_daily_reports(&db, &date).await {
+        Ok(daily_reports) if daily_reports.is_empty() => {
+            return HttpResponse::NotFound().body(format!("No reports for {date}"));
+        }
+        Ok(daily_reports) => daily_reports,
+        Err(e) => {
+            error!("Failed to get daily reports: {e:?}");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    match params.format {
+        ReportFormat::Json => HttpResponse::Ok().json(daily_reports),
+        ReportFormat::Csv => match reports::reports_csv(&daily_reports) {
+            Ok(body) => HttpResponse::Ok()
+                .content_type("text/csv; charset=utf-8")
+                .insert_header((
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"daily-report-{date}.csv\""),
+                ))
+                .body(body),
+            Err(e) => {
+                error!("Failed to write daily reports: {e:?}");
+                HttpResponse::InternalServerError().finish()
+            }
+        },
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct EpochRangeParams {
+    from_epoch: Option<i64>,
+    to_epoch: Option<i64>,
+}
+
+/// Handler to get burned versus validator fees, aggregated per epoch.
+async fn get_fee_burn(
+    db: web::Data<Arc<PgPool>>,
+    params: web::Query<EpochRangeParams>,
+) -> HttpResponse {
+    match get_fee_burn_by_epoch(&db, params.from_epoch, params.to_epoch).await {
+        Ok(epochs) => HttpResponse::Ok().json(epochs),
+        Err(e) => {
+            error!("Failed to fetch fee burn stats: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Handler to get the current epoch and how far into it the cluster is.
+async fn get_current_epoch(epochs: web::Data<Arc<EpochTracker>>) -> HttpResponse {
+    match epochs.current() {
+        Some(current) => HttpResponse::Ok().json(current),
+        None => HttpResponse::ServiceUnavailable().body("Epoch not fetched yet"),
+    }
+}
+
+/// Handler to get the stored transactions of an epoch.
+async fn get_transactions_in_epoch(
+    req: HttpRequest,
+    db: web::Data<Arc<PgPool>>,
+    ids: web::Data<IdCodec>,
+    labels: web::Data<Arc<LabelRegistry>>,
+    path: web::Path<i64>,
+    params: web::Query<PageParams>,
+    tenant: Option<web::ReqData<Tenant>>,
+) -> HttpResponse {
+    let after = match parse_cursor(&ids, params.after.as_deref()) {
+        Ok(after) => after,
+        Err(res) => return res,
+    };
+    let limit = params.limit.unwrap_or(100).clamp(1, 1000);
+
+    match get_epoch_transactions(&db, path.into_inner(), after, limit).await {
+        Ok(transactions) => {
+            record_rows(tenant, transactions.len());
+
+            Encoding::negotiate(&req).respond(
+                HttpResponse::Ok(),
+                &transactions
+                    .into_iter()
+                    .map(|stored| ApiTransaction::new(stored, &ids, &labels))
+                    .collect::<Vec<_>>(),
+            )
+        }
+        Err(e) => {
+            error!("Failed to fetch epoch transactions: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Search query and results per kind.
+#[derive(Debug, Deserialize)]
+struct SearchParams {
+    q: String,
+    limit: Option<i64>,
+}
+
+/// What a transaction was found by.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "lowercase")]
+enum SearchMatch {
+    Signature,
+    Memo,
+}
+
+/// A search result, tagged with its `type`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum SearchResult {
+    Transaction {
+        matched: SearchMatch,
+        #[serde(flatten)]
+        transaction: ApiTransaction,
+    },
+    Address {
+        #[serde(flatten)]
+        address: AddressMatch,
+        label: Option<String>,
+    },
+    Label {
+        #[serde(flatten)]
+        label: Label,
+    },
+}
+
+#[derive(Debug, Serialize)]
+struct ApiSearchResults {
+    query: String,
+    shape: QueryShape,
+    results: Vec<SearchResult>,
+}
+
+/// Handler to search transactions by signature or memo, and addresses by public key, prefix or
+/// label.
+async fn search_all(
+    db: web::Data<Arc<PgPool>>,
+    ids: web::Data<IdCodec>,
+    labels: web::Data<Arc<LabelRegistry>>,
+    params: web::Query<SearchParams>,
+    tenant: Option<web::ReqData<Tenant>>,
+) -> HttpResponse {
+    let query = params.q.trim();
+
+    if query.chars().count() < MIN_QUERY {
+        return HttpResponse::BadRequest()
+            .body(format!("`q` must be at least {MIN_QUERY} characters"));
+    }
+
+    let limit = params.limit.unwrap_or(10).clamp(1, 50);
+
+    let found = match search::search(&db, query, limit).await {
+        Ok(found) => found,
+        Err(e) => {
+            error!("Failed to search for `{query}`: {e:?}");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    let transaction = |matched, stored| SearchResult::Transaction {
+        matched,
+        transaction: ApiTransaction::new(stored, &ids, &labels),
+    };
+
+    let results = found
+        .transaction
+        .into_iter()
+        .map(|stored| transaction(SearchMatch::Signature, stored))
+        .chain(
+            found
+                .addresses
+                .into_iter()
+                .map(|address| SearchResult::Address {
+                    label: labels.label(&address.address),
+                    address,
+                }),
+        )
+        .chain(
+            found
+                .labels
+                .into_iter()
+                .map(|label| SearchResult::Label { label }),
+        )
+        .chain(
+            found
+                .memos
+                .into_iter()
+                .map(|stored| transaction(SearchMatch::Memo, stored)),
+        )
+        .collect::<Vec<_>>();
+
+    record_rows(tenant, results.len());
+
+    HttpResponse::Ok().json(ApiSearchResults {
+        query: query.to_string(),
+        shape: found.shape,
+        results,
     })
-    .bind("127.0.0.1:8080")?
-    .run()
+}
+
+/// Handler to get the stored Metaplex metadata of an NFT.
+async fn get_nft_metadata(db: web::Data<Arc<PgPool>>, path: web::Path<String>) -> HttpResponse {
+    let mint = match parse_pubkey(&path) {
+        Ok(mint) => mint,
+        Err(res) => return res,
+    };
+
+    match get_nft(&db, &mint).await {
+        Ok(Some(nft)) => HttpResponse::Ok().json(nft),
+        Ok(None) => HttpResponse::NotFound().body("NFT not found"),
+        Err(e) => {
+            error!("Failed to fetch NFT: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// A stored token transfer as exposed by the API, with its public id.
+#[derive(Debug, Serialize)]
+struct ApiTokenTransfer {
+    id: String,
+    #[serde(flatten)]
+    transfer: StoredTokenTransfer,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenTransfersParams {
+    after: Option<String>,
+    limit: Option<i64>,
+    mint: Option<String>,
+    /// Owner of the token accounts whose balance changed.
+    owner: Option<String>,
+    /// Only transfers of NFTs.
+    #[serde(default)]
+    nft: bool,
+}
+
+/// Handler to get the token transfers of stored transactions.
+async fn get_token_transfer_history(
+    db: web::Data<Arc<PgPool>>,
+    ids: web::Data<IdCodec>,
+    params: web::Query<TokenTransfersParams>,
+    tenant: Option<web::ReqData<Tenant>>,
+) -> HttpResponse {
+    let after = match parse_cursor(&ids, params.after.as_deref()) {
+        Ok(after) => after,
+        Err(res) => return res,
+    };
+
+    let (mint, owner) = match (
+        params.mint.as_deref().map(parse_pubkey).transpose(),
+        params.owner.as_deref().map(parse_pubkey).transpose(),
+    ) {
+        (Ok(mint), Ok(owner)) => (mint, owner),
+        (Err(res), _) | (_, Err(res)) => return res,
+    };
+    let limit = params.limit.unwrap_or(100).clamp(1, 1000);
+
+    match get_token_transfers(
+        &db,
+        mint.as_deref(),
+        owner.as_deref(),
+        params.nft,
+        after,
+        limit,
+    )
+    .await
+    {
+        Ok(transfers) => {
+            record_rows(tenant, transfers.len());
+
+            HttpResponse::Ok().json(
+                transfers
+                    .into_iter()
+                    .map(|transfer| ApiTokenTransfer {
+                        id: ids.encode(transfer.id),
+                        transfer,
+                    })
+                    .collect::<Vec<_>>(),
+            )
+        }
+        Err(e) => {
+            error!("Failed to fetch token transfers: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Bucket size, time range (unix seconds) and optional address for volume analytics.
+#[derive(Debug, Deserialize)]
+struct VolumeParams {
+    #[serde(default)]
+    interval: RollupInterval,
+    from: Option<i64>,
+    to: Option<i64>,
+    address: Option<String>,
+}
+
+/// Handler to get rolled-up transaction volume per time bucket.
+async fn get_volume(db: web::Data<Arc<PgPool>>, params: web::Query<VolumeParams>) -> HttpResponse {
+    let address = match params.address.as_deref().map(parse_pubkey).transpose() {
+        Ok(address) => address.unwrap_or_else(|| ALL_ADDRESSES.to_string()),
+        Err(res) => return res,
+    };
+
+    match get_volume_rollups(
+        &db,
+        params.interval.as_str(),
+        &address,
+        params.from,
+        params.to,
+    )
     .await
+    {
+        Ok(buckets) => HttpResponse::Ok().json(buckets),
+        Err(e) => {
+            error!("Failed to fetch volume rollups: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Buckets of priority fee analytics covered when no `from` is given, and at most.
+const DEFAULT_FEE_BUCKETS: i64 = 48;
+const MAX_FEE_BUCKETS: i64 = 1000;
+
+/// Handler to get priority fee percentiles per time bucket, computed from the stored
+/// transactions.
+async fn get_priority_fees(
+    db: web::Data<Arc<PgPool>>,
+    params: web::Query<VolumeParams>,
+) -> HttpResponse {
+    let address = match params.address.as_deref().map(parse_pubkey).transpose() {
+        Ok(address) => address,
+        Err(res) => return res,
+    };
+
+    let bucket_secs = params.interval.seconds();
+    let to = params.to.unwrap_or_else(unix_now);
+    let Some(from) = fee_from(params.from, to, bucket_secs) else {
+        return HttpResponse::BadRequest().body(format!(
+            "`from` must be before `to`, at most {MAX_FEE_BUCKETS} buckets apart"
+        ));
+    };
+
+    match get_priority_fee_percentiles(&db, bucket_secs, address.as_deref(), from, to).await {
+        Ok(buckets) => HttpResponse::Ok().json(buckets),
+        Err(e) => {
+            error!("Failed to compute priority fee percentiles: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// The start of a priority fee range ending at `to`, `DEFAULT_FEE_BUCKETS` before it unless given.
+/// `None` unless it's at most `MAX_FEE_BUCKETS` before `to`, including for bounds so far apart
+/// their span overflows.
+fn fee_from(from: Option<i64>, to: i64, bucket_secs: i64) -> Option<i64> {
+    let from = match from {
+        Some(from) => from,
+        None => to.checked_sub(DEFAULT_FEE_BUCKETS.checked_mul(bucket_secs)?)?,
+    };
+    let span = to.checked_sub(from)?;
+
+    (span >= 0 && span / bucket_secs <= MAX_FEE_BUCKETS).then_some(from)
+}
+
+/// Handler to run a dashboard defined in the config file, filling in its placeholders from the
+/// query string.
+async fn get_dashboard(
+    db: web::Data<Arc<PgPool>>,
+    stats: web::Data<Arc<StatsCache>>,
+    leaderboards: web::Data<Arc<Leaderboards>>,
+    dashboards: web::Data<Arc<Dashboards>>,
+    path: web::Path<String>,
+    params: web::Query<HashMap<String, String>>,
+) -> HttpResponse {
+    let dashboard = match dashboards.bind(&path, &params) {
+        Some(Ok(dashboard)) => dashboard,
+        Some(Err(message)) => return HttpResponse::BadRequest().body(message),
+        None => return HttpResponse::NotFound().body("No such dashboard"),
+    };
+
+    let sources = DashboardSources {
+        db: &db,
+        stats: &stats,
+        leaderboards: &leaderboards,
+    };
+
+    match dashboard.run(&sources).await {
+        Ok(payload) => HttpResponse::Ok().json(payload),
+        Err(e) => {
+            error!("Failed to run dashboard `{}`: {e:?}", dashboard.name);
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct AnalyzeParams {
+    limit: Option<usize>,
+}
+
+/// Handler to backfill and summarize an unwatched address once, without monitoring it.
+async fn analyze_address(
+    storage: web::Data<Arc<dyn Storage>>,
+    db: web::Data<Arc<PgPool>>,
+    analyzer: web::Data<Arc<Analyzer>>,
+    runtime: web::Data<Handle>,
+    path: web::Path<String>,
+    params: web::Query<AnalyzeParams>,
+) -> HttpResponse {
+    let Ok(address) = Pubkey::from_str(&path) else {
+        return HttpResponse::BadRequest().body("Invalid public key");
+    };
+
+    if analyzer.is_watched(&address) {
+        return HttpResponse::Conflict().body("Address is already monitored");
+    }
+
+    let (analyzer, storage, db) = (Arc::clone(&analyzer), Arc::clone(&storage), Arc::clone(&db));
+    let limit = params.limit;
+    let summary = on_main_runtime(&runtime, async move {
+        analyzer.analyze(&address, limit, &storage, &db).await
+    })
+    .await;
+
+    match summary.and_then(|summary| summary) {
+        Ok(summary) => HttpResponse::Ok().json(summary),
+        Err(e) => {
+            error!("Failed to analyze {address}: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct StreamParams {
+    address: Option<String>,
+}
+
+/// Handler to stream newly stored transactions as Server-Sent Events.
+async fn stream_transactions(
+    feed: web::Data<Arc<TransactionFeed>>,
+    params: web::Query<StreamParams>,
+) -> HttpResponse {
+    let address = match params.address.as_deref().map(parse_pubkey).transpose() {
+        Ok(address) => address,
+        Err(res) => return res,
+    };
+
+    HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .insert_header(("Cache-Control", "no-cache"))
+        // compressing would buffer events
+        .insert_header(header::ContentEncoding::Identity)
+        .streaming(feed.subscribe(address))
+}
+
+/// File format, time range (unix seconds) and optional address for exports.
+#[derive(Debug, Deserialize)]
+struct ExportParams {
+    #[serde(default)]
+    format: ExportFormat,
+    from: Option<i64>,
+    to: Option<i64>,
+    address: Option<String>,
+}
+
+/// Handler to download the matching transactions as a CSV, Parquet or NDJSON file, streamed as
+/// it's written.
+async fn export_transactions(
+    storage: web::Data<Arc<dyn Storage>>,
+    ids: web::Data<IdCodec>,
+    labels: web::Data<Arc<LabelRegistry>>,
+    params: web::Query<ExportParams>,
+    tenant: Option<web::ReqData<Tenant>>,
+) -> HttpResponse {
+    let address = match params.address.as_deref().map(parse_pubkey).transpose() {
+        Ok(address) => address,
+        Err(res) => return res,
+    };
+
+    let query = TransactionQuery {
+        address,
+        from: params.from,
+        to: params.to,
+        ..TransactionQuery::default()
+    };
+
+    HttpResponse::Ok()
+        .content_type(params.format.content_type())
+        .insert_header((
+            header::CONTENT_DISPOSITION,
+            format!(
+                "attachment; filename=\"transactions.{}\"",
+                params.format.extension()
+            ),
+        ))
+        .streaming(export::export(
+            Arc::clone(&storage),
+            IdCodec::clone(&ids),
+            Arc::clone(&labels),
+            query,
+            params.format,
+            tenant.map(web::ReqData::into_inner),
+        ))
+}
+
+#[derive(Debug, Deserialize)]
+struct LeaderboardParams {
+    #[serde(default)]
+    window: Window,
+    limit: Option<usize>,
+}
+
+/// Handler to get the most active senders, receivers or programs over a recent window.
+async fn get_leaderboard(
+    leaderboards: web::Data<Arc<Leaderboards>>,
+    path: web::Path<Dimension>,
+    params: web::Query<LeaderboardParams>,
+) -> HttpResponse {
+    let limit = params.limit.unwrap_or(10).clamp(1, 100);
+
+    HttpResponse::Ok().json(leaderboards.top(*path, params.window, limit))
+}
+
+/// Handler to report per-route latency percentiles and error ratios.
+async fn get_slo_report(slo: web::Data<Arc<SloTracker>>) -> HttpResponse {
+    HttpResponse::Ok().json(slo.report())
+}
+
+/// Handler to report the throughput of transaction inserts.
+async fn get_ingest_report(ingest: web::Data<Arc<IngestMetrics>>) -> HttpResponse {
+    HttpResponse::Ok().json(ingest.report())
+}
+
+/// Handler to report whether the monitor is paused, when it last polled and how it's doing.
+async fn get_monitor_status(monitor: web::Data<Arc<MonitorControl>>) -> HttpResponse {
+    HttpResponse::Ok().json(monitor.status())
+}
+
+/// Handler to stop polling once the poll in progress is done, until resumed.
+async fn pause_monitor(monitor: web::Data<Arc<MonitorControl>>) -> HttpResponse {
+    if monitor.pause() {
+        info!("Paused the monitor");
+    }
+
+    HttpResponse::Ok().json(monitor.status())
+}
+
+/// Handler to resume polling after a pause.
+async fn resume_monitor(monitor: web::Data<Arc<MonitorControl>>) -> HttpResponse {
+    if monitor.resume() {
+        info!("Resumed the monitor");
+    }
+
+    HttpResponse::Ok().json(monitor.status())
+}
+
+/// Handler to report the memory held by the ingestion pipeline and the process.
+async fn get_memory_report(memory: web::Data<Arc<MemoryBudget>>) -> HttpResponse {
+    HttpResponse::Ok().json(memory.report())
+}
+
+/// Handler to list the background jobs with their last and next runs.
+async fn get_schedules(schedules: web::Data<Arc<Schedules>>) -> HttpResponse {
+    HttpResponse::Ok().json(schedules.report())
+}
+
+/// Handler to run a background job immediately.
+async fn run_schedule(
+    schedules: web::Data<Arc<Schedules>>,
+    path: web::Path<String>,
+) -> HttpResponse {
+    if schedules.trigger(&path) {
+        HttpResponse::Accepted().finish()
+    } else {
+        HttpResponse::NotFound().body("No such background job")
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct NewApiKeyParams {
+    name: String,
+    rate_limit_per_minute: Option<i32>,
+}
+
+/// Handler to list API keys, without their secrets.
+async fn get_api_keys(auth: web::Data<Arc<Authenticator>>) -> HttpResponse {
+    match auth.keys().await {
+        Ok(keys) => HttpResponse::Ok().json(keys),
+        Err(e) => {
+            error!("Failed to get API keys: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Handler to create an API key, returning its secret.
+async fn create_api_key(
+    auth: web::Data<Arc<Authenticator>>,
+    params: web::Json<NewApiKeyParams>,
+) -> HttpResponse {
+    match auth
+        .create_key(&params.name, params.rate_limit_per_minute)
+        .await
+    {
+        Ok(key) => HttpResponse::Created().json(key),
+        Err(e) => {
+            error!("Failed to create API key: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Handler to revoke an API key.
+async fn revoke_api_key(auth: web::Data<Arc<Authenticator>>, path: web::Path<i32>) -> HttpResponse {
+    match auth.revoke_key(*path).await {
+        Ok(true) => HttpResponse::NoContent().finish(),
+        Ok(false) => HttpResponse::NotFound().body("No such API key"),
+        Err(e) => {
+            error!("Failed to revoke API key: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum UsageFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Deserialize)]
+struct UsageParams {
+    /// `YYYY-MM`, the current month by default.
+    month: Option<String>,
+    #[serde(default)]
+    format: UsageFormat,
+}
+
+/// Handler to export every API key's usage in a month, for billing.
+async fn get_usage(
+    auth: web::Data<Arc<Authenticator>>,
+    params: web::Query<UsageParams>,
+) -> HttpResponse {
+    let month = params.month.clone().unwrap_or_else(current_month);
+
+    if !is_month(&month) {
+        return HttpResponse::BadRequest().body("Invalid month: expected YYYY-MM");
+    }
+
+    let usage = match auth.usage().report(&month).await {
+        Ok(usage) => usage,
+        Err(e) => {
+            error!("Failed to fetch API usage: {e:?}");
+            return HttpResponse::InternalServerError().finish();
+        }
+    };
+
+    match params.format {
+        UsageFormat::Json => HttpResponse::Ok().json(usage),
+        UsageFormat::Csv => match usage_csv(&usage) {
+            Ok(csv) => HttpResponse::Ok()
+                .content_type("text/csv; charset=utf-8")
+                .insert_header((
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"usage-{month}.csv\""),
+                ))
+                .body(csv),
+            Err(e) => {
+                error!("Failed to write API usage: {e:?}");
+                HttpResponse::InternalServerError().finish()
+            }
+        },
+    }
+}
+
+/// Handler to report how far a warm standby has replicated its primary.
+async fn get_replication_report(follower: web::Data<Arc<Follower>>) -> HttpResponse {
+    HttpResponse::Ok().json(follower.report())
+}
+
+/// Handler for the missing transactions found and repaired per watched address.
+async fn get_gaps(gaps: web::Data<Arc<GapRepair>>) -> HttpResponse {
+    HttpResponse::Ok().json(gaps.report())
+}
+
+/// Handler to report the transactions queued after failing to insert.
+async fn get_dead_letters(dead_letters: web::Data<Arc<DeadLetterQueue>>) -> HttpResponse {
+    HttpResponse::Ok().json(dead_letters.report())
+}
+
+/// Handler to replay the dead-letter queue now rather than at its next scheduled retry.
+async fn replay_dead_letters(
+    dead_letters: web::Data<Arc<DeadLetterQueue>>,
+    schedules: web::Data<Arc<Schedules>>,
+) -> HttpResponse {
+    schedules.trigger(REPLAY_JOB);
+
+    HttpResponse::Accepted().json(dead_letters.report())
+}
+
+/// Handler for the hits and misses of the query cache.
+async fn get_cache_report(cache: web::Data<Arc<CachedStorage>>) -> HttpResponse {
+    HttpResponse::Ok().json(cache.report())
+}
+
+/// Handler for JSON-RPC requests to the caching RPC proxy.
+async fn post_rpc(
+    rpc_proxy: web::Data<Arc<RpcProxy>>,
+    runtime: web::Data<Handle>,
+    body: web::Bytes,
+) -> HttpResponse {
+    let rpc_proxy = Arc::clone(&rpc_proxy);
+
+    match on_main_runtime(&runtime, async move { rpc_proxy.handle(&body).await }).await {
+        Ok(response) => HttpResponse::Ok().json(response),
+        Err(e) => {
+            error!("Failed to answer RPC request: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Handler for where the RPC proxy's answers came from.
+async fn get_rpc_proxy_report(rpc_proxy: web::Data<Arc<RpcProxy>>) -> HttpResponse {
+    HttpResponse::Ok().json(rpc_proxy.report())
+}
+
+/// Handler to promote a warm standby, stopping replication and starting the monitor.
+async fn promote(follower: web::Data<Arc<Follower>>) -> HttpResponse {
+    if follower.promote() {
+        HttpResponse::Ok().json(follower.report())
+    } else {
+        HttpResponse::Conflict().body("Already promoted")
+    }
+}
+
+/// Handler to report table bloat and the last maintenance run of each table.
+async fn get_maintenance_report(
+    db: web::Data<Arc<PgPool>>,
+    maintenance: web::Data<Arc<MaintenanceScheduler>>,
+) -> HttpResponse {
+    match maintenance.report(&db).await {
+        Ok(report) => HttpResponse::Ok().json(report),
+        Err(e) => {
+            error!("Failed to build maintenance report: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// A rejected transaction as exposed by the API, with its public id.
+#[derive(Debug, Serialize)]
+struct ApiRejection {
+    id: String,
+    #[serde(flatten)]
+    rejection: StoredRejection,
+}
+
+#[derive(Debug, Deserialize)]
+struct RejectedTransactionsParams {
+    after: Option<String>,
+    limit: Option<i64>,
+}
+
+/// Handler to list the transactions that failed validation, with the rules each broke.
+async fn get_rejected_transaction_history(
+    db: web::Data<Arc<PgPool>>,
+    ids: web::Data<IdCodec>,
+    params: web::Query<RejectedTransactionsParams>,
+) -> HttpResponse {
+    let after = match parse_cursor(&ids, params.after.as_deref()) {
+        Ok(after) => after,
+        Err(res) => return res,
+    };
+    let limit = params.limit.unwrap_or(100).clamp(1, 1000);
+
+    match get_rejected_transactions(&db, after, limit).await {
+        Ok(rejections) => HttpResponse::Ok().json(
+            rejections
+                .into_iter()
+                .map(|rejection| ApiRejection {
+                    id: ids.encode(rejection.id),
+                    rejection,
+                })
+                .collect::<Vec<_>>(),
+        ),
+        Err(e) => {
+            error!("Failed to fetch rejected transactions: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    }
+}
+
+/// Handler to report the stored transactions finalized and dropped since startup.
+async fn get_finality_report(finality: web::Data<Arc<FinalityTracker>>) -> HttpResponse {
+    HttpResponse::Ok().json(finality.report())
+}
+
+/// Handler to report the rows reclaimed by expiring ad hoc analysis data.
+async fn get_analysis_report(analyzer: web::Data<Arc<Analyzer>>) -> HttpResponse {
+    HttpResponse::Ok().json(analyzer.cleanup_report())
+}
+
+/// Middleware rejecting requests without a valid API key, or over their key's rate limit, when
+/// auth is enabled, except on public routes. Keys are read from `X-API-Key` or an
+/// `Authorization: Bearer` header. Allowed requests carry their key as a `Tenant`, for metering.
+async fn authenticate(
+    req: ServiceRequest,
+    next: Next<impl MessageBody>,
+) -> Result<ServiceResponse<impl MessageBody>, actix_web::Error> {
+    let auth = req
+        .app_data::<web::Data<Arc<Authenticator>>>()
+        .map(|auth| Arc::clone(auth.get_ref()))
+        .filter(|auth| auth.is_enabled())
+        .filter(|auth| {
+            !req.match_pattern()
+                .is_some_and(|pattern| auth.is_public(&pattern))
+        });
+
+    let Some(auth) = auth else {
+        return next
+            .call(req)
+            .await
+            .map(ServiceResponse::map_into_left_body);
+    };
+
+    let headers = req.headers();
+    let secret = headers
+        .get("X-API-Key")
+        .and_then(|value| value.to_str().ok())
+        .or_else(|| {
+            headers
+                .get("Authorization")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.strip_prefix("Bearer "))
+        });
+
+    let res = match auth.check(secret).await {
+        Ok(Verdict::Allowed { key_id }) => {
+            req.extensions_mut()
+                .insert(Tenant::new(key_id, auth.usage()));
+
+            return next
+                .call(req)
+                .await
+                .map(ServiceResponse::map_into_left_body);
+        }
+        Ok(Verdict::Unauthorized) => {
+            HttpResponse::Unauthorized().body("Missing or invalid API key")
+        }
+        Ok(Verdict::RateLimited { retry_after }) => HttpResponse::TooManyRequests()
+            .insert_header(("Retry-After", retry_after.as_secs_f64().ceil().to_string()))
+            .body("Rate limit exceeded"),
+        Err(e) => {
+            error!("Failed to check API key: {e:?}");
+            HttpResponse::InternalServerError().finish()
+        }
+    };
+
+    Ok(req.into_response(res).map_into_right_body())
+}
+
+/// Register the routes of `exposure`.
+#[allow(clippy::too_many_arguments)]
+fn routes(
+    exposure: Exposure,
+    auth: Option<&Arc<Authenticator>>,
+    follower: Option<&Arc<Follower>>,
+    gaps: Option<&Arc<GapRepair>>,
+    rpc_proxy: Option<&Arc<RpcProxy>>,
+    dead_letters: Option<&Arc<DeadLetterQueue>>,
+    cache: Option<&Arc<CachedStorage>>,
+    solana_client: Option<&Arc<SolanaClient>>,
+    postgres: Option<&PostgresContext>,
+    cfg: &mut web::ServiceConfig,
+) {
+    // also where the authentication middleware finds the authenticator
+    if let Some(auth) = auth {
+        cfg.app_data(web::Data::new(Arc::clone(auth)));
+    }
+
+    cfg.route("/transactions", web::get().to(get_transactions))
+        .route("/transactions/{signature}", web::get().to(get_transaction))
+        .route("/accounts/{pubkey}", web::get().to(get_account_details))
+        .route("/graph", web::get().to(get_transfer_graph))
+        .route(
+            "/accounts/{pubkey}/transactions",
+            web::get().to(get_account_transactions),
+        )
+        .route("/stream/transactions", web::get().to(stream_transactions))
+        .route("/export", web::get().to(export_transactions))
+        .route("/leaderboards/{dimension}", web::get().to(get_leaderboard))
+        .route("/labels", web::get().to(get_labels))
+        .route("/directory", web::get().to(get_directory))
+        .route("/graphql", web::post().to(post_graphql));
+
+    if exposure == Exposure::Private {
+        cfg.route("/graphql", web::get().to(get_graphiql))
+            .route("/admin/slo", web::get().to(get_slo_report))
+            .route("/admin/ingest", web::get().to(get_ingest_report))
+            .route("/admin/monitor/status", web::get().to(get_monitor_status))
+            .route("/admin/monitor/pause", web::post().to(pause_monitor))
+            .route("/admin/monitor/resume", web::post().to(resume_monitor))
+            .route("/admin/memory", web::get().to(get_memory_report))
+            .route("/admin/schedules", web::get().to(get_schedules))
+            .route("/admin/schedules/{name}/run", web::post().to(run_schedule))
+            .route("/addresses/export", web::get().to(export_addresses));
+
+        if auth.is_some() {
+            cfg.route("/admin/api-keys", web::get().to(get_api_keys))
+                .route("/admin/api-keys", web::post().to(create_api_key))
+                .route("/admin/api-keys/{id}", web::delete().to(revoke_api_key))
+                .route("/admin/usage", web::get().to(get_usage));
+        }
+
+        if let Some(follower) = follower {
+            cfg.app_data(web::Data::new(Arc::clone(follower)))
+                .route("/admin/replication", web::get().to(get_replication_report))
+                .route("/admin/promote", web::post().to(promote));
+        }
+
+        if let Some(gaps) = gaps {
+            cfg.app_data(web::Data::new(Arc::clone(gaps)))
+                .route("/gaps", web::get().to(get_gaps));
+        }
+
+        if let Some(rpc_proxy) = rpc_proxy {
+            cfg.app_data(web::Data::new(Arc::clone(rpc_proxy)))
+                .route("/rpc", web::post().to(post_rpc))
+                .route("/admin/rpc-proxy", web::get().to(get_rpc_proxy_report));
+        }
+
+        if let Some(dead_letters) = dead_letters {
+            cfg.app_data(web::Data::new(Arc::clone(dead_letters)))
+                .route("/dlq", web::get().to(get_dead_letters))
+                .route("/dlq/replay", web::post().to(replay_dead_letters));
+        }
+
+        if let Some(cache) = cache {
+            cfg.app_data(web::Data::new(Arc::clone(cache)))
+                .route("/admin/cache", web::get().to(get_cache_report));
+        }
+
+        if let Some(solana_client) = solana_client {
+            cfg.app_data(web::Data::new(Arc::clone(solana_client)))
+                .route("/lookup/{signature}", web::get().to(lookup_transaction));
+        }
+    }
+
+    if let Some(postgres) = postgres {
+        postgres_routes(postgres, exposure, cfg);
+    }
+}
+
+/// Register the routes backed by Postgres-only tables.
+fn postgres_routes(context: &PostgresContext, exposure: Exposure, cfg: &mut web::ServiceConfig) {
+    cfg.app_data(web::Data::new(context.db.clone()))
+        .app_data(web::Data::new(context.layouts.clone()))
+        .app_data(web::Data::new(context.stats.clone()))
+        .app_data(web::Data::new(context.archive.clone()))
+        .app_data(web::Data::new(context.portfolios.clone()))
+        .app_data(web::Data::new(context.dashboards.clone()))
+        .route(
+            "/transactions/{signature}/raw",
+            web::get().to(get_raw_transaction),
+        )
+        .route("/accounts/{pubkey}/stats", web::get().to(get_account_stats))
+        .route(
+            "/accounts/{pubkey}/counterparties",
+            web::get().to(get_account_counterparties),
+        )
+        .route(
+            "/accounts/{pubkey}/activity",
+            web::get().to(get_account_activity),
+        )
+        .route(
+            "/accounts/{pubkey}/balance",
+            web::get().to(get_account_balance),
+        )
+        .route(
+            "/accounts/{pubkey}/balance/history",
+            web::get().to(get_account_balance_history),
+        )
+        .route(
+            "/accounts/{pubkey}/tokens",
+            web::get().to(get_account_tokens),
+        )
+        .route(
+            "/accounts/{pubkey}/changes",
+            web::get().to(get_account_changes),
+        )
+        .route(
+            "/accounts/{pubkey}/portfolio",
+            web::get().to(get_account_portfolio),
+        )
+        .route("/layouts", web::get().to(get_layouts))
+        .route("/stats", web::get().to(get_stats))
+        .route("/stats/fee-burn", web::get().to(get_fee_burn))
+        .route("/analytics/volume", web::get().to(get_volume))
+        .route("/analytics/fees", web::get().to(get_priority_fees))
+        .route("/dashboards/{name}", web::get().to(get_dashboard))
+        .route("/reports/daily", web::get().to(get_daily_report))
+        .route("/search", web::get().to(search_all))
+        .route("/nfts/{mint}", web::get().to(get_nft_metadata))
+        .route(
+            "/token-transfers",
+            web::get().to(get_token_transfer_history),
+        );
+
+    if let Some(epochs) = &context.epochs {
+        cfg.app_data(web::Data::new(epochs.clone()))
+            .route("/epochs/current", web::get().to(get_current_epoch))
+            .route(
+                "/epochs/{epoch}/transactions",
+                web::get().to(get_transactions_in_epoch),
+            );
+    }
+
+    if exposure == Exposure::Public {
+        return;
+    }
+
+    cfg.app_data(web::Data::new(context.maintenance.clone()))
+        .route("/layouts", web::post().to(post_layout))
+        .route("/labels", web::post().to(post_label))
+        .route("/labels/{pubkey}", web::delete().to(remove_label))
+        .route("/addresses/import", web::post().to(import_addresses))
+        .route("/admin/maintenance", web::get().to(get_maintenance_report))
+        .route(
+            "/admin/rejected-transactions",
+            web::get().to(get_rejected_transaction_history),
+        );
+
+    if let Some(alerts) = &context.alerts {
+        cfg.app_data(web::Data::new(alerts.clone()))
+            .route("/alerts", web::get().to(list_alert_rules))
+            .route("/alerts", web::post().to(create_alert_rule))
+            .route("/alerts/{id}", web::delete().to(remove_alert_rule));
+    }
+
+    if let Some(finality) = &context.finality {
+        cfg.app_data(web::Data::new(finality.clone()))
+            .route("/admin/finality", web::get().to(get_finality_report));
+    }
+
+    if let Some(analyzer) = &context.analyzer {
+        cfg.app_data(web::Data::new(analyzer.clone()))
+            .route("/analyze/{pubkey}", web::post().to(analyze_address))
+            .route("/admin/analysis", web::get().to(get_analysis_report));
+    }
+}
+
+/// CORS policy allowing the configured origins to call the API from browsers.
+fn cors(config: &CorsConfig) -> Cors {
+    let cors = Cors::default()
+        .allowed_methods(["GET", "POST", "DELETE"])
+        .allowed_headers([header::CONTENT_TYPE, header::AUTHORIZATION])
+        .allowed_header("X-API-Key")
+        .expose_headers([header::RETRY_AFTER])
+        .max_age(config.max_age_secs);
+
+    config
+        .allowed_origins
+        .iter()
+        .fold(cors, |cors, origin| match origin.as_str() {
+            "*" => cors.allow_any_origin(),
+            origin => cors.allowed_origin(origin),
+        })
+}
+
+/// Load the certificate chain and private key to terminate TLS with.
+pub fn tls_config(config: &TlsConfig) -> anyhow::Result<rustls::ServerConfig> {
+    let open = |path: &Path| {
+        File::open(path)
+            .map(BufReader::new)
+            .map_err(|e| anyhow::anyhow!("Failed to open `{}`: {e}", path.display()))
+    };
+
+    let certs =
+        rustls_pemfile::certs(&mut open(&config.cert_path)?).collect::<Result<Vec<_>, _>>()?;
+    let key = rustls_pemfile::private_key(&mut open(&config.key_path)?)?
+        .ok_or_else(|| anyhow::anyhow!("No private key in `{}`", config.key_path.display()))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let tls = rustls::ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)?;
+
+    Ok(tls)
+}
+
+/// Build an API server serving the routes of `exposure`, over TLS if `tls` is given.
+///
+/// Signal handling is left to the caller, which stops the server through its handle.
+pub fn server(
+    context: ApiContext,
+    exposure: Exposure,
+    host: &str,
+    port: u16,
+    tls: Option<rustls::ServerConfig>,
+    cors_config: &CorsConfig,
+) -> std::io::Result<Server> {
+    let ApiContext {
+        storage,
+        ids,
+        slo,
+        ingest,
+        monitor,
+        feed,
+        leaderboards,
+        memory,
+        schedules,
+        labels,
+        directory,
+        accounts,
+        watchlist,
+        auth,
+        follower,
+        gaps,
+        rpc_proxy,
+        dead_letters,
+        cache,
+        solana_client,
+        runtime,
+        postgres,
+    } = context;
+    let schema = web::Data::new(graphql::schema(
+        storage.clone(),
+        ids.clone(),
+        postgres
+            .as_ref()
+            .map(|postgres| (postgres.db.clone(), postgres.stats.clone())),
+    ));
+    let ids = web::Data::new(ids);
+    let cors_config = cors_config.clone();
+
+    let server = HttpServer::new(move || {
+        let tracker = Arc::clone(&slo);
+
+        App::new()
+            .app_data(web::Data::new(storage.clone()))
+            .app_data(ids.clone())
+            .app_data(schema.clone())
+            .app_data(web::Data::new(slo.clone()))
+            .app_data(web::Data::new(ingest.clone()))
+            .app_data(web::Data::new(monitor.clone()))
+            .app_data(web::Data::new(feed.clone()))
+            .app_data(web::Data::new(leaderboards.clone()))
+            .app_data(web::Data::new(memory.clone()))
+            .app_data(web::Data::new(schedules.clone()))
+            .app_data(web::Data::new(labels.clone()))
+            .app_data(web::Data::new(directory.clone()))
+            .app_data(web::Data::new(accounts.clone()))
+            .app_data(web::Data::new(watchlist.clone()))
+            .app_data(web::Data::new(runtime.clone()))
+            .wrap_fn(move |req, srv| {
+                let route = req
+                    .match_pattern()
+                    .unwrap_or_else(|| "<unmatched>".to_string());
+                let started_at = Instant::now();
+                let tracker = Arc::clone(&tracker);
+                let res = srv.call(req);
+
+                async move {
+                    let res = res.await;
+                    let is_error = res
+                        .as_ref()
+                        .map_or(true, |res| res.status().is_server_error());
+
+                    tracker.record(&route, started_at.elapsed(), is_error);
+
+                    res
+                }
+            })
+            .wrap(middleware::Compress::default())
+            .wrap(middleware::from_fn(authenticate))
+            // outermost, so preflight requests are answered without an API key
+            .wrap(Condition::new(
+                !cors_config.allowed_origins.is_empty(),
+                cors(&cors_config),
+            ))
+            .configure(|cfg| {
+                routes(
+                    exposure,
+                    auth.as_ref(),
+                    follower.as_ref(),
+                    gaps.as_ref(),
+                    rpc_proxy.as_ref(),
+                    dead_letters.as_ref(),
+                    cache.as_ref(),
+                    solana_client.as_ref(),
+                    postgres.as_ref(),
+                    cfg,
+                )
+            })
+    })
+    .disable_signals();
+
+    let server = match tls {
+        Some(tls) => server.bind_rustls_0_23((host, port), tls)?,
+        None => server.bind((host, port))?,
+    };
+
+    Ok(server.run())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{config::RpcRetryConfig, data_storage::InMemoryStorage};
+
+    use actix_web::test::TestRequest;
+    use tokio::runtime::Builder;
+
+    #[test]
+    fn test_lookup_runs_off_the_worker() {
+        // a real `RpcClient`, which nothing answers
+        let solana_client = Arc::new(SolanaClient::new("http://127.0.0.1:9").with_retry_policy(
+            RpcRetryConfig {
+                max_attempts: 1,
+                ..RpcRetryConfig::default()
+            },
+        ));
+        let storage: Arc<dyn Storage> = Arc::new(InMemoryStorage::new());
+
+        // the actix workers run current-thread runtimes
+        let main = Builder::new_multi_thread().enable_all().build().unwrap();
+        let worker = Builder::new_current_thread().enable_all().build().unwrap();
+
+        let lookup = fetch_and_store(solana_client, storage, None, Signature::default());
+        let lookup = worker.block_on(on_main_runtime(main.handle(), lookup));
+
+        assert!(matches!(lookup, Ok(Lookup::NotFound)));
+    }
+
+    #[test]
+    fn test_activity_from() {
+        let day = 86_400;
+
+        assert_eq!(activity_from(None, 400 * day, 28), Some(372 * day));
+        assert_eq!(activity_from(Some(day), 2 * day, 28), Some(day));
+        assert_eq!(activity_from(Some(2 * day), 2 * day, 28), None);
+        assert_eq!(activity_from(Some(0), 400 * day, 28), None);
+        assert_eq!(activity_from(None, day, 28), None);
+
+        // bounds that overflow are rejected rather than wrapping
+        assert_eq!(activity_from(None, i64::MIN, 28), None);
+        assert_eq!(activity_from(Some(i64::MIN), i64::MAX, 28), None);
+        assert_eq!(activity_from(Some(i64::MAX), i64::MIN, 28), None);
+        assert_eq!(activity_from(Some(i64::MIN), i64::MIN + day, 28), None);
+    }
+
+    #[test]
+    fn test_fee_from() {
+        let hour = 3600;
+
+        assert_eq!(fee_from(None, 100 * hour, hour), Some(52 * hour));
+        assert_eq!(fee_from(Some(hour), hour, hour), Some(hour));
+        assert_eq!(fee_from(Some(2 * hour), hour, hour), None);
+        assert_eq!(fee_from(Some(0), 1001 * hour, hour), None);
+
+        // bounds that overflow are rejected rather than wrapping
+        assert_eq!(fee_from(None, i64::MIN, hour), None);
+        assert_eq!(fee_from(Some(i64::MIN), i64::MAX, hour), None);
+        assert_eq!(fee_from(Some(i64::MAX), i64::MIN, hour), None);
+    }
+
+    #[tokio::test]
+    async fn test_etag_depends_on_encoding() {
+        let storage: Arc<dyn Storage> = Arc::new(InMemoryStorage::new());
+        let json = transactions_etag(&storage, Encoding::Json).await.unwrap();
+
+        let req = TestRequest::default()
+            .insert_header(header::IfNoneMatch::Items(vec![json]))
+            .to_http_request();
+
+        assert!(check_etag(&req, &storage, Encoding::Json).await.1);
+        assert!(!check_etag(&req, &storage, Encoding::MessagePack).await.1);
+    }
 }
@@ -0,0 +1,258 @@
+// Archives the raw RPC payload of every stored transaction, zstd-compressed
+
+// Responsibilities:
+// * Keep the full `getTransaction` JSON of monitored transactions, not just the parsed fields.
+// * Keep that archive small, and decompress it transparently on read.
+
+// Implementation:
+// * Payloads are compressed with zstd. Individual transactions are small and highly repetitive
+//   across each other (program ids, field names), which plain zstd can't exploit, so the first
+//   `dictionary_samples` payloads are used to train a dictionary.
+// * Dictionaries are stored in `zstd_dictionaries` and every archived row records the one it
+//   was compressed with (none for rows archived before the first one was trained), so rows stay
+//   readable if a dictionary is ever retrained.
+
+use crate::{
+    config::RawArchiveConfig,
+    data_storage::{
+        get_latest_zstd_dictionary, get_raw_transaction, get_zstd_dictionary,
+        insert_raw_transaction, insert_zstd_dictionary,
+    },
+};
+
+use log::{error, info};
+use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, EncodedTransaction};
+use sqlx::PgPool;
+use tokio::task;
+
+use std::{
+    collections::HashMap,
+    io::Read,
+    mem,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Debug, Default)]
+struct ArchiveState {
+    /// Dictionary new payloads are compressed with.
+    current: Option<i32>,
+    /// Dictionaries loaded so far, by id.
+    dictionaries: HashMap<i32, Arc<Vec<u8>>>,
+    /// Payloads collected to train the first dictionary.
+    samples: Vec<Vec<u8>>,
+    training: bool,
+}
+
+#[derive(Debug)]
+pub struct RawArchive {
+    level: i32,
+    dictionary_samples: usize,
+    dictionary_size: usize,
+    state: Mutex<ArchiveState>,
+}
+
+impl RawArchive {
+    /// Create the archive, picking up the most recently trained dictionary.
+    pub async fn load(config: &RawArchiveConfig, db: &Arc<PgPool>) -> anyhow::Result<Self> {
+        let mut state = ArchiveState::default();
+
+        if let Some((id, dictionary)) = get_latest_zstd_dictionary(db).await? {
+            state.current = Some(id);
+            state.dictionaries.insert(id, Arc::new(dictionary));
+        }
+
+        Ok(RawArchive {
+            level: config.level,
+            dictionary_samples: config.dictionary_samples,
+            dictionary_size: config.dictionary_size,
+            state: Mutex::new(state),
+        })
+    }
+
+    /// Serialize a transaction for archiving, keyed by its first signature.
+    pub fn payload(txn: &EncodedConfirmedTransactionWithStatusMeta) -> Option<(String, Vec<u8>)> {
+        let EncodedTransaction::Json(ui_txn) = &txn.transaction.transaction else {
+            return None;
+        };
+
+        let signature = ui_txn.signatures.first()?.clone();
+
+        match serde_json::to_vec(txn) {
+            Ok(json) => Some((signature, json)),
+            Err(e) => {
+                error!("Failed to serialize transaction {signature}: {e:?}");
+                None
+            }
+        }
+    }
+
+    /// Compress and store the raw JSON of a transaction.
+    pub async fn store(
+        &self,
+        db: &Arc<PgPool>,
+        signature: &str,
+        json: &[u8],
+    ) -> anyhow::Result<()> {
+        self.collect_sample(db, json).await?;
+
+        let (dictionary_id, dictionary) = {
+            let state = self.state.lock().expect("archive state lock poisoned");
+            let dictionary = state
+                .current
+                .and_then(|id| state.dictionaries.get(&id).cloned());
+
+            (state.current, dictionary)
+        };
+
+        let payload = compress(json, self.level, dictionary.as_deref().map(Vec::as_slice))?;
+        insert_raw_transaction(db, signature, dictionary_id, &payload).await
+    }
+
+    /// Fetch and decompress the raw JSON of an archived transaction.
+    pub async fn fetch(
+        &self,
+        db: &Arc<PgPool>,
+        signature: &str,
+    ) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some((dictionary_id, payload)) = get_raw_transaction(db, signature).await? else {
+            return Ok(None);
+        };
+
+        let dictionary = match dictionary_id {
+            Some(id) => Some(self.dictionary(db, id).await?),
+            None => None,
+        };
+
+        Ok(Some(decompress(
+            &payload,
+            dictionary.as_deref().map(Vec::as_slice),
+        )?))
+    }
+
+    async fn dictionary(&self, db: &Arc<PgPool>, id: i32) -> anyhow::Result<Arc<Vec<u8>>> {
+        let cached = self
+            .state
+            .lock()
+            .expect("archive state lock poisoned")
+            .dictionaries
+            .get(&id)
+            .cloned();
+
+        if let Some(dictionary) = cached {
+            return Ok(dictionary);
+        }
+
+        let Some(dictionary) = get_zstd_dictionary(db, id).await? else {
+            anyhow::bail!("zstd dictionary {id} is missing");
+        };
+
+        let dictionary = Arc::new(dictionary);
+        self.state
+            .lock()
+            .expect("archive state lock poisoned")
+            .dictionaries
+            .insert(id, Arc::clone(&dictionary));
+
+        Ok(dictionary)
+    }
+
+    /// Keep the payload as a training sample until there are enough to train a dictionary.
+    async fn collect_sample(&self, db: &Arc<PgPool>, json: &[u8]) -> anyhow::Result<()> {
+        let samples = {
+            let mut state = self.state.lock().expect("archive state lock poisoned");
+
+            if self.dictionary_samples == 0 || state.current.is_some() || state.training {
+                return Ok(());
+            }
+
+            state.samples.push(json.to_vec());
+
+            if state.samples.len() < self.dictionary_samples {
+                return Ok(());
+            }
+
+            state.training = true;
+            mem::take(&mut state.samples)
+        };
+
+        let dictionary_size = self.dictionary_size;
+        let trained =
+            task::spawn_blocking(move || zstd::dict::from_samples(&samples, dictionary_size))
+                .await?;
+
+        let stored = match trained {
+            Ok(dictionary) => insert_zstd_dictionary(db, &dictionary)
+                .await
+                .map(|id| (id, dictionary)),
+            Err(e) => Err(e.into()),
+        };
+
+        let mut state = self.state.lock().expect("archive state lock poisoned");
+        state.training = false;
+
+        let (id, dictionary) = stored?;
+        info!("Trained zstd dictionary {id} ({} bytes)", dictionary.len());
+
+        state.current = Some(id);
+        state.dictionaries.insert(id, Arc::new(dictionary));
+
+        Ok(())
+    }
+}
+
+fn compress(data: &[u8], level: i32, dictionary: Option<&[u8]>) -> std::io::Result<Vec<u8>> {
+    match dictionary {
+        Some(dictionary) => {
+            zstd::bulk::Compressor::with_dictionary(level, dictionary)?.compress(data)
+        }
+        None => zstd::bulk::compress(data, level),
+    }
+}
+
+fn decompress(payload: &[u8], dictionary: Option<&[u8]>) -> std::io::Result<Vec<u8>> {
+    match dictionary {
+        Some(dictionary) => {
+            let mut decoder = zstd::stream::read::Decoder::with_dictionary(payload, dictionary)?;
+            let mut data = Vec::new();
+            decoder.read_to_end(&mut data)?;
+            Ok(data)
+        }
+        None => zstd::stream::decode_all(payload),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(i: usize) -> Vec<u8> {
+        format!(
+            r#"{{"slot":{},"blockTime":{},"transaction":{{"signatures":["sig{i}"],"message":{{"accountKeys":["11111111111111111111111111111111","Vote111111111111111111111111111111111111111"],"recentBlockhash":"hash{i}"}}}},"meta":{{"fee":{},"err":null,"logMessages":["Program 11111111111111111111111111111111 invoke [1]","Program 11111111111111111111111111111111 success"]}}}}"#,
+            1000 + i,
+            1_700_000_000 + i,
+            5000 + i
+        )
+        .into_bytes()
+    }
+
+    #[test]
+    fn test_round_trip_without_dictionary() {
+        let data = sample(1);
+        let compressed = compress(&data, 3, None).unwrap();
+
+        assert_eq!(decompress(&compressed, None).unwrap(), data);
+    }
+
+    #[test]
+    fn test_round_trip_with_trained_dictionary() {
+        let samples = (0..1000).map(sample).collect::<Vec<_>>();
+        let dictionary = zstd::dict::from_samples(&samples, 4096).unwrap();
+
+        let data = sample(5000);
+        let plain = compress(&data, 3, None).unwrap();
+        let compressed = compress(&data, 3, Some(&dictionary)).unwrap();
+
+        assert!(compressed.len() < plain.len());
+        assert_eq!(decompress(&compressed, Some(&dictionary)).unwrap(), data);
+    }
+}
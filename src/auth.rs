@@ -0,0 +1,303 @@
+// Authenticates API requests with API keys and rate-limits each key
+
+// Responsibilities:
+// * Require a valid API key on every request when auth is enabled, except on the routes the
+//   config declares public.
+// * Enforce each key's request rate, rejecting requests over it.
+// * Create, list and revoke keys.
+// * Meter each key's usage.
+
+// Implementation:
+// * Keys are 32 random bytes, hex-encoded. Only their SHA-256 is stored, in `api_keys`, so a
+//   key is shown once, when it is created.
+// * Lookups, including misses, are cached for `CACHE_TTL` so most requests don't reach the
+//   database. Keys revoked through the API are evicted right away.
+// * Public routes are matched by their route pattern, so `/accounts/{pubkey}/stats` covers every
+//   account, and aren't rate limited.
+// * Each key has a token bucket holding up to a minute's worth of requests, refilled
+//   continuously, so short bursts are allowed but the sustained rate is capped.
+
+use crate::{
+    config::AuthConfig,
+    data_storage::{get_api_key_by_hash, get_api_keys, insert_api_key, revoke_api_key, ApiKey},
+    metering::UsageMeter,
+};
+
+use rand::RngCore;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::PgPool;
+use tokio::time::{Duration, Instant};
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const CACHE_TTL: Duration = Duration::from_secs(60);
+
+/// Cached lookups above which expired ones are dropped, so unknown keys can't grow the cache
+/// without bound.
+const CACHE_PRUNE_LEN: usize = 10_000;
+
+/// Characters of a key kept in the clear to identify it.
+const PREFIX_LEN: usize = 8;
+
+/// A newly created key, the only time its secret is available.
+#[derive(Debug, Serialize)]
+pub struct NewApiKey {
+    #[serde(flatten)]
+    pub key: ApiKey,
+    pub secret: String,
+}
+
+/// Outcome of checking a request's key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allowed {
+        key_id: i32,
+    },
+    /// Missing, unknown or revoked key.
+    Unauthorized,
+    RateLimited {
+        retry_after: Duration,
+    },
+}
+
+#[derive(Debug)]
+struct CachedKey {
+    key: Option<ApiKey>,
+    cached_at: Instant,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    updated_at: Instant,
+}
+
+impl Bucket {
+    /// Take one token, or report how long until one is available.
+    fn take(&mut self, per_minute: u32, now: Instant) -> Result<(), Duration> {
+        let capacity = f64::from(per_minute);
+        let per_sec = capacity / 60.0;
+
+        self.tokens = (self.tokens + (now - self.updated_at).as_secs_f64() * per_sec).min(capacity);
+        self.updated_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return Ok(());
+        }
+
+        if per_sec == 0.0 {
+            return Err(Duration::from_secs(60));
+        }
+
+        Err(Duration::from_secs_f64((1.0 - self.tokens) / per_sec))
+    }
+}
+
+pub struct Authenticator {
+    db: Arc<PgPool>,
+    enabled: bool,
+    default_rate_limit: u32,
+    public_routes: HashSet<String>,
+    /// Keyed by key hash.
+    cache: Mutex<HashMap<String, CachedKey>>,
+    /// Keyed by key id.
+    buckets: Mutex<HashMap<i32, Bucket>>,
+    usage: Arc<UsageMeter>,
+}
+
+impl Authenticator {
+    pub fn new(config: &AuthConfig, db: Arc<PgPool>) -> Self {
+        Authenticator {
+            usage: Arc::new(UsageMeter::new(Arc::clone(&db))),
+            db,
+            enabled: config.enabled,
+            default_rate_limit: config.rate_limit_per_minute,
+            public_routes: config.public_routes.iter().cloned().collect(),
+            cache: Mutex::new(HashMap::new()),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// Usage of the keys, counted as they are allowed.
+    pub fn usage(&self) -> Arc<UsageMeter> {
+        Arc::clone(&self.usage)
+    }
+
+    /// Whether the route with this pattern is served without a key.
+    pub fn is_public(&self, pattern: &str) -> bool {
+        self.public_routes.contains(pattern)
+    }
+
+    /// Check a request carrying `secret`, counting it against the key's rate limit and, if it is
+    /// allowed, towards the key's usage.
+    pub async fn check(&self, secret: Option<&str>) -> anyhow::Result<Verdict> {
+        let Some(secret) = secret else {
+            return Ok(Verdict::Unauthorized);
+        };
+
+        let Some(key) = self.lookup(&hash_key(secret)).await? else {
+            return Ok(Verdict::Unauthorized);
+        };
+
+        let per_minute = key
+            .rate_limit_per_minute
+            .map_or(self.default_rate_limit, |limit| limit.max(0) as u32);
+        let now = Instant::now();
+
+        let taken = self
+            .buckets
+            .lock()
+            .expect("rate limit lock poisoned")
+            .entry(key.id)
+            .or_insert_with(|| Bucket {
+                tokens: f64::from(per_minute),
+                updated_at: now,
+            })
+            .take(per_minute, now);
+
+        Ok(match taken {
+            Ok(()) => {
+                self.usage.record_request(key.id);
+                Verdict::Allowed { key_id: key.id }
+            }
+            Err(retry_after) => Verdict::RateLimited { retry_after },
+        })
+    }
+
+    async fn lookup(&self, key_hash: &str) -> anyhow::Result<Option<ApiKey>> {
+        {
+            let cache = self.cache.lock().expect("API key cache lock poisoned");
+
+            if let Some(cached) = cache.get(key_hash) {
+                if cached.cached_at.elapsed() < CACHE_TTL {
+                    return Ok(cached.key.clone());
+                }
+            }
+        }
+
+        let key = get_api_key_by_hash(&self.db, key_hash).await?;
+
+        let mut cache = self.cache.lock().expect("API key cache lock poisoned");
+
+        if cache.len() >= CACHE_PRUNE_LEN {
+            cache.retain(|_, cached| cached.cached_at.elapsed() < CACHE_TTL);
+        }
+
+        cache.insert(
+            key_hash.to_string(),
+            CachedKey {
+                key: key.clone(),
+                cached_at: Instant::now(),
+            },
+        );
+
+        Ok(key)
+    }
+
+    pub async fn create_key(
+        &self,
+        name: &str,
+        rate_limit_per_minute: Option<i32>,
+    ) -> anyhow::Result<NewApiKey> {
+        let mut bytes = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut bytes);
+        let secret = hex::encode(bytes);
+
+        let key = insert_api_key(
+            &self.db,
+            name,
+            &secret[..PREFIX_LEN],
+            &hash_key(&secret),
+            rate_limit_per_minute,
+            unix_now(),
+        )
+        .await?;
+
+        Ok(NewApiKey { key, secret })
+    }
+
+    pub async fn keys(&self) -> anyhow::Result<Vec<ApiKey>> {
+        get_api_keys(&self.db).await
+    }
+
+    /// Revoke a key. Returns `false` if there is no such unrevoked key.
+    pub async fn revoke_key(&self, id: i32) -> anyhow::Result<bool> {
+        let Some(key_hash) = revoke_api_key(&self.db, id, unix_now()).await? else {
+            return Ok(false);
+        };
+
+        self.cache
+            .lock()
+            .expect("API key cache lock poisoned")
+            .remove(&key_hash);
+        self.buckets
+            .lock()
+            .expect("rate limit lock poisoned")
+            .remove(&id);
+
+        Ok(true)
+    }
+}
+
+fn hash_key(secret: &str) -> String {
+    hex::encode(Sha256::digest(secret.as_bytes()))
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_allows_bursts_then_refills() {
+        let start = Instant::now();
+        let mut bucket = Bucket {
+            tokens: 60.0,
+            updated_at: start,
+        };
+
+        for _ in 0..60 {
+            assert!(bucket.take(60, start).is_ok());
+        }
+
+        let retry_after = bucket.take(60, start).unwrap_err();
+        assert_eq!(retry_after, Duration::from_secs(1));
+
+        // one request per second comes back
+        assert!(bucket.take(60, start + Duration::from_secs(1)).is_ok());
+        assert!(bucket.take(60, start + Duration::from_secs(1)).is_err());
+
+        // and the bucket never holds more than a minute's worth
+        let later = start + Duration::from_secs(3600);
+        for _ in 0..60 {
+            assert!(bucket.take(60, later).is_ok());
+        }
+        assert!(bucket.take(60, later).is_err());
+    }
+
+    #[test]
+    fn test_zero_limit_blocks() {
+        let now = Instant::now();
+        let mut bucket = Bucket {
+            tokens: 0.0,
+            updated_at: now,
+        };
+
+        assert_eq!(bucket.take(0, now), Err(Duration::from_secs(60)));
+    }
+}
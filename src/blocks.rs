@@ -0,0 +1,171 @@
+// Picks the transactions of interest out of whole blocks without parsing the rest
+
+// Responsibilities:
+// * Keep the transactions of a `getBlock` response that touch given accounts, in the form
+//   `SolanaClient::process` takes, for block-level ingestion.
+
+// Implementation:
+// * `jsonParsed` blocks run to tens of megabytes, and nearly all of their transactions touch none
+//   of the accounts. Going through a `serde_json::Value`, as `RpcClient` does, allocates every
+//   string, number and map of the block before any of it is looked at.
+// * Instead the response body is deserialized with borrowed data: each transaction stays a
+//   `RawValue` slice of the body, of which only the account keys are read, as `&str`s into it.
+//   Only the transactions touching one of the accounts are deserialized in full.
+// * `benches/block_parsing.rs` compares this with deserializing the whole block.
+
+use crate::rpc::raw_result;
+
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use solana_transaction_status::{
+    EncodedConfirmedTransactionWithStatusMeta, EncodedTransactionWithStatusMeta,
+};
+
+use std::collections::HashSet;
+
+/// A block, with its transactions left unparsed.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct RawBlock<'a> {
+    block_time: Option<i64>,
+    #[serde(borrow, default)]
+    transactions: Vec<&'a RawValue>,
+}
+
+/// The account keys of a `jsonParsed` transaction, the only part of it read to tell whether it's
+/// kept.
+#[derive(Debug, Deserialize)]
+struct TransactionKeys<'a> {
+    #[serde(borrow)]
+    transaction: MessageKeys<'a>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MessageKeys<'a> {
+    #[serde(borrow)]
+    message: AccountKeys<'a>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AccountKeys<'a> {
+    #[serde(borrow)]
+    account_keys: Vec<AccountKey<'a>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct AccountKey<'a> {
+    pubkey: &'a str,
+}
+
+/// The transactions of the `getBlock` response `body`, for the block at `slot`, that touch any of
+/// `accounts`. Empty when the slot has no block.
+pub fn transactions_touching(
+    slot: u64,
+    body: &[u8],
+    accounts: &HashSet<String>,
+) -> anyhow::Result<Vec<EncodedConfirmedTransactionWithStatusMeta>> {
+    let Some(result) = raw_result(body)? else {
+        return Ok(Vec::new());
+    };
+    let block: RawBlock = serde_json::from_str(result.get())?;
+
+    let mut transactions = Vec::new();
+
+    for raw in block.transactions {
+        let keys: TransactionKeys = serde_json::from_str(raw.get())?;
+        let touching = keys
+            .transaction
+            .message
+            .account_keys
+            .iter()
+            .any(|key| accounts.contains(key.pubkey));
+
+        if touching {
+            let transaction: EncodedTransactionWithStatusMeta = serde_json::from_str(raw.get())?;
+
+            transactions.push(EncodedConfirmedTransactionWithStatusMeta {
+                slot,
+                transaction,
+                block_time: block.block_time,
+            });
+        }
+    }
+
+    Ok(transactions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::{json, Value};
+    use solana_transaction_status::EncodedTransaction;
+
+    use std::path::Path;
+
+    /// The transfer in `fixtures/rpc/transfer.json`, as a block transaction.
+    fn fixture_transaction() -> Value {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/rpc/transfer.json");
+        let exchanges: Vec<Value> = serde_json::from_slice(&std::fs::read(path).unwrap()).unwrap();
+
+        let mut exchange = exchanges
+            .into_iter()
+            .find(|exchange| exchange["method"] == "getTransaction")
+            .unwrap();
+        let mut transaction = exchange["result"].take();
+        let fields = transaction.as_object_mut().unwrap();
+        fields.remove("slot");
+        fields.remove("blockTime");
+
+        transaction
+    }
+
+    fn response(result: Value) -> Vec<u8> {
+        serde_json::to_vec(&json!({ "jsonrpc": "2.0", "id": 1, "result": result })).unwrap()
+    }
+
+    #[test]
+    fn test_keeps_only_transactions_touching_accounts() {
+        let watched = fixture_transaction();
+        let mut other = fixture_transaction();
+        other["transaction"]["message"]["accountKeys"] = json!([{
+            "pubkey": "11111111111111111111111111111112",
+            "writable": true,
+            "signer": true,
+            "source": "transaction",
+        }]);
+
+        let body = response(json!({
+            "blockHeight": 300000000,
+            "blockTime": 1720000000,
+            "blockhash": "14khGK3iuJX28TsTNCm2M7HHaJN13BuhqBgANFJJtLrx",
+            "parentSlot": 312000041,
+            "previousBlockhash": "4sZ76MsNd8y3WSw2L1nfd3AqLoYxdmC98sERoMRbHV14",
+            "transactions": [other, watched],
+        }));
+        let accounts = HashSet::from(["BFfZpV9Kh8MdAKCNcMwuqAUiEYkatcojP4PJ3CQuTJ12".to_string()]);
+
+        let transactions = transactions_touching(312000042, &body, &accounts).unwrap();
+
+        assert_eq!(transactions.len(), 1);
+        assert_eq!(transactions[0].slot, 312000042);
+        assert_eq!(transactions[0].block_time, Some(1720000000));
+        assert!(matches!(
+            transactions[0].transaction.transaction,
+            EncodedTransaction::Json(_)
+        ));
+
+        // skipped slots have no block, and errors are reported
+        assert!(transactions_touching(1, &response(Value::Null), &accounts)
+            .unwrap()
+            .is_empty());
+
+        let error = json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "error": { "code": -32007, "message": "Slot 1 was skipped" },
+        });
+        assert!(transactions_touching(1, &serde_json::to_vec(&error).unwrap(), &accounts).is_err());
+    }
+}
@@ -0,0 +1,436 @@
+// Caches transaction queries in front of the storage backend
+
+// Responsibilities:
+// * Serve repeated `/transactions` queries and signature lookups from a cache instead of hitting
+//   the database on every request.
+// * Invalidate the cache whenever transactions are stored, or their commitment status changes, so
+//   a cached page never hides a newer transaction for longer than it takes to insert it.
+// * Count hits, misses, backend errors and invalidations, reported at `/admin/cache`. The repo
+//   has no Prometheus endpoint, so the counters are served as JSON next to the other admin reports.
+
+// Implementation:
+// * `CachedStorage` wraps any `Storage`, so every backend and every caller of `query` and
+//   `get_by_signature` is cached alike. Inserts go straight through.
+// * Keys are a SHA-256 of the query's filters, prefixed with a generation number. Invalidating
+//   bumps the generation, which orphans every entry at once; orphans expire after `ttl_secs`.
+// * The cache is in process (`moka`) by default, or Redis with the `redis` feature, so replicas
+//   behind a load balancer share one cache. With Redis the generation is a key of its own, so an
+//   insert on any replica invalidates the cache of all of them.
+// * Results of more than `max_rows_per_entry` rows are not cached, so one full export can't evict
+//   every page.
+// * A failing cache backend is logged and counted, and queries fall through to storage.
+// * Transactions stored by another process sharing the database show up once the entries expire,
+//   unless it shares the Redis cache too.
+
+use crate::{
+    config::CacheConfig,
+    data_processing::TransactionData,
+    data_storage::{Storage, StoredTransaction, TransactionQuery},
+};
+
+use async_trait::async_trait;
+use log::warn;
+use moka::future::Cache;
+use serde::{de::DeserializeOwned, Serialize};
+use sha2::{Digest, Sha256};
+
+use std::{
+    collections::HashSet,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+/// Where cached query results are kept.
+#[async_trait]
+pub trait CacheBackend: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Current generation of the cache, bumped by every invalidation.
+    async fn generation(&self) -> anyhow::Result<u64>;
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>>;
+
+    async fn put(&self, key: String, value: Vec<u8>) -> anyhow::Result<()>;
+
+    /// Make every cached entry stale.
+    async fn invalidate(&self) -> anyhow::Result<()>;
+}
+
+/// In-process cache, bounded in entries and age.
+pub struct MemoryCache {
+    entries: Cache<String, Arc<Vec<u8>>>,
+    generation: AtomicU64,
+}
+
+impl MemoryCache {
+    pub fn new(max_entries: u64, ttl: Duration) -> Self {
+        MemoryCache {
+            entries: Cache::builder()
+                .max_capacity(max_entries)
+                .time_to_live(ttl)
+                .build(),
+            generation: AtomicU64::new(0),
+        }
+    }
+}
+
+#[async_trait]
+impl CacheBackend for MemoryCache {
+    fn name(&self) -> &'static str {
+        "memory"
+    }
+
+    async fn generation(&self) -> anyhow::Result<u64> {
+        Ok(self.generation.load(Ordering::Acquire))
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        Ok(self
+            .entries
+            .get(key)
+            .await
+            .map(|value| value.as_ref().clone()))
+    }
+
+    async fn put(&self, key: String, value: Vec<u8>) -> anyhow::Result<()> {
+        self.entries.insert(key, Arc::new(value)).await;
+        Ok(())
+    }
+
+    async fn invalidate(&self) -> anyhow::Result<()> {
+        self.generation.fetch_add(1, Ordering::AcqRel);
+        // entries of older generations can't be hit anymore; free them now
+        self.entries.invalidate_all();
+        Ok(())
+    }
+}
+
+/// Cache shared through Redis, entries expiring after the configured TTL.
+#[cfg(feature = "redis")]
+pub struct RedisCache {
+    connection: redis::aio::ConnectionManager,
+    key_prefix: String,
+    ttl_secs: u64,
+}
+
+#[cfg(feature = "redis")]
+impl RedisCache {
+    pub async fn connect(url: &str, key_prefix: &str, ttl: Duration) -> anyhow::Result<Self> {
+        let client = redis::Client::open(url)?;
+
+        Ok(RedisCache {
+            connection: redis::aio::ConnectionManager::new(client).await?,
+            key_prefix: key_prefix.to_string(),
+            ttl_secs: ttl.as_secs().max(1),
+        })
+    }
+
+    fn generation_key(&self) -> String {
+        format!("{}:generation", self.key_prefix)
+    }
+}
+
+#[cfg(feature = "redis")]
+#[async_trait]
+impl CacheBackend for RedisCache {
+    fn name(&self) -> &'static str {
+        "redis"
+    }
+
+    async fn generation(&self) -> anyhow::Result<u64> {
+        let generation: Option<u64> =
+            redis::AsyncCommands::get(&mut self.connection.clone(), self.generation_key()).await?;
+
+        Ok(generation.unwrap_or(0))
+    }
+
+    async fn get(&self, key: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let key = format!("{}:{key}", self.key_prefix);
+
+        Ok(redis::AsyncCommands::get(&mut self.connection.clone(), key).await?)
+    }
+
+    async fn put(&self, key: String, value: Vec<u8>) -> anyhow::Result<()> {
+        let key = format!("{}:{key}", self.key_prefix);
+
+        redis::AsyncCommands::set_ex::<_, _, ()>(
+            &mut self.connection.clone(),
+            key,
+            value,
+            self.ttl_secs,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    async fn invalidate(&self) -> anyhow::Result<()> {
+        redis::AsyncCommands::incr::<_, _, ()>(
+            &mut self.connection.clone(),
+            self.generation_key(),
+            1,
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "redis")]
+async fn redis_backend(config: &CacheConfig) -> anyhow::Result<Box<dyn CacheBackend>> {
+    let redis = config.redis.as_ref().expect("checked by the caller");
+    let ttl = Duration::from_secs(config.ttl_secs);
+
+    Ok(Box::new(
+        RedisCache::connect(&redis.url, &redis.key_prefix, ttl).await?,
+    ))
+}
+
+#[cfg(not(feature = "redis"))]
+async fn redis_backend(config: &CacheConfig) -> anyhow::Result<Box<dyn CacheBackend>> {
+    anyhow::bail!(
+        "A Redis cache at {} is configured, but this build lacks the `redis` feature",
+        config.redis.as_ref().expect("checked by the caller").url
+    )
+}
+
+/// Cache activity since startup.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct CacheReport {
+    pub backend: &'static str,
+    pub hits: u64,
+    pub misses: u64,
+    /// Cache backend failures, each served from storage instead.
+    pub errors: u64,
+    pub invalidations: u64,
+}
+
+/// Storage whose queries and signature lookups are cached.
+pub struct CachedStorage {
+    inner: Arc<dyn Storage>,
+    backend: Box<dyn CacheBackend>,
+    max_rows: usize,
+    report: Mutex<CacheReport>,
+}
+
+impl CachedStorage {
+    /// Cache `inner` in Redis if it's configured, in process otherwise.
+    pub async fn new(config: &CacheConfig, inner: Arc<dyn Storage>) -> anyhow::Result<Self> {
+        let backend = match &config.redis {
+            Some(_) => redis_backend(config).await?,
+            None => Box::new(MemoryCache::new(
+                config.max_entries,
+                Duration::from_secs(config.ttl_secs),
+            )),
+        };
+
+        Ok(Self::with_backend(
+            backend,
+            config.max_rows_per_entry,
+            inner,
+        ))
+    }
+
+    pub fn with_backend(
+        backend: Box<dyn CacheBackend>,
+        max_rows: usize,
+        inner: Arc<dyn Storage>,
+    ) -> Self {
+        let report = CacheReport {
+            backend: backend.name(),
+            ..CacheReport::default()
+        };
+
+        CachedStorage {
+            inner,
+            backend,
+            max_rows,
+            report: Mutex::new(report),
+        }
+    }
+
+    /// Drop every cached result, for changes to stored transactions that don't go through
+    /// `insert_batch`.
+    pub async fn invalidate(&self) {
+        match self.backend.invalidate().await {
+            Ok(()) => self.count(|report| report.invalidations += 1),
+            Err(e) => self.failed("invalidate", e),
+        }
+    }
+
+    pub fn report(&self) -> CacheReport {
+        self.report
+            .lock()
+            .expect("cache report lock poisoned")
+            .clone()
+    }
+
+    /// The cached value of `key`, if there is one. `None` on a miss or a backend failure, with
+    /// the key to store the value under if it could be built.
+    async fn cached<T: DeserializeOwned>(&self, key: &str) -> (Option<T>, Option<String>) {
+        let key = match self.backend.generation().await {
+            Ok(generation) => format!("{generation}:{}", hex::encode(Sha256::digest(key))),
+            Err(e) => {
+                self.failed("read", e);
+                return (None, None);
+            }
+        };
+
+        let value = match self.backend.get(&key).await {
+            Ok(value) => value,
+            Err(e) => {
+                self.failed("read", e);
+                return (None, None);
+            }
+        };
+
+        match value.map(|value| serde_json::from_slice(&value)) {
+            Some(Ok(value)) => {
+                self.count(|report| report.hits += 1);
+                (Some(value), None)
+            }
+            Some(Err(e)) => {
+                self.failed("decode", e.into());
+                (None, Some(key))
+            }
+            None => {
+                self.count(|report| report.misses += 1);
+                (None, Some(key))
+            }
+        }
+    }
+
+    async fn store<T: Serialize>(&self, key: Option<String>, value: &T) {
+        let Some(key) = key else {
+            return;
+        };
+
+        let stored = match serde_json::to_vec(value) {
+            Ok(value) => self.backend.put(key, value).await,
+            Err(e) => Err(e.into()),
+        };
+
+        if let Err(e) = stored {
+            self.failed("write", e);
+        }
+    }
+
+    fn failed(&self, action: &str, e: anyhow::Error) {
+        warn!("Failed to {action} the query cache: {e:?}");
+        self.count(|report| report.errors += 1);
+    }
+
+    fn count(&self, update: impl FnOnce(&mut CacheReport)) {
+        update(&mut self.report.lock().expect("cache report lock poisoned"));
+    }
+}
+
+#[async_trait]
+impl Storage for CachedStorage {
+    async fn insert_batch(&self, txns: &[TransactionData]) -> anyhow::Result<Vec<String>> {
+        let inserted = self.inner.insert_batch(txns).await?;
+
+        if !inserted.is_empty() {
+            self.invalidate().await;
+        }
+
+        Ok(inserted)
+    }
+
+    async fn get_by_signature(&self, signature: &str) -> anyhow::Result<Option<StoredTransaction>> {
+        let (cached, key) = self.cached(&format!("signature:{signature}")).await;
+
+        if let Some(stored) = cached {
+            return Ok(stored);
+        }
+
+        let stored = self.inner.get_by_signature(signature).await?;
+        self.store(key, &stored).await;
+
+        Ok(stored)
+    }
+
+    async fn stored_signatures(&self, signatures: &[String]) -> anyhow::Result<HashSet<String>> {
+        // only used by ingestion, which needs it fresh
+        self.inner.stored_signatures(signatures).await
+    }
+
+    async fn query(&self, query: &TransactionQuery) -> anyhow::Result<Vec<StoredTransaction>> {
+        let (cached, key) = self.cached(&format!("query:{query:?}")).await;
+
+        if let Some(rows) = cached {
+            return Ok(rows);
+        }
+
+        let rows = self.inner.query(query).await?;
+
+        if rows.len() <= self.max_rows {
+            self.store(key, &rows).await;
+        }
+
+        Ok(rows)
+    }
+
+    async fn version(&self) -> anyhow::Result<i64> {
+        // what conditional requests are checked against, so it has to be fresh
+        self.inner.version().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data_storage::InMemoryStorage;
+
+    use solana_sdk::{pubkey::Pubkey, signature::Signature};
+
+    fn transaction() -> TransactionData {
+        TransactionData {
+            signature: Signature::new_unique().to_string(),
+            sender: Pubkey::new_unique().to_string(),
+            receiver: Pubkey::new_unique().to_string(),
+            sol_amount: 1000,
+            fee: 5000,
+            timestamp: 1625077743,
+            prev_blockhash: "4sZ76MsNd8y3WSw2L1nfd3AqLoYxdmC98sERoMRbHV14".to_string(),
+            slot: 42,
+            ..TransactionData::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_queries_are_cached_until_an_insert() -> anyhow::Result<()> {
+        let storage =
+            CachedStorage::new(&CacheConfig::default(), Arc::new(InMemoryStorage::new())).await?;
+        let query = TransactionQuery::default();
+
+        storage.insert(&transaction()).await?;
+        assert_eq!(storage.query(&query).await?.len(), 1);
+        assert_eq!(storage.query(&query).await?.len(), 1);
+
+        let report = storage.report();
+        assert_eq!(report.backend, "memory");
+        assert_eq!((report.hits, report.misses), (1, 1));
+
+        // inserting invalidates, so the next query sees the new transaction
+        let txn = transaction();
+        storage.insert(&txn).await?;
+        assert_eq!(storage.query(&query).await?.len(), 2);
+        assert_eq!(storage.report().invalidations, 2);
+
+        // inserting nothing new keeps the cache
+        storage.insert(&txn).await?;
+        assert_eq!(storage.query(&query).await?.len(), 2);
+        assert_eq!(storage.report().hits, 2);
+
+        let stored = storage.get_by_signature(&txn.signature).await?;
+        assert_eq!(stored.map(|stored| stored.id), Some(2));
+        assert!(storage.get_by_signature("missing").await?.is_none());
+
+        Ok(())
+    }
+}
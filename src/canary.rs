@@ -0,0 +1,144 @@
+// End-to-end synthetic probe ("canary") for the ingestion pipeline
+
+// Responsibilities:
+// * Periodically send a tiny self-transfer from a configured devnet keypair.
+// * Verify that the transaction is ingested into the database and served by the API within an SLO.
+// * Raise an operational alert when the SLO is breached.
+
+// Implementation:
+// * The canary address is monitored like any other address, so the probe exercises the real
+//   fetch → process → store → serve path.
+// * Only devnet is allowed, checked against the cluster's genesis hash.
+
+use crate::{
+    alerting::Alerter, config::CanaryConfig, data_retrieval::SolanaClient, data_storage::Storage,
+    schedules::Job,
+};
+
+use log::info;
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{read_keypair_file, Keypair},
+    signer::Signer,
+};
+use tokio::time::{self, Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+use std::sync::Arc;
+
+const DEVNET_GENESIS_HASH: &str = "EtWTRABZaYq6iMfeYKouRu166VU2xqa1wcaWoxPkrZBG";
+
+/// Amount moved by each probe; a self-transfer only costs the fee.
+const CANARY_LAMPORTS: u64 = 1;
+
+/// How often to check the database and API while waiting for a probe.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+pub struct Canary {
+    client: SolanaClient,
+    keypair: Keypair,
+    api_url: String,
+    slo: Duration,
+    http: reqwest::Client,
+    alerter: Alerter,
+}
+
+impl Canary {
+    /// Build the canary, checking that the RPC endpoint belongs to devnet.
+    pub async fn new(
+        config: &CanaryConfig,
+        rpc_url: &str,
+        api_url: String,
+        alerter: Alerter,
+    ) -> anyhow::Result<Self> {
+        let keypair = read_keypair_file(&config.keypair).map_err(|e| {
+            anyhow::anyhow!(
+                "Failed to read canary keypair `{}`: {e}",
+                config.keypair.display()
+            )
+        })?;
+
+        let client = SolanaClient::new(rpc_url);
+
+        let genesis_hash = client.genesis_hash().await?.to_string();
+        if genesis_hash != DEVNET_GENESIS_HASH {
+            anyhow::bail!("Canary mode is only supported on devnet (genesis hash {genesis_hash})");
+        }
+
+        Ok(Canary {
+            client,
+            keypair,
+            api_url: config.api_url.clone().unwrap_or(api_url),
+            slo: Duration::from_secs(config.slo_secs),
+            http: reqwest::Client::new(),
+            alerter,
+        })
+    }
+
+    /// Public key of the canary keypair, which must be monitored for the probe to succeed.
+    pub fn pubkey(&self) -> Pubkey {
+        self.keypair.pubkey()
+    }
+
+    /// Continuously probe the pipeline, alerting whenever a probe misses the SLO.
+    pub async fn run(&self, storage: &Arc<dyn Storage>, job: &Job, shutdown: &CancellationToken) {
+        while job.tick(shutdown).await {
+            let result = tokio::select! {
+                _ = shutdown.cancelled() => return,
+                result = job.run(self.probe(storage)) => result,
+            };
+
+            match result {
+                Ok(latency) => info!("Canary probe succeeded in {latency:?}"),
+                Err(e) => {
+                    self.alerter
+                        .raise("Canary probe failed", &format!("{e:#}"))
+                        .await
+                }
+            }
+        }
+    }
+
+    /// Send one self-transfer and wait for it to show up in the database and the API.
+    async fn probe(&self, storage: &Arc<dyn Storage>) -> anyhow::Result<Duration> {
+        let signature = self
+            .client
+            .send_self_transfer(&self.keypair, CANARY_LAMPORTS)
+            .await?
+            .to_string();
+        let sent_at = Instant::now();
+
+        info!("Sent canary transaction {signature}");
+
+        let mut stored = false;
+
+        while sent_at.elapsed() < self.slo {
+            time::sleep(POLL_INTERVAL).await;
+
+            if !stored {
+                stored = storage.get_by_signature(&signature).await?.is_some();
+            }
+
+            if stored && self.served_by_api(&signature).await? {
+                return Ok(sent_at.elapsed());
+            }
+        }
+
+        let stage = if stored { "API" } else { "storage" };
+
+        anyhow::bail!(
+            "Canary transaction {signature} did not reach the {stage} within {:?}",
+            self.slo
+        )
+    }
+
+    async fn served_by_api(&self, signature: &str) -> anyhow::Result<bool> {
+        let res = self
+            .http
+            .get(format!("{}/transactions/{signature}", self.api_url))
+            .send()
+            .await?;
+
+        Ok(res.status().is_success())
+    }
+}
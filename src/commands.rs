@@ -0,0 +1,141 @@
+// Runs one-shot CLI commands
+
+// Responsibilities:
+// * Backfill an address, fetch a single transaction, export stored transactions or apply
+//   migrations from the command line, without starting the API server or the monitor.
+
+// Implementation:
+// * Each command uses the same config, RPC client, storage backends and export encoding as the
+//   server, so ad hoc runs behave exactly like the running service.
+// * Results go to standard output and logs to standard error, so output can be piped.
+// * Backfilled transactions are stored like monitored ones, fee splits included on Postgres, but
+//   their raw payloads aren't archived.
+
+use crate::{open_storage, solana_client};
+
+use solana_data_aggregator::{
+    config::{Config, ExportArgs, StorageBackend},
+    data_storage::{get_labels, get_pool, get_sqlite_pool, TransactionQuery},
+    export,
+    ids::IdCodec,
+    labels::LabelRegistry,
+};
+
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use tokio::{
+    fs::File,
+    io::{self, AsyncWrite, AsyncWriteExt},
+};
+use tokio_stream::StreamExt;
+
+use std::{str::FromStr, sync::Arc};
+
+/// Fetch, process and store up to `limit` of an address's most recent transactions.
+pub async fn backfill(config: &Config, pubkey: &str, limit: usize) -> anyhow::Result<()> {
+    let address = parse_pubkey(pubkey)?;
+    let solana_client = solana_client(config).await;
+    let (db, storage) = open_storage(config).await?;
+
+    let signatures = solana_client
+        .fetch_recent_signatures(&address, limit)
+        .await?;
+    let txns = solana_client.fetch_transactions(&signatures).await?;
+    let batch = solana_client.process(txns, None);
+    let stored = solana_client.store(&batch, &storage, db.as_ref()).await?;
+
+    println!(
+        "Fetched {} signatures, {} valid transactions, {} newly stored",
+        signatures.len(),
+        batch.transactions.len(),
+        stored.len()
+    );
+
+    Ok(())
+}
+
+/// Print a transaction as JSON: parsed like stored ones, or as the RPC node returned it.
+pub async fn fetch(config: &Config, signature: &str, raw: bool) -> anyhow::Result<()> {
+    let signature = Signature::from_str(signature)
+        .map_err(|_| anyhow::anyhow!("Invalid signature {signature}"))?;
+    let solana_client = solana_client(config).await;
+
+    let Some(txn) = solana_client.fetch_transactions(&[signature]).await?.pop() else {
+        anyhow::bail!("Transaction {signature} not found");
+    };
+
+    if raw {
+        println!("{}", serde_json::to_string_pretty(&txn)?);
+        return Ok(());
+    }
+
+    let Some(parsed) = solana_client.process(vec![txn], None).transactions.pop() else {
+        anyhow::bail!("Transaction {signature} isn't a transfer the aggregator stores; see --raw");
+    };
+
+    println!("{}", serde_json::to_string_pretty(&parsed)?);
+
+    Ok(())
+}
+
+/// Write the matching stored transactions to a file, or to standard output.
+pub async fn export(config: &Config, args: &ExportArgs) -> anyhow::Result<()> {
+    let address = args.address.as_deref().map(parse_pubkey).transpose()?;
+    let (db, storage) = open_storage(config).await?;
+    let labels = Arc::new(LabelRegistry::new());
+
+    if let Some(db) = &db {
+        for label in get_labels(db).await? {
+            labels.register(label)?;
+        }
+    }
+
+    let query = TransactionQuery {
+        address: address.map(|address| address.to_string()),
+        from: args.from,
+        to: args.to,
+        ..TransactionQuery::default()
+    };
+
+    let mut output: Box<dyn AsyncWrite + Unpin> = match &args.output {
+        Some(path) => Box::new(File::create(path).await?),
+        None => Box::new(io::stdout()),
+    };
+
+    let mut chunks = export::export(
+        storage,
+        IdCodec::from_config(&config.ids)?,
+        labels,
+        query,
+        args.format,
+        None,
+    );
+
+    while let Some(chunk) = chunks.next().await {
+        output.write_all(&chunk?).await?;
+    }
+
+    output.flush().await?;
+
+    Ok(())
+}
+
+/// Apply the configured database's pending migrations.
+pub async fn migrate(config: &Config) -> anyhow::Result<()> {
+    match config.storage {
+        StorageBackend::Postgres => {
+            get_pool(&config.database_url).await?;
+        }
+        StorageBackend::Sqlite => {
+            get_sqlite_pool(&config.database_url).await?;
+        }
+        StorageBackend::Memory => anyhow::bail!("In-memory storage has no migrations"),
+    }
+
+    println!("Migrations are up to date");
+
+    Ok(())
+}
+
+fn parse_pubkey(pubkey: &str) -> anyhow::Result<Pubkey> {
+    Pubkey::from_str(pubkey).map_err(|_| anyhow::anyhow!("Invalid public key {pubkey}"))
+}
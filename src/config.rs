@@ -0,0 +1,1862 @@
+// Loads the application configuration
+
+// Responsibilities:
+// * Read settings from an optional TOML or YAML config file.
+// * Apply overrides from CLI arguments and environment variables.
+// * Fail fast, listing every required setting that is missing.
+
+// Implementation:
+// * Use `clap` for CLI arguments (each argument also reads its environment variable).
+// * Use `serde` to deserialize the config file.
+// * Precedence: CLI argument > environment variable > config file > default.
+
+use crate::{
+    dashboards::{self, Dashboard},
+    export::ExportFormat,
+    layouts::AccountLayout,
+};
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use serde::Deserialize;
+use solana_sdk::pubkey::Pubkey;
+use tokio::time::Duration;
+
+use std::{collections::HashMap, fs, path::Path, path::PathBuf, str::FromStr};
+
+const DEFAULT_HOST: &str = "127.0.0.1";
+const DEFAULT_PORT: u16 = 8080;
+const DEFAULT_LOG_LEVEL: &str = "info";
+const DEFAULT_POLL_INTERVAL_SECS: u64 = 10;
+const DEFAULT_PROGRAM_SIGNATURES_PER_POLL: usize = 100;
+
+#[derive(Debug, Parser)]
+#[command(version, about = "Aggregates Solana transactions into PostgreSQL and serves them over REST")]
+pub struct Cli {
+    /// What to run; `serve` if omitted.
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Path to a TOML or YAML config file.
+    #[arg(short, long, env = "CONFIG_FILE", global = true)]
+    pub config: Option<PathBuf>,
+
+    /// Solana RPC endpoint; repeat for fallbacks, tried in order.
+    #[arg(
+        long = "rpc-url",
+        env = "RPC_URL",
+        value_delimiter = ',',
+        global = true
+    )]
+    pub rpc_urls: Vec<String>,
+
+    /// Record every RPC response to this JSON file, to replay as a test fixture.
+    #[arg(long, value_name = "FILE", global = true)]
+    pub record_rpc: Option<PathBuf>,
+
+    /// Where transactions are stored.
+    #[arg(long, env = "STORAGE_BACKEND", value_enum, global = true)]
+    pub storage: Option<StorageBackend>,
+
+    /// PostgreSQL (or, for the SQLite backend, `sqlite://`) connection string.
+    #[arg(long, env = "DATABASE_URL", global = true)]
+    pub database_url: Option<String>,
+
+    /// Address the API server binds to.
+    #[arg(long, env = "HOST")]
+    pub host: Option<String>,
+
+    /// Port the API server binds to.
+    #[arg(long, env = "PORT")]
+    pub port: Option<u16>,
+
+    /// Address to monitor; repeat for several.
+    #[arg(long = "address", env = "WATCHED_ADDRESSES", value_delimiter = ',')]
+    pub addresses: Vec<String>,
+
+    /// Deprecated alias of `--address`.
+    #[arg(long = "address-a", env = "ADDRESS_A", value_delimiter = ',', hide = true)]
+    pub legacy_addresses: Vec<String>,
+
+    /// Program id whose transactions to monitor; repeat for several.
+    #[arg(long = "program", env = "WATCHED_PROGRAMS", value_delimiter = ',')]
+    pub programs: Vec<String>,
+
+    /// Log filter, in `env_logger` syntax.
+    #[arg(long, env = "RUST_LOG", global = true)]
+    pub log_level: Option<String>,
+
+    /// Seconds between polls of the watched addresses.
+    #[arg(long, env = "POLL_INTERVAL_SECS")]
+    pub poll_interval_secs: Option<u64>,
+
+    /// Create an API key with this name, print it and exit.
+    #[arg(long, value_name = "NAME")]
+    pub create_api_key: Option<String>,
+
+    /// Serve bundled sample transactions from memory instead of monitoring the chain; needs no
+    /// database, RPC endpoint or addresses.
+    #[arg(long)]
+    pub demo: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Subcommand)]
+pub enum Command {
+    /// Monitor the watched addresses and serve the API.
+    Serve,
+    /// Fetch and store up to `limit` of an address's most recent transactions, then exit.
+    Backfill {
+        pubkey: String,
+        /// At most 1000.
+        #[arg(long, default_value_t = 100)]
+        limit: usize,
+    },
+    /// Fetch a transaction from RPC and print it as JSON, without storing it.
+    Fetch {
+        signature: String,
+        /// Print the RPC node's payload instead of the parsed transaction.
+        #[arg(long)]
+        raw: bool,
+    },
+    /// Write stored transactions to a CSV or Parquet file.
+    Export(ExportArgs),
+    /// Apply pending database migrations, then exit.
+    Migrate,
+}
+
+impl Command {
+    fn needs_rpc(&self) -> bool {
+        matches!(
+            self,
+            Command::Serve | Command::Backfill { .. } | Command::Fetch { .. }
+        )
+    }
+
+    fn needs_storage(&self) -> bool {
+        !matches!(self, Command::Fetch { .. })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Args)]
+pub struct ExportArgs {
+    #[arg(long, value_enum, default_value_t)]
+    pub format: ExportFormat,
+    /// Only transactions at or after this unix time.
+    #[arg(long)]
+    pub from: Option<i64>,
+    /// Only transactions at or before this unix time.
+    #[arg(long)]
+    pub to: Option<i64>,
+    /// Only transactions sent or received by this address.
+    #[arg(long)]
+    pub address: Option<String>,
+    /// File to write; standard output if omitted.
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    #[default]
+    Postgres,
+    /// Transactions only, in the SQLite database at `database_url`.
+    Sqlite,
+    /// Keeps transactions in memory, for demos and tests. Postgres-only features are disabled.
+    Memory,
+}
+
+/// Settings as they appear in the config file; everything is optional here.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FileConfig {
+    pub rpc_urls: Vec<String>,
+    pub storage: Option<StorageBackend>,
+    pub database_url: Option<String>,
+    pub host: Option<String>,
+    pub port: Option<u16>,
+    pub addresses: Vec<String>,
+    pub programs: Vec<String>,
+    /// Most recent signatures fetched per poll of a watched program.
+    pub program_signatures_per_poll: Option<usize>,
+    pub log_level: Option<String>,
+    pub poll_interval_secs: Option<u64>,
+    pub alert_webhook_url: Option<String>,
+    pub canary: Option<CanaryConfig>,
+    pub public: Option<PublicConfig>,
+    pub grpc: Option<GrpcConfig>,
+    pub follower: Option<FollowerConfig>,
+    pub geyser: Option<GeyserConfig>,
+    pub tls: Option<TlsConfig>,
+    pub cors: CorsConfig,
+    pub auth: AuthConfig,
+    pub memory: MemoryConfig,
+    pub portfolio: PortfolioConfig,
+    pub prices: PricesConfig,
+    pub sinks: SinksConfig,
+    pub cache: CacheConfig,
+    pub ids: IdConfig,
+    pub slo: SloConfig,
+    pub fee_burn: FeeBurnConfig,
+    pub balances: BalanceConfig,
+    pub account_watch: AccountWatchConfig,
+    pub pipeline: PipelineConfig,
+    pub dedup: DedupConfig,
+    pub dlq: DlqConfig,
+    pub alerts: AlertsConfig,
+    pub reports: ReportsConfig,
+    pub gaps: GapConfig,
+    pub finality: FinalityConfig,
+    pub rpc_retry: RpcRetryConfig,
+    pub rpc_rate_limit: RpcRateLimitConfig,
+    pub rpc_proxy: RpcProxyConfig,
+    pub personal: PersonalConfig,
+    pub stats: StatsConfig,
+    pub account_details: AccountDetailsConfig,
+    pub raw_archive: RawArchiveConfig,
+    pub analysis: AnalysisConfig,
+    pub epochs: EpochConfig,
+    pub enrichment: EnrichmentConfig,
+    pub directory: DirectoryConfig,
+    pub timescale: TimescaleConfig,
+    pub maintenance: MaintenanceConfig,
+    pub layouts: Vec<AccountLayout>,
+    pub idls: Vec<IdlConfig>,
+    pub dashboards: Vec<Dashboard>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CanaryConfig {
+    /// Path to the devnet keypair that sends the probes.
+    pub keypair: PathBuf,
+    #[serde(default = "default_canary_interval_secs")]
+    pub interval_secs: u64,
+    #[serde(default = "default_canary_slo_secs")]
+    pub slo_secs: u64,
+    /// Base URL the probe checks the API on; defaults to the configured bind address.
+    pub api_url: Option<String>,
+}
+
+/// Second, public listener that only serves read endpoints.
+///
+/// When set, the listener on `host` and `port` is private: it keeps serving every route,
+/// including admin and write endpoints, and should not be reachable from outside.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PublicConfig {
+    #[serde(default = "default_public_host")]
+    pub host: String,
+    pub port: u16,
+}
+
+/// gRPC server run alongside the REST API; only served when built with the `grpc` feature.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GrpcConfig {
+    #[serde(default = "default_grpc_host")]
+    pub host: String,
+    pub port: u16,
+}
+
+/// Warm standby mode: replicate a primary aggregator's transactions instead of polling the chain
+/// until promoted.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FollowerConfig {
+    /// Base URL of the primary's API, e.g. `https://aggregator.internal:8080`.
+    pub primary_url: String,
+    /// Sent as `X-API-Key` when the primary requires API keys.
+    pub api_key: Option<String>,
+    #[serde(default = "default_follower_interval_secs")]
+    pub interval_secs: u64,
+    /// Transactions fetched from the primary per request.
+    #[serde(default = "default_follower_batch_size")]
+    pub batch_size: usize,
+}
+
+/// Yellowstone gRPC (Geyser) stream the monitor ingests from instead of polling RPC; only used
+/// when built with the `yellowstone` feature.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GeyserConfig {
+    /// e.g. `https://yellowstone.example.com:10000`.
+    pub endpoint: String,
+    /// Sent as `x-token` when the endpoint requires one.
+    pub x_token: Option<String>,
+    /// Seconds to wait before resubscribing once the stream drops.
+    #[serde(default = "default_geyser_reconnect_secs")]
+    pub reconnect_secs: u64,
+    /// Updates buffered between polls before the stream is held back.
+    #[serde(default = "default_geyser_buffer")]
+    pub buffer: usize,
+}
+
+/// TLS termination for both API listeners.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM certificate chain, leaf first.
+    pub cert_path: PathBuf,
+    /// PEM private key (PKCS#8, PKCS#1 or SEC1).
+    pub key_path: PathBuf,
+}
+
+/// Cross-origin access for browser dashboards served from other origins.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CorsConfig {
+    /// Origins such as `https://dashboard.example.com`, or `*` for any; empty disables CORS.
+    pub allowed_origins: Vec<String>,
+    /// How long browsers may cache preflight responses.
+    pub max_age_secs: usize,
+}
+
+impl Default for CorsConfig {
+    fn default() -> Self {
+        CorsConfig {
+            allowed_origins: Vec::new(),
+            max_age_secs: 3600,
+        }
+    }
+}
+
+/// API key authentication and per-key rate limiting.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Require an API key on every request; keys can be managed either way.
+    pub enabled: bool,
+    /// Requests per minute of keys without a limit of their own.
+    pub rate_limit_per_minute: u32,
+    /// Route patterns, as listed in the README (e.g. `/stats` or `/accounts/{pubkey}/stats`),
+    /// served without a key. Admin routes can't be listed.
+    pub public_routes: Vec<String>,
+    /// How often metered usage is written to the database.
+    pub usage_flush_interval_secs: u64,
+}
+
+impl Default for AuthConfig {
+    fn default() -> Self {
+        AuthConfig {
+            enabled: false,
+            rate_limit_per_minute: 600,
+            public_routes: Vec::new(),
+            usage_flush_interval_secs: 60,
+        }
+    }
+}
+
+/// Memory the ingestion pipeline may hold at once.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MemoryConfig {
+    /// Estimated size of fetched and processed transactions in flight above which new fetches
+    /// wait; 0 for no limit.
+    pub budget_mb: u64,
+}
+
+/// Where account portfolios get token prices from.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PortfolioConfig {
+    /// Jupiter-compatible price API; portfolios have no USD values without one.
+    pub price_api_url: Option<String>,
+}
+
+/// Where prices come from to value transactions in USD.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PriceProviderKind {
+    /// Historical prices from CoinGecko's API.
+    CoinGecko,
+    /// Current prices from Pyth's on-chain price accounts, read through the RPC node.
+    Pyth,
+}
+
+/// USD prices recorded with each stored transaction, at its block time.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PricesConfig {
+    /// `None` stores transactions unpriced.
+    pub provider: Option<PriceProviderKind>,
+    /// CoinGecko API base URL.
+    pub api_url: String,
+    /// CoinGecko demo or pro API key.
+    pub api_key: Option<String>,
+    /// CoinGecko coin id or Pyth price account of SOL, instead of the provider's default.
+    pub sol_feed: Option<String>,
+    /// Tokens to price, by mint: CoinGecko coin ids or Pyth price accounts.
+    pub token_feeds: HashMap<String, String>,
+    /// Largest gap between a transaction's block time and the price it's given.
+    pub max_age_secs: u64,
+    /// Shortest time between two fetches of a mint's prices for new transactions.
+    pub refresh_secs: u64,
+}
+
+impl Default for PricesConfig {
+    fn default() -> Self {
+        PricesConfig {
+            provider: None,
+            api_url: "https://api.coingecko.com/api/v3".to_string(),
+            api_key: None,
+            sol_feed: None,
+            token_feeds: HashMap::new(),
+            max_age_secs: 3600,
+            refresh_secs: 60,
+        }
+    }
+}
+
+/// Message buses every newly ingested transaction is also published to, as JSON.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SinksConfig {
+    /// Only publish monitored transactions to the sinks, without storing them.
+    pub skip_storage: bool,
+    /// Needs a build with the `kafka` feature.
+    pub kafka: Option<KafkaSinkConfig>,
+    /// Needs a build with the `nats` feature.
+    pub nats: Option<NatsSinkConfig>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct KafkaSinkConfig {
+    /// Comma-separated `host:port` list, as in `bootstrap.servers`.
+    pub brokers: String,
+    pub topic: String,
+    /// Publish through an outbox stored with the transactions and a transactional producer, so
+    /// the topic has every stored transaction exactly once, across crashes. Needs Postgres
+    /// storage.
+    #[serde(default)]
+    pub exactly_once: bool,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct NatsSinkConfig {
+    /// e.g. `nats://127.0.0.1:4222`.
+    pub url: String,
+    pub subject: String,
+}
+
+/// Caching of transaction queries, dropped whenever new transactions are stored.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheConfig {
+    pub enabled: bool,
+    pub ttl_secs: u64,
+    /// Most results kept in memory; unused with Redis.
+    pub max_entries: u64,
+    /// Results with more transactions than this aren't cached.
+    pub max_rows_per_entry: usize,
+    /// Cache in Redis instead of in memory. Needs a build with the `redis` feature.
+    pub redis: Option<RedisCacheConfig>,
+}
+
+impl Default for CacheConfig {
+    fn default() -> Self {
+        CacheConfig {
+            enabled: false,
+            ttl_secs: 30,
+            max_entries: 10_000,
+            max_rows_per_entry: 1000,
+            redis: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RedisCacheConfig {
+    /// e.g. `redis://127.0.0.1:6379`.
+    pub url: String,
+    /// Prepended to every key, so instances with different databases can share a server.
+    #[serde(default = "default_redis_key_prefix")]
+    pub key_prefix: String,
+}
+
+fn default_redis_key_prefix() -> String {
+    "solana-data-aggregator".to_string()
+}
+
+/// How row ids are exposed in API responses and cursors.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct IdConfig {
+    /// Expose opaque hashids instead of raw auto-increment ids.
+    pub obfuscate: bool,
+    /// Deployment-specific salt; required when `obfuscate` is set and must never change.
+    pub salt: Option<String>,
+    pub min_length: usize,
+}
+
+impl Default for IdConfig {
+    fn default() -> Self {
+        IdConfig {
+            obfuscate: false,
+            salt: None,
+            min_length: 8,
+        }
+    }
+}
+
+/// Rolling-window latency and error tracking for API routes.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SloConfig {
+    pub window_secs: u64,
+    pub check_interval_secs: u64,
+    pub routes: Vec<RouteSlo>,
+}
+
+impl Default for SloConfig {
+    fn default() -> Self {
+        SloConfig {
+            window_secs: 300,
+            check_interval_secs: 60,
+            routes: Vec::new(),
+        }
+    }
+}
+
+/// SLO thresholds for a single route, identified by its pattern (e.g. `/transactions/{signature}`).
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct RouteSlo {
+    pub route: String,
+    pub p99_ms: Option<f64>,
+    pub max_error_ratio: Option<f64>,
+    /// Minimum requests in the window before the thresholds are enforced.
+    #[serde(default = "default_slo_min_requests")]
+    pub min_requests: usize,
+}
+
+fn default_slo_min_requests() -> usize {
+    20
+}
+
+/// Periodic balance snapshots of the watched addresses.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct BalanceConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,
+    /// Also snapshot the SPL token accounts each watched address owns.
+    pub tokens: bool,
+}
+
+impl Default for BalanceConfig {
+    fn default() -> Self {
+        BalanceConfig {
+            enabled: true,
+            interval_secs: 60,
+            tokens: true,
+        }
+    }
+}
+
+/// Non-wallet accounts (oracles, config PDAs, ...) whose data is versioned on every change.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountWatchConfig {
+    pub accounts: Vec<String>,
+    pub interval_secs: u64,
+}
+
+impl Default for AccountWatchConfig {
+    fn default() -> Self {
+        AccountWatchConfig {
+            accounts: Vec::new(),
+            interval_secs: 60,
+        }
+    }
+}
+
+impl AccountWatchConfig {
+    pub fn pubkeys(&self) -> anyhow::Result<Vec<Pubkey>> {
+        parse_pubkeys(&self.accounts, "watched account")
+    }
+}
+
+/// Which valid transactions are ingested; the rest are counted in the ingest metrics.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PipelineConfig {
+    pub include_failed: bool,
+    /// Transactions that move no SOL to the receiver.
+    pub include_zero_amount: bool,
+    /// Transactions invoking the vote program.
+    pub include_votes: bool,
+    pub validation: ValidationConfig,
+}
+
+impl Default for PipelineConfig {
+    fn default() -> Self {
+        PipelineConfig {
+            include_failed: true,
+            include_zero_amount: false,
+            include_votes: false,
+            validation: ValidationConfig::default(),
+        }
+    }
+}
+
+/// Which validation rules transactions must pass to be ingested.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ValidationConfig {
+    /// The signature isn't empty.
+    pub signature: bool,
+    /// Sender and receiver are valid public keys.
+    pub pubkeys: bool,
+    /// Sender and receiver differ.
+    pub distinct_parties: bool,
+    pub nonzero_fee: bool,
+    /// The block time isn't negative.
+    pub timestamp: bool,
+    /// The previous blockhash is a valid hash.
+    pub blockhash: bool,
+    /// Record rejected transactions and their reasons in `rejected_transactions` (Postgres only).
+    pub store_rejected: bool,
+}
+
+impl Default for ValidationConfig {
+    fn default() -> Self {
+        ValidationConfig {
+            signature: true,
+            pubkeys: true,
+            distinct_parties: true,
+            nonzero_fee: true,
+            timestamp: true,
+            blockhash: true,
+            store_rejected: true,
+        }
+    }
+}
+
+/// Periodic reconciliation of stored transactions against the chain, to repair gaps.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct GapConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,
+    /// Most signatures checked per address and run, the newest first.
+    pub max_signatures_per_address: usize,
+}
+
+impl Default for GapConfig {
+    fn default() -> Self {
+        GapConfig {
+            enabled: true,
+            interval_secs: 600,
+            max_signatures_per_address: 1000,
+        }
+    }
+}
+
+/// Periodic re-checks of transactions stored before they were finalized, to catch those dropped by
+/// a fork.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FinalityConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,
+    /// Most signatures checked per run, the oldest first.
+    pub max_signatures_per_run: usize,
+}
+
+impl Default for FinalityConfig {
+    fn default() -> Self {
+        FinalityConfig {
+            enabled: true,
+            interval_secs: 30,
+            max_signatures_per_run: 2000,
+        }
+    }
+}
+
+/// Retries of the RPC calls that fetch signatures and transactions.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RpcRetryConfig {
+    /// Rounds over all RPC endpoints, including the first.
+    pub max_attempts: u32,
+    pub initial_backoff_ms: u64,
+    pub max_backoff_ms: u64,
+    /// Least wait after a 429 response.
+    pub rate_limit_backoff_ms: u64,
+}
+
+impl Default for RpcRetryConfig {
+    fn default() -> Self {
+        RpcRetryConfig {
+            max_attempts: 4,
+            initial_backoff_ms: 250,
+            max_backoff_ms: 10_000,
+            rate_limit_backoff_ms: 5_000,
+        }
+    }
+}
+
+/// Requests per second sent to each RPC endpoint; unlimited by default.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RpcRateLimitConfig {
+    /// Limit of every endpoint without one of its own.
+    pub requests_per_second: Option<f64>,
+    /// Limits of specific endpoints, keyed by URL as given in `rpc_urls`.
+    pub endpoints: HashMap<String, f64>,
+}
+
+impl RpcRateLimitConfig {
+    /// Limit of the endpoint at `url`, if any.
+    pub fn for_endpoint(&self, url: &str) -> Option<f64> {
+        self.endpoints
+            .get(url)
+            .copied()
+            .or(self.requests_per_second)
+    }
+}
+
+/// Bloom filters of polled signatures, so polls skip transactions that are already stored.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DedupConfig {
+    pub enabled: bool,
+    /// Signatures per address and filter generation.
+    pub capacity_per_address: usize,
+    pub false_positive_rate: f64,
+    /// Where the filters are persisted; without it they start empty on every restart.
+    pub path: Option<PathBuf>,
+    pub persist_interval_secs: u64,
+}
+
+impl Default for DedupConfig {
+    fn default() -> Self {
+        DedupConfig {
+            enabled: true,
+            capacity_per_address: 10_000,
+            false_positive_rate: 0.001,
+            path: None,
+            persist_interval_secs: 60,
+        }
+    }
+}
+
+/// Dead-letter queue of transactions whose insert failed, retried until they're stored.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DlqConfig {
+    pub enabled: bool,
+    /// Where the queue is persisted, so it survives restarts.
+    pub path: PathBuf,
+    pub retry_interval_secs: u64,
+    /// Most transactions queued; the oldest are dropped beyond that.
+    pub max_transactions: usize,
+}
+
+impl Default for DlqConfig {
+    fn default() -> Self {
+        DlqConfig {
+            enabled: true,
+            path: PathBuf::from("dead-letters.json"),
+            retry_interval_secs: 60,
+            max_transactions: 100_000,
+        }
+    }
+}
+
+/// Alert rules registered through the API. Postgres only, where the rules are stored.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AlertsConfig {
+    pub enabled: bool,
+    /// Time between checks of the inactivity rules.
+    pub check_interval_secs: u64,
+    /// Batches of monitored transactions that may fire a rule waiting to be evaluated ahead of
+    /// their insert. Once full, they're evaluated after it instead.
+    pub lane_capacity: usize,
+    /// Server email alerts are sent through. Needs a build with the `email` feature.
+    pub smtp: Option<SmtpConfig>,
+}
+
+impl Default for AlertsConfig {
+    fn default() -> Self {
+        AlertsConfig {
+            enabled: true,
+            check_interval_secs: 300,
+            lane_capacity: 64,
+            smtp: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SmtpConfig {
+    /// Connected to with STARTTLS.
+    pub host: String,
+    #[serde(default = "default_smtp_port")]
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Sender address, e.g. `Aggregator <alerts@example.com>`.
+    pub from: String,
+}
+
+fn default_smtp_port() -> u16 {
+    587
+}
+
+/// Daily activity reports of the watched addresses. Postgres only, where they're stored.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ReportsConfig {
+    pub enabled: bool,
+    /// Time between checks for a day to report on.
+    pub interval_secs: u64,
+    /// Counterparties listed per address.
+    pub top_counterparties: usize,
+    /// Emailed each day's reports through the `[alerts.smtp]` server.
+    pub recipients: Vec<String>,
+}
+
+impl Default for ReportsConfig {
+    fn default() -> Self {
+        ReportsConfig {
+            enabled: true,
+            interval_secs: 3600,
+            top_counterparties: 5,
+            recipients: Vec::new(),
+        }
+    }
+}
+
+/// Personal mode: wallets whose token and stake accounts are discovered and watched.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PersonalConfig {
+    /// Public keys of the owned wallets, which are watched themselves too.
+    pub owners: Vec<String>,
+    pub stake_accounts: bool,
+    pub discovery_interval_secs: u64,
+}
+
+impl Default for PersonalConfig {
+    fn default() -> Self {
+        PersonalConfig {
+            owners: Vec::new(),
+            stake_accounts: true,
+            discovery_interval_secs: 300,
+        }
+    }
+}
+
+impl PersonalConfig {
+    pub fn owners(&self) -> anyhow::Result<Vec<Pubkey>> {
+        parse_pubkeys(&self.owners, "owner")
+    }
+}
+
+/// Aggregated statistics served at `/stats`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct StatsConfig {
+    /// How long computed statistics are served before being recomputed.
+    pub cache_ttl_secs: u64,
+    pub top_counterparties: i64,
+}
+
+impl Default for StatsConfig {
+    fn default() -> Self {
+        StatsConfig {
+            cache_ttl_secs: 10,
+            top_counterparties: 10,
+        }
+    }
+}
+
+/// Live account details served at `/accounts/{pubkey}`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountDetailsConfig {
+    /// How long an account read from the RPC node is served before being read again.
+    pub cache_ttl_secs: u64,
+}
+
+impl Default for AccountDetailsConfig {
+    fn default() -> Self {
+        AccountDetailsConfig { cache_ttl_secs: 5 }
+    }
+}
+
+/// Archiving of the raw, zstd-compressed RPC payloads of monitored transactions.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RawArchiveConfig {
+    pub enabled: bool,
+    pub level: i32,
+    /// Payloads to collect before training a compression dictionary; 0 disables the dictionary.
+    pub dictionary_samples: usize,
+    /// Maximum dictionary size in bytes.
+    pub dictionary_size: usize,
+}
+
+impl Default for RawArchiveConfig {
+    fn default() -> Self {
+        RawArchiveConfig {
+            enabled: true,
+            level: 3,
+            dictionary_samples: 1000,
+            dictionary_size: 110 * 1024,
+        }
+    }
+}
+
+/// On-demand analysis of unwatched addresses at `POST /analyze/{pubkey}`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnalysisConfig {
+    pub enabled: bool,
+    /// Most recent transactions backfilled per analysis; the RPC node caps this at 1000.
+    pub max_transactions: usize,
+    /// How long ad hoc data is kept after its last analysis; 0 keeps it forever.
+    pub ttl_secs: u64,
+    /// How often expired ad hoc data is deleted.
+    pub cleanup_interval_secs: u64,
+}
+
+impl Default for AnalysisConfig {
+    fn default() -> Self {
+        AnalysisConfig {
+            enabled: true,
+            max_transactions: 100,
+            ttl_secs: 7 * 86_400,
+            cleanup_interval_secs: 3600,
+        }
+    }
+}
+
+/// Caching JSON-RPC proxy at `POST /rpc` for `getTransaction` and `getSignaturesForAddress`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RpcProxyConfig {
+    pub enabled: bool,
+    /// How long `getSignaturesForAddress` responses are served from the cache.
+    pub signatures_ttl_secs: u64,
+    /// Responses kept in memory; 0 disables the cache, but not the raw archive.
+    pub max_cached_responses: usize,
+}
+
+impl Default for RpcProxyConfig {
+    fn default() -> Self {
+        RpcProxyConfig {
+            enabled: false,
+            signatures_ttl_secs: 5,
+            max_cached_responses: 10_000,
+        }
+    }
+}
+
+/// Polling of the current epoch, and tagging of stored transactions with theirs.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EpochConfig {
+    pub enabled: bool,
+    pub interval_secs: u64,
+}
+
+impl Default for EpochConfig {
+    fn default() -> Self {
+        EpochConfig {
+            enabled: true,
+            interval_secs: 60,
+        }
+    }
+}
+
+/// Enrichment of stored token transfers with the Metaplex metadata of the NFTs they move.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EnrichmentConfig {
+    pub nfts: bool,
+    /// Mints remembered as NFTs or not, so they're looked up once.
+    pub max_cached_mints: usize,
+}
+
+impl Default for EnrichmentConfig {
+    fn default() -> Self {
+        EnrichmentConfig {
+            nfts: true,
+            max_cached_mints: 100_000,
+        }
+    }
+}
+
+/// Directory of well-known programs and exchange wallets that transactions are categorized with.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DirectoryConfig {
+    /// Tag processed transactions with categories.
+    pub auto_tag: bool,
+    /// Start from the built-in entries.
+    pub builtin: bool,
+    /// JSON file of entries added to, or replacing, the built-in ones.
+    pub path: Option<PathBuf>,
+}
+
+impl Default for DirectoryConfig {
+    fn default() -> Self {
+        DirectoryConfig {
+            auto_tag: true,
+            builtin: true,
+            path: None,
+        }
+    }
+}
+
+/// TimescaleDB mode of Postgres storage: `transactions` as a hypertable, and the rollups as
+/// continuous aggregates.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TimescaleConfig {
+    pub enabled: bool,
+    /// Span of each chunk of `transactions`, in seconds of transaction timestamps.
+    pub chunk_interval_secs: u64,
+    pub refresh_interval_secs: u64,
+    /// How far back each refresh recomputes the rollups. At least three days, so a refresh always
+    /// covers two complete daily buckets.
+    pub refresh_lookback_secs: u64,
+}
+
+impl Default for TimescaleConfig {
+    fn default() -> Self {
+        TimescaleConfig {
+            enabled: false,
+            chunk_interval_secs: 7 * 86_400,
+            refresh_interval_secs: 300,
+            refresh_lookback_secs: 7 * 86_400,
+        }
+    }
+}
+
+/// Off-peak ANALYZE and index maintenance of the hot tables.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaintenanceConfig {
+    pub enabled: bool,
+    /// Times of day (UTC, `HH:MM`) to run at.
+    pub times: Vec<String>,
+    pub tables: Vec<String>,
+    /// Also rebuild the tables' indexes (concurrently, so writes aren't blocked).
+    pub reindex: bool,
+}
+
+impl Default for MaintenanceConfig {
+    fn default() -> Self {
+        MaintenanceConfig {
+            enabled: true,
+            times: vec!["03:30".to_string()],
+            tables: [
+                "transactions",
+                "fee_burns",
+                "raw_transactions",
+                "balances",
+                "token_balances",
+                "account_snapshots",
+                "transaction_rollups",
+                "address_stats",
+                "counterparty_stats",
+            ]
+            .map(String::from)
+            .to_vec(),
+            reindex: false,
+        }
+    }
+}
+
+impl MaintenanceConfig {
+    /// The configured run times as minutes after midnight UTC.
+    pub fn schedule(&self) -> anyhow::Result<Vec<u32>> {
+        self.times
+            .iter()
+            .map(|time| {
+                parse_time_of_day(time).ok_or_else(|| {
+                    anyhow::anyhow!("Invalid maintenance time `{time}`, expected HH:MM")
+                })
+            })
+            .collect()
+    }
+}
+
+fn parse_time_of_day(time: &str) -> Option<u32> {
+    let (hours, minutes) = time.split_once(':')?;
+    let hours = hours.parse::<u32>().ok().filter(|h| *h < 24)?;
+    let minutes = minutes.parse::<u32>().ok().filter(|m| *m < 60)?;
+
+    Some(hours * 60 + minutes)
+}
+
+/// An Anchor IDL whose account types are registered as layouts.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct IdlConfig {
+    pub path: PathBuf,
+    /// Program id, if the IDL doesn't carry one (or to override it).
+    pub program: Option<String>,
+}
+
+/// Rules for splitting fees into burned and validator portions.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeeBurnConfig {
+    pub lamports_per_signature: u64,
+    /// Epoch from which priority fees go entirely to the validator (SIMD-0096) on this cluster.
+    pub priority_fee_to_validator_epoch: Option<u64>,
+}
+
+impl Default for FeeBurnConfig {
+    fn default() -> Self {
+        FeeBurnConfig {
+            lamports_per_signature: 5000,
+            priority_fee_to_validator_epoch: None,
+        }
+    }
+}
+
+fn default_canary_interval_secs() -> u64 {
+    300
+}
+
+fn default_canary_slo_secs() -> u64 {
+    60
+}
+
+fn default_follower_interval_secs() -> u64 {
+    5
+}
+
+fn default_follower_batch_size() -> usize {
+    500
+}
+
+fn default_geyser_reconnect_secs() -> u64 {
+    5
+}
+
+fn default_geyser_buffer() -> usize {
+    10_000
+}
+
+fn default_public_host() -> String {
+    "0.0.0.0".to_string()
+}
+
+fn default_grpc_host() -> String {
+    DEFAULT_HOST.to_string()
+}
+
+/// Fully resolved configuration.
+#[derive(Debug, Clone)]
+pub struct Config {
+    pub rpc_urls: Vec<String>,
+    pub storage: StorageBackend,
+    /// Empty with the in-memory storage backend.
+    pub database_url: String,
+    pub host: String,
+    pub port: u16,
+    pub addresses: Vec<Pubkey>,
+    /// Program ids whose transactions are monitored alongside the addresses.
+    pub programs: Vec<Pubkey>,
+    pub program_signatures_per_poll: usize,
+    pub log_level: String,
+    pub poll_interval: Duration,
+    pub alert_webhook_url: Option<String>,
+    pub canary: Option<CanaryConfig>,
+    pub public: Option<PublicConfig>,
+    pub grpc: Option<GrpcConfig>,
+    pub follower: Option<FollowerConfig>,
+    /// The monitor polls RPC without it.
+    pub geyser: Option<GeyserConfig>,
+    /// Both listeners are plain HTTP without it.
+    pub tls: Option<TlsConfig>,
+    pub cors: CorsConfig,
+    pub auth: AuthConfig,
+    pub memory: MemoryConfig,
+    pub portfolio: PortfolioConfig,
+    pub prices: PricesConfig,
+    pub sinks: SinksConfig,
+    pub cache: CacheConfig,
+    pub ids: IdConfig,
+    pub slo: SloConfig,
+    pub fee_burn: FeeBurnConfig,
+    pub balances: BalanceConfig,
+    pub account_watch: AccountWatchConfig,
+    pub pipeline: PipelineConfig,
+    pub dedup: DedupConfig,
+    pub dlq: DlqConfig,
+    pub alerts: AlertsConfig,
+    pub reports: ReportsConfig,
+    pub gaps: GapConfig,
+    pub finality: FinalityConfig,
+    pub rpc_retry: RpcRetryConfig,
+    pub rpc_rate_limit: RpcRateLimitConfig,
+    pub rpc_proxy: RpcProxyConfig,
+    pub personal: PersonalConfig,
+    pub stats: StatsConfig,
+    pub account_details: AccountDetailsConfig,
+    pub raw_archive: RawArchiveConfig,
+    pub analysis: AnalysisConfig,
+    pub epochs: EpochConfig,
+    pub enrichment: EnrichmentConfig,
+    pub directory: DirectoryConfig,
+    pub timescale: TimescaleConfig,
+    pub maintenance: MaintenanceConfig,
+    pub layouts: Vec<AccountLayout>,
+    pub idls: Vec<IdlConfig>,
+    pub dashboards: Vec<Dashboard>,
+    /// Name of an API key to create instead of running.
+    pub create_api_key: Option<String>,
+    /// Serve the bundled sample transactions instead of monitoring the chain.
+    pub demo: bool,
+    /// Fixture file to record RPC responses to.
+    pub record_rpc: Option<PathBuf>,
+    pub command: Command,
+    /// Deprecated settings in use, to warn about once logging is set up.
+    pub deprecations: Vec<&'static str>,
+}
+
+impl Config {
+    /// Load the configuration from the CLI arguments, the environment and the config file.
+    pub fn load() -> anyhow::Result<Self> {
+        let cli = Cli::parse();
+
+        let file = match &cli.config {
+            Some(path) => FileConfig::read(path)?,
+            None => FileConfig::default(),
+        };
+
+        Self::resolve(cli, file)
+    }
+
+    /// Merge CLI arguments over file settings, reporting all missing settings at once.
+    pub fn resolve(cli: Cli, file: FileConfig) -> anyhow::Result<Self> {
+        let rpc_urls = if cli.rpc_urls.is_empty() {
+            file.rpc_urls
+        } else {
+            cli.rpc_urls
+        };
+
+        let command = cli.command.unwrap_or(Command::Serve);
+
+        if cli.demo && command != Command::Serve {
+            anyhow::bail!("--demo only applies to `serve`");
+        }
+
+        let mut deprecations = Vec::new();
+
+        let raw_addresses = if !cli.addresses.is_empty() {
+            cli.addresses
+        } else if !cli.legacy_addresses.is_empty() {
+            deprecations.push("ADDRESS_A is deprecated, set WATCHED_ADDRESSES instead");
+            cli.legacy_addresses
+        } else {
+            file.addresses
+        };
+        let raw_programs = if cli.programs.is_empty() {
+            file.programs
+        } else {
+            cli.programs
+        };
+
+        let database_url = cli.database_url.or(file.database_url);
+
+        // a `sqlite:` database URL selects SQLite unless a backend is given explicitly
+        let inferred_storage = match database_url.as_deref() {
+            Some(url) if url.starts_with("sqlite:") => StorageBackend::Sqlite,
+            _ => StorageBackend::Postgres,
+        };
+        let storage = if cli.demo {
+            StorageBackend::Memory
+        } else {
+            cli.storage.or(file.storage).unwrap_or(inferred_storage)
+        };
+
+        let mut missing = Vec::new();
+
+        if rpc_urls.is_empty() && !cli.demo && command.needs_rpc() {
+            missing.push("rpc_urls (--rpc-url / RPC_URL)");
+        }
+        if storage != StorageBackend::Memory && database_url.is_none() && command.needs_storage() {
+            missing.push("database_url (--database-url / DATABASE_URL)");
+        }
+        if raw_addresses.is_empty()
+            && raw_programs.is_empty()
+            && file.personal.owners.is_empty()
+            && !cli.demo
+            && command == Command::Serve
+        {
+            missing.push(
+                "addresses (--address / WATCHED_ADDRESSES), programs (--program / WATCHED_PROGRAMS) \
+                 or personal.owners",
+            );
+        }
+        if file.ids.obfuscate && file.ids.salt.is_none() {
+            missing.push("ids.salt (required when ids.obfuscate is set)");
+        }
+
+        if !missing.is_empty() {
+            anyhow::bail!("Missing required settings:\n  - {}", missing.join("\n  - "));
+        }
+
+        if (file.auth.enabled || cli.create_api_key.is_some())
+            && storage != StorageBackend::Postgres
+        {
+            anyhow::bail!("API keys need Postgres storage");
+        }
+
+        if let Some(route) = file
+            .auth
+            .public_routes
+            .iter()
+            .find(|route| !route.starts_with('/') || route.starts_with("/admin"))
+        {
+            anyhow::bail!("Invalid public route `{route}`: expected a non-admin route pattern");
+        }
+
+        for origin in &file.cors.allowed_origins {
+            if origin != "*" && !is_origin(origin) {
+                anyhow::bail!(
+                    "Invalid CORS origin `{origin}`: expected scheme://host[:port] or `*`"
+                );
+            }
+        }
+
+        if !(file.dedup.false_positive_rate > 0.0 && file.dedup.false_positive_rate < 1.0) {
+            anyhow::bail!("dedup.false_positive_rate must be between 0 and 1");
+        }
+
+        if let Some(rate) = file
+            .rpc_rate_limit
+            .requests_per_second
+            .iter()
+            .chain(file.rpc_rate_limit.endpoints.values())
+            .find(|rate| !(rate.is_finite() && **rate > 0.0))
+        {
+            anyhow::bail!(
+                "Invalid RPC rate limit {rate}: expected a positive number of requests per second"
+            );
+        }
+
+        if let Some(limit) = file
+            .program_signatures_per_poll
+            .filter(|limit| !(1..=1000).contains(limit))
+        {
+            anyhow::bail!("program_signatures_per_poll must be between 1 and 1000, got {limit}");
+        }
+
+        if file.gaps.enabled && file.gaps.max_signatures_per_address == 0 {
+            anyhow::bail!("gaps.max_signatures_per_address must be at least 1");
+        }
+
+        if file.finality.enabled && file.finality.max_signatures_per_run == 0 {
+            anyhow::bail!("finality.max_signatures_per_run must be at least 1");
+        }
+
+        if file.dlq.enabled && file.dlq.retry_interval_secs == 0 {
+            anyhow::bail!("dlq.retry_interval_secs must be at least 1");
+        }
+
+        if file.alerts.enabled && file.alerts.check_interval_secs == 0 {
+            anyhow::bail!("alerts.check_interval_secs must be at least 1");
+        }
+
+        if file.alerts.enabled && file.alerts.lane_capacity == 0 {
+            anyhow::bail!("alerts.lane_capacity must be at least 1");
+        }
+
+        if file.reports.enabled && file.reports.interval_secs == 0 {
+            anyhow::bail!("reports.interval_secs must be at least 1");
+        }
+
+        if !file.reports.recipients.is_empty() && file.alerts.smtp.is_none() {
+            anyhow::bail!("reports.recipients need an `[alerts.smtp]` server");
+        }
+
+        if file.prices.provider.is_some() && file.prices.max_age_secs == 0 {
+            anyhow::bail!("prices.max_age_secs must be at least 1");
+        }
+
+        if file.timescale.enabled {
+            if storage != StorageBackend::Postgres {
+                anyhow::bail!("TimescaleDB mode needs Postgres storage");
+            }
+
+            if file.timescale.chunk_interval_secs == 0 {
+                anyhow::bail!("timescale.chunk_interval_secs must be at least 1");
+            }
+
+            if file.timescale.refresh_lookback_secs < 3 * 86_400 {
+                anyhow::bail!(
+                    "timescale.refresh_lookback_secs must be at least 259200 (three days)"
+                );
+            }
+        }
+
+        if file.rpc_retry.max_attempts == 0 {
+            anyhow::bail!("rpc_retry.max_attempts must be at least 1");
+        }
+
+        if file.sinks.skip_storage && file.sinks.kafka.is_none() && file.sinks.nats.is_none() {
+            anyhow::bail!("sinks.skip_storage needs a Kafka or NATS sink to publish to");
+        }
+
+        let exactly_once = file
+            .sinks
+            .kafka
+            .as_ref()
+            .is_some_and(|kafka| kafka.exactly_once);
+
+        if exactly_once && file.sinks.skip_storage {
+            anyhow::bail!("sinks.kafka.exactly_once only publishes stored transactions, not with sinks.skip_storage");
+        }
+
+        // the demo only serves its sample transactions
+        let (addresses, programs) = if cli.demo {
+            (Vec::new(), Vec::new())
+        } else {
+            (
+                parse_pubkeys(&raw_addresses, "watched address")?,
+                parse_pubkeys(&raw_programs, "watched program")?,
+            )
+        };
+        file.account_watch.pubkeys()?;
+        file.personal.owners()?;
+        file.maintenance.schedule()?;
+        dashboards::validate(&file.dashboards)?;
+
+        let port = cli.port.or(file.port).unwrap_or(DEFAULT_PORT);
+
+        if matches!(&file.public, Some(public) if public.port == port) {
+            anyhow::bail!("public.port must differ from the private API port {port}");
+        }
+
+        Ok(Config {
+            rpc_urls,
+            storage,
+            database_url: database_url.unwrap_or_default(),
+            host: cli
+                .host
+                .or(file.host)
+                .unwrap_or_else(|| DEFAULT_HOST.to_string()),
+            port,
+            addresses,
+            programs,
+            program_signatures_per_poll: file
+                .program_signatures_per_poll
+                .unwrap_or(DEFAULT_PROGRAM_SIGNATURES_PER_POLL),
+            log_level: cli
+                .log_level
+                .or(file.log_level)
+                .unwrap_or_else(|| DEFAULT_LOG_LEVEL.to_string()),
+            poll_interval: Duration::from_secs(
+                cli.poll_interval_secs
+                    .or(file.poll_interval_secs)
+                    .unwrap_or(DEFAULT_POLL_INTERVAL_SECS),
+            ),
+            alert_webhook_url: file.alert_webhook_url,
+            // the demo has no RPC endpoint to probe or price from
+            canary: if cli.demo { None } else { file.canary },
+            public: file.public,
+            grpc: file.grpc,
+            follower: file.follower,
+            geyser: file.geyser,
+            tls: file.tls,
+            cors: file.cors,
+            auth: file.auth,
+            memory: file.memory,
+            portfolio: file.portfolio,
+            prices: if cli.demo {
+                PricesConfig::default()
+            } else {
+                file.prices
+            },
+            sinks: file.sinks,
+            cache: file.cache,
+            ids: file.ids,
+            slo: file.slo,
+            fee_burn: file.fee_burn,
+            balances: file.balances,
+            account_watch: file.account_watch,
+            pipeline: file.pipeline,
+            dedup: file.dedup,
+            dlq: file.dlq,
+            alerts: file.alerts,
+            reports: file.reports,
+            gaps: file.gaps,
+            finality: file.finality,
+            rpc_retry: file.rpc_retry,
+            rpc_rate_limit: file.rpc_rate_limit,
+            rpc_proxy: file.rpc_proxy,
+            personal: if cli.demo {
+                PersonalConfig::default()
+            } else {
+                file.personal
+            },
+            stats: file.stats,
+            account_details: file.account_details,
+            raw_archive: file.raw_archive,
+            analysis: file.analysis,
+            epochs: file.epochs,
+            enrichment: file.enrichment,
+            directory: file.directory,
+            timescale: file.timescale,
+            maintenance: file.maintenance,
+            layouts: file.layouts,
+            idls: file.idls,
+            dashboards: file.dashboards,
+            create_api_key: cli.create_api_key,
+            demo: cli.demo,
+            record_rpc: cli.record_rpc,
+            command,
+            deprecations,
+        })
+    }
+
+    /// Primary RPC endpoint.
+    pub fn rpc_url(&self) -> &str {
+        &self.rpc_urls[0]
+    }
+
+    /// Base URL the API server is reachable on from this host.
+    pub fn api_url(&self) -> String {
+        let scheme = if self.tls.is_some() { "https" } else { "http" };
+
+        format!("{scheme}://{}:{}", self.host, self.port)
+    }
+}
+
+/// Whether `origin` is a bare `http` or `https` origin, without a path.
+fn is_origin(origin: &str) -> bool {
+    let Some((scheme, host)) = origin.split_once("://") else {
+        return false;
+    };
+
+    matches!(scheme, "http" | "https") && !host.is_empty() && !host.contains('/')
+}
+
+fn parse_pubkeys(raw: &[String], what: &str) -> anyhow::Result<Vec<Pubkey>> {
+    raw.iter()
+        .map(|pubkey| {
+            Pubkey::from_str(pubkey).map_err(|e| anyhow::anyhow!("Invalid {what} `{pubkey}`: {e}"))
+        })
+        .collect()
+}
+
+impl FileConfig {
+    /// Read a config file, choosing YAML or TOML by its extension.
+    pub fn read(path: &Path) -> anyhow::Result<Self> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| anyhow::anyhow!("Failed to read config file `{}`: {e}", path.display()))?;
+
+        let is_yaml = path
+            .extension()
+            .is_some_and(|ext| ext == "yaml" || ext == "yml");
+
+        let config = if is_yaml {
+            serde_yaml::from_str(&contents)?
+        } else {
+            toml::from_str(&contents)?
+        };
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn empty_cli() -> Cli {
+        Cli {
+            command: None,
+            config: None,
+            rpc_urls: vec![],
+            record_rpc: None,
+            storage: None,
+            database_url: None,
+            host: None,
+            port: None,
+            addresses: vec![],
+            legacy_addresses: vec![],
+            programs: vec![],
+            log_level: None,
+            poll_interval_secs: None,
+            create_api_key: None,
+            demo: false,
+        }
+    }
+
+    #[test]
+    fn test_missing_settings_are_listed() {
+        let err = Config::resolve(empty_cli(), FileConfig::default()).unwrap_err();
+        let message = err.to_string();
+
+        assert!(message.contains("rpc_urls"));
+        assert!(message.contains("database_url"));
+        assert!(message.contains("addresses"));
+    }
+
+    #[test]
+    fn test_cli_overrides_file() {
+        let address = Pubkey::new_unique().to_string();
+
+        let file: FileConfig = toml::from_str(&format!(
+            r#"
+            rpc_urls = ["https://api.devnet.solana.com"]
+            database_url = "postgres://file@localhost/db"
+            addresses = ["{address}"]
+            port = 9000
+            poll_interval_secs = 30
+            "#
+        ))
+        .unwrap();
+
+        let cli = Cli {
+            port: Some(9100),
+            database_url: Some("postgres://cli@localhost/db".to_string()),
+            ..empty_cli()
+        };
+
+        let config = Config::resolve(cli, file).unwrap();
+
+        assert_eq!(config.rpc_url(), "https://api.devnet.solana.com");
+        assert_eq!(config.database_url, "postgres://cli@localhost/db");
+        assert_eq!(config.port, 9100);
+        assert_eq!(config.host, DEFAULT_HOST);
+        assert_eq!(config.poll_interval, Duration::from_secs(30));
+        assert_eq!(config.addresses[0].to_string(), address);
+    }
+
+    #[test]
+    fn test_public_port_must_differ() {
+        let address = Pubkey::new_unique().to_string();
+        let file = || -> FileConfig {
+            toml::from_str(&format!(
+                r#"
+                rpc_urls = ["https://api.devnet.solana.com"]
+                database_url = "postgres://localhost/db"
+                addresses = ["{address}"]
+
+                [public]
+                port = 8080
+                "#
+            ))
+            .unwrap()
+        };
+
+        assert!(Config::resolve(empty_cli(), file()).is_err());
+
+        let cli = Cli {
+            port: Some(9000),
+            ..empty_cli()
+        };
+        let public = Config::resolve(cli, file()).unwrap().public.unwrap();
+
+        assert_eq!((public.host.as_str(), public.port), ("0.0.0.0", 8080));
+    }
+
+    #[test]
+    fn test_admin_routes_cant_be_public() {
+        let address = Pubkey::new_unique().to_string();
+        let file = |routes: &str| -> FileConfig {
+            toml::from_str(&format!(
+                r#"
+                rpc_urls = ["https://api.devnet.solana.com"]
+                database_url = "postgres://localhost/db"
+                addresses = ["{address}"]
+
+                [auth]
+                enabled = true
+                public_routes = [{routes}]
+                "#
+            ))
+            .unwrap()
+        };
+
+        let config =
+            Config::resolve(empty_cli(), file(r#""/stats", "/accounts/{pubkey}/stats""#)).unwrap();
+        assert_eq!(config.auth.public_routes.len(), 2);
+
+        assert!(Config::resolve(empty_cli(), file(r#""/admin/slo""#)).is_err());
+        assert!(Config::resolve(empty_cli(), file(r#""stats""#)).is_err());
+    }
+
+    #[test]
+    fn test_cors_origins_are_validated() {
+        let address = Pubkey::new_unique().to_string();
+        let file = |origins: &str| -> FileConfig {
+            toml::from_str(&format!(
+                r#"
+                rpc_urls = ["https://api.devnet.solana.com"]
+                database_url = "postgres://localhost/db"
+                addresses = ["{address}"]
+
+                [cors]
+                allowed_origins = [{origins}]
+                "#
+            ))
+            .unwrap()
+        };
+
+        let config = Config::resolve(
+            empty_cli(),
+            file(r#""https://dashboard.example.com", "http://localhost:3000""#),
+        )
+        .unwrap();
+        assert_eq!(config.cors.allowed_origins.len(), 2);
+        assert_eq!(config.cors.max_age_secs, 3600);
+
+        assert!(Config::resolve(empty_cli(), file(r#""*""#)).is_ok());
+        assert!(Config::resolve(empty_cli(), file(r#""dashboard.example.com""#)).is_err());
+        assert!(Config::resolve(empty_cli(), file(r#""https://example.com/app""#)).is_err());
+    }
+
+    #[test]
+    fn test_invalid_address_is_rejected() {
+        let cli = Cli {
+            rpc_urls: vec!["https://api.devnet.solana.com".to_string()],
+            database_url: Some("postgres://localhost/db".to_string()),
+            addresses: vec!["InvalidPubkeyString".to_string()],
+            ..empty_cli()
+        };
+
+        assert!(Config::resolve(cli, FileConfig::default()).is_err());
+    }
+
+    #[test]
+    fn test_maintenance_schedule() {
+        let config = MaintenanceConfig {
+            times: ["00:00", "03:30", "23:59"].map(String::from).to_vec(),
+            ..MaintenanceConfig::default()
+        };
+
+        assert_eq!(config.schedule().unwrap(), vec![0, 210, 1439]);
+
+        for time in ["24:00", "3", "03:60", "ab:cd"] {
+            let config = MaintenanceConfig {
+                times: vec![time.to_string()],
+                ..MaintenanceConfig::default()
+            };
+
+            assert!(config.schedule().is_err(), "{time} should be rejected");
+        }
+    }
+
+    #[test]
+    fn test_memory_storage_needs_no_database_url() {
+        let cli = Cli {
+            rpc_urls: vec!["https://api.devnet.solana.com".to_string()],
+            storage: Some(StorageBackend::Memory),
+            addresses: vec![Pubkey::new_unique().to_string()],
+            ..empty_cli()
+        };
+
+        let config = Config::resolve(cli, FileConfig::default()).unwrap();
+
+        assert_eq!(config.storage, StorageBackend::Memory);
+    }
+
+    #[test]
+    fn test_sqlite_url_selects_sqlite_storage() {
+        let cli = Cli {
+            rpc_urls: vec!["https://api.devnet.solana.com".to_string()],
+            database_url: Some("sqlite://aggregator.db".to_string()),
+            addresses: vec![Pubkey::new_unique().to_string()],
+            ..empty_cli()
+        };
+
+        let config = Config::resolve(cli, FileConfig::default()).unwrap();
+
+        assert_eq!(config.storage, StorageBackend::Sqlite);
+    }
+
+    #[test]
+    fn test_address_a_is_a_deprecated_alias() {
+        let address = Pubkey::new_unique().to_string();
+        let cli = Cli {
+            rpc_urls: vec!["https://api.devnet.solana.com".to_string()],
+            storage: Some(StorageBackend::Memory),
+            legacy_addresses: vec![address.clone()],
+            ..empty_cli()
+        };
+
+        let config = Config::resolve(cli, FileConfig::default()).unwrap();
+
+        assert_eq!(config.addresses[0].to_string(), address);
+        assert_eq!(config.deprecations.len(), 1);
+    }
+
+    #[test]
+    fn test_demo_needs_no_settings() {
+        let cli = Cli {
+            database_url: Some("postgres://localhost/db".to_string()),
+            demo: true,
+            ..empty_cli()
+        };
+
+        let config = Config::resolve(cli, FileConfig::default()).unwrap();
+
+        assert_eq!(config.storage, StorageBackend::Memory);
+        assert!(config.addresses.is_empty());
+        assert_eq!(config.host, "127.0.0.1");
+    }
+
+    #[test]
+    fn test_demo_drops_settings_that_need_rpc() {
+        let file: FileConfig = toml::from_str(
+            r#"
+            [prices]
+            provider = "coingecko"
+
+            [canary]
+            keypair = "canary.json"
+            "#,
+        )
+        .unwrap();
+        let cli = Cli {
+            demo: true,
+            ..empty_cli()
+        };
+
+        let config = Config::resolve(cli, file).unwrap();
+
+        assert!(config.canary.is_none());
+        assert!(config.prices.provider.is_none());
+    }
+
+    #[test]
+    fn test_commands_need_only_their_settings() {
+        let fetch = Cli {
+            command: Some(Command::Fetch {
+                signature: "sig".to_string(),
+                raw: false,
+            }),
+            rpc_urls: vec!["https://api.devnet.solana.com".to_string()],
+            ..empty_cli()
+        };
+        assert!(Config::resolve(fetch, FileConfig::default()).is_ok());
+
+        let migrate = Cli {
+            command: Some(Command::Migrate),
+            ..empty_cli()
+        };
+        let message = Config::resolve(migrate, FileConfig::default())
+            .unwrap_err()
+            .to_string();
+        assert!(message.contains("database_url"));
+        assert!(!message.contains("rpc_urls"));
+        assert!(!message.contains("addresses"));
+    }
+}
@@ -0,0 +1,367 @@
+// Serves dashboards defined in the config file
+
+// Responsibilities:
+// * Run a named bundle of statistics and time series queries and return their results as one
+//   payload, so new dashboard views are defined in config rather than in handler code.
+
+// Implementation:
+// * Each dashboard is a set of named panels, each one of the queries behind the stats, volume,
+//   fee burn, leaderboard and balance history endpoints, with the same parameters.
+// * Addresses can be `{name}` placeholders filled in from the request's query string, so one
+//   dashboard serves any account. Everything else is fixed in the config.
+// * Time ranges are relative (`window_secs` back from now), so a dashboard stays current.
+// * Panels run one after another; they read the same counters, rollups and caches as their
+//   endpoints, so each is cheap.
+
+use crate::{
+    analytics::RollupInterval,
+    data_storage::{get_balance_history, get_fee_burn_by_epoch, get_volume_rollups, ALL_ADDRESSES},
+    leaderboards::{Dimension, Leaderboards, Window},
+    stats::StatsCache,
+};
+
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use solana_sdk::pubkey::Pubkey;
+use sqlx::PgPool;
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    str::FromStr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A dashboard as defined in the config file.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Dashboard {
+    pub name: String,
+    /// Keyed by panel name.
+    pub panels: BTreeMap<String, Panel>,
+}
+
+/// The query behind a panel.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "query", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Panel {
+    /// Totals and top counterparties, as served at `/stats`.
+    Stats { address: Option<String> },
+    /// Rolled-up volume, as served at `/analytics/volume`.
+    Volume {
+        #[serde(default)]
+        interval: RollupInterval,
+        address: Option<String>,
+        /// All of history without it.
+        window_secs: Option<i64>,
+    },
+    /// Burned versus validator fees per epoch, as served at `/stats/fee-burn`.
+    FeeBurn {
+        from_epoch: Option<i64>,
+        to_epoch: Option<i64>,
+    },
+    /// Most active senders, receivers or programs, as served at `/leaderboards/{dimension}`.
+    Leaderboard {
+        dimension: Dimension,
+        #[serde(default)]
+        window: Window,
+        #[serde(default = "default_leaderboard_limit")]
+        limit: usize,
+    },
+    /// Recorded balances of an account, as served at `/accounts/{pubkey}/balance/history`.
+    BalanceHistory {
+        address: String,
+        window_secs: Option<i64>,
+        limit: Option<i64>,
+    },
+}
+
+fn default_leaderboard_limit() -> usize {
+    10
+}
+
+impl Panel {
+    /// The panel's address setting, if it has one.
+    fn address(&self) -> Option<&str> {
+        match self {
+            Panel::Stats { address } | Panel::Volume { address, .. } => address.as_deref(),
+            Panel::BalanceHistory { address, .. } => Some(address),
+            Panel::FeeBurn { .. } | Panel::Leaderboard { .. } => None,
+        }
+    }
+
+    async fn run(&self, sources: &DashboardSources<'_>, now: i64) -> anyhow::Result<Value> {
+        let since = |window_secs: &Option<i64>| window_secs.map(|secs| now - secs);
+
+        let value = match self {
+            Panel::Stats { address } => {
+                serde_json::to_value(sources.stats.get(sources.db, address.as_deref()).await?)?
+            }
+            Panel::Volume {
+                interval,
+                address,
+                window_secs,
+            } => serde_json::to_value(
+                get_volume_rollups(
+                    sources.db,
+                    interval.as_str(),
+                    address.as_deref().unwrap_or(ALL_ADDRESSES),
+                    since(window_secs),
+                    None,
+                )
+                .await?,
+            )?,
+            Panel::FeeBurn {
+                from_epoch,
+                to_epoch,
+            } => serde_json::to_value(
+                get_fee_burn_by_epoch(sources.db, *from_epoch, *to_epoch).await?,
+            )?,
+            Panel::Leaderboard {
+                dimension,
+                window,
+                limit,
+            } => serde_json::to_value(sources.leaderboards.top(
+                *dimension,
+                *window,
+                (*limit).clamp(1, 100),
+            ))?,
+            Panel::BalanceHistory {
+                address,
+                window_secs,
+                limit,
+            } => serde_json::to_value(
+                get_balance_history(sources.db, address, since(window_secs), None, *limit).await?,
+            )?,
+        };
+
+        Ok(value)
+    }
+}
+
+/// A `{name}` placeholder's name.
+fn placeholder(value: &str) -> Option<&str> {
+    value.strip_prefix('{')?.strip_suffix('}')
+}
+
+/// Fill in a panel's address from the request parameters.
+fn bind_address(address: &str, params: &HashMap<String, String>) -> Result<String, String> {
+    let address = match placeholder(address) {
+        Some(name) => params
+            .get(name)
+            .ok_or_else(|| format!("Missing parameter `{name}`"))?,
+        None => address,
+    };
+
+    Pubkey::from_str(address)
+        .map(|pubkey| pubkey.to_string())
+        .map_err(|_| format!("Invalid public key `{address}`"))
+}
+
+/// Check the dashboards defined in the config: unique names, and addresses that are either valid
+/// or placeholders.
+pub fn validate(dashboards: &[Dashboard]) -> anyhow::Result<()> {
+    let mut names = HashSet::new();
+
+    for dashboard in dashboards {
+        if !names.insert(&dashboard.name) {
+            anyhow::bail!("Duplicate dashboard `{}`", dashboard.name);
+        }
+
+        for (name, panel) in &dashboard.panels {
+            if let Some(address) = panel.address().filter(|a| placeholder(a).is_none()) {
+                if Pubkey::from_str(address).is_err() {
+                    anyhow::bail!(
+                        "Invalid address `{address}` in panel `{name}` of dashboard `{}`",
+                        dashboard.name
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// A dashboard's results.
+#[derive(Debug, Serialize)]
+pub struct DashboardPayload {
+    pub name: String,
+    /// Unix seconds.
+    pub generated_at: i64,
+    /// Each panel's result, keyed by panel name, shaped like the response of its endpoint.
+    pub panels: BTreeMap<String, Value>,
+}
+
+/// Whatever the panels read from.
+pub struct DashboardSources<'a> {
+    pub db: &'a Arc<PgPool>,
+    pub stats: &'a StatsCache,
+    pub leaderboards: &'a Leaderboards,
+}
+
+#[derive(Debug, Default)]
+pub struct Dashboards {
+    dashboards: HashMap<String, Dashboard>,
+}
+
+impl Dashboards {
+    pub fn new(dashboards: &[Dashboard]) -> Self {
+        Dashboards {
+            dashboards: dashboards
+                .iter()
+                .map(|dashboard| (dashboard.name.clone(), dashboard.clone()))
+                .collect(),
+        }
+    }
+
+    /// The panels of dashboard `name` with their placeholders filled in from `params`, `None`
+    /// if there is no such dashboard, or an error message for invalid parameters.
+    pub fn bind(
+        &self,
+        name: &str,
+        params: &HashMap<String, String>,
+    ) -> Option<Result<Dashboard, String>> {
+        let dashboard = self.dashboards.get(name)?;
+
+        let panels = dashboard
+            .panels
+            .iter()
+            .map(|(panel_name, panel)| {
+                let mut panel = panel.clone();
+
+                match &mut panel {
+                    Panel::Stats {
+                        address: Some(address),
+                    }
+                    | Panel::Volume {
+                        address: Some(address),
+                        ..
+                    }
+                    | Panel::BalanceHistory { address, .. } => {
+                        *address = bind_address(address, params)?;
+                    }
+                    _ => {}
+                }
+
+                Ok((panel_name.clone(), panel))
+            })
+            .collect::<Result<_, String>>();
+
+        Some(panels.map(|panels| Dashboard {
+            name: dashboard.name.clone(),
+            panels,
+        }))
+    }
+}
+
+impl Dashboard {
+    /// Run every panel of a bound dashboard.
+    pub async fn run(&self, sources: &DashboardSources<'_>) -> anyhow::Result<DashboardPayload> {
+        let now = unix_now();
+        let mut panels = BTreeMap::new();
+
+        for (name, panel) in &self.panels {
+            panels.insert(name.clone(), panel.run(sources, now).await?);
+        }
+
+        Ok(DashboardPayload {
+            name: self.name.clone(),
+            generated_at: now,
+            panels,
+        })
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn dashboards() -> Vec<Dashboard> {
+        #[derive(Deserialize)]
+        struct File {
+            dashboards: Vec<Dashboard>,
+        }
+
+        let file: File = toml::from_str(
+            r#"
+            [[dashboards]]
+            name = "account"
+
+            [dashboards.panels.totals]
+            query = "stats"
+            address = "{address}"
+
+            [dashboards.panels.daily_volume]
+            query = "volume"
+            interval = "day"
+            address = "{address}"
+            window_secs = 2592000
+
+            [dashboards.panels.top_senders]
+            query = "leaderboard"
+            dimension = "senders"
+            window = "24h"
+            "#,
+        )
+        .unwrap();
+
+        file.dashboards
+    }
+
+    #[test]
+    fn test_bind_fills_in_placeholders() {
+        let dashboards = dashboards();
+        validate(&dashboards).unwrap();
+        let dashboards = Dashboards::new(&dashboards);
+
+        let address = Pubkey::new_unique().to_string();
+        let params = HashMap::from([("address".to_string(), address.clone())]);
+        let bound = dashboards.bind("account", &params).unwrap().unwrap();
+
+        assert!(matches!(
+            &bound.panels["totals"],
+            Panel::Stats { address: Some(a) } if *a == address
+        ));
+        assert!(matches!(
+            &bound.panels["top_senders"],
+            Panel::Leaderboard { limit: 10, .. }
+        ));
+
+        assert!(dashboards.bind("missing", &params).is_none());
+        assert_eq!(
+            dashboards
+                .bind("account", &HashMap::new())
+                .unwrap()
+                .unwrap_err(),
+            "Missing parameter `address`"
+        );
+
+        let params = HashMap::from([("address".to_string(), "nope".to_string())]);
+        assert!(dashboards.bind("account", &params).unwrap().is_err());
+    }
+
+    #[test]
+    fn test_validate_rejects_duplicates_and_bad_addresses() {
+        let mut duplicated = dashboards();
+        duplicated.extend(dashboards());
+        assert!(validate(&duplicated).is_err());
+
+        let mut invalid = dashboards();
+        invalid[0].panels.insert(
+            "balances".to_string(),
+            Panel::BalanceHistory {
+                address: "not-a-pubkey".to_string(),
+                window_secs: None,
+                limit: None,
+            },
+        );
+        assert!(validate(&invalid).is_err());
+    }
+}
@@ -8,22 +8,490 @@
 // * Use `serde` for JSON deserialization.
 // * Implement functions to parse transaction data and extract information.
 
+use crate::{
+    config::{PipelineConfig, ValidationConfig},
+    metrics::ExcludedCounts,
+};
+
 use log::{error, info, warn};
-use regex::Regex;
 use serde::{Deserialize, Serialize};
+use solana_sdk::{bs58, hash::Hash, pubkey::Pubkey};
 use solana_transaction_status::{
-    EncodedConfirmedTransactionWithStatusMeta, EncodedTransaction, UiMessage, UiTransaction,
+    option_serializer::OptionSerializer, parse_instruction::ParsedInstruction,
+    EncodedConfirmedTransactionWithStatusMeta, EncodedTransaction, UiInstruction, UiMessage,
+    UiParsedInstruction, UiPartiallyDecodedInstruction, UiTransaction, UiTransactionTokenBalance,
+};
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    str::FromStr,
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct TransactionData {
     pub signature: String,
+    /// Source and destination of the transaction's first SOL transfer or, without one, its first
+    /// and last account keys.
     pub sender: String,
     pub receiver: String,
+    /// Lamports the sender's transfers to the receiver moved or, without any, the balance change
+    /// of the second account key; 0 if the transaction failed.
     pub sol_amount: u64,
     pub fee: u64,
     pub timestamp: i64,
     pub prev_blockhash: String,
+    pub slot: u64,
+    /// JSON-encoded `TransactionError`; `None` if the transaction succeeded.
+    pub error: Option<String>,
+    pub log_messages: Vec<String>,
+    /// `None` for transactions processed before the RPC node reported it.
+    pub compute_units_consumed: Option<u64>,
+    /// First watched program the transaction invokes, if any.
+    pub program: Option<String>,
+    /// Text of the transaction's SPL Memo instructions, if it has any.
+    pub memo: Option<String>,
+    /// `None` for transactions processed before compute budgets were parsed.
+    pub compute_budget: Option<ComputeBudget>,
+    /// `None` for transactions processed before they were classified.
+    pub txn_type: Option<TransactionType>,
+    /// Categories from the directory of known addresses (`dex_swap`, `cex_deposit`, ...); empty
+    /// for transactions processed before they were categorized.
+    #[serde(default)]
+    pub categories: Vec<String>,
+    /// SOL/USD price at the transaction's block time; `None` without a price feed or if it couldn't
+    /// be priced.
+    #[serde(default)]
+    pub sol_usd_price: Option<f64>,
+    /// Account that paid the fee, the first account key; `None` for transactions processed before
+    /// it was recorded, whose sender is their fee payer.
+    #[serde(default)]
+    pub fee_payer: Option<String>,
+    /// SOL transfers of the transaction's top-level System program instructions, in order; empty
+    /// for transactions processed before they were recorded.
+    #[serde(default)]
+    pub transfers: Vec<SolTransfer>,
+    /// Whether the transaction executed successfully. Failed ones still paid their fee but moved
+    /// no funds, and `error` holds why they failed.
+    #[serde(default = "default_success")]
+    pub success: bool,
+}
+
+fn default_success() -> bool {
+    true
+}
+
+/// An empty, successful transaction, to build others on with struct update syntax.
+impl Default for TransactionData {
+    fn default() -> Self {
+        TransactionData {
+            signature: String::new(),
+            sender: String::new(),
+            receiver: String::new(),
+            sol_amount: 0,
+            fee: 0,
+            timestamp: 0,
+            prev_blockhash: String::new(),
+            slot: 0,
+            error: None,
+            log_messages: Vec::new(),
+            compute_units_consumed: None,
+            program: None,
+            memo: None,
+            compute_budget: None,
+            txn_type: None,
+            categories: Vec::new(),
+            sol_usd_price: None,
+            fee_payer: None,
+            transfers: Vec::new(),
+            success: default_success(),
+        }
+    }
+}
+
+/// SOL moved by a System program `transfer` or `transferWithSeed` instruction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SolTransfer {
+    pub source: String,
+    pub destination: String,
+    pub lamports: u64,
+}
+
+/// What a transaction does, judged by the programs its top-level instructions call. A
+/// transaction calling several is classified by the first that applies, in declaration order.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TransactionType {
+    /// Votes of a validator.
+    Vote,
+    /// Stake account operations: delegating, deactivating, withdrawing, splitting, ...
+    Stake,
+    /// SPL Token or Token-2022 instructions.
+    Token,
+    /// SOL transfers with the System program.
+    Transfer,
+    Other,
+}
+
+impl TransactionType {
+    pub const ALL: [TransactionType; 5] = [
+        TransactionType::Vote,
+        TransactionType::Stake,
+        TransactionType::Token,
+        TransactionType::Transfer,
+        TransactionType::Other,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TransactionType::Vote => "vote",
+            TransactionType::Stake => "stake",
+            TransactionType::Token => "token",
+            TransactionType::Transfer => "transfer",
+            TransactionType::Other => "other",
+        }
+    }
+
+    /// The type stored as `name`, if it's one.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|txn_type| txn_type.as_str() == name)
+    }
+}
+
+/// What a transaction's compute budget instructions set, and the priority fee that follows.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ComputeBudget {
+    /// Set by `SetComputeUnitLimit`.
+    pub unit_limit: Option<u32>,
+    /// Set by `SetComputeUnitPrice`, in micro-lamports per compute unit.
+    pub unit_price: Option<u64>,
+    /// Lamports paid on top of the base fee: the unit price times the unit limit, or times the
+    /// default limit if none was set.
+    pub priority_fee: u64,
+}
+
+/// Balance of a watched account at a point in time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BalanceSnapshot {
+    pub pubkey: String,
+    pub lamports: u64,
+    /// Set when the account is an SPL token account.
+    pub token_mint: Option<String>,
+    pub token_amount: Option<u64>,
+    pub recorded_at: i64,
+}
+
+/// Current state of an account, read live from the RPC node.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AccountDetails {
+    pub pubkey: String,
+    pub lamports: u64,
+    /// Program owning the account.
+    pub owner: String,
+    pub executable: bool,
+    pub rent_epoch: u64,
+    /// Size of the account's data, in bytes.
+    pub data_len: usize,
+    /// Set when the account is an SPL token account.
+    pub token_mint: Option<String>,
+    pub token_amount: Option<u64>,
+    /// Slot the account was read at.
+    pub slot: u64,
+}
+
+/// SPL Token and Token-2022 program ids, whose accounts start with the same base layout.
+pub const TOKEN_PROGRAM_IDS: [&str; 2] = [
+    "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA",
+    "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb",
+];
+
+/// Extract the mint and raw amount from an SPL token account's data.
+///
+/// Token accounts start with `mint: Pubkey`, `owner: Pubkey`, `amount: u64` (little endian).
+pub fn parse_token_account(owner_program: &str, data: &[u8]) -> Option<(String, u64)> {
+    if !TOKEN_PROGRAM_IDS.contains(&owner_program) || data.len() < 72 {
+        return None;
+    }
+
+    let mint = Pubkey::try_from(&data[0..32]).ok()?;
+    let amount = u64::from_le_bytes(data[64..72].try_into().ok()?);
+
+    Some((mint.to_string(), amount))
+}
+
+/// Balance of an SPL token account.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TokenHolding {
+    /// The token account, not its owner.
+    pub account: String,
+    pub mint: String,
+    /// Raw amount, in the mint's smallest unit.
+    pub amount: u64,
+    pub decimals: u8,
+    pub frozen: bool,
+}
+
+impl TokenHolding {
+    /// Amount in whole tokens.
+    pub fn ui_amount(&self) -> f64 {
+        self.amount as f64 / 10f64.powi(i32::from(self.decimals))
+    }
+}
+
+/// Extract a token account's balance from its `jsonParsed` RPC encoding.
+pub fn parse_token_holding(account: &str, parsed: &serde_json::Value) -> Option<TokenHolding> {
+    let info = parsed.get("info")?;
+    let token_amount = info.get("tokenAmount")?;
+
+    Some(TokenHolding {
+        account: account.to_string(),
+        mint: info.get("mint")?.as_str()?.to_string(),
+        amount: token_amount.get("amount")?.as_str()?.parse().ok()?,
+        decimals: token_amount.get("decimals")?.as_u64()?.try_into().ok()?,
+        frozen: info.get("state").and_then(|state| state.as_str()) == Some("frozen"),
+    })
+}
+
+/// Metaplex Token Metadata program, whose accounts name and describe mints.
+pub const TOKEN_METADATA_PROGRAM_ID: &str = "metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s";
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TokenMetadata {
+    pub name: String,
+    pub symbol: String,
+    /// Off-chain JSON metadata, typically with the image of an NFT.
+    pub uri: Option<String>,
+    /// Collection the token claims to belong to, and whether the collection verified it.
+    pub collection: Option<String>,
+    pub collection_verified: bool,
+}
+
+/// Extract the name, symbol, URI and collection from a Metaplex metadata account's data.
+///
+/// Metadata accounts start with `key: u8`, `update_authority: Pubkey`, `mint: Pubkey`, then the
+/// name, symbol and URI as borsh strings (u32 length, little endian), padded with NULs. The
+/// collection comes after the royalties, creators, sale and edition fields, as a borsh
+/// `Option<(verified: bool, key: Pubkey)>`. Accounts cut short after the symbol still give a name
+/// and symbol.
+pub fn parse_token_metadata(data: &[u8]) -> Option<TokenMetadata> {
+    let mut rest = data.get(65..)?;
+
+    let name = read_borsh_string(&mut rest)?;
+    let symbol = read_borsh_string(&mut rest)?;
+    let uri = read_borsh_string(&mut rest);
+    let collection = uri.as_ref().and_then(|_| read_collection(&mut rest));
+
+    Some(TokenMetadata {
+        name,
+        symbol,
+        uri,
+        collection: collection.map(|(key, _)| key.to_string()),
+        collection_verified: collection.is_some_and(|(_, verified)| verified),
+    })
+}
+
+/// Skip from the end of a metadata account's URI to its collection, and read it.
+fn read_collection(data: &mut &[u8]) -> Option<(Pubkey, bool)> {
+    // seller_fee_basis_points: u16
+    skip(data, 2)?;
+
+    // creators: Option<Vec<(Pubkey, verified: bool, share: u8)>>
+    if read_u8(data)? == 1 {
+        let creators = u32::from_le_bytes(data.get(..4)?.try_into().ok()?) as usize;
+        skip(data, 4 + creators * 34)?;
+    }
+
+    // primary_sale_happened, is_mutable: bool
+    skip(data, 2)?;
+
+    // edition_nonce, token_standard: Option<u8>
+    for _ in 0..2 {
+        if read_u8(data)? == 1 {
+            skip(data, 1)?;
+        }
+    }
+
+    if read_u8(data)? != 1 {
+        return None;
+    }
+
+    let verified = read_u8(data)? == 1;
+    let key = Pubkey::try_from(data.get(..32)?).ok()?;
+
+    Some((key, verified))
+}
+
+fn read_u8(data: &mut &[u8]) -> Option<u8> {
+    let (byte, rest) = data.split_first()?;
+    *data = rest;
+
+    Some(*byte)
+}
+
+fn skip(data: &mut &[u8], len: usize) -> Option<()> {
+    *data = data.get(len..)?;
+
+    Some(())
+}
+
+/// Extract the supply and decimals from an SPL token mint's data.
+///
+/// Mints start with `mint_authority: COption<Pubkey>` (u32 tag and the key), then `supply: u64`
+/// (little endian) and `decimals: u8`.
+pub fn parse_mint(data: &[u8]) -> Option<(u64, u8)> {
+    let supply = u64::from_le_bytes(data.get(36..44)?.try_into().ok()?);
+    let decimals = *data.get(44)?;
+
+    Some((supply, decimals))
+}
+
+/// Change in one token account's balance over a transaction.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TokenTransfer {
+    /// Index of the token account among the transaction's account keys.
+    pub account_index: u8,
+    pub mint: String,
+    /// Owner of the token account, if the RPC node reported it.
+    pub owner: Option<String>,
+    /// Raw amount, in the mint's smallest unit; negative when tokens left the account.
+    pub change: i128,
+    pub decimals: u8,
+    /// USD price of the mint at the transaction's block time, once priced.
+    pub usd_price: Option<f64>,
+}
+
+/// The token balance changes of a transaction, from its pre and post token balances, by account
+/// index. Accounts opened or closed by the transaction count as having had no tokens. Failed
+/// transactions moved no tokens, so they have none.
+pub fn parse_token_transfers(
+    txn: &EncodedConfirmedTransactionWithStatusMeta,
+) -> Vec<TokenTransfer> {
+    let Some(meta) = txn
+        .transaction
+        .meta
+        .as_ref()
+        .filter(|meta| meta.err.is_none())
+    else {
+        return Vec::new();
+    };
+
+    let balances = |balances: &OptionSerializer<Vec<UiTransactionTokenBalance>>| {
+        Option::<&Vec<UiTransactionTokenBalance>>::from(balances.as_ref())
+            .map_or(&[][..], Vec::as_slice)
+    };
+
+    token_balance_changes(
+        balances(&meta.pre_token_balances),
+        balances(&meta.post_token_balances),
+    )
+}
+
+fn token_balance_changes(
+    pre: &[UiTransactionTokenBalance],
+    post: &[UiTransactionTokenBalance],
+) -> Vec<TokenTransfer> {
+    fn by_index(
+        balances: &[UiTransactionTokenBalance],
+    ) -> BTreeMap<u8, (&UiTransactionTokenBalance, i128)> {
+        balances
+            .iter()
+            .filter_map(|balance| {
+                let amount = balance.ui_token_amount.amount.parse::<i128>().ok()?;
+                Some((balance.account_index, (balance, amount)))
+            })
+            .collect()
+    }
+
+    let pre = by_index(pre);
+    let post = by_index(post);
+
+    pre.keys()
+        .chain(post.keys())
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .filter_map(|index| {
+            let before = pre.get(index);
+            let after = post.get(index);
+            let (balance, _) = after.or(before)?;
+            let change =
+                after.map_or(0, |(_, amount)| *amount) - before.map_or(0, |(_, amount)| *amount);
+
+            (change != 0).then(|| TokenTransfer {
+                account_index: *index,
+                mint: balance.mint.clone(),
+                owner: Option::<&String>::from(balance.owner.as_ref()).cloned(),
+                change,
+                decimals: balance.ui_token_amount.decimals,
+                usd_price: None,
+            })
+        })
+        .collect()
+}
+
+fn read_borsh_string(data: &mut &[u8]) -> Option<String> {
+    let len = u32::from_le_bytes(data.get(..4)?.try_into().ok()?) as usize;
+    let bytes = data.get(4..4 + len)?;
+    *data = &data[4 + len..];
+
+    Some(
+        String::from_utf8_lossy(bytes)
+            .trim_end_matches('\0')
+            .to_string(),
+    )
+}
+
+/// Raw contents of a watched data account at a point in time.
+#[derive(Debug, Clone)]
+pub struct AccountSnapshot {
+    pub pubkey: String,
+    pub owner: String,
+    pub lamports: u64,
+    pub data: Vec<u8>,
+    pub slot: u64,
+    pub recorded_at: i64,
+}
+
+/// A contiguous run of bytes that differs between two versions of an account's data.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct ByteChange {
+    pub offset: usize,
+    /// Hex-encoded bytes before the change; empty if the data grew.
+    pub old: String,
+    /// Hex-encoded bytes after the change; empty if the data shrank.
+    pub new: String,
+}
+
+/// Compute the changed byte ranges between two versions of account data.
+pub fn diff_account_data(old: &[u8], new: &[u8]) -> Vec<ByteChange> {
+    let mut changes = Vec::new();
+    let len = old.len().max(new.len());
+    let mut offset = 0;
+
+    while offset < len {
+        if old.get(offset) == new.get(offset) {
+            offset += 1;
+            continue;
+        }
+
+        let start = offset;
+        while offset < len && old.get(offset) != new.get(offset) {
+            offset += 1;
+        }
+
+        let slice = |data: &[u8]| {
+            hex::encode(&data[start.min(data.len())..offset.min(data.len())])
+        };
+
+        changes.push(ByteChange {
+            offset: start,
+            old: slice(old),
+            new: slice(new),
+        });
+    }
+
+    changes
 }
 
 /// Function to parse transaction data and extract relevant fields.
@@ -48,17 +516,8 @@ pub fn parse_transaction(
         }
     };
 
-    let sender = message
-        .account_keys
-        .first()
-        .cloned()
-        .map_or("".to_string(), |acc| acc.pubkey);
-
-    let receiver = message
-        .account_keys
-        .last()
-        .cloned()
-        .map_or("".to_string(), |acc| acc.pubkey);
+    // the fee payer signs first
+    let fee_payer = message.account_keys.first().map(|acc| acc.pubkey.clone());
 
     let meta = if let Some(meta) = txn.transaction.meta.as_ref() {
         meta
@@ -67,8 +526,39 @@ pub fn parse_transaction(
         return None;
     };
 
-    // calculate SOL amount transferred (simplified example)
-    let sol_amount = meta.post_balances[1] - meta.pre_balances[1];
+    // a failed transaction's instructions were rolled back, leaving only its fee paid
+    let success = meta.err.is_none();
+
+    // who moved funds, going by the first SOL transfer
+    let transfers = parse_sol_transfers(&message.instructions);
+
+    let (sender, receiver, sol_amount) = match transfers.first() {
+        Some(first) if !success => (first.source.clone(), first.destination.clone(), 0),
+        Some(first) => (
+            first.source.clone(),
+            first.destination.clone(),
+            transfers
+                .iter()
+                .filter(|transfer| {
+                    transfer.source == first.source && transfer.destination == first.destination
+                })
+                .map(|transfer| transfer.lamports)
+                .sum(),
+        ),
+        // without one, fall back to the first and last account keys (simplified example)
+        None => (
+            fee_payer.clone().unwrap_or_default(),
+            message
+                .account_keys
+                .last()
+                .map_or("".to_string(), |acc| acc.pubkey.clone()),
+            // what the second account gained, if anything
+            match (meta.pre_balances.get(1), meta.post_balances.get(1)) {
+                (Some(pre), Some(post)) if success => post.saturating_sub(*pre),
+                _ => 0,
+            },
+        ),
+    };
 
     // get transaction fee
     let fee = meta.fee;
@@ -79,6 +569,25 @@ pub fn parse_transaction(
     // get previous block hash
     let prev_blockhash = message.recent_blockhash.clone();
 
+    // get attached memo and compute budget
+    let memo = parse_memo(&message.instructions);
+    let compute_budget = parse_compute_budget(&message.instructions);
+    let txn_type = classify(&message.instructions);
+
+    // get execution status and cost
+    let error = meta
+        .err
+        .as_ref()
+        .and_then(|err| match serde_json::to_string(err) {
+            Ok(error) => Some(error),
+            Err(e) => {
+                error!("Failed to serialize transaction error {err:?}: {e:?}");
+                None
+            }
+        });
+    let log_messages: Vec<String> = Option::from(meta.log_messages.clone()).unwrap_or_default();
+    let compute_units_consumed: Option<u64> = Option::from(meta.compute_units_consumed.clone());
+
     // build `TransactionData` struct
     let transaction_data = TransactionData {
         signature: signatures
@@ -90,6 +599,19 @@ pub fn parse_transaction(
         fee,
         timestamp,
         prev_blockhash,
+        slot: txn.slot,
+        error,
+        log_messages,
+        compute_units_consumed,
+        program: None,
+        memo,
+        compute_budget: Some(compute_budget),
+        txn_type: Some(txn_type),
+        categories: Vec::new(),
+        sol_usd_price: None,
+        fee_payer,
+        transfers,
+        success,
     };
 
     info!("Parsed transaction: {:?}", transaction_data);
@@ -97,110 +619,430 @@ pub fn parse_transaction(
     Some(transaction_data)
 }
 
+/// Name the RPC node's instruction parser gives every version of the SPL Memo program.
+const MEMO_PROGRAM_NAME: &str = "spl-memo";
+
+/// Text of the SPL Memo instructions of a `jsonParsed` message, joined by newlines if there are
+/// several.
+///
+/// The RPC node only parses memos that are valid UTF-8, so any others are left out.
+fn parse_memo(instructions: &[UiInstruction]) -> Option<String> {
+    let memos = instructions
+        .iter()
+        .filter_map(|instruction| match instruction {
+            UiInstruction::Parsed(UiParsedInstruction::Parsed(ParsedInstruction {
+                program,
+                parsed,
+                ..
+            })) if program == MEMO_PROGRAM_NAME => parsed.as_str(),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+
+    (!memos.is_empty()).then(|| memos.join("\n"))
+}
+
+/// Compute Budget program, whose instructions set a transaction's compute unit limit and price.
+pub const COMPUTE_BUDGET_PROGRAM_ID: &str = "ComputeBudget111111111111111111111111111111";
+
+/// Compute units a transaction may use per instruction when it sets no limit, and at most overall.
+const DEFAULT_COMPUTE_UNITS_PER_INSTRUCTION: u64 = 200_000;
+const MAX_COMPUTE_UNIT_LIMIT: u64 = 1_400_000;
+
+const MICRO_LAMPORTS_PER_LAMPORT: u128 = 1_000_000;
+
+/// Read the compute budget instructions of a `jsonParsed` message.
+///
+/// The RPC node doesn't parse Compute Budget instructions, so their data is decoded here: a
+/// one-byte tag, then `SetComputeUnitLimit` takes a `u32` and `SetComputeUnitPrice` a `u64`, both
+/// little endian. Without a limit, every other instruction may use the default units, up to the
+/// maximum, as the runtime charges.
+fn parse_compute_budget(instructions: &[UiInstruction]) -> ComputeBudget {
+    let mut budget = ComputeBudget::default();
+    let mut other_instructions = 0;
+
+    for instruction in instructions {
+        let data = match instruction {
+            UiInstruction::Parsed(UiParsedInstruction::PartiallyDecoded(
+                UiPartiallyDecodedInstruction {
+                    program_id, data, ..
+                },
+            )) if program_id == COMPUTE_BUDGET_PROGRAM_ID => {
+                bs58::decode(data).into_vec().unwrap_or_default()
+            }
+            _ => {
+                other_instructions += 1;
+                continue;
+            }
+        };
+
+        match data.split_first() {
+            Some((2, limit)) => {
+                budget.unit_limit = limit
+                    .get(..4)
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .map(u32::from_le_bytes);
+            }
+            Some((3, price)) => {
+                budget.unit_price = price
+                    .get(..8)
+                    .and_then(|bytes| bytes.try_into().ok())
+                    .map(u64::from_le_bytes);
+            }
+            _ => {}
+        }
+    }
+
+    let unit_limit = budget.unit_limit.map_or(
+        (other_instructions * DEFAULT_COMPUTE_UNITS_PER_INSTRUCTION).min(MAX_COMPUTE_UNIT_LIMIT),
+        u64::from,
+    );
+    let micro_lamports = u128::from(budget.unit_price.unwrap_or(0)) * u128::from(unit_limit);
+
+    budget.priority_fee = micro_lamports
+        .div_ceil(MICRO_LAMPORTS_PER_LAMPORT)
+        .try_into()
+        .unwrap_or(u64::MAX);
+
+    budget
+}
+
+const STAKE_PROGRAM_ID: &str = "Stake11111111111111111111111111111111111111";
+const SYSTEM_PROGRAM_ID: &str = "11111111111111111111111111111111";
+
+/// Classify a transaction by the programs of its top-level instructions, and for the System
+/// program, by whether it transfers SOL rather than, say, only creating an account.
+fn classify(instructions: &[UiInstruction]) -> TransactionType {
+    instructions
+        .iter()
+        .filter_map(|instruction| {
+            let (program_id, parsed) = match instruction {
+                UiInstruction::Parsed(UiParsedInstruction::Parsed(instruction)) => {
+                    (instruction.program_id.as_str(), Some(&instruction.parsed))
+                }
+                UiInstruction::Parsed(UiParsedInstruction::PartiallyDecoded(instruction)) => {
+                    (instruction.program_id.as_str(), None)
+                }
+                UiInstruction::Compiled(_) => return None,
+            };
+
+            match program_id {
+                VOTE_PROGRAM_ID => Some(TransactionType::Vote),
+                STAKE_PROGRAM_ID => Some(TransactionType::Stake),
+                id if TOKEN_PROGRAM_IDS.contains(&id) => Some(TransactionType::Token),
+                SYSTEM_PROGRAM_ID => parsed
+                    .and_then(|parsed| parsed.get("type")?.as_str())
+                    .filter(|kind| matches!(*kind, "transfer" | "transferWithSeed"))
+                    .map(|_| TransactionType::Transfer),
+                _ => None,
+            }
+        })
+        .min()
+        .unwrap_or(TransactionType::Other)
+}
+
+/// SOL transfers of the top-level System program instructions of a `jsonParsed` message.
+fn parse_sol_transfers(instructions: &[UiInstruction]) -> Vec<SolTransfer> {
+    instructions
+        .iter()
+        .filter_map(|instruction| {
+            let UiInstruction::Parsed(UiParsedInstruction::Parsed(ParsedInstruction {
+                program_id,
+                parsed,
+                ..
+            })) = instruction
+            else {
+                return None;
+            };
+
+            let kind = parsed.get("type")?.as_str()?;
+
+            if program_id != SYSTEM_PROGRAM_ID || !matches!(kind, "transfer" | "transferWithSeed") {
+                return None;
+            }
+
+            let info = parsed.get("info")?;
+
+            Some(SolTransfer {
+                source: info.get("source")?.as_str()?.to_string(),
+                destination: info.get("destination")?.as_str()?.to_string(),
+                lamports: info.get("lamports")?.as_u64()?,
+            })
+        })
+        .collect()
+}
+
 /// Function to process a list of transactions.
 pub fn process_transactions(
     transactions: Vec<EncodedConfirmedTransactionWithStatusMeta>,
 ) -> Vec<TransactionData> {
-    info!("Processing transactions…");
+    process_transactions_with_canary(transactions, None, &PipelineConfig::default()).0
+}
 
-    transactions
-        .into_iter()
-        .filter_map(parse_transaction)
-        .filter(|txn| is_valid_transaction(txn))
-        .collect::<Vec<_>>()
+/// A validation rule a transaction broke.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum ValidationError {
+    EmptySignature,
+    InvalidSender {
+        pubkey: String,
+    },
+    InvalidReceiver {
+        pubkey: String,
+    },
+    /// Sender and receiver are the same address.
+    SelfTransfer,
+    ZeroFee,
+    NegativeTimestamp {
+        timestamp: i64,
+    },
+    InvalidBlockhash {
+        blockhash: String,
+    },
 }
 
-pub fn is_valid_transaction(txn: &TransactionData) -> bool {
-    is_valid_signature(&txn.signature)
-        && is_valid_pubkey(&txn.sender)
-        && is_valid_pubkey(&txn.receiver)
-        && is_valid_sender_receiver(&txn.sender, &txn.receiver)
-        && is_valid_amount(txn.sol_amount)
-        && is_valid_fee(txn.fee)
-        && is_valid_timestamp(txn.timestamp)
-        && is_valid_blockhash(&txn.prev_blockhash)
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::EmptySignature => write!(f, "signature is empty"),
+            ValidationError::InvalidSender { pubkey } => {
+                write!(f, "invalid sender public key `{pubkey}`")
+            }
+            ValidationError::InvalidReceiver { pubkey } => {
+                write!(f, "invalid receiver public key `{pubkey}`")
+            }
+            ValidationError::SelfTransfer => write!(f, "receiver cannot be sender"),
+            ValidationError::ZeroFee => write!(f, "fee must be positive"),
+            ValidationError::NegativeTimestamp { timestamp } => {
+                write!(f, "block timestamp {timestamp} is negative")
+            }
+            ValidationError::InvalidBlockhash { blockhash } => {
+                write!(f, "invalid blockhash `{blockhash}`")
+            }
+        }
+    }
 }
 
-fn is_valid_signature(signature: &str) -> bool {
-    if !signature.to_string().is_empty() {
-        true
+/// A transaction that failed validation, with every rule it broke.
+#[derive(Debug, Clone, Serialize)]
+pub struct Rejection {
+    pub transaction: TransactionData,
+    pub reasons: Vec<ValidationError>,
+}
+
+/// Vote program, whose transactions validators send every slot.
+pub const VOTE_PROGRAM_ID: &str = "Vote111111111111111111111111111111111111111";
+
+/// Why a transaction was left out of ingestion.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exclusion {
+    /// Unparsable, or failed validation.
+    Invalid,
+    Failed,
+    ZeroAmount,
+    Vote,
+}
+
+/// Function to process a list of transactions, keeping those sent by the canary probe and
+/// counting the ones left out. Also returns the transactions that failed validation, with why.
+///
+/// Canary probes are zero-value self-transfers, so they would never pass validation.
+pub fn process_transactions_with_canary(
+    transactions: Vec<EncodedConfirmedTransactionWithStatusMeta>,
+    canary: Option<&str>,
+    pipeline: &PipelineConfig,
+) -> (Vec<TransactionData>, ExcludedCounts, Vec<Rejection>) {
+    info!("Processing transactions…");
+
+    let mut excluded = ExcludedCounts::default();
+    let mut processed = Vec::new();
+    let mut rejected = Vec::new();
+
+    for txn in transactions {
+        let Some(txn) = parse_transaction(txn) else {
+            excluded.add(Exclusion::Invalid);
+            continue;
+        };
+
+        if canary.is_some_and(|c| txn.sender == c) {
+            processed.push(txn);
+            continue;
+        }
+
+        if let Err(reasons) = validate(&txn, &pipeline.validation) {
+            warn!(
+                "Skipping invalid transaction `{}`: {}",
+                txn.signature,
+                reasons
+                    .iter()
+                    .map(ValidationError::to_string)
+                    .collect::<Vec<_>>()
+                    .join("; ")
+            );
+
+            excluded.add(Exclusion::Invalid);
+            rejected.push(Rejection {
+                transaction: txn,
+                reasons,
+            });
+            continue;
+        }
+
+        match exclusion(&txn, pipeline) {
+            Some(reason) => excluded.add(reason),
+            None => processed.push(txn),
+        }
+    }
+
+    (processed, excluded, rejected)
+}
+
+/// Why a valid `txn` shouldn't be ingested under `pipeline`, if it shouldn't.
+pub fn exclusion(txn: &TransactionData, pipeline: &PipelineConfig) -> Option<Exclusion> {
+    if !pipeline.include_failed && !txn.success {
+        Some(Exclusion::Failed)
+    } else if !pipeline.include_votes && is_vote(txn) {
+        Some(Exclusion::Vote)
+    } else if !pipeline.include_zero_amount && txn.success && !is_valid_amount(txn.sol_amount) {
+        Some(Exclusion::ZeroAmount)
     } else {
-        warn!("Transaction signature is empty. Skipping transaction…");
-        false
+        None
     }
 }
 
-fn is_valid_pubkey(pubkey: &str) -> bool {
-    let re = Regex::new(r"^[1-9A-HJ-NP-Za-km-z]{32,44}$").expect("Invalid regex");
+/// Whether a transaction invokes the vote program, going by its log messages.
+pub fn is_vote(txn: &TransactionData) -> bool {
+    txn.log_messages.iter().any(|line| {
+        line.strip_prefix("Program ")
+            .is_some_and(|rest| rest.starts_with(VOTE_PROGRAM_ID))
+    })
+}
 
-    if pubkey.len() == 44 && re.is_match(pubkey) {
-        true
-    } else {
-        warn!("Invalid public key: `{pubkey}`. Skipping transaction…");
-        false
+/// Programs a transaction's log messages show being invoked, at any depth, each once and in the
+/// order they're first invoked.
+pub fn invoked_programs(log_messages: &[String]) -> Vec<&str> {
+    let mut programs = Vec::new();
+
+    for line in log_messages {
+        let mut words = line.split_whitespace();
+
+        if let (Some("Program"), Some(program), Some("invoke")) =
+            (words.next(), words.next(), words.next())
+        {
+            if !programs.contains(&program) {
+                programs.push(program);
+            }
+        }
     }
+
+    programs
 }
 
-fn is_valid_sender_receiver(sender: &str, receiver: &str) -> bool {
-    if sender != receiver {
-        true
+/// Whether a transaction passes every validation rule.
+pub fn is_valid_transaction(txn: &TransactionData) -> bool {
+    validate(txn, &ValidationConfig::default()).is_ok()
+}
+
+/// Check a transaction against the validation rules enabled in `rules`, returning every rule it
+/// broke. Whether a valid transaction is ingested also depends on the pipeline settings; see
+/// `exclusion`.
+pub fn validate(
+    txn: &TransactionData,
+    rules: &ValidationConfig,
+) -> Result<(), Vec<ValidationError>> {
+    let mut errors = Vec::new();
+
+    if rules.signature && !is_valid_signature(&txn.signature) {
+        errors.push(ValidationError::EmptySignature);
+    }
+
+    if rules.pubkeys && !is_valid_pubkey(&txn.sender) {
+        errors.push(ValidationError::InvalidSender {
+            pubkey: txn.sender.clone(),
+        });
+    }
+
+    if rules.pubkeys && !is_valid_pubkey(&txn.receiver) {
+        errors.push(ValidationError::InvalidReceiver {
+            pubkey: txn.receiver.clone(),
+        });
+    }
+
+    if rules.distinct_parties && !is_valid_sender_receiver(&txn.sender, &txn.receiver) {
+        errors.push(ValidationError::SelfTransfer);
+    }
+
+    if rules.nonzero_fee && !is_valid_fee(txn.fee) {
+        errors.push(ValidationError::ZeroFee);
+    }
+
+    if rules.timestamp && !is_valid_timestamp(txn.timestamp) {
+        errors.push(ValidationError::NegativeTimestamp {
+            timestamp: txn.timestamp,
+        });
+    }
+
+    if rules.blockhash && !is_valid_blockhash(&txn.prev_blockhash) {
+        errors.push(ValidationError::InvalidBlockhash {
+            blockhash: txn.prev_blockhash.clone(),
+        });
+    }
+
+    if errors.is_empty() {
+        Ok(())
     } else {
-        warn!("Receiver cannot be sender. Skipping transaction…");
-        false
+        Err(errors)
     }
 }
 
+fn is_valid_signature(signature: &str) -> bool {
+    !signature.is_empty()
+}
+
+/// Any base58 encoding of 32 bytes, which is 32 to 44 characters long.
+fn is_valid_pubkey(pubkey: &str) -> bool {
+    Pubkey::from_str(pubkey).is_ok()
+}
+
+fn is_valid_sender_receiver(sender: &str, receiver: &str) -> bool {
+    sender != receiver
+}
+
 fn is_valid_amount(amount: u64) -> bool {
-    if amount > 0 {
-        true
-    } else {
-        warn!("Transfer amount must be positive. Skipping transaction…");
-        false
-    }
+    amount > 0
 }
 
 fn is_valid_fee(fee: u64) -> bool {
-    if fee > 0 {
-        true
-    } else {
-        warn!("Transfer fee must be positive. Skipping transaction…");
-        false
-    }
+    fee > 0
 }
 
 fn is_valid_timestamp(timestamp: i64) -> bool {
-    if timestamp >= 0 {
-        true
-    } else {
-        warn!("Block timestamp cannot be negative. Skipping transaction…");
-        false
-    }
+    timestamp >= 0
 }
 
 fn is_valid_blockhash(hash: &str) -> bool {
-    let re = Regex::new(r"^[1-9A-HJ-NP-Za-km-z]{32,44}$").expect("Invalid regex");
-
-    if hash.len() == 44 && re.is_match(hash) {
-        true
-    } else {
-        warn!("Invalid blockhash: `{hash}`. Skipping transaction…");
-        false
-    }
+    Hash::from_str(hash).is_ok()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use solana_sdk::{message::MessageHeader, pubkey::Pubkey, signature::Signature};
+    use solana_account_decoder::parse_token::UiTokenAmount;
+    use solana_sdk::{
+        message::MessageHeader, pubkey::Pubkey, signature::Signature, stake, system_program,
+        transaction::TransactionError, vote,
+    };
     use solana_transaction_status::{
         option_serializer::OptionSerializer, parse_accounts::ParsedAccount,
         EncodedConfirmedTransactionWithStatusMeta, EncodedTransaction,
-        EncodedTransactionWithStatusMeta, UiMessage, UiParsedMessage, UiRawMessage, UiTransaction,
-        UiTransactionStatusMeta,
+        EncodedTransactionWithStatusMeta, UiMessage, UiParsedMessage,
+        UiPartiallyDecodedInstruction, UiRawMessage, UiTransaction, UiTransactionStatusMeta,
     };
 
-    use std::env;
+    /// The sender and receiver of the transfer in `fixtures/rpc/transfer.json`.
+    const ADDRESS_A: &str = "DLpcCvJooMbrbJosuGYeUjYGxGZTgRfgWkhPehTgngiB";
+    const ADDRESS_B: &str = "BFfZpV9Kh8MdAKCNcMwuqAUiEYkatcojP4PJ3CQuTJ12";
 
     #[test]
     fn test_valid_signature() {
@@ -213,9 +1055,7 @@ mod tests {
 
     #[test]
     fn test_valid_pubkey() {
-        let _ = dotenvy::dotenv();
-
-        let valid_pubkey = env::var("ADDRESS_A").expect("`ADDRESS_A` must be set");
+        let valid_pubkey = ADDRESS_A.to_string();
         assert!(is_valid_pubkey(&valid_pubkey.to_string()));
 
         let invalid_pubkey = "InvalidPubkeyString";
@@ -260,10 +1100,8 @@ mod tests {
 
     #[test]
     fn test_valid_sender_receiver() {
-        let _ = dotenvy::dotenv();
-
-        let sender = env::var("ADDRESS_A").expect("`ADDRESS_A` must be set");
-        let receiver = env::var("ADDRESS_B").expect("`ADDRESS_B` must be set");
+        let sender = ADDRESS_A.to_string();
+        let receiver = ADDRESS_B.to_string();
 
         assert!(is_valid_sender_receiver(&sender, &receiver));
 
@@ -274,10 +1112,8 @@ mod tests {
 
     #[test]
     fn test_valid_transaction() {
-        let _ = dotenvy::dotenv();
-
-        let sender = env::var("ADDRESS_A").expect("`ADDRESS_A` must be set");
-        let receiver = env::var("ADDRESS_B").expect("`ADDRESS_B` must be set");
+        let sender = ADDRESS_A.to_string();
+        let receiver = ADDRESS_B.to_string();
 
         let valid_transaction = TransactionData {
         signature: "5NzT3RMAGiJjxGqAXgy6xakdcTfV7oF2dt2m5x8y7vc48pmQ9JVDd8LfPtkMRNZkNmJmhYoP2cFHGip7vRtXVcdv".to_string(),
@@ -287,6 +1123,9 @@ mod tests {
         fee: 500,
         timestamp: 1625077743,
         prev_blockhash: "4sZ76MsNd8y3WSw2L1nfd3AqLoYxdmC98sERoMRbHV14".to_string(),
+        slot: 42,
+        compute_units_consumed: Some(150),
+        ..TransactionData::default()
     };
         assert!(is_valid_transaction(&valid_transaction));
 
@@ -298,11 +1137,423 @@ mod tests {
             fee: 0,
             timestamp: -1625077743,
             prev_blockhash: "InvalidHashString".to_string(),
+            ..TransactionData::default()
         };
 
         assert!(!is_valid_transaction(&invalid_transaction));
     }
 
+    #[test]
+    fn test_validate_collects_enabled_rules() {
+        let txn = TransactionData {
+            signature: String::new(),
+            sender: Pubkey::new_unique().to_string(),
+            receiver: "InvalidPubkeyString".to_string(),
+            sol_amount: 1000,
+            fee: 0,
+            timestamp: 1625077743,
+            prev_blockhash: "4sZ76MsNd8y3WSw2L1nfd3AqLoYxdmC98sERoMRbHV14".to_string(),
+            slot: 42,
+            ..TransactionData::default()
+        };
+
+        assert_eq!(
+            validate(&txn, &ValidationConfig::default()),
+            Err(vec![
+                ValidationError::EmptySignature,
+                ValidationError::InvalidReceiver {
+                    pubkey: "InvalidPubkeyString".to_string()
+                },
+                ValidationError::ZeroFee,
+            ])
+        );
+
+        let lenient = ValidationConfig {
+            signature: false,
+            pubkeys: false,
+            nonzero_fee: false,
+            ..ValidationConfig::default()
+        };
+        assert_eq!(validate(&txn, &lenient), Ok(()));
+
+        // shorter than 44 characters, like many valid keys
+        let short_key = Pubkey::new_from_array([0; 32]).to_string();
+        assert!(short_key.len() < 44);
+        assert!(is_valid_pubkey(&short_key));
+    }
+
+    #[test]
+    fn test_exclusion() {
+        let txn = TransactionData {
+            signature: Signature::new_unique().to_string(),
+            sender: ADDRESS_A.to_string(),
+            receiver: ADDRESS_B.to_string(),
+            sol_amount: 0,
+            fee: 5000,
+            timestamp: 1625077743,
+            prev_blockhash: "4sZ76MsNd8y3WSw2L1nfd3AqLoYxdmC98sERoMRbHV14".to_string(),
+            slot: 42,
+            ..TransactionData::default()
+        };
+        let everything = PipelineConfig {
+            include_failed: true,
+            include_zero_amount: true,
+            include_votes: true,
+            ..PipelineConfig::default()
+        };
+
+        assert_eq!(
+            exclusion(&txn, &PipelineConfig::default()),
+            Some(Exclusion::ZeroAmount)
+        );
+        assert_eq!(exclusion(&txn, &everything), None);
+
+        let failed_vote = TransactionData {
+            error: Some(r#"{"InstructionError":[0,"Custom"]}"#.to_string()),
+            log_messages: vec![format!("Program {VOTE_PROGRAM_ID} invoke [1]")],
+            success: false,
+            ..txn.clone()
+        };
+        let successful_only = PipelineConfig {
+            include_failed: false,
+            ..everything.clone()
+        };
+
+        assert_eq!(
+            exclusion(&failed_vote, &successful_only),
+            Some(Exclusion::Failed)
+        );
+        assert_eq!(
+            exclusion(&failed_vote, &PipelineConfig::default()),
+            Some(Exclusion::Vote)
+        );
+        assert_eq!(exclusion(&failed_vote, &everything), None);
+
+        // failed transactions move nothing, and are governed by `include_failed` alone
+        let failed = TransactionData {
+            log_messages: vec![],
+            ..failed_vote
+        };
+        assert_eq!(exclusion(&failed, &PipelineConfig::default()), None);
+    }
+
+    #[test]
+    fn test_parse_token_account() {
+        let mint = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+
+        let mut data = Vec::new();
+        data.extend_from_slice(mint.as_ref());
+        data.extend_from_slice(owner.as_ref());
+        data.extend_from_slice(&42_000u64.to_le_bytes());
+        data.resize(165, 0);
+
+        assert_eq!(
+            parse_token_account(TOKEN_PROGRAM_IDS[0], &data),
+            Some((mint.to_string(), 42_000))
+        );
+
+        let system_program = "11111111111111111111111111111111";
+        assert_eq!(parse_token_account(system_program, &data), None);
+        assert_eq!(parse_token_account(TOKEN_PROGRAM_IDS[0], &data[..40]), None);
+    }
+
+    #[test]
+    fn test_parse_token_holding() {
+        let parsed = serde_json::json!({
+            "info": {
+                "isNative": false,
+                "mint": "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v",
+                "owner": "9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM",
+                "state": "initialized",
+                "tokenAmount": {
+                    "amount": "2500000",
+                    "decimals": 6,
+                    "uiAmount": 2.5,
+                    "uiAmountString": "2.5"
+                }
+            },
+            "type": "account"
+        });
+
+        let holding = parse_token_holding("account", &parsed).unwrap();
+        assert_eq!(holding.mint, "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v");
+        assert_eq!((holding.amount, holding.decimals), (2_500_000, 6));
+        assert_eq!(holding.ui_amount(), 2.5);
+        assert!(!holding.frozen);
+
+        assert_eq!(parse_token_holding("account", &serde_json::json!({})), None);
+    }
+
+    #[test]
+    fn test_parse_token_metadata() {
+        let mut data = vec![4];
+        data.extend_from_slice(Pubkey::new_unique().as_ref());
+        data.extend_from_slice(Pubkey::new_unique().as_ref());
+
+        for (value, padded_len) in [("USD Coin", 32), ("USDC", 10)] {
+            let mut padded = value.as_bytes().to_vec();
+            padded.resize(padded_len, 0);
+            data.extend_from_slice(&(padded_len as u32).to_le_bytes());
+            data.extend_from_slice(&padded);
+        }
+
+        let metadata = parse_token_metadata(&data).unwrap();
+        assert_eq!(metadata.name, "USD Coin");
+        assert_eq!(metadata.symbol, "USDC");
+        assert_eq!(metadata.uri, None);
+
+        assert_eq!(parse_token_metadata(&data[..70]), None);
+
+        // an NFT, with a URI, two creators and a verified collection
+        let uri = "https://example.com/nft.json";
+        data.extend_from_slice(&(uri.len() as u32).to_le_bytes());
+        data.extend_from_slice(uri.as_bytes());
+        data.extend_from_slice(&500u16.to_le_bytes());
+        data.extend_from_slice(&[1, 2, 0, 0, 0]);
+        data.extend_from_slice(&[0; 68]);
+        data.extend_from_slice(&[1, 1, 1, 254, 1, 0]);
+
+        let collection = Pubkey::new_unique();
+        data.extend_from_slice(&[1, 1]);
+        data.extend_from_slice(collection.as_ref());
+
+        let metadata = parse_token_metadata(&data).unwrap();
+        assert_eq!(metadata.uri.as_deref(), Some(uri));
+        assert_eq!(metadata.collection, Some(collection.to_string()));
+        assert!(metadata.collection_verified);
+    }
+
+    #[test]
+    fn test_parse_mint() {
+        let mut data = vec![0; 82];
+        data[36..44].copy_from_slice(&1u64.to_le_bytes());
+
+        assert_eq!(parse_mint(&data), Some((1, 0)));
+        assert_eq!(parse_mint(&data[..44]), None);
+    }
+
+    #[test]
+    fn test_token_balance_changes() {
+        let balance = |index: u8, mint: &str, amount: &str| UiTransactionTokenBalance {
+            account_index: index,
+            mint: mint.to_string(),
+            ui_token_amount: UiTokenAmount {
+                ui_amount: None,
+                decimals: 0,
+                amount: amount.to_string(),
+                ui_amount_string: amount.to_string(),
+            },
+            owner: OptionSerializer::Some(format!("owner{index}")),
+            program_id: OptionSerializer::Some(TOKEN_PROGRAM_IDS[0].to_string()),
+        };
+
+        // an NFT moves from account 1 to a new account 2; account 3 is untouched
+        let transfers = token_balance_changes(
+            &[balance(1, "nft", "1"), balance(3, "usdc", "50")],
+            &[
+                balance(1, "nft", "0"),
+                balance(2, "nft", "1"),
+                balance(3, "usdc", "50"),
+            ],
+        );
+
+        assert_eq!(
+            transfers
+                .iter()
+                .map(|transfer| (transfer.account_index, transfer.change))
+                .collect::<Vec<_>>(),
+            vec![(1, -1), (2, 1)]
+        );
+        assert_eq!(transfers[1].mint, "nft");
+        assert_eq!(transfers[1].owner.as_deref(), Some("owner2"));
+    }
+
+    #[test]
+    fn test_diff_account_data() {
+        assert!(diff_account_data(&[1, 2, 3], &[1, 2, 3]).is_empty());
+
+        assert_eq!(
+            diff_account_data(&[1, 2, 3, 4, 5], &[1, 9, 9, 4, 6]),
+            vec![
+                ByteChange {
+                    offset: 1,
+                    old: "0203".to_string(),
+                    new: "0909".to_string(),
+                },
+                ByteChange {
+                    offset: 4,
+                    old: "05".to_string(),
+                    new: "06".to_string(),
+                },
+            ]
+        );
+
+        assert_eq!(
+            diff_account_data(&[1, 2], &[1, 2, 3]),
+            vec![ByteChange {
+                offset: 2,
+                old: "".to_string(),
+                new: "03".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_memo() {
+        let parsed = |program: &str, parsed: serde_json::Value| {
+            UiInstruction::Parsed(UiParsedInstruction::Parsed(ParsedInstruction {
+                program: program.to_string(),
+                program_id: Pubkey::new_unique().to_string(),
+                parsed,
+                stack_height: None,
+            }))
+        };
+        let transfer = parsed(
+            "system",
+            serde_json::json!({ "type": "transfer", "info": { "lamports": 10 } }),
+        );
+
+        assert_eq!(parse_memo(&[transfer.clone()]), None);
+        assert_eq!(
+            parse_memo(&[
+                transfer,
+                parsed("spl-memo", serde_json::json!("invoice 42")),
+                parsed("spl-memo", serde_json::json!("thanks")),
+            ]),
+            Some("invoice 42\nthanks".to_string())
+        );
+
+        // memos that aren't valid UTF-8 are left undecoded by the RPC node
+        let undecoded = UiInstruction::Parsed(UiParsedInstruction::PartiallyDecoded(
+            UiPartiallyDecodedInstruction {
+                program_id: Pubkey::new_unique().to_string(),
+                accounts: vec![],
+                data: "3yZe7d".to_string(),
+                stack_height: None,
+            },
+        ));
+        assert_eq!(parse_memo(&[undecoded]), None);
+    }
+
+    #[test]
+    fn test_classify() {
+        let parsed = |program_id: &str, kind: &str| {
+            UiInstruction::Parsed(UiParsedInstruction::Parsed(ParsedInstruction {
+                program: String::new(),
+                program_id: program_id.to_string(),
+                parsed: serde_json::json!({ "type": kind, "info": {} }),
+                stack_height: None,
+            }))
+        };
+        let transfer = parsed(SYSTEM_PROGRAM_ID, "transfer");
+        let create_account = parsed(SYSTEM_PROGRAM_ID, "createAccount");
+        let delegate = parsed(STAKE_PROGRAM_ID, "delegate");
+
+        assert_eq!(classify(&[]), TransactionType::Other);
+        assert_eq!(classify(&[create_account.clone()]), TransactionType::Other);
+        assert_eq!(classify(&[transfer.clone()]), TransactionType::Transfer);
+        assert_eq!(
+            classify(&[parsed(TOKEN_PROGRAM_IDS[1], "transferChecked")]),
+            TransactionType::Token
+        );
+        assert_eq!(
+            classify(&[parsed(VOTE_PROGRAM_ID, "towersync")]),
+            TransactionType::Vote
+        );
+
+        // creating and delegating a stake account is a stake operation, not a transfer
+        assert_eq!(
+            classify(&[create_account, transfer, delegate]),
+            TransactionType::Stake
+        );
+
+        assert_eq!(VOTE_PROGRAM_ID, vote::program::id().to_string());
+        assert_eq!(STAKE_PROGRAM_ID, stake::program::id().to_string());
+        assert_eq!(SYSTEM_PROGRAM_ID, system_program::id().to_string());
+        assert_eq!(
+            TransactionType::from_name("stake"),
+            Some(TransactionType::Stake)
+        );
+    }
+
+    #[test]
+    fn test_parse_sol_transfers() {
+        let parsed = |program_id: &str, kind: &str, lamports: u64| {
+            UiInstruction::Parsed(UiParsedInstruction::Parsed(ParsedInstruction {
+                program: String::new(),
+                program_id: program_id.to_string(),
+                parsed: serde_json::json!({
+                    "type": kind,
+                    "info": { "source": "payer", "destination": "receiver", "lamports": lamports },
+                }),
+                stack_height: None,
+            }))
+        };
+
+        assert_eq!(
+            parse_sol_transfers(&[
+                parsed(SYSTEM_PROGRAM_ID, "createAccount", 1),
+                parsed(SYSTEM_PROGRAM_ID, "transfer", 10),
+                parsed(TOKEN_PROGRAM_IDS[0], "transfer", 20),
+                parsed(SYSTEM_PROGRAM_ID, "transferWithSeed", 30),
+            ]),
+            [10, 30].map(|lamports| SolTransfer {
+                source: "payer".to_string(),
+                destination: "receiver".to_string(),
+                lamports,
+            })
+        );
+        assert!(parse_sol_transfers(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_parse_compute_budget() {
+        let compute_budget = |data: Vec<u8>| {
+            UiInstruction::Parsed(UiParsedInstruction::PartiallyDecoded(
+                UiPartiallyDecodedInstruction {
+                    program_id: COMPUTE_BUDGET_PROGRAM_ID.to_string(),
+                    accounts: vec![],
+                    data: bs58::encode(data).into_string(),
+                    stack_height: None,
+                },
+            ))
+        };
+        let transfer = UiInstruction::Parsed(UiParsedInstruction::Parsed(ParsedInstruction {
+            program: "system".to_string(),
+            program_id: "11111111111111111111111111111111".to_string(),
+            parsed: serde_json::json!({ "type": "transfer" }),
+            stack_height: None,
+        }));
+
+        let mut set_limit = vec![2];
+        set_limit.extend_from_slice(&300_000u32.to_le_bytes());
+        let mut set_price = vec![3];
+        set_price.extend_from_slice(&10_000u64.to_le_bytes());
+
+        assert_eq!(
+            parse_compute_budget(&[
+                compute_budget(set_limit),
+                compute_budget(set_price.clone()),
+                transfer.clone(),
+            ]),
+            ComputeBudget {
+                unit_limit: Some(300_000),
+                unit_price: Some(10_000),
+                priority_fee: 3000,
+            }
+        );
+
+        // without a limit, the default units of the one other instruction, rounded up
+        set_price[1..].copy_from_slice(&1u64.to_le_bytes());
+        assert_eq!(
+            parse_compute_budget(&[compute_budget(set_price), transfer.clone()]).priority_fee,
+            1
+        );
+
+        assert_eq!(parse_compute_budget(&[transfer]), ComputeBudget::default());
+    }
+
     #[test]
     fn test_parse_transaction() {
         // Test 1: Valid parsed transaction
@@ -337,13 +1588,15 @@ mod tests {
                     pre_balances: vec![100_000, 50_000],
                     post_balances: vec![85_000, 60_000],
                     inner_instructions: OptionSerializer::Some(vec![]),
-                    log_messages: OptionSerializer::Some(vec![]),
+                    log_messages: OptionSerializer::Some(vec![
+                        "Program 11111111111111111111111111111111 success".to_string(),
+                    ]),
                     pre_token_balances: OptionSerializer::Some(vec![]),
                     post_token_balances: OptionSerializer::Some(vec![]),
                     rewards: OptionSerializer::Some(vec![]),
                     loaded_addresses: OptionSerializer::Skip,
                     return_data: OptionSerializer::Skip,
-                    compute_units_consumed: OptionSerializer::Skip,
+                    compute_units_consumed: OptionSerializer::Some(150),
                 }),
                 version: None,
             },
@@ -351,11 +1604,50 @@ mod tests {
             block_time: Some(1625077743),
         };
 
+        let mut failed = txn.clone();
+        let mut swap = txn.clone();
+        let mut single_key = txn.clone();
         let parsed_transaction = parse_transaction(txn).unwrap();
         assert_eq!(parsed_transaction.sol_amount, 10_000);
         assert_eq!(parsed_transaction.fee, 5000);
         assert_eq!(parsed_transaction.timestamp, 1625077743);
         assert_eq!(parsed_transaction.prev_blockhash, "recent_blockhash");
+        assert_eq!(parsed_transaction.slot, 42);
+        assert_eq!(parsed_transaction.error, None);
+        assert!(parsed_transaction.success);
+        assert_eq!(parsed_transaction.log_messages.len(), 1);
+        assert_eq!(parsed_transaction.compute_units_consumed, Some(150));
+
+        // a failed transaction only paid its fee
+        let meta = failed.transaction.meta.as_mut().unwrap();
+        meta.err = Some(TransactionError::AccountNotFound);
+        meta.status = Err(TransactionError::AccountNotFound);
+        let parsed_transaction = parse_transaction(failed).unwrap();
+        assert!(!parsed_transaction.success);
+        assert_eq!(
+            parsed_transaction.error.as_deref(),
+            Some(r#""AccountNotFound""#)
+        );
+        assert_eq!(parsed_transaction.sol_amount, 0);
+
+        // the second account paid rather than received, e.g. in a swap
+        let meta = swap.transaction.meta.as_mut().unwrap();
+        meta.post_balances = vec![115_000, 40_000];
+        assert_eq!(parse_transaction(swap).unwrap().sol_amount, 0);
+
+        // the fee payer is the only account
+        let EncodedTransaction::Json(UiTransaction {
+            message: UiMessage::Parsed(message),
+            ..
+        }) = &mut single_key.transaction.transaction
+        else {
+            unreachable!()
+        };
+        message.account_keys.truncate(1);
+        let meta = single_key.transaction.meta.as_mut().unwrap();
+        meta.pre_balances.truncate(1);
+        meta.post_balances.truncate(1);
+        assert_eq!(parse_transaction(single_key).unwrap().sol_amount, 0);
 
         // Test 2: Raw message instead of parsed
         let txn = EncodedConfirmedTransactionWithStatusMeta {
@@ -432,10 +1724,8 @@ mod tests {
 
     #[test]
     fn test_process_transactions() {
-        let _ = dotenvy::dotenv();
-
-        let sender = env::var("ADDRESS_A").expect("`ADDRESS_A` must be set");
-        let receiver = env::var("ADDRESS_B").expect("`ADDRESS_B` must be set");
+        let sender = ADDRESS_A.to_string();
+        let receiver = ADDRESS_B.to_string();
 
         // Valid parsed transaction
         let txn1 = EncodedConfirmedTransactionWithStatusMeta {
@@ -10,45 +10,535 @@
 // * Implement a function to retrieve transactions and account data. This function will use asynchronous requests to fetch data.
 // * Use a background task (using `tokio::spawn`) to periodically poll the blockchain for new transactions.
 
-use crate::{data_processing::process_transactions, data_storage::insert_transaction};
+use crate::{
+    alerts::AlertEngine,
+    archive::RawArchive,
+    blocks,
+    config::{PipelineConfig, RpcRateLimitConfig, RpcRetryConfig},
+    data_processing::{
+        invoked_programs, parse_mint, parse_token_account, parse_token_holding,
+        parse_token_metadata, parse_token_transfers, process_transactions_with_canary,
+        AccountDetails, AccountSnapshot, BalanceSnapshot, Rejection, TokenHolding, TokenMetadata,
+        TokenTransfer, TransactionData, ValidationError, TOKEN_METADATA_PROGRAM_ID,
+        TOKEN_PROGRAM_IDS,
+    },
+    data_storage::{
+        insert_account_snapshot_if_changed, insert_balance_snapshot, insert_fee_split,
+        insert_rejected_transactions, record_token_balances, Storage,
+    },
+    dedup::SeenSignatures,
+    directory::Directory,
+    dlq::DeadLetterQueue,
+    enrichment::NftEnricher,
+    fee_burn::{FeeBurnPolicy, FeeSplit},
+    feed::TransactionFeed,
+    leaderboards::Leaderboards,
+    memory::{json_size, transaction_size, Charge, MemoryBudget, Stage},
+    metrics::IngestMetrics,
+    monitor::MonitorControl,
+    pipeline::Pipeline,
+    prices::PriceFeed,
+    rate_limit::RateLimiter,
+    retry::{backoff, classify},
+    rpc::{self, RpcApi},
+    schedules::Job,
+    sink::Sinks,
+    watchlist::Watchlist,
+};
 
-use log::{error, info};
+use log::{error, info, warn};
+use serde_json::{json, Value};
+use solana_account_decoder::{UiAccountData, UiAccountEncoding, UiDataSliceConfig};
 use solana_client::{
-    rpc_client::{GetConfirmedSignaturesForAddress2Config, RpcClient},
-    rpc_config::RpcTransactionConfig,
+    client_error::{ClientErrorKind, Result as ClientResult},
+    rpc_client::GetConfirmedSignaturesForAddress2Config,
+    rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig, RpcTransactionConfig},
+    rpc_filter::{Memcmp, RpcFilterType},
+    rpc_request::{RpcRequest, TokenAccountsFilter},
+};
+use solana_sdk::{
+    commitment_config::CommitmentConfig,
+    epoch_info::EpochInfo,
+    epoch_schedule::EpochSchedule,
+    hash::Hash,
+    pubkey::Pubkey,
+    signature::{Keypair, Signature},
+    signer::Signer,
+    stake, system_instruction,
+    transaction::Transaction,
+};
+use solana_transaction_status::{
+    EncodedConfirmedTransactionWithStatusMeta, EncodedTransaction, TransactionStatus,
+    UiTransactionEncoding,
 };
-use solana_sdk::{commitment_config::CommitmentConfig, pubkey::Pubkey, signature::Signature};
-use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, UiTransactionEncoding};
 use sqlx::PgPool;
-use tokio::time::{self, Duration};
+use tokio::time::{self, Instant};
+use tokio_util::sync::CancellationToken;
+
+use std::{
+    collections::{HashMap, HashSet},
+    mem::size_of,
+    str::FromStr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Most signatures `getSignaturesForAddress` returns in one call.
+pub const MAX_SIGNATURES_PER_REQUEST: usize = 1000;
+
+/// Signatures a poll of a watched address looks at.
+const ADDRESS_SIGNATURES_PER_POLL: usize = 3;
+
+/// Most signatures `getSignatureStatuses` accepts per request.
+const MAX_SIGNATURE_STATUSES_PER_REQUEST: usize = 256;
+
+/// Most accounts `getMultipleAccounts` returns in one call.
+const MAX_ACCOUNTS_PER_REQUEST: usize = 100;
+
+/// Offsets of the staker and withdrawer authorities in a stake account's data.
+const STAKE_AUTHORITY_OFFSETS: [usize; 2] = [12, 44];
+
+/// Valid transactions of one fetch, with what was derived from their raw RPC responses.
+#[derive(Debug, Default)]
+pub struct ProcessedBatch {
+    pub transactions: Vec<TransactionData>,
+    /// Keyed by signature.
+    pub fee_splits: HashMap<String, FeeSplit>,
+    /// Raw JSON payloads to archive, keyed by signature.
+    pub raw_payloads: HashMap<String, Vec<u8>>,
+    /// Token balance changes, keyed by signature; only kept for NFT enrichment.
+    pub token_transfers: HashMap<String, Vec<TokenTransfer>>,
+    /// Transactions that failed validation; only kept when they're recorded.
+    pub rejected: Vec<Rejection>,
+}
+
+impl ProcessedBatch {
+    /// Estimated memory held by the batch.
+    pub fn estimated_bytes(&self) -> usize {
+        let transactions = self
+            .transactions
+            .iter()
+            .map(transaction_size)
+            .sum::<usize>();
+        let fee_splits = self.fee_splits.len() * (size_of::<String>() + size_of::<FeeSplit>());
+        let raw_payloads = self
+            .raw_payloads
+            .iter()
+            .map(|(signature, json)| signature.capacity() + json.capacity())
+            .sum::<usize>();
+        let token_transfers = self
+            .token_transfers
+            .iter()
+            .map(|(signature, transfers)| {
+                signature.capacity()
+                    + transfers
+                        .iter()
+                        .map(|transfer| size_of::<TokenTransfer>() + transfer.mint.capacity())
+                        .sum::<usize>()
+            })
+            .sum::<usize>();
+        let rejected = self
+            .rejected
+            .iter()
+            .map(|rejection| {
+                transaction_size(&rejection.transaction)
+                    + rejection.reasons.len() * size_of::<ValidationError>()
+            })
+            .sum::<usize>();
 
-use std::{str::FromStr, sync::Arc};
+        transactions + fee_splits + raw_payloads + token_transfers + rejected
+    }
+}
 
+/// Fetches, processes and stores transactions and account data over Solana RPC.
+///
+/// Optional behavior (fee burn accounting, the raw archive, sinks, a memory budget, ...) is
+/// added with the `with_*` builder methods.
 pub struct SolanaClient {
-    client: RpcClient,
+    /// The primary RPC client followed by any fallbacks, tried in order.
+    clients: Vec<Box<dyn RpcApi>>,
+    /// Rate limit of each client, if it has one.
+    limiters: Vec<Option<RateLimiter>>,
+    canary: Option<Pubkey>,
+    /// Watched program ids, which transactions are tagged with.
+    programs: HashSet<String>,
+    program_signatures_per_poll: usize,
+    pipeline: PipelineConfig,
+    retry: RpcRetryConfig,
+    fee_burn: Option<FeeBurnPolicy>,
+    archive: Option<Arc<RawArchive>>,
+    metrics: Arc<IngestMetrics>,
+    monitor: Arc<MonitorControl>,
+    feed: Arc<TransactionFeed>,
+    leaderboards: Arc<Leaderboards>,
+    sinks: Option<Arc<Sinks>>,
+    memory: Arc<MemoryBudget>,
+    seen: Option<Arc<SeenSignatures>>,
+    nfts: Option<Arc<NftEnricher>>,
+    directory: Option<Arc<Directory>>,
+    dead_letters: Option<Arc<DeadLetterQueue>>,
+    alerts: Option<Arc<AlertEngine>>,
+    prices: Option<Arc<PriceFeed>>,
 }
 
 impl SolanaClient {
+    /// Create a client for a single RPC endpoint.
     pub fn new(rpc_url: &str) -> Self {
-        let client =
-            RpcClient::new_with_commitment(rpc_url.to_string(), CommitmentConfig::confirmed());
-        SolanaClient { client }
+        Self::with_fallbacks(&[rpc_url.to_string()])
     }
 
-    /// Fetch transaction signatures for a given address.
-    pub fn fetch_transaction_signatures(&self, address: &Pubkey) -> anyhow::Result<Vec<Signature>> {
-        let mut signature_list: Vec<Signature> = Vec::new();
+    /// Create a client that falls back to the next RPC endpoint whenever a call fails.
+    pub fn with_fallbacks(rpc_urls: &[String]) -> Self {
+        Self::with_endpoints(rpc::connect(rpc_urls))
+    }
+
+    /// Create a client for RPC endpoints of any kind, like recorded fixtures, tried in order.
+    pub fn with_endpoints(clients: Vec<Box<dyn RpcApi>>) -> Self {
+        SolanaClient {
+            limiters: clients.iter().map(|_| None).collect(),
+            clients,
+            canary: None,
+            programs: HashSet::new(),
+            program_signatures_per_poll: ADDRESS_SIGNATURES_PER_POLL,
+            pipeline: PipelineConfig::default(),
+            retry: RpcRetryConfig::default(),
+            fee_burn: None,
+            archive: None,
+            metrics: Arc::new(IngestMetrics::new()),
+            monitor: Arc::new(MonitorControl::new()),
+            feed: Arc::new(TransactionFeed::new()),
+            leaderboards: Arc::new(Leaderboards::new()),
+            sinks: None,
+            memory: Arc::new(MemoryBudget::default()),
+            seen: None,
+            nfts: None,
+            directory: None,
+            dead_letters: None,
+            alerts: None,
+            prices: None,
+        }
+    }
+
+    /// Keep the canary probe's self-transfers when monitoring, even though they carry no value.
+    pub fn with_canary(mut self, canary: Pubkey) -> Self {
+        self.canary = Some(canary);
+        self
+    }
+
+    /// Tag processed transactions with the first of `programs` they invoke, and poll those
+    /// programs `signatures_per_poll` signatures at a time, since they're usually far busier than
+    /// wallets.
+    pub fn with_watched_programs(
+        mut self,
+        programs: &[Pubkey],
+        signatures_per_poll: usize,
+    ) -> Self {
+        self.programs = programs.iter().map(Pubkey::to_string).collect();
+        self.program_signatures_per_poll = signatures_per_poll;
+        self
+    }
+
+    /// Choose which otherwise valid transactions are ingested.
+    pub fn with_pipeline(mut self, pipeline: PipelineConfig) -> Self {
+        self.pipeline = pipeline;
+        self
+    }
+
+    /// Cap the requests sent to each endpoint, whichever fetch they are made for.
+    pub fn with_rate_limit(mut self, config: &RpcRateLimitConfig) -> Self {
+        self.limiters = self
+            .clients
+            .iter()
+            .map(|client| config.for_endpoint(&client.url()).map(RateLimiter::new))
+            .collect();
+        self
+    }
+
+    /// Retry fetching signatures and transactions after transient RPC errors.
+    pub fn with_retry_policy(mut self, retry: RpcRetryConfig) -> Self {
+        self.retry = retry;
+        self
+    }
+
+    /// Record how each monitored transaction's fee splits into burned and validator portions.
+    pub fn with_fee_burn_policy(mut self, policy: FeeBurnPolicy) -> Self {
+        self.fee_burn = Some(policy);
+        self
+    }
+
+    /// Archive the raw RPC payload of each monitored transaction that gets stored.
+    pub fn with_raw_archive(mut self, archive: Arc<RawArchive>) -> Self {
+        self.archive = Some(archive);
+        self
+    }
+
+    /// Also publish newly stored transactions to message buses.
+    pub fn with_sinks(mut self, sinks: Sinks) -> Self {
+        self.sinks = Some(Arc::new(sinks));
+        self
+    }
+
+    /// Queue the transactions of batches that fail to insert, to be replayed later.
+    pub fn with_dead_letters(mut self, dead_letters: Arc<DeadLetterQueue>) -> Self {
+        self.dead_letters = Some(dead_letters);
+        self
+    }
+
+    /// Evaluate alert rules against newly stored transactions.
+    pub fn with_alerts(mut self, alerts: Arc<AlertEngine>) -> Self {
+        self.alerts = Some(alerts);
+        self
+    }
+
+    /// Record the USD prices of transactions and their token transfers when storing them.
+    pub fn with_prices(mut self, prices: Arc<PriceFeed>) -> Self {
+        self.prices = Some(prices);
+        self
+    }
+
+    /// Skip fetching the transactions the dedup filters show are already stored when polling.
+    pub fn with_seen_signatures(mut self, seen: Arc<SeenSignatures>) -> Self {
+        self.seen = Some(seen);
+        self
+    }
+
+    /// Hold back fetches while the pipeline holds more than `budget` allows.
+    pub fn with_memory_budget(mut self, budget: MemoryBudget) -> Self {
+        self.memory = Arc::new(budget);
+        self
+    }
+
+    /// Record the token transfers of stored transactions, enriched with NFT metadata. Postgres
+    /// only.
+    pub fn with_nft_enricher(mut self, nfts: Arc<NftEnricher>) -> Self {
+        self.nfts = Some(nfts);
+        self
+    }
+
+    /// Tag processed transactions with the categories `directory` gives them.
+    pub fn with_directory(mut self, directory: Arc<Directory>) -> Self {
+        self.directory = Some(directory);
+        self
+    }
+
+    /// Memory accounting of fetched and processed transactions.
+    pub fn memory_budget(&self) -> Arc<MemoryBudget> {
+        Arc::clone(&self.memory)
+    }
+
+    /// Wait until the pipeline is under its memory budget, before fetching more.
+    pub async fn wait_for_memory(&self) {
+        self.memory.wait_for_room().await;
+    }
+
+    /// Account for fetched RPC responses until they are processed.
+    pub fn charge_fetched(&self, txns: &[EncodedConfirmedTransactionWithStatusMeta]) -> Charge<'_> {
+        let bytes = txns.iter().map(json_size).sum();
+
+        self.memory.charge(Stage::Fetched, txns.len(), bytes)
+    }
+
+    /// Account for a processed batch until it is stored.
+    pub fn charge_processed(&self, batch: &ProcessedBatch) -> Charge<'_> {
+        self.memory.charge(
+            Stage::Processed,
+            batch.transactions.len(),
+            batch.estimated_bytes(),
+        )
+    }
+
+    /// Throughput of the batch inserts made by `store`.
+    pub fn ingest_metrics(&self) -> Arc<IngestMetrics> {
+        Arc::clone(&self.metrics)
+    }
+
+    /// Pause switch and status of the pipelines this client runs.
+    pub fn monitor_control(&self) -> Arc<MonitorControl> {
+        Arc::clone(&self.monitor)
+    }
+
+    /// Feed of the transactions newly stored by `store`.
+    pub fn transaction_feed(&self) -> Arc<TransactionFeed> {
+        Arc::clone(&self.feed)
+    }
+
+    /// Top senders, receivers and programs of recently stored transactions.
+    pub fn leaderboards(&self) -> Arc<Leaderboards> {
+        Arc::clone(&self.leaderboards)
+    }
+
+    /// Run an RPC call against each endpoint in turn until one succeeds.
+    ///
+    /// The call itself blocks in the `RpcClient`, which needs a multi-thread runtime: on a
+    /// current-thread one, such as an actix worker's, it panics.
+    async fn rpc<T>(&self, call: impl Fn(&dyn RpcApi) -> ClientResult<T>) -> anyhow::Result<T> {
+        Ok(self.rpc_round(&call).await?)
+    }
+
+    /// One round of `rpc`, failing with the last endpoint's error.
+    async fn rpc_round<T>(
+        &self,
+        call: &impl Fn(&dyn RpcApi) -> ClientResult<T>,
+    ) -> ClientResult<T> {
+        let mut last_error = None;
+
+        for (client, limiter) in self.clients.iter().zip(&self.limiters) {
+            if let Some(limiter) = limiter {
+                limiter.acquire().await;
+            }
+
+            match call(client.as_ref()) {
+                Ok(result) => return Ok(result),
+                Err(e) => {
+                    warn!("RPC call to {} failed: {e}", client.url());
+                    last_error = Some(e);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| {
+            ClientErrorKind::Custom("No RPC endpoints configured".to_string()).into()
+        }))
+    }
+
+    /// Like `rpc`, but retried with backoff while every endpoint fails with a transient error.
+    async fn rpc_with_retry<T>(
+        &self,
+        call: impl Fn(&dyn RpcApi) -> ClientResult<T>,
+    ) -> anyhow::Result<T> {
+        let mut retry = 0;
+
+        loop {
+            let error = match self.rpc_round(&call).await {
+                Ok(result) => return Ok(result),
+                Err(e) => e,
+            };
+
+            let Some(delay) = backoff(&self.retry, retry, classify(&error)) else {
+                return Err(error.into());
+            };
+
+            warn!("Retrying RPC call in {delay:?} after: {error}");
+            time::sleep(delay).await;
+            retry += 1;
+        }
+    }
+
+    /// Fetch the genesis hash of the cluster the client is connected to.
+    pub async fn genesis_hash(&self) -> anyhow::Result<Hash> {
+        self.rpc(|client| client.get_genesis_hash()).await
+    }
+
+    /// Fetch the epoch schedule of the cluster the client is connected to.
+    pub async fn epoch_schedule(&self) -> anyhow::Result<EpochSchedule> {
+        self.rpc(|client| client.get_epoch_schedule()).await
+    }
+
+    /// Fetch the current epoch and the position of the latest slot in it.
+    pub async fn epoch_info(&self) -> anyhow::Result<EpochInfo> {
+        self.rpc(|client| client.get_epoch_info()).await
+    }
+
+    /// Send a raw JSON-RPC request upstream, with the same fallbacks, retries and rate limits as
+    /// the client's own calls, and return its result as is.
+    pub async fn forward(&self, request: RpcRequest, params: Value) -> anyhow::Result<Value> {
+        self.rpc_with_retry(|client| client.send(request, params.clone()))
+            .await
+    }
+
+    /// Send a self-transfer of `lamports` from `payer` and wait for it to be confirmed.
+    pub async fn send_self_transfer(
+        &self,
+        payer: &Keypair,
+        lamports: u64,
+    ) -> anyhow::Result<Signature> {
+        let pubkey = payer.pubkey();
+        let blockhash = self.rpc(|client| client.get_latest_blockhash()).await?;
+
+        let instruction = system_instruction::transfer(&pubkey, &pubkey, lamports);
+        let txn =
+            Transaction::new_signed_with_payer(&[instruction], Some(&pubkey), &[payer], blockhash);
+
+        self.rpc(|client| client.send_and_confirm_transaction(&txn))
+            .await
+    }
 
-        let config = GetConfirmedSignaturesForAddress2Config {
-            before: None,
-            until: None,
-            limit: Some(3),
-            commitment: Some(CommitmentConfig::confirmed()),
+    /// Fetch the latest transaction signatures of a watched address or program, as many as a
+    /// poll looks at.
+    pub async fn fetch_transaction_signatures(
+        &self,
+        address: &Pubkey,
+    ) -> anyhow::Result<Vec<Signature>> {
+        let limit = if self.programs.contains(&address.to_string()) {
+            self.program_signatures_per_poll
+        } else {
+            ADDRESS_SIGNATURES_PER_POLL
         };
 
+        self.fetch_recent_signatures(address, limit).await
+    }
+
+    /// Fetch the signatures of up to `limit` of an address's most recent transactions.
+    pub async fn fetch_recent_signatures(
+        &self,
+        address: &Pubkey,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Signature>> {
+        self.fetch_signatures_page(address, None, None, limit).await
+    }
+
+    /// Fetch the signatures of an address's transactions newer than `until`, or of all of them
+    /// without it, newest first and up to `limit`, a page at a time.
+    pub async fn fetch_signatures_since(
+        &self,
+        address: &Pubkey,
+        until: Option<Signature>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Signature>> {
+        let mut signatures = Vec::new();
+        let mut before = None;
+
+        while signatures.len() < limit {
+            let page_limit = (limit - signatures.len()).min(MAX_SIGNATURES_PER_REQUEST);
+            let page = self
+                .fetch_signatures_page(address, before, until, page_limit)
+                .await?;
+            let exhausted = page.len() < page_limit;
+
+            before = page.last().copied();
+            signatures.extend(page);
+
+            if exhausted {
+                break;
+            }
+        }
+
+        Ok(signatures)
+    }
+
+    /// One page of `getSignaturesForAddress`, newest first: signatures older than `before` and
+    /// newer than `until`.
+    async fn fetch_signatures_page(
+        &self,
+        address: &Pubkey,
+        before: Option<Signature>,
+        until: Option<Signature>,
+        limit: usize,
+    ) -> anyhow::Result<Vec<Signature>> {
+        let mut signature_list: Vec<Signature> = Vec::new();
+
         let signatures = self
-            .client
-            .get_signatures_for_address_with_config(address, config)?;
+            .rpc_with_retry(|client| {
+                let config = GetConfirmedSignaturesForAddress2Config {
+                    before,
+                    until,
+                    limit: Some(limit.min(MAX_SIGNATURES_PER_REQUEST)),
+                    commitment: Some(CommitmentConfig::confirmed()),
+                };
+
+                client.get_signatures_for_address_with_config(address, config)
+            })
+            .await?;
 
         for txn in signatures {
             let sig = Signature::from_str(&txn.signature)?;
@@ -58,8 +548,32 @@ impl SolanaClient {
         Ok(signature_list)
     }
 
+    /// Fetch the statuses of transactions, in the order of `signatures`, searching the ledger
+    /// beyond the RPC node's recent status cache. `None` for those the node doesn't know.
+    pub async fn fetch_signature_statuses(
+        &self,
+        signatures: &[Signature],
+    ) -> anyhow::Result<Vec<Option<TransactionStatus>>> {
+        let mut statuses = Vec::with_capacity(signatures.len());
+
+        for chunk in signatures.chunks(MAX_SIGNATURE_STATUSES_PER_REQUEST) {
+            let response = self
+                .rpc_with_retry(|client| client.get_signature_statuses_with_history(chunk))
+                .await?;
+            statuses.extend(response.value);
+        }
+
+        Ok(statuses)
+    }
+
+    /// Fetch the latest finalized slot.
+    pub async fn fetch_finalized_slot(&self) -> anyhow::Result<u64> {
+        self.rpc_with_retry(|client| client.get_slot_with_commitment(CommitmentConfig::finalized()))
+            .await
+    }
+
     /// Fetch transactions based on their signatures.
-    pub fn fetch_transactions(
+    pub async fn fetch_transactions(
         &self,
         signatures: &[Signature],
     ) -> anyhow::Result<Vec<EncodedConfirmedTransactionWithStatusMeta>> {
@@ -73,7 +587,10 @@ impl SolanaClient {
                 ..Default::default()
             };
 
-            if let Ok(txn) = self.client.get_transaction_with_config(sig, config.clone()) {
+            if let Ok(txn) = self
+                .rpc_with_retry(|client| client.get_transaction_with_config(sig, config))
+                .await
+            {
                 transactions.push(txn);
             }
         }
@@ -81,93 +598,727 @@ impl SolanaClient {
         Ok(transactions)
     }
 
+    /// Fetch the transactions of the block at `slot` that touch any of `accounts`, parsing the
+    /// rest of the block only as far as it takes to skip it. Empty when the slot has no block.
+    pub async fn fetch_block_transactions(
+        &self,
+        slot: u64,
+        accounts: &HashSet<String>,
+    ) -> anyhow::Result<Vec<EncodedConfirmedTransactionWithStatusMeta>> {
+        let params = json!([slot, {
+            "encoding": "jsonParsed",
+            "transactionDetails": "full",
+            "rewards": false,
+            "maxSupportedTransactionVersion": 0,
+            "commitment": "confirmed",
+        }]);
+
+        let body = self
+            .rpc_with_retry(|client| client.send_raw(RpcRequest::GetBlock, params.clone()))
+            .await?;
+
+        blocks::transactions_touching(slot, &body, accounts)
+    }
+
+    /// Fetch the current balance of an account, including its token balance if it is a token
+    /// account. Accounts that don't exist yet are reported with zero lamports.
+    pub async fn fetch_balance_snapshot(
+        &self,
+        address: &Pubkey,
+    ) -> anyhow::Result<BalanceSnapshot> {
+        let account = self
+            .rpc(|client| {
+                client.get_account_with_commitment(address, CommitmentConfig::confirmed())
+            })
+            .await?
+            .value;
+
+        let (lamports, token) = match account {
+            Some(account) => (
+                account.lamports,
+                parse_token_account(&account.owner.to_string(), &account.data),
+            ),
+            None => (0, None),
+        };
+
+        let (token_mint, token_amount) = token.unzip();
+
+        Ok(BalanceSnapshot {
+            pubkey: address.to_string(),
+            lamports,
+            token_mint,
+            token_amount,
+            recorded_at: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64,
+        })
+    }
+
+    /// Fetch the current state of an account, with its token balance if it is a token account.
+    /// Returns `None` for accounts that don't exist.
+    pub async fn fetch_account_details(
+        &self,
+        address: &Pubkey,
+    ) -> anyhow::Result<Option<AccountDetails>> {
+        let response = self
+            .rpc(|client| {
+                client.get_account_with_commitment(address, CommitmentConfig::confirmed())
+            })
+            .await?;
+
+        let Some(account) = response.value else {
+            return Ok(None);
+        };
+
+        let owner = account.owner.to_string();
+        let (token_mint, token_amount) = parse_token_account(&owner, &account.data).unzip();
+
+        Ok(Some(AccountDetails {
+            pubkey: address.to_string(),
+            lamports: account.lamports,
+            owner,
+            executable: account.executable,
+            rent_epoch: account.rent_epoch,
+            data_len: account.data.len(),
+            token_mint,
+            token_amount,
+            slot: response.context.slot,
+        }))
+    }
+
+    /// Fetch the current SOL balance of an account, in lamports.
+    pub async fn fetch_lamports(&self, address: &Pubkey) -> anyhow::Result<u64> {
+        self.rpc(|client| client.get_balance(address)).await
+    }
+
+    /// Fetch the balances of every SPL Token and Token-2022 account owned by `owner`.
+    pub async fn fetch_token_holdings(&self, owner: &Pubkey) -> anyhow::Result<Vec<TokenHolding>> {
+        let mut holdings = Vec::new();
+
+        for program in TOKEN_PROGRAM_IDS {
+            let program = Pubkey::from_str(program)?;
+            let accounts = self
+                .rpc(|client| {
+                    client
+                        .get_token_accounts_by_owner(owner, TokenAccountsFilter::ProgramId(program))
+                })
+                .await?;
+
+            for keyed in &accounts {
+                if let UiAccountData::Json(parsed) = &keyed.account.data {
+                    holdings.extend(parse_token_holding(&keyed.pubkey, &parsed.parsed));
+                }
+            }
+        }
+
+        Ok(holdings)
+    }
+
+    /// Fetch the addresses of every SPL Token and Token-2022 account owned by `owner`.
+    pub async fn fetch_token_accounts(&self, owner: &Pubkey) -> anyhow::Result<Vec<Pubkey>> {
+        let mut accounts = Vec::new();
+
+        for program in TOKEN_PROGRAM_IDS {
+            let program = Pubkey::from_str(program)?;
+            let keyed = self
+                .rpc(|client| {
+                    client
+                        .get_token_accounts_by_owner(owner, TokenAccountsFilter::ProgramId(program))
+                })
+                .await?;
+
+            for keyed in keyed {
+                accounts.push(Pubkey::from_str(&keyed.pubkey)?);
+            }
+        }
+
+        Ok(accounts)
+    }
+
+    /// Fetch the addresses of the stake accounts `authority` can stake or withdraw from.
+    pub async fn fetch_stake_accounts(&self, authority: &Pubkey) -> anyhow::Result<Vec<Pubkey>> {
+        let mut accounts = Vec::new();
+
+        for offset in STAKE_AUTHORITY_OFFSETS {
+            let config = RpcProgramAccountsConfig {
+                filters: Some(vec![RpcFilterType::Memcmp(Memcmp::new_base58_encoded(
+                    offset,
+                    authority.as_ref(),
+                ))]),
+                // only the addresses are needed
+                account_config: RpcAccountInfoConfig {
+                    encoding: Some(UiAccountEncoding::Base64),
+                    data_slice: Some(UiDataSliceConfig {
+                        offset: 0,
+                        length: 0,
+                    }),
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+
+            let keyed = self
+                .rpc(|client| {
+                    client.get_program_accounts_with_config(&stake::program::id(), config.clone())
+                })
+                .await?;
+
+            accounts.extend(keyed.into_iter().map(|(pubkey, _)| pubkey));
+        }
+
+        accounts.sort();
+        accounts.dedup();
+
+        Ok(accounts)
+    }
+
+    /// Fetch the Metaplex metadata of `mints`, keyed by mint. Mints without metadata are left out.
+    pub async fn fetch_token_metadata(
+        &self,
+        mints: &[Pubkey],
+    ) -> anyhow::Result<HashMap<String, TokenMetadata>> {
+        let program = Pubkey::from_str(TOKEN_METADATA_PROGRAM_ID)?;
+        let mut metadata = HashMap::new();
+
+        for mints in mints.chunks(MAX_ACCOUNTS_PER_REQUEST) {
+            let addresses = mints
+                .iter()
+                .map(|mint| {
+                    let seeds: &[&[u8]] = &[b"metadata", program.as_ref(), mint.as_ref()];
+                    Pubkey::find_program_address(seeds, &program).0
+                })
+                .collect::<Vec<_>>();
+
+            let accounts = self
+                .rpc(|client| client.get_multiple_accounts(&addresses))
+                .await?;
+
+            for (mint, account) in mints.iter().zip(accounts) {
+                if let Some(parsed) = account.and_then(|data| parse_token_metadata(&data.data)) {
+                    metadata.insert(mint.to_string(), parsed);
+                }
+            }
+        }
+
+        Ok(metadata)
+    }
+
+    /// Fetch the supply and decimals of `mints`, keyed by mint. Accounts that don't exist or aren't
+    /// token mints are left out.
+    pub async fn fetch_mints(
+        &self,
+        mints: &[Pubkey],
+    ) -> anyhow::Result<HashMap<String, (u64, u8)>> {
+        let mut parsed = HashMap::new();
+
+        for mints in mints.chunks(MAX_ACCOUNTS_PER_REQUEST) {
+            let accounts = self
+                .rpc(|client| client.get_multiple_accounts(mints))
+                .await?;
+
+            for (mint, account) in mints.iter().zip(accounts) {
+                let Some(account) = account else {
+                    continue;
+                };
+
+                if !TOKEN_PROGRAM_IDS.contains(&account.owner.to_string().as_str()) {
+                    continue;
+                }
+
+                if let Some(supply) = parse_mint(&account.data) {
+                    parsed.insert(mint.to_string(), supply);
+                }
+            }
+        }
+
+        Ok(parsed)
+    }
+
+    /// Periodically snapshot the balances of the watched addresses, and those of the token
+    /// accounts they own if `tokens` is set, until `shutdown` is cancelled.
+    pub async fn track_balances(
+        &self,
+        watchlist: &Watchlist,
+        database: &Arc<PgPool>,
+        tokens: bool,
+        job: &Job,
+        shutdown: &CancellationToken,
+    ) {
+        while job.tick(shutdown).await {
+            let addresses = watchlist.addresses();
+            let _ = job
+                .run(self.snapshot_balances(&addresses, database, tokens))
+                .await;
+        }
+    }
+
+    /// Snapshot the balance of every address, and of the token accounts it owns if `tokens` is
+    /// set, failing if any of them couldn't be recorded.
+    async fn snapshot_balances(
+        &self,
+        addresses: &[Pubkey],
+        database: &Arc<PgPool>,
+        tokens: bool,
+    ) -> anyhow::Result<()> {
+        let mut failed = 0;
+
+        for address in addresses {
+            let snapshot = match self.fetch_balance_snapshot(address).await {
+                Ok(snapshot) => snapshot,
+                Err(e) => {
+                    error!("Error fetching balance for {address}: {e:?}");
+                    failed += 1;
+                    continue;
+                }
+            };
+
+            if let Err(e) = insert_balance_snapshot(database, &snapshot).await {
+                error!("Failed to insert balance snapshot: {e:?}");
+                failed += 1;
+            }
+
+            // token accounts don't own token accounts
+            if tokens && snapshot.token_mint.is_none() {
+                if let Err(e) = self.snapshot_token_balances(address, database).await {
+                    error!("Failed to snapshot token balances of {address}: {e:?}");
+                    failed += 1;
+                }
+            }
+        }
+
+        if failed > 0 {
+            anyhow::bail!(
+                "{failed} balance snapshots of {} addresses failed",
+                addresses.len()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Record the balances of the token accounts `owner` owns, and which of them were closed.
+    async fn snapshot_token_balances(
+        &self,
+        owner: &Pubkey,
+        database: &Arc<PgPool>,
+    ) -> anyhow::Result<()> {
+        let holdings = self.fetch_token_holdings(owner).await?;
+        let recorded_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+
+        record_token_balances(database, &owner.to_string(), &holdings, recorded_at).await?;
+
+        Ok(())
+    }
+
+    /// Fetch the raw data of an account, or `None` if it doesn't exist.
+    pub async fn fetch_account_snapshot(
+        &self,
+        address: &Pubkey,
+    ) -> anyhow::Result<Option<AccountSnapshot>> {
+        let response = self
+            .rpc(|client| {
+                client.get_account_with_commitment(address, CommitmentConfig::confirmed())
+            })
+            .await?;
+
+        let recorded_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+
+        Ok(response.value.map(|account| AccountSnapshot {
+            pubkey: address.to_string(),
+            owner: account.owner.to_string(),
+            lamports: account.lamports,
+            data: account.data,
+            slot: response.context.slot,
+            recorded_at,
+        }))
+    }
+
+    /// Periodically snapshot the data of watched accounts, storing a new version whenever it
+    /// changes, until `shutdown` is cancelled.
+    pub async fn watch_account_data(
+        &self,
+        accounts: &[Pubkey],
+        database: &Arc<PgPool>,
+        job: &Job,
+        shutdown: &CancellationToken,
+    ) {
+        while job.tick(shutdown).await {
+            let _ = job
+                .run(self.snapshot_account_data(accounts, database))
+                .await;
+        }
+    }
+
+    /// Snapshot the data of every account, failing if any of them couldn't be recorded.
+    async fn snapshot_account_data(
+        &self,
+        accounts: &[Pubkey],
+        database: &Arc<PgPool>,
+    ) -> anyhow::Result<()> {
+        let mut failed = 0;
+
+        for account in accounts {
+            let snapshot = match self.fetch_account_snapshot(account).await {
+                Ok(Some(snapshot)) => snapshot,
+                Ok(None) => continue,
+                Err(e) => {
+                    error!("Error fetching account data for {account}: {e:?}");
+                    failed += 1;
+                    continue;
+                }
+            };
+
+            match insert_account_snapshot_if_changed(database, &snapshot).await {
+                Ok(true) => {
+                    info!(
+                        "Account data of {account} changed at slot {}",
+                        snapshot.slot
+                    )
+                }
+                Ok(false) => {}
+                Err(e) => {
+                    error!("Failed to insert account snapshot: {e:?}");
+                    failed += 1;
+                }
+            }
+        }
+
+        if failed > 0 {
+            anyhow::bail!("{failed} of {} account snapshots failed", accounts.len());
+        }
+
+        Ok(())
+    }
+
     /// Fetch epoch data.
     pub async fn fetch_epoch_data(
         &self,
         address: &Pubkey,
     ) -> anyhow::Result<Vec<EncodedConfirmedTransactionWithStatusMeta>> {
-        let signatures = self.fetch_transaction_signatures(address)?;
-        let transactions = self.fetch_transactions(&signatures)?;
+        let signatures = self.fetch_transaction_signatures(address).await?;
+        let transactions = self.fetch_transactions(&signatures).await?;
 
         Ok(transactions)
     }
 
-    /// Continuously monitor the blockchain for new data.
-    pub async fn monitor_blockchain(&self, address: Pubkey, database: Option<&Arc<PgPool>>) {
-        let mut interval = time::interval(Duration::from_secs(10));
+    /// Continuously monitor the blockchain for new data until `shutdown` is cancelled, through
+    /// the monitor's `Pipeline`.
+    ///
+    /// Cancellation is only observed between polls, so a poll's inserts are always completed.
+    /// Fee splits and raw payloads are only recorded when a Postgres pool is given. Without
+    /// storage, transactions are only published to the sinks. The watchlist is read on every
+    /// poll, so discovered addresses are picked up as they're found, and watched programs and the
+    /// canary's address are polled alongside it.
+    pub async fn monitor_blockchain(
+        &self,
+        watchlist: &Watchlist,
+        storage: Option<&Arc<dyn Storage>>,
+        postgres: Option<&Arc<PgPool>>,
+        job: &Job,
+        shutdown: &CancellationToken,
+    ) {
+        Pipeline::monitor(self, watchlist, storage, postgres)
+            .run(job, shutdown)
+            .await;
 
-        loop {
-            interval.tick().await;
+        info!("Stopped monitoring the blockchain");
+    }
+
+    /// Address of the canary probe, whose transactions are monitored, if there is one.
+    pub fn canary(&self) -> Option<Pubkey> {
+        self.canary
+    }
+
+    /// Record an address's polled signatures in the dedup filters, if there are any, once its
+    /// transactions are stored.
+    pub fn record_polled(&self, address: &Pubkey, signatures: &[Signature]) {
+        if let Some(seen) = &self.seen {
+            seen.record(address, signatures);
+        }
+    }
+
+    /// Publish transactions to the sinks without storing them, skipping those published
+    /// recently, if there are sinks.
+    pub async fn publish_unstored(&self, txns: &[TransactionData]) -> anyhow::Result<()> {
+        match &self.sinks {
+            Some(sinks) => sinks.publish_unseen(txns).await,
+            None => Ok(()),
+        }
+    }
+
+    /// Fetch an address's latest transactions, skipping those the dedup filters show are already
+    /// in `storage`. Also returns every polled signature.
+    pub async fn fetch_unstored(
+        &self,
+        address: &Pubkey,
+        storage: Option<&Arc<dyn Storage>>,
+    ) -> anyhow::Result<(
+        Vec<Signature>,
+        Vec<EncodedConfirmedTransactionWithStatusMeta>,
+    )> {
+        let signatures = self.fetch_transaction_signatures(address).await?;
 
-            match self.fetch_epoch_data(&address).await {
-                Ok(txns) => {
-                    let processed_txns = process_transactions(txns);
+        let unstored = match (&self.seen, storage) {
+            (Some(seen), Some(storage)) => {
+                seen.unseen(address, signatures.clone(), storage).await?
+            }
+            _ => signatures.clone(),
+        };
 
-                    info!("Fetched {} transactions", processed_txns.len(),);
+        let txns = self.fetch_transactions(&unstored).await?;
+
+        Ok((signatures, txns))
+    }
+
+    /// Parse and validate fetched transactions, leaving out those the pipeline settings exclude,
+    /// and work out their fee splits and raw payloads before the RPC responses are consumed.
+    pub fn process(
+        &self,
+        txns: Vec<EncodedConfirmedTransactionWithStatusMeta>,
+        canary: Option<&str>,
+    ) -> ProcessedBatch {
+        let fee_splits = match &self.fee_burn {
+            Some(policy) => txns.iter().filter_map(|txn| policy.split(txn)).collect(),
+            None => HashMap::new(),
+        };
 
-                    if let Some(db) = database {
-                        for txn in processed_txns.iter() {
-                            if let Err(e) = insert_transaction(db, txn).await {
-                                error!("Failed to insert transaction: {e:?}");
-                            }
-                        }
+        let raw_payloads = match &self.archive {
+            Some(_) => txns.iter().filter_map(RawArchive::payload).collect(),
+            None => HashMap::new(),
+        };
+
+        let token_transfers = match &self.nfts {
+            Some(_) => txns
+                .iter()
+                .filter_map(|txn| {
+                    let EncodedTransaction::Json(ui_txn) = &txn.transaction.transaction else {
+                        return None;
+                    };
+
+                    let signature = ui_txn.signatures.first()?.clone();
+                    let transfers = parse_token_transfers(txn);
+                    (!transfers.is_empty()).then_some((signature, transfers))
+                })
+                .collect(),
+            None => HashMap::new(),
+        };
+
+        let mut categories = match &self.directory {
+            Some(directory) => txns
+                .iter()
+                .filter_map(|txn| directory.categorize(txn))
+                .collect(),
+            None => HashMap::new(),
+        };
+
+        let (mut transactions, excluded, mut rejected) =
+            process_transactions_with_canary(txns, canary, &self.pipeline);
+        self.metrics.record_exclusions(excluded);
+
+        if !self.pipeline.validation.store_rejected {
+            rejected.clear();
+        }
+
+        if !self.programs.is_empty() {
+            for txn in &mut transactions {
+                txn.program = invoked_programs(&txn.log_messages)
+                    .into_iter()
+                    .find(|program| self.programs.contains(*program))
+                    .map(str::to_string);
+            }
+        }
+
+        for txn in &mut transactions {
+            if let Some(categories) = categories.remove(&txn.signature) {
+                txn.categories = categories;
+            }
+        }
+
+        ProcessedBatch {
+            transactions,
+            fee_splits,
+            raw_payloads,
+            token_transfers,
+            rejected,
+        }
+    }
+
+    /// Send the transactions alert rules may fire on that aren't in `storage` yet down the alert
+    /// priority lane, if there are alert rules, so they're evaluated ahead of the insert.
+    pub async fn dispatch_alerts(&self, txns: &[TransactionData], storage: &Arc<dyn Storage>) {
+        if let Some(alerts) = &self.alerts {
+            alerts.dispatch(txns, storage).await;
+        }
+    }
+
+    /// Price the transactions of a batch that aren't priced yet, if there's a price feed, and
+    /// store them in one go, count the newly stored ones towards the leaderboards and publish
+    /// them to the feed and sinks, then store their fee splits and raw payloads, and the batch's
+    /// rejected transactions, if a Postgres pool is given. Returns the newly stored signatures. A
+    /// batch that fails to insert is dead-lettered, if there's a queue.
+    pub async fn store(
+        &self,
+        batch: &ProcessedBatch,
+        storage: &Arc<dyn Storage>,
+        postgres: Option<&Arc<PgPool>>,
+    ) -> anyhow::Result<Vec<String>> {
+        if let (Some(db), false) = (postgres, batch.rejected.is_empty()) {
+            let rejected_at = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+
+            if let Err(e) = insert_rejected_transactions(db, &batch.rejected, rejected_at).await {
+                error!("Failed to record rejected transactions: {e:?}");
+            }
+        }
+
+        if batch.transactions.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // priced before the insert, so their USD volumes are counted with them
+        let mut priced = None;
+
+        if let Some(prices) = &self.prices {
+            let mut transactions = batch.transactions.clone();
+            let mut token_transfers = batch.token_transfers.clone();
+            prices.price(&mut transactions, &mut token_transfers).await;
+            priced = Some((transactions, token_transfers));
+        }
+
+        let (transactions, token_transfers) = match &priced {
+            Some((transactions, token_transfers)) => (transactions, token_transfers),
+            None => (&batch.transactions, &batch.token_transfers),
+        };
+
+        let started_at = Instant::now();
+        let stored = match storage.insert_batch(transactions).await {
+            Ok(stored) => stored,
+            Err(e) => {
+                if let Some(dead_letters) = &self.dead_letters {
+                    dead_letters.push(transactions, &e);
+                }
+
+                return Err(e);
+            }
+        };
+
+        self.metrics
+            .record_batch(stored.len(), transactions.len(), started_at.elapsed());
+
+        let newly_stored = stored.iter().collect::<HashSet<_>>();
+        let is_new = |txn: &&TransactionData| newly_stored.contains(&txn.signature);
+        self.feed.publish(transactions.iter().filter(is_new));
+        self.leaderboards.record(transactions.iter().filter(is_new));
+
+        if let Some(sinks) = &self.sinks {
+            sinks.publish(transactions.iter().filter(is_new)).await;
+        }
+
+        if let Some(alerts) = &self.alerts {
+            // those sent down the priority lane are evaluated there
+            let undispatched = transactions
+                .iter()
+                .filter(is_new)
+                .filter(|txn| !alerts.was_dispatched(&txn.signature))
+                .collect::<Vec<_>>();
+
+            alerts.evaluate(&undispatched).await;
+        }
+
+        let Some(db) = postgres else {
+            return Ok(stored);
+        };
+
+        for signature in &stored {
+            if let Some(split) = batch.fee_splits.get(signature) {
+                if let Err(e) = insert_fee_split(db, signature, split).await {
+                    error!("Failed to insert fee split: {e:?}");
+                }
+            }
+
+            if let Some(archive) = &self.archive {
+                if let Some(json) = batch.raw_payloads.get(signature) {
+                    if let Err(e) = archive.store(db, signature, json).await {
+                        error!("Failed to archive raw transaction: {e:?}");
                     }
                 }
-                Err(e) => error!("Error fetching epoch data: {:?}", e),
             }
         }
+
+        if let Some(nfts) = &self.nfts {
+            let transfers = stored
+                .iter()
+                .filter_map(|signature| token_transfers.get_key_value(signature))
+                .collect::<Vec<_>>();
+
+            if !transfers.is_empty() {
+                if let Err(e) = nfts.record(self, db, &transfers).await {
+                    error!("Failed to record token transfers: {e:?}");
+                }
+            }
+        }
+
+        Ok(stored)
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+
+    use crate::{data_storage::InMemoryStorage, rpc::FixtureRpc};
+
     use anyhow::Result;
-    use std::env;
+    use std::path::Path;
 
-    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
-    async fn test_fetch_transactions() -> Result<()> {
-        dotenvy::dotenv()?;
+    const SENDER: &str = "DLpcCvJooMbrbJosuGYeUjYGxGZTgRfgWkhPehTgngiB";
+    const RECEIVER: &str = "BFfZpV9Kh8MdAKCNcMwuqAUiEYkatcojP4PJ3CQuTJ12";
 
-        let rpc_url = env::var("RPC_URL")?;
-        let solana_client = SolanaClient::new(&rpc_url);
+    fn fixture(name: &str) -> Result<Box<dyn RpcApi>> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("fixtures/rpc")
+            .join(name);
 
-        let address_a = env::var("ADDRESS_A")?;
-        let address_b = env::var("ADDRESS_B")?;
+        Ok(Box::new(FixtureRpc::load(&path)?))
+    }
 
-        let address_a_pubkey = Pubkey::from_str(&address_a).unwrap();
-        let address_b_pubkey = Pubkey::from_str(&address_b).unwrap();
+    #[tokio::test]
+    async fn test_fetch_process_and_store_from_fixtures() -> Result<()> {
+        // the first endpoint knows nothing, so every call falls back to the recorded one
+        let solana_client = SolanaClient::with_endpoints(vec![
+            Box::new(FixtureRpc::new("empty", Vec::new())),
+            fixture("transfer.json")?,
+        ]);
 
-        let signatures_a = solana_client.fetch_transaction_signatures(&address_a_pubkey)?;
-        let signatures_b = solana_client.fetch_transaction_signatures(&address_b_pubkey)?;
+        let signatures = solana_client
+            .fetch_transaction_signatures(&Pubkey::from_str(SENDER)?)
+            .await?;
+        assert_eq!(signatures.len(), 1);
 
-        println!("transaction signatures for address A: {:?}", signatures_a);
-        println!("transaction signatures for address B: {:?}", signatures_b);
+        let transactions = solana_client.fetch_transactions(&signatures).await?;
+        assert_eq!(transactions.len(), 1);
 
-        assert!(
-            !signatures_a.is_empty(),
-            "No transactions found for address A"
+        let batch = solana_client.process(transactions, None);
+        let txn = &batch.transactions[0];
+        assert_eq!(
+            (txn.sender.as_str(), txn.receiver.as_str()),
+            (SENDER, RECEIVER)
         );
-        assert!(
-            !signatures_b.is_empty(),
-            "No transactions found for address B"
+        assert_eq!(
+            (txn.sol_amount, txn.fee, txn.slot),
+            (250_000_000, 5000, 312000042)
         );
+        assert_eq!(txn.fee_payer.as_deref(), Some(SENDER));
+        assert_eq!(txn.transfers.len(), 1);
 
-        let transactions_a = solana_client.fetch_transactions(&signatures_a)?;
-
-        let transactions_b = solana_client.fetch_transactions(&signatures_b)?;
+        let storage: Arc<dyn Storage> = Arc::new(InMemoryStorage::new());
+        let stored = solana_client.store(&batch, &storage, None).await?;
+        assert_eq!(stored, vec![signatures[0].to_string()]);
 
-        assert!(
-            !transactions_a.is_empty(),
-            "No transaction data fetched for address A"
+        assert_eq!(
+            solana_client
+                .fetch_lamports(&Pubkey::from_str(RECEIVER)?)
+                .await?,
+            750_000_000
         );
-        assert!(
-            !transactions_b.is_empty(),
-            "No transaction data fetched for address B"
-        );
-
-        println!("transaction data for signature A: {:#?}", transactions_a);
-        println!("transaction data for signature B: {:#?}", transactions_b);
+        assert!(solana_client.epoch_info().await.is_err());
 
         Ok(())
     }
@@ -8,97 +8,3318 @@
 // * In-memory storage: Use a thread-safe data structure (e.g., `HashMap` or `Vec`) to store data temporarily.
 // * Database storage: Use `sqlx` to interact with a PostgreSQL database.
 
-use crate::data_processing::TransactionData;
+use crate::{
+    alerts::{AlertRule, NewAlertRule},
+    analytics::RollupInterval,
+    config::TimescaleConfig,
+    data_processing::{
+        AccountSnapshot, BalanceSnapshot, ComputeBudget, Rejection, SolTransfer, TokenHolding,
+        TokenMetadata, TokenTransfer, TransactionData, TransactionType, ValidationError,
+    },
+    fee_burn::FeeSplit,
+    labels::Label,
+    layouts::AccountLayout,
+};
 
-use log::info;
-use sqlx::{postgres::PgPoolOptions, PgPool};
+use async_trait::async_trait;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use sqlx::{
+    postgres::PgPoolOptions,
+    sqlite::{SqliteConnectOptions, SqlitePoolOptions},
+    types::Json,
+    PgConnection, PgExecutor, PgPool, QueryBuilder, Sqlite, SqlitePool,
+};
 
-use std::sync::Arc;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    slice,
+    str::FromStr,
+    sync::{Arc, RwLock},
+};
 
+/// Rows per multi-row INSERT on SQLite: 22 bound parameters each, well under its limit of 32766.
+const SQLITE_BATCH_ROWS: usize = 1000;
+
+/// Advisory lock serializing writes to `sink_outbox`, so its ids become visible in order.
+const OUTBOX_LOCK: i64 = 0x6f7574626f78;
+
+/// Connect to Postgres and bring its schema up to date.
 pub async fn get_pool(db_url: &str) -> anyhow::Result<PgPool> {
     let pool = PgPoolOptions::new()
         .max_connections(5)
         .connect(db_url)
         .await?;
 
+    sqlx::migrate!("./migrations/postgres").run(&pool).await?;
+
+    Ok(pool)
+}
+
+/// Recorded in `storage_features` once `transactions` is a hypertable.
+const TIMESCALE_FEATURE: &str = "timescale";
+
+/// Turn `transactions` into a TimescaleDB hypertable partitioned on its timestamp, and serve the
+/// rollups from continuous aggregates instead of folding new transactions into them on insert.
+/// Returns whether TimescaleDB mode is on: without the extension, plain Postgres is kept.
+pub async fn enable_timescale(pool: &PgPool, config: &TimescaleConfig) -> anyhow::Result<bool> {
+    // the TimescaleDB objects aren't in the migrated schema, so these are runtime queries
+    if let Err(e) = sqlx::query("CREATE EXTENSION IF NOT EXISTS timescaledb")
+        .execute(pool)
+        .await
+    {
+        warn!("TimescaleDB is not available, keeping plain Postgres storage: {e}");
+        return Ok(false);
+    }
+
+    let hypertable = sqlx::query_scalar::<_, bool>(
+            "SELECT EXISTS (
+                SELECT 1 FROM timescaledb_information.hypertables WHERE hypertable_name = 'transactions'
+            )",
+        )
+        .fetch_one(pool)
+        .await?;
+
+    if !hypertable {
+        info!("Converting transactions to a hypertable, which rewrites the table");
+
+        let mut tx = pool.begin().await?;
+
+        // unique indexes of a hypertable must include its partitioning column; a signature is
+        // always stored with the same timestamp, so duplicates are still skipped
+        for statement in [
+            "ALTER TABLE transactions DROP CONSTRAINT transactions_pkey",
+            "DROP INDEX transactions_signature_idx",
+            "CREATE UNIQUE INDEX transactions_id_timestamp_idx ON transactions (id, timestamp)",
+            "CREATE UNIQUE INDEX transactions_signature_timestamp_idx ON transactions (signature, timestamp)",
+            // continuous aggregates over an integer time column need a way to tell the time
+            "CREATE OR REPLACE FUNCTION transactions_unix_now() RETURNS BIGINT LANGUAGE SQL STABLE
+                AS $$ SELECT extract(epoch FROM now())::BIGINT $$",
+        ] {
+            sqlx::query(statement).execute(&mut *tx).await?;
+        }
+
+        sqlx::query(
+                "SELECT create_hypertable('transactions', 'timestamp', chunk_time_interval => $1::BIGINT,
+                    create_default_indexes => false, migrate_data => true)",
+            )
+            .bind(config.chunk_interval_secs as i64)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query("SELECT set_integer_now_func('transactions', 'transactions_unix_now')")
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+    }
+
+    let existing = sqlx::query_scalar::<_, String>(
+            "SELECT view_name::TEXT FROM timescaledb_information.continuous_aggregates",
+        )
+        .fetch_all(pool)
+        .await?;
+
+    // created before USD volumes were rolled up
+    let outdated = sqlx::query_scalar::<_, String>(
+            "SELECT view_name::TEXT FROM timescaledb_information.continuous_aggregates aggregate
+            WHERE NOT EXISTS (
+                SELECT 1 FROM information_schema.columns
+                WHERE table_name = aggregate.view_name AND column_name = 'usd_volume'
+            )",
+        )
+        .fetch_all(pool)
+        .await?;
+
+    for (name, interval, definition) in continuous_aggregates() {
+        if outdated.contains(&name) {
+            info!("Rebuilding continuous aggregate {name} with USD volumes");
+
+            // takes `volume_rollups` along, which is recreated below
+            sqlx::query(&format!("DROP MATERIALIZED VIEW {name} CASCADE"))
+                .execute(pool)
+                .await?;
+        } else if existing.contains(&name) {
+            continue;
+        }
+
+        sqlx::query(&definition).execute(pool).await?;
+
+        // everything stored so far; from then on the policy refreshes the recent buckets, and the
+        // newest ones are aggregated on the fly when read
+        sqlx::query("CALL refresh_continuous_aggregate($1::REGCLASS, NULL, NULL)")
+            .bind(&name)
+            .execute(pool)
+            .await?;
+
+        sqlx::query(
+                "SELECT add_continuous_aggregate_policy($1::REGCLASS,
+                    start_offset => $2::BIGINT, end_offset => $3::BIGINT,
+                    schedule_interval => make_interval(secs => $4))",
+            )
+            .bind(&name)
+            .bind(config.refresh_lookback_secs as i64)
+            .bind(interval.seconds())
+            .bind(config.refresh_interval_secs as f64)
+            .execute(pool)
+            .await?;
+
+        info!("Created continuous aggregate {name}");
+    }
+
+    sqlx::query(&volume_rollups_view()).execute(pool).await?;
+
+    // only now that the view reads the continuous aggregates, stop folding into the table
+    sqlx::query!(
+            "INSERT INTO storage_features (feature, enabled_at) VALUES ($1, EXTRACT(EPOCH FROM now())::BIGINT)
+            ON CONFLICT (feature) DO NOTHING",
+            TIMESCALE_FEATURE
+        )
+        .execute(pool)
+        .await?;
+
+    Ok(true)
+}
+
+/// Name, interval and definition of the continuous aggregates behind `volume_rollups` in
+/// TimescaleDB mode. Continuous aggregates can't union, so each interval has one by sender, one by
+/// receiver (when not the sender) and one over all transactions.
+fn continuous_aggregates() -> Vec<(String, RollupInterval, String)> {
+    let sides = [
+        ("senders", "sender AS address,", "", "GROUP BY 1, 2"),
+        (
+            "receivers",
+            "receiver AS address,",
+            "WHERE receiver <> sender",
+            "GROUP BY 1, 2",
+        ),
+        ("totals", "", "", "GROUP BY 1"),
+    ];
+
+    RollupInterval::ALL
+        .into_iter()
+        .flat_map(|interval| {
+            sides.map(|(side, address, filter, group_by)| {
+                let name = format!("transaction_rollups_{}_{side}", interval.as_str());
+                let definition = format!(
+                    "CREATE MATERIALIZED VIEW IF NOT EXISTS {name}
+                    WITH (timescaledb.continuous, timescaledb.materialized_only = false) AS
+                    SELECT time_bucket({}::BIGINT, timestamp) AS bucket, {address}
+                        COUNT(*) AS transaction_count, SUM(sol_amount) AS sol_volume, SUM(fee) AS fee_sum,
+                        SUM(sol_amount * sol_usd_price / 1e9) AS usd_volume
+                    FROM transactions {filter}
+                    {group_by}
+                    WITH NO DATA",
+                    interval.seconds()
+                );
+
+                (name, interval, definition)
+            })
+        })
+        .collect()
+}
+
+/// `volume_rollups` over the continuous aggregates, with the same columns as over
+/// `transaction_rollups`.
+fn volume_rollups_view() -> String {
+    let sides = RollupInterval::ALL
+        .into_iter()
+        .flat_map(|interval| {
+            let granularity = interval.as_str();
+
+            [
+                format!("SELECT '{granularity}'::VARCHAR AS granularity, bucket, address, transaction_count, sol_volume, fee_sum, usd_volume
+                    FROM transaction_rollups_{granularity}_senders"),
+                format!("SELECT '{granularity}'::VARCHAR, bucket, address, transaction_count, sol_volume, fee_sum, usd_volume
+                    FROM transaction_rollups_{granularity}_receivers"),
+                format!("SELECT '{granularity}'::VARCHAR, bucket, '{ALL_ADDRESSES}'::VARCHAR, transaction_count, sol_volume, fee_sum, usd_volume
+                    FROM transaction_rollups_{granularity}_totals"),
+            ]
+        })
+        .collect::<Vec<_>>()
+        .join("\n UNION ALL\n");
+
+    format!(
+        "CREATE OR REPLACE VIEW volume_rollups AS
+        SELECT granularity, bucket, address, SUM(transaction_count)::BIGINT AS transaction_count,
+            SUM(sol_volume)::BIGINT AS sol_volume, SUM(fee_sum)::BIGINT AS fee_sum,
+            COALESCE(SUM(usd_volume), 0)::FLOAT8 AS usd_volume
+        FROM ({sides}) sides
+        GROUP BY granularity, bucket, address"
+    )
+}
+
+/// Store a transaction, unless one with the same signature is already stored.
+pub async fn insert_transaction(pool: &PgPool, txn_data: &TransactionData) -> anyhow::Result<()> {
+    // through the batch path, so the counters and rollups stay in step
+    insert_transactions_batch(pool, slice::from_ref(txn_data)).await?;
+
+    Ok(())
+}
+
+/// Column arrays of a batch insert.
+#[derive(Default)]
+struct BatchColumns {
+    signatures: Vec<String>,
+    senders: Vec<String>,
+    receivers: Vec<String>,
+    sol_amounts: Vec<i64>,
+    fees: Vec<i64>,
+    timestamps: Vec<i64>,
+    prev_blockhashes: Vec<String>,
+    slots: Vec<i64>,
+    errors: Vec<Option<String>>,
+    log_messages: Vec<serde_json::Value>,
+    compute_units_consumed: Vec<Option<i64>>,
+    programs: Vec<Option<String>>,
+    memos: Vec<Option<String>>,
+    compute_unit_limits: Vec<Option<i64>>,
+    compute_unit_prices: Vec<Option<i64>>,
+    priority_fees: Vec<Option<i64>>,
+    txn_types: Vec<Option<String>>,
+    categories: Vec<serde_json::Value>,
+    sol_usd_prices: Vec<Option<f64>>,
+    fee_payers: Vec<Option<String>>,
+    transfers: Vec<serde_json::Value>,
+    successes: Vec<bool>,
+}
+
+/// Store transactions with a single multi-row INSERT, skipping those already stored, and fold the
+/// new ones into the counters and rollups in the same database transaction. Returns the
+/// signatures of the newly stored ones, in insertion order.
+pub async fn insert_transactions_batch(
+    pool: &PgPool,
+    txns: &[TransactionData],
+) -> anyhow::Result<Vec<String>> {
+    write_transactions_batch(pool, txns, false).await
+}
+
+/// Store transactions like `insert_transactions_batch`, and add the newly stored ones to
+/// `sink_outbox` in the same database transaction, to be published from there.
+pub async fn insert_transactions_batch_with_outbox(
+    pool: &PgPool,
+    txns: &[TransactionData],
+) -> anyhow::Result<Vec<String>> {
+    write_transactions_batch(pool, txns, true).await
+}
+
+async fn write_transactions_batch(
+    pool: &PgPool,
+    txns: &[TransactionData],
+    outbox: bool,
+) -> anyhow::Result<Vec<String>> {
+    if txns.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let mut columns = BatchColumns::default();
+
+    for txn in txns {
+        columns.signatures.push(txn.signature.clone());
+        columns.senders.push(txn.sender.clone());
+        columns.receivers.push(txn.receiver.clone());
+        columns.sol_amounts.push(txn.sol_amount as i64);
+        columns.fees.push(txn.fee as i64);
+        columns.timestamps.push(txn.timestamp);
+        columns.prev_blockhashes.push(txn.prev_blockhash.clone());
+        columns.slots.push(txn.slot as i64);
+        columns.errors.push(txn.error.clone());
+        columns
+            .log_messages
+            .push(serde_json::to_value(&txn.log_messages)?);
+        columns
+            .compute_units_consumed
+            .push(txn.compute_units_consumed.map(|units| units as i64));
+        columns.programs.push(txn.program.clone());
+        columns.memos.push(txn.memo.clone());
+
+        let budget = txn.compute_budget;
+        columns
+            .compute_unit_limits
+            .push(budget.and_then(|budget| budget.unit_limit.map(i64::from)));
+        columns
+            .compute_unit_prices
+            .push(budget.and_then(|budget| budget.unit_price.map(|price| price as i64)));
+        columns
+            .priority_fees
+            .push(budget.map(|budget| budget.priority_fee as i64));
+        columns
+            .txn_types
+            .push(txn.txn_type.map(|txn_type| txn_type.as_str().to_string()));
+        columns
+            .categories
+            .push(serde_json::to_value(&txn.categories)?);
+        columns.sol_usd_prices.push(txn.sol_usd_price);
+        columns.fee_payers.push(txn.fee_payer.clone());
+        columns.transfers.push(serde_json::to_value(&txn.transfers)?);
+        columns.successes.push(txn.success);
+    }
+
+    let mut tx = pool.begin().await?;
+
+    // one array per column, so the statement doesn't grow with the batch; no conflict target, as
+    // the unique index is on (signature, timestamp) in TimescaleDB mode
+    let rows = sqlx::query!(
+            "INSERT INTO transactions (signature, sender, receiver, sol_amount, fee, timestamp, prev_blockhash, slot, error, log_messages, compute_units_consumed, program, memo, compute_unit_limit, compute_unit_price, priority_fee, txn_type, categories, sol_usd_price, fee_payer, transfers, success)
+            SELECT * FROM UNNEST(
+                $1::VARCHAR[], $2::VARCHAR[], $3::VARCHAR[], $4::BIGINT[], $5::BIGINT[], $6::BIGINT[],
+                $7::VARCHAR[], $8::BIGINT[], $9::VARCHAR[], $10::JSONB[], $11::BIGINT[], $12::VARCHAR[],
+                $13::TEXT[], $14::BIGINT[], $15::BIGINT[], $16::BIGINT[], $17::VARCHAR[], $18::JSONB[],
+                $19::FLOAT8[], $20::VARCHAR[], $21::JSONB[], $22::BOOLEAN[]
+            )
+            ON CONFLICT DO NOTHING
+            RETURNING signature",
+            &columns.signatures,
+            &columns.senders,
+            &columns.receivers,
+            &columns.sol_amounts,
+            &columns.fees,
+            &columns.timestamps,
+            &columns.prev_blockhashes,
+            &columns.slots,
+            &columns.errors as &[Option<String>],
+            &columns.log_messages,
+            &columns.compute_units_consumed as &[Option<i64>],
+            &columns.programs as &[Option<String>],
+            &columns.memos as &[Option<String>],
+            &columns.compute_unit_limits as &[Option<i64>],
+            &columns.compute_unit_prices as &[Option<i64>],
+            &columns.priority_fees as &[Option<i64>],
+            &columns.txn_types as &[Option<String>],
+            &columns.categories,
+            &columns.sol_usd_prices as &[Option<f64>],
+            &columns.fee_payers as &[Option<String>],
+            &columns.transfers,
+            &columns.successes
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+    let inserted = rows
+        .into_iter()
+        .map(|row| row.signature)
+        .collect::<Vec<_>>();
+
+    // only the rows this insert stored, so a retried or replayed batch isn't counted again
+    fold_into_aggregates(&mut tx, &inserted, 1).await?;
+
+    if !inserted.is_empty() {
+        bump_version(&mut *tx).await?;
+    }
+
+    if outbox && !inserted.is_empty() {
+        let newly_stored = inserted.iter().collect::<HashSet<_>>();
+        let (signatures, payloads): (Vec<_>, Vec<_>) = txns
+            .iter()
+            .filter(|txn| newly_stored.contains(&txn.signature))
+            .map(|txn| Ok((txn.signature.clone(), serde_json::to_vec(txn)?)))
+            .collect::<anyhow::Result<Vec<_>>>()?
+            .into_iter()
+            .unzip();
+
+        // held until commit, so a later batch can't become visible before an earlier one
+        sqlx::query!("SELECT pg_advisory_xact_lock($1)", OUTBOX_LOCK)
+            .execute(&mut *tx)
+            .await?;
+
+        sqlx::query!(
+                "INSERT INTO sink_outbox (signature, payload)
+                SELECT * FROM UNNEST($1::VARCHAR[], $2::BYTEA[])",
+                &signatures,
+                &payloads
+            )
+            .execute(&mut *tx)
+            .await?;
+    }
+
+    tx.commit().await?;
+
+    info!("Inserted {} of {} transactions", inserted.len(), txns.len());
+
+    Ok(inserted)
+}
+
+/// How final a stored transaction is.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CommitmentStatus {
+    /// Confirmed by a supermajority when stored, but on a fork that could still be abandoned.
+    #[default]
+    Confirmed,
+    /// Rooted; it can no longer be rolled back.
+    Finalized,
+    /// Its fork was abandoned without it landing on the finalized chain.
+    Dropped,
+}
+
+impl CommitmentStatus {
+    pub const ALL: [CommitmentStatus; 3] = [
+        CommitmentStatus::Confirmed,
+        CommitmentStatus::Finalized,
+        CommitmentStatus::Dropped,
+    ];
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CommitmentStatus::Confirmed => "confirmed",
+            CommitmentStatus::Finalized => "finalized",
+            CommitmentStatus::Dropped => "dropped",
+        }
+    }
+
+    /// The status stored as `name`, if it's one.
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|status| status.as_str() == name)
+    }
+}
+
+/// A transaction as stored, together with its row id.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredTransaction {
+    pub id: i64,
+    pub transaction: TransactionData,
+    /// Only tracked with Postgres storage; always `confirmed` on the other backends.
+    #[serde(default)]
+    pub commitment_status: CommitmentStatus,
+}
+
+/// Filters for `Storage::query`. The default matches every stored transaction.
+#[derive(Debug, Clone, Default)]
+pub struct TransactionQuery {
+    /// Only transactions sent or received by this address.
+    pub address: Option<String>,
+    /// Only transactions sent or received by any of these addresses.
+    pub addresses: Option<Vec<String>>,
+    /// Only rows after this id, for cursor pagination.
+    pub after: i64,
+    /// Time range, in unix seconds.
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    /// Slot range.
+    pub from_slot: Option<i64>,
+    pub to_slot: Option<i64>,
+    /// Only transactions tagged with this watched program.
+    pub program: Option<String>,
+    /// Only transactions whose memo contains this text, case-sensitively.
+    pub memo_contains: Option<String>,
+    pub txn_type: Option<TransactionType>,
+    /// Only transactions tagged with this category.
+    pub category: Option<String>,
+    /// Also return transactions dropped by a fork, which are left out by default.
+    pub include_dropped: bool,
+    /// Only transactions that succeeded (`true`) or failed (`false`) on chain.
+    pub success: Option<bool>,
+    /// `None` returns every matching row.
+    pub limit: Option<i64>,
+}
+
+impl TransactionQuery {
+    fn matches(&self, stored: &StoredTransaction) -> bool {
+        let txn = &stored.transaction;
+
+        stored.id > self.after
+            && self.address.as_ref().map_or(true, |address| {
+                &txn.sender == address || &txn.receiver == address
+            })
+            && self.addresses.as_ref().map_or(true, |addresses| {
+                addresses.contains(&txn.sender) || addresses.contains(&txn.receiver)
+            })
+            && self.from.map_or(true, |from| txn.timestamp >= from)
+            && self.to.map_or(true, |to| txn.timestamp <= to)
+            && self.from_slot.map_or(true, |from| txn.slot as i64 >= from)
+            && self.to_slot.map_or(true, |to| txn.slot as i64 <= to)
+            && self
+                .program
+                .as_ref()
+                .map_or(true, |program| txn.program.as_ref() == Some(program))
+            && self.memo_contains.as_ref().map_or(true, |text| {
+                txn.memo.as_ref().is_some_and(|memo| memo.contains(text))
+            })
+            && self
+                .txn_type
+                .map_or(true, |txn_type| txn.txn_type == Some(txn_type))
+            && self
+                .category
+                .as_ref()
+                .map_or(true, |category| txn.categories.contains(category))
+            && (self.include_dropped || stored.commitment_status != CommitmentStatus::Dropped)
+            && self.success.map_or(true, |success| txn.success == success)
+    }
+}
+
+/// Core transaction storage, implemented by every backend.
+///
+/// The other tables (fee burns, balances, rollups, ...) are Postgres-only and keep taking a
+/// `PgPool` directly.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Store transactions in as few round trips as the backend allows, skipping those whose
+    /// signature is already stored. Returns the signatures of the newly stored ones.
+    async fn insert_batch(&self, txns: &[TransactionData]) -> anyhow::Result<Vec<String>>;
+
+    /// Store a transaction, unless one with the same signature is already stored.
+    async fn insert(&self, txn: &TransactionData) -> anyhow::Result<()> {
+        self.insert_batch(slice::from_ref(txn)).await.map(|_| ())
+    }
+
+    async fn get_by_signature(&self, signature: &str) -> anyhow::Result<Option<StoredTransaction>>;
+
+    /// Which of `signatures` are stored, looked up in as few round trips as the backend allows.
+    async fn stored_signatures(&self, signatures: &[String]) -> anyhow::Result<HashSet<String>> {
+        let mut stored = HashSet::new();
+
+        for signature in signatures {
+            if self.get_by_signature(signature).await?.is_some() {
+                stored.insert(signature.clone());
+            }
+        }
+
+        Ok(stored)
+    }
+
+    /// Stored transactions matching `query`, in insertion order.
+    async fn query(&self, query: &TransactionQuery) -> anyhow::Result<Vec<StoredTransaction>>;
+
+    async fn get_all(&self) -> anyhow::Result<Vec<StoredTransaction>> {
+        self.query(&TransactionQuery::default()).await
+    }
+
+    /// A number that changes whenever the stored transactions do: when one is stored, updated or
+    /// deleted, or a label changes. The default is the latest transaction's id, which only
+    /// changes when one is stored, and reads every transaction to find it, so backends whose
+    /// transactions change otherwise should keep a version.
+    async fn version(&self) -> anyhow::Result<i64> {
+        Ok(self.get_all().await?.last().map_or(0, |stored| stored.id))
+    }
+}
+
+/// A row of the `transactions` table.
+#[derive(sqlx::FromRow)]
+struct TransactionRow {
+    id: i32,
+    signature: String,
+    sender: String,
+    receiver: String,
+    sol_amount: i64,
+    fee: i64,
+    timestamp: i64,
+    prev_blockhash: String,
+    slot: i64,
+    error: Option<String>,
+    log_messages: Json<Vec<String>>,
+    compute_units_consumed: Option<i64>,
+    program: Option<String>,
+    memo: Option<String>,
+    compute_unit_limit: Option<i64>,
+    compute_unit_price: Option<i64>,
+    priority_fee: Option<i64>,
+    txn_type: Option<String>,
+    categories: Json<Vec<String>>,
+    sol_usd_price: Option<f64>,
+    commitment_status: String,
+    fee_payer: Option<String>,
+    transfers: Json<Vec<SolTransfer>>,
+    success: bool,
+}
+
+impl From<TransactionRow> for StoredTransaction {
+    fn from(row: TransactionRow) -> Self {
+        StoredTransaction {
+            id: row.id as i64,
+            transaction: TransactionData {
+                signature: row.signature,
+                sender: row.sender,
+                receiver: row.receiver,
+                sol_amount: row.sol_amount as u64,
+                fee: row.fee as u64,
+                timestamp: row.timestamp,
+                prev_blockhash: row.prev_blockhash,
+                slot: row.slot as u64,
+                error: row.error,
+                log_messages: row.log_messages.0,
+                compute_units_consumed: row.compute_units_consumed.map(|units| units as u64),
+                program: row.program,
+                memo: row.memo,
+                compute_budget: row.priority_fee.map(|priority_fee| ComputeBudget {
+                    unit_limit: row.compute_unit_limit.map(|limit| limit as u32),
+                    unit_price: row.compute_unit_price.map(|price| price as u64),
+                    priority_fee: priority_fee as u64,
+                }),
+                txn_type: row.txn_type.as_deref().and_then(TransactionType::from_name),
+                categories: row.categories.0,
+                sol_usd_price: row.sol_usd_price,
+                fee_payer: row.fee_payer,
+                transfers: row.transfers.0,
+                success: row.success,
+            },
+            commitment_status: CommitmentStatus::from_name(&row.commitment_status)
+                .unwrap_or_default(),
+        }
+    }
+}
+
+#[async_trait]
+impl Storage for PgPool {
+    async fn insert_batch(&self, txns: &[TransactionData]) -> anyhow::Result<Vec<String>> {
+        insert_transactions_batch(self, txns).await
+    }
+
+    async fn get_by_signature(&self, signature: &str) -> anyhow::Result<Option<StoredTransaction>> {
+        let row = sqlx::query_as!(
+                TransactionRow,
+                r#"SELECT id, signature, sender, receiver, sol_amount, fee, timestamp, prev_blockhash, slot, error,
+                    log_messages AS "log_messages: Json<Vec<String>>", compute_units_consumed, program, memo,
+                    compute_unit_limit, compute_unit_price, priority_fee, txn_type,
+                    categories AS "categories: Json<Vec<String>>", sol_usd_price, commitment_status,
+                    fee_payer, transfers AS "transfers: Json<Vec<SolTransfer>>", success
+                FROM transactions WHERE signature = $1"#,
+                signature
+            )
+            .fetch_optional(self)
+            .await?;
+
+        Ok(row.map(StoredTransaction::from))
+    }
+
+    async fn version(&self) -> anyhow::Result<i64> {
+        let version = sqlx::query_scalar!("SELECT version FROM transactions_version")
+            .fetch_one(self)
+            .await?;
+
+        Ok(version)
+    }
+
+    async fn stored_signatures(&self, signatures: &[String]) -> anyhow::Result<HashSet<String>> {
+        let stored = sqlx::query_scalar!(
+                "SELECT signature FROM transactions WHERE signature = ANY($1)",
+                signatures
+            )
+            .fetch_all(self)
+            .await?;
+
+        Ok(stored.into_iter().collect())
+    }
+
+    async fn query(&self, query: &TransactionQuery) -> anyhow::Result<Vec<StoredTransaction>> {
+        // separate statements so the per-address one can use the sender/receiver indexes
+        let rows = match &query.address {
+            Some(address) => sqlx::query_as!(
+                    TransactionRow,
+                    r#"SELECT id, signature, sender, receiver, sol_amount, fee, timestamp, prev_blockhash, slot, error,
+                        log_messages AS "log_messages: Json<Vec<String>>", compute_units_consumed, program, memo,
+                        compute_unit_limit, compute_unit_price, priority_fee, txn_type,
+                        categories AS "categories: Json<Vec<String>>", sol_usd_price, commitment_status,
+                        fee_payer, transfers AS "transfers: Json<Vec<SolTransfer>>", success
+                    FROM transactions
+                    WHERE (sender = $1 OR receiver = $1)
+                        AND id > $2::BIGINT
+                        AND ($3::BIGINT IS NULL OR timestamp >= $3)
+                        AND ($4::BIGINT IS NULL OR timestamp <= $4)
+                        AND ($5::BIGINT IS NULL OR slot >= $5)
+                        AND ($6::BIGINT IS NULL OR slot <= $6)
+                        AND ($7::VARCHAR IS NULL OR program = $7)
+                        AND ($8::TEXT IS NULL OR strpos(memo, $8) > 0)
+                        AND ($9::VARCHAR IS NULL OR txn_type = $9)
+                        AND ($10::VARCHAR[] IS NULL OR sender = ANY($10) OR receiver = ANY($10))
+                        AND ($11::TEXT IS NULL OR categories @> jsonb_build_array($11::TEXT))
+                        AND ($12::BOOLEAN OR commitment_status <> 'dropped')
+                        AND ($13::BOOLEAN IS NULL OR success = $13)
+                    ORDER BY id
+                    LIMIT $14"#,
+                    address,
+                    query.after,
+                    query.from,
+                    query.to,
+                    query.from_slot,
+                    query.to_slot,
+                    query.program,
+                    query.memo_contains,
+                    query.txn_type.map(TransactionType::as_str),
+                    query.addresses.as_deref(),
+                    query.category,
+                    query.include_dropped,
+                    query.success,
+                    query.limit
+                )
+                .fetch_all(self)
+                .await?,
+            None => sqlx::query_as!(
+                    TransactionRow,
+                    r#"SELECT id, signature, sender, receiver, sol_amount, fee, timestamp, prev_blockhash, slot, error,
+                        log_messages AS "log_messages: Json<Vec<String>>", compute_units_consumed, program, memo,
+                        compute_unit_limit, compute_unit_price, priority_fee, txn_type,
+                        categories AS "categories: Json<Vec<String>>", sol_usd_price, commitment_status,
+                        fee_payer, transfers AS "transfers: Json<Vec<SolTransfer>>", success
+                    FROM transactions
+                    WHERE id > $1::BIGINT
+                        AND ($2::BIGINT IS NULL OR timestamp >= $2)
+                        AND ($3::BIGINT IS NULL OR timestamp <= $3)
+                        AND ($4::BIGINT IS NULL OR slot >= $4)
+                        AND ($5::BIGINT IS NULL OR slot <= $5)
+                        AND ($6::VARCHAR IS NULL OR program = $6)
+                        AND ($7::TEXT IS NULL OR strpos(memo, $7) > 0)
+                        AND ($8::VARCHAR IS NULL OR txn_type = $8)
+                        AND ($9::VARCHAR[] IS NULL OR sender = ANY($9) OR receiver = ANY($9))
+                        AND ($10::TEXT IS NULL OR categories @> jsonb_build_array($10::TEXT))
+                        AND ($11::BOOLEAN OR commitment_status <> 'dropped')
+                        AND ($12::BOOLEAN IS NULL OR success = $12)
+                    ORDER BY id
+                    LIMIT $13"#,
+                    query.after,
+                    query.from,
+                    query.to,
+                    query.from_slot,
+                    query.to_slot,
+                    query.program,
+                    query.memo_contains,
+                    query.txn_type.map(TransactionType::as_str),
+                    query.addresses.as_deref(),
+                    query.category,
+                    query.include_dropped,
+                    query.success,
+                    query.limit
+                )
+                .fetch_all(self)
+                .await?,
+        };
+
+        Ok(rows.into_iter().map(StoredTransaction::from).collect())
+    }
+}
+
+#[derive(Debug, Default)]
+struct MemoryTables {
+    transactions: BTreeMap<i64, TransactionData>,
+    /// Row id of the transaction stored under each signature.
+    signatures: HashMap<String, i64>,
+    last_id: i64,
+}
+
+/// Thread-safe storage that keeps everything in memory, for demos and tests.
+#[derive(Debug, Default)]
+pub struct InMemoryStorage {
+    tables: RwLock<MemoryTables>,
+}
+
+impl InMemoryStorage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl Storage for InMemoryStorage {
+    async fn insert_batch(&self, txns: &[TransactionData]) -> anyhow::Result<Vec<String>> {
+        let mut tables = self
+            .tables
+            .write()
+            .expect("in-memory storage lock poisoned");
+        let mut inserted = Vec::new();
+
+        for txn in txns {
+            if tables.signatures.contains_key(&txn.signature) {
+                continue;
+            }
+
+            tables.last_id += 1;
+            let id = tables.last_id;
+
+            tables.transactions.insert(id, txn.clone());
+            tables.signatures.insert(txn.signature.clone(), id);
+            inserted.push(txn.signature.clone());
+        }
+
+        Ok(inserted)
+    }
+
+    async fn get_by_signature(&self, signature: &str) -> anyhow::Result<Option<StoredTransaction>> {
+        let tables = self.tables.read().expect("in-memory storage lock poisoned");
+
+        Ok(tables
+            .signatures
+            .get(signature)
+            .map(|id| StoredTransaction {
+                id: *id,
+                transaction: tables.transactions[id].clone(),
+                commitment_status: CommitmentStatus::Confirmed,
+            }))
+    }
+
+    async fn stored_signatures(&self, signatures: &[String]) -> anyhow::Result<HashSet<String>> {
+        let tables = self.tables.read().expect("in-memory storage lock poisoned");
+
+        Ok(signatures
+            .iter()
+            .filter(|signature| tables.signatures.contains_key(*signature))
+            .cloned()
+            .collect())
+    }
+
+    async fn query(&self, query: &TransactionQuery) -> anyhow::Result<Vec<StoredTransaction>> {
+        let tables = self.tables.read().expect("in-memory storage lock poisoned");
+        let limit = query
+            .limit
+            .map_or(usize::MAX, |limit| limit.max(0) as usize);
+
+        Ok(tables
+            .transactions
+            .range(query.after.saturating_add(1)..)
+            .map(|(id, txn)| StoredTransaction {
+                id: *id,
+                transaction: txn.clone(),
+                commitment_status: CommitmentStatus::Confirmed,
+            })
+            .filter(|stored| query.matches(stored))
+            .take(limit)
+            .collect())
+    }
+
+    async fn version(&self) -> anyhow::Result<i64> {
+        // transactions are only ever added
+        let tables = self.tables.read().expect("in-memory storage lock poisoned");
+
+        Ok(tables.last_id)
+    }
+}
+
+/// Open (creating it if needed) a SQLite database holding the transactions, and bring its schema
+/// up to date.
+///
+/// Only the core transaction storage is available on SQLite; the other tables are Postgres-only.
+pub async fn get_sqlite_pool(db_url: &str) -> anyhow::Result<SqlitePool> {
+    let options = SqliteConnectOptions::from_str(db_url)?.create_if_missing(true);
+
+    // every connection to an in-memory database would get a database of its own
+    let max_connections = if db_url.contains(":memory:") { 1 } else { 5 };
+
+    let pool = SqlitePoolOptions::new()
+        .max_connections(max_connections)
+        .connect_with(options)
+        .await?;
+
+    sqlx::migrate!("./migrations/sqlite").run(&pool).await?;
+
+    Ok(pool)
+}
+
+// The `query!` macros are checked against the Postgres schema, so SQLite uses runtime queries.
+#[async_trait]
+impl Storage for SqlitePool {
+    async fn insert_batch(&self, txns: &[TransactionData]) -> anyhow::Result<Vec<String>> {
+        let mut inserted = Vec::new();
+
+        // one multi-row INSERT per chunk, keeping under SQLite's bound parameter limit
+        for chunk in txns.chunks(SQLITE_BATCH_ROWS) {
+            let mut builder = QueryBuilder::<Sqlite>::new(
+                "INSERT INTO transactions (signature, sender, receiver, sol_amount, fee, timestamp, prev_blockhash, slot, error, log_messages, compute_units_consumed, program, memo, compute_unit_limit, compute_unit_price, priority_fee, txn_type, categories, sol_usd_price, fee_payer, transfers, success) ",
+            );
+
+            builder.push_values(chunk, |mut row, txn| {
+                row.push_bind(&txn.signature)
+                    .push_bind(&txn.sender)
+                    .push_bind(&txn.receiver)
+                    .push_bind(txn.sol_amount as i64)
+                    .push_bind(txn.fee as i64)
+                    .push_bind(txn.timestamp)
+                    .push_bind(&txn.prev_blockhash)
+                    .push_bind(txn.slot as i64)
+                    .push_bind(&txn.error)
+                    .push_bind(Json(&txn.log_messages))
+                    .push_bind(txn.compute_units_consumed.map(|units| units as i64))
+                    .push_bind(&txn.program)
+                    .push_bind(&txn.memo)
+                    .push_bind(
+                        txn.compute_budget
+                            .and_then(|budget| budget.unit_limit.map(i64::from)),
+                    )
+                    .push_bind(
+                        txn.compute_budget
+                            .and_then(|budget| budget.unit_price.map(|price| price as i64)),
+                    )
+                    .push_bind(txn.compute_budget.map(|budget| budget.priority_fee as i64))
+                    .push_bind(txn.txn_type.map(TransactionType::as_str))
+                    .push_bind(Json(&txn.categories))
+                    .push_bind(txn.sol_usd_price)
+                    .push_bind(&txn.fee_payer)
+                    .push_bind(Json(&txn.transfers))
+                    .push_bind(txn.success);
+            });
+            builder.push(" ON CONFLICT (signature) DO NOTHING RETURNING signature");
+
+            inserted.extend(
+                builder
+                    .build_query_scalar::<String>()
+                    .fetch_all(self)
+                    .await?,
+            );
+        }
+
+        Ok(inserted)
+    }
+
+    async fn get_by_signature(&self, signature: &str) -> anyhow::Result<Option<StoredTransaction>> {
+        let row = sqlx::query_as::<_, TransactionRow>(
+                "SELECT id, signature, sender, receiver, sol_amount, fee, timestamp, prev_blockhash, slot, error,
+                    log_messages, compute_units_consumed, program, memo,
+                    compute_unit_limit, compute_unit_price, priority_fee, txn_type,
+                    categories, sol_usd_price, commitment_status, fee_payer, transfers,
+                    success
+                FROM transactions WHERE signature = ?",
+            )
+            .bind(signature)
+            .fetch_optional(self)
+            .await?;
+
+        Ok(row.map(StoredTransaction::from))
+    }
+
+    async fn version(&self) -> anyhow::Result<i64> {
+        // transactions are only ever added on SQLite
+        let version = sqlx::query_scalar::<_, i64>("SELECT COALESCE(MAX(id), 0) FROM transactions")
+            .fetch_one(self)
+            .await?;
+
+        Ok(version)
+    }
+
+    async fn stored_signatures(&self, signatures: &[String]) -> anyhow::Result<HashSet<String>> {
+        let mut stored = HashSet::new();
+
+        for chunk in signatures.chunks(SQLITE_BATCH_ROWS) {
+            let mut builder = QueryBuilder::<Sqlite>::new(
+                "SELECT signature FROM transactions WHERE signature IN (",
+            );
+            let mut separated = builder.separated(", ");
+
+            for signature in chunk {
+                separated.push_bind(signature);
+            }
+
+            builder.push(")");
+
+            stored.extend(
+                builder
+                    .build_query_scalar::<String>()
+                    .fetch_all(self)
+                    .await?,
+            );
+        }
+
+        Ok(stored)
+    }
+
+    async fn query(&self, query: &TransactionQuery) -> anyhow::Result<Vec<StoredTransaction>> {
+        let rows = sqlx::query_as::<_, TransactionRow>(
+                "SELECT id, signature, sender, receiver, sol_amount, fee, timestamp, prev_blockhash, slot, error,
+                    log_messages, compute_units_consumed, program, memo,
+                    compute_unit_limit, compute_unit_price, priority_fee, txn_type,
+                    categories, sol_usd_price, commitment_status, fee_payer, transfers,
+                    success
+                FROM transactions
+                WHERE id > ?1
+                    AND (?2 IS NULL OR sender = ?2 OR receiver = ?2)
+                    AND (?3 IS NULL OR timestamp >= ?3)
+                    AND (?4 IS NULL OR timestamp <= ?4)
+                    AND (?5 IS NULL OR slot >= ?5)
+                    AND (?6 IS NULL OR slot <= ?6)
+                    AND (?7 IS NULL OR program = ?7)
+                    AND (?8 IS NULL OR instr(memo, ?8) > 0)
+                    AND (?9 IS NULL OR txn_type = ?9)
+                    AND (?10 IS NULL
+                        OR sender IN (SELECT value FROM json_each(?10))
+                        OR receiver IN (SELECT value FROM json_each(?10)))
+                    AND (?11 IS NULL OR EXISTS (SELECT 1 FROM json_each(categories) WHERE value = ?11))
+                    AND (?12 OR commitment_status <> 'dropped')
+                    AND (?13 IS NULL OR success = ?13)
+                ORDER BY id
+                LIMIT ?14",
+            )
+            .bind(query.after)
+            .bind(query.address.as_deref())
+            .bind(query.from)
+            .bind(query.to)
+            .bind(query.from_slot)
+            .bind(query.to_slot)
+            .bind(query.program.as_deref())
+            .bind(query.memo_contains.as_deref())
+            .bind(query.txn_type.map(TransactionType::as_str))
+            // SQLite has no arrays, so the addresses go in as a JSON array
+            .bind(query.addresses.as_ref().map(Json))
+            .bind(query.category.as_deref())
+            .bind(query.include_dropped)
+            .bind(query.success)
+            // a negative limit means no limit in SQLite
+            .bind(query.limit.unwrap_or(-1))
+            .fetch_all(self)
+            .await?;
+
+        Ok(rows.into_iter().map(StoredTransaction::from).collect())
+    }
+}
+
+/// Record how a transaction's fee split into burned and validator portions.
+pub async fn insert_fee_split(
+    pool: &Arc<PgPool>,
+    signature: &str,
+    split: &FeeSplit,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+            "INSERT INTO fee_burns (signature, epoch, base_fee, priority_fee, burned, to_validator)
+            VALUES ($1, $2, $3, $4, $5, $6)
+            ON CONFLICT (signature) DO NOTHING",
+            signature,
+            split.epoch as i64,
+            split.base_fee as i64,
+            split.priority_fee as i64,
+            split.burned as i64,
+            split.to_validator as i64
+        )
+        .execute(pool.as_ref())
+        .await?;
+
+    Ok(())
+}
+
+/// Fee totals for a single epoch.
+#[derive(Debug, Serialize)]
+pub struct EpochFeeBurn {
+    pub epoch: i64,
+    pub transactions: i64,
+    pub base_fee: i64,
+    pub priority_fee: i64,
+    pub burned: i64,
+    pub to_validator: i64,
+}
+
+/// Fees burned and paid to validators per epoch, within an optional epoch range.
+pub async fn get_fee_burn_by_epoch(
+    pool: &Arc<PgPool>,
+    from_epoch: Option<i64>,
+    to_epoch: Option<i64>,
+) -> anyhow::Result<Vec<EpochFeeBurn>> {
+    let rows = sqlx::query_as!(
+            EpochFeeBurn,
+            r#"SELECT epoch,
+                COUNT(*) AS "transactions!",
+                SUM(base_fee)::BIGINT AS "base_fee!",
+                SUM(priority_fee)::BIGINT AS "priority_fee!",
+                SUM(burned)::BIGINT AS "burned!",
+                SUM(to_validator)::BIGINT AS "to_validator!"
+            FROM fee_burns
+            WHERE ($1::BIGINT IS NULL OR epoch >= $1) AND ($2::BIGINT IS NULL OR epoch <= $2)
+            GROUP BY epoch
+            ORDER BY epoch"#,
+            from_epoch,
+            to_epoch
+        )
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(rows)
+}
+
+/// Store the compressed raw payload of a transaction, unless it is already archived.
+pub async fn insert_raw_transaction(
+    pool: &Arc<PgPool>,
+    signature: &str,
+    dictionary_id: Option<i32>,
+    payload: &[u8],
+) -> anyhow::Result<()> {
+    sqlx::query!(
+            "INSERT INTO raw_transactions (signature, dictionary_id, payload)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (signature) DO NOTHING",
+            signature,
+            dictionary_id,
+            payload
+        )
+        .execute(pool.as_ref())
+        .await?;
+
+    Ok(())
+}
+
+/// The compressed raw payload of a transaction and the dictionary it was compressed with.
+pub async fn get_raw_transaction(
+    pool: &Arc<PgPool>,
+    signature: &str,
+) -> anyhow::Result<Option<(Option<i32>, Vec<u8>)>> {
+    let row = sqlx::query!(
+            "SELECT dictionary_id, payload FROM raw_transactions WHERE signature = $1",
+            signature
+        )
+        .fetch_optional(pool.as_ref())
+        .await?;
+
+    Ok(row.map(|row| (row.dictionary_id, row.payload)))
+}
+
+/// Store a trained compression dictionary, returning its id.
+pub async fn insert_zstd_dictionary(pool: &Arc<PgPool>, dictionary: &[u8]) -> anyhow::Result<i32> {
+    let row = sqlx::query!(
+            "INSERT INTO zstd_dictionaries (dictionary) VALUES ($1) RETURNING id",
+            dictionary
+        )
+        .fetch_one(pool.as_ref())
+        .await?;
+
+    Ok(row.id)
+}
+
+/// A compression dictionary by id.
+pub async fn get_zstd_dictionary(pool: &Arc<PgPool>, id: i32) -> anyhow::Result<Option<Vec<u8>>> {
+    let row = sqlx::query!("SELECT dictionary FROM zstd_dictionaries WHERE id = $1", id)
+        .fetch_optional(pool.as_ref())
+        .await?;
+
+    Ok(row.map(|row| row.dictionary))
+}
+
+/// The most recently trained compression dictionary, with its id.
+pub async fn get_latest_zstd_dictionary(
+    pool: &Arc<PgPool>,
+) -> anyhow::Result<Option<(i32, Vec<u8>)>> {
+    let row = sqlx::query!("SELECT id, dictionary FROM zstd_dictionaries ORDER BY id DESC LIMIT 1")
+        .fetch_optional(pool.as_ref())
+        .await?;
+
+    Ok(row.map(|row| (row.id, row.dictionary)))
+}
+
+/// An address and how much it transacted, within the scope of a statistics query.
+#[derive(Debug, Clone, Serialize)]
+pub struct Counterparty {
+    pub address: String,
+    pub transaction_count: i64,
+    pub sol_amount: i64,
+}
+
+/// Totals over stored transactions, either all of them or those of one account.
+#[derive(Debug, Clone, Serialize)]
+pub struct TransactionStats {
+    pub transaction_count: i64,
+    pub total_sol_amount: i64,
+    pub total_fees: i64,
+    pub average_fee: f64,
+    /// SOL moved, valued at each transaction's SOL/USD price; unpriced transactions count as 0.
+    pub total_usd_volume: f64,
+    pub first_activity: Option<i64>,
+    pub last_activity: Option<i64>,
+    pub top_counterparties: Vec<Counterparty>,
+}
+
+/// Totals over stored transactions, restricted to those sent or received by `pubkey` if given.
+///
+/// For an account, the counterparties are the addresses on the other side of its transactions;
+/// overall, they are the most active addresses on either side. Everything is read from the
+/// counters maintained on insert, so the cost doesn't grow with the number of transactions.
+pub async fn get_transaction_stats(
+    pool: &Arc<PgPool>,
+    pubkey: Option<&str>,
+    top_counterparties: i64,
+) -> anyhow::Result<TransactionStats> {
+    let totals = sqlx::query!(
+            "SELECT transaction_count, total_sol_amount, total_fees, total_usd_volume, first_activity, last_activity
+            FROM address_stats
+            WHERE address = $1",
+            pubkey.unwrap_or(ALL_ADDRESSES)
+        )
+        .fetch_optional(pool.as_ref())
+        .await?;
+
+    let top_counterparties = match pubkey {
+        Some(pubkey) => sqlx::query_as!(
+                Counterparty,
+                "SELECT counterparty AS address, transaction_count, sol_amount FROM counterparty_stats
+                WHERE address = $1
+                ORDER BY transaction_count DESC, sol_amount DESC
+                LIMIT $2",
+                pubkey,
+                top_counterparties
+            )
+            .fetch_all(pool.as_ref())
+            .await?,
+        None => sqlx::query_as!(
+                Counterparty,
+                "SELECT address, transaction_count, total_sol_amount AS sol_amount FROM address_stats
+                WHERE address <> $1
+                ORDER BY transaction_count DESC, total_sol_amount DESC
+                LIMIT $2",
+                ALL_ADDRESSES,
+                top_counterparties
+            )
+            .fetch_all(pool.as_ref())
+            .await?,
+    };
+
+    let Some(totals) = totals else {
+        return Ok(TransactionStats {
+            transaction_count: 0,
+            total_sol_amount: 0,
+            total_fees: 0,
+            average_fee: 0.0,
+            total_usd_volume: 0.0,
+            first_activity: None,
+            last_activity: None,
+            top_counterparties,
+        });
+    };
+
+    Ok(TransactionStats {
+        transaction_count: totals.transaction_count,
+        total_sol_amount: totals.total_sol_amount,
+        total_fees: totals.total_fees,
+        average_fee: average(totals.total_fees, totals.transaction_count),
+        total_usd_volume: totals.total_usd_volume,
+        first_activity: totals.first_activity,
+        last_activity: totals.last_activity,
+        top_counterparties,
+    })
+}
+
+fn average(total: i64, count: i64) -> f64 {
+    if count == 0 {
+        return 0.0;
+    }
+
+    total as f64 / count as f64
+}
+
+/// How many stored transactions an account sent or received recently.
+#[derive(Debug, Clone, Serialize)]
+pub struct ActivityCounts {
+    pub last_24h: i64,
+    pub last_7d: i64,
+    pub last_30d: i64,
+    /// Unix seconds of its latest stored transaction.
+    pub last_activity: Option<i64>,
+}
+
+/// Count the stored transactions of `pubkey` in the day, week and 30 days before `now`.
+pub async fn get_activity_counts(
+    pool: &Arc<PgPool>,
+    pubkey: &str,
+    now: i64,
+) -> anyhow::Result<ActivityCounts> {
+    let counts = sqlx::query_as!(
+            ActivityCounts,
+            r#"SELECT
+                COUNT(*) FILTER (WHERE timestamp > $2 - 86400) AS "last_24h!",
+                COUNT(*) FILTER (WHERE timestamp > $2 - 604800) AS "last_7d!",
+                COUNT(*) FILTER (WHERE timestamp > $2 - 2592000) AS "last_30d!",
+                MAX(timestamp) AS last_activity
+            FROM transactions
+            WHERE sender = $1 OR receiver = $1"#,
+            pubkey,
+            now
+        )
+        .fetch_one(pool.as_ref())
+        .await?;
+
+    Ok(counts)
+}
+
+/// Everything an address exchanged with one other address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CounterpartySummary {
+    pub address: String,
+    pub transaction_count: i64,
+    /// Lamports sent to the counterparty.
+    pub total_sent: i64,
+    /// Lamports received from the counterparty.
+    pub total_received: i64,
+    /// Unix seconds of their latest stored transaction.
+    pub last_interaction: i64,
+}
+
+/// Every address `pubkey` has stored transactions with, optionally within a time range, most
+/// transactions first, `limit` at a time from `offset`. Transactions dropped by a fork aren't
+/// counted.
+pub async fn get_counterparties(
+    pool: &Arc<PgPool>,
+    pubkey: &str,
+    from: Option<i64>,
+    to: Option<i64>,
+    limit: i64,
+    offset: i64,
+) -> anyhow::Result<Vec<CounterpartySummary>> {
+    let counterparties = sqlx::query_as!(
+            CounterpartySummary,
+            r#"SELECT counterparty AS "address!",
+                COUNT(*) AS "transaction_count!",
+                SUM(sent)::BIGINT AS "total_sent!",
+                SUM(received)::BIGINT AS "total_received!",
+                MAX(timestamp) AS "last_interaction!"
+            FROM (
+                SELECT CASE WHEN sender = $1 THEN receiver ELSE sender END AS counterparty,
+                    CASE WHEN sender = $1 THEN sol_amount ELSE 0 END AS sent,
+                    CASE WHEN sender = $1 THEN 0 ELSE sol_amount END AS received,
+                    timestamp
+                FROM transactions
+                WHERE (sender = $1 OR receiver = $1)
+                    AND sender <> receiver
+                    AND commitment_status <> 'dropped'
+                    AND ($2::BIGINT IS NULL OR timestamp >= $2)
+                    AND ($3::BIGINT IS NULL OR timestamp <= $3)
+            ) interactions
+            GROUP BY counterparty
+            ORDER BY COUNT(*) DESC, counterparty
+            LIMIT $4 OFFSET $5"#,
+            pubkey,
+            from,
+            to,
+            limit,
+            offset
+        )
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(counterparties)
+}
+
+/// Record an account's balance at a point in time.
+pub async fn insert_balance_snapshot(
+    pool: &Arc<PgPool>,
+    snapshot: &BalanceSnapshot,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+            "INSERT INTO balances (pubkey, lamports, token_mint, token_amount, recorded_at)
+            VALUES ($1, $2, $3, $4, $5)",
+            snapshot.pubkey,
+            snapshot.lamports as i64,
+            snapshot.token_mint,
+            snapshot.token_amount.map(|amount| amount as i64),
+            snapshot.recorded_at
+        )
+        .execute(pool.as_ref())
+        .await?;
+
+    Ok(())
+}
+
+/// An account's most recently recorded balance.
+pub async fn get_latest_balance(
+    pool: &Arc<PgPool>,
+    pubkey: &str,
+) -> anyhow::Result<Option<BalanceSnapshot>> {
+    let row = sqlx::query!(
+            "SELECT pubkey, lamports, token_mint, token_amount, recorded_at FROM balances
+            WHERE pubkey = $1 ORDER BY recorded_at DESC, id DESC LIMIT 1",
+            pubkey
+        )
+        .fetch_optional(pool.as_ref())
+        .await?;
+
+    Ok(row.map(|row| BalanceSnapshot {
+        pubkey: row.pubkey,
+        lamports: row.lamports as u64,
+        token_mint: row.token_mint,
+        token_amount: row.token_amount.map(|amount| amount as u64),
+        recorded_at: row.recorded_at,
+    }))
+}
+
+/// Balance snapshots of an account within `[from, to]` (unix seconds), oldest first.
+pub async fn get_balance_history(
+    pool: &Arc<PgPool>,
+    pubkey: &str,
+    from: Option<i64>,
+    to: Option<i64>,
+    limit: Option<i64>,
+) -> anyhow::Result<Vec<BalanceSnapshot>> {
+    let rows = sqlx::query!(
+            "SELECT pubkey, lamports, token_mint, token_amount, recorded_at FROM balances
+            WHERE pubkey = $1
+                AND ($2::BIGINT IS NULL OR recorded_at >= $2)
+                AND ($3::BIGINT IS NULL OR recorded_at <= $3)
+            ORDER BY recorded_at, id
+            LIMIT $4",
+            pubkey,
+            from,
+            to,
+            limit
+        )
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| BalanceSnapshot {
+            pubkey: row.pubkey,
+            lamports: row.lamports as u64,
+            token_mint: row.token_mint,
+            token_amount: row.token_amount.map(|amount| amount as u64),
+            recorded_at: row.recorded_at,
+        })
+        .collect())
+}
+
+/// An SPL token balance of a watched address, as stored in `token_balances`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TokenBalance {
+    /// The token account.
+    pub account: String,
+    pub mint: String,
+    /// Raw amount, in the mint's smallest unit.
+    pub amount: i64,
+    pub decimals: i16,
+    /// Unix seconds.
+    pub recorded_at: i64,
+}
+
+/// Record the token accounts `owner` holds, as fetched at `recorded_at`: a row for each account
+/// whose balance changed since it was last recorded, and a zero balance for each previously
+/// recorded account `holdings` doesn't list, i.e. that was closed. Returns the rows added.
+/// Accounts holding more than `i64::MAX` of the smallest unit can't be stored, so they're logged
+/// and left as last recorded.
+pub async fn record_token_balances(
+    pool: &Arc<PgPool>,
+    owner: &str,
+    holdings: &[TokenHolding],
+    recorded_at: i64,
+) -> anyhow::Result<u64> {
+    // every held account, so those that can't be stored aren't taken for closed
+    let held = holdings
+        .iter()
+        .map(|holding| holding.account.clone())
+        .collect::<Vec<_>>();
+
+    let storable = holdings
+        .iter()
+        .filter_map(|holding| match i64::try_from(holding.amount) {
+            Ok(amount) => Some((holding, amount)),
+            Err(_) => {
+                warn!(
+                    "Not recording the balance of token account {}: {} of {} is too large to store",
+                    holding.account, holding.amount, holding.mint
+                );
+                None
+            }
+        })
+        .collect::<Vec<_>>();
+
+    let accounts = storable
+        .iter()
+        .map(|(holding, _)| holding.account.clone())
+        .collect::<Vec<_>>();
+    let mints = storable
+        .iter()
+        .map(|(holding, _)| holding.mint.clone())
+        .collect::<Vec<_>>();
+    let amounts = storable
+        .iter()
+        .map(|(_, amount)| *amount)
+        .collect::<Vec<_>>();
+    let decimals = storable
+        .iter()
+        .map(|(holding, _)| i16::from(holding.decimals))
+        .collect::<Vec<_>>();
+
+    let mut tx = pool.begin().await?;
+
+    let changed = sqlx::query!(
+            "INSERT INTO token_balances (owner, account, mint, amount, decimals, recorded_at)
+            SELECT $1, fresh.account, fresh.mint, fresh.amount, fresh.decimals, $6
+            FROM UNNEST($2::VARCHAR[], $3::VARCHAR[], $4::BIGINT[], $5::SMALLINT[])
+                AS fresh(account, mint, amount, decimals)
+            LEFT JOIN LATERAL (
+                SELECT amount FROM token_balances
+                WHERE owner = $1 AND account = fresh.account
+                ORDER BY id DESC LIMIT 1
+            ) latest ON TRUE
+            WHERE latest.amount IS DISTINCT FROM fresh.amount",
+            owner,
+            &accounts,
+            &mints,
+            &amounts,
+            &decimals,
+            recorded_at
+        )
+        .execute(&mut *tx)
+        .await?;
+
+    let closed = sqlx::query!(
+            "INSERT INTO token_balances (owner, account, mint, amount, decimals, recorded_at)
+            SELECT owner, account, mint, 0, decimals, $3
+            FROM (
+                SELECT DISTINCT ON (account) owner, account, mint, amount, decimals
+                FROM token_balances
+                WHERE owner = $1
+                ORDER BY account, id DESC
+            ) latest
+            WHERE latest.amount <> 0 AND NOT (latest.account = ANY($2))",
+            owner,
+            &held,
+            recorded_at
+        )
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(changed.rows_affected() + closed.rows_affected())
+}
+
+/// The latest recorded balance of every token account `owner` holds, by mint. Closed and empty
+/// accounts are left out.
+pub async fn get_current_token_balances(
+    pool: &Arc<PgPool>,
+    owner: &str,
+) -> anyhow::Result<Vec<TokenBalance>> {
+    let balances = sqlx::query_as!(
+            TokenBalance,
+            "SELECT account, mint, amount, decimals, recorded_at
+            FROM (
+                SELECT DISTINCT ON (account) account, mint, amount, decimals, recorded_at
+                FROM token_balances
+                WHERE owner = $1
+                ORDER BY account, id DESC
+            ) latest
+            WHERE amount <> 0
+            ORDER BY mint, account",
+            owner
+        )
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(balances)
+}
+
+/// Recorded balance changes of the token accounts `owner` holds within `[from, to]` (unix
+/// seconds), those of `mint` only if given, oldest first.
+pub async fn get_token_balance_history(
+    pool: &Arc<PgPool>,
+    owner: &str,
+    mint: Option<&str>,
+    from: Option<i64>,
+    to: Option<i64>,
+    limit: Option<i64>,
+) -> anyhow::Result<Vec<TokenBalance>> {
+    let history = sqlx::query_as!(
+            TokenBalance,
+            "SELECT account, mint, amount, decimals, recorded_at FROM token_balances
+            WHERE owner = $1
+                AND ($2::VARCHAR IS NULL OR mint = $2)
+                AND ($3::BIGINT IS NULL OR recorded_at >= $3)
+                AND ($4::BIGINT IS NULL OR recorded_at <= $4)
+            ORDER BY recorded_at, id
+            LIMIT $5",
+            owner,
+            mint,
+            from,
+            to,
+            limit
+        )
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(history)
+}
+
+/// Counter and rollup rows under this address cover all transactions rather than a single
+/// address.
+pub const ALL_ADDRESSES: &str = "*";
+
+/// Fold stored transactions into the statistics counters and the volume rollups, or take them
+/// back out with `sign` -1. Transactions dropped by a fork were taken out when dropped, so they're
+/// skipped.
+///
+/// Each transaction counts towards its sender, its receiver (once for a self-transfer) and
+/// `ALL_ADDRESSES`. Rows are upserted in key order, so concurrent batches lock them in the same
+/// order and can't deadlock. First and last activity are only moved forward; callers taking
+/// transactions out recompute them.
+async fn fold_into_aggregates(
+    conn: &mut PgConnection,
+    signatures: &[String],
+    sign: i64,
+) -> anyhow::Result<()> {
+    if signatures.is_empty() {
+        return Ok(());
+    }
+
+    sqlx::query!(
+            "WITH folded AS (
+                SELECT sender, receiver, sol_amount, fee, timestamp,
+                    COALESCE(sol_amount * sol_usd_price / 1e9, 0) AS usd_volume
+                FROM transactions
+                WHERE signature = ANY($1) AND commitment_status <> 'dropped'
+            ), sides AS (
+                SELECT sender AS address, sol_amount, fee, timestamp, usd_volume FROM folded
+                UNION ALL
+                SELECT receiver, sol_amount, fee, timestamp, usd_volume FROM folded WHERE receiver <> sender
+                UNION ALL
+                SELECT $2::VARCHAR, sol_amount, fee, timestamp, usd_volume FROM folded
+            )
+            INSERT INTO address_stats
+                (address, transaction_count, total_sol_amount, total_fees, first_activity, last_activity, total_usd_volume)
+            SELECT address, $3 * COUNT(*), $3 * SUM(sol_amount), $3 * SUM(fee), MIN(timestamp), MAX(timestamp),
+                $3 * SUM(usd_volume)
+            FROM sides
+            GROUP BY address
+            ORDER BY address
+            ON CONFLICT (address) DO UPDATE SET
+                transaction_count = address_stats.transaction_count + EXCLUDED.transaction_count,
+                total_sol_amount = address_stats.total_sol_amount + EXCLUDED.total_sol_amount,
+                total_fees = address_stats.total_fees + EXCLUDED.total_fees,
+                total_usd_volume = address_stats.total_usd_volume + EXCLUDED.total_usd_volume,
+                first_activity = CASE WHEN $3 > 0
+                    THEN LEAST(address_stats.first_activity, EXCLUDED.first_activity)
+                    ELSE address_stats.first_activity END,
+                last_activity = CASE WHEN $3 > 0
+                    THEN GREATEST(address_stats.last_activity, EXCLUDED.last_activity)
+                    ELSE address_stats.last_activity END",
+            signatures,
+            ALL_ADDRESSES,
+            sign
+        )
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query!(
+            "WITH folded AS (
+                SELECT sender, receiver, sol_amount FROM transactions
+                WHERE signature = ANY($1) AND commitment_status <> 'dropped'
+            ), pairs AS (
+                SELECT sender AS address, receiver AS counterparty, sol_amount FROM folded
+                UNION ALL
+                SELECT receiver, sender, sol_amount FROM folded WHERE receiver <> sender
+            )
+            INSERT INTO counterparty_stats (address, counterparty, transaction_count, sol_amount)
+            SELECT address, counterparty, $2 * COUNT(*), $2 * SUM(sol_amount)
+            FROM pairs
+            GROUP BY address, counterparty
+            ORDER BY address, counterparty
+            ON CONFLICT (address, counterparty) DO UPDATE SET
+                transaction_count = counterparty_stats.transaction_count + EXCLUDED.transaction_count,
+                sol_amount = counterparty_stats.sol_amount + EXCLUDED.sol_amount",
+            signatures,
+            sign
+        )
+        .execute(&mut *conn)
+        .await?;
+
+    for interval in RollupInterval::ALL {
+        sqlx::query!(
+                "WITH folded AS (
+                    SELECT sender, receiver, sol_amount, fee, timestamp,
+                        COALESCE(sol_amount * sol_usd_price / 1e9, 0) AS usd_volume
+                    FROM transactions
+                    WHERE signature = ANY($1) AND commitment_status <> 'dropped'
+                ), sides AS (
+                    SELECT sender AS address, sol_amount, fee, timestamp, usd_volume FROM folded
+                    UNION ALL
+                    SELECT receiver, sol_amount, fee, timestamp, usd_volume FROM folded WHERE receiver <> sender
+                    UNION ALL
+                    SELECT $4::VARCHAR, sol_amount, fee, timestamp, usd_volume FROM folded
+                )
+                INSERT INTO transaction_rollups
+                    (granularity, bucket, address, transaction_count, sol_volume, fee_sum, usd_volume)
+                SELECT $2::VARCHAR, timestamp - timestamp % $3::BIGINT, address, $5 * COUNT(*), $5 * SUM(sol_amount), $5 * SUM(fee),
+                    $5 * SUM(usd_volume)
+                FROM sides
+                -- served from continuous aggregates instead in TimescaleDB mode
+                WHERE NOT EXISTS (SELECT 1 FROM storage_features WHERE feature = $6)
+                GROUP BY 2, 3
+                ORDER BY 3, 2
+                ON CONFLICT (granularity, address, bucket) DO UPDATE SET
+                    transaction_count = transaction_rollups.transaction_count + EXCLUDED.transaction_count,
+                    sol_volume = transaction_rollups.sol_volume + EXCLUDED.sol_volume,
+                    fee_sum = transaction_rollups.fee_sum + EXCLUDED.fee_sum,
+                    usd_volume = transaction_rollups.usd_volume + EXCLUDED.usd_volume",
+                signatures,
+                interval.as_str(),
+                interval.seconds(),
+                ALL_ADDRESSES,
+                sign,
+                TIMESCALE_FEATURE
+            )
+            .execute(&mut *conn)
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// A transaction waiting in the outbox to be published.
+#[derive(Debug, Clone)]
+pub struct OutboxEntry {
+    pub id: i64,
+    pub signature: String,
+    /// The transaction as JSON.
+    pub payload: Vec<u8>,
+}
+
+/// Up to `limit` outbox entries after `after`, oldest first.
+pub async fn get_outbox_entries(
+    pool: &PgPool,
+    after: i64,
+    limit: i64,
+) -> anyhow::Result<Vec<OutboxEntry>> {
+    let entries = sqlx::query_as!(
+            OutboxEntry,
+            "SELECT id, signature, payload FROM sink_outbox WHERE id > $1 ORDER BY id LIMIT $2",
+            after,
+            limit
+        )
+        .fetch_all(pool)
+        .await?;
+
+    Ok(entries)
+}
+
+/// Drop the outbox entries up to and including `through`, once they're published.
+pub async fn delete_outbox_entries(pool: &PgPool, through: i64) -> anyhow::Result<u64> {
+    let deleted = sqlx::query!("DELETE FROM sink_outbox WHERE id <= $1", through)
+        .execute(pool)
+        .await?
+        .rows_affected();
+
+    Ok(deleted)
+}
+
+/// Bump the version of the stored transactions, after they or their labels changed.
+async fn bump_version(conn: impl PgExecutor<'_>) -> anyhow::Result<()> {
+    sqlx::query!("UPDATE transactions_version SET version = version + 1")
+        .execute(conn)
+        .await?;
+
+    Ok(())
+}
+
+/// Delete transactions and take them out of the counters and rollups, dropping rows that no
+/// longer count anything. Returns the number of transactions deleted.
+async fn delete_transactions(conn: &mut PgConnection, signatures: &[String]) -> anyhow::Result<u64> {
+    if signatures.is_empty() {
+        return Ok(0);
+    }
+
+    let addresses = sqlx::query!(
+            r#"SELECT DISTINCT address AS "address!" FROM (
+                SELECT sender AS address FROM transactions WHERE signature = ANY($1)
+                UNION ALL
+                SELECT receiver FROM transactions WHERE signature = ANY($1)
+            ) sides"#,
+            signatures
+        )
+        .fetch_all(&mut *conn)
+        .await?
+        .into_iter()
+        .map(|row| row.address)
+        .collect::<Vec<_>>();
+
+    fold_into_aggregates(conn, signatures, -1).await?;
+
+    let deleted = sqlx::query!(
+            "DELETE FROM transactions WHERE signature = ANY($1)",
+            signatures
+        )
+        .execute(&mut *conn)
+        .await?
+        .rows_affected();
+
+    if deleted > 0 {
+        bump_version(&mut *conn).await?;
+    }
+
+    sqlx::query!(
+            "DELETE FROM address_stats WHERE address = ANY($1) AND transaction_count = 0",
+            &addresses
+        )
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query!(
+            "DELETE FROM counterparty_stats
+            WHERE (address = ANY($1) OR counterparty = ANY($1)) AND transaction_count = 0",
+            &addresses
+        )
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query!(
+            "DELETE FROM transaction_rollups
+            WHERE (address = ANY($1) OR address = $2) AND transaction_count = 0",
+            &addresses,
+            ALL_ADDRESSES
+        )
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query!(
+            "UPDATE address_stats SET
+                first_activity = (
+                    SELECT MIN(timestamp) FROM transactions
+                    WHERE sender = address_stats.address OR receiver = address_stats.address
+                ),
+                last_activity = (
+                    SELECT MAX(timestamp) FROM transactions
+                    WHERE sender = address_stats.address OR receiver = address_stats.address
+                )
+            WHERE address = ANY($1)",
+            &addresses
+        )
+        .execute(&mut *conn)
+        .await?;
+
+    sqlx::query!(
+            "UPDATE address_stats SET
+                first_activity = (SELECT MIN(timestamp) FROM transactions),
+                last_activity = (SELECT MAX(timestamp) FROM transactions)
+            WHERE address = $1",
+            ALL_ADDRESSES
+        )
+        .execute(&mut *conn)
+        .await?;
+
+    Ok(deleted)
+}
+
+/// A stored transaction that isn't finalized yet.
+#[derive(Debug, Clone)]
+pub struct UnfinalizedTransaction {
+    pub signature: String,
+    pub slot: i64,
+}
+
+/// Up to `limit` transactions stored while only confirmed, oldest first.
+pub async fn get_unfinalized_transactions(
+    pool: &Arc<PgPool>,
+    limit: i64,
+) -> anyhow::Result<Vec<UnfinalizedTransaction>> {
+    let rows = sqlx::query_as!(
+            UnfinalizedTransaction,
+            "SELECT signature, slot FROM transactions
+            WHERE commitment_status = 'confirmed'
+            ORDER BY id
+            LIMIT $1",
+            limit
+        )
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(rows)
+}
+
+/// Mark confirmed transactions finalized. Returns the number of transactions updated.
+pub async fn finalize_transactions(
+    pool: &Arc<PgPool>,
+    signatures: &[String],
+) -> anyhow::Result<u64> {
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query!(
+            "UPDATE transactions SET commitment_status = 'finalized'
+            WHERE signature = ANY($1) AND commitment_status = 'confirmed'",
+            signatures
+        )
+        .execute(&mut *tx)
+        .await?;
+
+    if result.rows_affected() > 0 {
+        bump_version(&mut *tx).await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Mark confirmed transactions dropped by a fork and take them out of the counters and rollups.
+/// The rows are kept, for `include_dropped` queries. Returns the number of transactions dropped.
+pub async fn drop_transactions(pool: &Arc<PgPool>, signatures: &[String]) -> anyhow::Result<u64> {
+    if signatures.is_empty() {
+        return Ok(0);
+    }
+
+    let mut tx = pool.begin().await?;
+
+    // locked, so a concurrent run can't take them out of the aggregates twice
+    let dropped = sqlx::query_scalar!(
+            "SELECT signature FROM transactions
+            WHERE signature = ANY($1) AND commitment_status = 'confirmed'
+            ORDER BY signature
+            FOR UPDATE",
+            signatures
+        )
+        .fetch_all(&mut *tx)
+        .await?;
+
+    fold_into_aggregates(&mut tx, &dropped, -1).await?;
+
+    sqlx::query!(
+            "UPDATE transactions SET commitment_status = 'dropped' WHERE signature = ANY($1)",
+            &dropped
+        )
+        .execute(&mut *tx)
+        .await?;
+
+    if !dropped.is_empty() {
+        bump_version(&mut *tx).await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(dropped.len() as u64)
+}
+
+/// Totals for one time bucket.
+#[derive(Debug, Serialize)]
+pub struct VolumeBucket {
+    /// Start of the bucket, in unix seconds.
+    pub bucket: i64,
+    pub transaction_count: i64,
+    pub sol_volume: i64,
+    pub fee_sum: i64,
+    /// SOL volume valued at each transaction's SOL/USD price.
+    pub usd_volume: f64,
+}
+
+/// Rolled-up volume of `address` (or `ALL_ADDRESSES`) with buckets starting within `[from, to]`.
+pub async fn get_volume_rollups(
+    pool: &Arc<PgPool>,
+    granularity: &str,
+    address: &str,
+    from: Option<i64>,
+    to: Option<i64>,
+) -> anyhow::Result<Vec<VolumeBucket>> {
+    let rows = sqlx::query_as!(
+            VolumeBucket,
+            r#"SELECT bucket AS "bucket!", transaction_count AS "transaction_count!",
+                sol_volume AS "sol_volume!", fee_sum AS "fee_sum!", usd_volume AS "usd_volume!"
+            FROM volume_rollups
+            WHERE granularity = $1
+                AND address = $2
+                AND ($3::BIGINT IS NULL OR bucket >= $3)
+                AND ($4::BIGINT IS NULL OR bucket <= $4)
+            ORDER BY bucket"#,
+            granularity,
+            address,
+            from,
+            to
+        )
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(rows)
+}
+
+/// Priority fee distribution of the transactions in one time bucket, in lamports.
+#[derive(Debug, Serialize)]
+pub struct PriorityFeeBucket {
+    /// Start of the bucket, in unix seconds.
+    pub bucket: i64,
+    pub transaction_count: i64,
+    /// Share of the transactions that paid any priority fee, from 0 to 1.
+    pub prioritized: f64,
+    pub p50: f64,
+    pub p75: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: i64,
+}
+
+/// Priority fee percentiles per `bucket_secs` bucket of the transactions within `[from, to]`,
+/// those of `address` only if given. Transactions stored before compute budgets were parsed are
+/// left out.
+pub async fn get_priority_fee_percentiles(
+    pool: &Arc<PgPool>,
+    bucket_secs: i64,
+    address: Option<&str>,
+    from: i64,
+    to: i64,
+) -> anyhow::Result<Vec<PriorityFeeBucket>> {
+    let rows = sqlx::query_as!(
+            PriorityFeeBucket,
+            r#"SELECT timestamp - timestamp % $1 AS "bucket!",
+                COUNT(*) AS "transaction_count!",
+                AVG(CASE WHEN priority_fee > 0 THEN 1.0 ELSE 0.0 END)::FLOAT8 AS "prioritized!",
+                percentile_cont(0.5) WITHIN GROUP (ORDER BY priority_fee) AS "p50!",
+                percentile_cont(0.75) WITHIN GROUP (ORDER BY priority_fee) AS "p75!",
+                percentile_cont(0.9) WITHIN GROUP (ORDER BY priority_fee) AS "p90!",
+                percentile_cont(0.99) WITHIN GROUP (ORDER BY priority_fee) AS "p99!",
+                MAX(priority_fee) AS "max!"
+            FROM transactions
+            WHERE timestamp >= $2
+                AND timestamp <= $3
+                AND priority_fee IS NOT NULL
+                AND ($4::VARCHAR IS NULL OR sender = $4 OR receiver = $4)
+            GROUP BY 1
+            ORDER BY 1"#,
+            bucket_secs,
+            from,
+            to,
+            address
+        )
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(rows)
+}
+
+/// Transactions and SOL volume of an address in one cell of an activity heatmap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ActivityCell {
+    /// Day of the week, from 0 (Monday) to 6 (Sunday), in UTC.
+    pub weekday: i64,
+    /// Hour of the day (UTC) by hour, or days since the unix epoch by day.
+    pub bucket: i64,
+    pub transaction_count: i64,
+    /// Lamports sent and received.
+    pub sol_volume: i64,
+}
+
+/// Activity of `pubkey` in the stored transactions of `[from, to)`, in unix seconds, per weekday
+/// and hour of the day if `by_hour`, per day otherwise. Transactions dropped by a fork aren't
+/// counted.
+pub async fn get_activity_cells(
+    pool: &Arc<PgPool>,
+    pubkey: &str,
+    by_hour: bool,
+    from: i64,
+    to: i64,
+) -> anyhow::Result<Vec<ActivityCell>> {
+    let cells = sqlx::query_as!(
+            ActivityCell,
+            r#"SELECT (timestamp / 86400 + 3) % 7 AS "weekday!",
+                CASE WHEN $2 THEN timestamp % 86400 / 3600 ELSE timestamp / 86400 END AS "bucket!",
+                COUNT(*) AS "transaction_count!",
+                COALESCE(SUM(sol_amount), 0)::BIGINT AS "sol_volume!"
+            FROM transactions
+            WHERE (sender = $1 OR receiver = $1)
+                AND commitment_status <> 'dropped'
+                AND timestamp >= $3 AND timestamp < $4
+            GROUP BY 1, 2
+            ORDER BY 2, 1"#,
+            pubkey,
+            by_hour,
+            from,
+            to
+        )
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(cells)
+}
+
+/// Size and bloat of a table, as tracked by Postgres' statistics collector.
+#[derive(Debug, Serialize)]
+pub struct TableHealth {
+    pub table: String,
+    pub live_tuples: i64,
+    pub dead_tuples: i64,
+    /// Share of dead tuples, the main bloat indicator.
+    pub dead_ratio: f64,
+    /// Size including indexes and TOAST, in bytes.
+    pub total_bytes: i64,
+    /// Last (auto)vacuum and (auto)analyze, in unix seconds.
+    pub last_vacuum: Option<i64>,
+    pub last_analyze: Option<i64>,
+}
+
+/// Size, bloat and maintenance timestamps of `tables`.
+pub async fn get_table_health(
+    pool: &Arc<PgPool>,
+    tables: &[String],
+) -> anyhow::Result<Vec<TableHealth>> {
+    let rows = sqlx::query_as!(
+            TableHealth,
+            r#"SELECT
+                relname::TEXT AS "table!",
+                n_live_tup AS "live_tuples!",
+                n_dead_tup AS "dead_tuples!",
+                COALESCE(n_dead_tup::FLOAT8 / NULLIF(n_live_tup + n_dead_tup, 0), 0) AS "dead_ratio!",
+                pg_total_relation_size(relid) AS "total_bytes!",
+                EXTRACT(EPOCH FROM GREATEST(last_vacuum, last_autovacuum))::BIGINT AS last_vacuum,
+                EXTRACT(EPOCH FROM GREATEST(last_analyze, last_autoanalyze))::BIGINT AS last_analyze
+            FROM pg_stat_user_tables
+            WHERE relname::TEXT = ANY($1::TEXT[])
+            ORDER BY relname"#,
+            tables
+        )
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(rows)
+}
+
+/// Quote a table name for use in a statement that can't take it as a parameter.
+fn quote_ident(name: &str) -> String {
+    format!("\"{}\"", name.replace('"', "\"\""))
+}
+
+/// Reclaim dead tuples and refresh planner statistics.
+pub async fn vacuum_analyze_table(pool: &Arc<PgPool>, table: &str) -> anyhow::Result<()> {
+    // VACUUM can't run inside a transaction, so use the simple query protocol
+    sqlx::raw_sql(&format!("VACUUM (ANALYZE) {}", quote_ident(table)))
+        .execute(pool.as_ref())
+        .await?;
+
+    Ok(())
+}
+
+/// Rebuild the indexes of a table without blocking writes to it.
+pub async fn reindex_table(pool: &Arc<PgPool>, table: &str) -> anyhow::Result<()> {
+    sqlx::raw_sql(&format!("REINDEX TABLE CONCURRENTLY {}", quote_ident(table)))
+        .execute(pool.as_ref())
+        .await?;
+
+    Ok(())
+}
+
+/// Store a snapshot of a watched account unless its data, owner and lamports are unchanged
+/// since the last stored version. Returns whether a new version was stored.
+pub async fn insert_account_snapshot_if_changed(
+    pool: &Arc<PgPool>,
+    snapshot: &AccountSnapshot,
+) -> anyhow::Result<bool> {
+    let result = sqlx::query!(
+            "INSERT INTO account_snapshots (pubkey, owner, lamports, data, slot, recorded_at)
+            SELECT $1, $2, $3, $4, $5, $6
+            WHERE NOT EXISTS (
+                SELECT 1 FROM (
+                    SELECT owner, lamports, data FROM account_snapshots
+                    WHERE pubkey = $1 ORDER BY id DESC LIMIT 1
+                ) latest
+                WHERE latest.owner = $2 AND latest.lamports = $3 AND latest.data = $4
+            )",
+            snapshot.pubkey,
+            snapshot.owner,
+            snapshot.lamports as i64,
+            snapshot.data,
+            snapshot.slot as i64,
+            snapshot.recorded_at
+        )
+        .execute(pool.as_ref())
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Stored versions of a watched account, newest first.
+pub async fn get_account_snapshots(
+    pool: &Arc<PgPool>,
+    pubkey: &str,
+    limit: i64,
+) -> anyhow::Result<Vec<AccountSnapshot>> {
+    let rows = sqlx::query!(
+            "SELECT pubkey, owner, lamports, data, slot, recorded_at FROM account_snapshots
+            WHERE pubkey = $1 ORDER BY id DESC LIMIT $2",
+            pubkey,
+            limit
+        )
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(rows
+        .into_iter()
+        .map(|row| AccountSnapshot {
+            pubkey: row.pubkey,
+            owner: row.owner,
+            lamports: row.lamports as u64,
+            data: row.data,
+            slot: row.slot as u64,
+            recorded_at: row.recorded_at,
+        })
+        .collect())
+}
+
+/// Persist a layout registered through the API, replacing any with the same program and name.
+pub async fn upsert_account_layout(
+    pool: &Arc<PgPool>,
+    layout: &AccountLayout,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+            "INSERT INTO account_layouts (program, name, definition)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (program, name) DO UPDATE SET definition = EXCLUDED.definition",
+            layout.program,
+            layout.name,
+            serde_json::to_value(layout)?
+        )
+        .execute(pool.as_ref())
+        .await?;
+
+    Ok(())
+}
+
+/// Layouts registered through the API.
+pub async fn get_account_layouts(pool: &Arc<PgPool>) -> anyhow::Result<Vec<AccountLayout>> {
+    let rows = sqlx::query!("SELECT definition FROM account_layouts")
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    rows.into_iter()
+        .map(|row| Ok(serde_json::from_value(row.definition)?))
+        .collect()
+}
+
+/// Store the label of an address, replacing any stored before.
+pub async fn upsert_label(
+    pool: &Arc<PgPool>,
+    label: &Label,
+    updated_at: i64,
+) -> anyhow::Result<()> {
+    let mut tx = pool.begin().await?;
+
+    sqlx::query!(
+            "INSERT INTO labels (pubkey, label, tags, updated_at)
+            VALUES ($1, $2, $3, $4)
+            ON CONFLICT (pubkey) DO UPDATE SET label = $2, tags = $3, updated_at = $4",
+            label.pubkey,
+            label.label,
+            &label.tags,
+            updated_at
+        )
+        .execute(&mut *tx)
+        .await?;
+
+    // labels are part of the transactions the API serves
+    bump_version(&mut *tx).await?;
+
+    tx.commit().await?;
+
+    Ok(())
+}
+
+/// Labels registered through the API.
+pub async fn get_labels(pool: &Arc<PgPool>) -> anyhow::Result<Vec<Label>> {
+    let labels = sqlx::query_as!(Label, "SELECT pubkey, label, tags FROM labels")
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(labels)
+}
+
+/// Labels matching `pattern`, a case-insensitive `LIKE` pattern, most similar to `text` first.
+pub async fn search_labels(
+    pool: &Arc<PgPool>,
+    pattern: &str,
+    text: &str,
+    limit: i64,
+) -> anyhow::Result<Vec<Label>> {
+    let labels = sqlx::query_as!(
+            Label,
+            "SELECT pubkey, label, tags FROM labels
+            WHERE label ILIKE $1
+            ORDER BY similarity(label, $2) DESC, label
+            LIMIT $3",
+            pattern,
+            text,
+            limit
+        )
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(labels)
+}
+
+/// An address of the stored transactions, as found by search.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AddressMatch {
+    pub address: String,
+    pub transaction_count: i64,
+    /// Unix seconds of its latest stored transaction.
+    pub last_activity: Option<i64>,
+}
+
+/// Addresses of the stored transactions starting with `prefix`, `prefix` itself first, then most
+/// transactions first.
+pub async fn search_addresses(
+    pool: &Arc<PgPool>,
+    prefix: &str,
+    limit: i64,
+) -> anyhow::Result<Vec<AddressMatch>> {
+    // prefixes are base58, so they hold no wildcards
+    let addresses = sqlx::query_as!(
+            AddressMatch,
+            "SELECT address, transaction_count, last_activity FROM address_stats
+            WHERE address LIKE $1 || '%'
+            ORDER BY address = $1 DESC, transaction_count DESC, address
+            LIMIT $2",
+            prefix,
+            limit
+        )
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(addresses)
+}
+
+/// Transactions whose memo matches `pattern`, a case-insensitive `LIKE` pattern, newest first.
+/// Transactions dropped by a fork are left out.
+pub async fn search_memos(
+    pool: &Arc<PgPool>,
+    pattern: &str,
+    limit: i64,
+) -> anyhow::Result<Vec<StoredTransaction>> {
+    let rows = sqlx::query_as!(
+            TransactionRow,
+            r#"SELECT id, signature, sender, receiver, sol_amount, fee, timestamp, prev_blockhash, slot, error,
+                log_messages AS "log_messages: Json<Vec<String>>", compute_units_consumed, program, memo,
+                compute_unit_limit, compute_unit_price, priority_fee, txn_type,
+                categories AS "categories: Json<Vec<String>>", sol_usd_price, commitment_status,
+                fee_payer, transfers AS "transfers: Json<Vec<SolTransfer>>", success
+            FROM transactions
+            WHERE memo ILIKE $1 AND commitment_status <> 'dropped'
+            ORDER BY id DESC
+            LIMIT $2"#,
+            pattern,
+            limit
+        )
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(rows.into_iter().map(StoredTransaction::from).collect())
+}
+
+/// Delete the label of an address, returning whether there was one.
+pub async fn delete_label(pool: &Arc<PgPool>, pubkey: &str) -> anyhow::Result<bool> {
+    let mut tx = pool.begin().await?;
+
+    let result = sqlx::query!("DELETE FROM labels WHERE pubkey = $1", pubkey)
+        .execute(&mut *tx)
+        .await?;
+
+    if result.rows_affected() > 0 {
+        bump_version(&mut *tx).await?;
+    }
+
+    tx.commit().await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+/// Store addresses imported to the watchlist, keeping when those already stored were added.
+pub async fn insert_watched_addresses(
+    pool: &Arc<PgPool>,
+    pubkeys: &[String],
+    added_at: i64,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+            "INSERT INTO watched_addresses (pubkey, added_at)
+            SELECT pubkey, $2 FROM UNNEST($1::VARCHAR[]) AS pubkey
+            ON CONFLICT (pubkey) DO NOTHING",
+            pubkeys,
+            added_at
+        )
+        .execute(pool.as_ref())
+        .await?;
+
+    Ok(())
+}
+
+/// Addresses imported through the API, oldest first.
+pub async fn get_watched_addresses(pool: &Arc<PgPool>) -> anyhow::Result<Vec<String>> {
+    let pubkeys = sqlx::query_scalar!(
+            "SELECT pubkey FROM watched_addresses ORDER BY added_at, pubkey"
+        )
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(pubkeys)
+}
+
+/// Store an alert rule, returning its id.
+pub async fn insert_alert_rule(
+    pool: &Arc<PgPool>,
+    rule: &NewAlertRule,
+    api_key_id: Option<i32>,
+    created_at: i64,
+) -> anyhow::Result<i32> {
+    let id = sqlx::query_scalar!(
+            "INSERT INTO alert_rules (name, condition, channels, api_key_id, created_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id",
+            rule.name,
+            serde_json::to_value(&rule.condition)?,
+            serde_json::to_value(&rule.channels)?,
+            api_key_id,
+            created_at
+        )
+        .fetch_one(pool.as_ref())
+        .await?;
+
+    Ok(id)
+}
+
+/// Alert rules registered through the API.
+pub async fn get_alert_rules(pool: &Arc<PgPool>) -> anyhow::Result<Vec<AlertRule>> {
+    let rows = sqlx::query!(
+            "SELECT id, name, condition, channels, api_key_id, created_at, last_fired_at
+            FROM alert_rules
+            ORDER BY id"
+        )
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    rows.into_iter()
+        .map(|row| {
+            Ok(AlertRule {
+                id: row.id,
+                name: row.name,
+                condition: serde_json::from_value(row.condition)?,
+                channels: serde_json::from_value(row.channels)?,
+                api_key_id: row.api_key_id,
+                created_at: row.created_at,
+                last_fired_at: row.last_fired_at,
+            })
+        })
+        .collect()
+}
+
+/// Delete an alert rule, returning whether there was one.
+pub async fn delete_alert_rule(pool: &Arc<PgPool>, id: i32) -> anyhow::Result<bool> {
+    let result = sqlx::query!("DELETE FROM alert_rules WHERE id = $1", id)
+        .execute(pool.as_ref())
+        .await?;
+
+    Ok(result.rows_affected() > 0)
+}
+
+pub async fn set_alert_rule_fired(
+    pool: &Arc<PgPool>,
+    id: i32,
+    fired_at: i64,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+            "UPDATE alert_rules SET last_fired_at = $2 WHERE id = $1",
+            id,
+            fired_at
+        )
+        .execute(pool.as_ref())
+        .await?;
+
+    Ok(())
+}
+
+/// Which of `candidates` `address` has a stored transaction with, besides the `excluded`
+/// signatures.
+pub async fn get_known_counterparties(
+    pool: &Arc<PgPool>,
+    address: &str,
+    candidates: &[String],
+    excluded: &[String],
+) -> anyhow::Result<HashSet<String>> {
+    let counterparties = sqlx::query_scalar!(
+            r#"SELECT DISTINCT CASE WHEN sender = $1 THEN receiver ELSE sender END AS "counterparty!"
+            FROM transactions
+            WHERE ((sender = $1 AND receiver = ANY($2)) OR (receiver = $1 AND sender = ANY($2)))
+                AND NOT signature = ANY($3)"#,
+            address,
+            candidates,
+            excluded
+        )
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(counterparties.into_iter().collect())
+}
+
+/// Block time of the latest stored transaction sent or received by `address`.
+pub async fn get_last_activity(pool: &Arc<PgPool>, address: &str) -> anyhow::Result<Option<i64>> {
+    let timestamp = sqlx::query_scalar!(
+            "SELECT MAX(timestamp) FROM transactions WHERE sender = $1 OR receiver = $1",
+            address
+        )
+        .fetch_one(pool.as_ref())
+        .await?;
+
+    Ok(timestamp)
+}
+
+/// Record transactions that failed validation, replacing earlier rejections of the same
+/// signatures.
+pub async fn insert_rejected_transactions(
+    pool: &Arc<PgPool>,
+    rejected: &[Rejection],
+    rejected_at: i64,
+) -> anyhow::Result<()> {
+    for rejection in rejected {
+        sqlx::query!(
+                "INSERT INTO rejected_transactions (signature, reasons, transaction, rejected_at)
+                VALUES ($1, $2, $3, $4)
+                ON CONFLICT (signature) DO UPDATE
+                SET reasons = EXCLUDED.reasons, transaction = EXCLUDED.transaction,
+                    rejected_at = EXCLUDED.rejected_at",
+                rejection.transaction.signature,
+                serde_json::to_value(&rejection.reasons)?,
+                serde_json::to_value(&rejection.transaction)?,
+                rejected_at
+            )
+            .execute(pool.as_ref())
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// A transaction that failed validation, as recorded.
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredRejection {
+    /// Exposed through the API's id codec.
+    #[serde(skip_serializing)]
+    pub id: i64,
+    pub signature: String,
+    pub reasons: Json<Vec<ValidationError>>,
+    pub transaction: Json<TransactionData>,
+    /// Unix seconds.
+    pub rejected_at: i64,
+}
+
+/// Up to `limit` rejected transactions after the row id `after`.
+pub async fn get_rejected_transactions(
+    pool: &Arc<PgPool>,
+    after: i64,
+    limit: i64,
+) -> anyhow::Result<Vec<StoredRejection>> {
+    let rejections = sqlx::query_as!(
+            StoredRejection,
+            r#"SELECT id, signature, reasons AS "reasons: Json<Vec<ValidationError>>",
+                transaction AS "transaction: Json<TransactionData>", rejected_at
+            FROM rejected_transactions
+            WHERE id > $1
+            ORDER BY id
+            LIMIT $2"#,
+            after,
+            limit
+        )
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(rejections)
+}
+
+/// Flag a transaction as stored by an on-demand analysis of `pubkey` rather than by monitoring.
+pub async fn insert_ad_hoc_transaction(
+    pool: &Arc<PgPool>,
+    pubkey: &str,
+    signature: &str,
+    analyzed_at: i64,
+) -> anyhow::Result<()> {
     sqlx::query!(
-        "CREATE TABLE IF NOT EXISTS transactions (
-        id SERIAL PRIMARY KEY,
-        signature VARCHAR NOT NULL,
-        sender VARCHAR NOT NULL,
-        receiver VARCHAR NOT NULL,
-        sol_amount BIGINT NOT NULL,
-        fee BIGINT NOT NULL,
-        timestamp BIGINT NOT NULL,
-        prev_blockhash VARCHAR NOT NULL
-    )"
-    )
-    .execute(&pool)
-    .await?;
+            "INSERT INTO ad_hoc_transactions (pubkey, signature, analyzed_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (pubkey, signature) DO UPDATE SET analyzed_at = EXCLUDED.analyzed_at",
+            pubkey,
+            signature,
+            analyzed_at
+        )
+        .execute(pool.as_ref())
+        .await?;
 
-    Ok(pool)
+    Ok(())
+}
+
+/// Rows removed by one expiry of ad hoc analysis data.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct AdHocCleanup {
+    pub transactions: u64,
+    pub fee_burns: u64,
+    pub raw_transactions: u64,
+    /// Ad hoc flags dropped because their address has since been added to the watchlist.
+    pub promoted: u64,
+}
+
+/// Delete transactions that were only stored by analyses older than `cutoff` (unix seconds),
+/// along with their fee splits and raw payloads.
+///
+/// Flags of `watched` addresses are dropped without deleting anything, and transactions a
+/// watched address took part in are kept, since monitoring would have stored them anyway.
+pub async fn expire_ad_hoc_transactions(
+    pool: &Arc<PgPool>,
+    cutoff: i64,
+    watched: &[String],
+) -> anyhow::Result<AdHocCleanup> {
+    let mut tx = pool.begin().await?;
+
+    let promoted = sqlx::query!(
+            "DELETE FROM ad_hoc_transactions WHERE pubkey = ANY($1)",
+            watched
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+    // a transaction analyzed for several addresses expires with its latest analysis
+    let expired = sqlx::query!(
+            "SELECT signature FROM ad_hoc_transactions
+            GROUP BY signature
+            HAVING MAX(analyzed_at) < $1",
+            cutoff
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|row| row.signature)
+        .collect::<Vec<_>>();
+
+    let deletable = sqlx::query!(
+            "SELECT signature FROM transactions
+            WHERE signature = ANY($1)
+                AND sender <> ALL($2)
+                AND receiver <> ALL($2)",
+            &expired,
+            watched
+        )
+        .fetch_all(&mut *tx)
+        .await?
+        .into_iter()
+        .map(|row| row.signature)
+        .collect::<Vec<_>>();
+
+    let transactions = delete_transactions(&mut tx, &deletable).await?;
+
+    let fee_burns = sqlx::query!(
+            "DELETE FROM fee_burns
+            WHERE signature = ANY($1)
+                AND NOT EXISTS (SELECT 1 FROM transactions WHERE transactions.signature = fee_burns.signature)",
+            &expired
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+    let raw_transactions = sqlx::query!(
+            "DELETE FROM raw_transactions
+            WHERE signature = ANY($1)
+                AND NOT EXISTS (SELECT 1 FROM transactions WHERE transactions.signature = raw_transactions.signature)",
+            &expired
+        )
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+    sqlx::query!(
+            "DELETE FROM ad_hoc_transactions WHERE signature = ANY($1)",
+            &expired
+        )
+        .execute(&mut *tx)
+        .await?;
+
+    tx.commit().await?;
+
+    Ok(AdHocCleanup {
+        transactions,
+        fee_burns,
+        raw_transactions,
+        promoted,
+    })
+}
+
+/// An API key, without its secret.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKey {
+    pub id: i32,
+    pub name: String,
+    /// First characters of the key, to tell keys apart.
+    pub prefix: String,
+    /// `None` uses the configured default.
+    pub rate_limit_per_minute: Option<i32>,
+    pub created_at: i64,
+    pub revoked_at: Option<i64>,
+}
+
+/// Store a new API key by its hash.
+pub async fn insert_api_key(
+    pool: &Arc<PgPool>,
+    name: &str,
+    prefix: &str,
+    key_hash: &str,
+    rate_limit_per_minute: Option<i32>,
+    created_at: i64,
+) -> anyhow::Result<ApiKey> {
+    let key = sqlx::query_as!(
+            ApiKey,
+            "INSERT INTO api_keys (name, prefix, key_hash, rate_limit_per_minute, created_at)
+            VALUES ($1, $2, $3, $4, $5)
+            RETURNING id, name, prefix, rate_limit_per_minute, created_at, revoked_at",
+            name,
+            prefix,
+            key_hash,
+            rate_limit_per_minute,
+            created_at
+        )
+        .fetch_one(pool.as_ref())
+        .await?;
+
+    Ok(key)
+}
+
+/// The unrevoked key with the given hash, if any.
+pub async fn get_api_key_by_hash(
+    pool: &Arc<PgPool>,
+    key_hash: &str,
+) -> anyhow::Result<Option<ApiKey>> {
+    let key = sqlx::query_as!(
+            ApiKey,
+            "SELECT id, name, prefix, rate_limit_per_minute, created_at, revoked_at FROM api_keys
+            WHERE key_hash = $1 AND revoked_at IS NULL",
+            key_hash
+        )
+        .fetch_optional(pool.as_ref())
+        .await?;
+
+    Ok(key)
+}
+
+/// Every API key, revoked ones included, oldest first.
+pub async fn get_api_keys(pool: &Arc<PgPool>) -> anyhow::Result<Vec<ApiKey>> {
+    let keys = sqlx::query_as!(
+            ApiKey,
+            "SELECT id, name, prefix, rate_limit_per_minute, created_at, revoked_at FROM api_keys
+            ORDER BY id"
+        )
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(keys)
+}
+
+/// Revoke a key, returning its hash, or `None` if there is no such unrevoked key.
+pub async fn revoke_api_key(
+    pool: &Arc<PgPool>,
+    id: i32,
+    revoked_at: i64,
+) -> anyhow::Result<Option<String>> {
+    let row = sqlx::query!(
+            "UPDATE api_keys SET revoked_at = $2
+            WHERE id = $1 AND revoked_at IS NULL
+            RETURNING key_hash",
+            id,
+            revoked_at
+        )
+        .fetch_optional(pool.as_ref())
+        .await?;
+
+    Ok(row.map(|row| row.key_hash))
+}
+
+/// What an address did in a UTC day.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DailyActivity {
+    pub transaction_count: i64,
+    /// Lamports received from other addresses.
+    pub inflow: i64,
+    /// Lamports sent to other addresses.
+    pub outflow: i64,
+    /// Lamports paid in fees.
+    pub fees: i64,
+}
+
+/// Activity of `pubkey` in the stored transactions of `[from, to)`, in unix seconds. Transactions
+/// dropped by a fork aren't counted.
+pub async fn get_daily_activity(
+    pool: &Arc<PgPool>,
+    pubkey: &str,
+    from: i64,
+    to: i64,
+) -> anyhow::Result<DailyActivity> {
+    let activity = sqlx::query_as!(
+            DailyActivity,
+            r#"SELECT COUNT(*) AS "transaction_count!",
+                COALESCE(SUM(sol_amount) FILTER (WHERE receiver = $1 AND sender <> $1), 0)::BIGINT
+                    AS "inflow!",
+                COALESCE(SUM(sol_amount) FILTER (WHERE sender = $1 AND receiver <> $1), 0)::BIGINT
+                    AS "outflow!",
+                COALESCE(SUM(fee) FILTER (WHERE COALESCE(fee_payer, sender) = $1), 0)::BIGINT
+                    AS "fees!"
+            FROM transactions
+            WHERE (sender = $1 OR receiver = $1 OR fee_payer = $1)
+                AND commitment_status <> 'dropped'
+                AND timestamp >= $2 AND timestamp < $3"#,
+            pubkey,
+            from,
+            to
+        )
+        .fetch_one(pool.as_ref())
+        .await?;
+
+    Ok(activity)
+}
+
+/// A watched address's activity in a UTC day, as stored in `daily_reports`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DailyReport {
+    /// `YYYY-MM-DD`.
+    pub date: String,
+    pub pubkey: String,
+    pub transaction_count: i64,
+    /// Lamports received from other addresses.
+    pub inflow: i64,
+    /// Lamports sent to other addresses.
+    pub outflow: i64,
+    /// Lamports paid in fees.
+    pub fees: i64,
+    /// Most transactions first.
+    pub top_counterparties: Json<Vec<CounterpartySummary>>,
+    /// Unix seconds.
+    pub generated_at: i64,
+}
+
+/// Store daily reports, replacing those of the same day and address.
+pub async fn upsert_daily_reports(pool: &Arc<PgPool>, reports: &[DailyReport]) -> anyhow::Result<()> {
+    let mut dates = Vec::with_capacity(reports.len());
+    let mut pubkeys = Vec::with_capacity(reports.len());
+    let mut transaction_counts = Vec::with_capacity(reports.len());
+    let mut inflows = Vec::with_capacity(reports.len());
+    let mut outflows = Vec::with_capacity(reports.len());
+    let mut fees = Vec::with_capacity(reports.len());
+    let mut top_counterparties = Vec::with_capacity(reports.len());
+    let mut generated_at = Vec::with_capacity(reports.len());
+
+    for report in reports {
+        dates.push(report.date.clone());
+        pubkeys.push(report.pubkey.clone());
+        transaction_counts.push(report.transaction_count);
+        inflows.push(report.inflow);
+        outflows.push(report.outflow);
+        fees.push(report.fees);
+        top_counterparties.push(serde_json::to_value(&report.top_counterparties)?);
+        generated_at.push(report.generated_at);
+    }
+
+    sqlx::query!(
+            "INSERT INTO daily_reports (date, pubkey, transaction_count, inflow, outflow, fees,
+                top_counterparties, generated_at)
+            SELECT * FROM UNNEST($1::VARCHAR[], $2::VARCHAR[], $3::BIGINT[], $4::BIGINT[],
+                $5::BIGINT[], $6::BIGINT[], $7::JSONB[], $8::BIGINT[])
+            ON CONFLICT (date, pubkey) DO UPDATE SET transaction_count = EXCLUDED.transaction_count,
+                inflow = EXCLUDED.inflow, outflow = EXCLUDED.outflow, fees = EXCLUDED.fees,
+                top_counterparties = EXCLUDED.top_counterparties,
+                generated_at = EXCLUDED.generated_at",
+            &dates,
+            &pubkeys,
+            &transaction_counts,
+            &inflows,
+            &outflows,
+            &fees,
+            &top_counterparties,
+            &generated_at
+        )
+        .execute(pool.as_ref())
+        .await?;
+
+    Ok(())
+}
+
+/// Daily reports of `date`, a `YYYY-MM-DD` string, by address.
+pub async fn get_daily_reports(pool: &Arc<PgPool>, date: &str) -> anyhow::Result<Vec<DailyReport>> {
+    let reports = sqlx::query_as!(
+            DailyReport,
+            r#"SELECT date, pubkey, transaction_count, inflow, outflow, fees,
+                top_counterparties AS "top_counterparties: Json<Vec<CounterpartySummary>>",
+                generated_at
+            FROM daily_reports
+            WHERE date = $1
+            ORDER BY pubkey"#,
+            date
+        )
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(reports)
+}
+
+/// Whether any address was reported on for `date`, a `YYYY-MM-DD` string.
+pub async fn has_daily_reports(pool: &Arc<PgPool>, date: &str) -> anyhow::Result<bool> {
+    let reported = sqlx::query_scalar!(
+            r#"SELECT EXISTS (SELECT 1 FROM daily_reports WHERE date = $1) AS "reported!""#,
+            date
+        )
+        .fetch_one(pool.as_ref())
+        .await?;
+
+    Ok(reported)
+}
+
+/// An API key's usage in a month.
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiUsage {
+    pub api_key_id: i32,
+    pub name: String,
+    pub prefix: String,
+    pub month: String,
+    pub requests: i64,
+    pub rows_returned: i64,
+    pub webhook_deliveries: i64,
 }
 
-#[allow(dead_code)]
-pub async fn insert_transaction(
+/// Add to the usage of an API key in `month`, a `YYYY-MM` string.
+pub async fn add_api_usage(
     pool: &Arc<PgPool>,
-    txn_data: &TransactionData,
+    api_key_id: i32,
+    month: &str,
+    requests: i64,
+    rows_returned: i64,
+    webhook_deliveries: i64,
 ) -> anyhow::Result<()> {
     sqlx::query!(
-            "INSERT INTO transactions (signature, sender, receiver, sol_amount, fee, timestamp, prev_blockhash)
-            VALUES ($1, $2, $3, $4, $5, $6, $7)",
-            txn_data.signature,
-            txn_data.sender,
-            txn_data.receiver,
-            txn_data.sol_amount as i64,
-            txn_data.fee as i64,
-            txn_data.timestamp,
-            txn_data.prev_blockhash
+            "INSERT INTO api_usage (api_key_id, month, requests, rows_returned, webhook_deliveries)
+            VALUES ($1, $2, $3, $4, $5)
+            ON CONFLICT (api_key_id, month) DO UPDATE SET
+                requests = api_usage.requests + EXCLUDED.requests,
+                rows_returned = api_usage.rows_returned + EXCLUDED.rows_returned,
+                webhook_deliveries = api_usage.webhook_deliveries + EXCLUDED.webhook_deliveries",
+            api_key_id,
+            month,
+            requests,
+            rows_returned,
+            webhook_deliveries
         )
         .execute(pool.as_ref())
         .await?;
 
-    info!(
-        "Inserted transaction in PostgreSQL database: {:?}",
-        txn_data
-    );
+    Ok(())
+}
+
+/// Usage of every API key that was used in `month`, a `YYYY-MM` string, by key id.
+pub async fn get_api_usage(pool: &Arc<PgPool>, month: &str) -> anyhow::Result<Vec<ApiUsage>> {
+    let usage = sqlx::query_as!(
+            ApiUsage,
+            "SELECT u.api_key_id, k.name, k.prefix, u.month, u.requests, u.rows_returned,
+                u.webhook_deliveries
+            FROM api_usage u JOIN api_keys k ON k.id = u.api_key_id
+            WHERE u.month = $1
+            ORDER BY u.api_key_id",
+            month
+        )
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(usage)
+}
+
+/// Cursor a standby has replicated `primary_url` up to, if any.
+pub async fn get_replication_cursor(
+    pool: &Arc<PgPool>,
+    primary_url: &str,
+) -> anyhow::Result<Option<String>> {
+    let row = sqlx::query!(
+            "SELECT cursor FROM replication_cursors WHERE primary_url = $1",
+            primary_url
+        )
+        .fetch_optional(pool.as_ref())
+        .await?;
+
+    Ok(row.map(|row| row.cursor))
+}
+
+/// Remember how far a standby has replicated from `primary_url`.
+pub async fn upsert_replication_cursor(
+    pool: &Arc<PgPool>,
+    primary_url: &str,
+    cursor: &str,
+    updated_at: i64,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+            "INSERT INTO replication_cursors (primary_url, cursor, updated_at)
+            VALUES ($1, $2, $3)
+            ON CONFLICT (primary_url) DO UPDATE SET cursor = $2, updated_at = $3",
+            primary_url,
+            cursor,
+            updated_at
+        )
+        .execute(pool.as_ref())
+        .await?;
 
     Ok(())
 }
 
-pub async fn get_all_transactions(pool: &Arc<PgPool>) -> anyhow::Result<Vec<TransactionData>> {
-    let rows = sqlx::query!(
-            "SELECT signature, sender, receiver, sol_amount, fee, timestamp, prev_blockhash FROM transactions"
+/// Record that `epoch`, spanning `first_slot` to `last_slot`, was current at `seen_at`.
+pub async fn upsert_epoch(
+    pool: &Arc<PgPool>,
+    epoch: i64,
+    first_slot: i64,
+    last_slot: i64,
+    seen_at: i64,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+            "INSERT INTO epochs (epoch, first_slot, last_slot, first_seen_at, last_seen_at)
+            VALUES ($1, $2, $3, $4, $4)
+            ON CONFLICT (epoch) DO UPDATE SET last_seen_at = $4",
+            epoch,
+            first_slot,
+            last_slot,
+            seen_at
+        )
+        .execute(pool.as_ref())
+        .await?;
+
+    Ok(())
+}
+
+/// Tag up to `limit` untagged transactions from `first_normal_slot` on with their epoch, for a
+/// schedule of `slots_per_epoch` slots per epoch from `first_normal_epoch` on. Rows without a slot
+/// are left alone. Returns the number of rows tagged.
+pub async fn tag_transaction_epochs(
+    pool: &Arc<PgPool>,
+    first_normal_epoch: i64,
+    first_normal_slot: i64,
+    slots_per_epoch: i64,
+    limit: i64,
+) -> anyhow::Result<u64> {
+    let result = sqlx::query!(
+            "UPDATE transactions SET epoch = $1 + (slot - $2) / $3
+            WHERE id IN (
+                SELECT id FROM transactions
+                WHERE epoch IS NULL AND slot >= $2 AND slot > 0
+                LIMIT $4
+            )",
+            first_normal_epoch,
+            first_normal_slot,
+            slots_per_epoch,
+            limit
+        )
+        .execute(pool.as_ref())
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// Up to `limit` transactions tagged with `epoch`, after the row id `after`.
+pub async fn get_epoch_transactions(
+    pool: &Arc<PgPool>,
+    epoch: i64,
+    after: i64,
+    limit: i64,
+) -> anyhow::Result<Vec<StoredTransaction>> {
+    let rows = sqlx::query_as!(
+            TransactionRow,
+            r#"SELECT id, signature, sender, receiver, sol_amount, fee, timestamp, prev_blockhash, slot, error,
+                log_messages AS "log_messages: Json<Vec<String>>", compute_units_consumed, program, memo,
+                compute_unit_limit, compute_unit_price, priority_fee, txn_type,
+                categories AS "categories: Json<Vec<String>>", sol_usd_price, commitment_status,
+                fee_payer, transfers AS "transfers: Json<Vec<SolTransfer>>", success
+            FROM transactions
+            WHERE epoch = $1 AND id > $2::BIGINT AND commitment_status <> 'dropped'
+            ORDER BY id
+            LIMIT $3"#,
+            epoch,
+            after,
+            limit
         )
         .fetch_all(pool.as_ref())
         .await?;
 
-    Ok(rows
-        .into_iter()
-        .map(|row| TransactionData {
-            signature: row.signature,
-            sender: row.sender,
-            receiver: row.receiver,
-            sol_amount: row.sol_amount as u64,
-            fee: row.fee as u64,
-            timestamp: row.timestamp,
-            prev_blockhash: row.prev_blockhash,
-        })
-        .collect())
+    Ok(rows.into_iter().map(StoredTransaction::from).collect())
+}
+
+/// An NFT's Metaplex metadata, as stored.
+#[derive(Debug, Clone, Serialize)]
+pub struct Nft {
+    pub mint: String,
+    pub name: String,
+    pub symbol: String,
+    pub uri: Option<String>,
+    /// Mint of the collection NFT.
+    pub collection: Option<String>,
+    pub collection_verified: bool,
+    /// Unix seconds.
+    pub fetched_at: i64,
+}
+
+/// Store the metadata of NFT `mint`, replacing any stored before.
+pub async fn upsert_nft(
+    pool: &Arc<PgPool>,
+    mint: &str,
+    metadata: &TokenMetadata,
+    fetched_at: i64,
+) -> anyhow::Result<()> {
+    sqlx::query!(
+            "INSERT INTO nfts (mint, name, symbol, uri, collection, collection_verified, fetched_at)
+            VALUES ($1, $2, $3, $4, $5, $6, $7)
+            ON CONFLICT (mint) DO UPDATE SET
+                name = $2, symbol = $3, uri = $4, collection = $5, collection_verified = $6,
+                fetched_at = $7",
+            mint,
+            metadata.name,
+            metadata.symbol,
+            metadata.uri,
+            metadata.collection,
+            metadata.collection_verified,
+            fetched_at
+        )
+        .execute(pool.as_ref())
+        .await?;
+
+    Ok(())
+}
+
+/// The stored metadata of NFT `mint`, if any.
+pub async fn get_nft(pool: &Arc<PgPool>, mint: &str) -> anyhow::Result<Option<Nft>> {
+    let nft = sqlx::query_as!(
+            Nft,
+            "SELECT mint, name, symbol, uri, collection, collection_verified, fetched_at FROM nfts
+            WHERE mint = $1",
+            mint
+        )
+        .fetch_optional(pool.as_ref())
+        .await?;
+
+    Ok(nft)
+}
+
+/// Which of `mints` are stored as NFTs.
+pub async fn get_nft_mints(
+    pool: &Arc<PgPool>,
+    mints: &[String],
+) -> anyhow::Result<HashSet<String>> {
+    let rows = sqlx::query!("SELECT mint FROM nfts WHERE mint = ANY($1)", mints)
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(rows.into_iter().map(|row| row.mint).collect())
+}
+
+/// Store the token transfers of stored transactions, each with its signature and whether it
+/// moves an NFT. Transfers stored before are skipped. Returns the number of rows stored.
+pub async fn insert_token_transfers(
+    pool: &Arc<PgPool>,
+    transfers: &[(&str, &TokenTransfer, bool)],
+) -> anyhow::Result<u64> {
+    let mut signatures = Vec::with_capacity(transfers.len());
+    let mut account_indexes = Vec::with_capacity(transfers.len());
+    let mut mints = Vec::with_capacity(transfers.len());
+    let mut owners = Vec::with_capacity(transfers.len());
+    let mut changes = Vec::with_capacity(transfers.len());
+    let mut decimals = Vec::with_capacity(transfers.len());
+    let mut nfts = Vec::with_capacity(transfers.len());
+    let mut usd_prices = Vec::with_capacity(transfers.len());
+
+    for (signature, transfer, nft) in transfers {
+        signatures.push(signature.to_string());
+        account_indexes.push(i16::from(transfer.account_index));
+        mints.push(transfer.mint.clone());
+        owners.push(transfer.owner.clone());
+        changes.push(transfer.change.clamp(i64::MIN.into(), i64::MAX.into()) as i64);
+        decimals.push(i16::from(transfer.decimals));
+        nfts.push(*nft);
+        usd_prices.push(transfer.usd_price);
+    }
+
+    let result = sqlx::query!(
+            "INSERT INTO token_transfers (signature, account_index, mint, owner, change, decimals, nft, usd_price)
+            SELECT * FROM UNNEST(
+                $1::VARCHAR[], $2::SMALLINT[], $3::VARCHAR[], $4::VARCHAR[], $5::BIGINT[],
+                $6::SMALLINT[], $7::BOOLEAN[], $8::FLOAT8[]
+            )
+            ON CONFLICT (signature, account_index) DO NOTHING",
+            &signatures,
+            &account_indexes,
+            &mints,
+            &owners as &[Option<String>],
+            &changes,
+            &decimals,
+            &nfts,
+            &usd_prices as &[Option<f64>]
+        )
+        .execute(pool.as_ref())
+        .await?;
+
+    Ok(result.rows_affected())
+}
+
+/// A token transfer as stored, with its transaction's timestamp and, for NFTs, the NFT's name.
+#[derive(Debug, Clone, Serialize)]
+pub struct StoredTokenTransfer {
+    /// Exposed through the API's id codec.
+    #[serde(skip_serializing)]
+    pub id: i64,
+    pub signature: String,
+    pub timestamp: i64,
+    pub account_index: i16,
+    pub mint: String,
+    pub owner: Option<String>,
+    /// In the mint's smallest unit; negative when tokens left the account.
+    pub change: i64,
+    pub decimals: i16,
+    pub nft: bool,
+    pub nft_name: Option<String>,
+    /// USD price of the mint at the transaction's block time, if it was priced.
+    pub usd_price: Option<f64>,
+}
+
+/// Up to `limit` token transfers after the row id `after`, optionally of one mint, to or from
+/// one owner, or of NFTs only.
+pub async fn get_token_transfers(
+    pool: &Arc<PgPool>,
+    mint: Option<&str>,
+    owner: Option<&str>,
+    nft_only: bool,
+    after: i64,
+    limit: i64,
+) -> anyhow::Result<Vec<StoredTokenTransfer>> {
+    let transfers = sqlx::query_as!(
+            StoredTokenTransfer,
+            r#"SELECT tt.id, tt.signature, t.timestamp, tt.account_index, tt.mint, tt.owner,
+                tt.change, tt.decimals, tt.nft, n.name AS "nft_name?", tt.usd_price
+            FROM token_transfers tt
+            JOIN transactions t ON t.signature = tt.signature
+            LEFT JOIN nfts n ON n.mint = tt.mint
+            WHERE tt.id > $1::BIGINT
+                AND ($2::VARCHAR IS NULL OR tt.mint = $2)
+                AND ($3::VARCHAR IS NULL OR tt.owner = $3)
+                AND (NOT $4::BOOLEAN OR tt.nft)
+            ORDER BY tt.id
+            LIMIT $5"#,
+            after,
+            mint,
+            owner,
+            nft_only,
+            limit
+        )
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(transfers)
+}
+
+/// Every token transfer of up to `limit` token transfer rows' worth of the transactions in which
+/// any of `owners` had a token balance change, optionally within a time range, in id order.
+pub async fn get_token_flows(
+    pool: &Arc<PgPool>,
+    owners: &[String],
+    from: Option<i64>,
+    to: Option<i64>,
+    limit: i64,
+) -> anyhow::Result<Vec<StoredTokenTransfer>> {
+    let transfers = sqlx::query_as!(
+            StoredTokenTransfer,
+            r#"SELECT tt.id, tt.signature, t.timestamp, tt.account_index, tt.mint, tt.owner,
+                tt.change, tt.decimals, tt.nft, n.name AS "nft_name?", tt.usd_price
+            FROM token_transfers tt
+            JOIN transactions t ON t.signature = tt.signature
+            LEFT JOIN nfts n ON n.mint = tt.mint
+            WHERE tt.signature IN (
+                    SELECT signature FROM token_transfers WHERE owner = ANY($1::VARCHAR[])
+                )
+                AND ($2::BIGINT IS NULL OR t.timestamp >= $2)
+                AND ($3::BIGINT IS NULL OR t.timestamp <= $3)
+            ORDER BY tt.id
+            LIMIT $4"#,
+            owners,
+            from,
+            to,
+            limit
+        )
+        .fetch_all(pool.as_ref())
+        .await?;
+
+    Ok(transfers)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use sqlx::PgPool;
-    use std::env;
+    /// The sender and receiver of the transfer in `fixtures/rpc/transfer.json`.
+    const ADDRESS_A: &str = "DLpcCvJooMbrbJosuGYeUjYGxGZTgRfgWkhPehTgngiB";
+    const ADDRESS_B: &str = "BFfZpV9Kh8MdAKCNcMwuqAUiEYkatcojP4PJ3CQuTJ12";
 
     #[tokio::test]
     async fn test_store_transaction() -> Result<(), anyhow::Error> {
-        let _ = dotenvy::dotenv();
-
-        let sender = env::var("ADDRESS_A").expect("`ADDRESS_A` must be set");
-        let receiver = env::var("ADDRESS_B").expect("`ADDRESS_B` must be set");
+        let sender = ADDRESS_A.to_string();
+        let receiver = ADDRESS_B.to_string();
 
         // Setup: Initialize a test database (using a different test database connection)
         let pool = PgPool::connect("postgres://postgres@localhost/test_database").await?;
@@ -111,6 +3332,9 @@ mod tests {
         fee: 500,
         timestamp: 1625077743,
         prev_blockhash: "4sZ76MsNd8y3WSw2L1nfd3AqLoYxdmC98sERoMRbHV14".to_string(),
+        slot: 42,
+        compute_units_consumed: Some(150),
+        ..TransactionData::default()
     };
 
         // Act: Store the transaction
@@ -131,7 +3355,211 @@ mod tests {
         assert_eq!(result.fee, valid_transaction.fee as i64);
         assert_eq!(result.timestamp, valid_transaction.timestamp);
         assert_eq!(result.prev_blockhash, valid_transaction.prev_blockhash);
+        assert_eq!(result.slot, valid_transaction.slot as i64);
+        assert_eq!(result.compute_units_consumed, Some(150));
+
+        Ok(())
+    }
+
+    fn transaction(
+        signature: &str,
+        sender: &str,
+        receiver: &str,
+        timestamp: i64,
+    ) -> TransactionData {
+        TransactionData {
+            signature: signature.to_string(),
+            sender: sender.to_string(),
+            receiver: receiver.to_string(),
+            sol_amount: 1000,
+            fee: 5000,
+            timestamp,
+            prev_blockhash: "4sZ76MsNd8y3WSw2L1nfd3AqLoYxdmC98sERoMRbHV14".to_string(),
+            slot: timestamp as u64 * 10,
+            log_messages: vec![format!("Program log: {signature}")],
+            compute_units_consumed: Some(150),
+            ..TransactionData::default()
+        }
+    }
+
+    /// Exercise a backend through the `Storage` trait, starting from an empty store.
+    async fn check_storage(storage: &dyn Storage) -> Result<(), anyhow::Error> {
+        let empty = storage.version().await?;
+
+        storage
+            .insert(&transaction("sig1", "alice", "bob", 100))
+            .await?;
+        storage
+            .insert(&transaction("sig2", "bob", "carol", 200))
+            .await?;
+        storage
+            .insert(&transaction("sig3", "carol", "alice", 300))
+            .await?;
+
+        let version = storage.version().await?;
+        assert_ne!(version, empty);
+
+        // already stored
+        storage
+            .insert(&transaction("sig2", "bob", "carol", 200))
+            .await?;
+        assert_eq!(storage.version().await?, version);
+
+        let stored = storage.get_by_signature("sig2").await?.unwrap();
+        assert_eq!(stored.id, 2);
+        assert_eq!(stored.transaction.sender, "bob");
+        assert_eq!(stored.transaction.slot, 2000);
+        assert_eq!(stored.transaction.log_messages, vec!["Program log: sig2"]);
+        assert_eq!(stored.transaction.compute_units_consumed, Some(150));
+        assert!(storage.get_by_signature("missing").await?.is_none());
+
+        assert_eq!(storage.get_all().await?.len(), 3);
+
+        let alice = storage
+            .query(&TransactionQuery {
+                address: Some("alice".to_string()),
+                ..TransactionQuery::default()
+            })
+            .await?;
+        assert_eq!(
+            alice.iter().map(|stored| stored.id).collect::<Vec<_>>(),
+            vec![1, 3]
+        );
+
+        let page = storage
+            .query(&TransactionQuery {
+                after: 1,
+                from: Some(150),
+                limit: Some(1),
+                ..TransactionQuery::default()
+            })
+            .await?;
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].transaction.signature, "sig2");
+
+        let slots = storage
+            .query(&TransactionQuery {
+                from_slot: Some(1500),
+                to_slot: Some(2500),
+                ..TransactionQuery::default()
+            })
+            .await?;
+        assert_eq!(slots.len(), 1);
+        assert_eq!(slots[0].transaction.signature, "sig2");
+
+        // only the transactions not stored yet are inserted, once
+        let tagged = TransactionData {
+            program: Some("program".to_string()),
+            memo: Some("invoice 42".to_string()),
+            compute_budget: Some(ComputeBudget {
+                unit_limit: None,
+                unit_price: Some(5),
+                priority_fee: 1,
+            }),
+            txn_type: Some(TransactionType::Stake),
+            categories: vec!["cex_deposit".to_string()],
+            error: Some(r#"{"InstructionError":[0,"Custom"]}"#.to_string()),
+            success: false,
+            ..transaction("sig4", "dave", "erin", 400)
+        };
+        let inserted = storage
+            .insert_batch(&[
+                transaction("sig3", "carol", "alice", 300),
+                tagged.clone(),
+                tagged,
+            ])
+            .await?;
+        assert_eq!(inserted, vec!["sig4"]);
+        assert_eq!(storage.get_all().await?.len(), 4);
+
+        let program = storage
+            .query(&TransactionQuery {
+                program: Some("program".to_string()),
+                ..TransactionQuery::default()
+            })
+            .await?;
+        assert_eq!(program.len(), 1);
+        assert_eq!(program[0].transaction.signature, "sig4");
+        assert_eq!(
+            program[0].transaction.compute_budget.unwrap().unit_price,
+            Some(5)
+        );
+
+        let memo = storage
+            .query(&TransactionQuery {
+                memo_contains: Some("voice".to_string()),
+                ..TransactionQuery::default()
+            })
+            .await?;
+        assert_eq!(memo.len(), 1);
+        assert_eq!(memo[0].transaction.memo.as_deref(), Some("invoice 42"));
+
+        let stake = storage
+            .query(&TransactionQuery {
+                txn_type: Some(TransactionType::Stake),
+                ..TransactionQuery::default()
+            })
+            .await?;
+        assert_eq!(stake.len(), 1);
+        assert_eq!(stake[0].transaction.signature, "sig4");
+
+        let deposits = storage
+            .query(&TransactionQuery {
+                category: Some("cex_deposit".to_string()),
+                ..TransactionQuery::default()
+            })
+            .await?;
+        assert_eq!(deposits.len(), 1);
+        assert_eq!(deposits[0].transaction.categories, vec!["cex_deposit"]);
+
+        let failed = storage
+            .query(&TransactionQuery {
+                success: Some(false),
+                ..TransactionQuery::default()
+            })
+            .await?;
+        assert_eq!(failed.len(), 1);
+        assert!(!failed[0].transaction.success);
+        assert_eq!(
+            storage
+                .query(&TransactionQuery {
+                    success: Some(true),
+                    ..TransactionQuery::default()
+                })
+                .await?
+                .len(),
+            3
+        );
 
         Ok(())
     }
+
+    #[tokio::test]
+    async fn test_in_memory_storage() -> Result<(), anyhow::Error> {
+        check_storage(&InMemoryStorage::new()).await
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_storage() -> Result<(), anyhow::Error> {
+        check_storage(&get_sqlite_pool("sqlite::memory:").await?).await
+    }
+
+    #[test]
+    fn test_timescale_rollup_definitions() {
+        let aggregates = continuous_aggregates();
+        assert_eq!(aggregates.len(), 3 * RollupInterval::ALL.len());
+
+        let view = volume_rollups_view();
+
+        for (name, interval, definition) in &aggregates {
+            assert!(definition.contains(&format!("time_bucket({}::BIGINT", interval.seconds())));
+            assert!(view.contains(&format!("FROM {name}")));
+        }
+
+        // receivers aren't counted twice for self-transfers, and totals have no address
+        let (_, _, receivers) = &aggregates[1];
+        assert!(receivers.contains("WHERE receiver <> sender"));
+        let (_, _, totals) = &aggregates[2];
+        assert!(totals.contains("GROUP BY 1\n"));
+    }
 }
@@ -0,0 +1,361 @@
+// Remembers which signatures the monitor has already handled
+
+// Responsibilities:
+// * Keep a Bloom filter of recently polled signatures per watched address, so polls only fetch
+//   transactions that may be new.
+// * Persist the filters periodically and at shutdown, so a restart doesn't refetch everything.
+
+// Implementation:
+// * A signature the filter hasn't seen is new for sure and is fetched straight away. One it has
+//   seen may be a false positive, so those are checked against the unique signature index in a
+//   single query; a false positive costs a lookup, never a transaction. An already-seen poll then
+//   costs one indexed lookup instead of a `getTransaction` call and an insert per signature.
+// * Bloom filters can't forget, so each address has two generations: once the current one holds
+//   `capacity_per_address` signatures it becomes the previous one and a fresh one starts. Lookups
+//   check both, so at least the latest `capacity_per_address` signatures are remembered and the
+//   false positive rate stays near the configured one.
+// * Signatures are hashed with SHA-256, which stays the same across builds, so persisted filters
+//   remain valid. The file is JSON with the filter bits base64-encoded, written to a temporary
+//   file first so a crash never leaves it half written.
+
+use crate::{config::DedupConfig, data_storage::Storage, schedules::Job};
+
+use base64::{engine::general_purpose::STANDARD, Engine};
+use log::{error, info};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use tokio_util::sync::CancellationToken;
+
+use std::{
+    collections::HashMap,
+    f64::consts::LN_2,
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct BloomFilter {
+    #[serde(
+        serialize_with = "serialize_bits",
+        deserialize_with = "deserialize_bits"
+    )]
+    bits: Vec<u64>,
+    hashes: u32,
+    /// Signatures inserted so far.
+    items: usize,
+}
+
+impl BloomFilter {
+    /// A filter sized for `capacity` items at a false positive rate of `fp_rate`.
+    fn new(capacity: usize, fp_rate: f64) -> Self {
+        let capacity = capacity.max(1) as f64;
+        let bits = (-capacity * fp_rate.ln() / (LN_2 * LN_2)).ceil().max(64.0);
+        let hashes = (bits / capacity * LN_2).round().clamp(1.0, 32.0);
+
+        BloomFilter {
+            bits: vec![0; (bits as usize).div_ceil(64)],
+            hashes: hashes as u32,
+            items: 0,
+        }
+    }
+
+    /// Bit positions of `key`, by double hashing.
+    fn positions(&self, key: &str) -> impl Iterator<Item = usize> {
+        let digest = Sha256::digest(key.as_bytes());
+        let h1 = u64::from_le_bytes(digest[0..8].try_into().expect("8 bytes"));
+        let h2 = u64::from_le_bytes(digest[8..16].try_into().expect("8 bytes")) | 1;
+        let len = (self.bits.len() * 64) as u64;
+
+        (0..u64::from(self.hashes))
+            .map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+
+    fn insert(&mut self, key: &str) {
+        for position in self.positions(key) {
+            self.bits[position / 64] |= 1 << (position % 64);
+        }
+
+        self.items += 1;
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.positions(key)
+            .all(|position| self.bits[position / 64] & (1 << (position % 64)) != 0)
+    }
+}
+
+fn serialize_bits<S: Serializer>(bits: &[u64], serializer: S) -> Result<S::Ok, S::Error> {
+    let bytes = bits
+        .iter()
+        .flat_map(|word| word.to_le_bytes())
+        .collect::<Vec<_>>();
+
+    serializer.serialize_str(&STANDARD.encode(bytes))
+}
+
+fn deserialize_bits<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u64>, D::Error> {
+    let encoded = String::deserialize(deserializer)?;
+    let bytes = STANDARD.decode(encoded).map_err(serde::de::Error::custom)?;
+
+    if bytes.is_empty() || bytes.len() % 8 != 0 {
+        return Err(serde::de::Error::custom("invalid Bloom filter length"));
+    }
+
+    Ok(bytes
+        .chunks_exact(8)
+        .map(|word| u64::from_le_bytes(word.try_into().expect("8 bytes")))
+        .collect())
+}
+
+/// An address's current and previous filters.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Generations {
+    current: BloomFilter,
+    previous: Option<BloomFilter>,
+}
+
+impl Generations {
+    fn contains(&self, signature: &str) -> bool {
+        self.current.contains(signature)
+            || self
+                .previous
+                .as_ref()
+                .is_some_and(|previous| previous.contains(signature))
+    }
+}
+
+pub struct SeenSignatures {
+    capacity: usize,
+    fp_rate: f64,
+    path: Option<PathBuf>,
+    /// Keyed by address.
+    filters: Mutex<HashMap<String, Generations>>,
+}
+
+impl SeenSignatures {
+    /// Empty filters, or those persisted at `config.path` if there are any.
+    pub fn load(config: &DedupConfig) -> anyhow::Result<Self> {
+        let filters = match &config.path {
+            Some(path) if path.exists() => {
+                let filters: HashMap<String, Generations> =
+                    serde_json::from_slice(&fs::read(path)?).map_err(|e| {
+                        anyhow::anyhow!("Invalid dedup filters in `{}`: {e}", path.display())
+                    })?;
+
+                info!(
+                    "Loaded dedup filters of {} addresses from {}",
+                    filters.len(),
+                    path.display()
+                );
+
+                filters
+            }
+            _ => HashMap::new(),
+        };
+
+        Ok(SeenSignatures {
+            capacity: config.capacity_per_address,
+            fp_rate: config.false_positive_rate,
+            path: config.path.clone(),
+            filters: Mutex::new(filters),
+        })
+    }
+
+    /// The signatures that may not be stored yet: those the filter hasn't seen, plus those it has
+    /// but `storage` doesn't have.
+    pub async fn unseen(
+        &self,
+        address: &Pubkey,
+        signatures: Vec<Signature>,
+        storage: &Arc<dyn Storage>,
+    ) -> anyhow::Result<Vec<Signature>> {
+        let (mut unseen, maybe_seen) = self.partition(address, signatures);
+
+        if maybe_seen.is_empty() {
+            return Ok(unseen);
+        }
+
+        let keys = maybe_seen
+            .iter()
+            .map(|signature| signature.to_string())
+            .collect::<Vec<_>>();
+        let stored = storage.stored_signatures(&keys).await?;
+
+        unseen.extend(
+            maybe_seen
+                .into_iter()
+                .zip(keys)
+                .filter(|(_, key)| !stored.contains(key))
+                .map(|(signature, _)| signature),
+        );
+
+        Ok(unseen)
+    }
+
+    /// Split signatures into those the filter hasn't seen and those it may have.
+    fn partition(
+        &self,
+        address: &Pubkey,
+        signatures: Vec<Signature>,
+    ) -> (Vec<Signature>, Vec<Signature>) {
+        let filters = self.filters.lock().expect("dedup filters lock poisoned");
+
+        let Some(generations) = filters.get(&address.to_string()) else {
+            return (signatures, Vec::new());
+        };
+
+        let (maybe_seen, unseen) = signatures
+            .into_iter()
+            .partition(|signature| generations.contains(&signature.to_string()));
+
+        (unseen, maybe_seen)
+    }
+
+    /// Remember an address's signatures once its poll has been handled.
+    pub fn record(&self, address: &Pubkey, signatures: &[Signature]) {
+        let mut filters = self.filters.lock().expect("dedup filters lock poisoned");
+        let generations = filters
+            .entry(address.to_string())
+            .or_insert_with(|| Generations {
+                current: BloomFilter::new(self.capacity, self.fp_rate),
+                previous: None,
+            });
+
+        for signature in signatures {
+            let key = signature.to_string();
+
+            // polls see the same recent signatures again, which mustn't count towards capacity
+            if generations.contains(&key) {
+                continue;
+            }
+
+            if generations.current.items >= self.capacity {
+                let fresh = BloomFilter::new(self.capacity, self.fp_rate);
+                generations.previous = Some(std::mem::replace(&mut generations.current, fresh));
+            }
+
+            generations.current.insert(&key);
+        }
+    }
+
+    /// Persist the filters every time `job` is due, and once more at shutdown.
+    pub async fn run(&self, job: &Job, shutdown: &CancellationToken) {
+        while job.tick(shutdown).await {
+            let _ = job.run(async { self.save() }).await;
+        }
+
+        if let Err(e) = self.save() {
+            error!("Failed to save dedup filters: {e:?}");
+        }
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+
+        let json = {
+            let filters = self.filters.lock().expect("dedup filters lock poisoned");
+            serde_json::to_vec(&*filters)?
+        };
+
+        write_atomically(path, &json)
+    }
+}
+
+fn write_atomically(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let temporary = path.with_extension("tmp");
+
+    fs::write(&temporary, contents)?;
+    fs::rename(&temporary, path)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_storage::InMemoryStorage;
+
+    fn config(path: Option<PathBuf>) -> DedupConfig {
+        DedupConfig {
+            capacity_per_address: 100,
+            // so no test signature is mistaken for a recorded one
+            false_positive_rate: 1e-9,
+            path,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_bloom_filter_false_positive_rate() {
+        let mut filter = BloomFilter::new(1000, 0.01);
+
+        for i in 0..1000 {
+            filter.insert(&format!("seen{i}"));
+        }
+
+        assert!((0..1000).all(|i| filter.contains(&format!("seen{i}"))));
+
+        let false_positives = (0..10_000)
+            .filter(|i| filter.contains(&format!("unseen{i}")))
+            .count();
+        assert!(false_positives < 300, "{false_positives} false positives");
+    }
+
+    #[tokio::test]
+    async fn test_maybe_seen_signatures_are_confirmed() {
+        let seen = SeenSignatures::load(&config(None)).unwrap();
+        let storage: Arc<dyn Storage> = Arc::new(InMemoryStorage::new());
+        let address = Pubkey::new_unique();
+        let signatures = (0..3).map(|_| Signature::new_unique()).collect::<Vec<_>>();
+
+        let unseen = seen
+            .unseen(&address, signatures.clone(), &storage)
+            .await
+            .unwrap();
+        assert_eq!(unseen, signatures);
+
+        // seen but never stored, e.g. left out by the pipeline settings: fetched again
+        seen.record(&address, &signatures);
+        let unseen = seen
+            .unseen(&address, signatures.clone(), &storage)
+            .await
+            .unwrap();
+        assert_eq!(unseen.len(), 3);
+
+        let (unseen, maybe_seen) = seen.partition(&address, signatures);
+        assert!(unseen.is_empty());
+        assert_eq!(maybe_seen.len(), 3);
+    }
+
+    #[test]
+    fn test_generations_rotate_and_persist() {
+        let dir = std::env::temp_dir().join(format!("dedup-{}", Pubkey::new_unique()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("filters.json");
+
+        let seen = SeenSignatures::load(&config(Some(path.clone()))).unwrap();
+        let address = Pubkey::new_unique();
+        let signatures = (0..150)
+            .map(|_| Signature::new_unique())
+            .collect::<Vec<_>>();
+        seen.record(&address, &signatures);
+
+        {
+            let filters = seen.filters.lock().unwrap();
+            let generations = &filters[&address.to_string()];
+            assert_eq!(generations.current.items, 50);
+            assert_eq!(generations.previous.as_ref().unwrap().items, 100);
+        }
+
+        seen.save().unwrap();
+        let loaded = SeenSignatures::load(&config(Some(path))).unwrap();
+        let (unseen, _) = loaded.partition(&address, signatures);
+        assert!(unseen.is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
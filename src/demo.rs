@@ -0,0 +1,89 @@
+// Seeds the demo mode with sample transactions
+
+// Responsibilities:
+// * Give `--demo` a realistic set of transactions to serve, so the API can be explored without a
+//   database, RPC endpoint or watched addresses.
+
+// Implementation:
+// * The transactions are bundled into the binary from `fixtures/demo_transactions.json`: a few
+//   wallets sending SOL to each other over two hours, some with memos and compute budget
+//   instructions, and a couple of failures.
+// * Their timestamps are shifted so the newest happened at startup, so time filters, leaderboards
+//   and rollups have recent data to show.
+// * They're stored through the ingestion path, so the feed and leaderboards see them too.
+
+use crate::{
+    data_processing::TransactionData,
+    data_retrieval::{ProcessedBatch, SolanaClient},
+    data_storage::Storage,
+};
+
+use std::{
+    collections::HashMap,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const FIXTURE: &str = include_str!("../fixtures/demo_transactions.json");
+
+/// The sample transactions, with the newest one happening at `now`.
+fn transactions(now: i64) -> anyhow::Result<Vec<TransactionData>> {
+    let mut txns: Vec<TransactionData> = serde_json::from_str(FIXTURE)?;
+    let shift = now - txns.iter().map(|txn| txn.timestamp).max().unwrap_or(now);
+
+    for txn in &mut txns {
+        txn.timestamp += shift;
+    }
+
+    Ok(txns)
+}
+
+/// Store the sample transactions, returning how many were stored.
+pub async fn seed(
+    solana_client: &SolanaClient,
+    storage: &Arc<dyn Storage>,
+) -> anyhow::Result<usize> {
+    let batch = ProcessedBatch {
+        transactions: transactions(unix_now())?,
+        fee_splits: HashMap::new(),
+        raw_payloads: HashMap::new(),
+        token_transfers: HashMap::new(),
+        rejected: Vec::new(),
+    };
+
+    Ok(solana_client.store(&batch, storage, None).await?.len())
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use solana_sdk::{pubkey::Pubkey, signature::Signature};
+    use std::{collections::HashSet, str::FromStr};
+
+    #[test]
+    fn test_fixture_is_valid_and_recent() {
+        let now = 1_800_000_000;
+        let txns = transactions(now).unwrap();
+
+        assert!(!txns.is_empty());
+        assert_eq!(txns.iter().map(|txn| txn.timestamp).max(), Some(now));
+
+        for txn in &txns {
+            assert!(Signature::from_str(&txn.signature).is_ok());
+            assert!(Pubkey::from_str(&txn.sender).is_ok());
+            assert!(Pubkey::from_str(&txn.receiver).is_ok());
+        }
+
+        let signatures = txns
+            .iter()
+            .map(|txn| &txn.signature)
+            .collect::<HashSet<_>>();
+        assert_eq!(signatures.len(), txns.len());
+    }
+}
@@ -0,0 +1,412 @@
+// Directory of well-known program ids and exchange wallets, used to categorize transactions
+
+// Responsibilities:
+// * Ship a built-in list of popular DEX programs, core programs and centralized exchange hot
+//   wallets, optionally extended or overridden from a JSON file, served at `/directory`.
+// * Tag every processed transaction with categories (`cex_deposit`, `dex_swap`,
+//   `system_transfer`, ...) from the directory, so stored data is categorized on the way in.
+
+// Implementation:
+// * Entries are keyed by address; entries from the file replace built-in ones with the same
+//   address, and `builtin = false` starts from an empty directory instead.
+// * Categories are derived from the raw transaction: the programs among its account keys, the
+//   SOL moved by its System program transfers (top-level or inner) and its token balance changes.
+// * A transfer between two exchange wallets is neither a deposit nor a withdrawal.
+
+use crate::{config::DirectoryConfig, data_processing::parse_token_transfers};
+
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use solana_transaction_status::{
+    option_serializer::OptionSerializer, EncodedConfirmedTransactionWithStatusMeta,
+    EncodedTransaction, UiInstruction, UiMessage, UiParsedInstruction,
+};
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs,
+    str::FromStr,
+};
+
+/// SOL moved by a System program transfer.
+pub const SYSTEM_TRANSFER: &str = "system_transfer";
+/// A transaction invoking a DEX program.
+pub const DEX_SWAP: &str = "dex_swap";
+/// SOL or tokens moved into an exchange wallet from outside the exchange.
+pub const CEX_DEPOSIT: &str = "cex_deposit";
+/// SOL or tokens moved out of an exchange wallet to outside the exchange.
+pub const CEX_WITHDRAWAL: &str = "cex_withdrawal";
+
+const SYSTEM_PROGRAM_ID: &str = "11111111111111111111111111111111";
+
+/// What a known address is.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EntryKind {
+    /// Hot wallet of a centralized exchange.
+    Exchange,
+    /// Program of a decentralized exchange or swap aggregator.
+    Dex,
+    /// Any other well-known program.
+    Program,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct DirectoryEntry {
+    pub address: String,
+    pub name: String,
+    pub kind: EntryKind,
+}
+
+/// Built-in entries: address, name and kind.
+const BUILTIN: &[(&str, &str, EntryKind)] = &[
+    // core programs
+    (SYSTEM_PROGRAM_ID, "System Program", EntryKind::Program),
+    (
+        "Vote111111111111111111111111111111111111111",
+        "Vote Program",
+        EntryKind::Program,
+    ),
+    (
+        "Stake11111111111111111111111111111111111111",
+        "Stake Program",
+        EntryKind::Program,
+    ),
+    (
+        "ComputeBudget111111111111111111111111111111",
+        "Compute Budget Program",
+        EntryKind::Program,
+    ),
+    (
+        "TokenkegQfeZyiNwAJbNbGKPFXCWuBvf9Ss623VQ5DA",
+        "SPL Token",
+        EntryKind::Program,
+    ),
+    (
+        "TokenzQdBNbLqP5VEhdkAS6EPFLC1PHnBqCXEpPxuEb",
+        "SPL Token-2022",
+        EntryKind::Program,
+    ),
+    (
+        "ATokenGPvbdGVxr1b2hvZbsiqW5xWH25efTNsLJA8knL",
+        "SPL Associated Token Account",
+        EntryKind::Program,
+    ),
+    (
+        "MemoSq4gqABAXKb96qnH8TysNcWxMyWCqXgDLGmfcHr",
+        "SPL Memo",
+        EntryKind::Program,
+    ),
+    (
+        "metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s",
+        "Metaplex Token Metadata",
+        EntryKind::Program,
+    ),
+    // decentralized exchanges
+    (
+        "JUP6LkbZbjS1jKKwapdHNy74zcZ3tLUZoi5QNyVTaV4",
+        "Jupiter Aggregator v6",
+        EntryKind::Dex,
+    ),
+    (
+        "675kPX9MHTjS2zt1qfr1NYHuzeLXfQM9H24wFSUt1Mp8",
+        "Raydium AMM v4",
+        EntryKind::Dex,
+    ),
+    (
+        "CAMMCzo5YL8w4VFF8KVHrK22GGUsp5VTaW7grrKgrWqK",
+        "Raydium CLMM",
+        EntryKind::Dex,
+    ),
+    (
+        "whirLbMiicVdio4qvUfM5KAg6Ct8VwpYzGff3uctyCc",
+        "Orca Whirlpools",
+        EntryKind::Dex,
+    ),
+    (
+        "LBUZKhRxPF3XUpBCjp4YzTKgLccjZhTSDM9YuVaPwxo",
+        "Meteora DLMM",
+        EntryKind::Dex,
+    ),
+    (
+        "PhoeNiXZ8ByJGLkxNfZRnkUfjvmuYqLR89jjFHGqdXY",
+        "Phoenix",
+        EntryKind::Dex,
+    ),
+    (
+        "srmqPvymJeFKQ4zGQed1GFppgkRHL9kaELCbyksJtPX",
+        "OpenBook",
+        EntryKind::Dex,
+    ),
+    (
+        "6EF8rrecthR5Dkzon8Nwu78hRvfCKubJ14M5uBEwF6P",
+        "Pump.fun",
+        EntryKind::Dex,
+    ),
+    // centralized exchange hot wallets
+    (
+        "5tzFkiKscXHK5ZXCGbXZxdw7gTjjD1mBwuoFbhUvuAi9",
+        "Binance",
+        EntryKind::Exchange,
+    ),
+    (
+        "9WzDXwBbmkg8ZTbNMqUxvQRAyrZzDsGYdLVL9zYtAWWM",
+        "Binance",
+        EntryKind::Exchange,
+    ),
+    (
+        "H8sMJSCQxfKiFTCfDR3DUMLPwcRbM61LGFJ8N4dK3WjS",
+        "Coinbase",
+        EntryKind::Exchange,
+    ),
+    (
+        "2AQdpHJ2JpcEgPiATUXjQxA8QmafFegfQwSLWSprPicm",
+        "Coinbase",
+        EntryKind::Exchange,
+    ),
+    (
+        "FWznbcNXWQuHTawe9RxvQ2LdCENssh12dsznf4RiouN5",
+        "Kraken",
+        EntryKind::Exchange,
+    ),
+];
+
+#[derive(Debug, Default)]
+pub struct Directory {
+    /// Keyed by address.
+    entries: BTreeMap<String, DirectoryEntry>,
+}
+
+impl Directory {
+    /// The built-in entries.
+    pub fn builtin() -> Self {
+        let entries = BUILTIN
+            .iter()
+            .map(|(address, name, kind)| {
+                let entry = DirectoryEntry {
+                    address: address.to_string(),
+                    name: name.to_string(),
+                    kind: *kind,
+                };
+
+                (entry.address.clone(), entry)
+            })
+            .collect();
+
+        Directory { entries }
+    }
+
+    /// The built-in entries unless turned off, overridden by those of the configured file.
+    pub fn load(config: &DirectoryConfig) -> anyhow::Result<Self> {
+        let mut directory = match config.builtin {
+            true => Self::builtin(),
+            false => Self::default(),
+        };
+
+        if let Some(path) = &config.path {
+            let json = fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Failed to read {}: {e}", path.display()))?;
+            let entries = serde_json::from_str::<Vec<DirectoryEntry>>(&json)
+                .map_err(|e| anyhow::anyhow!("Invalid directory {}: {e}", path.display()))?;
+
+            for entry in entries {
+                directory.insert(entry)?;
+            }
+        }
+
+        Ok(directory)
+    }
+
+    /// Add an entry, replacing any with the same address.
+    pub fn insert(&mut self, entry: DirectoryEntry) -> anyhow::Result<()> {
+        Pubkey::from_str(&entry.address)
+            .map_err(|e| anyhow::anyhow!("Invalid address `{}`: {e}", entry.address))?;
+
+        self.entries.insert(entry.address.clone(), entry);
+
+        Ok(())
+    }
+
+    pub fn get(&self, address: &str) -> Option<&DirectoryEntry> {
+        self.entries.get(address)
+    }
+
+    /// Every entry, by address.
+    pub fn entries(&self) -> Vec<DirectoryEntry> {
+        self.entries.values().cloned().collect()
+    }
+
+    fn is(&self, address: &str, kind: EntryKind) -> bool {
+        self.get(address).is_some_and(|entry| entry.kind == kind)
+    }
+
+    /// The signature of a transaction and its categories, sorted.
+    pub fn categorize(
+        &self,
+        txn: &EncodedConfirmedTransactionWithStatusMeta,
+    ) -> Option<(String, Vec<String>)> {
+        let EncodedTransaction::Json(ui_txn) = &txn.transaction.transaction else {
+            return None;
+        };
+        let UiMessage::Parsed(message) = &ui_txn.message else {
+            return None;
+        };
+
+        let signature = ui_txn.signatures.first()?.clone();
+        let mut categories = BTreeSet::new();
+
+        if message
+            .account_keys
+            .iter()
+            .any(|key| self.is(&key.pubkey, EntryKind::Dex))
+        {
+            categories.insert(DEX_SWAP);
+        }
+
+        // (from, to) of every movement of SOL or tokens
+        let mut movements = Vec::new();
+        let inner = match txn
+            .transaction
+            .meta
+            .as_ref()
+            .map(|meta| &meta.inner_instructions)
+        {
+            Some(OptionSerializer::Some(inner)) => inner.as_slice(),
+            _ => &[],
+        };
+        let instructions = message
+            .instructions
+            .iter()
+            .chain(inner.iter().flat_map(|inner| inner.instructions.iter()));
+
+        for (from, to) in instructions.filter_map(system_transfer) {
+            categories.insert(SYSTEM_TRANSFER);
+            movements.push((from, to));
+        }
+
+        // each token balance change, as sent by the losing owners to the gaining ones
+        let transfers = parse_token_transfers(txn);
+        let owners = |gaining: bool| {
+            transfers
+                .iter()
+                .filter(move |transfer| transfer.change != 0 && (transfer.change > 0) == gaining)
+                .filter_map(|transfer| transfer.owner.as_deref())
+        };
+
+        for from in owners(false) {
+            for to in owners(true) {
+                movements.push((from, to));
+            }
+        }
+
+        for (from, to) in movements {
+            match (
+                self.is(from, EntryKind::Exchange),
+                self.is(to, EntryKind::Exchange),
+            ) {
+                (false, true) => categories.insert(CEX_DEPOSIT),
+                (true, false) => categories.insert(CEX_WITHDRAWAL),
+                _ => false,
+            };
+        }
+
+        Some((
+            signature,
+            categories.into_iter().map(str::to_string).collect(),
+        ))
+    }
+}
+
+/// Source and destination of a System program SOL transfer.
+fn system_transfer(instruction: &UiInstruction) -> Option<(&str, &str)> {
+    let UiInstruction::Parsed(UiParsedInstruction::Parsed(instruction)) = instruction else {
+        return None;
+    };
+
+    if instruction.program_id != SYSTEM_PROGRAM_ID {
+        return None;
+    }
+
+    let kind = instruction.parsed.get("type")?.as_str()?;
+
+    if !matches!(kind, "transfer" | "transferWithSeed") {
+        return None;
+    }
+
+    let info = instruction.parsed.get("info")?;
+
+    Some((
+        info.get("source")?.as_str()?,
+        info.get("destination")?.as_str()?,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use serde_json::json;
+    use solana_transaction_status::parse_instruction::ParsedInstruction;
+
+    fn transfer(source: &str, destination: &str) -> UiInstruction {
+        UiInstruction::Parsed(UiParsedInstruction::Parsed(ParsedInstruction {
+            program: "system".to_string(),
+            program_id: SYSTEM_PROGRAM_ID.to_string(),
+            parsed: json!({
+                "type": "transfer",
+                "info": { "source": source, "destination": destination, "lamports": 1 },
+            }),
+            stack_height: None,
+        }))
+    }
+
+    #[test]
+    fn test_builtin_entries_and_overrides() {
+        let mut directory = Directory::builtin();
+
+        for entry in directory.entries() {
+            assert!(
+                Pubkey::from_str(&entry.address).is_ok(),
+                "{}",
+                entry.address
+            );
+        }
+
+        let binance = "5tzFkiKscXHK5ZXCGbXZxdw7gTjjD1mBwuoFbhUvuAi9";
+        assert!(directory.is(binance, EntryKind::Exchange));
+
+        directory
+            .insert(DirectoryEntry {
+                address: binance.to_string(),
+                name: "Not an exchange".to_string(),
+                kind: EntryKind::Program,
+            })
+            .unwrap();
+        assert!(!directory.is(binance, EntryKind::Exchange));
+
+        assert!(directory
+            .insert(DirectoryEntry {
+                address: "not-an-address".to_string(),
+                name: "x".to_string(),
+                kind: EntryKind::Dex,
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn test_system_transfer() {
+        assert_eq!(
+            system_transfer(&transfer("from", "to")),
+            Some(("from", "to"))
+        );
+
+        let create = UiInstruction::Parsed(UiParsedInstruction::Parsed(ParsedInstruction {
+            program: "system".to_string(),
+            program_id: SYSTEM_PROGRAM_ID.to_string(),
+            parsed: json!({ "type": "createAccount", "info": {} }),
+            stack_height: None,
+        }));
+        assert_eq!(system_transfer(&create), None);
+    }
+}
@@ -0,0 +1,350 @@
+// Keeps the transactions whose insert failed and retries them until they're stored
+
+// Responsibilities:
+// * Queue the transactions of a batch that couldn't be inserted, so a database outage doesn't
+//   lose them.
+// * Retry the queue every `retry_interval_secs`, and right away on `POST /dlq/replay` once the
+//   database has recovered. What's queued is reported at `/dlq`.
+
+// Implementation:
+// * The queue is a JSON file rewritten on every change, to a temporary file first so a crash never
+//   leaves it half written. It doesn't depend on the database whose failure filled it.
+// * Transactions are queued by signature: one that fails again keeps its place and counts another
+//   attempt. Beyond `max_transactions`, the oldest are dropped and counted.
+// * Replays store the oldest `REPLAY_CHUNK` transactions at a time like a poll's batch, so they
+//   reach the feed and sinks once stored, and stop at the first chunk that fails again. Inserts
+//   skip stored transactions, so replaying one a later poll already stored is harmless.
+// * Only transactions are queued; the fee splits, raw payloads and token transfers of a failed
+//   batch are not.
+
+use crate::{
+    config::DlqConfig,
+    data_processing::TransactionData,
+    data_retrieval::{ProcessedBatch, SolanaClient},
+    data_storage::Storage,
+    schedules::Job,
+};
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio_util::sync::CancellationToken;
+
+use std::{
+    collections::{HashSet, VecDeque},
+    fs,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Name of the background job replaying the queue, which `POST /dlq/replay` triggers.
+pub const REPLAY_JOB: &str = "dlq-replay";
+
+/// Queued transactions stored at a time when replaying.
+const REPLAY_CHUNK: usize = 100;
+
+/// A transaction whose insert failed.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DeadLetter {
+    pub transaction: TransactionData,
+    /// Of the latest failed attempt.
+    pub error: String,
+    /// Unix seconds.
+    pub first_failed_at: i64,
+    pub last_failed_at: i64,
+    pub attempts: u32,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct DlqReport {
+    pub queued: usize,
+    /// Unix seconds; when the oldest queued transaction first failed.
+    pub oldest_failed_at: Option<i64>,
+    /// Of the latest failed insert.
+    pub last_error: Option<String>,
+    /// Since startup.
+    pub replayed: u64,
+    pub dropped: u64,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    /// Oldest first.
+    letters: VecDeque<DeadLetter>,
+    /// Signatures of `letters`.
+    signatures: HashSet<String>,
+    last_error: Option<String>,
+    replayed: u64,
+    dropped: u64,
+}
+
+pub struct DeadLetterQueue {
+    path: PathBuf,
+    max_transactions: usize,
+    queue: Mutex<Queue>,
+}
+
+impl DeadLetterQueue {
+    /// An empty queue, or the one persisted at `config.path` if there is one.
+    pub fn load(config: &DlqConfig) -> anyhow::Result<Self> {
+        let mut queue = Queue::default();
+
+        if config.path.exists() {
+            let letters: VecDeque<DeadLetter> = serde_json::from_slice(&fs::read(&config.path)?)
+                .map_err(|e| {
+                    anyhow::anyhow!(
+                        "Invalid dead-letter queue in `{}`: {e}",
+                        config.path.display()
+                    )
+                })?;
+
+            if !letters.is_empty() {
+                warn!(
+                    "Loaded {} transactions that failed to insert from {}",
+                    letters.len(),
+                    config.path.display()
+                );
+            }
+
+            queue.signatures = letters
+                .iter()
+                .map(|letter| letter.transaction.signature.clone())
+                .collect();
+            queue.letters = letters;
+        }
+
+        Ok(DeadLetterQueue {
+            path: config.path.clone(),
+            max_transactions: config.max_transactions,
+            queue: Mutex::new(queue),
+        })
+    }
+
+    /// Queue transactions whose insert failed with `error`.
+    pub fn push(&self, txns: &[TransactionData], error: &anyhow::Error) {
+        let now = unix_now();
+        let error = format!("{error:#}");
+        let mut queue = self.queue.lock().expect("dead-letter queue lock poisoned");
+
+        for txn in txns {
+            if queue.signatures.contains(&txn.signature) {
+                if let Some(letter) = queue
+                    .letters
+                    .iter_mut()
+                    .find(|letter| letter.transaction.signature == txn.signature)
+                {
+                    letter.error.clone_from(&error);
+                    letter.last_failed_at = now;
+                    letter.attempts += 1;
+                }
+
+                continue;
+            }
+
+            if queue.letters.len() >= self.max_transactions {
+                let Some(oldest) = queue.letters.pop_front() else {
+                    break;
+                };
+
+                queue.signatures.remove(&oldest.transaction.signature);
+                queue.dropped += 1;
+                error!(
+                    "Dead-letter queue is full, dropping transaction {}",
+                    oldest.transaction.signature
+                );
+            }
+
+            queue.signatures.insert(txn.signature.clone());
+            queue.letters.push_back(DeadLetter {
+                transaction: txn.clone(),
+                error: error.clone(),
+                first_failed_at: now,
+                last_failed_at: now,
+                attempts: 1,
+            });
+        }
+
+        queue.last_error = Some(error);
+        self.save(&queue);
+    }
+
+    /// Replay the queue every time `job` is due or triggered, until `shutdown` is cancelled.
+    pub async fn run(
+        &self,
+        solana_client: &SolanaClient,
+        storage: &Arc<dyn Storage>,
+        job: &Job,
+        shutdown: &CancellationToken,
+    ) {
+        while job.tick(shutdown).await {
+            let _ = job.run(self.replay(solana_client, storage)).await;
+        }
+    }
+
+    /// Store the queued transactions, oldest first, stopping at the first chunk that fails again.
+    /// Returns how many were replayed.
+    pub async fn replay(
+        &self,
+        solana_client: &SolanaClient,
+        storage: &Arc<dyn Storage>,
+    ) -> anyhow::Result<usize> {
+        let mut replayed = 0;
+
+        loop {
+            let transactions = {
+                let queue = self.queue.lock().expect("dead-letter queue lock poisoned");
+
+                queue
+                    .letters
+                    .iter()
+                    .take(REPLAY_CHUNK)
+                    .map(|letter| letter.transaction.clone())
+                    .collect::<Vec<_>>()
+            };
+
+            if transactions.is_empty() {
+                break;
+            }
+
+            let batch = ProcessedBatch {
+                transactions,
+                ..ProcessedBatch::default()
+            };
+
+            // a failed insert queues the chunk again, counting another attempt
+            solana_client.store(&batch, storage, None).await?;
+
+            self.remove(&batch.transactions);
+            replayed += batch.transactions.len();
+        }
+
+        if replayed > 0 {
+            info!("Replayed {replayed} transactions that had failed to insert");
+        }
+
+        Ok(replayed)
+    }
+
+    /// Dequeue transactions once they're stored.
+    fn remove(&self, txns: &[TransactionData]) {
+        let mut queue = self.queue.lock().expect("dead-letter queue lock poisoned");
+        let before = queue.letters.len();
+
+        for txn in txns {
+            queue.signatures.remove(&txn.signature);
+        }
+
+        let Queue {
+            letters,
+            signatures,
+            ..
+        } = &mut *queue;
+        letters.retain(|letter| signatures.contains(&letter.transaction.signature));
+        queue.replayed += (before - queue.letters.len()) as u64;
+
+        self.save(&queue);
+    }
+
+    /// Persist the queue, logging failures: the transactions stay queued in memory either way.
+    fn save(&self, queue: &Queue) {
+        let written = serde_json::to_vec(&queue.letters)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| write_atomically(&self.path, &json));
+
+        if let Err(e) = written {
+            error!("Failed to save dead-letter queue: {e:?}");
+        }
+    }
+
+    pub fn report(&self) -> DlqReport {
+        let queue = self.queue.lock().expect("dead-letter queue lock poisoned");
+
+        DlqReport {
+            queued: queue.letters.len(),
+            oldest_failed_at: queue.letters.front().map(|letter| letter.first_failed_at),
+            last_error: queue.last_error.clone(),
+            replayed: queue.replayed,
+            dropped: queue.dropped,
+        }
+    }
+}
+
+fn write_atomically(path: &Path, contents: &[u8]) -> anyhow::Result<()> {
+    let temporary = path.with_extension("tmp");
+
+    fs::write(&temporary, contents)?;
+    fs::rename(&temporary, path)?;
+
+    Ok(())
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use solana_sdk::{pubkey::Pubkey, signature::Signature};
+
+    fn transaction() -> TransactionData {
+        TransactionData {
+            signature: Signature::new_unique().to_string(),
+            sender: Pubkey::new_unique().to_string(),
+            receiver: Pubkey::new_unique().to_string(),
+            sol_amount: 1000,
+            fee: 5000,
+            timestamp: 1625077743,
+            prev_blockhash: "4sZ76MsNd8y3WSw2L1nfd3AqLoYxdmC98sERoMRbHV14".to_string(),
+            slot: 42,
+            ..TransactionData::default()
+        }
+    }
+
+    #[test]
+    fn test_queue_counts_attempts_drops_oldest_and_persists() {
+        let dir = std::env::temp_dir().join(format!("dlq-{}", Pubkey::new_unique()));
+        fs::create_dir_all(&dir).unwrap();
+        let config = DlqConfig {
+            path: dir.join("dead-letters.json"),
+            max_transactions: 2,
+            ..DlqConfig::default()
+        };
+
+        let queue = DeadLetterQueue::load(&config).unwrap();
+        let txns = (0..3).map(|_| transaction()).collect::<Vec<_>>();
+
+        queue.push(&txns[..2], &anyhow::anyhow!("connection refused"));
+        queue.push(&txns[..1], &anyhow::anyhow!("pool timed out"));
+
+        let report = queue.report();
+        assert_eq!(report.queued, 2);
+        assert_eq!(report.last_error.as_deref(), Some("pool timed out"));
+
+        // a full queue drops its oldest transaction
+        queue.push(&txns[2..], &anyhow::anyhow!("connection refused"));
+        assert_eq!(queue.report().dropped, 1);
+
+        let loaded = DeadLetterQueue::load(&config).unwrap();
+        let letters = loaded.queue.lock().unwrap().letters.clone();
+        assert_eq!(
+            letters
+                .iter()
+                .map(|letter| (letter.transaction.signature.as_str(), letter.attempts))
+                .collect::<Vec<_>>(),
+            vec![
+                (txns[1].signature.as_str(), 1),
+                (txns[2].signature.as_str(), 1)
+            ]
+        );
+
+        loaded.remove(&txns[1..2]);
+        assert_eq!(loaded.report().queued, 1);
+        assert_eq!(loaded.report().replayed, 1);
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
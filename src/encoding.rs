@@ -0,0 +1,151 @@
+// Serializes API responses in the format the client accepts
+
+// Responsibilities:
+// * Pick JSON, MessagePack or CBOR from a request's `Accept` header, for machine consumers that
+//   would rather not parse JSON.
+// * Serialize responses, and each record of a streamed response, in the chosen format.
+
+// Implementation:
+// * The supported type the client prefers most wins. JSON is the default, also when the client
+//   accepts none of them.
+// * MessagePack is written with field names, i.e. structs as maps, so it decodes into the same
+//   shape as the JSON; CBOR likewise.
+// * Both are self-delimiting, so a streamed response is just its records back to back: a
+//   MessagePack stream, or a CBOR sequence (RFC 8742).
+
+use actix_web::{http::header, HttpMessage, HttpRequest, HttpResponse, HttpResponseBuilder};
+use log::error;
+use serde::Serialize;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Json,
+    MessagePack,
+    Cbor,
+}
+
+impl Encoding {
+    /// The encoding the client prefers, from its `Accept` header.
+    pub fn negotiate(req: &HttpRequest) -> Self {
+        req.get_header::<header::Accept>()
+            .and_then(|accept| {
+                accept
+                    .ranked()
+                    .iter()
+                    .find_map(|mime| Self::from_mime(mime.essence_str()))
+            })
+            .unwrap_or_default()
+    }
+
+    fn from_mime(mime: &str) -> Option<Self> {
+        match mime {
+            "application/json" => Some(Encoding::Json),
+            "application/msgpack" | "application/x-msgpack" => Some(Encoding::MessagePack),
+            "application/cbor" => Some(Encoding::Cbor),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            Encoding::Json => "application/json",
+            Encoding::MessagePack => "application/msgpack",
+            Encoding::Cbor => "application/cbor",
+        }
+    }
+
+    /// Append `value` to `out`.
+    pub fn write<T: Serialize + ?Sized>(self, out: &mut Vec<u8>, value: &T) -> anyhow::Result<()> {
+        match self {
+            Encoding::Json => serde_json::to_writer(out, value)?,
+            Encoding::MessagePack => rmp_serde::encode::write_named(out, value)?,
+            Encoding::Cbor => ciborium::into_writer(value, out)?,
+        }
+
+        Ok(())
+    }
+
+    /// Finish `res` with `value` as its body.
+    pub fn respond<T: Serialize + ?Sized>(
+        self,
+        mut res: HttpResponseBuilder,
+        value: &T,
+    ) -> HttpResponse {
+        let mut body = Vec::new();
+
+        match self.write(&mut body, value) {
+            Ok(()) => res
+                .content_type(self.content_type())
+                .insert_header((header::VARY, "Accept"))
+                .body(body),
+            Err(e) => {
+                error!("Failed to encode {} response: {e:?}", self.content_type());
+                HttpResponse::InternalServerError().finish()
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use actix_web::test::TestRequest;
+
+    #[derive(Serialize)]
+    struct Record {
+        signature: &'static str,
+        fee: u64,
+        error: Option<String>,
+    }
+
+    fn negotiate(accept: &str) -> Encoding {
+        Encoding::negotiate(
+            &TestRequest::default()
+                .insert_header((header::ACCEPT, accept))
+                .to_http_request(),
+        )
+    }
+
+    #[test]
+    fn test_negotiate_and_encode() {
+        assert_eq!(
+            Encoding::negotiate(&TestRequest::default().to_http_request()),
+            Encoding::Json
+        );
+        assert_eq!(negotiate("*/*"), Encoding::Json);
+        assert_eq!(negotiate("application/msgpack"), Encoding::MessagePack);
+        assert_eq!(
+            negotiate("text/html, application/cbor;q=0.9, application/json;q=0.5"),
+            Encoding::Cbor
+        );
+        assert_eq!(
+            negotiate("application/cbor;q=0.5, application/json"),
+            Encoding::Json
+        );
+
+        let record = Record {
+            signature: "sig",
+            fee: 5000,
+            error: None,
+        };
+        let expected = serde_json::json!({ "signature": "sig", "fee": 5000, "error": null });
+
+        let mut msgpack = Vec::new();
+        Encoding::MessagePack.write(&mut msgpack, &record).unwrap();
+        let decoded = rmp_serde::from_slice::<serde_json::Value>(&msgpack).unwrap();
+        assert_eq!(decoded, expected);
+
+        // records back to back decode one after the other
+        let mut cbor = Vec::new();
+        Encoding::Cbor.write(&mut cbor, &record).unwrap();
+        Encoding::Cbor.write(&mut cbor, &record).unwrap();
+        let mut reader = cbor.as_slice();
+        for _ in 0..2 {
+            let decoded = ciborium::from_reader::<serde_json::Value, _>(&mut reader).unwrap();
+            assert_eq!(decoded, expected);
+        }
+        assert!(reader.is_empty());
+    }
+}
@@ -0,0 +1,221 @@
+// Enriches stored token transfers with the Metaplex metadata of the NFTs they move
+
+// Responsibilities:
+// * Record the token balance changes of stored transactions in `token_transfers`, flagging the
+//   ones that move an NFT, so NFT activity can be listed at `/token-transfers?nft=true`.
+// * Store the name, symbol, URI and collection of every NFT seen in `nfts`, served at
+//   `/nfts/{mint}`.
+
+// Implementation:
+// * An NFT is a mint with a supply of 1 and 0 decimals. Only mints moved with 0 decimals are
+//   looked at, since the decimals come with the token balances.
+// * Whether a mint is an NFT is remembered in memory, up to `max_cached_mints` before the cache
+//   starts over, then looked up in `nfts`, and only then fetched over RPC: the mints in one
+//   `getMultipleAccounts` call, and the metadata accounts of the NFTs among them in another.
+// * NFTs without a metadata account are flagged but not stored in `nfts`, so they're fetched
+//   again after a restart.
+// * Postgres only, like the other tables derived from raw RPC responses.
+
+use crate::{
+    config::EnrichmentConfig,
+    data_processing::TokenTransfer,
+    data_retrieval::SolanaClient,
+    data_storage::{get_nft_mints, insert_token_transfers, upsert_nft},
+};
+
+use log::info;
+use solana_sdk::pubkey::Pubkey;
+use sqlx::PgPool;
+
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub struct NftEnricher {
+    max_cached_mints: usize,
+    /// Whether each mint looked up is an NFT.
+    mints: Mutex<HashMap<String, bool>>,
+}
+
+impl NftEnricher {
+    pub fn new(config: &EnrichmentConfig) -> Self {
+        NftEnricher {
+            max_cached_mints: config.max_cached_mints,
+            mints: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Store the token transfers of stored transactions, keyed by signature, flagging the ones
+    /// that move an NFT and storing the metadata of NFTs not seen before.
+    pub async fn record(
+        &self,
+        solana_client: &SolanaClient,
+        db: &Arc<PgPool>,
+        transfers: &[(&String, &Vec<TokenTransfer>)],
+    ) -> anyhow::Result<()> {
+        let candidates = transfers
+            .iter()
+            .flat_map(|(_, transfers)| transfers.iter())
+            .filter(|transfer| transfer.decimals == 0)
+            .map(|transfer| transfer.mint.as_str())
+            .collect::<BTreeSet<_>>();
+        let nfts = self.nft_mints(solana_client, db, candidates).await?;
+
+        let mut rows = Vec::new();
+
+        for (signature, transfers) in transfers {
+            for transfer in transfers.iter() {
+                rows.push((signature.as_str(), transfer, nfts.contains(&transfer.mint)));
+            }
+        }
+
+        insert_token_transfers(db, &rows).await?;
+
+        Ok(())
+    }
+
+    /// Which of `candidates` are NFTs.
+    async fn nft_mints(
+        &self,
+        solana_client: &SolanaClient,
+        db: &Arc<PgPool>,
+        candidates: BTreeSet<&str>,
+    ) -> anyhow::Result<HashSet<String>> {
+        let (mut nfts, unknown) = self.cached(candidates);
+
+        if unknown.is_empty() {
+            return Ok(nfts);
+        }
+
+        // stored by an earlier run
+        let stored = get_nft_mints(db, &unknown).await?;
+        let unknown = unknown
+            .iter()
+            .filter(|mint| !stored.contains(*mint))
+            .filter_map(|mint| Pubkey::from_str(mint).ok())
+            .collect::<Vec<_>>();
+
+        let supplies = solana_client.fetch_mints(&unknown).await?;
+        let new_nfts = unknown
+            .iter()
+            .filter(|mint| {
+                supplies
+                    .get(&mint.to_string())
+                    .is_some_and(|(supply, decimals)| is_nft(*supply, *decimals))
+            })
+            .copied()
+            .collect::<Vec<_>>();
+
+        let metadata = solana_client.fetch_token_metadata(&new_nfts).await?;
+        let fetched_at = unix_now();
+
+        for (mint, metadata) in &metadata {
+            upsert_nft(db, mint, metadata, fetched_at).await?;
+        }
+
+        if !new_nfts.is_empty() {
+            info!(
+                "Found {} new NFTs, {} with metadata",
+                new_nfts.len(),
+                metadata.len()
+            );
+        }
+
+        let new_nfts = new_nfts
+            .iter()
+            .map(|mint| mint.to_string())
+            .collect::<HashSet<_>>();
+
+        self.remember(
+            stored
+                .iter()
+                .map(|mint| (mint.clone(), true))
+                .chain(unknown.iter().map(|mint| {
+                    let mint = mint.to_string();
+                    let nft = new_nfts.contains(&mint);
+                    (mint, nft)
+                })),
+        );
+
+        nfts.extend(stored);
+        nfts.extend(new_nfts);
+
+        Ok(nfts)
+    }
+
+    /// Split `candidates` into the ones known to be NFTs and the ones not looked up yet.
+    fn cached(&self, candidates: BTreeSet<&str>) -> (HashSet<String>, Vec<String>) {
+        let mints = self.mints.lock().expect("nft cache lock poisoned");
+        let mut nfts = HashSet::new();
+        let mut unknown = Vec::new();
+
+        for mint in candidates {
+            match mints.get(mint) {
+                Some(true) => {
+                    nfts.insert(mint.to_string());
+                }
+                Some(false) => {}
+                None => unknown.push(mint.to_string()),
+            }
+        }
+
+        (nfts, unknown)
+    }
+
+    fn remember(&self, classified: impl IntoIterator<Item = (String, bool)>) {
+        let mut mints = self.mints.lock().expect("nft cache lock poisoned");
+
+        for (mint, nft) in classified {
+            if mints.len() >= self.max_cached_mints {
+                mints.clear();
+            }
+
+            if self.max_cached_mints > 0 {
+                mints.insert(mint, nft);
+            }
+        }
+    }
+}
+
+/// Whether a mint with this supply and decimals is an NFT.
+fn is_nft(supply: u64, decimals: u8) -> bool {
+    supply == 1 && decimals == 0
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_classifies_and_starts_over() {
+        let enricher = NftEnricher::new(&EnrichmentConfig {
+            nfts: true,
+            max_cached_mints: 2,
+        });
+
+        enricher.remember([("nft".to_string(), true), ("usdc".to_string(), false)]);
+
+        let (nfts, unknown) = enricher.cached(BTreeSet::from(["nft", "usdc", "bonk"]));
+        assert_eq!(nfts, HashSet::from(["nft".to_string()]));
+        assert_eq!(unknown, vec!["bonk".to_string()]);
+
+        // a full cache starts over
+        enricher.remember([("bonk".to_string(), false)]);
+        let (nfts, unknown) = enricher.cached(BTreeSet::from(["nft", "bonk"]));
+        assert!(nfts.is_empty());
+        assert_eq!(unknown, vec!["nft".to_string()]);
+
+        assert!(is_nft(1, 0));
+        assert!(!is_nft(1, 6));
+        assert!(!is_nft(1000, 0));
+    }
+}
@@ -0,0 +1,189 @@
+// Tracks the cluster's epochs
+
+// Responsibilities:
+// * Poll the current epoch and slot, and record the boundaries of every epoch seen in `epochs`.
+// * Tag stored transactions with the epoch their slot falls in, so an epoch's transactions can be
+//   listed.
+
+// Implementation:
+// * `getEpochInfo` gives the current epoch, the latest slot and its index in the epoch, from which
+//   the epoch's first and last slots follow.
+// * Transactions are tagged after insert, `TAG_BATCH` rows per statement, rather than on the way
+//   in, so every ingestion path is covered without threading the epoch through it. Tags follow
+//   from the slot and the cluster's epoch schedule, fetched once, so transactions backfilled from
+//   earlier epochs are tagged too.
+// * Warmup epochs, which only exist early in a cluster's life, have varying lengths and are left
+//   untagged, as are rows stored before slots were recorded.
+
+use crate::{
+    data_retrieval::SolanaClient,
+    data_storage::{tag_transaction_epochs, upsert_epoch},
+    schedules::Job,
+};
+
+use log::info;
+use serde::Serialize;
+use solana_sdk::{epoch_info::EpochInfo, epoch_schedule::EpochSchedule};
+use sqlx::PgPool;
+use tokio_util::sync::CancellationToken;
+
+use std::{
+    sync::{Arc, OnceLock, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Transactions tagged per statement.
+const TAG_BATCH: i64 = 10_000;
+
+/// The current epoch as last polled.
+#[derive(Debug, Clone, Serialize)]
+pub struct CurrentEpoch {
+    pub epoch: u64,
+    pub first_slot: u64,
+    pub last_slot: u64,
+    pub slots_in_epoch: u64,
+    /// Latest slot, and its position in the epoch.
+    pub absolute_slot: u64,
+    pub slot_index: u64,
+    /// Share of the epoch's slots that have passed, from 0 to 1.
+    pub progress: f64,
+    pub block_height: u64,
+    /// Transactions processed by the cluster since genesis, if the node reports it.
+    pub transaction_count: Option<u64>,
+    /// Unix seconds.
+    pub observed_at: i64,
+}
+
+impl CurrentEpoch {
+    fn new(info: &EpochInfo, observed_at: i64) -> Self {
+        let first_slot = info.absolute_slot - info.slot_index;
+
+        CurrentEpoch {
+            epoch: info.epoch,
+            first_slot,
+            last_slot: first_slot + info.slots_in_epoch.saturating_sub(1),
+            slots_in_epoch: info.slots_in_epoch,
+            absolute_slot: info.absolute_slot,
+            slot_index: info.slot_index,
+            progress: info.slot_index as f64 / info.slots_in_epoch.max(1) as f64,
+            block_height: info.block_height,
+            transaction_count: info.transaction_count,
+            observed_at,
+        }
+    }
+}
+
+pub struct EpochTracker {
+    solana_client: Arc<SolanaClient>,
+    schedule: OnceLock<EpochSchedule>,
+    current: RwLock<Option<CurrentEpoch>>,
+}
+
+impl EpochTracker {
+    pub fn new(solana_client: Arc<SolanaClient>) -> Self {
+        EpochTracker {
+            solana_client,
+            schedule: OnceLock::new(),
+            current: RwLock::new(None),
+        }
+    }
+
+    /// The current epoch as of the last poll, `None` before the first one succeeded.
+    pub fn current(&self) -> Option<CurrentEpoch> {
+        self.current
+            .read()
+            .expect("epoch tracker lock poisoned")
+            .clone()
+    }
+
+    /// Poll the epoch and tag new transactions every time `job` is due, until `shutdown` is
+    /// cancelled.
+    pub async fn run(&self, db: &Arc<PgPool>, job: &Job, shutdown: &CancellationToken) {
+        while job.tick(shutdown).await {
+            let _ = job.run(self.refresh(db)).await;
+        }
+    }
+
+    async fn refresh(&self, db: &Arc<PgPool>) -> anyhow::Result<()> {
+        let current = CurrentEpoch::new(&self.solana_client.epoch_info().await?, unix_now());
+
+        upsert_epoch(
+            db,
+            current.epoch as i64,
+            current.first_slot as i64,
+            current.last_slot as i64,
+            current.observed_at,
+        )
+        .await?;
+
+        *self.current.write().expect("epoch tracker lock poisoned") = Some(current);
+
+        let schedule = self.schedule().await?;
+        let mut tagged = 0;
+
+        loop {
+            let rows = tag_transaction_epochs(
+                db,
+                schedule.first_normal_epoch as i64,
+                schedule.first_normal_slot as i64,
+                schedule.slots_per_epoch as i64,
+                TAG_BATCH,
+            )
+            .await?;
+            tagged += rows;
+
+            if rows < TAG_BATCH as u64 {
+                break;
+            }
+        }
+
+        if tagged > 0 {
+            info!("Tagged {tagged} transactions with their epoch");
+        }
+
+        Ok(())
+    }
+
+    async fn schedule(&self) -> anyhow::Result<&EpochSchedule> {
+        if let Some(schedule) = self.schedule.get() {
+            return Ok(schedule);
+        }
+
+        let schedule = self.solana_client.epoch_schedule().await?;
+
+        Ok(self.schedule.get_or_init(|| schedule))
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_current_epoch_boundaries() {
+        let info = EpochInfo {
+            epoch: 700,
+            slot_index: 108_000,
+            slots_in_epoch: 432_000,
+            absolute_slot: 302_508_000,
+            block_height: 280_000_000,
+            transaction_count: Some(400_000_000_000),
+        };
+
+        let current = CurrentEpoch::new(&info, 1_760_000_000);
+        assert_eq!(current.first_slot, 302_400_000);
+        assert_eq!(current.last_slot, 302_831_999);
+        assert_eq!(current.progress, 0.25);
+
+        // slots are tagged with the same epoch from the schedule
+        let schedule = EpochSchedule::without_warmup();
+        assert_eq!(schedule.get_epoch(current.first_slot), 700);
+        assert_eq!(schedule.get_epoch(current.last_slot), 700);
+    }
+}
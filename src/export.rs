@@ -0,0 +1,565 @@
+// Exports stored transactions as CSV, Parquet, newline-delimited JSON, MessagePack or CBOR
+
+// Responsibilities:
+// * Stream every transaction matching a time range and address to the client as a CSV or Parquet
+//   file, for analysts who work with files rather than the API.
+// * Stream large `/transactions` results as newline-delimited JSON, one transaction per line as it
+//   would appear in the JSON array, instead of building the whole array in memory. MessagePack
+//   and CBOR clients get the same records, back to back.
+
+// Implementation:
+// * A background task pages through storage `PAGE_SIZE` rows at a time, by id, and sends each
+//   encoded page down a bounded channel that the response body reads from. Only a page and a few
+//   chunks are in memory at once, and a slow client slows the export rather than buffering it.
+// * Pages are keyset queries rather than one long-lived database cursor, so a slow download
+//   doesn't hold a connection and every backend streams alike. The query's `after` and `limit`
+//   still apply, the limit to the export as a whole.
+// * CSV is written with a header row; Parquet with the `parquet` crate's Arrow writer, zstd
+//   compressed, in row groups of `ROW_GROUP_ROWS`. The Parquet footer comes last, so a Parquet
+//   export is only readable once complete.
+// * An error partway through aborts the response, so clients see a truncated transfer rather than
+//   a file that silently ends early.
+// * Log messages aren't exported to CSV or Parquet; fetch them per transaction through the API.
+// * Exports made with an API key are metered a page at a time, as the pages are read.
+
+use crate::{
+    data_processing::TransactionData,
+    data_storage::{CommitmentStatus, Storage, StoredTransaction, TransactionQuery},
+    encoding::Encoding,
+    ids::IdCodec,
+    labels::LabelRegistry,
+    metering::Tenant,
+};
+
+use actix_web::web::Bytes;
+use arrow::{
+    array::{ArrayRef, Int64Array, StringArray, UInt64Array},
+    datatypes::{DataType, Field, Schema, SchemaRef},
+    record_batch::RecordBatch,
+};
+use clap::ValueEnum;
+use log::error;
+use parquet::{
+    arrow::ArrowWriter,
+    basic::{Compression, ZstdLevel},
+    file::properties::WriterProperties,
+};
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
+
+use std::{io, sync::Arc};
+
+/// Rows fetched from storage at a time.
+const PAGE_SIZE: i64 = 1000;
+
+const ROW_GROUP_ROWS: usize = 100_000;
+
+/// Encoded chunks buffered ahead of the client.
+const CHANNEL_CAPACITY: usize = 4;
+
+const COLUMNS: [&str; 12] = [
+    "id",
+    "signature",
+    "sender",
+    "receiver",
+    "sol_amount",
+    "fee",
+    "timestamp",
+    "slot",
+    "prev_blockhash",
+    "error",
+    "compute_units_consumed",
+    "fee_payer",
+];
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportFormat {
+    #[default]
+    Csv,
+    Parquet,
+    Ndjson,
+    Msgpack,
+    Cbor,
+}
+
+impl ExportFormat {
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+            ExportFormat::Ndjson => "application/x-ndjson",
+            ExportFormat::Msgpack => Encoding::MessagePack.content_type(),
+            ExportFormat::Cbor => Encoding::Cbor.content_type(),
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Parquet => "parquet",
+            ExportFormat::Ndjson => "ndjson",
+            ExportFormat::Msgpack => "msgpack",
+            ExportFormat::Cbor => "cbor",
+        }
+    }
+
+    /// How each record is encoded, for formats streamed a record at a time.
+    fn encoding(self) -> Option<Encoding> {
+        match self {
+            ExportFormat::Csv | ExportFormat::Parquet => None,
+            ExportFormat::Ndjson => Some(Encoding::Json),
+            ExportFormat::Msgpack => Some(Encoding::MessagePack),
+            ExportFormat::Cbor => Some(Encoding::Cbor),
+        }
+    }
+}
+
+/// A transaction as a record of a newline-delimited JSON, MessagePack or CBOR stream, shaped like
+/// the API's responses.
+#[derive(Serialize)]
+struct Record<'a> {
+    id: String,
+    #[serde(flatten)]
+    transaction: &'a TransactionData,
+    sender_label: Option<String>,
+    receiver_label: Option<String>,
+    commitment_status: CommitmentStatus,
+}
+
+/// Stream the transactions matching `query` as a file, labelling them from `labels` in records and
+/// metering the rows read for `tenant`, if any.
+pub fn export(
+    storage: Arc<dyn Storage>,
+    ids: IdCodec,
+    labels: Arc<LabelRegistry>,
+    query: TransactionQuery,
+    format: ExportFormat,
+    tenant: Option<Tenant>,
+) -> ReceiverStream<Result<Bytes, io::Error>> {
+    let (sender, receiver) = mpsc::channel(CHANNEL_CAPACITY);
+
+    tokio::spawn(async move {
+        let pages = Pages::new(storage, query, tenant);
+
+        let result = match format {
+            ExportFormat::Csv => export_csv(pages, &ids, &sender).await,
+            ExportFormat::Parquet => export_parquet(pages, &ids, &sender).await,
+            ExportFormat::Ndjson | ExportFormat::Msgpack | ExportFormat::Cbor => {
+                export_records(pages, &ids, &labels, format, &sender).await
+            }
+        };
+
+        if let Err(e) = result {
+            error!("Failed to export transactions: {e:?}");
+            let _ = sender.send(Err(io::Error::other(e.to_string()))).await;
+        }
+    });
+
+    ReceiverStream::new(receiver)
+}
+
+/// Matching transactions, a page at a time in id order.
+struct Pages {
+    storage: Arc<dyn Storage>,
+    query: TransactionQuery,
+    /// Rows left to read; `None` reads every matching row.
+    remaining: Option<i64>,
+    tenant: Option<Tenant>,
+    done: bool,
+}
+
+impl Pages {
+    fn new(storage: Arc<dyn Storage>, query: TransactionQuery, tenant: Option<Tenant>) -> Self {
+        Pages {
+            storage,
+            remaining: query.limit.map(|limit| limit.max(0)),
+            query,
+            tenant,
+            done: false,
+        }
+    }
+
+    async fn next(&mut self) -> anyhow::Result<Option<Vec<StoredTransaction>>> {
+        let size = self
+            .remaining
+            .map_or(PAGE_SIZE, |remaining| remaining.min(PAGE_SIZE));
+
+        if self.done || size == 0 {
+            return Ok(None);
+        }
+
+        self.query.limit = Some(size);
+        let page = self.storage.query(&self.query).await?;
+        self.done = (page.len() as i64) < size;
+        self.remaining = self
+            .remaining
+            .map(|remaining| remaining - page.len() as i64);
+
+        if let Some(tenant) = &self.tenant {
+            tenant.record_rows(page.len());
+        }
+
+        match page.last() {
+            Some(last) => self.query.after = last.id,
+            None => return Ok(None),
+        }
+
+        Ok(Some(page))
+    }
+}
+
+/// Send a chunk to the client. `false` once the client has gone away.
+async fn send(sender: &mpsc::Sender<Result<Bytes, io::Error>>, chunk: Vec<u8>) -> bool {
+    chunk.is_empty() || sender.send(Ok(Bytes::from(chunk))).await.is_ok()
+}
+
+async fn export_csv(
+    mut pages: Pages,
+    ids: &IdCodec,
+    sender: &mpsc::Sender<Result<Bytes, io::Error>>,
+) -> anyhow::Result<()> {
+    let mut header = csv::Writer::from_writer(Vec::new());
+    header.write_record(COLUMNS)?;
+
+    if !send(sender, header.into_inner()?).await {
+        return Ok(());
+    }
+
+    while let Some(page) = pages.next().await? {
+        if !send(sender, csv_rows(&page, ids)?).await {
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
+
+fn csv_rows(page: &[StoredTransaction], ids: &IdCodec) -> anyhow::Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+
+    for stored in page {
+        let txn = &stored.transaction;
+
+        writer.write_record([
+            ids.encode(stored.id),
+            txn.signature.clone(),
+            txn.sender.clone(),
+            txn.receiver.clone(),
+            txn.sol_amount.to_string(),
+            txn.fee.to_string(),
+            txn.timestamp.to_string(),
+            txn.slot.to_string(),
+            txn.prev_blockhash.clone(),
+            txn.error.clone().unwrap_or_default(),
+            txn.compute_units_consumed
+                .map(|units| units.to_string())
+                .unwrap_or_default(),
+            txn.fee_payer.clone().unwrap_or_default(),
+        ])?;
+    }
+
+    Ok(writer.into_inner()?)
+}
+
+async fn export_records(
+    mut pages: Pages,
+    ids: &IdCodec,
+    labels: &LabelRegistry,
+    format: ExportFormat,
+    sender: &mpsc::Sender<Result<Bytes, io::Error>>,
+) -> anyhow::Result<()> {
+    while let Some(page) = pages.next().await? {
+        if !send(sender, records(&page, ids, labels, format)?).await {
+            return Ok(());
+        }
+    }
+
+    Ok(())
+}
+
+fn records(
+    page: &[StoredTransaction],
+    ids: &IdCodec,
+    labels: &LabelRegistry,
+    format: ExportFormat,
+) -> anyhow::Result<Vec<u8>> {
+    let encoding = format.encoding().unwrap_or_default();
+    let mut records = Vec::new();
+
+    for stored in page {
+        let txn = &stored.transaction;
+
+        encoding.write(
+            &mut records,
+            &Record {
+                id: ids.encode(stored.id),
+                transaction: txn,
+                sender_label: labels.label(&txn.sender),
+                receiver_label: labels.label(&txn.receiver),
+                commitment_status: stored.commitment_status,
+            },
+        )?;
+
+        if format == ExportFormat::Ndjson {
+            records.push(b'\n');
+        }
+    }
+
+    Ok(records)
+}
+
+async fn export_parquet(
+    mut pages: Pages,
+    ids: &IdCodec,
+    sender: &mpsc::Sender<Result<Bytes, io::Error>>,
+) -> anyhow::Result<()> {
+    let schema = parquet_schema();
+    let properties = WriterProperties::builder()
+        .set_compression(Compression::ZSTD(ZstdLevel::default()))
+        .set_max_row_group_size(ROW_GROUP_ROWS)
+        .build();
+    let mut writer = ArrowWriter::try_new(Vec::new(), Arc::clone(&schema), Some(properties))?;
+
+    while let Some(page) = pages.next().await? {
+        writer.write(&record_batch(&page, ids, &schema)?)?;
+
+        // whatever the writer has flushed so far, i.e. completed row groups
+        if !send(sender, std::mem::take(writer.inner_mut())).await {
+            return Ok(());
+        }
+    }
+
+    send(sender, writer.into_inner()?).await;
+
+    Ok(())
+}
+
+fn parquet_schema() -> SchemaRef {
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Utf8, false),
+        Field::new("signature", DataType::Utf8, false),
+        Field::new("sender", DataType::Utf8, false),
+        Field::new("receiver", DataType::Utf8, false),
+        Field::new("sol_amount", DataType::UInt64, false),
+        Field::new("fee", DataType::UInt64, false),
+        Field::new("timestamp", DataType::Int64, false),
+        Field::new("slot", DataType::UInt64, false),
+        Field::new("prev_blockhash", DataType::Utf8, false),
+        Field::new("error", DataType::Utf8, true),
+        Field::new("compute_units_consumed", DataType::UInt64, true),
+        Field::new("fee_payer", DataType::Utf8, true),
+    ]))
+}
+
+fn record_batch(
+    page: &[StoredTransaction],
+    ids: &IdCodec,
+    schema: &SchemaRef,
+) -> anyhow::Result<RecordBatch> {
+    let txns = page.iter().map(|stored| &stored.transaction);
+    let strings = |field: fn(&StoredTransaction) -> &str| -> ArrayRef {
+        Arc::new(StringArray::from_iter_values(page.iter().map(field)))
+    };
+
+    let columns: Vec<ArrayRef> = vec![
+        Arc::new(StringArray::from_iter_values(
+            page.iter().map(|stored| ids.encode(stored.id)),
+        )),
+        strings(|stored| &stored.transaction.signature),
+        strings(|stored| &stored.transaction.sender),
+        strings(|stored| &stored.transaction.receiver),
+        Arc::new(UInt64Array::from_iter_values(
+            txns.clone().map(|txn| txn.sol_amount),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            txns.clone().map(|txn| txn.fee),
+        )),
+        Arc::new(Int64Array::from_iter_values(
+            txns.clone().map(|txn| txn.timestamp),
+        )),
+        Arc::new(UInt64Array::from_iter_values(
+            txns.clone().map(|txn| txn.slot),
+        )),
+        strings(|stored| &stored.transaction.prev_blockhash),
+        Arc::new(
+            txns.clone()
+                .map(|txn| txn.error.as_deref())
+                .collect::<StringArray>(),
+        ),
+        Arc::new(
+            txns.clone()
+                .map(|txn| txn.compute_units_consumed)
+                .collect::<UInt64Array>(),
+        ),
+        Arc::new(
+            txns.map(|txn| txn.fee_payer.as_deref())
+                .collect::<StringArray>(),
+        ),
+    ];
+
+    Ok(RecordBatch::try_new(Arc::clone(schema), columns)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{data_storage::InMemoryStorage, labels::Label};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use solana_sdk::pubkey::Pubkey;
+    use tokio_stream::StreamExt;
+
+    fn txn(i: u64, sender: &str) -> TransactionData {
+        TransactionData {
+            signature: format!("sig{i}"),
+            sender: sender.to_string(),
+            receiver: "receiver".to_string(),
+            sol_amount: i,
+            fee: 5000,
+            timestamp: 1_700_000_000 + i as i64,
+            prev_blockhash: "hash".to_string(),
+            slot: i,
+            error: (i == 1).then(|| r#"{"InstructionError":[0,"Custom"]}"#.to_string()),
+            compute_units_consumed: Some(150),
+            success: i != 1,
+            ..TransactionData::default()
+        }
+    }
+
+    async fn storage(count: u64) -> Arc<dyn Storage> {
+        let storage = InMemoryStorage::new();
+        let txns = (1..=count)
+            .map(|i| txn(i, if i % 2 == 0 { "even" } else { "odd" }))
+            .collect::<Vec<_>>();
+        storage.insert_batch(&txns).await.unwrap();
+
+        Arc::new(storage)
+    }
+
+    async fn collect(stream: ReceiverStream<Result<Bytes, io::Error>>) -> Vec<u8> {
+        stream
+            .map(|chunk| chunk.unwrap())
+            .collect::<Vec<_>>()
+            .await
+            .concat()
+    }
+
+    #[tokio::test]
+    async fn test_csv_export_filters_and_quotes() {
+        let query = TransactionQuery {
+            address: Some("odd".to_string()),
+            to: Some(1_700_000_003),
+            ..Default::default()
+        };
+
+        let csv = collect(export(
+            storage(5).await,
+            IdCodec::Raw,
+            Arc::new(LabelRegistry::new()),
+            query,
+            ExportFormat::Csv,
+            None,
+        ))
+        .await;
+        let csv = String::from_utf8(csv).unwrap();
+        let lines = csv.lines().collect::<Vec<_>>();
+
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[0], COLUMNS.join(","));
+        assert_eq!(
+            lines[1],
+            r#"1,sig1,odd,receiver,1,5000,1700000001,1,hash,"{""InstructionError"":[0,""Custom""]}",150,"#
+        );
+        assert!(lines[2].starts_with("3,sig3,odd,"));
+    }
+
+    #[tokio::test]
+    async fn test_parquet_export_spans_pages() {
+        let count = PAGE_SIZE as u64 * 2 + 1;
+        let parquet = collect(export(
+            storage(count).await,
+            IdCodec::Raw,
+            Arc::new(LabelRegistry::new()),
+            TransactionQuery::default(),
+            ExportFormat::Parquet,
+            None,
+        ))
+        .await;
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(Bytes::from(parquet))
+            .unwrap()
+            .build()
+            .unwrap();
+        let batches = reader.collect::<Result<Vec<_>, _>>().unwrap();
+
+        let rows = batches.iter().map(|batch| batch.num_rows()).sum::<usize>();
+        assert_eq!(rows as u64, count);
+        let schema = batches[0].schema();
+        let columns = schema
+            .fields()
+            .iter()
+            .map(|field| field.name().as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(columns, COLUMNS);
+    }
+
+    #[tokio::test]
+    async fn test_ndjson_export_labels_and_limits() {
+        let labels = Arc::new(LabelRegistry::new());
+        let labelled = Pubkey::new_unique().to_string();
+        labels
+            .register(Label {
+                pubkey: labelled.clone(),
+                label: "Treasury".to_string(),
+                tags: vec![],
+            })
+            .unwrap();
+
+        let storage = storage(PAGE_SIZE as u64 + 10).await;
+        storage.insert(&txn(0, &labelled)).await.unwrap();
+
+        let query = TransactionQuery {
+            after: 5,
+            limit: Some(PAGE_SIZE + 2),
+            ..Default::default()
+        };
+        let ndjson = collect(export(
+            Arc::clone(&storage),
+            IdCodec::Raw,
+            Arc::clone(&labels),
+            query,
+            ExportFormat::Ndjson,
+            None,
+        ))
+        .await;
+        let lines = String::from_utf8(ndjson).unwrap();
+        let lines = lines
+            .lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap())
+            .collect::<Vec<_>>();
+
+        // the limit spans pages
+        assert_eq!(lines.len() as i64, PAGE_SIZE + 2);
+        assert_eq!(lines[0]["id"], "6");
+        assert_eq!(lines[0]["signature"], "sig6");
+        assert!(lines[0]["sender_label"].is_null());
+
+        let query = TransactionQuery {
+            address: Some(labelled),
+            ..Default::default()
+        };
+        let ndjson = collect(export(
+            storage,
+            IdCodec::Raw,
+            labels,
+            query,
+            ExportFormat::Ndjson,
+            None,
+        ))
+        .await;
+        let line = serde_json::from_slice::<serde_json::Value>(&ndjson).unwrap();
+        assert_eq!(line["sender_label"], "Treasury");
+        assert!(ndjson.ends_with(b"\n"));
+    }
+}
@@ -0,0 +1,131 @@
+// Splits transaction fees into the burned portion and the validator's share
+
+// Responsibilities:
+// * Work out, per transaction, how much of the fee was burned and how much went to the leader.
+// * Apply the burn rule that was in force in the transaction's epoch.
+
+// Implementation:
+// * The base fee is `lamports_per_signature` per signature; anything above it is priority fee.
+// * 50% of the base fee is always burned.
+// * Priority fees were also 50% burned until SIMD-0096, after which they go entirely to the
+//   validator. The activation epoch differs per cluster, so it is configurable.
+
+use crate::config::FeeBurnConfig;
+
+use serde::Serialize;
+use solana_sdk::epoch_schedule::EpochSchedule;
+use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, EncodedTransaction};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct FeeSplit {
+    pub epoch: u64,
+    pub base_fee: u64,
+    pub priority_fee: u64,
+    pub burned: u64,
+    pub to_validator: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct FeeBurnPolicy {
+    epoch_schedule: EpochSchedule,
+    lamports_per_signature: u64,
+    priority_fee_to_validator_epoch: Option<u64>,
+}
+
+impl FeeBurnPolicy {
+    pub fn new(epoch_schedule: EpochSchedule, config: &FeeBurnConfig) -> Self {
+        FeeBurnPolicy {
+            epoch_schedule,
+            lamports_per_signature: config.lamports_per_signature,
+            priority_fee_to_validator_epoch: config.priority_fee_to_validator_epoch,
+        }
+    }
+
+    /// Split the fee of a single transaction, keyed by its first signature.
+    pub fn split(
+        &self,
+        txn: &EncodedConfirmedTransactionWithStatusMeta,
+    ) -> Option<(String, FeeSplit)> {
+        let EncodedTransaction::Json(ui_txn) = &txn.transaction.transaction else {
+            return None;
+        };
+
+        let signature = ui_txn.signatures.first()?.clone();
+        let fee = txn.transaction.meta.as_ref()?.fee;
+        let epoch = self.epoch_schedule.get_epoch(txn.slot);
+
+        Some((
+            signature,
+            self.split_fee(fee, ui_txn.signatures.len() as u64, epoch),
+        ))
+    }
+
+    fn split_fee(&self, fee: u64, signature_count: u64, epoch: u64) -> FeeSplit {
+        let base_fee = fee.min(self.lamports_per_signature * signature_count);
+        let priority_fee = fee - base_fee;
+
+        let priority_fee_burned = match self.priority_fee_to_validator_epoch {
+            Some(activation_epoch) if epoch >= activation_epoch => 0,
+            _ => priority_fee / 2,
+        };
+
+        let burned = base_fee / 2 + priority_fee_burned;
+
+        FeeSplit {
+            epoch,
+            base_fee,
+            priority_fee,
+            burned,
+            to_validator: fee - burned,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(priority_fee_to_validator_epoch: Option<u64>) -> FeeBurnPolicy {
+        FeeBurnPolicy::new(
+            EpochSchedule::without_warmup(),
+            &FeeBurnConfig {
+                lamports_per_signature: 5000,
+                priority_fee_to_validator_epoch,
+            },
+        )
+    }
+
+    #[test]
+    fn test_base_fee_only() {
+        let split = policy(None).split_fee(5000, 1, 10);
+
+        assert_eq!(split.base_fee, 5000);
+        assert_eq!(split.priority_fee, 0);
+        assert_eq!(split.burned, 2500);
+        assert_eq!(split.to_validator, 2500);
+    }
+
+    #[test]
+    fn test_priority_fee_before_and_after_activation() {
+        let policy = policy(Some(100));
+
+        let before = policy.split_fee(15_000, 2, 99);
+        assert_eq!(before.base_fee, 10_000);
+        assert_eq!(before.priority_fee, 5000);
+        assert_eq!(before.burned, 7500);
+        assert_eq!(before.to_validator, 7500);
+
+        let after = policy.split_fee(15_000, 2, 100);
+        assert_eq!(after.burned, 5000);
+        assert_eq!(after.to_validator, 10_000);
+    }
+
+    #[test]
+    fn test_fee_below_base_fee() {
+        let split = policy(None).split_fee(3000, 1, 0);
+
+        assert_eq!(split.base_fee, 3000);
+        assert_eq!(split.priority_fee, 0);
+        assert_eq!(split.burned + split.to_validator, 3000);
+    }
+}
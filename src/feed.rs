@@ -0,0 +1,155 @@
+// Broadcasts newly stored transactions to live subscribers
+
+// Responsibilities:
+// * Publish every transaction the ingestion path newly stores, as soon as it is stored.
+// * Serve them to API clients as a Server-Sent Events stream, so dashboards don't have to poll.
+
+// Implementation:
+// * A `tokio::sync::broadcast` channel: publishing never waits on subscribers. A subscriber that
+//   falls more than `CHANNEL_CAPACITY` transactions behind skips the oldest ones and is sent a
+//   `lagged` event with how many it missed.
+// * Idle streams get an SSE comment every `KEEPALIVE`, so proxies don't close them.
+
+use crate::data_processing::TransactionData;
+
+use actix_web::web::Bytes;
+use log::error;
+use tokio::{
+    sync::broadcast,
+    time::{self, Duration, Instant},
+};
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream, IntervalStream},
+    Stream, StreamExt,
+};
+
+use std::{convert::Infallible, sync::Arc};
+
+/// Transactions a subscriber may fall behind by before it starts missing some.
+const CHANNEL_CAPACITY: usize = 1024;
+
+const KEEPALIVE: Duration = Duration::from_secs(15);
+
+#[derive(Debug)]
+pub struct TransactionFeed {
+    sender: broadcast::Sender<Arc<TransactionData>>,
+}
+
+impl Default for TransactionFeed {
+    fn default() -> Self {
+        TransactionFeed {
+            sender: broadcast::channel(CHANNEL_CAPACITY).0,
+        }
+    }
+}
+
+impl TransactionFeed {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Send newly stored transactions to every current subscriber.
+    pub fn publish<'a>(&self, txns: impl IntoIterator<Item = &'a TransactionData>) {
+        if self.sender.receiver_count() == 0 {
+            return;
+        }
+
+        for txn in txns {
+            // only fails when the last subscriber has just gone away
+            let _ = self.sender.send(Arc::new(txn.clone()));
+        }
+    }
+
+    /// Receiver of the transactions stored from now on, for consumers other than SSE.
+    pub fn receiver(&self) -> broadcast::Receiver<Arc<TransactionData>> {
+        self.sender.subscribe()
+    }
+
+    /// Server-Sent Events of the transactions stored from now on, optionally only those sent or
+    /// received by `address`.
+    pub fn subscribe(
+        &self,
+        address: Option<String>,
+    ) -> impl Stream<Item = Result<Bytes, Infallible>> + 'static {
+        let events = BroadcastStream::new(self.receiver())
+            .filter_map(move |received| subscriber_event(received, address.as_deref()));
+
+        let interval = time::interval_at(Instant::now() + KEEPALIVE, KEEPALIVE);
+        let keepalive =
+            IntervalStream::new(interval).map(|_| Bytes::from_static(b": keepalive\n\n"));
+
+        events.merge(keepalive).map(Ok)
+    }
+}
+
+/// The event to send a subscriber for what it received, if any.
+fn subscriber_event(
+    received: Result<Arc<TransactionData>, BroadcastStreamRecvError>,
+    address: Option<&str>,
+) -> Option<Bytes> {
+    match received {
+        Ok(txn) => {
+            let matches = address.map_or(true, |address| {
+                txn.sender == address || txn.receiver == address
+            });
+
+            matches.then(|| transaction_event(&txn)).flatten()
+        }
+        Err(BroadcastStreamRecvError::Lagged(skipped)) => Some(event(
+            "lagged",
+            &serde_json::json!({ "skipped": skipped }).to_string(),
+        )),
+    }
+}
+
+fn transaction_event(txn: &TransactionData) -> Option<Bytes> {
+    match serde_json::to_string(txn) {
+        Ok(json) => Some(event("transaction", &json)),
+        Err(e) => {
+            error!("Failed to serialize transaction {}: {e:?}", txn.signature);
+            None
+        }
+    }
+}
+
+/// Format one SSE event; `data` must not contain newlines.
+fn event(name: &str, data: &str) -> Bytes {
+    Bytes::from(format!("event: {name}\ndata: {data}\n\n"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transaction(signature: &str, sender: &str, receiver: &str) -> TransactionData {
+        TransactionData {
+            signature: signature.to_string(),
+            sender: sender.to_string(),
+            receiver: receiver.to_string(),
+            sol_amount: 1,
+            fee: 5000,
+            timestamp: 1_700_000_000,
+            ..TransactionData::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_subscribers_receive_matching_transactions() {
+        let feed = TransactionFeed::new();
+        let all = feed.subscribe(None);
+        let alice = feed.subscribe(Some("alice".to_string()));
+        tokio::pin!(all, alice);
+
+        feed.publish(&[
+            transaction("sig1", "bob", "carol"),
+            transaction("sig2", "alice", "bob"),
+        ]);
+
+        let first = all.next().await.unwrap().unwrap();
+        assert!(first.starts_with(b"event: transaction\ndata: {"));
+        assert!(String::from_utf8_lossy(&first).contains("\"sig1\""));
+
+        let second = alice.next().await.unwrap().unwrap();
+        assert!(String::from_utf8_lossy(&second).contains("\"sig2\""));
+    }
+}
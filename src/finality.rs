@@ -0,0 +1,233 @@
+// Upgrades stored transactions to finalized, or marks them dropped when a fork abandoned them
+
+// Responsibilities:
+// * Transactions are fetched and stored at `confirmed` commitment, which a fork can still roll
+//   back. Periodically re-check the stored transactions that aren't finalized yet against
+//   `getSignatureStatuses`, and record them as `finalized` or `dropped`.
+// * Take dropped transactions out of the counters and rollups, and report what was checked at
+//   `/admin/finality`.
+
+// Implementation:
+// * Postgres only: the other backends keep every transaction `confirmed`. The oldest
+//   `max_signatures_per_run` unfinalized transactions are checked per run, through a partial
+//   index over just those rows.
+// * Statuses are looked up with `searchTransactionHistory`, so transactions older than the RPC
+//   node's status cache are found too.
+// * A transaction the node doesn't know is only dropped once the finalized slot is more than
+//   `MAX_PROCESSING_AGE` slots past the one it was stored at: its blockhash has expired by then,
+//   so it can't land on the surviving fork anymore. Until then it stays `confirmed`.
+// * A transaction that landed again on the surviving fork is finalized as is, keeping the slot
+//   and block time it was first stored with.
+// * Dropped rows are kept, and left out of query results unless `include_dropped` is set. In
+//   TimescaleDB mode they stay counted in the continuous aggregates, which are computed from the
+//   rows themselves.
+// * Status changes invalidate the query cache, like inserts do.
+
+use crate::{
+    cache::CachedStorage,
+    config::FinalityConfig,
+    data_retrieval::SolanaClient,
+    data_storage::{
+        drop_transactions, finalize_transactions, get_unfinalized_transactions,
+        UnfinalizedTransaction,
+    },
+    schedules::Job,
+};
+
+use log::{info, warn};
+use serde::Serialize;
+use solana_sdk::{
+    clock::MAX_PROCESSING_AGE, commitment_config::CommitmentConfig, signature::Signature,
+};
+use solana_transaction_status::TransactionStatus;
+use sqlx::PgPool;
+use tokio_util::sync::CancellationToken;
+
+use std::{
+    str::FromStr,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct FinalityReport {
+    /// Since startup.
+    pub finalized: u64,
+    pub dropped: u64,
+    /// Transactions the latest run left `confirmed`.
+    pub pending: u64,
+    /// Finalized slot as of the latest run.
+    pub finalized_slot: Option<u64>,
+    /// Unix seconds.
+    pub last_checked_at: Option<i64>,
+}
+
+pub struct FinalityTracker {
+    max_signatures: i64,
+    /// Invalidated when statuses change, if queries are cached.
+    cache: Option<Arc<CachedStorage>>,
+    report: Mutex<FinalityReport>,
+}
+
+impl FinalityTracker {
+    pub fn new(config: &FinalityConfig, cache: Option<Arc<CachedStorage>>) -> Self {
+        FinalityTracker {
+            max_signatures: config.max_signatures_per_run as i64,
+            cache,
+            report: Mutex::new(FinalityReport::default()),
+        }
+    }
+
+    /// Re-check unfinalized transactions each time `job` is due, until `shutdown` is cancelled.
+    pub async fn run(
+        &self,
+        solana_client: &SolanaClient,
+        db: &Arc<PgPool>,
+        job: &Job,
+        shutdown: &CancellationToken,
+    ) {
+        while job.tick(shutdown).await {
+            let _ = job.run(self.check(solana_client, db)).await;
+        }
+    }
+
+    async fn check(&self, solana_client: &SolanaClient, db: &Arc<PgPool>) -> anyhow::Result<()> {
+        let unfinalized = get_unfinalized_transactions(db, self.max_signatures).await?;
+
+        if unfinalized.is_empty() {
+            self.record(0, 0, 0, None);
+            return Ok(());
+        }
+
+        let signatures = unfinalized
+            .iter()
+            .map(|txn| Signature::from_str(&txn.signature))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        // before the statuses, so whatever the slot has passed had its chance to be found
+        let finalized_slot = solana_client.fetch_finalized_slot().await?;
+        let statuses = solana_client.fetch_signature_statuses(&signatures).await?;
+        let (finalized, dropped) = settle(&unfinalized, &statuses, finalized_slot);
+
+        let finalized = finalize_transactions(db, &finalized).await?;
+        let dropped = drop_transactions(db, &dropped).await?;
+
+        if finalized > 0 {
+            info!("Finalized {finalized} stored transactions");
+        }
+
+        if dropped > 0 {
+            warn!("{dropped} stored transactions were dropped by a fork");
+        }
+
+        if finalized > 0 || dropped > 0 {
+            if let Some(cache) = &self.cache {
+                cache.invalidate().await;
+            }
+        }
+
+        let pending = (unfinalized.len() as u64).saturating_sub(finalized + dropped);
+        self.record(finalized, dropped, pending, Some(finalized_slot));
+
+        Ok(())
+    }
+
+    fn record(&self, finalized: u64, dropped: u64, pending: u64, finalized_slot: Option<u64>) {
+        let mut report = self.report.lock().expect("finality report lock poisoned");
+
+        report.finalized += finalized;
+        report.dropped += dropped;
+        report.pending = pending;
+        report.finalized_slot = finalized_slot.or(report.finalized_slot);
+        report.last_checked_at = Some(unix_now());
+    }
+
+    pub fn report(&self) -> FinalityReport {
+        self.report
+            .lock()
+            .expect("finality report lock poisoned")
+            .clone()
+    }
+}
+
+/// Signatures of the transactions to mark finalized, and of those to mark dropped, given their
+/// statuses in the same order.
+fn settle(
+    unfinalized: &[UnfinalizedTransaction],
+    statuses: &[Option<TransactionStatus>],
+    finalized_slot: u64,
+) -> (Vec<String>, Vec<String>) {
+    let mut finalized = Vec::new();
+    let mut dropped = Vec::new();
+
+    for (txn, status) in unfinalized.iter().zip(statuses) {
+        match status {
+            Some(status) if status.satisfies_commitment(CommitmentConfig::finalized()) => {
+                finalized.push(txn.signature.clone());
+            }
+            None if finalized_slot > txn.slot as u64 + MAX_PROCESSING_AGE as u64 => {
+                dropped.push(txn.signature.clone());
+            }
+            _ => {}
+        }
+    }
+
+    (finalized, dropped)
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use solana_transaction_status::TransactionConfirmationStatus;
+
+    fn unfinalized(signature: &str, slot: i64) -> UnfinalizedTransaction {
+        UnfinalizedTransaction {
+            signature: signature.to_string(),
+            slot,
+        }
+    }
+
+    fn status(slot: u64, confirmations: Option<usize>) -> TransactionStatus {
+        TransactionStatus {
+            slot,
+            confirmations,
+            status: Ok(()),
+            err: None,
+            confirmation_status: Some(match confirmations {
+                Some(_) => TransactionConfirmationStatus::Confirmed,
+                None => TransactionConfirmationStatus::Finalized,
+            }),
+        }
+    }
+
+    #[test]
+    fn test_settle_finalizes_rooted_and_drops_expired_unknown() {
+        let unfinalized = [
+            unfinalized("rooted", 1000),
+            unfinalized("voting", 1100),
+            unfinalized("expired", 1000),
+            unfinalized("recent", 1100),
+        ];
+        let statuses = [
+            Some(status(1000, None)),
+            Some(status(1100, Some(20))),
+            None,
+            None,
+        ];
+
+        let (finalized, dropped) = settle(&unfinalized, &statuses, 1200);
+        assert_eq!(finalized, vec!["rooted".to_string()]);
+        assert_eq!(dropped, vec!["expired".to_string()]);
+
+        // nothing is dropped before its blockhash could have expired
+        let (_, dropped) = settle(&unfinalized, &statuses, 1000 + MAX_PROCESSING_AGE as u64);
+        assert!(dropped.is_empty());
+    }
+}
@@ -0,0 +1,282 @@
+// Finds and repairs gaps in the stored transactions of watched addresses and programs
+
+// Responsibilities:
+// * Periodically compare each watched address's and program's signatures on chain with the stored
+//   ones, so transactions missed while the process was down or a poll failed are found.
+// * Fetch, process and store the missing transactions, and report what was found and repaired,
+//   per address at `/gaps` and in total at `/admin/ingest`.
+
+// Implementation:
+// * Polls only look at an address's latest few signatures, so anything older than that when a
+//   poll resumes would otherwise never be fetched.
+// * Each run pages through `getSignaturesForAddress` from the newest signature back to the newest
+//   one the previous run fully reconciled, or `max_signatures_per_address` back on the first run
+//   after startup. Downtime is covered by that first run, as long as the address had fewer
+//   transactions than that in the meantime.
+// * On that first run, signatures older than the oldest stored one among them are history rather
+//   than a gap, so reconciliation never backfills an address, not even one with nothing stored.
+// * Stored signatures are looked up in one query per address; the missing ones are fetched
+//   `REPAIR_CHUNK` at a time, within the memory budget, and stored like polled ones.
+// * Transactions the pipeline settings leave out are never stored, so they show up as missing
+//   but not repaired. The reconciled position only moves once every missing transaction could
+//   be fetched, so transactions the RPC node failed to return are retried by the next run.
+
+use crate::{
+    config::GapConfig, data_retrieval::SolanaClient, data_storage::Storage, schedules::Job,
+    watchlist::Watchlist,
+};
+
+use log::{info, warn};
+use serde::Serialize;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use sqlx::PgPool;
+use tokio_util::sync::CancellationToken;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Missing transactions fetched and stored at a time.
+const REPAIR_CHUNK: usize = 100;
+
+/// Reconciliation of one address since startup.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AddressGaps {
+    /// Newest signature up to which everything is stored or was left out on purpose.
+    pub reconciled_through: Option<String>,
+    /// Unix seconds.
+    pub last_checked_at: Option<i64>,
+    pub signatures_checked: u64,
+    pub missing: u64,
+    pub repaired: u64,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct GapReport {
+    pub signatures_checked: u64,
+    pub missing: u64,
+    pub repaired: u64,
+    /// Keyed by address.
+    pub addresses: BTreeMap<String, AddressGaps>,
+}
+
+pub struct GapRepair {
+    max_signatures: usize,
+    /// Keyed by address.
+    addresses: Mutex<HashMap<Pubkey, AddressGaps>>,
+}
+
+impl GapRepair {
+    pub fn new(config: &GapConfig) -> Self {
+        GapRepair {
+            max_signatures: config.max_signatures_per_address,
+            addresses: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Reconcile every watched address and program each time `job` is due, until `shutdown` is cancelled.
+    pub async fn run(
+        &self,
+        solana_client: &SolanaClient,
+        watchlist: &Watchlist,
+        storage: &Arc<dyn Storage>,
+        postgres: Option<&Arc<PgPool>>,
+        job: &Job,
+        shutdown: &CancellationToken,
+    ) {
+        while job.tick(shutdown).await {
+            let _ = job
+                .run(self.repair(solana_client, watchlist, storage, postgres))
+                .await;
+        }
+    }
+
+    /// Reconcile every watched address and program, failing if any of them couldn't be.
+    async fn repair(
+        &self,
+        solana_client: &SolanaClient,
+        watchlist: &Watchlist,
+        storage: &Arc<dyn Storage>,
+        postgres: Option<&Arc<PgPool>>,
+    ) -> anyhow::Result<()> {
+        let addresses = watchlist.polled();
+        let mut failed = 0;
+
+        for address in &addresses {
+            if let Err(e) = self
+                .repair_address(address, solana_client, storage, postgres)
+                .await
+            {
+                warn!("Failed to reconcile {address}: {e:?}");
+                failed += 1;
+            }
+        }
+
+        if failed > 0 {
+            anyhow::bail!(
+                "{failed} of {} addresses failed to reconcile",
+                addresses.len()
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn repair_address(
+        &self,
+        address: &Pubkey,
+        solana_client: &SolanaClient,
+        storage: &Arc<dyn Storage>,
+        postgres: Option<&Arc<PgPool>>,
+    ) -> anyhow::Result<()> {
+        let until = self.reconciled_through(address);
+        let signatures = solana_client
+            .fetch_signatures_since(address, until, self.max_signatures)
+            .await?;
+
+        let Some(newest) = signatures.first().copied() else {
+            self.record(address, None, 0, 0, 0);
+            return Ok(());
+        };
+
+        let keys = signatures
+            .iter()
+            .map(|signature| signature.to_string())
+            .collect::<Vec<_>>();
+        let stored = storage.stored_signatures(&keys).await?;
+
+        // newest first; without a reconciled position, anything older than the oldest stored
+        // signature is history rather than a gap
+        let in_scope = match until {
+            Some(_) => signatures.len(),
+            None => keys
+                .iter()
+                .rposition(|key| stored.contains(key))
+                .map_or(0, |oldest_stored| oldest_stored + 1),
+        };
+        let missing = signatures[..in_scope]
+            .iter()
+            .zip(&keys)
+            .filter(|(_, key)| !stored.contains(*key))
+            .map(|(signature, _)| *signature)
+            .collect::<Vec<_>>();
+
+        let mut repaired = 0;
+        let mut fetched_all = true;
+
+        for chunk in missing.chunks(REPAIR_CHUNK) {
+            solana_client.wait_for_memory().await;
+
+            let txns = solana_client.fetch_transactions(chunk).await?;
+            fetched_all &= txns.len() == chunk.len();
+
+            let fetched = solana_client.charge_fetched(&txns);
+            let batch = solana_client.process(txns, None);
+            drop(fetched);
+            let _processed = solana_client.charge_processed(&batch);
+
+            repaired += solana_client.store(&batch, storage, postgres).await?.len();
+        }
+
+        if repaired > 0 {
+            solana_client.ingest_metrics().record_repairs(repaired);
+            info!(
+                "Repaired {repaired} of {} missing transactions of {address}",
+                missing.len()
+            );
+        }
+
+        self.record(
+            address,
+            fetched_all.then_some(newest),
+            signatures.len(),
+            missing.len(),
+            repaired,
+        );
+
+        Ok(())
+    }
+
+    fn reconciled_through(&self, address: &Pubkey) -> Option<Signature> {
+        let addresses = self.addresses.lock().expect("gap report lock poisoned");
+
+        addresses
+            .get(address)?
+            .reconciled_through
+            .as_deref()?
+            .parse()
+            .ok()
+    }
+
+    fn record(
+        &self,
+        address: &Pubkey,
+        reconciled_through: Option<Signature>,
+        checked: usize,
+        missing: usize,
+        repaired: usize,
+    ) {
+        let mut addresses = self.addresses.lock().expect("gap report lock poisoned");
+        let gaps = addresses.entry(*address).or_default();
+
+        if let Some(signature) = reconciled_through {
+            gaps.reconciled_through = Some(signature.to_string());
+        }
+
+        gaps.last_checked_at = Some(unix_now());
+        gaps.signatures_checked += checked as u64;
+        gaps.missing += missing as u64;
+        gaps.repaired += repaired as u64;
+    }
+
+    /// What was checked, found missing and repaired since startup, overall and per address.
+    pub fn report(&self) -> GapReport {
+        let addresses = self.addresses.lock().expect("gap report lock poisoned");
+
+        addresses
+            .iter()
+            .fold(GapReport::default(), |mut report, (address, gaps)| {
+                report.signatures_checked += gaps.signatures_checked;
+                report.missing += gaps.missing;
+                report.repaired += gaps.repaired;
+                report.addresses.insert(address.to_string(), gaps.clone());
+                report
+            })
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report_accumulates_and_keeps_position() {
+        let gaps = GapRepair::new(&GapConfig::default());
+        let address = Pubkey::new_unique();
+        let first = Signature::new_unique();
+
+        gaps.record(&address, Some(first), 50, 4, 3);
+        assert_eq!(gaps.reconciled_through(&address), Some(first));
+
+        // a run that couldn't fetch everything keeps the previous position
+        gaps.record(&address, None, 10, 2, 1);
+        assert_eq!(gaps.reconciled_through(&address), Some(first));
+
+        gaps.record(&Pubkey::new_unique(), None, 5, 0, 0);
+
+        let report = gaps.report();
+        assert_eq!(report.signatures_checked, 65);
+        assert_eq!(report.missing, 6);
+        assert_eq!(report.repaired, 4);
+        assert_eq!(report.addresses[&address.to_string()].repaired, 4);
+        assert_eq!(report.addresses.len(), 2);
+    }
+}
@@ -0,0 +1,507 @@
+// Ingests from a Yellowstone gRPC (Geyser) stream instead of polling RPC
+
+// Responsibilities:
+// * Subscribe to the transactions of the watched addresses and programs, and to the updates of
+//   the watched accounts, on a Yellowstone gRPC endpoint.
+// * Hand the transactions to the monitor's pipeline as a `Source`, in place of `WatchlistSource`,
+//   so they're processed, stored and published like polled ones.
+// * Record the balances the account updates carry, with Postgres.
+
+// Implementation:
+// * The subscription runs in the background and buffers updates in a bounded channel. Each poll
+//   drains up to `buffer` of them, so a stalled pipeline holds the stream back, and the endpoint
+//   may drop a client that falls too far behind.
+// * Transactions arrive as protobuf, at `confirmed` commitment like polled ones, and are encoded
+//   as `jsonParsed` the way `getTransaction` returns them. Updates carry no block time, so the time
+//   a transaction arrived stands in for it.
+// * Each transaction is batched under the first watched address among its accounts, whose dedup
+//   filter its signature is then recorded in.
+// * Every poll compares the watchlist with the subscription's filters and sends new ones on the
+//   open stream when they differ, so discovered addresses are picked up as they're found.
+// * A dropped stream is resubscribed after `reconnect_secs`. Polls fail while it's down, and the
+//   transactions sent in the meantime are left for the gap repair job to find.
+// * Only the latest update of each account per poll is recorded as a balance.
+
+use crate::{
+    config::GeyserConfig,
+    data_processing::{parse_token_account, BalanceSnapshot},
+    data_retrieval::SolanaClient,
+    data_storage::{insert_balance_snapshot, Storage},
+    pipeline::{Fetched, Pipeline, Source},
+    schedules::Job,
+    watchlist::Watchlist,
+};
+
+use async_trait::async_trait;
+use futures::{SinkExt, StreamExt};
+use log::{error, info, warn};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use solana_transaction_status::{
+    ConfirmedTransactionWithStatusMeta, EncodedConfirmedTransactionWithStatusMeta,
+    UiTransactionEncoding,
+};
+use sqlx::PgPool;
+use tokio::{
+    sync::{mpsc, watch, Mutex},
+    time::{sleep, Duration},
+};
+use tokio_util::sync::{CancellationToken, DropGuard};
+use tonic::transport::ClientTlsConfig;
+use yellowstone_grpc_client::GeyserGrpcClient;
+use yellowstone_grpc_proto::{
+    convert_from::create_tx_with_meta,
+    prelude::{
+        subscribe_update::UpdateOneof, CommitmentLevel, SubscribeRequest,
+        SubscribeRequestFilterAccounts, SubscribeRequestFilterTransactions, SubscribeRequestPing,
+        SubscribeUpdateAccountInfo, SubscribeUpdateTransaction,
+    },
+};
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Name of the subscription's filters, echoed back on the updates they match.
+const FILTER: &str = "watchlist";
+
+/// Ingest from the Geyser stream until `shutdown` is cancelled, storing transactions in `storage`,
+/// or only publishing them to the sinks without it, each time `job` is due.
+pub async fn monitor(
+    config: &GeyserConfig,
+    client: &SolanaClient,
+    watchlist: &Watchlist,
+    storage: Option<&Arc<dyn Storage>>,
+    postgres: Option<&Arc<PgPool>>,
+    job: &Job,
+    shutdown: &CancellationToken,
+) {
+    let source = GeyserSource::subscribe(config, client, watchlist, postgres, shutdown);
+
+    Pipeline::storing(client, source, storage, postgres)
+        .run(job, shutdown)
+        .await;
+
+    info!("Stopped ingesting from the Geyser stream");
+}
+
+/// What the subscription asks the endpoint for.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+struct Filters {
+    /// Accounts whose updates are sent: the watched addresses.
+    accounts: Vec<String>,
+    /// Accounts whose transactions are sent: the watched addresses and programs, and the
+    /// canary's address.
+    transactions: Vec<String>,
+}
+
+impl Filters {
+    fn request(&self) -> SubscribeRequest {
+        let mut request = SubscribeRequest {
+            commitment: Some(CommitmentLevel::Confirmed as i32),
+            ..SubscribeRequest::default()
+        };
+
+        if !self.accounts.is_empty() {
+            request.accounts.insert(
+                FILTER.to_string(),
+                SubscribeRequestFilterAccounts {
+                    account: self.accounts.clone(),
+                    ..SubscribeRequestFilterAccounts::default()
+                },
+            );
+        }
+
+        // an empty `account_include` would match every transaction
+        if !self.transactions.is_empty() {
+            request.transactions.insert(
+                FILTER.to_string(),
+                SubscribeRequestFilterTransactions {
+                    vote: Some(false),
+                    account_include: self.transactions.clone(),
+                    ..SubscribeRequestFilterTransactions::default()
+                },
+            );
+        }
+
+        request
+    }
+}
+
+enum Update {
+    Transaction {
+        transaction: EncodedConfirmedTransactionWithStatusMeta,
+        signature: Signature,
+        accounts: Vec<Pubkey>,
+    },
+    Balance(BalanceSnapshot),
+}
+
+/// The transactions of a Yellowstone gRPC subscription to the watchlist.
+pub struct GeyserSource<'a> {
+    client: &'a SolanaClient,
+    watchlist: &'a Watchlist,
+    /// Balances are only recorded with a Postgres pool.
+    postgres: Option<&'a Arc<PgPool>>,
+    filters: watch::Sender<Filters>,
+    updates: Mutex<mpsc::Receiver<Update>>,
+    buffer: usize,
+    connected: Arc<AtomicBool>,
+    /// Ends the subscription along with the source.
+    _subscription: DropGuard,
+}
+
+impl<'a> GeyserSource<'a> {
+    /// Subscribe to the watchlist's transactions and account updates in the background, until
+    /// the source is dropped or `shutdown` is cancelled.
+    pub fn subscribe(
+        config: &GeyserConfig,
+        client: &'a SolanaClient,
+        watchlist: &'a Watchlist,
+        postgres: Option<&'a Arc<PgPool>>,
+        shutdown: &CancellationToken,
+    ) -> Self {
+        let buffer = config.buffer.max(1);
+        let (filters, filters_receiver) = watch::channel(watched(client, watchlist));
+        let (updates_sender, updates) = mpsc::channel(buffer);
+        let connected = Arc::new(AtomicBool::new(false));
+        let subscription = shutdown.child_token();
+
+        tokio::spawn(subscribe(
+            config.clone(),
+            filters_receiver,
+            updates_sender,
+            Arc::clone(&connected),
+            subscription.clone(),
+        ));
+
+        GeyserSource {
+            client,
+            watchlist,
+            postgres,
+            filters,
+            updates: Mutex::new(updates),
+            buffer,
+            connected,
+            _subscription: subscription.drop_guard(),
+        }
+    }
+}
+
+#[async_trait]
+impl Source for GeyserSource<'_> {
+    async fn poll(&self, output: &mpsc::Sender<Fetched>) -> anyhow::Result<()> {
+        let filters = watched(self.client, self.watchlist);
+        self.filters.send_if_modified(|current| {
+            let changed = *current != filters;
+            *current = filters.clone();
+            changed
+        });
+
+        self.client.wait_for_memory().await;
+
+        let watched = filters
+            .transactions
+            .iter()
+            .filter_map(|address| address.parse().ok())
+            .collect::<Vec<Pubkey>>();
+        let mut batches = BTreeMap::new();
+        let mut balances = HashMap::new();
+
+        {
+            let mut updates = self.updates.lock().await;
+
+            for _ in 0..self.buffer {
+                let Ok(update) = updates.try_recv() else {
+                    break;
+                };
+
+                match update {
+                    Update::Transaction {
+                        transaction,
+                        signature,
+                        accounts,
+                    } => {
+                        let Some(address) = batch_address(&accounts, &watched) else {
+                            continue;
+                        };
+
+                        let fetched = batches.entry(address).or_insert_with(|| Fetched {
+                            address,
+                            signatures: Vec::new(),
+                            transactions: Vec::new(),
+                        });
+                        fetched.signatures.push(signature);
+                        fetched.transactions.push(transaction);
+                    }
+                    Update::Balance(snapshot) => {
+                        balances.insert(snapshot.pubkey.clone(), snapshot);
+                    }
+                }
+            }
+        }
+
+        if let Some(postgres) = self.postgres {
+            for snapshot in balances.values() {
+                if let Err(e) = insert_balance_snapshot(postgres, snapshot).await {
+                    error!("Failed to record the balance of {}: {e:?}", snapshot.pubkey);
+                }
+            }
+        }
+
+        for fetched in batches.into_values() {
+            if output.send(fetched).await.is_err() {
+                break;
+            }
+        }
+
+        if !self.connected.load(Ordering::Relaxed) {
+            anyhow::bail!("Not subscribed to the Geyser stream");
+        }
+
+        Ok(())
+    }
+}
+
+/// The filters matching the watchlist and the canary's address.
+fn watched(client: &SolanaClient, watchlist: &Watchlist) -> Filters {
+    Filters {
+        accounts: watchlist
+            .addresses()
+            .iter()
+            .map(Pubkey::to_string)
+            .collect(),
+        transactions: watchlist
+            .polled()
+            .into_iter()
+            .chain(client.canary())
+            .map(|address| address.to_string())
+            .collect(),
+    }
+}
+
+/// The first watched address among a transaction's accounts or, if the filters changed since it
+/// was sent and none is watched anymore, its fee payer.
+fn batch_address(accounts: &[Pubkey], watched: &[Pubkey]) -> Option<Pubkey> {
+    accounts
+        .iter()
+        .find(|account| watched.contains(account))
+        .or(accounts.first())
+        .copied()
+}
+
+/// Keep subscribed with the latest filters, resubscribing once the stream drops, until
+/// `shutdown` is cancelled or the source is gone.
+async fn subscribe(
+    config: GeyserConfig,
+    mut filters: watch::Receiver<Filters>,
+    updates: mpsc::Sender<Update>,
+    connected: Arc<AtomicBool>,
+    shutdown: CancellationToken,
+) {
+    loop {
+        let result = tokio::select! {
+            result = stream(&config, &mut filters, &updates, &connected) => result,
+            _ = shutdown.cancelled() => return,
+        };
+        connected.store(false, Ordering::Relaxed);
+
+        match result {
+            Ok(()) if updates.is_closed() => return,
+            Ok(()) => warn!("The Geyser stream ended"),
+            Err(e) => error!("The Geyser stream failed: {e:?}"),
+        }
+
+        tokio::select! {
+            _ = sleep(Duration::from_secs(config.reconnect_secs)) => {}
+            _ = shutdown.cancelled() => return,
+        }
+    }
+}
+
+/// Subscribe once, forwarding updates until the stream ends or the source is gone.
+async fn stream(
+    config: &GeyserConfig,
+    filters: &mut watch::Receiver<Filters>,
+    updates: &mpsc::Sender<Update>,
+    connected: &AtomicBool,
+) -> anyhow::Result<()> {
+    let mut builder = GeyserGrpcClient::build_from_shared(config.endpoint.clone())?
+        .x_token(config.x_token.clone())?;
+
+    if config.endpoint.starts_with("https://") {
+        builder = builder.tls_config(ClientTlsConfig::new().with_native_roots())?;
+    }
+
+    let mut client = builder.connect().await?;
+    let request = filters.borrow_and_update().request();
+    let (mut requests, mut stream) = client.subscribe_with_request(Some(request)).await?;
+
+    connected.store(true, Ordering::Relaxed);
+    info!("Subscribed to the Geyser stream at {}", config.endpoint);
+
+    loop {
+        let update = tokio::select! {
+            update = stream.next() => update,
+            changed = filters.changed() => {
+                if changed.is_err() {
+                    return Ok(());
+                }
+
+                let request = filters.borrow_and_update().request();
+                requests.send(request).await?;
+                continue;
+            }
+        };
+
+        let Some(update) = update.transpose()? else {
+            return Ok(());
+        };
+
+        let update = match update.update_oneof {
+            Some(UpdateOneof::Transaction(transaction)) => match encode_transaction(transaction) {
+                Ok(update) => update,
+                Err(e) => {
+                    error!("Failed to decode a transaction from the Geyser stream: {e:?}");
+                    continue;
+                }
+            },
+            Some(UpdateOneof::Account(account)) => match account.account.map(balance_snapshot) {
+                Some(Ok(snapshot)) => Update::Balance(snapshot),
+                Some(Err(e)) => {
+                    error!("Failed to decode an account update from the Geyser stream: {e:?}");
+                    continue;
+                }
+                None => continue,
+            },
+            // load balancers in front of some endpoints drop streams that don't answer pings
+            Some(UpdateOneof::Ping(_)) => {
+                requests
+                    .send(SubscribeRequest {
+                        ping: Some(SubscribeRequestPing { id: 1 }),
+                        ..SubscribeRequest::default()
+                    })
+                    .await?;
+                continue;
+            }
+            _ => continue,
+        };
+
+        if updates.send(update).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+/// Encode a streamed transaction the way `getTransaction` returns it with `jsonParsed`.
+fn encode_transaction(update: SubscribeUpdateTransaction) -> anyhow::Result<Update> {
+    let info = update
+        .transaction
+        .ok_or_else(|| anyhow::anyhow!("Transaction update without a transaction"))?;
+    let tx_with_meta = create_tx_with_meta(info).map_err(anyhow::Error::msg)?;
+
+    let signature = *tx_with_meta.transaction_signature();
+    let accounts = tx_with_meta.account_keys().iter().copied().collect();
+
+    let transaction = ConfirmedTransactionWithStatusMeta {
+        slot: update.slot,
+        tx_with_meta,
+        block_time: Some(unix_now()),
+    }
+    .encode(UiTransactionEncoding::JsonParsed, Some(0))?;
+
+    Ok(Update::Transaction {
+        transaction,
+        signature,
+        accounts,
+    })
+}
+
+/// The balance an account update carries, with its token balance if it's a token account.
+fn balance_snapshot(account: SubscribeUpdateAccountInfo) -> anyhow::Result<BalanceSnapshot> {
+    let pubkey = Pubkey::try_from(account.pubkey.as_slice())
+        .map_err(|_| anyhow::anyhow!("Invalid account key"))?;
+    let owner = Pubkey::try_from(account.owner.as_slice())
+        .map_err(|_| anyhow::anyhow!("Invalid owner key"))?;
+
+    let (token_mint, token_amount) = parse_token_account(&owner.to_string(), &account.data).unzip();
+
+    Ok(BalanceSnapshot {
+        pubkey: pubkey.to_string(),
+        lamports: account.lamports,
+        token_mint,
+        token_amount,
+        recorded_at: unix_now(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::data_processing::TOKEN_PROGRAM_IDS;
+
+    #[test]
+    fn test_filters_and_updates() {
+        let (address, program) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let filters = Filters {
+            accounts: vec![address.to_string()],
+            transactions: vec![address.to_string(), program.to_string()],
+        };
+
+        let request = filters.request();
+        assert_eq!(request.accounts[FILTER].account, filters.accounts);
+        assert_eq!(
+            request.transactions[FILTER].account_include,
+            filters.transactions
+        );
+        assert_eq!(request.transactions[FILTER].vote, Some(false));
+
+        // nothing watched subscribes to nothing, rather than to everything
+        let request = Filters::default().request();
+        assert!(request.accounts.is_empty() && request.transactions.is_empty());
+
+        let payer = Pubkey::new_unique();
+        assert_eq!(
+            batch_address(&[payer, program], &[address, program]),
+            Some(program)
+        );
+        assert_eq!(batch_address(&[payer], &[address]), Some(payer));
+        assert_eq!(batch_address(&[], &[address]), None);
+
+        let mint = Pubkey::new_unique();
+        let mut data = mint.to_bytes().to_vec();
+        data.extend_from_slice(&Pubkey::new_unique().to_bytes());
+        data.extend_from_slice(&42u64.to_le_bytes());
+        data.resize(165, 0);
+
+        let snapshot = balance_snapshot(SubscribeUpdateAccountInfo {
+            pubkey: address.to_bytes().to_vec(),
+            lamports: 2_039_280,
+            owner: TOKEN_PROGRAM_IDS[0]
+                .parse::<Pubkey>()
+                .unwrap()
+                .to_bytes()
+                .to_vec(),
+            data,
+            ..SubscribeUpdateAccountInfo::default()
+        })
+        .unwrap();
+        assert_eq!(snapshot.pubkey, address.to_string());
+        assert_eq!(snapshot.lamports, 2_039_280);
+        assert_eq!(snapshot.token_mint, Some(mint.to_string()));
+        assert_eq!(snapshot.token_amount, Some(42));
+
+        assert!(balance_snapshot(SubscribeUpdateAccountInfo::default()).is_err());
+    }
+}
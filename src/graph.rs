@@ -0,0 +1,388 @@
+// Builds the directed transfer graph around an address from stored transactions
+
+// Responsibilities:
+// * Walk out from an address through the addresses it sent to or received from, up to a depth,
+//   and aggregate the SOL and token flows between them into edges, served at `/graph` as nodes
+//   and edges ready for d3 or Cytoscape.
+
+// Implementation:
+// * The walk is breadth-first and in memory: each level is one `Storage::query` for the
+//   transactions of the addresses found at the previous level, so it works on every backend.
+// * Token flows come from `token_transfers`, so they're Postgres only. Within a transaction, each
+//   mint's decreases are paired with its increases when either side is a single owner; transfers
+//   between several senders and several receivers, like some swaps, are left out since who paid
+//   whom is ambiguous.
+// * At most `MAX_TRANSACTIONS` transactions and as many token balance changes are read per graph.
+//   A graph that hit either bound is flagged `truncated`: hubs like exchanges reach a huge part of
+//   the chain within two hops.
+
+use crate::{
+    data_storage::{get_token_flows, Storage, StoredTokenTransfer, TransactionQuery},
+    labels::LabelRegistry,
+};
+
+use serde::Serialize;
+use sqlx::PgPool;
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::Arc,
+};
+
+pub const MAX_DEPTH: usize = 3;
+
+const SOL_DECIMALS: u8 = 9;
+
+/// Most transactions, and most token balance changes, read for one graph.
+const MAX_TRANSACTIONS: i64 = 50_000;
+
+/// An address in the graph.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Node {
+    pub id: String,
+    pub label: Option<String>,
+    /// Hops from the root address.
+    pub depth: usize,
+}
+
+/// Aggregated flow from one address to another, of SOL or of one token.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Edge {
+    pub source: String,
+    pub target: String,
+    /// `None` for SOL.
+    pub mint: Option<String>,
+    /// In lamports, or the mint's smallest unit.
+    pub amount: u64,
+    pub decimals: u8,
+    pub transactions: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct TransferGraph {
+    pub root: String,
+    pub depth: usize,
+    pub truncated: bool,
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+/// Which part of the stored history the graph covers.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct GraphScope {
+    /// Time range, in unix seconds.
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+#[derive(Default)]
+struct GraphBuilder {
+    /// Hops from the root, by address.
+    depths: HashMap<String, usize>,
+    /// Keyed by source, target and mint, so edges come out in a stable order.
+    edges: BTreeMap<(String, String, Option<String>), Edge>,
+}
+
+impl GraphBuilder {
+    fn new(root: &str) -> Self {
+        let mut builder = GraphBuilder::default();
+        builder.depths.insert(root.to_string(), 0);
+        builder
+    }
+
+    /// Add a transfer found at `depth`, returning the addresses it reached for the first time.
+    fn add(
+        &mut self,
+        depth: usize,
+        source: &str,
+        target: &str,
+        mint: Option<&str>,
+        decimals: u8,
+        amount: u64,
+    ) -> Vec<String> {
+        let edge = self
+            .edges
+            .entry((
+                source.to_string(),
+                target.to_string(),
+                mint.map(str::to_string),
+            ))
+            .or_insert_with(|| Edge {
+                source: source.to_string(),
+                target: target.to_string(),
+                mint: mint.map(str::to_string),
+                amount: 0,
+                decimals,
+                transactions: 0,
+            });
+        edge.amount = edge.amount.saturating_add(amount);
+        edge.transactions += 1;
+
+        let mut reached = Vec::new();
+
+        for address in [source, target] {
+            if !self.depths.contains_key(address) {
+                self.depths.insert(address.to_string(), depth + 1);
+                reached.push(address.to_string());
+            }
+        }
+
+        reached
+    }
+
+    /// Pair the token balance changes of a transaction's mint into transfers.
+    fn add_token_changes(&mut self, depth: usize, changes: &[&StoredTokenTransfer]) -> Vec<String> {
+        let owned = |sent: bool| {
+            changes
+                .iter()
+                .filter(move |change| (change.change < 0) == sent && change.change != 0)
+                .filter_map(|change| Some((change.owner.as_deref()?, change)))
+                .collect::<Vec<_>>()
+        };
+        let (senders, receivers) = (owned(true), owned(false));
+
+        let pairs = match (senders.as_slice(), receivers.as_slice()) {
+            ([(sender, _)], receivers) => receivers
+                .iter()
+                .map(|(receiver, change)| (*sender, *receiver, change.change.unsigned_abs()))
+                .collect::<Vec<_>>(),
+            (senders, [(receiver, _)]) => senders
+                .iter()
+                .map(|(sender, change)| (*sender, *receiver, change.change.unsigned_abs()))
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let mut reached = Vec::new();
+
+        for (sender, receiver, amount) in pairs {
+            if sender != receiver {
+                let change = changes[0];
+                reached.extend(self.add(
+                    depth,
+                    sender,
+                    receiver,
+                    Some(change.mint.as_str()),
+                    change.decimals as u8,
+                    amount,
+                ));
+            }
+        }
+
+        reached
+    }
+
+    fn build(
+        self,
+        root: &str,
+        depth: usize,
+        truncated: bool,
+        labels: &LabelRegistry,
+    ) -> TransferGraph {
+        let mut nodes = self
+            .depths
+            .into_iter()
+            .map(|(id, depth)| Node {
+                label: labels.label(&id),
+                id,
+                depth,
+            })
+            .collect::<Vec<_>>();
+        nodes.sort_by(|a, b| (a.depth, &a.id).cmp(&(b.depth, &b.id)));
+
+        TransferGraph {
+            root: root.to_string(),
+            depth,
+            truncated,
+            nodes,
+            edges: self.edges.into_values().collect(),
+        }
+    }
+}
+
+/// The transfer graph up to `depth` hops around `root`, with token flows when `postgres` is
+/// given.
+pub async fn transfer_graph(
+    storage: &Arc<dyn Storage>,
+    postgres: Option<&Arc<PgPool>>,
+    labels: &LabelRegistry,
+    root: &str,
+    depth: usize,
+    scope: GraphScope,
+) -> anyhow::Result<TransferGraph> {
+    let mut builder = GraphBuilder::new(root);
+    let mut frontier = vec![root.to_string()];
+    let mut seen = HashSet::new();
+    let mut seen_token_changes = HashSet::new();
+    let mut transactions_left = MAX_TRANSACTIONS;
+    let mut token_changes_left = MAX_TRANSACTIONS;
+    let mut truncated = false;
+
+    for level in 0..depth {
+        if frontier.is_empty() {
+            break;
+        }
+
+        let mut next = Vec::new();
+
+        let txns = storage
+            .query(&TransactionQuery {
+                addresses: Some(frontier.clone()),
+                from: scope.from,
+                to: scope.to,
+                limit: Some(transactions_left),
+                ..TransactionQuery::default()
+            })
+            .await?;
+        transactions_left -= txns.len() as i64;
+        truncated |= transactions_left == 0;
+
+        for stored in txns {
+            let txn = &stored.transaction;
+
+            // reached from both ends, or at an earlier level
+            if !seen.insert(stored.id) || txn.sender == txn.receiver {
+                continue;
+            }
+
+            next.extend(builder.add(
+                level,
+                &txn.sender,
+                &txn.receiver,
+                None,
+                SOL_DECIMALS,
+                txn.sol_amount,
+            ));
+        }
+
+        if let Some(pool) = postgres {
+            let changes =
+                get_token_flows(pool, &frontier, scope.from, scope.to, token_changes_left).await?;
+            token_changes_left -= changes.len() as i64;
+            truncated |= token_changes_left == 0;
+
+            let mut by_mint = BTreeMap::<_, Vec<_>>::new();
+
+            for change in &changes {
+                if seen_token_changes.insert(change.id) {
+                    by_mint
+                        .entry((change.signature.as_str(), change.mint.as_str()))
+                        .or_default()
+                        .push(change);
+                }
+            }
+
+            for changes in by_mint.values() {
+                next.extend(builder.add_token_changes(level, changes));
+            }
+        }
+
+        if truncated {
+            break;
+        }
+
+        frontier = next;
+    }
+
+    Ok(builder.build(root, depth, truncated, labels))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{data_processing::TransactionData, data_storage::InMemoryStorage, labels::Label};
+
+    use solana_sdk::pubkey::Pubkey;
+
+    fn transfer(sender: &str, receiver: &str, sol_amount: u64) -> TransactionData {
+        TransactionData {
+            signature: format!("{sender}-{receiver}-{sol_amount}"),
+            sender: sender.to_string(),
+            receiver: receiver.to_string(),
+            sol_amount,
+            fee: 5000,
+            timestamp: 1625077743,
+            prev_blockhash: "4sZ76MsNd8y3WSw2L1nfd3AqLoYxdmC98sERoMRbHV14".to_string(),
+            slot: 42,
+            ..TransactionData::default()
+        }
+    }
+
+    fn token_change(owner: &str, change: i64) -> StoredTokenTransfer {
+        StoredTokenTransfer {
+            id: 0,
+            signature: "sig".to_string(),
+            timestamp: 0,
+            account_index: 0,
+            mint: "mint".to_string(),
+            owner: Some(owner.to_string()),
+            change,
+            decimals: 6,
+            nft: false,
+            nft_name: None,
+            usd_price: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_graph_walks_levels_and_aggregates() -> anyhow::Result<()> {
+        let storage: Arc<dyn Storage> = Arc::new(InMemoryStorage::new());
+        let labels = LabelRegistry::new();
+        let [a, b, c, d] = [(); 4].map(|_| Pubkey::new_unique().to_string());
+
+        labels.register(Label {
+            pubkey: b.clone(),
+            label: "Exchange".to_string(),
+            tags: vec![],
+        })?;
+
+        storage
+            .insert_batch(&[
+                transfer(&a, &b, 10),
+                transfer(&a, &b, 5),
+                transfer(&c, &a, 1),
+                transfer(&b, &d, 7),
+            ])
+            .await?;
+
+        let graph = transfer_graph(&storage, None, &labels, &a, 1, GraphScope::default()).await?;
+        assert_eq!(graph.nodes.len(), 3);
+        assert_eq!(graph.edges.len(), 2);
+
+        let edge = graph.edges.iter().find(|edge| edge.source == a).unwrap();
+        assert_eq!((edge.amount, edge.transactions), (15, 2));
+
+        let graph = transfer_graph(&storage, None, &labels, &a, 2, GraphScope::default()).await?;
+        let node = |id: &str| graph.nodes.iter().find(|node| node.id == id).unwrap();
+        assert_eq!(graph.edges.len(), 3);
+        assert_eq!(node(&a).depth, 0);
+        assert_eq!(node(&b).label.as_deref(), Some("Exchange"));
+        assert_eq!(node(&d).depth, 2);
+        assert!(!graph.truncated);
+
+        // one sender pays several receivers; several senders and receivers are ambiguous
+        let mut builder = GraphBuilder::new("x");
+        let changes = [
+            token_change("x", -30),
+            token_change("y", 20),
+            token_change("z", 10),
+        ];
+        let reached = builder.add_token_changes(0, &changes.iter().collect::<Vec<_>>());
+        assert_eq!(reached, vec!["y".to_string(), "z".to_string()]);
+        assert_eq!(builder.edges.len(), 2);
+
+        let changes = [
+            token_change("x", -30),
+            token_change("w", -10),
+            token_change("y", 20),
+            token_change("z", 20),
+        ];
+        assert!(builder
+            .add_token_changes(0, &changes.iter().collect::<Vec<_>>())
+            .is_empty());
+        assert_eq!(builder.edges.len(), 2);
+
+        Ok(())
+    }
+}
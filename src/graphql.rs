@@ -0,0 +1,522 @@
+// Serves stored data over GraphQL
+
+// Responsibilities:
+// * Expose transactions, accounts, balances and statistics through one `/graphql` endpoint, so
+//   dashboards can fetch exactly the fields and combinations they need in a single request.
+// * Resolve every field against the same storage functions as the REST routes.
+
+// Implementation:
+// * The schema is read-only: queries only, no mutations or subscriptions.
+// * Transaction lists take the same filters and cursor pagination as
+//   `/accounts/{pubkey}/transactions`, and ids and cursors go through the same `IdCodec`.
+// * Balances and statistics live in Postgres-only tables. Without Postgres those fields resolve to
+//   an error.
+// * Queries are capped in depth and complexity so a single request can't fan out unboundedly.
+// * Internal errors are logged and reported to clients without their details, as on REST.
+
+use crate::{
+    data_processing::{BalanceSnapshot, TransactionData},
+    data_storage::{
+        get_balance_history, get_latest_balance, Counterparty, Storage, StoredTransaction,
+        TransactionQuery, TransactionStats,
+    },
+    ids::IdCodec,
+    stats::StatsCache,
+};
+
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, Error, Object, Result, Schema, SimpleObject,
+};
+use log::error;
+use solana_sdk::pubkey::Pubkey;
+use sqlx::PgPool;
+
+use std::{str::FromStr, sync::Arc};
+
+pub type AggregatorSchema = Schema<Query, EmptyMutation, EmptySubscription>;
+
+const MAX_DEPTH: usize = 8;
+
+const MAX_COMPLEXITY: usize = 256;
+
+/// Page size when `limit` isn't given, and the largest allowed.
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+/// Build the schema. `postgres` enables the balance and statistics fields.
+pub fn schema(
+    storage: Arc<dyn Storage>,
+    ids: IdCodec,
+    postgres: Option<(Arc<PgPool>, Arc<StatsCache>)>,
+) -> AggregatorSchema {
+    let builder = Schema::build(Query, EmptyMutation, EmptySubscription)
+        .data(storage)
+        .data(ids)
+        .limit_depth(MAX_DEPTH)
+        .limit_complexity(MAX_COMPLEXITY);
+
+    match postgres {
+        Some((db, stats)) => builder.data(db).data(stats).finish(),
+        None => builder.finish(),
+    }
+}
+
+#[derive(Debug, SimpleObject)]
+pub struct Transaction {
+    /// Public id, usable as an `after` cursor.
+    id: String,
+    signature: String,
+    sender: String,
+    receiver: String,
+    sol_amount: u64,
+    fee: u64,
+    timestamp: i64,
+    prev_blockhash: String,
+    slot: u64,
+    error: Option<String>,
+    log_messages: Vec<String>,
+    compute_units_consumed: Option<u64>,
+    program: Option<String>,
+    memo: Option<String>,
+    /// Set by the transaction's compute budget instructions.
+    compute_unit_limit: Option<u32>,
+    /// Micro-lamports per compute unit.
+    compute_unit_price: Option<u64>,
+    /// Lamports paid on top of the base fee.
+    priority_fee: Option<u64>,
+    /// `vote`, `stake`, `token`, `transfer` or `other`.
+    txn_type: Option<String>,
+    /// `system_transfer`, `dex_swap`, `cex_deposit` or `cex_withdrawal`.
+    categories: Vec<String>,
+    /// SOL/USD price at the transaction's block time, if it was priced.
+    sol_usd_price: Option<f64>,
+    /// `confirmed`, `finalized` or `dropped`.
+    commitment_status: String,
+    /// Account that paid the fee; unset on transactions stored before it was recorded, whose
+    /// sender paid it.
+    fee_payer: Option<String>,
+    /// SOL moved by the transaction's System program transfers, in order.
+    transfers: Vec<SolTransfer>,
+    /// False when the transaction failed on chain, with its `error`.
+    success: bool,
+}
+
+/// SOL moved by a System program transfer.
+#[derive(Debug, SimpleObject)]
+pub struct SolTransfer {
+    source: String,
+    destination: String,
+    lamports: u64,
+}
+
+impl Transaction {
+    fn new(stored: StoredTransaction, ids: &IdCodec) -> Self {
+        let TransactionData {
+            signature,
+            sender,
+            receiver,
+            sol_amount,
+            fee,
+            timestamp,
+            prev_blockhash,
+            slot,
+            error,
+            log_messages,
+            compute_units_consumed,
+            program,
+            memo,
+            compute_budget,
+            txn_type,
+            categories,
+            sol_usd_price,
+            fee_payer,
+            transfers,
+            success,
+        } = stored.transaction;
+
+        Transaction {
+            id: ids.encode(stored.id),
+            signature,
+            sender,
+            receiver,
+            sol_amount,
+            fee,
+            timestamp,
+            prev_blockhash,
+            slot,
+            error,
+            log_messages,
+            compute_units_consumed,
+            program,
+            memo,
+            compute_unit_limit: compute_budget.and_then(|budget| budget.unit_limit),
+            compute_unit_price: compute_budget.and_then(|budget| budget.unit_price),
+            priority_fee: compute_budget.map(|budget| budget.priority_fee),
+            txn_type: txn_type.map(|txn_type| txn_type.as_str().to_string()),
+            categories,
+            sol_usd_price,
+            commitment_status: stored.commitment_status.as_str().to_string(),
+            fee_payer,
+            transfers: transfers
+                .into_iter()
+                .map(|transfer| SolTransfer {
+                    source: transfer.source,
+                    destination: transfer.destination,
+                    lamports: transfer.lamports,
+                })
+                .collect(),
+            success,
+        }
+    }
+}
+
+/// A recorded balance of a watched account.
+#[derive(Debug, SimpleObject)]
+pub struct Balance {
+    lamports: u64,
+    /// Set when the account is an SPL token account.
+    token_mint: Option<String>,
+    token_amount: Option<u64>,
+    recorded_at: i64,
+}
+
+impl From<BalanceSnapshot> for Balance {
+    fn from(snapshot: BalanceSnapshot) -> Self {
+        Balance {
+            lamports: snapshot.lamports,
+            token_mint: snapshot.token_mint,
+            token_amount: snapshot.token_amount,
+            recorded_at: snapshot.recorded_at,
+        }
+    }
+}
+
+#[derive(Debug, SimpleObject)]
+pub struct CounterpartyStats {
+    address: String,
+    transaction_count: i64,
+    sol_amount: i64,
+}
+
+impl From<Counterparty> for CounterpartyStats {
+    fn from(counterparty: Counterparty) -> Self {
+        CounterpartyStats {
+            address: counterparty.address,
+            transaction_count: counterparty.transaction_count,
+            sol_amount: counterparty.sol_amount,
+        }
+    }
+}
+
+#[derive(Debug, SimpleObject)]
+pub struct Stats {
+    transaction_count: i64,
+    total_sol_amount: i64,
+    total_fees: i64,
+    average_fee: f64,
+    /// SOL moved, valued at each transaction's SOL/USD price.
+    total_usd_volume: f64,
+    first_activity: Option<i64>,
+    last_activity: Option<i64>,
+    top_counterparties: Vec<CounterpartyStats>,
+}
+
+impl From<TransactionStats> for Stats {
+    fn from(stats: TransactionStats) -> Self {
+        Stats {
+            transaction_count: stats.transaction_count,
+            total_sol_amount: stats.total_sol_amount,
+            total_fees: stats.total_fees,
+            average_fee: stats.average_fee,
+            total_usd_volume: stats.total_usd_volume,
+            first_activity: stats.first_activity,
+            last_activity: stats.last_activity,
+            top_counterparties: stats
+                .top_counterparties
+                .into_iter()
+                .map(CounterpartyStats::from)
+                .collect(),
+        }
+    }
+}
+
+/// Filters and pagination shared by the transaction lists.
+struct TransactionArgs {
+    after: Option<String>,
+    from: Option<i64>,
+    to: Option<i64>,
+    from_slot: Option<i64>,
+    to_slot: Option<i64>,
+    limit: Option<i64>,
+}
+
+impl TransactionArgs {
+    async fn resolve(self, ctx: &Context<'_>, address: Option<String>) -> Result<Vec<Transaction>> {
+        let storage = ctx.data::<Arc<dyn Storage>>()?;
+        let ids = ctx.data::<IdCodec>()?;
+
+        let after = match self.after.as_deref() {
+            Some(cursor) => ids.decode(cursor).ok_or("Invalid cursor")?,
+            None => 0,
+        };
+
+        let query = TransactionQuery {
+            address,
+            addresses: None,
+            after,
+            from: self.from,
+            to: self.to,
+            from_slot: self.from_slot,
+            to_slot: self.to_slot,
+            program: None,
+            memo_contains: None,
+            txn_type: None,
+            category: None,
+            include_dropped: false,
+            success: None,
+            limit: Some(self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)),
+        };
+
+        let transactions = storage
+            .query(&query)
+            .await
+            .map_err(|e| internal("fetch transactions", e))?;
+
+        Ok(transactions
+            .into_iter()
+            .map(|stored| Transaction::new(stored, ids))
+            .collect())
+    }
+}
+
+pub struct Query;
+
+#[Object]
+impl Query {
+    /// Stored transactions in insertion order, optionally only those sent or received by
+    /// `address`. Paginate by passing the `id` of the last transaction received as `after`.
+    #[allow(clippy::too_many_arguments)]
+    async fn transactions(
+        &self,
+        ctx: &Context<'_>,
+        address: Option<String>,
+        after: Option<String>,
+        from: Option<i64>,
+        to: Option<i64>,
+        from_slot: Option<i64>,
+        to_slot: Option<i64>,
+        limit: Option<i64>,
+    ) -> Result<Vec<Transaction>> {
+        let address = address.as_deref().map(parse_pubkey).transpose()?;
+
+        TransactionArgs {
+            after,
+            from,
+            to,
+            from_slot,
+            to_slot,
+            limit,
+        }
+        .resolve(ctx, address)
+        .await
+    }
+
+    async fn transaction(
+        &self,
+        ctx: &Context<'_>,
+        signature: String,
+    ) -> Result<Option<Transaction>> {
+        let storage = ctx.data::<Arc<dyn Storage>>()?;
+        let ids = ctx.data::<IdCodec>()?;
+
+        let stored = storage
+            .get_by_signature(&signature)
+            .await
+            .map_err(|e| internal("fetch transaction", e))?;
+
+        Ok(stored.map(|stored| Transaction::new(stored, ids)))
+    }
+
+    async fn account(&self, pubkey: String) -> Result<Account> {
+        Ok(Account {
+            pubkey: parse_pubkey(&pubkey)?,
+        })
+    }
+
+    /// Totals over all stored transactions. Requires Postgres.
+    async fn stats(&self, ctx: &Context<'_>) -> Result<Stats> {
+        let (db, stats) = postgres(ctx)?;
+
+        let stats = stats
+            .get(db, None)
+            .await
+            .map_err(|e| internal("compute statistics", e))?;
+
+        Ok(stats.into())
+    }
+}
+
+pub struct Account {
+    pubkey: String,
+}
+
+#[Object]
+impl Account {
+    async fn pubkey(&self) -> &str {
+        &self.pubkey
+    }
+
+    /// Stored transactions the account sent or received, in insertion order.
+    #[allow(clippy::too_many_arguments)]
+    async fn transactions(
+        &self,
+        ctx: &Context<'_>,
+        after: Option<String>,
+        from: Option<i64>,
+        to: Option<i64>,
+        from_slot: Option<i64>,
+        to_slot: Option<i64>,
+        limit: Option<i64>,
+    ) -> Result<Vec<Transaction>> {
+        TransactionArgs {
+            after,
+            from,
+            to,
+            from_slot,
+            to_slot,
+            limit,
+        }
+        .resolve(ctx, Some(self.pubkey.clone()))
+        .await
+    }
+
+    /// Latest recorded balance, if the account is watched. Requires Postgres.
+    async fn balance(&self, ctx: &Context<'_>) -> Result<Option<Balance>> {
+        let (db, _) = postgres(ctx)?;
+
+        let balance = get_latest_balance(db, &self.pubkey)
+            .await
+            .map_err(|e| internal("fetch balance", e))?;
+
+        Ok(balance.map(Balance::from))
+    }
+
+    /// Recorded balances within `[from, to]` (unix seconds), oldest first. Requires Postgres.
+    async fn balance_history(
+        &self,
+        ctx: &Context<'_>,
+        from: Option<i64>,
+        to: Option<i64>,
+        limit: Option<i64>,
+    ) -> Result<Vec<Balance>> {
+        let (db, _) = postgres(ctx)?;
+
+        let history = get_balance_history(db, &self.pubkey, from, to, limit)
+            .await
+            .map_err(|e| internal("fetch balance history", e))?;
+
+        Ok(history.into_iter().map(Balance::from).collect())
+    }
+
+    /// Statistics over the account's transactions; its counterparties are the addresses on the
+    /// other side. Requires Postgres.
+    async fn stats(&self, ctx: &Context<'_>) -> Result<Stats> {
+        let (db, stats) = postgres(ctx)?;
+
+        let stats = stats
+            .get(db, Some(&self.pubkey))
+            .await
+            .map_err(|e| internal("compute account statistics", e))?;
+
+        Ok(stats.into())
+    }
+}
+
+fn parse_pubkey(pubkey: &str) -> Result<String> {
+    Pubkey::from_str(pubkey)
+        .map(|pubkey| pubkey.to_string())
+        .map_err(|_| Error::new("Invalid public key"))
+}
+
+fn postgres<'a>(ctx: &Context<'a>) -> Result<(&'a Arc<PgPool>, &'a Arc<StatsCache>)> {
+    match (
+        ctx.data_opt::<Arc<PgPool>>(),
+        ctx.data_opt::<Arc<StatsCache>>(),
+    ) {
+        (Some(db), Some(stats)) => Ok((db, stats)),
+        _ => Err(Error::new("Only available with PostgreSQL storage")),
+    }
+}
+
+/// Log an internal error and hide its details from the client.
+fn internal(action: &str, e: anyhow::Error) -> Error {
+    error!("Failed to {action}: {e:?}");
+    Error::new("Internal error")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_storage::InMemoryStorage;
+
+    fn transaction(signature: &str, sender: &str, receiver: &str) -> TransactionData {
+        TransactionData {
+            signature: signature.to_string(),
+            sender: sender.to_string(),
+            receiver: receiver.to_string(),
+            sol_amount: 1,
+            fee: 5000,
+            timestamp: 1_700_000_000,
+            ..TransactionData::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_account_transactions_query() {
+        let alice = Pubkey::new_unique().to_string();
+        let bob = Pubkey::new_unique().to_string();
+        let carol = Pubkey::new_unique().to_string();
+
+        let storage = Arc::new(InMemoryStorage::new());
+        storage
+            .insert_batch(&[
+                transaction("sig1", &alice, &bob),
+                transaction("sig2", &bob, &carol),
+                transaction("sig3", &carol, &alice),
+            ])
+            .await
+            .unwrap();
+
+        let schema = schema(storage, IdCodec::Raw, None);
+        let query = format!(
+            r#"{{ account(pubkey: "{alice}") {{ transactions(limit: 10) {{ id signature }} }} }}"#
+        );
+        let response = schema.execute(query).await;
+
+        assert!(response.errors.is_empty(), "{:?}", response.errors);
+        assert_eq!(
+            response.data.into_json().unwrap(),
+            serde_json::json!({
+                "account": {
+                    "transactions": [
+                        { "id": "1", "signature": "sig1" },
+                        { "id": "3", "signature": "sig3" },
+                    ]
+                }
+            })
+        );
+
+        // Postgres-only fields need Postgres
+        let response = schema
+            .execute(format!(
+                r#"{{ account(pubkey: "{alice}") {{ pubkey stats {{ transactionCount }} }} }}"#
+            ))
+            .await;
+
+        assert_eq!(response.errors.len(), 1);
+        assert_eq!(
+            response.errors[0].message,
+            "Only available with PostgreSQL storage"
+        );
+    }
+}
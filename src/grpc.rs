@@ -0,0 +1,395 @@
+// Serves stored data over gRPC
+
+// Responsibilities:
+// * Give backend services a typed gRPC interface to list, fetch and stream transactions and to
+//   read account statistics, alongside the REST API.
+// * Apply the same API keys and rate limits as REST.
+
+// Implementation:
+// * The service is generated by tonic from `proto/aggregator.proto` and only built with the
+//   `grpc` feature.
+// * Every RPC goes through the same storage layer, id codec, live feed and statistics cache as
+//   the REST routes, so both interfaces always agree.
+// * Keys are read from `x-api-key` or `authorization: Bearer` metadata. Route-based public access
+//   doesn't apply: with auth enabled every RPC needs a key.
+// * A stream subscriber that falls behind the live feed has its stream ended with `DATA_LOSS`
+//   rather than silently skipping transactions.
+
+use crate::{
+    api::ApiContext,
+    auth::{Authenticator, Verdict},
+    config::GrpcConfig,
+    data_processing::TransactionData,
+    data_storage::{Storage, StoredTransaction, TransactionQuery, TransactionStats},
+    feed::TransactionFeed,
+    ids::IdCodec,
+    stats::StatsCache,
+};
+
+use log::{error, info};
+use solana_sdk::pubkey::Pubkey;
+use sqlx::PgPool;
+use tokio::net;
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream},
+    Stream, StreamExt,
+};
+use tokio_util::sync::CancellationToken;
+use tonic::{transport::Server, Request, Response, Status};
+
+use std::{pin::Pin, str::FromStr, sync::Arc};
+
+pub mod proto {
+    tonic::include_proto!("aggregator.v1");
+}
+
+use proto::aggregator_server::{Aggregator, AggregatorServer};
+
+/// Page size when `limit` isn't given, and the largest allowed.
+const DEFAULT_LIMIT: i64 = 100;
+const MAX_LIMIT: i64 = 1000;
+
+pub struct AggregatorService {
+    storage: Arc<dyn Storage>,
+    ids: IdCodec,
+    feed: Arc<TransactionFeed>,
+    auth: Option<Arc<Authenticator>>,
+    /// Only set with the Postgres backend.
+    stats: Option<(Arc<PgPool>, Arc<StatsCache>)>,
+}
+
+impl AggregatorService {
+    pub fn new(context: &ApiContext) -> Self {
+        AggregatorService {
+            storage: Arc::clone(&context.storage),
+            ids: context.ids.clone(),
+            feed: Arc::clone(&context.feed),
+            auth: context.auth.clone(),
+            stats: context
+                .postgres
+                .as_ref()
+                .map(|postgres| (Arc::clone(&postgres.db), Arc::clone(&postgres.stats))),
+        }
+    }
+
+    /// Check the request's API key when auth is enabled, counting it against the key's rate
+    /// limit.
+    async fn authorize<T>(&self, request: &Request<T>) -> Result<(), Status> {
+        let Some(auth) = self.auth.as_ref().filter(|auth| auth.is_enabled()) else {
+            return Ok(());
+        };
+
+        let metadata = request.metadata();
+        let secret = metadata
+            .get("x-api-key")
+            .and_then(|value| value.to_str().ok())
+            .or_else(|| {
+                metadata
+                    .get("authorization")
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|value| value.strip_prefix("Bearer "))
+            });
+
+        match auth.check(secret).await {
+            Ok(Verdict::Allowed { .. }) => Ok(()),
+            Ok(Verdict::Unauthorized) => Err(Status::unauthenticated("Missing or invalid API key")),
+            Ok(Verdict::RateLimited { retry_after }) => Err(Status::resource_exhausted(format!(
+                "Rate limit exceeded, retry after {}s",
+                retry_after.as_secs_f64().ceil()
+            ))),
+            Err(e) => Err(internal("check API key", e)),
+        }
+    }
+}
+
+#[tonic::async_trait]
+impl Aggregator for AggregatorService {
+    async fn list_transactions(
+        &self,
+        request: Request<proto::ListTransactionsRequest>,
+    ) -> Result<Response<proto::ListTransactionsResponse>, Status> {
+        self.authorize(&request).await?;
+        let request = request.into_inner();
+
+        let after = match request.after.as_deref() {
+            Some(cursor) => self
+                .ids
+                .decode(cursor)
+                .ok_or_else(|| Status::invalid_argument("Invalid cursor"))?,
+            None => 0,
+        };
+
+        let query = TransactionQuery {
+            address: request.address.as_deref().map(parse_pubkey).transpose()?,
+            addresses: None,
+            after,
+            from: request.from,
+            to: request.to,
+            from_slot: request.from_slot,
+            to_slot: request.to_slot,
+            program: None,
+            memo_contains: None,
+            txn_type: None,
+            category: None,
+            include_dropped: false,
+            success: None,
+            limit: Some(request.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)),
+        };
+
+        let transactions = self
+            .storage
+            .query(&query)
+            .await
+            .map_err(|e| internal("fetch transactions", e))?;
+
+        Ok(Response::new(proto::ListTransactionsResponse {
+            transactions: transactions
+                .into_iter()
+                .map(|stored| stored_transaction(stored, &self.ids))
+                .collect(),
+        }))
+    }
+
+    async fn get_transaction(
+        &self,
+        request: Request<proto::GetTransactionRequest>,
+    ) -> Result<Response<proto::Transaction>, Status> {
+        self.authorize(&request).await?;
+
+        match self
+            .storage
+            .get_by_signature(&request.into_inner().signature)
+            .await
+        {
+            Ok(Some(stored)) => Ok(Response::new(stored_transaction(stored, &self.ids))),
+            Ok(None) => Err(Status::not_found("Transaction not found")),
+            Err(e) => Err(internal("fetch transaction", e)),
+        }
+    }
+
+    type StreamTransactionsStream =
+        Pin<Box<dyn Stream<Item = Result<proto::Transaction, Status>> + Send>>;
+
+    async fn stream_transactions(
+        &self,
+        request: Request<proto::StreamTransactionsRequest>,
+    ) -> Result<Response<Self::StreamTransactionsStream>, Status> {
+        self.authorize(&request).await?;
+
+        let address = request
+            .into_inner()
+            .address
+            .as_deref()
+            .map(parse_pubkey)
+            .transpose()?;
+
+        let stream = BroadcastStream::new(self.feed.receiver()).filter_map(move |received| {
+            match received {
+                Ok(txn) => address
+                    .as_ref()
+                    .map_or(true, |address| {
+                        &txn.sender == address || &txn.receiver == address
+                    })
+                    .then(|| Ok(transaction(String::new(), TransactionData::clone(&txn)))),
+                // ends the stream
+                Err(BroadcastStreamRecvError::Lagged(skipped)) => Some(Err(Status::data_loss(
+                    format!("Fell behind and missed {skipped} transactions"),
+                ))),
+            }
+        });
+
+        Ok(Response::new(Box::pin(stream)))
+    }
+
+    async fn get_account_stats(
+        &self,
+        request: Request<proto::GetAccountStatsRequest>,
+    ) -> Result<Response<proto::AccountStats>, Status> {
+        self.authorize(&request).await?;
+
+        let pubkey = parse_pubkey(&request.into_inner().pubkey)?;
+
+        let Some((db, stats)) = &self.stats else {
+            return Err(Status::failed_precondition(
+                "Only available with PostgreSQL storage",
+            ));
+        };
+
+        match stats.get(db, Some(&pubkey)).await {
+            Ok(stats) => Ok(Response::new(account_stats(stats))),
+            Err(e) => Err(internal("compute account statistics", e)),
+        }
+    }
+}
+
+/// Serve the gRPC API on the configured host and port until `shutdown` is cancelled.
+pub async fn serve(
+    service: AggregatorService,
+    config: &GrpcConfig,
+    shutdown: CancellationToken,
+) -> anyhow::Result<()> {
+    let addr = net::lookup_host((config.host.as_str(), config.port))
+        .await?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("No address found for gRPC host {}", config.host))?;
+
+    info!("Serving gRPC on {addr}");
+
+    Server::builder()
+        .add_service(AggregatorServer::new(service))
+        .serve_with_shutdown(addr, async move { shutdown.cancelled().await })
+        .await?;
+
+    Ok(())
+}
+
+fn stored_transaction(stored: StoredTransaction, ids: &IdCodec) -> proto::Transaction {
+    transaction(ids.encode(stored.id), stored.transaction)
+}
+
+fn transaction(id: String, txn: TransactionData) -> proto::Transaction {
+    proto::Transaction {
+        id,
+        signature: txn.signature,
+        sender: txn.sender,
+        receiver: txn.receiver,
+        sol_amount: txn.sol_amount,
+        fee: txn.fee,
+        timestamp: txn.timestamp,
+        prev_blockhash: txn.prev_blockhash,
+        slot: txn.slot,
+        error: txn.error,
+        log_messages: txn.log_messages,
+        compute_units_consumed: txn.compute_units_consumed,
+        fee_payer: txn.fee_payer,
+        success: txn.success,
+    }
+}
+
+fn account_stats(stats: TransactionStats) -> proto::AccountStats {
+    proto::AccountStats {
+        transaction_count: stats.transaction_count,
+        total_sol_amount: stats.total_sol_amount,
+        total_fees: stats.total_fees,
+        average_fee: stats.average_fee,
+        total_usd_volume: stats.total_usd_volume,
+        first_activity: stats.first_activity,
+        last_activity: stats.last_activity,
+        top_counterparties: stats
+            .top_counterparties
+            .into_iter()
+            .map(|counterparty| proto::Counterparty {
+                address: counterparty.address,
+                transaction_count: counterparty.transaction_count,
+                sol_amount: counterparty.sol_amount,
+            })
+            .collect(),
+    }
+}
+
+fn parse_pubkey(pubkey: &str) -> Result<String, Status> {
+    Pubkey::from_str(pubkey)
+        .map(|pubkey| pubkey.to_string())
+        .map_err(|_| Status::invalid_argument("Invalid public key"))
+}
+
+/// Log an internal error and hide its details from the client.
+fn internal(action: &str, e: anyhow::Error) -> Status {
+    error!("Failed to {action}: {e:?}");
+    Status::internal("Internal error")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_storage::InMemoryStorage;
+
+    fn txn(signature: &str, sender: &str, receiver: &str) -> TransactionData {
+        TransactionData {
+            signature: signature.to_string(),
+            sender: sender.to_string(),
+            receiver: receiver.to_string(),
+            sol_amount: 1,
+            fee: 5000,
+            timestamp: 1_700_000_000,
+            ..TransactionData::default()
+        }
+    }
+
+    fn service(storage: Arc<dyn Storage>) -> AggregatorService {
+        AggregatorService {
+            storage,
+            ids: IdCodec::Raw,
+            feed: Arc::new(TransactionFeed::new()),
+            auth: None,
+            stats: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_list_and_get_transactions() {
+        let alice = Pubkey::new_unique().to_string();
+        let bob = Pubkey::new_unique().to_string();
+
+        let storage = Arc::new(InMemoryStorage::new());
+        storage
+            .insert_batch(&[txn("sig1", &alice, &bob), txn("sig2", &bob, &bob)])
+            .await
+            .unwrap();
+        let service = service(storage);
+
+        let page = service
+            .list_transactions(Request::new(proto::ListTransactionsRequest {
+                address: Some(alice.clone()),
+                ..Default::default()
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        assert_eq!(page.transactions.len(), 1);
+        assert_eq!(page.transactions[0].id, "1");
+        assert_eq!(page.transactions[0].signature, "sig1");
+
+        let missing = service
+            .get_transaction(Request::new(proto::GetTransactionRequest {
+                signature: "sig3".to_string(),
+            }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(missing.code(), tonic::Code::NotFound);
+
+        let stats = service
+            .get_account_stats(Request::new(proto::GetAccountStatsRequest {
+                pubkey: alice,
+            }))
+            .await
+            .unwrap_err();
+
+        assert_eq!(stats.code(), tonic::Code::FailedPrecondition);
+    }
+
+    #[tokio::test]
+    async fn test_stream_filters_by_address() {
+        let alice = Pubkey::new_unique().to_string();
+        let bob = Pubkey::new_unique().to_string();
+        let service = service(Arc::new(InMemoryStorage::new()));
+
+        let mut stream = service
+            .stream_transactions(Request::new(proto::StreamTransactionsRequest {
+                address: Some(alice.clone()),
+            }))
+            .await
+            .unwrap()
+            .into_inner();
+
+        service
+            .feed
+            .publish(&[txn("sig1", &bob, &bob), txn("sig2", &bob, &alice)]);
+
+        let streamed = stream.next().await.unwrap().unwrap();
+        assert_eq!(streamed.signature, "sig2");
+        assert!(streamed.id.is_empty());
+    }
+}
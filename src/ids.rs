@@ -0,0 +1,104 @@
+// Encodes database row ids for public consumption
+
+// Responsibilities:
+// * Turn auto-increment row ids into the ids shown in API responses and accepted as cursors.
+// * Optionally make them opaque, so consumers can't enumerate rows or infer ingestion volume.
+
+// Implementation:
+// * Raw mode exposes the row id as a decimal string.
+// * Hashids mode uses the `harsh` crate with a deployment-specific salt.
+
+use crate::config::IdConfig;
+
+use harsh::Harsh;
+
+#[derive(Debug, Clone)]
+pub enum IdCodec {
+    Raw,
+    Hashids(Harsh),
+}
+
+impl IdCodec {
+    pub fn from_config(config: &IdConfig) -> anyhow::Result<Self> {
+        if !config.obfuscate {
+            return Ok(IdCodec::Raw);
+        }
+
+        let salt = config
+            .salt
+            .as_deref()
+            .ok_or_else(|| anyhow::anyhow!("`ids.salt` is required when `ids.obfuscate` is set"))?;
+
+        let harsh = Harsh::builder()
+            .salt(salt)
+            .length(config.min_length)
+            .build()
+            .map_err(|e| anyhow::anyhow!("Invalid hashids settings: {e}"))?;
+
+        Ok(IdCodec::Hashids(harsh))
+    }
+
+    /// Encode a row id for an API response.
+    pub fn encode(&self, id: i64) -> String {
+        match self {
+            IdCodec::Raw => id.to_string(),
+            IdCodec::Hashids(harsh) => harsh.encode(&[id as u64]),
+        }
+    }
+
+    /// Decode an id received from a client, or `None` if it isn't one we issued.
+    pub fn decode(&self, encoded: &str) -> Option<i64> {
+        match self {
+            IdCodec::Raw => encoded.parse().ok(),
+            IdCodec::Hashids(harsh) => match harsh.decode(encoded).ok()?.as_slice() {
+                [id] => i64::try_from(*id).ok(),
+                _ => None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hashids() -> IdCodec {
+        IdCodec::from_config(&IdConfig {
+            obfuscate: true,
+            salt: Some("test salt".to_string()),
+            min_length: 8,
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_raw_round_trip() {
+        let codec = IdCodec::Raw;
+
+        assert_eq!(codec.encode(42), "42");
+        assert_eq!(codec.decode("42"), Some(42));
+        assert_eq!(codec.decode("not-an-id"), None);
+    }
+
+    #[test]
+    fn test_hashids_round_trip() {
+        let codec = hashids();
+        let encoded = codec.encode(42);
+
+        assert_ne!(encoded, "42");
+        assert!(encoded.len() >= 8);
+        assert_eq!(codec.decode(&encoded), Some(42));
+        assert_eq!(codec.decode("42"), None);
+    }
+
+    #[test]
+    fn test_hashids_requires_salt() {
+        let config = IdConfig {
+            obfuscate: true,
+            salt: None,
+            min_length: 8,
+        };
+
+        assert!(IdCodec::from_config(&config).is_err());
+    }
+}
@@ -0,0 +1,404 @@
+// Bulk import and export of watched addresses
+
+// Responsibilities:
+// * Parse a list of addresses, each with an optional label, from a JSON or CSV body.
+// * Validate every entry, reporting the invalid ones rather than rejecting the whole list.
+// * Watch the addresses that aren't watched yet, persisted so they're still watched after a
+//   restart, and register the labels given for them.
+// * Optionally backfill the recent transactions of the newly watched addresses.
+// * Write the watchlist back out, with labels, in either format.
+
+// Implementation:
+// * JSON bodies are an array of `{"pubkey": ..., "label": ...}` objects or of plain pubkeys. CSV
+//   bodies have the pubkey in the first column and an optional label in the second; a
+//   `pubkey,label` header row is optional.
+// * Duplicates within a body are dropped, keeping the first. Addresses that are already watched,
+//   whether configured, imported, owned or discovered, are reported as such; their labels are
+//   still registered.
+// * A label given for an address keeps the tags it already had.
+// * Addresses are stored before they're watched, so an address is never polled that a restart
+//   would forget.
+// * Backfills run in the background once the import is answered, one address after another,
+//   through the same processing and storage as monitored transactions.
+
+use crate::{
+    data_retrieval::SolanaClient,
+    data_storage::{insert_watched_addresses, upsert_label, Storage},
+    labels::{Label, LabelRegistry},
+    watchlist::Watchlist,
+};
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use sqlx::PgPool;
+
+use std::{
+    collections::HashSet,
+    str::FromStr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Most transactions backfilled per imported address.
+pub const MAX_BACKFILL: usize = 1000;
+
+/// Format of an address list.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ListFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+impl ListFormat {
+    /// The format of a body with the given `Content-Type`: CSV for `text/csv`, JSON otherwise.
+    pub fn from_content_type(content_type: &str) -> Self {
+        if content_type.eq_ignore_ascii_case("text/csv") {
+            ListFormat::Csv
+        } else {
+            ListFormat::Json
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ListFormat::Json => "application/json",
+            ListFormat::Csv => "text/csv; charset=utf-8",
+        }
+    }
+}
+
+/// An address to watch, with an optional label.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AddressEntry {
+    pub pubkey: String,
+    #[serde(default)]
+    pub label: Option<String>,
+}
+
+/// An entry of a JSON list: either a plain pubkey or an object with a label.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JsonEntry {
+    Pubkey(String),
+    Entry(AddressEntry),
+}
+
+/// Outcome of an import.
+#[derive(Debug, Default, PartialEq, Eq, Serialize)]
+pub struct ImportReport {
+    /// Addresses that are now watched.
+    pub added: Vec<String>,
+    /// Valid addresses that were watched already.
+    pub already_watched: Vec<String>,
+    /// How many labels were registered or replaced.
+    pub labelled: usize,
+    pub invalid: Vec<InvalidEntry>,
+    /// How many of the added addresses are being backfilled, if a backfill was asked for.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backfilling: Option<usize>,
+}
+
+/// An entry that was skipped, and why.
+#[derive(Debug, PartialEq, Eq, Serialize)]
+pub struct InvalidEntry {
+    /// Position in the list, from 1.
+    pub entry: usize,
+    pub pubkey: String,
+    pub error: String,
+}
+
+/// Parse an address list in `format`.
+pub fn parse(body: &[u8], format: ListFormat) -> anyhow::Result<Vec<AddressEntry>> {
+    match format {
+        ListFormat::Json => {
+            let entries = serde_json::from_slice::<Vec<JsonEntry>>(body)?
+                .into_iter()
+                .map(|entry| match entry {
+                    JsonEntry::Pubkey(pubkey) => AddressEntry {
+                        pubkey,
+                        label: None,
+                    },
+                    JsonEntry::Entry(entry) => entry,
+                })
+                .collect();
+
+            Ok(entries)
+        }
+        ListFormat::Csv => {
+            let mut reader = csv::ReaderBuilder::new()
+                .has_headers(false)
+                .flexible(true)
+                .trim(csv::Trim::All)
+                .from_reader(body);
+            let mut entries = Vec::new();
+
+            for (i, record) in reader.records().enumerate() {
+                let record = record?;
+                let pubkey = record.get(0).unwrap_or_default();
+
+                if i == 0 && pubkey.eq_ignore_ascii_case("pubkey") {
+                    continue;
+                }
+
+                entries.push(AddressEntry {
+                    pubkey: pubkey.to_string(),
+                    label: record.get(1).map(str::to_string),
+                });
+            }
+
+            Ok(entries)
+        }
+    }
+}
+
+/// Write `entries` in `format`.
+pub fn write(entries: &[AddressEntry], format: ListFormat) -> anyhow::Result<Vec<u8>> {
+    match format {
+        ListFormat::Json => Ok(serde_json::to_vec(entries)?),
+        ListFormat::Csv => {
+            let mut writer = csv::Writer::from_writer(Vec::new());
+            writer.write_record(["pubkey", "label"])?;
+
+            for entry in entries {
+                writer.write_record([&entry.pubkey, entry.label.as_deref().unwrap_or_default()])?;
+            }
+
+            Ok(writer.into_inner()?)
+        }
+    }
+}
+
+/// The watched addresses with their labels, in watchlist order.
+pub fn watched(watchlist: &Watchlist, labels: &LabelRegistry) -> Vec<AddressEntry> {
+    watchlist
+        .addresses()
+        .iter()
+        .map(|address| {
+            let pubkey = address.to_string();
+            let label = labels.label(&pubkey);
+
+            AddressEntry { pubkey, label }
+        })
+        .collect()
+}
+
+/// Check every entry, dropping duplicates. Returns the valid addresses, each with its label
+/// normalized, and the invalid entries.
+fn validate(
+    entries: Vec<AddressEntry>,
+    labels: &LabelRegistry,
+) -> (Vec<(Pubkey, Option<Label>)>, Vec<InvalidEntry>) {
+    let mut seen = HashSet::new();
+    let mut valid = Vec::new();
+    let mut invalid = Vec::new();
+
+    for (i, entry) in entries.into_iter().enumerate() {
+        let checked = Pubkey::from_str(entry.pubkey.trim())
+            .map_err(|e| anyhow::anyhow!("Invalid public key: {e}"))
+            .and_then(|address| {
+                let label = entry
+                    .label
+                    .as_deref()
+                    .filter(|label| !label.trim().is_empty())
+                    .map(|label| {
+                        let pubkey = address.to_string();
+                        let tags = labels.get(&pubkey).map(|label| label.tags);
+
+                        Label {
+                            pubkey,
+                            label: label.to_string(),
+                            tags: tags.unwrap_or_default(),
+                        }
+                        .normalized()
+                    })
+                    .transpose()?;
+
+                Ok((address, label))
+            });
+
+        match checked {
+            Ok((address, label)) => {
+                if seen.insert(address) {
+                    valid.push((address, label));
+                }
+            }
+            Err(e) => invalid.push(InvalidEntry {
+                entry: i + 1,
+                pubkey: entry.pubkey,
+                error: e.to_string(),
+            }),
+        }
+    }
+
+    (valid, invalid)
+}
+
+/// Watch the valid entries that aren't watched yet and register their labels.
+pub async fn import(
+    entries: Vec<AddressEntry>,
+    watchlist: &Watchlist,
+    labels: &LabelRegistry,
+    db: &Arc<PgPool>,
+) -> anyhow::Result<ImportReport> {
+    let (valid, invalid) = validate(entries, labels);
+    let watched = watchlist.addresses().into_iter().collect::<HashSet<_>>();
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+
+    let new = valid
+        .iter()
+        .map(|(address, _)| *address)
+        .filter(|address| !watched.contains(address))
+        .collect::<Vec<_>>();
+
+    // stored first, so an address is never polled that a restart would forget
+    let pubkeys = new.iter().map(Pubkey::to_string).collect::<Vec<_>>();
+    insert_watched_addresses(db, &pubkeys, now).await?;
+
+    let added = watchlist.import(&new);
+    let mut labelled = 0;
+
+    for label in valid.iter().filter_map(|(_, label)| label.clone()) {
+        upsert_label(db, &label, now).await?;
+        labels.register(label)?;
+        labelled += 1;
+    }
+
+    info!(
+        "Imported {} addresses, {} already watched, {} invalid",
+        added.len(),
+        valid.len() - added.len(),
+        invalid.len()
+    );
+
+    Ok(ImportReport {
+        added: added.iter().map(Pubkey::to_string).collect(),
+        already_watched: valid
+            .iter()
+            .map(|(address, _)| address)
+            .filter(|address| !added.contains(address))
+            .map(Pubkey::to_string)
+            .collect(),
+        labelled,
+        invalid,
+        backfilling: None,
+    })
+}
+
+/// Store up to `limit` of the most recent transactions of each address, one address after
+/// another. Failures are logged and the next address is backfilled.
+pub async fn backfill(
+    solana_client: Arc<SolanaClient>,
+    storage: Arc<dyn Storage>,
+    db: Arc<PgPool>,
+    addresses: Vec<Pubkey>,
+    limit: usize,
+) {
+    let limit = limit.clamp(1, MAX_BACKFILL);
+    let mut stored = 0;
+
+    for address in &addresses {
+        match backfill_address(&solana_client, &storage, &db, address, limit).await {
+            Ok(count) => stored += count,
+            Err(e) => error!("Failed to backfill {address}: {e:?}"),
+        }
+    }
+
+    info!(
+        "Backfilled {stored} transactions of {} imported addresses",
+        addresses.len()
+    );
+}
+
+/// Store up to `limit` of the most recent transactions of an address, returning how many were
+/// newly stored.
+async fn backfill_address(
+    solana_client: &SolanaClient,
+    storage: &Arc<dyn Storage>,
+    db: &Arc<PgPool>,
+    address: &Pubkey,
+    limit: usize,
+) -> anyhow::Result<usize> {
+    solana_client.wait_for_memory().await;
+
+    let signatures = solana_client
+        .fetch_recent_signatures(address, limit)
+        .await?;
+    let txns = solana_client.fetch_transactions(&signatures).await?;
+
+    let fetched = solana_client.charge_fetched(&txns);
+    let batch = solana_client.process(txns, None);
+    drop(fetched);
+    let _processed = solana_client.charge_processed(&batch);
+
+    let stored = solana_client.store(&batch, storage, Some(db)).await?;
+    solana_client.record_polled(address, &signatures);
+
+    Ok(stored.len())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_and_validate_address_lists() {
+        let first = Pubkey::new_unique().to_string();
+        let second = Pubkey::new_unique().to_string();
+
+        let json = format!(r#"["{first}", {{"pubkey": "{second}", "label": " Treasury "}}]"#);
+        let entries = parse(json.as_bytes(), ListFormat::Json).unwrap();
+        assert_eq!(entries[0].label, None);
+        assert_eq!(entries[1].label.as_deref(), Some(" Treasury "));
+
+        // the header is optional, and so is the label column
+        let csv = format!("pubkey,label\n{first}\n{second}, Treasury \n{first},Again\nnope,x\n");
+        assert_eq!(parse(csv.as_bytes(), ListFormat::Csv).unwrap().len(), 4);
+
+        let labels = LabelRegistry::new();
+        labels
+            .register(Label {
+                pubkey: second.clone(),
+                label: "Old".to_string(),
+                tags: vec!["dao".to_string()],
+            })
+            .unwrap();
+
+        // an empty label is no label
+        let csv = format!("{first},\n");
+        let mut entries = parse(csv.as_bytes(), ListFormat::Csv).unwrap();
+        entries.extend(parse(json.as_bytes(), ListFormat::Json).unwrap());
+        entries.push(AddressEntry {
+            pubkey: "nope".to_string(),
+            label: None,
+        });
+
+        // the duplicate is dropped and the invalid entry reported by position
+        let (valid, invalid) = validate(entries, &labels);
+        assert_eq!(valid.len(), 2);
+        assert_eq!(valid[0].1, None);
+
+        let label = valid[1].1.as_ref().unwrap();
+        assert_eq!(label.label, "Treasury");
+        assert_eq!(label.tags, vec!["dao"]);
+
+        assert_eq!(invalid.len(), 1);
+        assert_eq!(invalid[0].entry, 4);
+
+        let written = write(
+            &[AddressEntry {
+                pubkey: first.clone(),
+                label: Some("Hot, wallet".to_string()),
+            }],
+            ListFormat::Csv,
+        )
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(written).unwrap(),
+            format!("pubkey,label\n{first},\"Hot, wallet\"\n")
+        );
+    }
+}
@@ -0,0 +1,185 @@
+// Registry of human-readable labels for addresses
+
+// Responsibilities:
+// * Hold a label and tags per address, registered through the API and persisted in `labels`, so
+//   transaction responses can name known wallets ("Binance hot wallet") instead of showing base58.
+// * Resolve a tag to the addresses carrying it, for filtering transactions by tag.
+
+// Implementation:
+// * Labels are loaded from Postgres at startup and kept in memory, so labelling a response is a
+//   map lookup per address rather than a join on every transaction query.
+// * Tags are trimmed, lowercased and deduplicated on registration, so filtering by tag is
+//   case-insensitive.
+
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    str::FromStr,
+    sync::RwLock,
+};
+
+/// Longest label accepted, in bytes.
+const MAX_LABEL_LEN: usize = 200;
+
+/// A label and tags for an address, as registered through the API.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Label {
+    pub pubkey: String,
+    pub label: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+impl Label {
+    /// Check the label and normalize its pubkey and tags.
+    pub fn normalized(self) -> anyhow::Result<Self> {
+        let pubkey = Pubkey::from_str(&self.pubkey)
+            .map_err(|e| anyhow::anyhow!("Invalid public key `{}`: {e}", self.pubkey))?;
+
+        let label = self.label.trim();
+
+        if label.is_empty() || label.len() > MAX_LABEL_LEN {
+            anyhow::bail!("Labels must be between 1 and {MAX_LABEL_LEN} bytes");
+        }
+
+        let tags = self
+            .tags
+            .iter()
+            .map(|tag| tag.trim().to_lowercase())
+            .filter(|tag| !tag.is_empty())
+            .collect::<BTreeSet<_>>();
+
+        Ok(Label {
+            pubkey: pubkey.to_string(),
+            label: label.to_string(),
+            tags: tags.into_iter().collect(),
+        })
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct LabelRegistry {
+    /// Keyed by pubkey.
+    labels: RwLock<BTreeMap<String, Label>>,
+}
+
+impl LabelRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register (or replace) the label of an address, returning it as normalized.
+    pub fn register(&self, label: Label) -> anyhow::Result<Label> {
+        let label = label.normalized()?;
+
+        self.labels
+            .write()
+            .expect("label registry lock poisoned")
+            .insert(label.pubkey.clone(), label.clone());
+
+        Ok(label)
+    }
+
+    /// Forget the label of an address, returning whether it had one.
+    pub fn remove(&self, pubkey: &str) -> bool {
+        self.labels
+            .write()
+            .expect("label registry lock poisoned")
+            .remove(pubkey)
+            .is_some()
+    }
+
+    /// The label and tags of an address, if it has any.
+    pub fn get(&self, pubkey: &str) -> Option<Label> {
+        self.labels
+            .read()
+            .expect("label registry lock poisoned")
+            .get(pubkey)
+            .cloned()
+    }
+
+    pub fn label(&self, pubkey: &str) -> Option<String> {
+        self.labels
+            .read()
+            .expect("label registry lock poisoned")
+            .get(pubkey)
+            .map(|label| label.label.clone())
+    }
+
+    /// Addresses carrying `tag`, compared case-insensitively.
+    pub fn tagged(&self, tag: &str) -> Vec<String> {
+        let tag = tag.trim().to_lowercase();
+
+        self.labels
+            .read()
+            .expect("label registry lock poisoned")
+            .values()
+            .filter(|label| label.tags.contains(&tag))
+            .map(|label| label.pubkey.clone())
+            .collect()
+    }
+
+    /// Every label, by pubkey.
+    pub fn labels(&self) -> Vec<Label> {
+        self.labels
+            .read()
+            .expect("label registry lock poisoned")
+            .values()
+            .cloned()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_normalizes_and_resolves_tags() {
+        let registry = LabelRegistry::new();
+        let pubkey = Pubkey::new_unique().to_string();
+
+        let label = registry
+            .register(Label {
+                pubkey: pubkey.clone(),
+                label: " Binance hot wallet ".to_string(),
+                tags: vec![
+                    "Exchange".to_string(),
+                    "exchange".to_string(),
+                    " ".to_string(),
+                ],
+            })
+            .unwrap();
+        assert_eq!(label.label, "Binance hot wallet");
+        assert_eq!(label.tags, vec!["exchange"]);
+
+        assert_eq!(
+            registry.label(&pubkey).as_deref(),
+            Some("Binance hot wallet")
+        );
+        assert_eq!(registry.tagged("EXCHANGE"), vec![pubkey.clone()]);
+        assert!(registry.tagged("dex").is_empty());
+
+        assert!(registry
+            .register(Label {
+                pubkey: "not-a-pubkey".to_string(),
+                label: "x".to_string(),
+                tags: vec![],
+            })
+            .is_err());
+        assert!(registry
+            .register(Label {
+                pubkey: pubkey.clone(),
+                label: String::new(),
+                tags: vec![],
+            })
+            .is_err());
+
+        assert!(registry.remove(&pubkey));
+        assert!(!registry.remove(&pubkey));
+        assert_eq!(registry.label(&pubkey), None);
+    }
+}
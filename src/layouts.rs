@@ -0,0 +1,493 @@
+// Registry of account data layouts used to decode watched accounts
+
+// Responsibilities:
+// * Hold borsh layouts per program, registered from the config file, Anchor IDL files or the API.
+// * Decode raw account data into JSON fields using the matching layout.
+
+// Implementation:
+// * Layout types use the Anchor IDL type syntax (`"u64"`, `{ "vec": "u8" }`, `{ "defined": "X" }`),
+//   so IDL account types can be loaded as-is.
+// * A layout matches account data by its 8-byte discriminator; a program with a single layout
+//   and no discriminator matches all of its accounts.
+
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+use solana_sdk::pubkey::Pubkey;
+
+use std::{collections::HashMap, fs, path::Path, str::FromStr, sync::RwLock};
+
+/// Maximum nesting of `defined` types, to reject self-referential layouts.
+const MAX_DEPTH: usize = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Primitive {
+    Bool,
+    U8,
+    U16,
+    U32,
+    U64,
+    U128,
+    I8,
+    I16,
+    I32,
+    I64,
+    I128,
+    F32,
+    F64,
+    String,
+    Bytes,
+    #[serde(alias = "publicKey")]
+    Pubkey,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum DefinedRef {
+    Name(String),
+    Named { name: String },
+}
+
+impl DefinedRef {
+    fn name(&self) -> &str {
+        match self {
+            DefinedRef::Name(name) | DefinedRef::Named { name } => name,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum FieldType {
+    Primitive(Primitive),
+    Vec { vec: Box<FieldType> },
+    Option { option: Box<FieldType> },
+    Array { array: (Box<FieldType>, usize) },
+    Defined { defined: DefinedRef },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Field {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: FieldType,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum VariantFields {
+    Named(Vec<Field>),
+    Tuple(Vec<FieldType>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Variant {
+    pub name: String,
+    pub fields: Option<VariantFields>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+pub enum TypeDefKind {
+    Struct { fields: Vec<Field> },
+    Enum { variants: Vec<Variant> },
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TypeDef {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub ty: TypeDefKind,
+}
+
+/// Layout of one account type owned by a program.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AccountLayout {
+    pub program: String,
+    pub name: String,
+    /// Hex-encoded prefix identifying this account type (Anchor's is 8 bytes).
+    pub discriminator: Option<String>,
+    pub fields: Vec<Field>,
+    /// Types referenced from `fields` through `defined`.
+    #[serde(default)]
+    pub types: Vec<TypeDef>,
+}
+
+/// Account data decoded with a registered layout.
+#[derive(Debug, Clone, Serialize)]
+pub struct DecodedAccount {
+    pub layout: String,
+    pub fields: Value,
+}
+
+#[derive(Debug, Default)]
+struct ProgramLayouts {
+    accounts: Vec<(AccountLayout, Option<Vec<u8>>)>,
+    types: HashMap<String, TypeDefKind>,
+}
+
+#[derive(Debug, Default)]
+pub struct LayoutRegistry {
+    programs: RwLock<HashMap<String, ProgramLayouts>>,
+}
+
+impl LayoutRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register (or replace) a layout.
+    pub fn register(&self, layout: AccountLayout) -> anyhow::Result<()> {
+        Pubkey::from_str(&layout.program)
+            .map_err(|e| anyhow::anyhow!("Invalid program id `{}`: {e}", layout.program))?;
+
+        let discriminator = layout
+            .discriminator
+            .as_deref()
+            .map(hex::decode)
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("Invalid discriminator for `{}`: {e}", layout.name))?;
+
+        let mut programs = self.programs.write().expect("Layout registry lock poisoned");
+        let program = programs.entry(layout.program.clone()).or_default();
+
+        for type_def in &layout.types {
+            program
+                .types
+                .insert(type_def.name.clone(), type_def.ty.clone());
+        }
+
+        program
+            .accounts
+            .retain(|(existing, _)| existing.name != layout.name);
+        program.accounts.push((layout, discriminator));
+
+        Ok(())
+    }
+
+    /// Register every account type of an Anchor IDL (legacy or 0.30+ format).
+    ///
+    /// `program` overrides the program id found in the IDL.
+    pub fn register_idl(&self, path: &Path, program: Option<&str>) -> anyhow::Result<usize> {
+        let idl: Value = serde_json::from_str(&fs::read_to_string(path).map_err(|e| {
+            anyhow::anyhow!("Failed to read IDL `{}`: {e}", path.display())
+        })?)?;
+
+        let program = program
+            .or_else(|| idl["address"].as_str())
+            .or_else(|| idl["metadata"]["address"].as_str())
+            .ok_or_else(|| anyhow::anyhow!("IDL `{}` has no program address", path.display()))?
+            .to_string();
+
+        let types: Vec<TypeDef> = serde_json::from_value(idl["types"].clone()).unwrap_or_default();
+
+        let accounts = idl["accounts"].as_array().cloned().unwrap_or_default();
+        let mut registered = 0;
+
+        for account in accounts {
+            let Some(name) = account["name"].as_str() else {
+                continue;
+            };
+
+            // legacy IDLs inline the account type, newer ones reference `types`
+            let ty: TypeDefKind = match account.get("type") {
+                Some(ty) => serde_json::from_value(ty.clone())?,
+                None => match types.iter().find(|t| t.name == name) {
+                    Some(type_def) => type_def.ty.clone(),
+                    None => continue,
+                },
+            };
+
+            let TypeDefKind::Struct { fields } = ty else {
+                continue;
+            };
+
+            let discriminator = match account["discriminator"].as_array() {
+                Some(bytes) => bytes
+                    .iter()
+                    .map(|b| b.as_u64().map(|b| b as u8))
+                    .collect::<Option<Vec<_>>>()
+                    .ok_or_else(|| anyhow::anyhow!("Invalid discriminator for `{name}`"))?,
+                None => anchor_discriminator(name).to_vec(),
+            };
+
+            self.register(AccountLayout {
+                program: program.clone(),
+                name: name.to_string(),
+                discriminator: Some(hex::encode(discriminator)),
+                fields,
+                types: types.clone(),
+            })?;
+
+            registered += 1;
+        }
+
+        Ok(registered)
+    }
+
+    /// All registered layouts.
+    pub fn layouts(&self) -> Vec<AccountLayout> {
+        let programs = self.programs.read().expect("Layout registry lock poisoned");
+
+        programs
+            .values()
+            .flat_map(|program| program.accounts.iter().map(|(layout, _)| layout.clone()))
+            .collect()
+    }
+
+    /// Decode account data owned by `program`, or `None` if no registered layout matches.
+    pub fn decode(&self, program: &str, data: &[u8]) -> Option<DecodedAccount> {
+        let programs = self.programs.read().expect("Layout registry lock poisoned");
+        let program = programs.get(program)?;
+
+        let (layout, body) = program.accounts.iter().find_map(|(layout, discriminator)| {
+            match discriminator {
+                Some(prefix) => data
+                    .strip_prefix(prefix.as_slice())
+                    .map(|body| (layout, body)),
+                None if program.accounts.len() == 1 => Some((layout, data)),
+                None => None,
+            }
+        })?;
+
+        let mut decoder = Decoder {
+            data: body,
+            types: &program.types,
+        };
+
+        decoder
+            .decode_fields(&layout.fields, 0)
+            .map(|fields| DecodedAccount {
+                layout: layout.name.clone(),
+                fields,
+            })
+    }
+}
+
+/// Anchor's account discriminator: the first 8 bytes of `sha256("account:<Name>")`.
+pub fn anchor_discriminator(name: &str) -> [u8; 8] {
+    let hash = Sha256::digest(format!("account:{name}"));
+    let mut discriminator = [0; 8];
+    discriminator.copy_from_slice(&hash[..8]);
+    discriminator
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    types: &'a HashMap<String, TypeDefKind>,
+}
+
+impl<'a> Decoder<'a> {
+    fn take(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.data.len() < len {
+            return None;
+        }
+
+        let (taken, rest) = self.data.split_at(len);
+        self.data = rest;
+        Some(taken)
+    }
+
+    fn take_array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        self.take(N)?.try_into().ok()
+    }
+
+    fn take_len(&mut self) -> Option<usize> {
+        Some(u32::from_le_bytes(self.take_array()?) as usize)
+    }
+
+    fn decode_fields(&mut self, fields: &[Field], depth: usize) -> Option<Value> {
+        let mut object = Map::new();
+
+        for field in fields {
+            object.insert(field.name.clone(), self.decode(&field.ty, depth)?);
+        }
+
+        Some(Value::Object(object))
+    }
+
+    fn decode(&mut self, ty: &FieldType, depth: usize) -> Option<Value> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+
+        match ty {
+            FieldType::Primitive(primitive) => self.decode_primitive(*primitive),
+            FieldType::Vec { vec } => {
+                let len = self.take_len()?;
+                // every element takes at least one byte, so this bounds hostile lengths
+                if len > self.data.len() {
+                    return None;
+                }
+                (0..len)
+                    .map(|_| self.decode(vec, depth + 1))
+                    .collect::<Option<Vec<_>>>()
+                    .map(Value::Array)
+            }
+            FieldType::Option { option } => match self.take_array::<1>()?[0] {
+                0 => Some(Value::Null),
+                1 => self.decode(option, depth + 1),
+                _ => None,
+            },
+            FieldType::Array { array: (item, len) } => (0..*len)
+                .map(|_| self.decode(item, depth + 1))
+                .collect::<Option<Vec<_>>>()
+                .map(Value::Array),
+            FieldType::Defined { defined } => match self.types.get(defined.name())? {
+                TypeDefKind::Struct { fields } => self.decode_fields(fields, depth + 1),
+                TypeDefKind::Enum { variants } => {
+                    let variant = variants.get(self.take_array::<1>()?[0] as usize)?;
+
+                    let value = match &variant.fields {
+                        None => return Some(json!(variant.name)),
+                        Some(VariantFields::Named(fields)) => {
+                            self.decode_fields(fields, depth + 1)?
+                        }
+                        Some(VariantFields::Tuple(types)) => Value::Array(
+                            types
+                                .iter()
+                                .map(|ty| self.decode(ty, depth + 1))
+                                .collect::<Option<Vec<_>>>()?,
+                        ),
+                    };
+
+                    let mut object = Map::new();
+                    object.insert(variant.name.clone(), value);
+                    Some(Value::Object(object))
+                }
+            },
+        }
+    }
+
+    fn decode_primitive(&mut self, primitive: Primitive) -> Option<Value> {
+        let value = match primitive {
+            Primitive::Bool => match self.take_array::<1>()?[0] {
+                0 => json!(false),
+                1 => json!(true),
+                _ => return None,
+            },
+            Primitive::U8 => json!(u8::from_le_bytes(self.take_array()?)),
+            Primitive::U16 => json!(u16::from_le_bytes(self.take_array()?)),
+            Primitive::U32 => json!(u32::from_le_bytes(self.take_array()?)),
+            Primitive::U64 => json!(u64::from_le_bytes(self.take_array()?)),
+            // 128-bit integers don't fit JSON numbers safely
+            Primitive::U128 => json!(u128::from_le_bytes(self.take_array()?).to_string()),
+            Primitive::I8 => json!(i8::from_le_bytes(self.take_array()?)),
+            Primitive::I16 => json!(i16::from_le_bytes(self.take_array()?)),
+            Primitive::I32 => json!(i32::from_le_bytes(self.take_array()?)),
+            Primitive::I64 => json!(i64::from_le_bytes(self.take_array()?)),
+            Primitive::I128 => json!(i128::from_le_bytes(self.take_array()?).to_string()),
+            Primitive::F32 => json!(f32::from_le_bytes(self.take_array()?)),
+            Primitive::F64 => json!(f64::from_le_bytes(self.take_array()?)),
+            Primitive::String => {
+                let len = self.take_len()?;
+                json!(std::str::from_utf8(self.take(len)?).ok()?)
+            }
+            Primitive::Bytes => {
+                let len = self.take_len()?;
+                json!(BASE64.encode(self.take(len)?))
+            }
+            Primitive::Pubkey => json!(Pubkey::new_from_array(self.take_array()?).to_string()),
+        };
+
+        Some(value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn program_id() -> String {
+        Pubkey::new_unique().to_string()
+    }
+
+    fn layout(program: &str) -> AccountLayout {
+        serde_json::from_value(json!({
+            "program": program,
+            "name": "Config",
+            "discriminator": hex::encode(anchor_discriminator("Config")),
+            "fields": [
+                { "name": "authority", "type": "publicKey" },
+                { "name": "fee_bps", "type": "u16" },
+                { "name": "label", "type": "string" },
+                { "name": "limits", "type": { "vec": "u64" } },
+                { "name": "pending", "type": { "option": "u8" } },
+                { "name": "mode", "type": { "defined": "Mode" } }
+            ],
+            "types": [
+                {
+                    "name": "Mode",
+                    "type": { "kind": "enum", "variants": [{ "name": "Paused" }, { "name": "Active" }] }
+                }
+            ]
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_decode_with_discriminator() {
+        let program = program_id();
+        let registry = LayoutRegistry::new();
+        registry.register(layout(&program)).unwrap();
+
+        let authority = Pubkey::new_unique();
+
+        let mut data = anchor_discriminator("Config").to_vec();
+        data.extend_from_slice(authority.as_ref());
+        data.extend_from_slice(&250u16.to_le_bytes());
+        data.extend_from_slice(&4u32.to_le_bytes());
+        data.extend_from_slice(b"main");
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&10u64.to_le_bytes());
+        data.extend_from_slice(&20u64.to_le_bytes());
+        data.push(0);
+        data.push(1);
+
+        let decoded = registry.decode(&program, &data).unwrap();
+
+        assert_eq!(decoded.layout, "Config");
+        assert_eq!(
+            decoded.fields,
+            json!({
+                "authority": authority.to_string(),
+                "fee_bps": 250,
+                "label": "main",
+                "limits": [10, 20],
+                "pending": null,
+                "mode": "Active"
+            })
+        );
+    }
+
+    #[test]
+    fn test_decode_rejects_mismatch() {
+        let program = program_id();
+        let registry = LayoutRegistry::new();
+        registry.register(layout(&program)).unwrap();
+
+        // wrong discriminator
+        assert!(registry.decode(&program, &[0; 64]).is_none());
+
+        // truncated data
+        let data = anchor_discriminator("Config").to_vec();
+        assert!(registry.decode(&program, &data).is_none());
+
+        // unknown program
+        assert!(registry.decode(&program_id(), &[0; 64]).is_none());
+    }
+
+    #[test]
+    fn test_register_replaces_layout() {
+        let program = program_id();
+        let registry = LayoutRegistry::new();
+
+        registry.register(layout(&program)).unwrap();
+        registry.register(layout(&program)).unwrap();
+
+        assert_eq!(registry.layouts().len(), 1);
+    }
+}
@@ -0,0 +1,374 @@
+// Tracks the most active senders, receivers and programs
+
+// Responsibilities:
+// * Keep approximate top-N leaderboards of senders, receivers and invoked programs over the last
+//   5 minutes, hour and day, updated as transactions are stored.
+// * Answer leaderboard queries from memory, without scanning stored transactions.
+
+// Implementation:
+// * Each window is split into `BUCKETS_PER_WINDOW` buckets by block time, so it slides one bucket
+//   at a time and expired buckets are simply dropped.
+// * Each bucket counts keys with the space-saving algorithm: at most `CAPACITY` keys are kept and
+//   a new key replaces the smallest count, inheriting it as its possible overcount. Memory stays
+//   bounded however many distinct addresses show up, and any key above 1/`CAPACITY` of a bucket's
+//   transactions is always kept.
+// * Queries add up a window's buckets. Counts may be overestimated by at most `max_error`, and
+//   keys that were evicted from some buckets may be undercounted there.
+// * Programs are those the log messages show being invoked, each counted once per transaction.
+// * Counts start empty on every restart.
+
+use crate::data_processing::{invoked_programs, TransactionData};
+
+use serde::{Deserialize, Serialize};
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Keys counted per bucket and dimension.
+const CAPACITY: usize = 1000;
+
+const BUCKETS_PER_WINDOW: i64 = 12;
+
+/// What a leaderboard ranks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Dimension {
+    Senders,
+    Receivers,
+    Programs,
+}
+
+impl Dimension {
+    fn index(self) -> usize {
+        match self {
+            Dimension::Senders => 0,
+            Dimension::Receivers => 1,
+            Dimension::Programs => 2,
+        }
+    }
+}
+
+/// How far back a leaderboard looks.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize, Serialize)]
+pub enum Window {
+    #[serde(rename = "5m")]
+    FiveMinutes,
+    #[default]
+    #[serde(rename = "1h")]
+    Hour,
+    #[serde(rename = "24h")]
+    Day,
+}
+
+impl Window {
+    const ALL: [Window; 3] = [Window::FiveMinutes, Window::Hour, Window::Day];
+
+    fn index(self) -> usize {
+        match self {
+            Window::FiveMinutes => 0,
+            Window::Hour => 1,
+            Window::Day => 2,
+        }
+    }
+
+    fn seconds(self) -> i64 {
+        match self {
+            Window::FiveMinutes => 300,
+            Window::Hour => 3600,
+            Window::Day => 86_400,
+        }
+    }
+
+    fn bucket_seconds(self) -> i64 {
+        self.seconds() / BUCKETS_PER_WINDOW
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LeaderboardEntry {
+    pub key: String,
+    /// Transactions in the window, possibly overestimated by up to `max_error`.
+    pub count: u64,
+    pub max_error: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Leaderboard {
+    pub dimension: Dimension,
+    pub window: Window,
+    pub entries: Vec<LeaderboardEntry>,
+    /// Unix seconds.
+    pub as_of: i64,
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Counter {
+    count: u64,
+    error: u64,
+}
+
+/// Space-saving summary of one bucket's keys.
+#[derive(Debug, Default)]
+struct SpaceSaving {
+    counters: HashMap<String, Counter>,
+}
+
+impl SpaceSaving {
+    fn add(&mut self, key: &str) {
+        if let Some(counter) = self.counters.get_mut(key) {
+            counter.count += 1;
+            return;
+        }
+
+        if self.counters.len() < CAPACITY {
+            self.counters
+                .insert(key.to_string(), Counter { count: 1, error: 0 });
+            return;
+        }
+
+        let Some((smallest, min)) = self
+            .counters
+            .iter()
+            .min_by_key(|(_, counter)| counter.count)
+            .map(|(key, counter)| (key.clone(), counter.count))
+        else {
+            return;
+        };
+
+        self.counters.remove(&smallest);
+        self.counters.insert(
+            key.to_string(),
+            Counter {
+                count: min + 1,
+                error: min,
+            },
+        );
+    }
+}
+
+#[derive(Debug, Default)]
+struct Bucket {
+    /// Unix seconds.
+    start: i64,
+    /// Indexed by `Dimension::index`.
+    summaries: [SpaceSaving; 3],
+}
+
+/// A window's buckets, oldest first.
+#[derive(Debug, Default)]
+struct SlidingWindow {
+    buckets: VecDeque<Bucket>,
+}
+
+impl SlidingWindow {
+    fn bucket(&mut self, start: i64) -> &mut Bucket {
+        let position = self.buckets.partition_point(|bucket| bucket.start < start);
+
+        if self
+            .buckets
+            .get(position)
+            .map_or(true, |bucket| bucket.start != start)
+        {
+            self.buckets.insert(
+                position,
+                Bucket {
+                    start,
+                    ..Default::default()
+                },
+            );
+        }
+
+        &mut self.buckets[position]
+    }
+
+    /// Drop the buckets that ended before `cutoff`.
+    fn expire(&mut self, cutoff: i64, bucket_seconds: i64) {
+        while self
+            .buckets
+            .front()
+            .is_some_and(|bucket| bucket.start + bucket_seconds <= cutoff)
+        {
+            self.buckets.pop_front();
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Leaderboards {
+    /// Indexed by `Window::index`.
+    windows: Mutex<[SlidingWindow; 3]>,
+}
+
+impl Leaderboards {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Count newly stored transactions.
+    pub fn record<'a>(&self, txns: impl IntoIterator<Item = &'a TransactionData>) {
+        self.record_at(txns, unix_now());
+    }
+
+    fn record_at<'a>(&self, txns: impl IntoIterator<Item = &'a TransactionData>, now: i64) {
+        let mut windows = self.windows.lock().expect("leaderboards lock poisoned");
+
+        for window in Window::ALL {
+            windows[window.index()].expire(now - window.seconds(), window.bucket_seconds());
+        }
+
+        for txn in txns {
+            let programs = invoked_programs(&txn.log_messages);
+
+            for window in Window::ALL {
+                if txn.timestamp <= now - window.seconds() {
+                    continue;
+                }
+
+                let bucket_seconds = window.bucket_seconds();
+                let bucket = windows[window.index()]
+                    .bucket(txn.timestamp - txn.timestamp.rem_euclid(bucket_seconds));
+
+                bucket.summaries[Dimension::Senders.index()].add(&txn.sender);
+                bucket.summaries[Dimension::Receivers.index()].add(&txn.receiver);
+
+                for program in &programs {
+                    bucket.summaries[Dimension::Programs.index()].add(program);
+                }
+            }
+        }
+    }
+
+    /// The `limit` highest counts of `dimension` over `window`.
+    pub fn top(&self, dimension: Dimension, window: Window, limit: usize) -> Leaderboard {
+        self.top_at(dimension, window, limit, unix_now())
+    }
+
+    fn top_at(&self, dimension: Dimension, window: Window, limit: usize, now: i64) -> Leaderboard {
+        let mut windows = self.windows.lock().expect("leaderboards lock poisoned");
+        let sliding = &mut windows[window.index()];
+        sliding.expire(now - window.seconds(), window.bucket_seconds());
+
+        let mut totals = HashMap::<&str, Counter>::new();
+
+        for bucket in &sliding.buckets {
+            for (key, counter) in &bucket.summaries[dimension.index()].counters {
+                let total = totals.entry(key).or_default();
+                total.count += counter.count;
+                total.error += counter.error;
+            }
+        }
+
+        let mut entries = totals
+            .into_iter()
+            .map(|(key, counter)| LeaderboardEntry {
+                key: key.to_string(),
+                count: counter.count,
+                max_error: counter.error,
+            })
+            .collect::<Vec<_>>();
+
+        entries.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.key.cmp(&b.key)));
+        entries.truncate(limit);
+
+        Leaderboard {
+            dimension,
+            window,
+            entries,
+            as_of: now,
+        }
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn txn(sender: &str, receiver: &str, timestamp: i64) -> TransactionData {
+        TransactionData {
+            signature: format!("{sender}-{receiver}-{timestamp}"),
+            sender: sender.to_string(),
+            receiver: receiver.to_string(),
+            sol_amount: 1,
+            fee: 5000,
+            timestamp,
+            log_messages: vec![
+                "Program 11111111111111111111111111111111 invoke [1]".to_string(),
+                "Program 11111111111111111111111111111111 success".to_string(),
+            ],
+            ..TransactionData::default()
+        }
+    }
+
+    fn keys(leaderboard: &Leaderboard) -> Vec<(&str, u64)> {
+        leaderboard
+            .entries
+            .iter()
+            .map(|entry| (entry.key.as_str(), entry.count))
+            .collect()
+    }
+
+    #[test]
+    fn test_windows_rank_recent_activity() {
+        let leaderboards = Leaderboards::new();
+        leaderboards.record_at(
+            &[
+                txn("alice", "bob", NOW - 10),
+                txn("alice", "carol", NOW - 20),
+                txn("bob", "carol", NOW - 30),
+                // only in the hour and day windows
+                txn("dave", "carol", NOW - 1000),
+                txn("dave", "carol", NOW - 1100),
+                txn("dave", "carol", NOW - 1200),
+                // too old for any window
+                txn("erin", "carol", NOW - 100_000),
+            ],
+            NOW,
+        );
+
+        let recent = leaderboards.top_at(Dimension::Senders, Window::FiveMinutes, 10, NOW);
+        assert_eq!(keys(&recent), [("alice", 2), ("bob", 1)]);
+
+        let hour = leaderboards.top_at(Dimension::Senders, Window::Hour, 2, NOW);
+        assert_eq!(keys(&hour), [("dave", 3), ("alice", 2)]);
+
+        let receivers = leaderboards.top_at(Dimension::Receivers, Window::Day, 1, NOW);
+        assert_eq!(keys(&receivers), [("carol", 5)]);
+
+        let programs = leaderboards.top_at(Dimension::Programs, Window::Day, 10, NOW);
+        assert_eq!(keys(&programs), [("11111111111111111111111111111111", 6)]);
+
+        // ten minutes later the five-minute window is empty
+        let later = leaderboards.top_at(Dimension::Senders, Window::FiveMinutes, 10, NOW + 600);
+        assert!(later.entries.is_empty());
+    }
+
+    #[test]
+    fn test_space_saving_keeps_heavy_hitters() {
+        let mut summary = SpaceSaving::default();
+
+        for i in 0..CAPACITY * 3 {
+            summary.add(&format!("key{i}"));
+
+            if i % 2 == 0 {
+                summary.add("heavy");
+            }
+        }
+
+        assert_eq!(summary.counters.len(), CAPACITY);
+
+        let heavy = summary.counters["heavy"];
+        assert_eq!(heavy.count, (CAPACITY * 3 / 2) as u64);
+        assert_eq!(heavy.error, 0);
+    }
+}
@@ -0,0 +1,100 @@
+//! Aggregates Solana transactions into PostgreSQL, SQLite or memory and serves them over REST,
+//! GraphQL and gRPC.
+//!
+//! The aggregator binary is a thin layer over this crate. Services that only need part of it
+//! can use these modules directly:
+//!
+//! * [`data_retrieval`]: [`SolanaClient`] fetches transactions and account data over RPC, with
+//!   fallback endpoints, and processes and stores them in one go.
+//! * [`data_processing`]: [`parse_transaction`] turns a `jsonParsed` RPC transaction into a
+//!   [`TransactionData`], and the ingestion filters decide which of them are kept.
+//! * [`data_storage`]: the [`Storage`] trait, implemented for PostgreSQL, SQLite and memory, and
+//!   the queries for the Postgres-only tables.
+//! * [`pipeline`]: [`Pipeline`] runs ingestion as a source, a chain of processors and a
+//!   destination connected by bounded channels, to insert custom filters and transforms.
+//!
+//! ```no_run
+//! use solana_data_aggregator::{data_storage::get_pool, SolanaClient, Storage};
+//! use solana_sdk::pubkey::Pubkey;
+//! use std::{str::FromStr, sync::Arc};
+//!
+//! # async fn run() -> anyhow::Result<()> {
+//! let client = SolanaClient::new("https://api.devnet.solana.com");
+//! let storage: Arc<dyn Storage> = Arc::new(get_pool("postgres://localhost/solana").await?);
+//!
+//! let address = Pubkey::from_str("9WgXgM4UQftvDStk9SMeLBjQ1tr1sVpYzVv9ekDwpa5X")?;
+//! let signatures = client.fetch_recent_signatures(&address, 100).await?;
+//! let batch = client.process(client.fetch_transactions(&signatures).await?, None);
+//! client.store(&batch, &storage, None).await?;
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! The remaining modules make up the server around them. They are public so the binary can be
+//! built on the library, but they follow the server's needs rather than a stable API.
+
+pub mod accounts;
+pub mod alerting;
+pub mod alerts;
+pub mod analysis;
+pub mod analytics;
+pub mod api;
+pub mod archive;
+pub mod auth;
+pub mod blocks;
+pub mod cache;
+pub mod canary;
+pub mod config;
+pub mod dashboards;
+pub mod data_processing;
+pub mod data_retrieval;
+pub mod data_storage;
+pub mod dedup;
+pub mod demo;
+pub mod directory;
+pub mod dlq;
+pub mod encoding;
+pub mod enrichment;
+pub mod epochs;
+pub mod export;
+pub mod fee_burn;
+pub mod feed;
+pub mod finality;
+pub mod gaps;
+#[cfg(feature = "yellowstone")]
+pub mod geyser;
+pub mod graph;
+pub mod graphql;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod ids;
+pub mod imports;
+pub mod labels;
+pub mod layouts;
+pub mod leaderboards;
+pub mod maintenance;
+pub mod memory;
+pub mod metering;
+pub mod metrics;
+pub mod monitor;
+pub mod outbox;
+pub mod pipeline;
+pub mod portfolio;
+pub mod prices;
+pub mod rate_limit;
+pub mod replication;
+pub mod reports;
+pub mod retry;
+pub mod rpc;
+pub mod rpc_proxy;
+pub mod schedules;
+pub mod search;
+pub mod sink;
+pub mod slo;
+pub mod stats;
+pub mod watchlist;
+
+pub use data_processing::{parse_transaction, TransactionData};
+pub use data_retrieval::SolanaClient;
+pub use data_storage::Storage;
+pub use pipeline::Pipeline;
@@ -1,43 +1,1053 @@
-mod api;
-mod data_processing;
-mod data_retrieval;
-mod data_storage;
+mod commands;
 
-use solana_sdk::pubkey::Pubkey;
-use tokio::task;
+use log::{error, info, warn};
+use solana_sdk::{epoch_schedule::EpochSchedule, pubkey::Pubkey};
+use sqlx::PgPool;
+use tokio::{
+    runtime::Handle,
+    signal::unix::{signal, SignalKind},
+    task::{self, JoinHandle},
+    time::{self, Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
 
-use std::{env, str::FromStr, sync::Arc};
+use std::{
+    future::{self, Future},
+    str::FromStr,
+    sync::Arc,
+};
 
-use data_retrieval::SolanaClient;
-use data_storage::get_pool;
+use solana_data_aggregator::{
+    accounts::AccountCache,
+    alerting::Alerter,
+    alerts::AlertEngine,
+    analysis::Analyzer,
+    api::{self, ApiContext, Exposure, PostgresContext},
+    archive::RawArchive,
+    auth::Authenticator,
+    cache::CachedStorage,
+    canary::Canary,
+    config::{Command, Config, StorageBackend},
+    dashboards::Dashboards,
+    data_retrieval::SolanaClient,
+    data_storage::{
+        enable_timescale, get_account_layouts, get_labels, get_pool, get_sqlite_pool,
+        get_watched_addresses, InMemoryStorage, Storage,
+    },
+    dedup::SeenSignatures,
+    demo,
+    directory::Directory,
+    dlq::{DeadLetterQueue, REPLAY_JOB},
+    enrichment::NftEnricher,
+    epochs::EpochTracker,
+    fee_burn::FeeBurnPolicy,
+    finality::FinalityTracker,
+    gaps::GapRepair,
+    ids::IdCodec,
+    labels::LabelRegistry,
+    layouts::LayoutRegistry,
+    maintenance::MaintenanceScheduler,
+    memory::MemoryBudget,
+    outbox::{OutboxRelay, OutboxStorage},
+    portfolio::Portfolios,
+    prices::PriceFeed,
+    replication::Follower,
+    reports::DailyReporter,
+    rpc,
+    rpc_proxy::RpcProxy,
+    schedules::Schedules,
+    sink::Sinks,
+    slo::SloTracker,
+    stats::StatsCache,
+    watchlist::Watchlist,
+};
+
+#[cfg(feature = "yellowstone")]
+use solana_data_aggregator::geyser;
+#[cfg(feature = "grpc")]
+use solana_data_aggregator::grpc;
+
+#[cfg(feature = "jemalloc")]
+#[global_allocator]
+static GLOBAL: tikv_jemallocator::Jemalloc = tikv_jemallocator::Jemalloc;
+
+/// First delay before restarting a crashed task; doubled on every consecutive crash.
+const INITIAL_RESTART_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_RESTART_BACKOFF: Duration = Duration::from_secs(300);
+
+/// A task that ran at least this long before crashing is considered to have been healthy,
+/// so the backoff starts over.
+const HEALTHY_RUN: Duration = Duration::from_secs(600);
+
+/// Time between runs of the outbox relay, which publish everything waiting in the outbox.
+const OUTBOX_RELAY_INTERVAL: Duration = Duration::from_secs(1);
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
-    dotenvy::dotenv()?;
+    let _ = dotenvy::dotenv();
 
-    env_logger::init();
+    let config = Config::load()?;
 
-    // RPC client setup
-    let rpc_url = env::var("RPC_URL")?;
-    let solana_client = SolanaClient::new(&rpc_url);
+    env_logger::Builder::new()
+        .parse_filters(&config.log_level)
+        .init();
 
-    // monitored address's public key
-    let address = Pubkey::from_str(&env::var("ADDRESS_A")?)?;
+    for deprecation in &config.deprecations {
+        warn!("{deprecation}");
+    }
 
-    // database setup
-    let db_url = env::var("DATABASE_URL")?;
-    let db = Arc::new(get_pool(&db_url).await?);
-    let db_clone = Arc::clone(&db);
+    match config.command.clone() {
+        Command::Serve => serve(config).await,
+        Command::Backfill { pubkey, limit } => commands::backfill(&config, &pubkey, limit).await,
+        Command::Fetch { signature, raw } => commands::fetch(&config, &signature, raw).await,
+        Command::Export(args) => commands::export(&config, &args).await,
+        Command::Migrate => commands::migrate(&config).await,
+    }
+}
 
-    // start monitoring the blockchain
-    task::spawn(async move {
-        solana_client
-            .monitor_blockchain(address, Some(&db_clone))
-            .await;
+/// Monitor the watched addresses and serve the API until shut down.
+async fn serve(config: Config) -> anyhow::Result<()> {
+    let alerter = Alerter::new(config.alert_webhook_url.clone());
+    let id_codec = IdCodec::from_config(&config.ids)?;
+    let shutdown = CancellationToken::new();
+
+    let mut solana_client = solana_client(&config).await;
+
+    // monitored addresses: the configured ones, those imported through the API and, in personal
+    // mode, those found for owners; watched programs are polled alongside them
+    let watchlist = Arc::new(Watchlist::new(
+        config.addresses.clone(),
+        config.programs.clone(),
+        &config.personal,
+    )?);
+
+    // everything beyond the transactions themselves needs Postgres
+    let (db, storage) = open_storage(&config).await?;
+
+    if let Some(db) = &db {
+        let imported = get_watched_addresses(db)
+            .await?
+            .iter()
+            .map(|pubkey| Pubkey::from_str(pubkey))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        watchlist.import(&imported);
+    }
+
+    // addresses the analyzer refuses, since the monitor covers them
+    let mut addresses = watchlist.addresses();
+
+    // transaction queries served from a cache, dropped whenever transactions are stored
+    let cache = if config.cache.enabled {
+        Some(Arc::new(
+            CachedStorage::new(&config.cache, Arc::clone(&storage)).await?,
+        ))
+    } else {
+        None
+    };
+    let storage: Arc<dyn Storage> = match &cache {
+        Some(cache) => cache.clone(),
+        None => storage,
+    };
+
+    if db.is_none() {
+        warn!(
+            "Postgres-only features are off with {:?} storage",
+            config.storage
+        );
+    }
+
+    // API keys live in Postgres, which the config requires when they're used
+    let auth = db
+        .as_ref()
+        .map(|db| Arc::new(Authenticator::new(&config.auth, Arc::clone(db))));
+
+    if let Some(name) = &config.create_api_key {
+        let auth = auth.as_ref().expect("API keys need Postgres storage");
+        let key = auth.create_key(name, None).await?;
+
+        info!("Created API key {} ({})", key.key.id, key.key.name);
+        println!("{}", key.secret);
+        return Ok(());
+    }
+
+    // account data layouts: config file first, then IDLs, then those registered through the API
+    let layouts = Arc::new(LayoutRegistry::new());
+
+    for layout in config.layouts.iter().cloned() {
+        layouts.register(layout)?;
+    }
+
+    for idl in &config.idls {
+        let registered = layouts.register_idl(&idl.path, idl.program.as_deref())?;
+        info!(
+            "Registered {registered} layouts from {}",
+            idl.path.display()
+        );
+    }
+
+    if let Some(db) = &db {
+        for layout in get_account_layouts(db).await? {
+            layouts.register(layout)?;
+        }
+    }
+
+    // address labels registered through the API
+    let labels = Arc::new(LabelRegistry::new());
+
+    if let Some(db) = &db {
+        for label in get_labels(db).await? {
+            labels.register(label)?;
+        }
+    }
+
+    // compressed archive of raw transaction payloads, always readable through the API
+    let archive = match &db {
+        Some(db) => Some(Arc::new(RawArchive::load(&config.raw_archive, db).await?)),
+        None => None,
+    };
+
+    if let Some(archive) = archive.as_ref().filter(|_| config.raw_archive.enabled) {
+        solana_client = solana_client.with_raw_archive(Arc::clone(archive));
+    }
+
+    // token transfers of stored transactions, with the metadata of the NFTs they move
+    if config.enrichment.nfts && db.is_some() {
+        solana_client =
+            solana_client.with_nft_enricher(Arc::new(NftEnricher::new(&config.enrichment)));
+    }
+
+    // USD prices stored with transactions and token transfers
+    if config.prices.provider.is_some() {
+        solana_client =
+            solana_client.with_prices(Arc::new(PriceFeed::new(&config.prices, config.rpc_url())?));
+    }
+
+    // well-known programs and exchange wallets, which processed transactions are categorized with
+    let directory = Arc::new(Directory::load(&config.directory)?);
+
+    if config.directory.auto_tag {
+        solana_client = solana_client.with_directory(Arc::clone(&directory));
+    }
+
+    // message buses transactions are published to as they're ingested
+    if let Some(sinks) = Sinks::from_config(&config.sinks).await? {
+        solana_client = solana_client.with_sinks(sinks);
+    }
+
+    let mut background_tasks = Vec::new();
+    let schedules = Arc::new(Schedules::new());
+
+    // Kafka publishing through the outbox the storage writes, instead of the sinks
+    if let (Some(kafka), Some(db)) = (&config.sinks.kafka, &db) {
+        if kafka.exactly_once {
+            let relay = OutboxRelay::connect(kafka).await?;
+            let db = Arc::clone(db);
+            let relay_job = schedules.register("sink-outbox", Some(OUTBOX_RELAY_INTERVAL));
+            let relay_shutdown = shutdown.clone();
+
+            info!("Publishing stored transactions to Kafka exactly once");
+
+            background_tasks.push(task::spawn(async move {
+                relay.run(&db, &relay_job, &relay_shutdown).await;
+            }));
+        }
+    }
+
+    // Bloom filters of polled signatures, so polls skip stored transactions; only useful when
+    // transactions are stored
+    if config.dedup.enabled && !config.sinks.skip_storage {
+        let seen = Arc::new(SeenSignatures::load(&config.dedup)?);
+        solana_client = solana_client.with_seen_signatures(Arc::clone(&seen));
+
+        if config.dedup.path.is_some() {
+            let dedup_job = schedules.register(
+                "dedup-persist",
+                Some(Duration::from_secs(config.dedup.persist_interval_secs)),
+            );
+            let dedup_shutdown = shutdown.clone();
+
+            background_tasks.push(task::spawn(async move {
+                seen.run(&dedup_job, &dedup_shutdown).await;
+            }));
+        }
+    }
+
+    // transactions whose insert failed, replayed once the database recovers
+    let dead_letters = (config.dlq.enabled && !config.sinks.skip_storage)
+        .then(|| DeadLetterQueue::load(&config.dlq))
+        .transpose()?
+        .map(Arc::new);
+
+    if let Some(dead_letters) = &dead_letters {
+        solana_client = solana_client.with_dead_letters(Arc::clone(dead_letters));
+    }
+
+    // API key usage, written to the database in batches
+    let usage = auth.as_ref().map(|auth| auth.usage());
+
+    if let Some(usage) = usage.clone() {
+        let usage_job = schedules.register(
+            "usage-flush",
+            Some(Duration::from_secs(config.auth.usage_flush_interval_secs)),
+        );
+        let usage_shutdown = shutdown.clone();
+
+        background_tasks.push(task::spawn(async move {
+            usage.run(&usage_job, &usage_shutdown).await;
+        }));
+    }
+
+    // alert rules on ingested activity, which are stored in Postgres
+    let alerts = match (&db, config.alerts.enabled) {
+        (Some(db), true) => Some(Arc::new(
+            AlertEngine::load(&config.alerts, Arc::clone(db), usage.clone()).await?,
+        )),
+        _ => None,
+    };
+
+    if let Some(alerts) = &alerts {
+        solana_client = solana_client.with_alerts(Arc::clone(alerts));
+
+        let alerts = Arc::clone(alerts);
+        let alerts_job = schedules.register(
+            "alert-inactivity",
+            Some(Duration::from_secs(config.alerts.check_interval_secs)),
+        );
+        let alerts_shutdown = shutdown.clone();
+
+        background_tasks.push(task::spawn(async move {
+            alerts.run(&alerts_job, &alerts_shutdown).await;
+        }));
+
+        let lane = Arc::clone(alerts);
+        let lane_shutdown = shutdown.clone();
+
+        background_tasks.push(task::spawn(async move {
+            lane.run_lane(&lane_shutdown).await;
+        }));
+    }
+
+    // optional end-to-end canary probe, whose address is monitored alongside the others
+    if let Some(canary_config) = &config.canary {
+        let canary = Canary::new(
+            canary_config,
+            config.rpc_url(),
+            config.api_url(),
+            alerter.clone(),
+        )
+        .await?;
+
+        addresses.push(canary.pubkey());
+        solana_client = solana_client.with_canary(canary.pubkey());
+
+        let canary_storage = Arc::clone(&storage);
+        let canary_job = schedules.register(
+            "canary",
+            Some(Duration::from_secs(canary_config.interval_secs)),
+        );
+        let canary_shutdown = shutdown.clone();
+
+        background_tasks.push(task::spawn(async move {
+            canary
+                .run(&canary_storage, &canary_job, &canary_shutdown)
+                .await;
+        }));
+    }
+
+    let solana_client = Arc::new(solana_client);
+
+    // personal mode: keep the owners' token and stake accounts on the watchlist
+    if watchlist.discovers() {
+        let discoverer = Arc::clone(&watchlist);
+        let solana_client = Arc::clone(&solana_client);
+        let discovery_job = schedules.register(
+            "discovery",
+            Some(Duration::from_secs(config.personal.discovery_interval_secs)),
+        );
+        let discovery_shutdown = shutdown.clone();
+
+        background_tasks.push(task::spawn(async move {
+            discoverer
+                .run(&solana_client, &discovery_job, &discovery_shutdown)
+                .await;
+        }));
+    }
+
+    let postgres = match (&db, archive) {
+        (Some(db), Some(archive)) => {
+            let context = PostgresContext {
+                db: Arc::clone(db),
+                layouts,
+                stats: Arc::new(StatsCache::new(&config.stats)),
+                archive,
+                maintenance: Arc::new(MaintenanceScheduler::new(&config.maintenance)?),
+                analyzer: config.analysis.enabled.then(|| {
+                    Arc::new(Analyzer::new(
+                        &config.analysis,
+                        Arc::clone(&solana_client),
+                        &addresses,
+                    ))
+                }),
+                portfolios: Arc::new(Portfolios::new(
+                    &config.portfolio,
+                    Arc::clone(&solana_client),
+                )),
+                dashboards: Arc::new(Dashboards::new(&config.dashboards)),
+                epochs: config
+                    .epochs
+                    .enabled
+                    .then(|| Arc::new(EpochTracker::new(Arc::clone(&solana_client)))),
+                alerts,
+                finality: (config.finality.enabled && !config.sinks.skip_storage)
+                    .then(|| Arc::new(FinalityTracker::new(&config.finality, cache.clone()))),
+            };
+
+            spawn_postgres_tasks(
+                &config,
+                &context,
+                &watchlist,
+                &solana_client,
+                &schedules,
+                &alerter,
+                &shutdown,
+                &mut background_tasks,
+            )?;
+
+            Some(context)
+        }
+        _ => None,
+    };
+
+    // JSON-RPC proxy for API consumers, answering from the raw archive when it can
+    let rpc_proxy = config.rpc_proxy.enabled.then(|| {
+        Arc::new(RpcProxy::new(
+            &config.rpc_proxy,
+            Arc::clone(&solana_client),
+            postgres
+                .as_ref()
+                .map(|postgres| (Arc::clone(&postgres.db), Arc::clone(&postgres.archive))),
+        ))
     });
 
+    let ingest = solana_client.ingest_metrics();
+    let monitor = solana_client.monitor_control();
+    let feed = solana_client.transaction_feed();
+    let leaderboards = solana_client.leaderboards();
+    let memory = solana_client.memory_budget();
+    // lookups store what they fetch, so they're only served when transactions are stored
+    let lookup_client = (!config.sinks.skip_storage).then(|| Arc::clone(&solana_client));
+    let accounts = Arc::new(AccountCache::new(
+        &config.account_details,
+        Arc::clone(&solana_client),
+    ));
+
+    // as a warm standby, replicate the primary until promoted; the monitor waits until then
+    let follower = match &config.follower {
+        Some(follower_config) => {
+            info!(
+                "Replicating from {} until promoted",
+                follower_config.primary_url
+            );
+
+            let follower = Arc::new(Follower::new(follower_config, db.clone(), &shutdown));
+            let replicator = Arc::clone(&follower);
+            let solana_client = Arc::clone(&solana_client);
+            let storage = Arc::clone(&storage);
+            let replication_job = schedules.register(
+                "replication",
+                Some(Duration::from_secs(follower_config.interval_secs)),
+            );
+
+            background_tasks.push(task::spawn(async move {
+                replicator
+                    .replicate(&solana_client, &storage, &replication_job)
+                    .await;
+            }));
+
+            Some(follower)
+        }
+        None => None,
+    };
+
+    // the demo serves its sample transactions; with no addresses the monitor below stays idle
+    if config.demo {
+        let seeded = demo::seed(&solana_client, &storage).await?;
+
+        info!(
+            "Demo mode: serving {seeded} sample transactions at http://{}:{}/transactions",
+            config.host, config.port
+        );
+    }
+
+    // reconcile stored transactions with the chain, so those missed while down are stored too
+    let gaps = (config.gaps.enabled && !config.sinks.skip_storage)
+        .then(|| Arc::new(GapRepair::new(&config.gaps)));
+
+    if let Some(gaps) = gaps.clone() {
+        let solana_client = Arc::clone(&solana_client);
+        let watchlist = Arc::clone(&watchlist);
+        let storage = Arc::clone(&storage);
+        let db = db.clone();
+        let follower = follower.clone();
+        let gaps_job = schedules.register(
+            "gap-repair",
+            Some(Duration::from_secs(config.gaps.interval_secs)),
+        );
+        let gaps_shutdown = shutdown.clone();
+
+        background_tasks.push(task::spawn(async move {
+            if let Some(follower) = follower {
+                if !follower.until_promoted(&gaps_shutdown).await {
+                    return;
+                }
+            }
+
+            gaps.run(
+                &solana_client,
+                &watchlist,
+                &storage,
+                db.as_ref(),
+                &gaps_job,
+                &gaps_shutdown,
+            )
+            .await;
+        }));
+    }
+
+    // upgrade stored transactions to finalized, or mark those a fork abandoned dropped
+    let finality = postgres
+        .as_ref()
+        .and_then(|postgres| Some((postgres.finality.clone()?, Arc::clone(&postgres.db))));
+
+    if let Some((finality, db)) = finality {
+        let solana_client = Arc::clone(&solana_client);
+        let follower = follower.clone();
+        let finality_job = schedules.register(
+            "finality",
+            Some(Duration::from_secs(config.finality.interval_secs)),
+        );
+        let finality_shutdown = shutdown.clone();
+
+        background_tasks.push(task::spawn(async move {
+            if let Some(follower) = follower {
+                if !follower.until_promoted(&finality_shutdown).await {
+                    return;
+                }
+            }
+
+            finality
+                .run(&solana_client, &db, &finality_job, &finality_shutdown)
+                .await;
+        }));
+    }
+
+    if let Some(dead_letters) = dead_letters.clone() {
+        let solana_client = Arc::clone(&solana_client);
+        let storage = Arc::clone(&storage);
+        let dlq_job = schedules.register(
+            REPLAY_JOB,
+            Some(Duration::from_secs(config.dlq.retry_interval_secs)),
+        );
+        let dlq_shutdown = shutdown.clone();
+
+        background_tasks.push(task::spawn(async move {
+            dead_letters
+                .run(&solana_client, &storage, &dlq_job, &dlq_shutdown)
+                .await;
+        }));
+    }
+
+    // start monitoring the blockchain, restarting the monitor if it crashes
+    #[cfg(feature = "yellowstone")]
+    let monitor_geyser = config.geyser.clone();
+    #[cfg(not(feature = "yellowstone"))]
+    if config.geyser.is_some() {
+        warn!("Ignoring `[geyser]`: built without the `yellowstone` feature");
+    }
+
+    let monitor_watchlist = Arc::clone(&watchlist);
+    let monitor_storage = (!config.sinks.skip_storage).then(|| Arc::clone(&storage));
+    let monitor_db = db.clone();
+    let monitor_job = schedules.register("monitor", Some(config.poll_interval));
+    let monitor_shutdown = shutdown.clone();
+    let monitor_follower = follower.clone();
+
+    background_tasks.push(task::spawn(supervise(
+        "blockchain monitor",
+        shutdown.clone(),
+        alerter.clone(),
+        move || {
+            let solana_client = Arc::clone(&solana_client);
+            let watchlist = Arc::clone(&monitor_watchlist);
+            let storage = monitor_storage.clone();
+            let db = monitor_db.clone();
+            let job = Arc::clone(&monitor_job);
+            let shutdown = monitor_shutdown.clone();
+            let follower = monitor_follower.clone();
+            #[cfg(feature = "yellowstone")]
+            let geyser = monitor_geyser.clone();
+
+            async move {
+                if let Some(follower) = follower {
+                    if !follower.until_promoted(&shutdown).await {
+                        return;
+                    }
+                }
+
+                #[cfg(feature = "yellowstone")]
+                if let Some(geyser) = geyser {
+                    geyser::monitor(
+                        &geyser,
+                        &solana_client,
+                        &watchlist,
+                        storage.as_ref(),
+                        db.as_ref(),
+                        &job,
+                        &shutdown,
+                    )
+                    .await;
+                    return;
+                }
+
+                solana_client
+                    .monitor_blockchain(&watchlist, storage.as_ref(), db.as_ref(), &job, &shutdown)
+                    .await;
+            }
+        },
+    )));
+
+    // evaluate API SLOs in the background
+    let slo = Arc::new(SloTracker::new(config.slo.clone()));
+    let slo_checker = Arc::clone(&slo);
+    let slo_alerter = alerter.clone();
+    let slo_job = schedules.register(
+        "slo-checks",
+        Some(Duration::from_secs(config.slo.check_interval_secs)),
+    );
+    let slo_shutdown = shutdown.clone();
+
+    background_tasks.push(task::spawn(async move {
+        slo_checker
+            .run_checks(&slo_alerter, &slo_job, &slo_shutdown)
+            .await;
+    }));
+
     // run API server
-    api::main(db)?;
+    let tls = config.tls.as_ref().map(api::tls_config).transpose()?;
+    let context = ApiContext {
+        storage,
+        ids: id_codec,
+        slo,
+        ingest,
+        monitor,
+        feed,
+        leaderboards,
+        memory,
+        schedules,
+        labels,
+        directory,
+        accounts,
+        watchlist: Arc::clone(&watchlist),
+        auth,
+        follower,
+        gaps,
+        rpc_proxy,
+        dead_letters,
+        cache,
+        solana_client: lookup_client,
+        runtime: Handle::current(),
+        postgres,
+    };
+
+    // serve gRPC alongside REST
+    match &config.grpc {
+        #[cfg(feature = "grpc")]
+        Some(grpc_config) => {
+            let grpc_config = grpc_config.clone();
+            let service = grpc::AggregatorService::new(&context);
+            let grpc_shutdown = shutdown.clone();
+
+            background_tasks.push(task::spawn(async move {
+                if let Err(e) = grpc::serve(service, &grpc_config, grpc_shutdown).await {
+                    error!("gRPC server failed: {e:?}");
+                }
+            }));
+        }
+        #[cfg(not(feature = "grpc"))]
+        Some(_) => warn!("Ignoring `[grpc]`: built without the `grpc` feature"),
+        None => {}
+    }
+
+    let public_server = match &config.public {
+        Some(public) => Some(api::server(
+            context.clone(),
+            Exposure::Public,
+            &public.host,
+            public.port,
+            tls.clone(),
+            &config.cors,
+        )?),
+        None => None,
+    };
+    let server = api::server(
+        context,
+        Exposure::Private,
+        &config.host,
+        config.port,
+        tls,
+        &config.cors,
+    )?;
+
+    let server_handles = [Some(&server), public_server.as_ref()]
+        .into_iter()
+        .flatten()
+        .map(|server| server.handle())
+        .collect::<Vec<_>>();
+    let mut server_task = task::spawn(server);
+    let mut public_server_task = public_server.map(task::spawn);
+
+    tokio::select! {
+        res = shutdown_signal() => {
+            res?;
+            info!("Shutdown signal received");
+        }
+        res = &mut server_task => {
+            error!("API server exited unexpectedly: {res:?}");
+            alerter.raise("API server exited", &format!("{res:?}")).await;
+        }
+        res = async {
+            match public_server_task.as_mut() {
+                Some(task) => task.await,
+                None => future::pending().await,
+            }
+        } => {
+            error!("Public API server exited unexpectedly: {res:?}");
+            alerter.raise("Public API server exited", &format!("{res:?}")).await;
+        }
+    }
+
+    // stop background work first so in-flight inserts are flushed, then the server
+    shutdown.cancel();
+
+    for background_task in background_tasks {
+        if let Err(e) = background_task.await {
+            error!("Background task failed during shutdown: {e:?}");
+        }
+    }
+
+    for server_handle in server_handles {
+        server_handle.stop(true).await;
+    }
+
+    // only now that no more requests are served
+    if let Some(usage) = usage {
+        if let Err(e) = usage.flush().await {
+            error!("Failed to flush API usage: {e:?}");
+        }
+    }
+
+    info!("Shutdown complete");
 
     Ok(())
 }
+
+/// RPC client with the configured fallbacks, retries, rate limits, memory budget, watched
+/// programs and fee burn policy.
+async fn solana_client(config: &Config) -> SolanaClient {
+    let endpoints = match &config.record_rpc {
+        Some(path) => {
+            info!("Recording RPC responses to {}", path.display());
+            rpc::connect_recording(&config.rpc_urls, path)
+        }
+        None => rpc::connect(&config.rpc_urls),
+    };
+
+    let solana_client = SolanaClient::with_endpoints(endpoints)
+        .with_memory_budget(MemoryBudget::new(config.memory.budget_mb))
+        .with_pipeline(config.pipeline.clone())
+        .with_watched_programs(&config.programs, config.program_signatures_per_poll)
+        .with_retry_policy(config.rpc_retry.clone())
+        .with_rate_limit(&config.rpc_rate_limit);
+
+    // fee burn accounting needs the cluster's epoch schedule to place transactions in epochs;
+    // the demo has no RPC endpoint to ask
+    let epoch_schedule = if config.demo {
+        EpochSchedule::without_warmup()
+    } else {
+        solana_client.epoch_schedule().await.unwrap_or_else(|e| {
+            warn!("Failed to fetch epoch schedule, assuming no warmup: {e:?}");
+            EpochSchedule::without_warmup()
+        })
+    };
+
+    solana_client.with_fee_burn_policy(FeeBurnPolicy::new(epoch_schedule, &config.fee_burn))
+}
+
+/// Connect to the configured storage backend, applying pending migrations. The Postgres pool is
+/// also returned on its own, for the Postgres-only tables.
+async fn open_storage(config: &Config) -> anyhow::Result<(Option<Arc<PgPool>>, Arc<dyn Storage>)> {
+    let db = match config.storage {
+        StorageBackend::Postgres => {
+            let pool = get_pool(&config.database_url).await?;
+
+            if config.timescale.enabled {
+                enable_timescale(&pool, &config.timescale).await?;
+            }
+
+            Some(Arc::new(pool))
+        }
+        _ => None,
+    };
+
+    let exactly_once = config
+        .sinks
+        .kafka
+        .as_ref()
+        .is_some_and(|kafka| kafka.exactly_once);
+
+    if exactly_once && db.is_none() {
+        anyhow::bail!("sinks.kafka.exactly_once needs Postgres storage, for its outbox");
+    }
+
+    let storage: Arc<dyn Storage> = match (&db, config.storage) {
+        // the outbox is written with the transactions, so it has each stored one exactly once
+        (Some(db), _) if exactly_once => Arc::new(OutboxStorage::new(Arc::clone(db))),
+        (Some(db), _) => db.clone(),
+        (None, StorageBackend::Sqlite) => Arc::new(get_sqlite_pool(&config.database_url).await?),
+        (None, _) => Arc::new(InMemoryStorage::new()),
+    };
+
+    Ok((db, storage))
+}
+
+/// Start the background tasks that only work against Postgres: balance tracking, account data
+/// watching, ad hoc data expiry and table maintenance.
+fn spawn_postgres_tasks(
+    config: &Config,
+    context: &PostgresContext,
+    watchlist: &Arc<Watchlist>,
+    solana_client: &Arc<SolanaClient>,
+    schedules: &Schedules,
+    alerter: &Alerter,
+    shutdown: &CancellationToken,
+    background_tasks: &mut Vec<JoinHandle<()>>,
+) -> anyhow::Result<()> {
+    let db = &context.db;
+
+    // snapshot balances of the watched addresses (not the canary's)
+    if config.balances.enabled {
+        let solana_client = Arc::clone(solana_client);
+        let watched = Arc::clone(watchlist);
+        let balances_job = schedules.register(
+            "balances",
+            Some(Duration::from_secs(config.balances.interval_secs)),
+        );
+        let balances_db = Arc::clone(db);
+        let balances_shutdown = shutdown.clone();
+        let tokens = config.balances.tokens;
+
+        background_tasks.push(task::spawn(supervise(
+            "balance tracker",
+            shutdown.clone(),
+            alerter.clone(),
+            move || {
+                let solana_client = Arc::clone(&solana_client);
+                let watched = Arc::clone(&watched);
+                let db = Arc::clone(&balances_db);
+                let job = Arc::clone(&balances_job);
+                let shutdown = balances_shutdown.clone();
+
+                async move {
+                    solana_client
+                        .track_balances(&watched, &db, tokens, &job, &shutdown)
+                        .await;
+                }
+            },
+        )));
+    }
+
+    // version the data of watched non-wallet accounts
+    let watched_accounts = config.account_watch.pubkeys()?;
+
+    if !watched_accounts.is_empty() {
+        let solana_client = Arc::clone(solana_client);
+        let watched_accounts = Arc::new(watched_accounts);
+        let watch_job = schedules.register(
+            "account-watch",
+            Some(Duration::from_secs(config.account_watch.interval_secs)),
+        );
+        let watch_db = Arc::clone(db);
+        let watch_shutdown = shutdown.clone();
+
+        background_tasks.push(task::spawn(supervise(
+            "account data watcher",
+            shutdown.clone(),
+            alerter.clone(),
+            move || {
+                let solana_client = Arc::clone(&solana_client);
+                let watched_accounts = Arc::clone(&watched_accounts);
+                let db = Arc::clone(&watch_db);
+                let job = Arc::clone(&watch_job);
+                let shutdown = watch_shutdown.clone();
+
+                async move {
+                    solana_client
+                        .watch_account_data(&watched_accounts, &db, &job, &shutdown)
+                        .await;
+                }
+            },
+        )));
+    }
+
+    // track epochs and tag stored transactions with theirs
+    if let Some(tracker) = &context.epochs {
+        let tracker = Arc::clone(tracker);
+        let epochs_job = schedules.register(
+            "epochs",
+            Some(Duration::from_secs(config.epochs.interval_secs)),
+        );
+        let epochs_db = Arc::clone(db);
+        let epochs_shutdown = shutdown.clone();
+
+        background_tasks.push(task::spawn(async move {
+            tracker.run(&epochs_db, &epochs_job, &epochs_shutdown).await;
+        }));
+    }
+
+    // daily activity reports of the watched addresses
+    if config.reports.enabled {
+        let reporter = Arc::new(DailyReporter::new(
+            &config.reports,
+            config.alerts.smtp.clone(),
+            Arc::clone(watchlist),
+        )?);
+        let reports_job = schedules.register(
+            "daily-reports",
+            Some(Duration::from_secs(config.reports.interval_secs)),
+        );
+        let reports_db = Arc::clone(db);
+        let reports_shutdown = shutdown.clone();
+
+        background_tasks.push(task::spawn(supervise(
+            "daily reports",
+            shutdown.clone(),
+            alerter.clone(),
+            move || {
+                let reporter = Arc::clone(&reporter);
+                let db = Arc::clone(&reports_db);
+                let job = Arc::clone(&reports_job);
+                let shutdown = reports_shutdown.clone();
+
+                async move {
+                    reporter.run(&db, &job, &shutdown).await;
+                }
+            },
+        )));
+    }
+
+    // expire ad hoc analysis data, unless it is kept forever
+    let expiring_analyzer = context
+        .analyzer
+        .as_ref()
+        .filter(|_| config.analysis.ttl_secs > 0);
+
+    if let Some(analyzer) = expiring_analyzer {
+        let analyzer = Arc::clone(analyzer);
+        let cleanup_job = schedules.register(
+            "ad-hoc-cleanup",
+            Some(Duration::from_secs(config.analysis.cleanup_interval_secs)),
+        );
+        let cleanup_db = Arc::clone(db);
+        let cleanup_shutdown = shutdown.clone();
+
+        background_tasks.push(task::spawn(supervise(
+            "ad hoc data cleanup",
+            shutdown.clone(),
+            alerter.clone(),
+            move || {
+                let analyzer = Arc::clone(&analyzer);
+                let db = Arc::clone(&cleanup_db);
+                let job = Arc::clone(&cleanup_job);
+                let shutdown = cleanup_shutdown.clone();
+
+                async move {
+                    analyzer.expire_ad_hoc_data(&db, &job, &shutdown).await;
+                }
+            },
+        )));
+    }
+
+    // off-peak table maintenance
+    if config.maintenance.enabled {
+        let maintenance_scheduler = Arc::clone(&context.maintenance);
+        let maintenance_db = Arc::clone(db);
+        let maintenance_job = schedules.register("maintenance", None);
+        let maintenance_shutdown = shutdown.clone();
+
+        background_tasks.push(task::spawn(async move {
+            maintenance_scheduler
+                .run(&maintenance_db, &maintenance_job, &maintenance_shutdown)
+                .await;
+        }));
+    }
+
+    Ok(())
+}
+
+/// Wait for SIGINT or SIGTERM.
+async fn shutdown_signal() -> anyhow::Result<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+
+    tokio::select! {
+        res = tokio::signal::ctrl_c() => res?,
+        _ = sigterm.recv() => {}
+    }
+
+    Ok(())
+}
+
+/// Run the task built by `spawn` until shutdown, restarting it with exponential backoff
+/// whenever it panics or exits on its own.
+async fn supervise<F, Fut>(
+    name: &'static str,
+    shutdown: CancellationToken,
+    alerter: Alerter,
+    mut spawn: F,
+) where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = ()> + Send + 'static,
+{
+    let mut backoff = INITIAL_RESTART_BACKOFF;
+
+    loop {
+        let started_at = Instant::now();
+        let result = task::spawn(spawn()).await;
+
+        if shutdown.is_cancelled() {
+            return;
+        }
+
+        match result {
+            Err(e) if e.is_panic() => {
+                alerter
+                    .raise(&format!("{name} crashed"), &format!("{e:?}"))
+                    .await
+            }
+            _ => warn!("{name} exited unexpectedly"),
+        }
+
+        if started_at.elapsed() >= HEALTHY_RUN {
+            backoff = INITIAL_RESTART_BACKOFF;
+        }
+
+        info!("Restarting {name} in {backoff:?}");
+
+        tokio::select! {
+            _ = shutdown.cancelled() => return,
+            _ = time::sleep(backoff) => {}
+        }
+
+        backoff = (backoff * 2).min(MAX_RESTART_BACKOFF);
+    }
+}
@@ -0,0 +1,220 @@
+// Schedules off-peak maintenance of the hot tables
+
+// Responsibilities:
+// * Run VACUUM (ANALYZE), and optionally a concurrent REINDEX, on the configured tables at the
+//   configured off-peak times, since continuous ingestion otherwise bloats tables and indexes
+//   faster than autovacuum keeps up with and leaves planner statistics stale.
+// * Report table bloat and when each table was last maintained.
+
+// Implementation:
+// * Run times are minutes after midnight UTC; the scheduler sleeps until the next one.
+// * Tables are maintained one after the other, so only one heavy statement runs at a time.
+// * A failure on one table is recorded and logged, and does not stop the others.
+
+use crate::{
+    config::MaintenanceConfig,
+    data_storage::{get_table_health, reindex_table, vacuum_analyze_table, TableHealth},
+    schedules::Job,
+};
+
+use log::{error, info};
+use serde::Serialize;
+use sqlx::PgPool;
+use tokio::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const SECS_PER_DAY: u64 = 86_400;
+
+/// Outcome of the last maintenance run on a table.
+#[derive(Debug, Clone, Serialize)]
+pub struct TableRun {
+    /// Unix seconds.
+    pub finished_at: u64,
+    pub duration_ms: u64,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TableReport {
+    #[serde(flatten)]
+    pub health: TableHealth,
+    pub last_run: Option<TableRun>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MaintenanceReport {
+    /// Unix seconds; `None` if no run is scheduled.
+    pub next_run_at: Option<u64>,
+    pub tables: Vec<TableReport>,
+}
+
+#[derive(Debug)]
+pub struct MaintenanceScheduler {
+    enabled: bool,
+    /// Minutes after midnight UTC.
+    schedule: Vec<u32>,
+    tables: Vec<String>,
+    reindex: bool,
+    last_runs: Mutex<HashMap<String, TableRun>>,
+}
+
+impl MaintenanceScheduler {
+    pub fn new(config: &MaintenanceConfig) -> anyhow::Result<Self> {
+        Ok(MaintenanceScheduler {
+            enabled: config.enabled,
+            schedule: config.schedule()?,
+            tables: config.tables.clone(),
+            reindex: config.reindex,
+            last_runs: Mutex::new(HashMap::new()),
+        })
+    }
+
+    fn next_run_in(&self) -> Option<u64> {
+        if !self.enabled {
+            return None;
+        }
+
+        secs_until_next(unix_now(), &self.schedule)
+    }
+
+    /// Maintain the tables at every scheduled time until shut down.
+    pub async fn run(&self, db: &Arc<PgPool>, job: &Job, shutdown: &CancellationToken) {
+        loop {
+            let Some(wait) = self.next_run_in() else {
+                return;
+            };
+
+            if !job.wait(Duration::from_secs(wait), shutdown).await {
+                return;
+            }
+
+            let _ = job.run(self.maintain_tables(db, shutdown)).await;
+        }
+    }
+
+    /// Maintain every table in turn, failing if any of them failed.
+    async fn maintain_tables(
+        &self,
+        db: &Arc<PgPool>,
+        shutdown: &CancellationToken,
+    ) -> anyhow::Result<()> {
+        let mut failed = 0;
+
+        for table in &self.tables {
+            if shutdown.is_cancelled() {
+                break;
+            }
+
+            if !self.maintain(db, table).await {
+                failed += 1;
+            }
+        }
+
+        if failed > 0 {
+            anyhow::bail!("{failed} of {} tables failed", self.tables.len());
+        }
+
+        Ok(())
+    }
+
+    /// Maintain one table, returning whether it succeeded.
+    async fn maintain(&self, db: &Arc<PgPool>, table: &str) -> bool {
+        let started_at = Instant::now();
+        let mut result = vacuum_analyze_table(db, table).await;
+
+        if result.is_ok() && self.reindex {
+            result = reindex_table(db, table).await;
+        }
+
+        let run = TableRun {
+            finished_at: unix_now(),
+            duration_ms: started_at.elapsed().as_millis() as u64,
+            error: result.err().map(|e| {
+                error!("Maintenance of table {table} failed: {e:?}");
+                e.to_string()
+            }),
+        };
+
+        let succeeded = run.error.is_none();
+
+        if succeeded {
+            info!("Maintained table {table} in {}ms", run.duration_ms);
+        }
+
+        self.last_runs
+            .lock()
+            .expect("maintenance runs lock poisoned")
+            .insert(table.to_string(), run);
+
+        succeeded
+    }
+
+    /// Current bloat of the maintained tables and the outcome of their last run.
+    pub async fn report(&self, db: &Arc<PgPool>) -> anyhow::Result<MaintenanceReport> {
+        let health = get_table_health(db, &self.tables).await?;
+        let last_runs = self
+            .last_runs
+            .lock()
+            .expect("maintenance runs lock poisoned")
+            .clone();
+
+        Ok(MaintenanceReport {
+            next_run_at: self.next_run_in().map(|wait| unix_now() + wait),
+            tables: health
+                .into_iter()
+                .map(|health| TableReport {
+                    last_run: last_runs.get(&health.table).cloned(),
+                    health,
+                })
+                .collect(),
+        })
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+/// Seconds from `now` (unix seconds) until the next scheduled minute of the day.
+fn secs_until_next(now: u64, schedule: &[u32]) -> Option<u64> {
+    let time_of_day = now % SECS_PER_DAY;
+
+    schedule
+        .iter()
+        .map(|minute| {
+            let at = u64::from(*minute) * 60;
+
+            if at > time_of_day {
+                at - time_of_day
+            } else {
+                at + SECS_PER_DAY - time_of_day
+            }
+        })
+        .min()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secs_until_next() {
+        // 1970-01-02 03:00:00 UTC
+        let now = SECS_PER_DAY + 3 * 3600;
+
+        assert_eq!(secs_until_next(now, &[210]), Some(30 * 60));
+        assert_eq!(secs_until_next(now, &[60, 240]), Some(3600));
+        // a run due right now is next due tomorrow
+        assert_eq!(secs_until_next(now, &[180]), Some(SECS_PER_DAY));
+        assert_eq!(secs_until_next(now, &[60]), Some(SECS_PER_DAY - 2 * 3600));
+        assert_eq!(secs_until_next(now, &[]), None);
+    }
+}
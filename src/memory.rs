@@ -0,0 +1,299 @@
+// Accounts for the memory held by the ingestion pipeline
+
+// Responsibilities:
+// * Track how many items each pipeline stage holds and roughly how many bytes they take.
+// * Hold back new fetches while the pipeline holds more than the configured budget.
+// * Report the pipeline's accounting alongside the process's actual memory use.
+
+// Implementation:
+// * Stages charge what they hold through a `Charge`, which gives it back when dropped, so an
+//   early return or error can't leak accounting.
+// * Sizes are estimates: raw RPC responses are measured by their JSON encoding, processed
+//   transactions by their fields. They track real usage closely enough to size containers,
+//   not to the byte.
+// * The budget is checked before a fetch starts, since what a fetch returns isn't known in
+//   advance. A single fetch can still take the pipeline over budget; the next ones then wait.
+// * Heap statistics come from jemalloc when built with the `jemalloc` feature. Resident memory
+//   is read from `/proc`, so it is only reported on Linux.
+
+use crate::data_processing::TransactionData;
+
+use serde::Serialize;
+use tokio::sync::Notify;
+
+use std::{collections::BTreeMap, fs, io, mem::size_of, sync::Mutex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    /// Raw RPC responses waiting to be processed.
+    Fetched,
+    /// Processed transactions waiting to be stored.
+    Processed,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct StageReport {
+    pub items: usize,
+    pub bytes: usize,
+    /// Most bytes held at once since startup.
+    pub peak_bytes: usize,
+}
+
+/// Allocator statistics, in bytes.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct HeapStats {
+    pub allocated: usize,
+    pub active: usize,
+    pub resident: usize,
+    pub mapped: usize,
+    pub retained: usize,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MemoryReport {
+    /// `None` without a budget.
+    pub budget_bytes: Option<usize>,
+    pub in_flight_bytes: usize,
+    pub stages: BTreeMap<Stage, StageReport>,
+    /// Fetches that had to wait for the pipeline to get back under budget since startup.
+    pub throttled: u64,
+    /// Resident set size of the whole process; `None` off Linux.
+    pub resident_bytes: Option<u64>,
+    /// `None` unless built with the `jemalloc` feature.
+    pub heap: Option<HeapStats>,
+}
+
+#[derive(Debug, Default)]
+struct Usage {
+    stages: BTreeMap<Stage, StageReport>,
+    throttled: u64,
+}
+
+impl Usage {
+    fn in_flight(&self) -> usize {
+        self.stages.values().map(|stage| stage.bytes).sum()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MemoryBudget {
+    budget_bytes: Option<usize>,
+    usage: Mutex<Usage>,
+    released: Notify,
+}
+
+/// Memory charged to a stage, given back when dropped.
+#[must_use]
+#[derive(Debug)]
+pub struct Charge<'a> {
+    budget: &'a MemoryBudget,
+    stage: Stage,
+    items: usize,
+    bytes: usize,
+}
+
+impl Drop for Charge<'_> {
+    fn drop(&mut self) {
+        {
+            let mut usage = self
+                .budget
+                .usage
+                .lock()
+                .expect("memory usage lock poisoned");
+            let stage = usage.stages.entry(self.stage).or_default();
+            stage.items -= self.items;
+            stage.bytes -= self.bytes;
+        }
+
+        self.budget.released.notify_waiters();
+    }
+}
+
+impl MemoryBudget {
+    /// A budget of `budget_mb` megabytes, or no budget if 0.
+    pub fn new(budget_mb: u64) -> Self {
+        MemoryBudget {
+            budget_bytes: (budget_mb > 0).then(|| budget_mb as usize * 1024 * 1024),
+            ..Self::default()
+        }
+    }
+
+    /// Wait until the pipeline holds less than the budget.
+    pub async fn wait_for_room(&self) {
+        let mut throttled = false;
+
+        loop {
+            // registered before checking, so a release in between isn't missed
+            let released = self.released.notified();
+            tokio::pin!(released);
+            released.as_mut().enable();
+
+            {
+                let mut usage = self.usage.lock().expect("memory usage lock poisoned");
+
+                if self
+                    .budget_bytes
+                    .map_or(true, |budget| usage.in_flight() < budget)
+                {
+                    return;
+                }
+
+                if !throttled {
+                    throttled = true;
+                    usage.throttled += 1;
+                }
+            }
+
+            released.await;
+        }
+    }
+
+    /// Charge `items` taking `bytes` to `stage` until the returned charge is dropped.
+    pub fn charge(&self, stage: Stage, items: usize, bytes: usize) -> Charge<'_> {
+        let mut usage = self.usage.lock().expect("memory usage lock poisoned");
+        let report = usage.stages.entry(stage).or_default();
+
+        report.items += items;
+        report.bytes += bytes;
+        report.peak_bytes = report.peak_bytes.max(report.bytes);
+
+        Charge {
+            budget: self,
+            stage,
+            items,
+            bytes,
+        }
+    }
+
+    pub fn report(&self) -> MemoryReport {
+        let usage = self.usage.lock().expect("memory usage lock poisoned");
+
+        MemoryReport {
+            budget_bytes: self.budget_bytes,
+            in_flight_bytes: usage.in_flight(),
+            stages: usage.stages.clone(),
+            throttled: usage.throttled,
+            resident_bytes: resident_bytes(),
+            heap: heap_stats(),
+        }
+    }
+}
+
+/// Size of a value's JSON encoding, without allocating it.
+pub fn json_size(value: &impl Serialize) -> usize {
+    struct Counter(usize);
+
+    impl io::Write for Counter {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0 += buf.len();
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut counter = Counter(0);
+    let _ = serde_json::to_writer(&mut counter, value);
+    counter.0
+}
+
+/// Estimated heap and inline size of a processed transaction.
+pub fn transaction_size(txn: &TransactionData) -> usize {
+    size_of::<TransactionData>()
+        + txn.signature.capacity()
+        + txn.sender.capacity()
+        + txn.receiver.capacity()
+        + txn.prev_blockhash.capacity()
+        + txn.error.as_ref().map_or(0, String::capacity)
+        + txn
+            .log_messages
+            .iter()
+            .map(|log| size_of::<String>() + log.capacity())
+            .sum::<usize>()
+}
+
+fn resident_bytes() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kb = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+
+    Some(kb * 1024)
+}
+
+#[cfg(feature = "jemalloc")]
+fn heap_stats() -> Option<HeapStats> {
+    use tikv_jemalloc_ctl::{epoch, stats};
+
+    // jemalloc only refreshes its statistics when the epoch is advanced
+    epoch::advance().ok()?;
+
+    Some(HeapStats {
+        allocated: stats::allocated::read().ok()?,
+        active: stats::active::read().ok()?,
+        resident: stats::resident::read().ok()?,
+        mapped: stats::mapped::read().ok()?,
+        retained: stats::retained::read().ok()?,
+    })
+}
+
+#[cfg(not(feature = "jemalloc"))]
+fn heap_stats() -> Option<HeapStats> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::time::{self, Duration};
+
+    #[test]
+    fn test_charges_are_given_back() {
+        let budget = MemoryBudget::new(0);
+
+        let fetched = budget.charge(Stage::Fetched, 3, 3000);
+        {
+            let _processed = budget.charge(Stage::Processed, 2, 500);
+            assert_eq!(budget.report().in_flight_bytes, 3500);
+        }
+        drop(fetched);
+
+        let report = budget.report();
+        assert_eq!(report.in_flight_bytes, 0);
+        assert_eq!(report.budget_bytes, None);
+
+        let fetched = report.stages[&Stage::Fetched];
+        assert_eq!(
+            (fetched.items, fetched.bytes, fetched.peak_bytes),
+            (0, 0, 3000)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetches_wait_for_room() {
+        let budget = MemoryBudget::new(1);
+
+        // under budget, no waiting
+        budget.wait_for_room().await;
+
+        let charge = budget.charge(Stage::Processed, 1, 2 * 1024 * 1024);
+        assert!(
+            time::timeout(Duration::from_millis(50), budget.wait_for_room())
+                .await
+                .is_err()
+        );
+
+        // and a waiting fetch goes ahead once memory is given back
+        let (_, waited) = tokio::join!(
+            async move {
+                time::sleep(Duration::from_millis(20)).await;
+                drop(charge);
+            },
+            time::timeout(Duration::from_secs(1), budget.wait_for_room()),
+        );
+        assert!(waited.is_ok());
+        assert_eq!(budget.report().throttled, 2);
+    }
+}
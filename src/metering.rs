@@ -0,0 +1,270 @@
+// Meters what each API key uses, for billing
+
+// Responsibilities:
+// * Count each API key's requests, the rows returned to it and the webhook deliveries made for it,
+//   per calendar month.
+// * Export a month's usage, so operators can bill customers from the aggregator's own records.
+
+// Implementation:
+// * Counts are kept in memory and added to `api_usage` every `usage_flush_interval_secs` and once
+//   more at shutdown, so metering costs no database write per request. Counts that fail to flush
+//   are kept for the next flush.
+// * Requests are counted by the authenticator as it allows them, over REST and gRPC alike. Rows
+//   are counted by the REST handlers that return transactions, balances or account versions, from
+//   the `Tenant` the authentication middleware attaches to the request.
+// * Only keyed requests are metered: nothing is counted with auth disabled or on public routes.
+// * Months are UTC.
+
+use crate::{
+    data_storage::{add_api_usage, get_api_usage, ApiUsage},
+    schedules::Job,
+};
+
+use sqlx::PgPool;
+use tokio_util::sync::CancellationToken;
+
+use std::{
+    collections::HashMap,
+    mem,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct Usage {
+    requests: i64,
+    rows_returned: i64,
+    webhook_deliveries: i64,
+}
+
+impl Usage {
+    fn merge(&mut self, other: Usage) {
+        self.requests += other.requests;
+        self.rows_returned += other.rows_returned;
+        self.webhook_deliveries += other.webhook_deliveries;
+    }
+}
+
+pub struct UsageMeter {
+    db: Arc<PgPool>,
+    /// Counts not flushed yet, keyed by key id and month.
+    pending: Mutex<HashMap<(i32, String), Usage>>,
+}
+
+impl UsageMeter {
+    pub fn new(db: Arc<PgPool>) -> Self {
+        UsageMeter {
+            db,
+            pending: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn record_request(&self, key_id: i32) {
+        self.add(key_id, |usage| usage.requests += 1);
+    }
+
+    pub fn record_rows(&self, key_id: i32, rows: usize) {
+        self.add(key_id, |usage| usage.rows_returned += rows as i64);
+    }
+
+    pub fn record_webhook_delivery(&self, key_id: i32) {
+        self.add(key_id, |usage| usage.webhook_deliveries += 1);
+    }
+
+    fn add(&self, key_id: i32, count: impl FnOnce(&mut Usage)) {
+        let mut pending = self.pending.lock().expect("usage lock poisoned");
+
+        count(pending.entry((key_id, current_month())).or_default());
+    }
+
+    /// Flush the counts every time `job` is due, until `shutdown` is cancelled. The server still
+    /// answers requests after that, so the final flush is left to the caller.
+    pub async fn run(&self, job: &Job, shutdown: &CancellationToken) {
+        while job.tick(shutdown).await {
+            let _ = job.run(self.flush()).await;
+        }
+    }
+
+    /// Add the pending counts to the usage table.
+    pub async fn flush(&self) -> anyhow::Result<()> {
+        let pending = mem::take(&mut *self.pending.lock().expect("usage lock poisoned"))
+            .into_iter()
+            .collect::<Vec<_>>();
+
+        for (flushed, ((key_id, month), usage)) in pending.iter().enumerate() {
+            if let Err(e) = add_api_usage(
+                &self.db,
+                *key_id,
+                month,
+                usage.requests,
+                usage.rows_returned,
+                usage.webhook_deliveries,
+            )
+            .await
+            {
+                // keep what wasn't flushed for the next attempt
+                let mut unflushed = self.pending.lock().expect("usage lock poisoned");
+
+                for (key, usage) in &pending[flushed..] {
+                    unflushed.entry(key.clone()).or_default().merge(*usage);
+                }
+
+                return Err(e);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Every key's usage in `month`, a `YYYY-MM` string, including the counts not flushed yet.
+    pub async fn report(&self, month: &str) -> anyhow::Result<Vec<ApiUsage>> {
+        self.flush().await?;
+
+        get_api_usage(&self.db, month).await
+    }
+}
+
+/// The API key a request was authenticated with, for handlers to meter the rows they return.
+#[derive(Clone)]
+pub struct Tenant {
+    key_id: i32,
+    meter: Arc<UsageMeter>,
+}
+
+impl Tenant {
+    pub fn new(key_id: i32, meter: Arc<UsageMeter>) -> Self {
+        Tenant { key_id, meter }
+    }
+
+    pub fn key_id(&self) -> i32 {
+        self.key_id
+    }
+
+    pub fn record_rows(&self, rows: usize) {
+        self.meter.record_rows(self.key_id, rows);
+    }
+}
+
+/// A month's usage as CSV, with a header row even when no key was used.
+pub fn usage_csv(usage: &[ApiUsage]) -> anyhow::Result<Vec<u8>> {
+    let mut writer = csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(Vec::new());
+
+    writer.write_record([
+        "api_key_id",
+        "name",
+        "prefix",
+        "month",
+        "requests",
+        "rows_returned",
+        "webhook_deliveries",
+    ])?;
+
+    for row in usage {
+        writer.serialize(row)?;
+    }
+
+    Ok(writer.into_inner()?)
+}
+
+/// The current UTC month as `YYYY-MM`.
+pub fn current_month() -> String {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64);
+
+    month_of(now)
+}
+
+/// The UTC month of a unix timestamp as `YYYY-MM`.
+fn month_of(unix_secs: i64) -> String {
+    let (year, month, _) = civil_date(unix_secs);
+
+    format!("{year:04}-{month:02}")
+}
+
+/// The UTC year, month and day of a unix timestamp.
+pub(crate) fn civil_date(unix_secs: i64) -> (i64, i64, i64) {
+    // days to civil date, after Howard Hinnant's `civil_from_days`
+    let days = unix_secs.div_euclid(86_400) + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+/// Whether `month` is a `YYYY-MM` string.
+pub fn is_month(month: &str) -> bool {
+    match month.split_once('-') {
+        Some((year, month)) => {
+            year.len() == 4
+                && month.len() == 2
+                && year
+                    .bytes()
+                    .chain(month.bytes())
+                    .all(|b| b.is_ascii_digit())
+                && matches!(month.parse::<u8>(), Ok(1..=12))
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_month_of() {
+        assert_eq!(month_of(0), "1970-01");
+        // 2024-02-29T23:59:59Z, then the next second
+        assert_eq!(month_of(1_709_251_199), "2024-02");
+        assert_eq!(month_of(1_709_251_200), "2024-03");
+        // 2025-12-31T12:00:00Z
+        assert_eq!(month_of(1_767_182_400), "2025-12");
+        assert_eq!(month_of(1_767_225_600), "2026-01");
+    }
+
+    #[test]
+    fn test_usage_csv() {
+        let usage = ApiUsage {
+            api_key_id: 3,
+            name: "acme".to_string(),
+            prefix: "0a1b2c3d".to_string(),
+            month: "2026-10".to_string(),
+            requests: 1200,
+            rows_returned: 45_000,
+            webhook_deliveries: 7,
+        };
+
+        let csv = String::from_utf8(usage_csv(&[usage]).unwrap()).unwrap();
+        assert_eq!(
+            csv,
+            "api_key_id,name,prefix,month,requests,rows_returned,webhook_deliveries\n\
+             3,acme,0a1b2c3d,2026-10,1200,45000,7\n"
+        );
+
+        let csv = String::from_utf8(usage_csv(&[]).unwrap()).unwrap();
+        assert_eq!(csv.lines().count(), 1);
+    }
+
+    #[test]
+    fn test_is_month() {
+        assert!(is_month("2026-10"));
+        assert!(!is_month("2026-13"));
+        assert!(!is_month("2026-1"));
+        assert!(!is_month("26-10"));
+        assert!(!is_month("2026-+1"));
+    }
+}
@@ -0,0 +1,184 @@
+// Tracks ingestion throughput
+
+// Responsibilities:
+// * Record how many transactions each batch insert stored and how long it took.
+// * Report rows per second, overall and for the latest batch.
+// * Count the transactions left out before storage, by reason.
+// * Count the missing transactions found and stored by gap repair.
+
+// Implementation:
+// * Rates are computed over the time spent inserting, not wall-clock time, so they reflect
+//   storage throughput rather than how often the chain is polled.
+
+use crate::data_processing::Exclusion;
+
+use serde::Serialize;
+use tokio::time::Duration;
+
+use std::sync::Mutex;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct BatchReport {
+    /// Transactions newly stored by the batch.
+    pub rows: usize,
+    /// Transactions in the batch, including those already stored.
+    pub submitted: usize,
+    pub duration_ms: f64,
+    pub rows_per_sec: f64,
+}
+
+/// Transactions left out before storage, by reason.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ExcludedCounts {
+    /// Unparsable or malformed.
+    pub invalid: u64,
+    pub failed: u64,
+    pub zero_amount: u64,
+    pub votes: u64,
+}
+
+impl ExcludedCounts {
+    pub fn add(&mut self, exclusion: Exclusion) {
+        match exclusion {
+            Exclusion::Invalid => self.invalid += 1,
+            Exclusion::Failed => self.failed += 1,
+            Exclusion::ZeroAmount => self.zero_amount += 1,
+            Exclusion::Vote => self.votes += 1,
+        }
+    }
+
+    fn merge(&mut self, other: ExcludedCounts) {
+        self.invalid += other.invalid;
+        self.failed += other.failed;
+        self.zero_amount += other.zero_amount;
+        self.votes += other.votes;
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct IngestReport {
+    pub batches: u64,
+    pub rows: u64,
+    pub rows_per_sec: f64,
+    pub last_batch: Option<BatchReport>,
+    /// Since startup. Transactions are fetched again on every poll, so one left out transaction
+    /// is counted each time it's fetched.
+    pub excluded: ExcludedCounts,
+    /// Missing transactions stored by gap repair since startup. They're counted in `rows` too.
+    pub repaired: u64,
+}
+
+#[derive(Debug, Default)]
+struct IngestTotals {
+    batches: u64,
+    rows: u64,
+    insert_time: Duration,
+    last_batch: Option<BatchReport>,
+    excluded: ExcludedCounts,
+    repaired: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct IngestMetrics {
+    totals: Mutex<IngestTotals>,
+}
+
+fn rate(rows: u64, elapsed: Duration) -> f64 {
+    if elapsed.is_zero() {
+        return 0.0;
+    }
+
+    rows as f64 / elapsed.as_secs_f64()
+}
+
+impl IngestMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a batch insert that stored `rows` of `submitted` transactions.
+    pub fn record_batch(&self, rows: usize, submitted: usize, elapsed: Duration) {
+        let mut totals = self.totals.lock().expect("ingest metrics lock poisoned");
+
+        totals.batches += 1;
+        totals.rows += rows as u64;
+        totals.insert_time += elapsed;
+        totals.last_batch = Some(BatchReport {
+            rows,
+            submitted,
+            duration_ms: elapsed.as_secs_f64() * 1000.0,
+            rows_per_sec: rate(rows as u64, elapsed),
+        });
+    }
+
+    /// Record the transactions a batch left out.
+    pub fn record_exclusions(&self, excluded: ExcludedCounts) {
+        let mut totals = self.totals.lock().expect("ingest metrics lock poisoned");
+
+        totals.excluded.merge(excluded);
+    }
+
+    /// Record missing transactions that gap repair stored.
+    pub fn record_repairs(&self, repaired: usize) {
+        let mut totals = self.totals.lock().expect("ingest metrics lock poisoned");
+
+        totals.repaired += repaired as u64;
+    }
+
+    pub fn report(&self) -> IngestReport {
+        let totals = self.totals.lock().expect("ingest metrics lock poisoned");
+
+        IngestReport {
+            batches: totals.batches,
+            rows: totals.rows,
+            rows_per_sec: rate(totals.rows, totals.insert_time),
+            last_batch: totals.last_batch,
+            excluded: totals.excluded,
+            repaired: totals.repaired,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rows_per_sec() {
+        let metrics = IngestMetrics::new();
+
+        assert_eq!(metrics.report().rows_per_sec, 0.0);
+        assert!(metrics.report().last_batch.is_none());
+
+        metrics.record_batch(100, 100, Duration::from_millis(500));
+        metrics.record_batch(50, 60, Duration::from_millis(500));
+
+        let report = metrics.report();
+        assert_eq!(report.batches, 2);
+        assert_eq!(report.rows, 150);
+        assert_eq!(report.rows_per_sec, 150.0);
+
+        let last_batch = report.last_batch.unwrap();
+        assert_eq!((last_batch.rows, last_batch.submitted), (50, 60));
+        assert_eq!(last_batch.rows_per_sec, 100.0);
+    }
+
+    #[test]
+    fn test_exclusions_accumulate() {
+        let metrics = IngestMetrics::new();
+
+        let mut batch = ExcludedCounts::default();
+        batch.add(Exclusion::Failed);
+        batch.add(Exclusion::Vote);
+        batch.add(Exclusion::Vote);
+
+        metrics.record_exclusions(batch);
+        metrics.record_exclusions(batch);
+
+        let excluded = metrics.report().excluded;
+        assert_eq!(
+            (excluded.failed, excluded.votes, excluded.invalid),
+            (2, 4, 0)
+        );
+    }
+}
@@ -0,0 +1,206 @@
+// Runtime control of the blockchain monitor
+
+// Responsibilities:
+// * Let operators pause and resume ingestion without stopping the process, e.g. while the
+//   database is under maintenance.
+// * Report the monitor's state: whether it's paused, when it last polled, the latest slot it saw,
+//   how many polls and writes failed, and how many batches wait to be written.
+
+// Implementation:
+// * One `MonitorControl` is shared by the `SolanaClient`, whose pipelines check it before every
+//   poll, and the API. A pause takes effect once the poll in progress is done, so the batches it
+//   fetched are still written; nothing is fetched while paused.
+// * Only ingestion is paused. Gap repair, finality checks and the other background jobs keep
+//   running, and can be run by hand as ever.
+// * Pausing isn't persisted, so a restarted process monitors again.
+
+use serde::Serialize;
+use tokio::sync::Notify;
+use tokio_util::sync::CancellationToken;
+
+use std::{
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct MonitorStatus {
+    pub paused: bool,
+    /// Unix seconds; `None` unless paused.
+    pub paused_at: Option<u64>,
+    /// Unix seconds the latest poll started at.
+    pub last_tick: Option<u64>,
+    /// Highest slot of the transactions processed since startup.
+    pub last_slot: Option<u64>,
+    /// Polls and failed polls since startup.
+    pub polls: u64,
+    pub failed_polls: u64,
+    /// Batches that failed to write since startup.
+    pub failed_writes: u64,
+    /// Processed batches waiting to be written.
+    pub queue_depth: usize,
+}
+
+#[derive(Debug, Default)]
+pub struct MonitorControl {
+    status: Mutex<MonitorStatus>,
+    resumed: Notify,
+}
+
+impl MonitorControl {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stop polling once the poll in progress is done. Returns `false` if already paused.
+    pub fn pause(&self) -> bool {
+        let mut status = self.status.lock().expect("monitor status lock poisoned");
+
+        if status.paused {
+            return false;
+        }
+
+        status.paused = true;
+        status.paused_at = Some(unix_now());
+        true
+    }
+
+    /// Poll again. Returns `false` if not paused.
+    pub fn resume(&self) -> bool {
+        let mut status = self.status.lock().expect("monitor status lock poisoned");
+
+        if !status.paused {
+            return false;
+        }
+
+        status.paused = false;
+        status.paused_at = None;
+        self.resumed.notify_waiters();
+        true
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.status
+            .lock()
+            .expect("monitor status lock poisoned")
+            .paused
+    }
+
+    /// Wait until the monitor isn't paused. Returns `false` once shut down.
+    pub async fn until_resumed(&self, shutdown: &CancellationToken) -> bool {
+        loop {
+            // registered before checking, so a resume in between isn't missed
+            let resumed = self.resumed.notified();
+
+            if !self.is_paused() {
+                return true;
+            }
+
+            tokio::select! {
+                _ = shutdown.cancelled() => return false,
+                _ = resumed => {}
+            }
+        }
+    }
+
+    /// Record that a poll started.
+    pub fn record_tick(&self) {
+        self.status
+            .lock()
+            .expect("monitor status lock poisoned")
+            .last_tick = Some(unix_now());
+    }
+
+    /// Record how a poll went.
+    pub fn record_poll(&self, success: bool) {
+        let mut status = self.status.lock().expect("monitor status lock poisoned");
+
+        status.polls += 1;
+        status.failed_polls += u64::from(!success);
+    }
+
+    /// Record the highest slot of a processed batch, if it has transactions.
+    pub fn record_slot(&self, slot: Option<u64>) {
+        let mut status = self.status.lock().expect("monitor status lock poisoned");
+
+        status.last_slot = status.last_slot.max(slot);
+    }
+
+    /// Record that a processed batch is waiting to be written.
+    pub fn enqueue(&self) {
+        self.status
+            .lock()
+            .expect("monitor status lock poisoned")
+            .queue_depth += 1;
+    }
+
+    /// Record that a batch was written, or failed to be.
+    pub fn dequeue(&self, success: bool) {
+        let mut status = self.status.lock().expect("monitor status lock poisoned");
+
+        status.queue_depth = status.queue_depth.saturating_sub(1);
+        status.failed_writes += u64::from(!success);
+    }
+
+    pub fn status(&self) -> MonitorStatus {
+        self.status
+            .lock()
+            .expect("monitor status lock poisoned")
+            .clone()
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use tokio::time::{self, Duration};
+
+    #[tokio::test]
+    async fn test_pause_holds_polls_until_resumed() {
+        let control = MonitorControl::new();
+        let shutdown = CancellationToken::new();
+
+        assert!(control.until_resumed(&shutdown).await);
+
+        assert!(control.pause());
+        assert!(!control.pause());
+        assert!(control.status().paused_at.is_some());
+
+        let waiting = control.until_resumed(&shutdown);
+        tokio::pin!(waiting);
+        assert!(time::timeout(Duration::from_millis(50), &mut waiting)
+            .await
+            .is_err());
+
+        assert!(control.resume());
+        assert!(!control.resume());
+        assert!(time::timeout(Duration::from_secs(1), waiting)
+            .await
+            .unwrap());
+
+        control.record_slot(Some(42));
+        control.record_slot(None);
+        control.record_poll(false);
+        control.enqueue();
+        control.enqueue();
+        control.dequeue(false);
+
+        let status = control.status();
+        assert!(!status.paused);
+        assert_eq!(status.last_slot, Some(42));
+        assert_eq!((status.polls, status.failed_polls), (1, 1));
+        assert_eq!((status.queue_depth, status.failed_writes), (1, 1));
+
+        // a paused monitor still stops on shutdown
+        control.pause();
+        shutdown.cancel();
+        assert!(!control.until_resumed(&shutdown).await);
+    }
+}
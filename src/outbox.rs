@@ -0,0 +1,357 @@
+// Publishes stored transactions to Kafka exactly once, through a transactional outbox
+
+// Responsibilities:
+// * With `exactly_once` set on the Kafka sink, guarantee that the topic has every stored
+//   transaction once, with no duplicates or gaps, across crashes of the aggregator or the brokers.
+
+// Implementation:
+// * `OutboxStorage` wraps the Postgres storage, so every batch insert also writes its newly stored
+//   transactions to `sink_outbox` in the same database transaction: a transaction is in the
+//   outbox if and only if it's stored. Outbox writes are serialized, so ids become visible in
+//   order and the relay never skips one that commits late.
+// * `OutboxRelay` publishes the outbox in id order, up to `OUTBOX_BATCH` entries per Kafka
+//   transaction, each message keyed by signature and carrying its outbox id in the
+//   `outbox-id` header. Entries are deleted once the Kafka transaction commits.
+// * The producer is idempotent and transactional, with a `transactional.id` derived from the
+//   topic. Starting it aborts whatever a previous run left open, so a crash mid-batch leaves
+//   nothing visible to `read_committed` consumers and the batch is published again.
+// * The high watermark, the last outbox id the topic has, is read back from the topic at startup:
+//   the last committed message of each partition, read with `read_committed`. A crash between the
+//   Kafka commit and the outbox delete thus doesn't publish those entries again.
+// * librdkafka's transactional calls block, so they run on the blocking pool.
+// * Needs a build with the `kafka` feature.
+
+use crate::{
+    data_processing::TransactionData,
+    data_storage::{
+        insert_transactions_batch_with_outbox, Storage, StoredTransaction, TransactionQuery,
+    },
+};
+
+use async_trait::async_trait;
+use sqlx::PgPool;
+
+use std::{collections::HashSet, sync::Arc};
+
+/// Outbox entries published per Kafka transaction.
+#[cfg_attr(not(feature = "kafka"), allow(dead_code))]
+const OUTBOX_BATCH: i64 = 500;
+
+/// Header carrying a message's outbox id.
+#[cfg_attr(not(feature = "kafka"), allow(dead_code))]
+const OUTBOX_ID_HEADER: &str = "outbox-id";
+
+/// Postgres storage that adds the transactions it stores to the outbox.
+pub struct OutboxStorage {
+    db: Arc<PgPool>,
+}
+
+impl OutboxStorage {
+    pub fn new(db: Arc<PgPool>) -> Self {
+        OutboxStorage { db }
+    }
+}
+
+#[async_trait]
+impl Storage for OutboxStorage {
+    async fn insert_batch(&self, txns: &[TransactionData]) -> anyhow::Result<Vec<String>> {
+        insert_transactions_batch_with_outbox(&self.db, txns).await
+    }
+
+    async fn get_by_signature(&self, signature: &str) -> anyhow::Result<Option<StoredTransaction>> {
+        self.db.get_by_signature(signature).await
+    }
+
+    async fn stored_signatures(&self, signatures: &[String]) -> anyhow::Result<HashSet<String>> {
+        self.db.stored_signatures(signatures).await
+    }
+
+    async fn query(&self, query: &TransactionQuery) -> anyhow::Result<Vec<StoredTransaction>> {
+        self.db.query(query).await
+    }
+
+    async fn version(&self) -> anyhow::Result<i64> {
+        self.db.version().await
+    }
+}
+
+/// Where to start reading a partition from `low` to `high` to see its last `lookback` offsets.
+#[cfg_attr(not(feature = "kafka"), allow(dead_code))]
+fn window_start(low: i64, high: i64, lookback: i64) -> i64 {
+    high.saturating_sub(lookback).max(low)
+}
+
+#[cfg(feature = "kafka")]
+pub use relay::OutboxRelay;
+
+#[cfg(feature = "kafka")]
+mod relay {
+    use super::{window_start, OUTBOX_BATCH, OUTBOX_ID_HEADER};
+
+    use crate::{
+        config::KafkaSinkConfig,
+        data_storage::{delete_outbox_entries, get_outbox_entries, OutboxEntry},
+        schedules::Job,
+    };
+
+    use log::{error, info};
+    use rdkafka::{
+        consumer::{BaseConsumer, Consumer},
+        error::KafkaError,
+        message::{BorrowedMessage, Header, Headers, Message, OwnedHeaders},
+        producer::{FutureProducer, FutureRecord, Producer},
+        util::Timeout,
+        ClientConfig, Offset, TopicPartitionList,
+    };
+    use sqlx::PgPool;
+    use tokio::task;
+    use tokio_util::sync::CancellationToken;
+
+    use std::{sync::Arc, time::Duration};
+
+    /// How long librdkafka's blocking calls may take.
+    const KAFKA_TIMEOUT: Duration = Duration::from_secs(30);
+
+    pub struct OutboxRelay {
+        producer: FutureProducer,
+        brokers: String,
+        topic: String,
+    }
+
+    impl OutboxRelay {
+        /// Start the transactional producer, fencing off any previous one.
+        pub async fn connect(config: &KafkaSinkConfig) -> anyhow::Result<Self> {
+            let producer: FutureProducer = ClientConfig::new()
+                .set("bootstrap.servers", &config.brokers)
+                .set("message.timeout.ms", "10000")
+                .set("enable.idempotence", "true")
+                .set(
+                    "transactional.id",
+                    format!("solana-data-aggregator-{}", config.topic),
+                )
+                .create()?;
+
+            let starting = producer.clone();
+            task::spawn_blocking(move || starting.init_transactions(KAFKA_TIMEOUT)).await??;
+
+            Ok(OutboxRelay {
+                producer,
+                brokers: config.brokers.clone(),
+                topic: config.topic.clone(),
+            })
+        }
+
+        /// Publish the outbox each time `job` is due, until `shutdown` is cancelled.
+        pub async fn run(&self, db: &Arc<PgPool>, job: &Job, shutdown: &CancellationToken) {
+            let mut published = None;
+
+            while job.tick(shutdown).await {
+                let result = job.run(self.relay(db, &mut published)).await;
+
+                if let Err(e) = result {
+                    error!("Failed to publish the outbox to Kafka: {e:?}");
+
+                    // a failed commit may still have gone through, so look it up again
+                    published = None;
+                }
+            }
+        }
+
+        /// Publish what's in the outbox after the last published entry, looked up in the topic
+        /// the first time.
+        async fn relay(&self, db: &Arc<PgPool>, published: &mut Option<i64>) -> anyhow::Result<()> {
+            let mut after = match *published {
+                Some(after) => after,
+                None => {
+                    let (brokers, topic) = (self.brokers.clone(), self.topic.clone());
+                    let last = task::spawn_blocking(move || last_published_id(&brokers, &topic))
+                        .await??
+                        .unwrap_or(0);
+
+                    let deleted = delete_outbox_entries(db, last).await?;
+                    info!("Kafka has the outbox up to {last}, dropped {deleted} published entries");
+
+                    *published = Some(last);
+                    last
+                }
+            };
+
+            loop {
+                let entries = get_outbox_entries(db, after, OUTBOX_BATCH).await?;
+
+                let Some(last) = entries.last().map(|entry| entry.id) else {
+                    return Ok(());
+                };
+
+                self.in_transaction(|producer| producer.begin_transaction())
+                    .await?;
+
+                if let Err(e) = self.send(&entries).await {
+                    self.in_transaction(|producer| producer.abort_transaction(KAFKA_TIMEOUT))
+                        .await?;
+
+                    return Err(e);
+                }
+
+                if let Err(e) = self
+                    .in_transaction(|producer| producer.commit_transaction(KAFKA_TIMEOUT))
+                    .await
+                {
+                    let _ = self
+                        .in_transaction(|producer| producer.abort_transaction(KAFKA_TIMEOUT))
+                        .await;
+
+                    return Err(e);
+                }
+
+                after = last;
+                *published = Some(last);
+
+                delete_outbox_entries(db, last).await?;
+            }
+        }
+
+        async fn send(&self, entries: &[OutboxEntry]) -> anyhow::Result<()> {
+            for entry in entries {
+                let id = entry.id.to_string();
+                let record = FutureRecord::to(&self.topic)
+                    .key(&entry.signature)
+                    .payload(&entry.payload)
+                    .headers(OwnedHeaders::new().insert(Header {
+                        key: OUTBOX_ID_HEADER,
+                        value: Some(&id),
+                    }));
+
+                self.producer
+                    .send(record, Timeout::Never)
+                    .await
+                    .map_err(|(e, _)| e)?;
+            }
+
+            Ok(())
+        }
+
+        /// Run one of the producer's blocking transactional calls on the blocking pool.
+        async fn in_transaction(
+            &self,
+            call: impl FnOnce(&FutureProducer) -> Result<(), KafkaError> + Send + 'static,
+        ) -> anyhow::Result<()> {
+            let producer = self.producer.clone();
+            task::spawn_blocking(move || call(&producer)).await??;
+
+            Ok(())
+        }
+    }
+
+    /// The last outbox id the topic has, if any: the highest among the committed messages at the
+    /// end of its partitions.
+    fn last_published_id(brokers: &str, topic: &str) -> anyhow::Result<Option<i64>> {
+        let consumer: BaseConsumer = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("group.id", format!("solana-data-aggregator-{topic}-outbox"))
+            .set("enable.auto.commit", "false")
+            .set("enable.partition.eof", "true")
+            .set("isolation.level", "read_committed")
+            .create()?;
+
+        let metadata = consumer.fetch_metadata(Some(topic), KAFKA_TIMEOUT)?;
+        let partitions = metadata
+            .topics()
+            .iter()
+            .flat_map(|metadata| metadata.partitions())
+            .map(|partition| partition.id())
+            .collect::<Vec<_>>();
+
+        let mut last = None;
+
+        for partition in partitions {
+            let (low, high) = consumer.fetch_watermarks(topic, partition, KAFKA_TIMEOUT)?;
+
+            // the last committed transaction ends within a batch and its marker of the end,
+            // unless aborted ones came after it, so look further back until one is found
+            let mut lookback = OUTBOX_BATCH + 1;
+
+            while high > low {
+                let start = window_start(low, high, lookback);
+                let found = read_last_id(&consumer, topic, partition, start)?;
+
+                if found.is_some() || start == low {
+                    last = last.max(found);
+                    break;
+                }
+
+                lookback = lookback.saturating_mul(2);
+            }
+        }
+
+        Ok(last)
+    }
+
+    /// The highest outbox id in a partition from `start` to its end.
+    fn read_last_id(
+        consumer: &BaseConsumer,
+        topic: &str,
+        partition: i32,
+        start: i64,
+    ) -> anyhow::Result<Option<i64>> {
+        let mut assignment = TopicPartitionList::new();
+        assignment.add_partition_offset(topic, partition, Offset::Offset(start))?;
+        consumer.assign(&assignment)?;
+
+        let mut last = None;
+
+        loop {
+            match consumer.poll(KAFKA_TIMEOUT) {
+                Some(Ok(message)) => last = last.max(outbox_id(&message)),
+                Some(Err(KafkaError::PartitionEOF(_))) => return Ok(last),
+                Some(Err(e)) => return Err(e.into()),
+                None => anyhow::bail!("Timed out reading partition {partition} of {topic}"),
+            }
+        }
+    }
+
+    fn outbox_id(message: &BorrowedMessage) -> Option<i64> {
+        message
+            .headers()?
+            .iter()
+            .find(|header| header.key == OUTBOX_ID_HEADER)?
+            .value
+            .and_then(|value| std::str::from_utf8(value).ok())?
+            .parse()
+            .ok()
+    }
+}
+
+#[cfg(not(feature = "kafka"))]
+pub struct OutboxRelay;
+
+#[cfg(not(feature = "kafka"))]
+impl OutboxRelay {
+    pub async fn connect(config: &crate::config::KafkaSinkConfig) -> anyhow::Result<Self> {
+        anyhow::bail!(
+            "Exactly-once publishing to {} is configured, but this build lacks the `kafka` feature",
+            config.brokers
+        )
+    }
+
+    pub async fn run(
+        &self,
+        _db: &Arc<PgPool>,
+        _job: &crate::schedules::Job,
+        _shutdown: &tokio_util::sync::CancellationToken,
+    ) {
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_window_start() {
+        assert_eq!(window_start(0, 1000, OUTBOX_BATCH + 1), 499);
+        assert_eq!(window_start(800, 1000, OUTBOX_BATCH + 1), 800);
+        assert_eq!(window_start(0, i64::MAX, i64::MAX), 0);
+        assert_eq!(window_start(5, 10, i64::MAX), 5);
+    }
+}
@@ -0,0 +1,461 @@
+// Connects the fetch, process and store stages of ingestion
+
+// Responsibilities:
+// * Run ingestion as a `Pipeline` of pluggable stages: a `Source` fetching transactions, a chain
+//   of `Processor`s filtering or transforming the processed batches, and a `Destination` writing
+//   them, so library users can insert their own filters and transforms or swap either end.
+// * Provide the stages the monitor uses: polling the watchlist, and storing batches or, without
+//   storage, publishing them to the sinks.
+
+// Implementation:
+// * Stages are connected by bounded channels and run concurrently within a poll, so a slow
+//   destination holds the source back once `capacity` batches wait in each channel, rather than
+//   letting fetched transactions pile up.
+// * Every fetched batch goes through `SolanaClient::process` first, which parses and validates it
+//   under the pipeline settings, then through the processors in the order they were added.
+// * Batches are charged to the memory budget once the process stage takes them, and until they're
+//   written; the channels' capacities bound what waits in between. The watchlist source waits for
+//   room in the budget before each fetch, like polls always have.
+// * The stages run on the polling task itself, so blocking RPC calls hold up the other stages
+//   while they last, as they do elsewhere in the client.
+// * Pipelines storing their batches send those alert rules may fire on down the alert priority
+//   lane as soon as they're processed, ahead of the write.
+// * A batch that fails to write doesn't stop the others. A poll fails if its source failed or any
+//   batch failed to write.
+// * `run` waits before each poll while the client's `MonitorControl` is paused, and records every
+//   poll, the slots seen and the batches waiting to be written there.
+
+use crate::{
+    data_retrieval::{ProcessedBatch, SolanaClient},
+    data_storage::Storage,
+    schedules::Job,
+    watchlist::Watchlist,
+};
+
+use async_trait::async_trait;
+use log::{error, info};
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use solana_transaction_status::EncodedConfirmedTransactionWithStatusMeta;
+use sqlx::PgPool;
+use tokio::sync::mpsc;
+use tokio_util::sync::CancellationToken;
+
+use std::sync::Arc;
+
+/// Batches each channel between two stages holds before the earlier stage waits.
+pub const DEFAULT_CAPACITY: usize = 4;
+
+/// Transactions fetched for one address, on their way to be processed.
+pub struct Fetched {
+    pub address: Pubkey,
+    /// Every polled signature, including those of transactions that were already stored.
+    pub signatures: Vec<Signature>,
+    pub transactions: Vec<EncodedConfirmedTransactionWithStatusMeta>,
+}
+
+/// A processed batch of one address, on its way to the destination.
+pub struct Processed {
+    pub address: Pubkey,
+    pub signatures: Vec<Signature>,
+    pub batch: ProcessedBatch,
+}
+
+/// Fetches the transactions a pipeline ingests.
+#[async_trait]
+pub trait Source: Send + Sync {
+    /// Fetch what's new, sending each batch to `output` as soon as it's fetched. Fails if
+    /// anything couldn't be fetched, once what could has been sent.
+    async fn poll(&self, output: &mpsc::Sender<Fetched>) -> anyhow::Result<()>;
+}
+
+/// Filters or transforms processed batches before they're written.
+pub trait Processor: Send + Sync {
+    fn process(&self, batch: &mut ProcessedBatch);
+}
+
+impl<F> Processor for F
+where
+    F: Fn(&mut ProcessedBatch) + Send + Sync,
+{
+    fn process(&self, batch: &mut ProcessedBatch) {
+        self(batch)
+    }
+}
+
+/// Writes the processed batches of a pipeline.
+#[async_trait]
+pub trait Destination: Send + Sync {
+    async fn write(&self, processed: &Processed) -> anyhow::Result<()>;
+}
+
+pub struct Pipeline<'a> {
+    client: &'a SolanaClient,
+    source: Box<dyn Source + 'a>,
+    processors: Vec<Box<dyn Processor + 'a>>,
+    destination: Box<dyn Destination + 'a>,
+    capacity: usize,
+    /// Storage the destination writes to, checked for already stored transactions before they're
+    /// sent down the alert priority lane. Without it, alerts are evaluated once stored.
+    alert_storage: Option<&'a Arc<dyn Storage>>,
+}
+
+impl<'a> Pipeline<'a> {
+    /// Ingest what `source` fetches into `destination`, processing it with `client`.
+    pub fn new(
+        client: &'a SolanaClient,
+        source: impl Source + 'a,
+        destination: impl Destination + 'a,
+    ) -> Self {
+        Pipeline {
+            client,
+            source: Box::new(source),
+            processors: Vec::new(),
+            destination: Box::new(destination),
+            capacity: DEFAULT_CAPACITY,
+            alert_storage: None,
+        }
+    }
+
+    /// The monitor's pipeline: poll the watchlist and store the transactions in `storage`, or
+    /// only publish them to the sinks without it.
+    pub fn monitor(
+        client: &'a SolanaClient,
+        watchlist: &'a Watchlist,
+        storage: Option<&'a Arc<dyn Storage>>,
+        postgres: Option<&'a Arc<PgPool>>,
+    ) -> Self {
+        let source = WatchlistSource {
+            client,
+            watchlist,
+            storage,
+        };
+
+        Self::storing(client, source, storage, postgres)
+    }
+
+    /// Store what `source` fetches in `storage`, like the monitor does, or only publish it to the
+    /// sinks without it.
+    pub fn storing(
+        client: &'a SolanaClient,
+        source: impl Source + 'a,
+        storage: Option<&'a Arc<dyn Storage>>,
+        postgres: Option<&'a Arc<PgPool>>,
+    ) -> Self {
+        match storage {
+            Some(storage) => Pipeline {
+                alert_storage: Some(storage),
+                ..Self::new(
+                    client,
+                    source,
+                    StorageDestination {
+                        client,
+                        storage,
+                        postgres,
+                    },
+                )
+            },
+            None => Self::new(client, source, SinksDestination { client }),
+        }
+    }
+
+    /// Run processed batches through `processor`, after the processors added before it.
+    pub fn with_processor(mut self, processor: impl Processor + 'a) -> Self {
+        self.processors.push(Box::new(processor));
+        self
+    }
+
+    /// Hold up to `capacity` batches between two stages.
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Poll each time `job` is due and the monitor isn't paused, until `shutdown` is cancelled.
+    pub async fn run(&self, job: &Job, shutdown: &CancellationToken) {
+        let control = self.client.monitor_control();
+
+        while job.tick(shutdown).await {
+            if !control.until_resumed(shutdown).await {
+                break;
+            }
+
+            control.record_tick();
+            let result = job.run(self.poll()).await;
+            control.record_poll(result.is_ok());
+        }
+    }
+
+    /// Fetch, process and write what the source has, with the stages running concurrently.
+    pub async fn poll(&self) -> anyhow::Result<()> {
+        let (fetched_sender, mut fetched) = mpsc::channel::<Fetched>(self.capacity);
+        let (processed_sender, mut processed) = mpsc::channel(self.capacity);
+        let canary = self.client.canary().map(|canary| canary.to_string());
+        let control = self.client.monitor_control();
+        // borrowed, so the process and write stages can both use it
+        let control = control.as_ref();
+
+        let source = async move {
+            // dropping the sender once done ends the other stages
+            self.source.poll(&fetched_sender).await
+        };
+
+        let process = async move {
+            while let Some(Fetched {
+                address,
+                signatures,
+                transactions,
+            }) = fetched.recv().await
+            {
+                let fetched_charge = self.client.charge_fetched(&transactions);
+                let mut batch = self.client.process(transactions, canary.as_deref());
+                drop(fetched_charge);
+
+                for processor in &self.processors {
+                    processor.process(&mut batch);
+                }
+
+                if let Some(storage) = self.alert_storage {
+                    self.client
+                        .dispatch_alerts(&batch.transactions, storage)
+                        .await;
+                }
+
+                info!(
+                    "Fetched {} transactions for {address}",
+                    batch.transactions.len()
+                );
+
+                control.record_slot(batch.transactions.iter().map(|txn| txn.slot).max());
+
+                let charge = self.client.charge_processed(&batch);
+                let processed = Processed {
+                    address,
+                    signatures,
+                    batch,
+                };
+
+                control.enqueue();
+
+                if processed_sender.send((processed, charge)).await.is_err() {
+                    control.dequeue(true);
+                    break;
+                }
+            }
+        };
+
+        let write = async move {
+            let (mut written, mut failed) = (0, 0);
+
+            while let Some((processed, _charge)) = processed.recv().await {
+                written += 1;
+
+                let result = self.destination.write(&processed).await;
+                control.dequeue(result.is_ok());
+
+                if let Err(e) = result {
+                    error!(
+                        "Failed to write the transactions of {}: {e:?}",
+                        processed.address
+                    );
+                    failed += 1;
+                }
+            }
+
+            (written, failed)
+        };
+
+        let (fetched, (), (written, failed)) = tokio::join!(source, process, write);
+        fetched?;
+
+        if failed > 0 {
+            anyhow::bail!("{failed} of {written} batches failed to write");
+        }
+
+        Ok(())
+    }
+}
+
+/// Polls the watchlist's addresses, the watched programs and the canary's address, reading the
+/// watchlist on every poll so discovered addresses are picked up as they're found.
+pub struct WatchlistSource<'a> {
+    pub client: &'a SolanaClient,
+    pub watchlist: &'a Watchlist,
+    /// Skips the transactions the dedup filters show are already stored here, if given.
+    pub storage: Option<&'a Arc<dyn Storage>>,
+}
+
+#[async_trait]
+impl Source for WatchlistSource<'_> {
+    async fn poll(&self, output: &mpsc::Sender<Fetched>) -> anyhow::Result<()> {
+        let addresses = self
+            .watchlist
+            .polled()
+            .into_iter()
+            .chain(self.client.canary())
+            .collect::<Vec<_>>();
+        let mut failed = 0;
+
+        for address in &addresses {
+            self.client.wait_for_memory().await;
+
+            let (signatures, transactions) =
+                match self.client.fetch_unstored(address, self.storage).await {
+                    Ok(fetched) => fetched,
+                    Err(e) => {
+                        error!("Error fetching epoch data for {address}: {:?}", e);
+                        failed += 1;
+                        continue;
+                    }
+                };
+
+            let fetched = Fetched {
+                address: *address,
+                signatures,
+                transactions,
+            };
+
+            if output.send(fetched).await.is_err() {
+                break;
+            }
+        }
+
+        if failed > 0 {
+            anyhow::bail!("{failed} of {} addresses failed to poll", addresses.len());
+        }
+
+        Ok(())
+    }
+}
+
+/// Stores batches, then records their polled signatures in the dedup filters.
+pub struct StorageDestination<'a> {
+    pub client: &'a SolanaClient,
+    pub storage: &'a Arc<dyn Storage>,
+    /// Fee splits, raw payloads and rejected transactions are only stored with a Postgres pool.
+    pub postgres: Option<&'a Arc<PgPool>>,
+}
+
+#[async_trait]
+impl Destination for StorageDestination<'_> {
+    async fn write(&self, processed: &Processed) -> anyhow::Result<()> {
+        self.client
+            .store(&processed.batch, self.storage, self.postgres)
+            .await?;
+        self.client
+            .record_polled(&processed.address, &processed.signatures);
+
+        Ok(())
+    }
+}
+
+/// Publishes batches to the sinks without storing them.
+pub struct SinksDestination<'a> {
+    pub client: &'a SolanaClient,
+}
+
+#[async_trait]
+impl Destination for SinksDestination<'_> {
+    async fn write(&self, processed: &Processed) -> anyhow::Result<()> {
+        self.client
+            .publish_unstored(&processed.batch.transactions)
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        data_storage::{InMemoryStorage, TransactionQuery},
+        rpc::FixtureRpc,
+    };
+
+    use std::{path::Path, str::FromStr, sync::Mutex};
+
+    const SENDER: &str = "DLpcCvJooMbrbJosuGYeUjYGxGZTgRfgWkhPehTgngiB";
+
+    /// Fetches the same address's transactions a number of times.
+    struct Repeat {
+        client: Arc<SolanaClient>,
+        times: usize,
+    }
+
+    #[async_trait]
+    impl Source for Repeat {
+        async fn poll(&self, output: &mpsc::Sender<Fetched>) -> anyhow::Result<()> {
+            let address = Pubkey::from_str(SENDER)?;
+
+            for _ in 0..self.times {
+                let signatures = self.client.fetch_transaction_signatures(&address).await?;
+                let transactions = self.client.fetch_transactions(&signatures).await?;
+                output
+                    .send(Fetched {
+                        address,
+                        signatures,
+                        transactions,
+                    })
+                    .await?;
+            }
+
+            Ok(())
+        }
+    }
+
+    /// Records how many transactions each batch it's given has.
+    #[derive(Clone, Default)]
+    struct Counts(Arc<Mutex<Vec<usize>>>);
+
+    #[async_trait]
+    impl Destination for Counts {
+        async fn write(&self, processed: &Processed) -> anyhow::Result<()> {
+            self.0
+                .lock()
+                .unwrap()
+                .push(processed.batch.transactions.len());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_pipeline_runs_batches_through_processors() -> anyhow::Result<()> {
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/rpc/transfer.json");
+        let client = Arc::new(SolanaClient::with_endpoints(vec![Box::new(
+            FixtureRpc::load(&fixture)?,
+        )]));
+
+        // more batches than the channels hold, so the source has to wait for the destination
+        let counts = Counts::default();
+        let source = Repeat {
+            client: Arc::clone(&client),
+            times: 5,
+        };
+        let pipeline = Pipeline::new(&client, source, counts.clone())
+            .with_capacity(1)
+            .with_processor(|batch: &mut ProcessedBatch| {
+                batch
+                    .transactions
+                    .retain(|txn| txn.sol_amount > 1_000_000_000)
+            });
+        pipeline.poll().await?;
+        assert_eq!(*counts.0.lock().unwrap(), vec![0; 5]);
+
+        let storage: Arc<dyn Storage> = Arc::new(InMemoryStorage::new());
+        let source = Repeat {
+            client: Arc::clone(&client),
+            times: 1,
+        };
+        let destination = StorageDestination {
+            client: &client,
+            storage: &storage,
+            postgres: None,
+        };
+        Pipeline::new(&client, source, destination).poll().await?;
+
+        let stored = storage.query(&TransactionQuery::default()).await?;
+        assert_eq!(stored.len(), 1);
+        assert_eq!(stored[0].transaction.sender, SENDER);
+
+        Ok(())
+    }
+}
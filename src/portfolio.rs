@@ -0,0 +1,249 @@
+// Builds wallet overviews
+
+// Responsibilities:
+// * Combine an account's SOL balance, token holdings and recent activity into one response.
+// * Name tokens from their on-chain metadata and value holdings in USD when prices are available.
+
+// Implementation:
+// * Balances and holdings are fetched live from RPC, so any account can be looked up, watched or
+//   not. Activity counts come from the stored transactions.
+// * Token names and symbols come from Metaplex metadata accounts, fetched in batches.
+// * USD prices come from a Jupiter-compatible price API (`GET <url>?ids=<mint>,...` answering
+//   `{"data": {"<mint>": {"price": "1.23"}}}`), with SOL priced as wrapped SOL. Without one, or
+//   when it fails, the overview is returned without USD values.
+
+use crate::{
+    config::PortfolioConfig,
+    data_processing::TokenHolding,
+    data_retrieval::SolanaClient,
+    data_storage::{get_activity_counts, ActivityCounts},
+};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use solana_sdk::{native_token::lamports_to_sol, pubkey::Pubkey};
+use sqlx::PgPool;
+
+use std::{
+    collections::{BTreeSet, HashMap},
+    str::FromStr,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Mint of wrapped SOL, under which price APIs list SOL.
+pub const WRAPPED_SOL_MINT: &str = "So11111111111111111111111111111111111111112";
+
+#[derive(Debug, Serialize)]
+pub struct PortfolioToken {
+    #[serde(flatten)]
+    pub holding: TokenHolding,
+    /// Amount in whole tokens.
+    pub ui_amount: f64,
+    /// `None` for mints without Metaplex metadata.
+    pub name: Option<String>,
+    pub symbol: Option<String>,
+    pub usd_price: Option<f64>,
+    pub usd_value: Option<f64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Portfolio {
+    pub pubkey: String,
+    pub lamports: u64,
+    pub sol: f64,
+    pub sol_usd_price: Option<f64>,
+    pub sol_usd_value: Option<f64>,
+    /// Largest holdings first, by USD value where known, then by amount.
+    pub tokens: Vec<PortfolioToken>,
+    /// Sum of the SOL and token values that could be priced; `None` without prices.
+    pub total_usd_value: Option<f64>,
+    pub activity: ActivityCounts,
+    /// Unix seconds.
+    pub fetched_at: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct PriceResponse {
+    data: HashMap<String, Option<PriceEntry>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PriceEntry {
+    price: String,
+}
+
+pub struct Portfolios {
+    solana_client: Arc<SolanaClient>,
+    price_api_url: Option<String>,
+    http: reqwest::Client,
+}
+
+impl Portfolios {
+    pub fn new(config: &PortfolioConfig, solana_client: Arc<SolanaClient>) -> Self {
+        Portfolios {
+            solana_client,
+            price_api_url: config.price_api_url.clone(),
+            http: reqwest::Client::new(),
+        }
+    }
+
+    /// Overview of `address`, leaving out empty token accounts unless `include_zero` is set.
+    pub async fn get(
+        &self,
+        address: &Pubkey,
+        include_zero: bool,
+        db: &Arc<PgPool>,
+    ) -> anyhow::Result<Portfolio> {
+        let fetched_at = unix_now();
+        let lamports = self.solana_client.fetch_lamports(address).await?;
+
+        let holdings = self
+            .solana_client
+            .fetch_token_holdings(address)
+            .await?
+            .into_iter()
+            .filter(|holding| include_zero || holding.amount > 0)
+            .collect::<Vec<_>>();
+
+        let mints = holdings
+            .iter()
+            .map(|holding| holding.mint.as_str())
+            .collect::<BTreeSet<_>>();
+        let mint_pubkeys = mints
+            .iter()
+            .filter_map(|mint| Pubkey::from_str(mint).ok())
+            .collect::<Vec<_>>();
+
+        let mut metadata = self
+            .solana_client
+            .fetch_token_metadata(&mint_pubkeys)
+            .await?;
+        let prices = self
+            .prices(mints.into_iter().chain([WRAPPED_SOL_MINT]))
+            .await;
+
+        let sol = lamports_to_sol(lamports);
+        let sol_usd_price = prices.get(WRAPPED_SOL_MINT).copied();
+
+        let mut tokens = holdings
+            .into_iter()
+            .map(|holding| {
+                let ui_amount = holding.ui_amount();
+                let usd_price = prices.get(&holding.mint).copied();
+                let metadata = metadata.remove(&holding.mint);
+
+                PortfolioToken {
+                    ui_amount,
+                    name: metadata.as_ref().map(|metadata| metadata.name.clone()),
+                    symbol: metadata.map(|metadata| metadata.symbol),
+                    usd_price,
+                    usd_value: usd_price.map(|price| price * ui_amount),
+                    holding,
+                }
+            })
+            .collect::<Vec<_>>();
+
+        tokens.sort_by(|a, b| {
+            b.usd_value
+                .unwrap_or(0.0)
+                .total_cmp(&a.usd_value.unwrap_or(0.0))
+                .then(b.ui_amount.total_cmp(&a.ui_amount))
+        });
+
+        let sol_usd_value = sol_usd_price.map(|price| price * sol);
+        let total_usd_value = (!prices.is_empty()).then(|| {
+            sol_usd_value.unwrap_or(0.0)
+                + tokens
+                    .iter()
+                    .filter_map(|token| token.usd_value)
+                    .sum::<f64>()
+        });
+
+        Ok(Portfolio {
+            pubkey: address.to_string(),
+            lamports,
+            sol,
+            sol_usd_price,
+            sol_usd_value,
+            tokens,
+            total_usd_value,
+            activity: get_activity_counts(db, &address.to_string(), fetched_at).await?,
+            fetched_at,
+        })
+    }
+
+    /// USD prices of the given mints, keyed by mint. Empty without a price API or if it fails.
+    async fn prices<'a>(&self, mints: impl Iterator<Item = &'a str>) -> HashMap<String, f64> {
+        let Some(url) = &self.price_api_url else {
+            return HashMap::new();
+        };
+
+        let ids = mints.collect::<Vec<_>>().join(",");
+
+        match self.fetch_prices(url, &ids).await {
+            Ok(prices) => prices,
+            Err(e) => {
+                warn!("Failed to fetch token prices, leaving out USD values: {e:?}");
+                HashMap::new()
+            }
+        }
+    }
+
+    async fn fetch_prices(&self, url: &str, ids: &str) -> anyhow::Result<HashMap<String, f64>> {
+        let response: PriceResponse = self
+            .http
+            .get(url)
+            .query(&[("ids", ids)])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(parse_prices(response))
+    }
+}
+
+/// Prices by mint, skipping mints the API couldn't price.
+fn parse_prices(response: PriceResponse) -> HashMap<String, f64> {
+    response
+        .data
+        .into_iter()
+        .filter_map(|(mint, entry)| Some((mint, entry?.price.parse().ok()?)))
+        .collect()
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_prices() {
+        let response: PriceResponse = serde_json::from_str(
+            r#"{
+                "data": {
+                    "So11111111111111111111111111111111111111112": {
+                        "id": "So11111111111111111111111111111111111111112",
+                        "type": "derivedPrice",
+                        "price": "142.50"
+                    },
+                    "UnpricedMint1111111111111111111111111111111": null
+                },
+                "timeTaken": 0.003
+            }"#,
+        )
+        .unwrap();
+
+        let prices = parse_prices(response);
+
+        assert_eq!(prices.len(), 1);
+        assert_eq!(prices[WRAPPED_SOL_MINT], 142.5);
+    }
+}
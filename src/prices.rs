@@ -0,0 +1,503 @@
+// Prices transactions and token transfers in USD at their block time
+
+// Responsibilities:
+// * Look up the SOL/USD price, and the USD price of each configured token, at the block time of
+//   every transaction about to be stored, so statistics and analytics can report USD volumes.
+// * Hide where prices come from behind `PriceProvider`: CoinGecko's HTTP API or Pyth's on-chain
+//   price accounts.
+
+// Implementation:
+// * Providers return price points over a time range, and a transaction gets the point nearest its
+//   block time if one is within `max_age_secs`. Points are cached per mint, so consecutive
+//   batches mostly reuse them.
+// * CoinGecko keeps 5-minute prices for the last day, hourly ones up to 90 days back and daily
+//   ones beyond, so older backfilled transactions are priced coarsely, or not at all with a small
+//   `max_age_secs`.
+// * Pyth price accounts only hold the current price, so with Pyth only transactions stored soon
+//   after their block time are priced.
+// * A mint is fetched again at most every `refresh_secs` for timestamps its cached points don't
+//   cover yet: the latest prices lag a little behind the chain anyway. Failed fetches wait as
+//   long before they're retried.
+// * Pricing never holds up storage: what couldn't be priced is stored unpriced, and isn't priced
+//   later.
+
+use crate::{
+    config::{PriceProviderKind, PricesConfig},
+    data_processing::{TokenTransfer, TransactionData},
+    portfolio::WRAPPED_SOL_MINT,
+};
+
+use async_trait::async_trait;
+use log::warn;
+use serde::Deserialize;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    str::FromStr,
+    sync::Mutex,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// CoinGecko coin id of SOL.
+const COINGECKO_SOL: &str = "solana";
+
+/// Pyth SOL/USD price account on mainnet.
+const PYTH_SOL_USD: &str = "H6ARHf6YXhGYeQfUzQNGk6rDNnLBQKrenN712K4AQJEG";
+
+/// Most price points cached per mint; the oldest are dropped beyond that.
+const MAX_POINTS_PER_MINT: usize = 10_000;
+
+/// Where USD prices come from.
+#[async_trait]
+pub trait PriceProvider: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// USD prices of `feed` between `from` and `to` (unix seconds), as `(timestamp, price)`
+    /// points in any order.
+    async fn history(&self, feed: &str, from: i64, to: i64) -> anyhow::Result<Vec<(i64, f64)>>;
+}
+
+/// Historical prices from CoinGecko's `market_chart/range`, with feeds being coin ids.
+pub struct CoinGecko {
+    http: reqwest::Client,
+    api_url: String,
+    api_key: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MarketChart {
+    /// Unix milliseconds and price.
+    prices: Vec<(f64, f64)>,
+}
+
+impl CoinGecko {
+    pub fn new(config: &PricesConfig) -> Self {
+        CoinGecko {
+            http: reqwest::Client::new(),
+            api_url: config.api_url.trim_end_matches('/').to_string(),
+            api_key: config.api_key.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl PriceProvider for CoinGecko {
+    fn name(&self) -> &'static str {
+        "coingecko"
+    }
+
+    async fn history(&self, feed: &str, from: i64, to: i64) -> anyhow::Result<Vec<(i64, f64)>> {
+        let mut request = self
+            .http
+            .get(format!("{}/coins/{feed}/market_chart/range", self.api_url))
+            .query(&[
+                ("vs_currency", "usd"),
+                ("from", &from.to_string()),
+                ("to", &to.to_string()),
+            ]);
+
+        if let Some(key) = &self.api_key {
+            // paid plans are served from their own host
+            let header = if self.api_url.contains("pro-api.") {
+                "x-cg-pro-api-key"
+            } else {
+                "x-cg-demo-api-key"
+            };
+            request = request.header(header, key);
+        }
+
+        let chart: MarketChart = request.send().await?.error_for_status()?.json().await?;
+
+        Ok(chart
+            .prices
+            .into_iter()
+            .map(|(millis, price)| ((millis / 1000.0) as i64, price))
+            .collect())
+    }
+}
+
+/// Current prices from Pyth price accounts, with feeds being their addresses.
+pub struct Pyth {
+    rpc: RpcClient,
+}
+
+impl Pyth {
+    pub fn new(rpc_url: &str) -> Self {
+        Pyth {
+            rpc: RpcClient::new(rpc_url.to_string()),
+        }
+    }
+}
+
+#[async_trait]
+impl PriceProvider for Pyth {
+    fn name(&self) -> &'static str {
+        "pyth"
+    }
+
+    async fn history(&self, feed: &str, _from: i64, _to: i64) -> anyhow::Result<Vec<(i64, f64)>> {
+        let data = self.rpc.get_account_data(&Pubkey::from_str(feed)?)?;
+
+        Ok(vec![parse_pyth_price(&data)?])
+    }
+}
+
+/// Publish time and aggregate price of a Pyth price account.
+fn parse_pyth_price(data: &[u8]) -> anyhow::Result<(i64, f64)> {
+    let u32_at = |offset: usize| data.get(offset..offset + 4).map(|b| b.try_into().unwrap());
+    let i64_at = |offset: usize| data.get(offset..offset + 8).map(|b| b.try_into().unwrap());
+
+    // magic number and account type 3, for price accounts
+    if u32_at(0).map(u32::from_le_bytes) != Some(0xa1b2c3d4)
+        || u32_at(8).map(u32::from_le_bytes) != Some(3)
+    {
+        anyhow::bail!("Not a Pyth price account");
+    }
+
+    let (Some(exponent), Some(timestamp), Some(price), Some(status)) = (
+        u32_at(20).map(i32::from_le_bytes),
+        i64_at(96).map(i64::from_le_bytes),
+        i64_at(208).map(i64::from_le_bytes),
+        u32_at(224).map(u32::from_le_bytes),
+    ) else {
+        anyhow::bail!("Truncated Pyth price account");
+    };
+
+    // 1 is trading; otherwise the aggregate price is stale or unknown
+    if status != 1 {
+        anyhow::bail!("Pyth price isn't trading (status {status})");
+    }
+
+    Ok((timestamp, price as f64 * 10f64.powi(exponent)))
+}
+
+/// Cached price points of one mint.
+#[derive(Debug, Default)]
+struct Series {
+    /// Oldest first.
+    points: Vec<(i64, f64)>,
+    /// Of the latest fetch, successful or not.
+    fetched_at: i64,
+    /// Start of the range the latest fetch asked for.
+    requested_from: i64,
+}
+
+impl Series {
+    /// Price of the point nearest `timestamp`, if one is within `max_age` seconds of it.
+    fn price_at(&self, timestamp: i64, max_age: i64) -> Option<f64> {
+        let next = self.points.partition_point(|(at, _)| *at < timestamp);
+
+        [next.checked_sub(1), Some(next)]
+            .into_iter()
+            .flatten()
+            .filter_map(|i| self.points.get(i))
+            .filter(|(at, _)| (at - timestamp).abs() <= max_age)
+            .min_by_key(|(at, _)| (at - timestamp).abs())
+            .map(|(_, price)| *price)
+    }
+
+    fn merge(&mut self, points: Vec<(i64, f64)>) {
+        self.points.extend(points);
+        self.points.sort_by_key(|(at, _)| *at);
+        self.points.dedup_by_key(|(at, _)| *at);
+
+        if self.points.len() > MAX_POINTS_PER_MINT {
+            self.points.drain(..self.points.len() - MAX_POINTS_PER_MINT);
+        }
+    }
+}
+
+pub struct PriceFeed {
+    provider: Box<dyn PriceProvider>,
+    /// Feed of each priced mint, SOL's under `WRAPPED_SOL_MINT`.
+    feeds: HashMap<String, String>,
+    max_age_secs: i64,
+    refresh_secs: i64,
+    series: Mutex<HashMap<String, Series>>,
+}
+
+impl PriceFeed {
+    pub fn new(config: &PricesConfig, rpc_url: &str) -> anyhow::Result<Self> {
+        let provider: Box<dyn PriceProvider> = match config.provider {
+            Some(PriceProviderKind::CoinGecko) => Box::new(CoinGecko::new(config)),
+            Some(PriceProviderKind::Pyth) => Box::new(Pyth::new(rpc_url)),
+            None => anyhow::bail!("No price provider configured"),
+        };
+        let sol_feed = match config.provider {
+            Some(PriceProviderKind::Pyth) => PYTH_SOL_USD,
+            _ => COINGECKO_SOL,
+        };
+
+        Self::with_provider(config, provider, sol_feed)
+    }
+
+    fn with_provider(
+        config: &PricesConfig,
+        provider: Box<dyn PriceProvider>,
+        sol_feed: &str,
+    ) -> anyhow::Result<Self> {
+        let mut feeds = config.token_feeds.clone();
+        feeds.insert(
+            WRAPPED_SOL_MINT.to_string(),
+            config.sol_feed.as_deref().unwrap_or(sol_feed).to_string(),
+        );
+
+        if config.provider == Some(PriceProviderKind::Pyth) {
+            for (mint, feed) in &feeds {
+                Pubkey::from_str(feed).map_err(|_| {
+                    anyhow::anyhow!("Invalid Pyth price account `{feed}` for mint {mint}")
+                })?;
+            }
+        }
+
+        Ok(PriceFeed {
+            provider,
+            feeds,
+            max_age_secs: config.max_age_secs as i64,
+            refresh_secs: config.refresh_secs as i64,
+            series: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// Price the transactions, and the token transfers of those with a feed, that aren't priced
+    /// yet, at their block time.
+    pub async fn price(
+        &self,
+        txns: &mut [TransactionData],
+        token_transfers: &mut HashMap<String, Vec<TokenTransfer>>,
+    ) {
+        let timestamps = txns
+            .iter()
+            .map(|txn| (txn.signature.clone(), txn.timestamp))
+            .collect::<HashMap<_, _>>();
+
+        // timestamps to price, by mint
+        let mut wanted = BTreeMap::<String, Vec<i64>>::new();
+
+        for txn in txns.iter().filter(|txn| txn.sol_usd_price.is_none()) {
+            wanted
+                .entry(WRAPPED_SOL_MINT.to_string())
+                .or_default()
+                .push(txn.timestamp);
+        }
+
+        for (signature, transfers) in token_transfers.iter() {
+            let Some(timestamp) = timestamps.get(signature) else {
+                continue;
+            };
+
+            for transfer in transfers {
+                if transfer.usd_price.is_none() && self.feeds.contains_key(&transfer.mint) {
+                    wanted
+                        .entry(transfer.mint.clone())
+                        .or_default()
+                        .push(*timestamp);
+                }
+            }
+        }
+
+        for (mint, timestamps) in &wanted {
+            self.refresh(mint, timestamps).await;
+        }
+
+        let series = self.series.lock().expect("price cache lock poisoned");
+        let price_at =
+            |mint: &str, timestamp: i64| series.get(mint)?.price_at(timestamp, self.max_age_secs);
+
+        for txn in txns.iter_mut().filter(|txn| txn.sol_usd_price.is_none()) {
+            txn.sol_usd_price = price_at(WRAPPED_SOL_MINT, txn.timestamp);
+        }
+
+        for (signature, transfers) in token_transfers.iter_mut() {
+            let Some(timestamp) = timestamps.get(signature) else {
+                continue;
+            };
+
+            for transfer in transfers.iter_mut().filter(|t| t.usd_price.is_none()) {
+                transfer.usd_price = price_at(&transfer.mint, *timestamp);
+            }
+        }
+    }
+
+    /// Fetch the prices of `mint` around those of `timestamps` not cached yet, unless it was
+    /// fetched for them within `refresh_secs`.
+    async fn refresh(&self, mint: &str, timestamps: &[i64]) {
+        let Some(feed) = self.feeds.get(mint) else {
+            return;
+        };
+
+        let now = unix_now();
+        let (from, to) = {
+            let series = self.series.lock().expect("price cache lock poisoned");
+            let cached = series.get(mint);
+            let missing = timestamps.iter().copied().filter(|timestamp| {
+                cached
+                    .and_then(|series| series.price_at(*timestamp, self.max_age_secs))
+                    .is_none()
+            });
+
+            let (Some(from), Some(to)) = (missing.clone().min(), missing.max()) else {
+                return;
+            };
+
+            if cached.is_some_and(|series| {
+                now - series.fetched_at < self.refresh_secs && from >= series.requested_from
+            }) {
+                return;
+            }
+
+            (from - self.max_age_secs, (to + self.max_age_secs).min(now))
+        };
+
+        let fetched = self.provider.history(feed, from, to).await;
+
+        let mut series = self.series.lock().expect("price cache lock poisoned");
+        let series = series.entry(mint.to_string()).or_default();
+        series.fetched_at = now;
+        series.requested_from = from;
+
+        match fetched {
+            Ok(points) => series.merge(points),
+            Err(e) => warn!(
+                "Failed to fetch {mint} prices from {}, storing transactions unpriced: {e:?}",
+                self.provider.name()
+            ),
+        }
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    /// SOL at 100 and the token at 2, every 300 seconds, counting fetches.
+    struct Fixed {
+        fetches: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl PriceProvider for Fixed {
+        fn name(&self) -> &'static str {
+            "fixed"
+        }
+
+        async fn history(&self, feed: &str, from: i64, to: i64) -> anyhow::Result<Vec<(i64, f64)>> {
+            self.fetches.fetch_add(1, Ordering::SeqCst);
+            let price = if feed == "sol" { 100.0 } else { 2.0 };
+
+            Ok((from - from % 300..=to)
+                .step_by(300)
+                .map(|at| (at, price))
+                .collect())
+        }
+    }
+
+    fn transaction(signature: &str, timestamp: i64) -> TransactionData {
+        TransactionData {
+            signature: signature.to_string(),
+            sender: "sender".to_string(),
+            receiver: "receiver".to_string(),
+            sol_amount: 1_000_000_000,
+            fee: 5000,
+            timestamp,
+            prev_blockhash: "4sZ76MsNd8y3WSw2L1nfd3AqLoYxdmC98sERoMRbHV14".to_string(),
+            slot: 42,
+            ..TransactionData::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_prices_transactions_and_tokens_from_cache() {
+        let fetches = Arc::new(AtomicUsize::new(0));
+        let config = PricesConfig {
+            sol_feed: Some("sol".to_string()),
+            token_feeds: HashMap::from([("mint".to_string(), "token".to_string())]),
+            max_age_secs: 600,
+            ..PricesConfig::default()
+        };
+        let feed = PriceFeed::with_provider(
+            &config,
+            Box::new(Fixed {
+                fetches: Arc::clone(&fetches),
+            }),
+            COINGECKO_SOL,
+        )
+        .unwrap();
+
+        let mut txns = vec![
+            transaction("a", 1_600_000_000),
+            transaction("b", 1_600_003_000),
+        ];
+        let transfer = |mint: &str| TokenTransfer {
+            account_index: 1,
+            mint: mint.to_string(),
+            owner: None,
+            change: 5,
+            decimals: 6,
+            usd_price: None,
+        };
+        let mut token_transfers = HashMap::from([(
+            "a".to_string(),
+            vec![transfer("mint"), transfer("unpriced")],
+        )]);
+
+        feed.price(&mut txns, &mut token_transfers).await;
+        assert_eq!(txns[0].sol_usd_price, Some(100.0));
+        assert_eq!(txns[1].sol_usd_price, Some(100.0));
+        assert_eq!(token_transfers["a"][0].usd_price, Some(2.0));
+        assert_eq!(token_transfers["a"][1].usd_price, None);
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+        // already cached
+        let mut txns = vec![transaction("c", 1_600_001_000)];
+        feed.price(&mut txns, &mut HashMap::new()).await;
+        assert_eq!(txns[0].sol_usd_price, Some(100.0));
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+        // newer than the cache, but fetched moments ago
+        let mut txns = vec![transaction("d", unix_now())];
+        feed.price(&mut txns, &mut HashMap::new()).await;
+        assert_eq!(txns[0].sol_usd_price, None);
+        assert_eq!(fetches.load(Ordering::SeqCst), 2);
+
+        // older than anything fetched, then cached
+        for _ in 0..2 {
+            let mut txns = vec![transaction("e", 1_500_000_000)];
+            feed.price(&mut txns, &mut HashMap::new()).await;
+            assert_eq!(txns[0].sol_usd_price, Some(100.0));
+        }
+        assert_eq!(fetches.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn test_parse_pyth_price() {
+        let mut data = vec![0; 240];
+        data[0..4].copy_from_slice(&0xa1b2c3d4u32.to_le_bytes());
+        data[8..12].copy_from_slice(&3u32.to_le_bytes());
+        data[20..24].copy_from_slice(&(-8i32).to_le_bytes());
+        data[96..104].copy_from_slice(&1_700_000_000i64.to_le_bytes());
+        data[208..216].copy_from_slice(&14_250_000_000i64.to_le_bytes());
+        data[224..228].copy_from_slice(&1u32.to_le_bytes());
+
+        let (timestamp, price) = parse_pyth_price(&data).unwrap();
+        assert_eq!(timestamp, 1_700_000_000);
+        assert!((price - 142.5).abs() < 1e-9);
+
+        data[224..228].copy_from_slice(&0u32.to_le_bytes());
+        assert!(parse_pyth_price(&data).is_err());
+        assert!(parse_pyth_price(&data[..100]).is_err());
+    }
+}
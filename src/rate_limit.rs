@@ -0,0 +1,105 @@
+// Keeps outbound RPC requests under each provider's rate limit
+
+// Responsibilities:
+// * Space out the requests sent to each RPC endpoint, so aggressive backfills and analyses don't
+//   get the API key banned by the provider.
+
+// Implementation:
+// * One token bucket per endpoint, shared by every fetch path through the `SolanaClient` that
+//   owns it: monitoring, backfills, on-demand analyses and portfolio lookups.
+// * Buckets hold up to a second's worth of requests, so short bursts go out right away while the
+//   sustained rate is capped.
+// * A caller reserves its slot before waiting for it, so concurrent callers queue up in turn
+//   rather than all waking at once. The wait is a tokio sleep, so queued callers don't hold up
+//   the runtime's threads.
+
+use tokio::time;
+
+use std::{
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug)]
+struct Bucket {
+    /// Negative while callers are queued for future slots.
+    tokens: f64,
+    updated_at: Instant,
+}
+
+#[derive(Debug)]
+pub struct RateLimiter {
+    per_sec: f64,
+    bucket: Mutex<Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(per_sec: f64) -> Self {
+        RateLimiter {
+            per_sec,
+            bucket: Mutex::new(Bucket {
+                tokens: capacity(per_sec),
+                updated_at: Instant::now(),
+            }),
+        }
+    }
+
+    /// Wait until a request may be sent.
+    pub async fn acquire(&self) {
+        let wait = self.reserve(Instant::now());
+
+        if !wait.is_zero() {
+            time::sleep(wait).await;
+        }
+    }
+
+    /// Reserve the next request slot, returning how long until it comes up.
+    fn reserve(&self, now: Instant) -> Duration {
+        let mut bucket = self.bucket.lock().expect("rate limiter lock poisoned");
+
+        let refill = now
+            .saturating_duration_since(bucket.updated_at)
+            .as_secs_f64()
+            * self.per_sec;
+        bucket.tokens = (bucket.tokens + refill).min(capacity(self.per_sec)) - 1.0;
+        bucket.updated_at = now;
+
+        if bucket.tokens >= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64(-bucket.tokens / self.per_sec)
+        }
+    }
+}
+
+/// Requests a bucket holds: a second's worth, and at least one.
+fn capacity(per_sec: f64) -> f64 {
+    per_sec.max(1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bursts_then_queues() {
+        let limiter = RateLimiter::new(10.0);
+        let start = limiter.bucket.lock().unwrap().updated_at;
+
+        for _ in 0..10 {
+            assert_eq!(limiter.reserve(start), Duration::ZERO);
+        }
+
+        // queued callers get consecutive slots
+        let millis = |wait: Duration| (wait.as_secs_f64() * 1000.0).round();
+        assert_eq!(millis(limiter.reserve(start)), 100.0);
+        assert_eq!(millis(limiter.reserve(start)), 200.0);
+
+        // a second later the queue has drained and eight tokens are back
+        let later = start + Duration::from_secs(1);
+        for _ in 0..8 {
+            assert_eq!(limiter.reserve(later), Duration::ZERO);
+        }
+        assert!(!limiter.reserve(later).is_zero());
+    }
+}
@@ -0,0 +1,286 @@
+// Runs an instance as a warm standby of a primary aggregator
+
+// Responsibilities:
+// * Tail the primary's stored transactions into the local storage.
+// * Keep the blockchain monitor idle until the standby is promoted, then hand ingestion over to it.
+// * Report how far replication has got.
+
+// Implementation:
+// * Replication pages through the primary's `/transactions` endpoint with its `after` cursor, so
+//   it needs nothing from the primary beyond its API, and works with obfuscated ids too.
+// * Replicated transactions go through the same batch store as polled ones, so they show up in
+//   the ingest metrics and on the live stream. Transactions already stored are skipped.
+// * With Postgres the cursor is persisted after every page, so a restarted standby picks up where
+//   it left off; otherwise it starts over and skips what it already has.
+// * Only transactions are replicated. Fee splits, raw payloads and everything derived by the
+//   primary's background jobs are recorded by the standby itself once it is promoted.
+
+use crate::{
+    config::FollowerConfig,
+    data_processing::TransactionData,
+    data_retrieval::{ProcessedBatch, SolanaClient},
+    data_storage::{get_replication_cursor, upsert_replication_cursor, Storage},
+    schedules::Job,
+};
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use sqlx::PgPool;
+use tokio_util::sync::CancellationToken;
+
+use std::{
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// A transaction as served by the primary's API.
+#[derive(Debug, Deserialize)]
+struct ReplicatedTransaction {
+    id: String,
+    #[serde(flatten)]
+    transaction: TransactionData,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ReplicationReport {
+    pub primary_url: String,
+    /// `true` once promoted; replication has stopped and the monitor polls the chain.
+    pub promoted: bool,
+    /// Unix seconds.
+    pub promoted_at: Option<i64>,
+    /// The primary's id of the last replicated transaction.
+    pub cursor: Option<String>,
+    /// Unix seconds of the last run that reached the end of the primary's transactions.
+    pub caught_up_at: Option<i64>,
+    /// Transactions newly stored since startup.
+    pub replicated: u64,
+}
+
+pub struct Follower {
+    primary_url: String,
+    api_key: Option<String>,
+    batch_size: usize,
+    http: reqwest::Client,
+    db: Option<Arc<PgPool>>,
+    /// Cancelled on promotion, and on shutdown as a child of the shutdown token.
+    standby: CancellationToken,
+    report: Mutex<ReplicationReport>,
+}
+
+impl Follower {
+    pub fn new(
+        config: &FollowerConfig,
+        db: Option<Arc<PgPool>>,
+        shutdown: &CancellationToken,
+    ) -> Self {
+        let primary_url = config.primary_url.trim_end_matches('/').to_string();
+
+        Follower {
+            api_key: config.api_key.clone(),
+            batch_size: config.batch_size.max(1),
+            http: reqwest::Client::new(),
+            db,
+            standby: shutdown.child_token(),
+            report: Mutex::new(ReplicationReport {
+                primary_url: primary_url.clone(),
+                promoted: false,
+                promoted_at: None,
+                cursor: None,
+                caught_up_at: None,
+                replicated: 0,
+            }),
+            primary_url,
+        }
+    }
+
+    /// Stop replicating and let the monitor take over. Returns `false` if already promoted.
+    pub fn promote(&self) -> bool {
+        {
+            let mut report = self
+                .report
+                .lock()
+                .expect("replication report lock poisoned");
+
+            if report.promoted {
+                return false;
+            }
+
+            report.promoted = true;
+            report.promoted_at = Some(unix_now());
+        }
+
+        info!("Promoted from standby of {}", self.primary_url);
+        self.standby.cancel();
+
+        true
+    }
+
+    /// Wait until promoted. Returns `false` if shut down first.
+    pub async fn until_promoted(&self, shutdown: &CancellationToken) -> bool {
+        self.standby.cancelled().await;
+
+        !shutdown.is_cancelled()
+    }
+
+    pub fn report(&self) -> ReplicationReport {
+        self.report
+            .lock()
+            .expect("replication report lock poisoned")
+            .clone()
+    }
+
+    /// Replicate the primary's transactions until promoted or shut down.
+    pub async fn replicate(
+        &self,
+        solana_client: &SolanaClient,
+        storage: &Arc<dyn Storage>,
+        job: &Job,
+    ) {
+        let mut cursor = match &self.db {
+            Some(db) => match get_replication_cursor(db, &self.primary_url).await {
+                Ok(cursor) => cursor,
+                Err(e) => {
+                    error!("Failed to load the replication cursor, starting over: {e:?}");
+                    None
+                }
+            },
+            None => None,
+        };
+
+        self.report
+            .lock()
+            .expect("replication report lock poisoned")
+            .cursor = cursor.clone();
+
+        while job.tick(&self.standby).await {
+            let result = job
+                .run(self.catch_up(solana_client, storage, &mut cursor))
+                .await;
+
+            if let Err(e) = result {
+                error!("Failed to replicate from {}: {e:?}", self.primary_url);
+            }
+        }
+
+        info!("Stopped replicating from {}", self.primary_url);
+    }
+
+    /// Copy pages of transactions after `cursor` until the primary has no more, or promotion.
+    async fn catch_up(
+        &self,
+        solana_client: &SolanaClient,
+        storage: &Arc<dyn Storage>,
+        cursor: &mut Option<String>,
+    ) -> anyhow::Result<()> {
+        while !self.standby.is_cancelled() {
+            let page = self.fetch_page(cursor.as_deref()).await?;
+            let full = page.len() >= self.batch_size;
+
+            if let Some(last) = page.last() {
+                let next = last.id.clone();
+                let batch = ProcessedBatch {
+                    transactions: page.into_iter().map(|txn| txn.transaction).collect(),
+                    ..ProcessedBatch::default()
+                };
+                let stored = solana_client.store(&batch, storage, None).await?;
+
+                if let Some(db) = &self.db {
+                    upsert_replication_cursor(db, &self.primary_url, &next, unix_now()).await?;
+                }
+
+                let mut report = self
+                    .report
+                    .lock()
+                    .expect("replication report lock poisoned");
+                report.replicated += stored.len() as u64;
+                report.cursor = Some(next.clone());
+                *cursor = Some(next);
+            }
+
+            if !full {
+                self.report
+                    .lock()
+                    .expect("replication report lock poisoned")
+                    .caught_up_at = Some(unix_now());
+                break;
+            }
+        }
+
+        Ok(())
+    }
+
+    async fn fetch_page(&self, after: Option<&str>) -> anyhow::Result<Vec<ReplicatedTransaction>> {
+        let mut query = vec![("limit", self.batch_size.to_string())];
+
+        if let Some(after) = after {
+            query.push(("after", after.to_string()));
+        }
+
+        let mut request = self
+            .http
+            .get(format!("{}/transactions", self.primary_url))
+            .query(&query);
+
+        if let Some(api_key) = &self.api_key {
+            request = request.header("X-API-Key", api_key);
+        }
+
+        let page = request.send().await?.error_for_status()?.json().await?;
+
+        Ok(page)
+    }
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_primary_transactions_deserialize() {
+        let json = r#"[{
+            "id": "jR",
+            "signature": "sig1",
+            "sender": "alice",
+            "receiver": "bob",
+            "sol_amount": 1000,
+            "fee": 5000,
+            "timestamp": 1700000000,
+            "prev_blockhash": "hash",
+            "slot": 42,
+            "error": null,
+            "log_messages": [],
+            "compute_units_consumed": 150
+        }]"#;
+
+        let page: Vec<ReplicatedTransaction> = serde_json::from_str(json).unwrap();
+
+        assert_eq!(page[0].id, "jR");
+        assert_eq!(page[0].transaction.signature, "sig1");
+        assert_eq!(page[0].transaction.slot, 42);
+    }
+
+    #[tokio::test]
+    async fn test_promotion() {
+        let shutdown = CancellationToken::new();
+        let config = FollowerConfig {
+            primary_url: "http://primary:8080/".to_string(),
+            api_key: None,
+            interval_secs: 5,
+            batch_size: 500,
+        };
+        let follower = Follower::new(&config, None, &shutdown);
+
+        assert_eq!(follower.report().primary_url, "http://primary:8080");
+
+        assert!(follower.promote());
+        assert!(!follower.promote());
+        assert!(follower.until_promoted(&shutdown).await);
+        assert!(follower.report().promoted_at.is_some());
+    }
+}
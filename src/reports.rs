@@ -0,0 +1,275 @@
+// Daily activity reports of the watched addresses
+
+// Responsibilities:
+// * Summarize what each watched address did in a UTC day: its transactions, the SOL it received
+//   and sent, the fees it paid and the counterparties it had the most transactions with.
+// * Store the summaries in `daily_reports`, served as JSON or CSV by `GET /reports/daily`.
+// * Email each day's reports to the configured recipients.
+
+// Implementation:
+// * Every `interval_secs`, the previous UTC day is reported on unless it already was, so a restart
+//   neither skips the day nor reports on it twice. Days still in progress are never reported on.
+// * Reports are computed from the stored transactions when the day is reported on; transactions
+//   stored later, e.g. by gap repair, aren't added to it.
+// * Transfers between an address and itself count as neither inflow nor outflow. Fees are those of
+//   the transactions the address paid for: its fee payer, or its sender for transactions stored
+//   before fee payers were recorded. Transactions dropped by a fork are left out.
+// * Emails go through the `[alerts.smtp]` server, which needs the `email` feature. A failed email
+//   is logged and not sent again.
+
+use crate::{
+    alerts::{check_email, send_email},
+    config::{ReportsConfig, SmtpConfig},
+    data_storage::{
+        get_counterparties, get_daily_activity, has_daily_reports, upsert_daily_reports,
+        DailyReport,
+    },
+    metering::civil_date,
+    schedules::Job,
+    watchlist::Watchlist,
+};
+
+use log::{error, info};
+use serde::Deserialize;
+use sqlx::{types::Json, PgPool};
+use tokio_util::sync::CancellationToken;
+
+use std::{
+    cmp::Reverse,
+    fmt::Write,
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const DAY_SECS: i64 = 86_400;
+
+/// Format daily reports are downloaded in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+pub struct DailyReporter {
+    watchlist: Arc<Watchlist>,
+    top_counterparties: usize,
+    recipients: Vec<String>,
+    smtp: Option<SmtpConfig>,
+}
+
+impl DailyReporter {
+    pub fn new(
+        config: &ReportsConfig,
+        smtp: Option<SmtpConfig>,
+        watchlist: Arc<Watchlist>,
+    ) -> anyhow::Result<Self> {
+        for to in &config.recipients {
+            check_email(to)?;
+        }
+
+        Ok(DailyReporter {
+            watchlist,
+            top_counterparties: config.top_counterparties,
+            recipients: config.recipients.clone(),
+            smtp,
+        })
+    }
+
+    /// Report on every day once it's over, checking every time `job` is due, until `shutdown` is
+    /// cancelled.
+    pub async fn run(&self, db: &Arc<PgPool>, job: &Job, shutdown: &CancellationToken) {
+        while job.tick(shutdown).await {
+            let _ = job.run(self.report_previous_day(db)).await;
+        }
+    }
+
+    /// Report on the previous UTC day and email the reports, unless it was reported on already.
+    async fn report_previous_day(&self, db: &Arc<PgPool>) -> anyhow::Result<()> {
+        let date = previous_day();
+
+        if has_daily_reports(db, &date).await? {
+            return Ok(());
+        }
+
+        let reports = self.report(db, &date).await?;
+        info!("Reported on {} addresses for {date}", reports.len());
+
+        if let Some(smtp) = self.smtp.as_ref().filter(|_| !reports.is_empty()) {
+            let subject = format!("Daily report for {date}");
+            let body = email_body(&date, &reports);
+
+            for to in &self.recipients {
+                if let Err(e) = send_email(smtp, to, subject.clone(), body.clone()).await {
+                    error!("Failed to email the daily report to {to}: {e:?}");
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Summarize the activity of every watched address on `date`, a `YYYY-MM-DD` string, storing
+    /// the reports.
+    pub async fn report(&self, db: &Arc<PgPool>, date: &str) -> anyhow::Result<Vec<DailyReport>> {
+        let from = day_start(date).ok_or_else(|| anyhow::anyhow!("Invalid date `{date}`"))?;
+        let generated_at = unix_now();
+        let mut reports = Vec::new();
+
+        for address in self.watchlist.addresses() {
+            let pubkey = address.to_string();
+            let activity = get_daily_activity(db, &pubkey, from, from + DAY_SECS).await?;
+            let top_counterparties = get_counterparties(
+                db,
+                &pubkey,
+                Some(from),
+                Some(from + DAY_SECS - 1),
+                self.top_counterparties as i64,
+                0,
+            )
+            .await?;
+
+            reports.push(DailyReport {
+                date: date.to_string(),
+                pubkey,
+                transaction_count: activity.transaction_count,
+                inflow: activity.inflow,
+                outflow: activity.outflow,
+                fees: activity.fees,
+                top_counterparties: Json(top_counterparties),
+                generated_at,
+            });
+        }
+
+        upsert_daily_reports(db, &reports).await?;
+
+        Ok(reports)
+    }
+}
+
+/// Write daily reports as CSV, with the top counterparties' addresses separated by spaces.
+pub fn reports_csv(reports: &[DailyReport]) -> anyhow::Result<Vec<u8>> {
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer.write_record([
+        "date",
+        "pubkey",
+        "transaction_count",
+        "inflow",
+        "outflow",
+        "fees",
+        "top_counterparties",
+    ])?;
+
+    for report in reports {
+        let top_counterparties = report
+            .top_counterparties
+            .iter()
+            .map(|counterparty| counterparty.address.as_str())
+            .collect::<Vec<_>>()
+            .join(" ");
+
+        writer.write_record([
+            report.date.clone(),
+            report.pubkey.clone(),
+            report.transaction_count.to_string(),
+            report.inflow.to_string(),
+            report.outflow.to_string(),
+            report.fees.to_string(),
+            top_counterparties,
+        ])?;
+    }
+
+    Ok(writer.into_inner()?)
+}
+
+/// One line per address, busiest first.
+fn email_body(date: &str, reports: &[DailyReport]) -> String {
+    let mut reports = reports.iter().collect::<Vec<_>>();
+    reports.sort_by_key(|report| Reverse(report.transaction_count));
+
+    let mut body = format!("Activity of the watched addresses on {date} (UTC):\n\n");
+
+    for report in reports {
+        let _ = writeln!(
+            body,
+            "{}: {} transactions, {} SOL in, {} SOL out, {} SOL in fees",
+            report.pubkey,
+            report.transaction_count,
+            report.inflow as f64 / 1e9,
+            report.outflow as f64 / 1e9,
+            report.fees as f64 / 1e9
+        );
+    }
+
+    body
+}
+
+/// The previous UTC day as `YYYY-MM-DD`, the latest one reported on.
+pub fn previous_day() -> String {
+    date_of(unix_now().div_euclid(DAY_SECS) * DAY_SECS - DAY_SECS)
+}
+
+/// The UTC day of a unix timestamp as `YYYY-MM-DD`.
+fn date_of(unix_secs: i64) -> String {
+    let (year, month, day) = civil_date(unix_secs);
+
+    format!("{year:04}-{month:02}-{day:02}")
+}
+
+/// Unix seconds of the start of `date`, if it is a valid `YYYY-MM-DD` date.
+pub fn day_start(date: &str) -> Option<i64> {
+    let mut parts = date.splitn(3, '-');
+    let (year, month, day) = (parts.next()?, parts.next()?, parts.next()?);
+
+    if year.len() != 4 || month.len() != 2 || day.len() != 2 {
+        return None;
+    }
+
+    let (year, month, day) = (
+        year.parse::<i64>().ok()?,
+        month.parse::<i64>().ok()?,
+        day.parse::<i64>().ok()?,
+    );
+
+    // civil date to days, after Howard Hinnant's `days_from_civil`
+    let year_of_march = year - i64::from(month <= 2);
+    let era = year_of_march.div_euclid(400);
+    let year_of_era = year_of_march.rem_euclid(400);
+    let shifted_month = (month + 9) % 12;
+    let day_of_year = (153 * shifted_month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let start = (era * 146_097 + day_of_era - 719_468) * DAY_SECS;
+
+    // rejects months and days out of range, e.g. February 30th
+    (date_of(start) == date).then_some(start)
+}
+
+fn unix_now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs() as i64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dates() {
+        assert_eq!(date_of(0), "1970-01-01");
+        // 2024-02-29T23:59:59Z, then the next second
+        assert_eq!(date_of(1_709_251_199), "2024-02-29");
+        assert_eq!(date_of(1_709_251_200), "2024-03-01");
+
+        assert_eq!(day_start("1970-01-01"), Some(0));
+        assert_eq!(day_start("2024-02-29"), Some(1_709_164_800));
+        assert_eq!(day_start("2024-03-01"), Some(1_709_251_200));
+        assert_eq!(day_start("2025-12-31"), Some(1_767_139_200));
+
+        assert_eq!(day_start("2025-02-29"), None);
+        assert_eq!(day_start("2025-13-01"), None);
+        assert_eq!(day_start("2025-1-01"), None);
+        assert_eq!(day_start("yesterday"), None);
+    }
+}
@@ -0,0 +1,108 @@
+// Decides when and how long to wait before retrying a failed RPC call
+
+// Responsibilities:
+// * Tell transient provider errors (timeouts, rate limits, unhealthy nodes, ...) from those a
+//   retry can't fix.
+// * Space out retries with exponential backoff, so a struggling provider isn't hammered.
+
+// Implementation:
+// * Backoff uses full jitter: a random delay up to `initial_backoff_ms` doubled per attempt and
+//   capped at `max_backoff_ms`, which keeps clients that failed together from retrying together.
+// * The Solana HTTP client already retries 429 responses itself, honoring `Retry-After`, before it
+//   gives up and reports them. A rate limit that outlasts those retries is waited out for at
+//   least `rate_limit_backoff_ms`.
+
+use crate::config::RpcRetryConfig;
+
+use rand::Rng;
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    rpc_request::RpcError,
+};
+
+use std::time::Duration;
+
+/// JSON-RPC error codes of a node that may answer once it has caught up or recovered.
+const TRANSIENT_RPC_CODES: [i64; 5] = [
+    -32004, // block not available
+    -32005, // node unhealthy
+    -32014, // block status not available yet
+    -32016, // minimum context slot not reached
+    -32603, // internal error
+];
+
+/// How a failed RPC call is treated.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// Retrying won't help, e.g. a malformed request or a transaction that doesn't exist.
+    Permanent,
+    Transient,
+    /// The provider answered 429 Too Many Requests.
+    RateLimited,
+}
+
+pub fn classify(error: &ClientError) -> Failure {
+    match error.kind() {
+        ClientErrorKind::Io(_) => Failure::Transient,
+        ClientErrorKind::Reqwest(e) => match e.status() {
+            Some(status) if status.as_u16() == 429 => Failure::RateLimited,
+            Some(status) if !status.is_server_error() => Failure::Permanent,
+            // timeouts and connection errors have no status
+            _ => Failure::Transient,
+        },
+        ClientErrorKind::RpcError(RpcError::RpcResponseError { code, .. })
+            if TRANSIENT_RPC_CODES.contains(code) =>
+        {
+            Failure::Transient
+        }
+        _ => Failure::Permanent,
+    }
+}
+
+/// Delay before retry number `retry` (starting at 0) after a `failure`, or `None` if it shouldn't
+/// be retried.
+pub fn backoff(config: &RpcRetryConfig, retry: u32, failure: Failure) -> Option<Duration> {
+    if failure == Failure::Permanent || retry + 1 >= config.max_attempts {
+        return None;
+    }
+
+    let ceiling = config
+        .initial_backoff_ms
+        .saturating_mul(1 << retry.min(32))
+        .min(config.max_backoff_ms);
+    let mut delay = rand::thread_rng().gen_range(0..=ceiling);
+
+    if failure == Failure::RateLimited {
+        delay = delay.max(config.rate_limit_backoff_ms);
+    }
+
+    Some(Duration::from_millis(delay))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_backoff_grows_to_cap() {
+        let config = RpcRetryConfig {
+            max_attempts: 10,
+            initial_backoff_ms: 100,
+            max_backoff_ms: 1000,
+            rate_limit_backoff_ms: 5000,
+        };
+
+        for retry in 0..9 {
+            let ceiling = Duration::from_millis((100 << retry).min(1000));
+            let delay = backoff(&config, retry, Failure::Transient).unwrap();
+            assert!(delay <= ceiling, "retry {retry} waited {delay:?}");
+
+            let delay = backoff(&config, retry, Failure::RateLimited).unwrap();
+            assert_eq!(delay, Duration::from_millis(5000));
+        }
+
+        // the last attempt failed
+        assert_eq!(backoff(&config, 9, Failure::Transient), None);
+        assert_eq!(backoff(&config, 0, Failure::Permanent), None);
+    }
+}
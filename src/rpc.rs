@@ -0,0 +1,651 @@
+// Abstracts the Solana JSON-RPC calls, so they can be replayed from recorded fixtures
+
+// Responsibilities:
+// * Define `RpcApi`, the RPC calls `SolanaClient` makes, implemented by the real `RpcClient`.
+// * Replay recorded JSON-RPC responses from a fixture file with `FixtureRpc`, so retrieval and
+//   the ingestion pipeline can be tested offline and deterministically.
+// * Record the responses of a real RPC node to such a file with `RecordingRpc`, behind the
+//   `--record-rpc` CLI argument.
+
+// Implementation:
+// * Every call has a default implementation that builds the JSON-RPC request, hands it to `send`
+//   and decodes the result like `RpcClient` does, so a fixture or recording endpoint only
+//   implements `send`. `RpcClient` overrides them all with its own.
+// * A fixture file is a JSON array of exchanges: a method, its params and either the result or an
+//   error message. Recorded params match calls whose params contain them, so hand-written
+//   fixtures only need the params that tell calls apart, e.g. a signature; without params an
+//   exchange answers every call of its method. The first matching exchange answers.
+// * `send_raw` returns response bodies unparsed, so large results such as whole blocks can be
+//   parsed with borrowed data. `RpcClient` only hands out parsed results, so for it the request
+//   is posted to the endpoint directly, blocking like its own calls do.
+// * Sending transactions isn't replayable: `FixtureRpc` fails it, and `RecordingRpc` sends it
+//   without recording.
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, value::RawValue, Value};
+use solana_account_decoder::{UiAccount, UiAccountEncoding};
+use solana_client::{
+    client_error::{ClientErrorKind, Result as ClientResult},
+    rpc_client::{GetConfirmedSignaturesForAddress2Config, RpcClient},
+    rpc_config::{
+        RpcAccountInfoConfig, RpcProgramAccountsConfig, RpcSignatureStatusConfig,
+        RpcSignaturesForAddressConfig, RpcTokenAccountsFilter, RpcTransactionConfig,
+    },
+    rpc_request::{RpcError, RpcRequest, RpcResponseErrorData, TokenAccountsFilter},
+    rpc_response::{
+        Response, RpcBlockhash, RpcConfirmedTransactionStatusWithSignature, RpcKeyedAccount,
+    },
+};
+use solana_sdk::{
+    account::Account, commitment_config::CommitmentConfig, epoch_info::EpochInfo,
+    epoch_schedule::EpochSchedule, hash::Hash, pubkey::Pubkey, signature::Signature,
+    transaction::Transaction,
+};
+use solana_transaction_status::{EncodedConfirmedTransactionWithStatusMeta, TransactionStatus};
+use tokio::{runtime::Handle, task};
+
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+
+/// How long a raw request may take, as long as `RpcClient`'s own.
+const RAW_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// The Solana JSON-RPC calls the aggregator makes, at `confirmed` commitment unless told
+/// otherwise.
+pub trait RpcApi: Send + Sync {
+    /// Endpoint the calls go to.
+    fn url(&self) -> String;
+
+    /// Send a raw JSON-RPC request and return its result as is.
+    fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value>;
+
+    /// Send a raw JSON-RPC request and return the response body unparsed, for results too large
+    /// to go through a `Value`. JSON-RPC errors fail the call, like they do `send`.
+    fn send_raw(&self, request: RpcRequest, params: Value) -> ClientResult<Vec<u8>> {
+        let result = self.send(request, params)?;
+
+        Ok(serde_json::to_vec(
+            &json!({ "jsonrpc": "2.0", "id": 1, "result": result }),
+        )?)
+    }
+
+    fn get_genesis_hash(&self) -> ClientResult<Hash> {
+        let hash = decode::<String>(self.send(RpcRequest::GetGenesisHash, json!([]))?)?;
+        parse_hash(&hash)
+    }
+
+    fn get_epoch_schedule(&self) -> ClientResult<EpochSchedule> {
+        decode(self.send(RpcRequest::GetEpochSchedule, json!([]))?)
+    }
+
+    fn get_epoch_info(&self) -> ClientResult<EpochInfo> {
+        decode(self.send(
+            RpcRequest::GetEpochInfo,
+            json!([CommitmentConfig::confirmed()]),
+        )?)
+    }
+
+    fn get_latest_blockhash(&self) -> ClientResult<Hash> {
+        let response = decode::<Response<RpcBlockhash>>(self.send(
+            RpcRequest::GetLatestBlockhash,
+            json!([CommitmentConfig::confirmed()]),
+        )?)?;
+        parse_hash(&response.value.blockhash)
+    }
+
+    fn send_and_confirm_transaction(&self, transaction: &Transaction) -> ClientResult<Signature> {
+        Err(ClientErrorKind::Custom(format!(
+            "{} can't send transaction {}",
+            self.url(),
+            transaction.signatures[0]
+        ))
+        .into())
+    }
+
+    fn get_signatures_for_address_with_config(
+        &self,
+        address: &Pubkey,
+        config: GetConfirmedSignaturesForAddress2Config,
+    ) -> ClientResult<Vec<RpcConfirmedTransactionStatusWithSignature>> {
+        let config = RpcSignaturesForAddressConfig {
+            before: config.before.map(|signature| signature.to_string()),
+            until: config.until.map(|signature| signature.to_string()),
+            limit: config.limit,
+            commitment: config.commitment,
+            min_context_slot: None,
+        };
+
+        decode(self.send(
+            RpcRequest::GetSignaturesForAddress,
+            json!([address.to_string(), config]),
+        )?)
+    }
+
+    fn get_signature_statuses_with_history(
+        &self,
+        signatures: &[Signature],
+    ) -> ClientResult<Response<Vec<Option<TransactionStatus>>>> {
+        let signatures = signatures
+            .iter()
+            .map(Signature::to_string)
+            .collect::<Vec<_>>();
+        let config = RpcSignatureStatusConfig {
+            search_transaction_history: true,
+        };
+
+        decode(self.send(
+            RpcRequest::GetSignatureStatuses,
+            json!([signatures, config]),
+        )?)
+    }
+
+    fn get_slot_with_commitment(&self, commitment: CommitmentConfig) -> ClientResult<u64> {
+        decode(self.send(RpcRequest::GetSlot, json!([commitment]))?)
+    }
+
+    fn get_transaction_with_config(
+        &self,
+        signature: &Signature,
+        config: RpcTransactionConfig,
+    ) -> ClientResult<EncodedConfirmedTransactionWithStatusMeta> {
+        decode(self.send(
+            RpcRequest::GetTransaction,
+            json!([signature.to_string(), config]),
+        )?)
+    }
+
+    fn get_account_with_commitment(
+        &self,
+        address: &Pubkey,
+        commitment: CommitmentConfig,
+    ) -> ClientResult<Response<Option<Account>>> {
+        let config = RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            commitment: Some(commitment),
+            ..RpcAccountInfoConfig::default()
+        };
+        let response = decode::<Response<Option<UiAccount>>>(self.send(
+            RpcRequest::GetAccountInfo,
+            json!([address.to_string(), config]),
+        )?)?;
+
+        Ok(Response {
+            context: response.context,
+            value: response.value.as_ref().and_then(UiAccount::decode),
+        })
+    }
+
+    fn get_balance(&self, address: &Pubkey) -> ClientResult<u64> {
+        let response = decode::<Response<u64>>(self.send(
+            RpcRequest::GetBalance,
+            json!([address.to_string(), CommitmentConfig::confirmed()]),
+        )?)?;
+
+        Ok(response.value)
+    }
+
+    fn get_token_accounts_by_owner(
+        &self,
+        owner: &Pubkey,
+        filter: TokenAccountsFilter,
+    ) -> ClientResult<Vec<RpcKeyedAccount>> {
+        let filter = match filter {
+            TokenAccountsFilter::Mint(mint) => RpcTokenAccountsFilter::Mint(mint.to_string()),
+            TokenAccountsFilter::ProgramId(program) => {
+                RpcTokenAccountsFilter::ProgramId(program.to_string())
+            }
+        };
+        let config = RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::JsonParsed),
+            commitment: Some(CommitmentConfig::confirmed()),
+            ..RpcAccountInfoConfig::default()
+        };
+        let response = decode::<Response<Vec<RpcKeyedAccount>>>(self.send(
+            RpcRequest::GetTokenAccountsByOwner,
+            json!([owner.to_string(), filter, config]),
+        )?)?;
+
+        Ok(response.value)
+    }
+
+    fn get_program_accounts_with_config(
+        &self,
+        program: &Pubkey,
+        mut config: RpcProgramAccountsConfig,
+    ) -> ClientResult<Vec<(Pubkey, Account)>> {
+        config.account_config.encoding = config
+            .account_config
+            .encoding
+            .or(Some(UiAccountEncoding::Base64));
+        config.account_config.commitment = config
+            .account_config
+            .commitment
+            .or(Some(CommitmentConfig::confirmed()));
+        config.with_context = Some(false);
+
+        let keyed = decode::<Vec<RpcKeyedAccount>>(self.send(
+            RpcRequest::GetProgramAccounts,
+            json!([program.to_string(), config]),
+        )?)?;
+
+        keyed
+            .into_iter()
+            .map(|keyed| {
+                let pubkey = Pubkey::from_str(&keyed.pubkey).map_err(|_| {
+                    ClientErrorKind::Custom(format!("Invalid pubkey {}", keyed.pubkey))
+                })?;
+                let account = keyed.account.decode().ok_or_else(|| {
+                    ClientErrorKind::Custom(format!("Undecodable account {pubkey}"))
+                })?;
+
+                Ok((pubkey, account))
+            })
+            .collect()
+    }
+
+    fn get_multiple_accounts(&self, addresses: &[Pubkey]) -> ClientResult<Vec<Option<Account>>> {
+        let addresses = addresses.iter().map(Pubkey::to_string).collect::<Vec<_>>();
+        let config = RpcAccountInfoConfig {
+            encoding: Some(UiAccountEncoding::Base64),
+            commitment: Some(CommitmentConfig::confirmed()),
+            ..RpcAccountInfoConfig::default()
+        };
+        let response = decode::<Response<Vec<Option<UiAccount>>>>(
+            self.send(RpcRequest::GetMultipleAccounts, json!([addresses, config]))?,
+        )?;
+
+        Ok(response
+            .value
+            .iter()
+            .map(|account| account.as_ref().and_then(UiAccount::decode))
+            .collect())
+    }
+}
+
+/// A JSON-RPC response, with its result left unparsed.
+#[derive(Debug, Deserialize)]
+struct RawResponse<'a> {
+    #[serde(borrow)]
+    result: Option<&'a RawValue>,
+    error: Option<RawResponseError>,
+}
+
+#[derive(Debug, Deserialize)]
+struct RawResponseError {
+    code: i64,
+    message: String,
+}
+
+/// The unparsed result of the JSON-RPC response `body`, `None` if it's `null`.
+pub fn raw_result(body: &[u8]) -> ClientResult<Option<&RawValue>> {
+    let response: RawResponse = serde_json::from_slice(body)?;
+
+    match response.error {
+        Some(RawResponseError { code, message }) => {
+            Err(ClientErrorKind::RpcError(RpcError::RpcResponseError {
+                code,
+                message,
+                data: RpcResponseErrorData::Empty,
+            })
+            .into())
+        }
+        None => Ok(response.result),
+    }
+}
+
+/// HTTP client of the raw requests to every endpoint.
+fn http() -> &'static reqwest::Client {
+    static HTTP: OnceLock<reqwest::Client> = OnceLock::new();
+
+    HTTP.get_or_init(reqwest::Client::new)
+}
+
+fn decode<T: serde::de::DeserializeOwned>(result: Value) -> ClientResult<T> {
+    Ok(serde_json::from_value(result)?)
+}
+
+fn parse_hash(hash: &str) -> ClientResult<Hash> {
+    Hash::from_str(hash).map_err(|_| ClientErrorKind::Custom(format!("Invalid hash {hash}")).into())
+}
+
+impl RpcApi for RpcClient {
+    fn url(&self) -> String {
+        RpcClient::url(self)
+    }
+
+    fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        RpcClient::send(self, request, params)
+    }
+
+    fn send_raw(&self, request: RpcRequest, params: Value) -> ClientResult<Vec<u8>> {
+        let request = request.build_request_json(1, params);
+        let url = RpcClient::url(self);
+
+        let body = task::block_in_place(|| {
+            Handle::current().block_on(async {
+                http()
+                    .post(url)
+                    .json(&request)
+                    .timeout(RAW_REQUEST_TIMEOUT)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .bytes()
+                    .await
+            })
+        })?;
+
+        raw_result(&body)?;
+
+        Ok(body.to_vec())
+    }
+
+    fn get_genesis_hash(&self) -> ClientResult<Hash> {
+        RpcClient::get_genesis_hash(self)
+    }
+
+    fn get_epoch_schedule(&self) -> ClientResult<EpochSchedule> {
+        RpcClient::get_epoch_schedule(self)
+    }
+
+    fn get_epoch_info(&self) -> ClientResult<EpochInfo> {
+        RpcClient::get_epoch_info(self)
+    }
+
+    fn get_latest_blockhash(&self) -> ClientResult<Hash> {
+        RpcClient::get_latest_blockhash(self)
+    }
+
+    fn send_and_confirm_transaction(&self, transaction: &Transaction) -> ClientResult<Signature> {
+        RpcClient::send_and_confirm_transaction(self, transaction)
+    }
+
+    fn get_signatures_for_address_with_config(
+        &self,
+        address: &Pubkey,
+        config: GetConfirmedSignaturesForAddress2Config,
+    ) -> ClientResult<Vec<RpcConfirmedTransactionStatusWithSignature>> {
+        RpcClient::get_signatures_for_address_with_config(self, address, config)
+    }
+
+    fn get_signature_statuses_with_history(
+        &self,
+        signatures: &[Signature],
+    ) -> ClientResult<Response<Vec<Option<TransactionStatus>>>> {
+        RpcClient::get_signature_statuses_with_history(self, signatures)
+    }
+
+    fn get_slot_with_commitment(&self, commitment: CommitmentConfig) -> ClientResult<u64> {
+        RpcClient::get_slot_with_commitment(self, commitment)
+    }
+
+    fn get_transaction_with_config(
+        &self,
+        signature: &Signature,
+        config: RpcTransactionConfig,
+    ) -> ClientResult<EncodedConfirmedTransactionWithStatusMeta> {
+        RpcClient::get_transaction_with_config(self, signature, config)
+    }
+
+    fn get_account_with_commitment(
+        &self,
+        address: &Pubkey,
+        commitment: CommitmentConfig,
+    ) -> ClientResult<Response<Option<Account>>> {
+        RpcClient::get_account_with_commitment(self, address, commitment)
+    }
+
+    fn get_balance(&self, address: &Pubkey) -> ClientResult<u64> {
+        RpcClient::get_balance(self, address)
+    }
+
+    fn get_token_accounts_by_owner(
+        &self,
+        owner: &Pubkey,
+        filter: TokenAccountsFilter,
+    ) -> ClientResult<Vec<RpcKeyedAccount>> {
+        RpcClient::get_token_accounts_by_owner(self, owner, filter)
+    }
+
+    fn get_program_accounts_with_config(
+        &self,
+        program: &Pubkey,
+        config: RpcProgramAccountsConfig,
+    ) -> ClientResult<Vec<(Pubkey, Account)>> {
+        RpcClient::get_program_accounts_with_config(self, program, config)
+    }
+
+    fn get_multiple_accounts(&self, addresses: &[Pubkey]) -> ClientResult<Vec<Option<Account>>> {
+        RpcClient::get_multiple_accounts(self, addresses)
+    }
+}
+
+/// Clients of the RPC endpoints, in order, at `confirmed` commitment.
+pub fn connect(rpc_urls: &[String]) -> Vec<Box<dyn RpcApi>> {
+    rpc_urls
+        .iter()
+        .map(|url| Box::new(client(url)) as Box<dyn RpcApi>)
+        .collect()
+}
+
+/// Like `connect`, recording every response of every endpoint to a fixture file at `path`.
+pub fn connect_recording(rpc_urls: &[String], path: &Path) -> Vec<Box<dyn RpcApi>> {
+    let recording = Arc::new(Recording {
+        path: path.to_path_buf(),
+        exchanges: Mutex::new(Vec::new()),
+    });
+
+    rpc_urls
+        .iter()
+        .map(|url| {
+            Box::new(RecordingRpc {
+                client: client(url),
+                recording: Arc::clone(&recording),
+            }) as Box<dyn RpcApi>
+        })
+        .collect()
+}
+
+fn client(url: &str) -> RpcClient {
+    RpcClient::new_with_commitment(url.to_string(), CommitmentConfig::confirmed())
+}
+
+/// A JSON-RPC request and the response it got.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Exchange {
+    pub method: String,
+    /// Matches the params of any call when left out.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub params: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub result: Option<Value>,
+    /// Replayed as a failed call when set.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl Exchange {
+    fn answers(&self, method: &str, params: &Value) -> bool {
+        self.method == method
+            && self
+                .params
+                .as_ref()
+                .map_or(true, |recorded| contains(params, recorded))
+    }
+}
+
+/// Whether `params` contain everything in `recorded`: the same values, with arrays compared by
+/// their first elements and objects by the keys of `recorded`.
+fn contains(params: &Value, recorded: &Value) -> bool {
+    match (params, recorded) {
+        (Value::Array(params), Value::Array(recorded)) => {
+            recorded.len() <= params.len()
+                && params
+                    .iter()
+                    .zip(recorded)
+                    .all(|(param, recorded)| contains(param, recorded))
+        }
+        (Value::Object(params), Value::Object(recorded)) => {
+            recorded.iter().all(|(key, recorded)| {
+                params
+                    .get(key)
+                    .is_some_and(|param| contains(param, recorded))
+            })
+        }
+        _ => params == recorded,
+    }
+}
+
+/// An RPC endpoint answering from recorded exchanges instead of a node.
+pub struct FixtureRpc {
+    name: String,
+    exchanges: Vec<Exchange>,
+}
+
+impl FixtureRpc {
+    pub fn new(name: &str, exchanges: Vec<Exchange>) -> Self {
+        FixtureRpc {
+            name: name.to_string(),
+            exchanges,
+        }
+    }
+
+    /// Replay the exchanges of a fixture file.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let exchanges = serde_json::from_slice(&fs::read(path)?)
+            .map_err(|e| anyhow::anyhow!("Invalid RPC fixture `{}`: {e}", path.display()))?;
+
+        Ok(Self::new(&path.display().to_string(), exchanges))
+    }
+}
+
+impl RpcApi for FixtureRpc {
+    fn url(&self) -> String {
+        format!("fixture:{}", self.name)
+    }
+
+    fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        let method = request.to_string();
+
+        let Some(exchange) = self
+            .exchanges
+            .iter()
+            .find(|exchange| exchange.answers(&method, &params))
+        else {
+            return Err(ClientErrorKind::Custom(format!(
+                "No recorded response to {method} {params}"
+            ))
+            .into());
+        };
+
+        match &exchange.error {
+            Some(error) => Err(ClientErrorKind::Custom(error.clone()).into()),
+            None => Ok(exchange.result.clone().unwrap_or(Value::Null)),
+        }
+    }
+}
+
+/// Exchanges recorded so far, shared by the endpoints recording to the same file.
+struct Recording {
+    path: PathBuf,
+    exchanges: Mutex<Vec<Exchange>>,
+}
+
+impl Recording {
+    /// Record an exchange and rewrite the file, logging failures: recording never fails a call.
+    fn record(&self, exchange: Exchange) {
+        let mut exchanges = self.exchanges.lock().expect("RPC recording lock poisoned");
+        exchanges.push(exchange);
+
+        let written = serde_json::to_vec_pretty(&*exchanges)
+            .map_err(anyhow::Error::from)
+            .and_then(|json| Ok(fs::write(&self.path, json)?));
+
+        if let Err(e) = written {
+            warn!(
+                "Failed to record RPC responses to {}: {e:?}",
+                self.path.display()
+            );
+        }
+    }
+}
+
+/// An RPC endpoint recording the responses of a real node as it forwards calls to it.
+pub struct RecordingRpc {
+    client: RpcClient,
+    recording: Arc<Recording>,
+}
+
+impl RpcApi for RecordingRpc {
+    fn url(&self) -> String {
+        self.client.url()
+    }
+
+    fn send(&self, request: RpcRequest, params: Value) -> ClientResult<Value> {
+        let result = RpcClient::send::<Value>(&self.client, request, params.clone());
+
+        self.recording.record(Exchange {
+            method: request.to_string(),
+            params: Some(params),
+            result: result.as_ref().ok().cloned(),
+            error: result.as_ref().err().map(ToString::to_string),
+        });
+
+        result
+    }
+
+    fn send_and_confirm_transaction(&self, transaction: &Transaction) -> ClientResult<Signature> {
+        self.client.send_and_confirm_transaction(transaction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn exchange(params: Option<Value>, result: Option<Value>, error: Option<&str>) -> Exchange {
+        Exchange {
+            method: "getBalance".to_string(),
+            params,
+            result,
+            error: error.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn test_fixture_matches_params_and_replays_errors() {
+        let [known, unknown] = [(); 2].map(|_| Pubkey::new_unique());
+        let rpc = FixtureRpc::new(
+            "balances",
+            vec![
+                exchange(
+                    Some(json!([known.to_string()])),
+                    Some(json!({ "context": { "slot": 42 }, "value": 5000 })),
+                    None,
+                ),
+                exchange(None, None, Some("Node is behind by 120 slots")),
+            ],
+        );
+
+        // recorded params match calls whose params contain them
+        assert_eq!(rpc.get_balance(&known).unwrap(), 5000);
+        assert!(rpc
+            .get_balance(&unknown)
+            .unwrap_err()
+            .to_string()
+            .contains("Node is behind"));
+        assert!(rpc.get_epoch_info().is_err());
+
+        assert!(contains(
+            &json!([{ "a": 1, "b": [1, 2] }, 3]),
+            &json!([{ "b": [1] }])
+        ));
+        assert!(!contains(&json!([{ "a": 1 }]), &json!([{ "a": 2 }])));
+    }
+}
@@ -0,0 +1,368 @@
+// Serves a safe subset of Solana JSON-RPC to API consumers, from the aggregator's own store first
+
+// Responsibilities:
+// * Answer `getTransaction` and `getSignaturesForAddress` requests at `POST /rpc`, so consumers
+//   can point an RPC client at the aggregator and share its RPC provider and cache.
+// * Refuse every other method, so the proxy can't be used to send transactions or run expensive
+//   scans on the provider's bill.
+
+// Implementation:
+// * `getTransaction` with `jsonParsed` encoding is served from the raw archive when the
+//   transaction is archived: the archive keeps the very `getTransaction` response it was stored
+//   from.
+// * Other answers are cached in memory by method and params, up to `max_cached_responses` with the
+//   oldest evicted first. Transactions don't change once confirmed, so they're kept until evicted;
+//   signature lists grow with every new transaction, so they expire after
+//   `signatures_ttl_secs`. `null` results (transactions not found yet) aren't cached.
+// * Misses go upstream through the `SolanaClient`, with its fallbacks, retries and rate limits.
+//   Upstream JSON-RPC errors are passed on with their code and message.
+// * Batches are answered one request at a time, in order.
+
+use crate::{archive::RawArchive, config::RpcProxyConfig, data_retrieval::SolanaClient};
+
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use solana_client::{
+    client_error::{ClientError, ClientErrorKind},
+    rpc_request::{RpcError, RpcRequest},
+};
+use sqlx::PgPool;
+use tokio::time::{Duration, Instant};
+
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INTERNAL_ERROR: i64 = -32603;
+
+/// A single JSON-RPC request.
+#[derive(Debug, Deserialize)]
+struct RpcCall {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// A JSON-RPC error.
+#[derive(Debug)]
+struct RpcFault {
+    code: i64,
+    message: String,
+}
+
+impl RpcFault {
+    fn from_upstream(error: &anyhow::Error) -> Self {
+        match error.downcast_ref::<ClientError>().map(ClientError::kind) {
+            Some(ClientErrorKind::RpcError(RpcError::RpcResponseError {
+                code, message, ..
+            })) => RpcFault {
+                code: *code,
+                message: message.clone(),
+            },
+            _ => RpcFault {
+                code: INTERNAL_ERROR,
+                message: "Upstream RPC request failed".to_string(),
+            },
+        }
+    }
+}
+
+fn error_response(id: Value, code: i64, message: &str) -> Value {
+    json!({
+        "jsonrpc": "2.0",
+        "id": id,
+        "error": { "code": code, "message": message },
+    })
+}
+
+#[derive(Debug)]
+struct CachedResponse {
+    result: Value,
+    /// `None` for responses kept until evicted.
+    expires_at: Option<Instant>,
+}
+
+/// Responses by method and params, evicting the oldest first.
+#[derive(Debug)]
+struct ResponseCache {
+    capacity: usize,
+    entries: HashMap<String, CachedResponse>,
+    /// Keys of `entries`, oldest first.
+    order: VecDeque<String>,
+}
+
+impl ResponseCache {
+    fn new(capacity: usize) -> Self {
+        ResponseCache {
+            capacity,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+        }
+    }
+
+    fn get(&self, key: &str, now: Instant) -> Option<Value> {
+        self.entries
+            .get(key)
+            .filter(|cached| {
+                cached
+                    .expires_at
+                    .map_or(true, |expires_at| expires_at > now)
+            })
+            .map(|cached| cached.result.clone())
+    }
+
+    fn insert(&mut self, key: String, result: Value, expires_at: Option<Instant>) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        if !self.entries.contains_key(&key) {
+            while self.entries.len() >= self.capacity {
+                let Some(oldest) = self.order.pop_front() else {
+                    break;
+                };
+
+                self.entries.remove(&oldest);
+            }
+
+            self.order.push_back(key.clone());
+        }
+
+        self.entries
+            .insert(key, CachedResponse { result, expires_at });
+    }
+}
+
+/// Where the proxy's answers came from since startup.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct ProxyReport {
+    pub archive_hits: u64,
+    pub cache_hits: u64,
+    pub upstream_calls: u64,
+    pub upstream_errors: u64,
+    /// Requests for methods that aren't proxied.
+    pub rejected: u64,
+    pub cached_responses: usize,
+}
+
+pub struct RpcProxy {
+    solana_client: Arc<SolanaClient>,
+    /// Only set with Postgres storage.
+    archive: Option<(Arc<PgPool>, Arc<RawArchive>)>,
+    signatures_ttl: Duration,
+    cache: Mutex<ResponseCache>,
+    report: Mutex<ProxyReport>,
+}
+
+impl RpcProxy {
+    pub fn new(
+        config: &RpcProxyConfig,
+        solana_client: Arc<SolanaClient>,
+        archive: Option<(Arc<PgPool>, Arc<RawArchive>)>,
+    ) -> Self {
+        RpcProxy {
+            solana_client,
+            archive,
+            signatures_ttl: Duration::from_secs(config.signatures_ttl_secs),
+            cache: Mutex::new(ResponseCache::new(config.max_cached_responses)),
+            report: Mutex::new(ProxyReport::default()),
+        }
+    }
+
+    /// Answer a JSON-RPC request body, a single request or a batch.
+    pub async fn handle(&self, body: &[u8]) -> Value {
+        match serde_json::from_slice::<Value>(body) {
+            Ok(Value::Array(calls)) if !calls.is_empty() => {
+                let mut responses = Vec::with_capacity(calls.len());
+
+                for call in calls {
+                    responses.push(self.call(call).await);
+                }
+
+                Value::Array(responses)
+            }
+            Ok(call) => self.call(call).await,
+            Err(_) => error_response(Value::Null, PARSE_ERROR, "Parse error"),
+        }
+    }
+
+    async fn call(&self, call: Value) -> Value {
+        let Ok(call) = serde_json::from_value::<RpcCall>(call) else {
+            return error_response(Value::Null, INVALID_REQUEST, "Invalid request");
+        };
+
+        match self.dispatch(&call.method, call.params).await {
+            Ok(result) => json!({ "jsonrpc": "2.0", "id": call.id, "result": result }),
+            Err(fault) => error_response(call.id, fault.code, &fault.message),
+        }
+    }
+
+    async fn dispatch(&self, method: &str, params: Value) -> Result<Value, RpcFault> {
+        match method {
+            "getTransaction" => {
+                if let Some(archived) = self.archived_transaction(&params).await {
+                    self.count(|report| report.archive_hits += 1);
+                    return Ok(archived);
+                }
+
+                self.cached(RpcRequest::GetTransaction, params, None).await
+            }
+            "getSignaturesForAddress" => {
+                self.cached(
+                    RpcRequest::GetSignaturesForAddress,
+                    params,
+                    Some(self.signatures_ttl),
+                )
+                .await
+            }
+            _ => {
+                self.count(|report| report.rejected += 1);
+
+                Err(RpcFault {
+                    code: METHOD_NOT_FOUND,
+                    message: format!("Method not found or not proxied: {method}"),
+                })
+            }
+        }
+    }
+
+    /// The archived response to a `getTransaction` request, if it asks for the encoding the
+    /// archive keeps and the transaction is archived.
+    async fn archived_transaction(&self, params: &Value) -> Option<Value> {
+        let (db, archive) = self.archive.as_ref()?;
+        let signature = archived_signature(params)?;
+
+        match archive.fetch(db, signature).await {
+            Ok(json) => serde_json::from_slice(&json?).ok(),
+            Err(e) => {
+                warn!("Failed to read archived transaction {signature}: {e:?}");
+                None
+            }
+        }
+    }
+
+    /// Answer from the cache, or from upstream, caching the result for `ttl` (`None` to keep it
+    /// until evicted).
+    async fn cached(
+        &self,
+        request: RpcRequest,
+        params: Value,
+        ttl: Option<Duration>,
+    ) -> Result<Value, RpcFault> {
+        let key = format!("{request}:{params}");
+        let cached = self
+            .cache
+            .lock()
+            .expect("rpc proxy cache lock poisoned")
+            .get(&key, Instant::now());
+
+        if let Some(result) = cached {
+            self.count(|report| report.cache_hits += 1);
+            return Ok(result);
+        }
+
+        self.count(|report| report.upstream_calls += 1);
+
+        let result = self
+            .solana_client
+            .forward(request, params)
+            .await
+            .map_err(|e| {
+                self.count(|report| report.upstream_errors += 1);
+                RpcFault::from_upstream(&e)
+            })?;
+
+        if !result.is_null() {
+            self.cache
+                .lock()
+                .expect("rpc proxy cache lock poisoned")
+                .insert(key, result.clone(), ttl.map(|ttl| Instant::now() + ttl));
+        }
+
+        Ok(result)
+    }
+
+    fn count(&self, update: impl FnOnce(&mut ProxyReport)) {
+        update(&mut self.report.lock().expect("rpc proxy report lock poisoned"));
+    }
+
+    pub fn report(&self) -> ProxyReport {
+        let cached_responses = self
+            .cache
+            .lock()
+            .expect("rpc proxy cache lock poisoned")
+            .entries
+            .len();
+
+        ProxyReport {
+            cached_responses,
+            ..*self.report.lock().expect("rpc proxy report lock poisoned")
+        }
+    }
+}
+
+/// The signature of a `getTransaction` request whose response the archive can stand in for:
+/// those asking for `jsonParsed`, the encoding transactions are fetched and archived in.
+fn archived_signature(params: &Value) -> Option<&str> {
+    let signature = params.get(0)?.as_str()?;
+
+    let encoding = match params.get(1) {
+        // the deprecated form passes only the encoding
+        Some(Value::String(encoding)) => encoding.as_str(),
+        Some(config) => config.get("encoding")?.as_str()?,
+        None => return None,
+    };
+
+    (encoding == "jsonParsed").then_some(signature)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_archived_signature() {
+        let params = json!(["5sig", { "encoding": "jsonParsed", "commitment": "finalized" }]);
+        assert_eq!(archived_signature(&params), Some("5sig"));
+        assert_eq!(
+            archived_signature(&json!(["5sig", "jsonParsed"])),
+            Some("5sig")
+        );
+
+        // the default encoding is `json`, which the archive doesn't keep
+        assert_eq!(archived_signature(&json!(["5sig"])), None);
+        assert_eq!(
+            archived_signature(&json!(["5sig", { "encoding": "base64" }])),
+            None
+        );
+    }
+
+    #[test]
+    fn test_cache_expires_and_evicts_oldest() {
+        let mut cache = ResponseCache::new(2);
+        let now = Instant::now();
+
+        cache.insert("a".to_string(), json!(1), None);
+        cache.insert(
+            "b".to_string(),
+            json!(2),
+            Some(now + Duration::from_secs(5)),
+        );
+        assert_eq!(cache.get("b", now), Some(json!(2)));
+        assert_eq!(cache.get("b", now + Duration::from_secs(5)), None);
+
+        // replacing an entry keeps its place
+        cache.insert("a".to_string(), json!(3), None);
+        cache.insert("c".to_string(), json!(4), None);
+        assert_eq!(cache.get("a", now), None);
+        assert_eq!(cache.get("b", now), Some(json!(2)));
+        assert_eq!(cache.get("c", now), Some(json!(4)));
+    }
+}
@@ -0,0 +1,255 @@
+// Tracks the background jobs and runs them on demand
+
+// Responsibilities:
+// * Keep a registry of the recurring background jobs (polling, rollups, expiry, maintenance...).
+// * Record when each job last ran, how long it took, whether it succeeded and when it runs next.
+// * Let operators run a job immediately instead of waiting for its next scheduled run.
+
+// Implementation:
+// * Every job's loop waits through its `Job`, which wakes it when the next run is due, when it
+//   is triggered, or on shutdown.
+// * Periodic jobs run `every` after the previous run started, or right after it if it overran.
+// * A trigger that arrives while the job is running is kept, and starts another run as soon as
+//   the current one finishes.
+
+use log::warn;
+use serde::Serialize;
+use tokio::{
+    sync::Notify,
+    time::{self, Duration, Instant},
+};
+use tokio_util::sync::CancellationToken;
+
+use std::{
+    collections::BTreeMap,
+    future::Future,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Outcome of the last run of a job.
+#[derive(Debug, Clone, Serialize)]
+pub struct JobRun {
+    /// Unix seconds.
+    pub started_at: u64,
+    pub duration_ms: u64,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct JobReport {
+    pub name: String,
+    /// `None` for jobs that run at fixed times of day.
+    pub every_secs: Option<u64>,
+    pub running: bool,
+    /// Unix seconds; `None` while running or before the job first waits.
+    pub next_run_at: Option<u64>,
+    pub last_run: Option<JobRun>,
+    /// Runs and failed runs since startup.
+    pub runs: u64,
+    pub failures: u64,
+}
+
+#[derive(Debug, Default)]
+struct JobState {
+    running: bool,
+    next_run_at: Option<u64>,
+    last_started: Option<Instant>,
+    last_run: Option<JobRun>,
+    runs: u64,
+    failures: u64,
+}
+
+#[derive(Debug)]
+pub struct Job {
+    name: String,
+    every: Option<Duration>,
+    state: Mutex<JobState>,
+    trigger: Notify,
+}
+
+impl Job {
+    /// Wait until the periodic job is next due, or until it is triggered. The first run is due
+    /// right away. Returns `false` once shut down.
+    pub async fn tick(&self, shutdown: &CancellationToken) -> bool {
+        let delay = {
+            let state = self.state.lock().expect("job state lock poisoned");
+
+            match (state.last_started, self.every) {
+                (Some(last_started), Some(every)) => {
+                    (last_started + every).saturating_duration_since(Instant::now())
+                }
+                _ => Duration::ZERO,
+            }
+        };
+
+        self.wait(delay, shutdown).await
+    }
+
+    /// Wait for `delay`, or until the job is triggered. Returns `false` once shut down.
+    pub async fn wait(&self, delay: Duration, shutdown: &CancellationToken) -> bool {
+        self.state
+            .lock()
+            .expect("job state lock poisoned")
+            .next_run_at = Some(unix_now() + delay.as_secs());
+
+        tokio::select! {
+            _ = shutdown.cancelled() => false,
+            _ = time::sleep(delay) => true,
+            _ = self.trigger.notified() => true,
+        }
+    }
+
+    /// Run one iteration of the job, recording its duration and outcome.
+    pub async fn run<T, F>(&self, iteration: F) -> anyhow::Result<T>
+    where
+        F: Future<Output = anyhow::Result<T>>,
+    {
+        let started_at = unix_now();
+        let started = Instant::now();
+
+        {
+            let mut state = self.state.lock().expect("job state lock poisoned");
+            state.running = true;
+            state.next_run_at = None;
+            state.last_started = Some(started);
+        }
+
+        let result = iteration.await;
+
+        let mut state = self.state.lock().expect("job state lock poisoned");
+        state.running = false;
+        state.runs += 1;
+        state.failures += u64::from(result.is_err());
+        state.last_run = Some(JobRun {
+            started_at,
+            duration_ms: started.elapsed().as_millis() as u64,
+            success: result.is_ok(),
+            error: result.as_ref().err().map(|e| format!("{e:#}")),
+        });
+
+        result
+    }
+
+    fn report(&self) -> JobReport {
+        let state = self.state.lock().expect("job state lock poisoned");
+
+        JobReport {
+            name: self.name.clone(),
+            every_secs: self.every.map(|every| every.as_secs()),
+            running: state.running,
+            next_run_at: state.next_run_at,
+            last_run: state.last_run.clone(),
+            runs: state.runs,
+            failures: state.failures,
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Schedules {
+    jobs: Mutex<BTreeMap<String, Arc<Job>>>,
+}
+
+impl Schedules {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Register a job running every `every`, or at times of its own choosing if `None`.
+    pub fn register(&self, name: &str, every: Option<Duration>) -> Arc<Job> {
+        let job = Arc::new(Job {
+            name: name.to_string(),
+            every,
+            state: Mutex::new(JobState::default()),
+            trigger: Notify::new(),
+        });
+
+        let replaced = self
+            .jobs
+            .lock()
+            .expect("schedules lock poisoned")
+            .insert(name.to_string(), Arc::clone(&job));
+
+        if replaced.is_some() {
+            warn!("Background job {name} was registered twice");
+        }
+
+        job
+    }
+
+    /// Run a job as soon as possible. Returns `false` if there is no such job.
+    pub fn trigger(&self, name: &str) -> bool {
+        let jobs = self.jobs.lock().expect("schedules lock poisoned");
+
+        match jobs.get(name) {
+            Some(job) => {
+                job.trigger.notify_one();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The registered jobs, by name.
+    pub fn report(&self) -> Vec<JobReport> {
+        self.jobs
+            .lock()
+            .expect("schedules lock poisoned")
+            .values()
+            .map(|job| job.report())
+            .collect()
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_runs_are_recorded() {
+        let schedules = Schedules::new();
+        let job = schedules.register("rollups", Some(Duration::from_secs(60)));
+
+        job.run(async { Ok(()) }).await.unwrap();
+        let failed: anyhow::Result<()> = job.run(async { anyhow::bail!("database is down") }).await;
+        assert!(failed.is_err());
+
+        let report = schedules.report();
+        assert_eq!(report.len(), 1);
+        assert_eq!((report[0].runs, report[0].failures), (2, 1));
+        assert_eq!(report[0].every_secs, Some(60));
+        assert!(!report[0].running);
+
+        let last_run = report[0].last_run.as_ref().unwrap();
+        assert!(!last_run.success);
+        assert_eq!(last_run.error.as_deref(), Some("database is down"));
+    }
+
+    #[tokio::test]
+    async fn test_trigger_wakes_job() {
+        let schedules = Schedules::new();
+        let job = schedules.register("monitor", Some(Duration::from_secs(3600)));
+        let shutdown = CancellationToken::new();
+
+        assert!(job.tick(&shutdown).await);
+        job.run(async { Ok(()) }).await.unwrap();
+
+        // the next run is an hour away, but a trigger starts it right away
+        assert!(schedules.trigger("monitor"));
+        assert!(!schedules.trigger("unknown"));
+        assert!(time::timeout(Duration::from_secs(1), job.tick(&shutdown))
+            .await
+            .unwrap());
+
+        shutdown.cancel();
+        assert!(!job.tick(&shutdown).await);
+    }
+}
@@ -0,0 +1,159 @@
+// Search across the stored transactions, addresses and labels
+
+// Responsibilities:
+// * Back a single search box over the whole dataset: transactions by signature or memo text,
+//   addresses by public key or prefix, and labelled addresses by label.
+
+// Implementation:
+// * The query's shape decides what is searched. A full signature is looked up by signature and a
+//   full public key is answered as an address. Any other query is matched against memos and
+//   labels as case-insensitive text and, if it could be the start of a public key, against the
+//   start of the addresses in the stored transactions.
+// * Every search is an indexed query: memos and labels have trigram indexes (`pg_trgm`) and
+//   addresses a pattern index on `address_stats`. Trigrams can't narrow down queries shorter
+//   than `MIN_QUERY` characters, so those are rejected.
+// * Each kind of result is capped at the same limit. Transactions dropped by a fork aren't found
+//   by their memo.
+
+use crate::{
+    data_storage::{
+        search_addresses, search_labels, search_memos, AddressMatch, Storage, StoredTransaction,
+    },
+    labels::Label,
+};
+
+use serde::Serialize;
+use solana_sdk::{pubkey::Pubkey, signature::Signature};
+use sqlx::PgPool;
+
+use std::{str::FromStr, sync::Arc};
+
+/// Characters a query needs at least.
+pub const MIN_QUERY: usize = 3;
+
+/// Characters an address prefix needs at least.
+const MIN_PREFIX: usize = 4;
+
+const BASE58_ALPHABET: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+/// What a query looks like, and so what it is searched for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QueryShape {
+    Signature,
+    Address,
+    /// Text that could also be the start of an address.
+    AddressPrefix,
+    Text,
+}
+
+impl QueryShape {
+    pub fn of(query: &str) -> Self {
+        if Signature::from_str(query).is_ok() {
+            QueryShape::Signature
+        } else if Pubkey::from_str(query).is_ok() {
+            QueryShape::Address
+        } else if query.chars().count() >= MIN_PREFIX
+            && query.chars().all(|c| BASE58_ALPHABET.contains(c))
+        {
+            QueryShape::AddressPrefix
+        } else {
+            QueryShape::Text
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct SearchResults {
+    pub shape: QueryShape,
+    /// The transaction with the searched signature, if stored.
+    pub transaction: Option<StoredTransaction>,
+    /// Addresses, most transactions first.
+    pub addresses: Vec<AddressMatch>,
+    /// Labels, most similar first.
+    pub labels: Vec<Label>,
+    /// Transactions whose memo contains the query, newest first.
+    pub memos: Vec<StoredTransaction>,
+}
+
+/// Search for `query`, finding at most `limit` results of each kind.
+pub async fn search(db: &Arc<PgPool>, query: &str, limit: i64) -> anyhow::Result<SearchResults> {
+    let shape = QueryShape::of(query);
+    let mut results = SearchResults {
+        shape,
+        transaction: None,
+        addresses: Vec::new(),
+        labels: Vec::new(),
+        memos: Vec::new(),
+    };
+
+    match shape {
+        QueryShape::Signature => {
+            results.transaction = db.get_by_signature(query).await?;
+        }
+        QueryShape::Address => {
+            // a valid address is found even without stored transactions
+            let address = search_addresses(db, query, 1)
+                .await?
+                .into_iter()
+                .find(|address| address.address == query)
+                .unwrap_or_else(|| AddressMatch {
+                    address: query.to_string(),
+                    transaction_count: 0,
+                    last_activity: None,
+                });
+
+            results.addresses.push(address);
+        }
+        QueryShape::AddressPrefix | QueryShape::Text => {
+            if shape == QueryShape::AddressPrefix {
+                results.addresses = search_addresses(db, query, limit).await?;
+            }
+
+            let pattern = like_pattern(query);
+            results.labels = search_labels(db, &pattern, query, limit).await?;
+            results.memos = search_memos(db, &pattern, limit).await?;
+        }
+    }
+
+    Ok(results)
+}
+
+/// A `LIKE` pattern matching text that contains `text`.
+fn like_pattern(text: &str) -> String {
+    let mut pattern = String::with_capacity(text.len() + 2);
+    pattern.push('%');
+
+    for c in text.chars() {
+        if matches!(c, '%' | '_' | '\\') {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+
+    pattern.push('%');
+    pattern
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_query_shapes() {
+        let signature = Signature::from([7; 64]).to_string();
+        let pubkey = Pubkey::new_unique().to_string();
+
+        assert_eq!(QueryShape::of(&signature), QueryShape::Signature);
+        assert_eq!(QueryShape::of(&pubkey), QueryShape::Address);
+        assert_eq!(QueryShape::of(&pubkey[..8]), QueryShape::AddressPrefix);
+        assert_eq!(QueryShape::of("Jupiter"), QueryShape::AddressPrefix);
+        // `l` and `0` aren't base58
+        assert_eq!(QueryShape::of("hello"), QueryShape::Text);
+        assert_eq!(QueryShape::of("order 100"), QueryShape::Text);
+        assert_eq!(QueryShape::of("abc"), QueryShape::Text);
+
+        assert_eq!(like_pattern("gm"), "%gm%");
+        assert_eq!(like_pattern("100%_off\\"), "%100\\%\\_off\\\\%");
+    }
+}
@@ -0,0 +1,341 @@
+// Publishes ingested transactions to message buses
+
+// Responsibilities:
+// * Fan every newly ingested transaction out to Kafka and/or NATS as JSON, so other services can
+//   consume them without polling the API.
+// * Optionally replace storage altogether, publishing monitored transactions without storing them.
+
+// Implementation:
+// * Each bus implements the `Sink` trait. Kafka (via rdkafka) and NATS (via async-nats) are behind
+//   the `kafka` and `nats` features, so builds that don't use them don't pull in their clients.
+// * When storing, only newly stored transactions are published, so each is published once. A
+//   failing sink is logged and doesn't hold up ingestion. A Kafka sink with `exactly_once` isn't
+//   one of them: `outbox` publishes what's stored instead.
+// * Without storage nothing dedupes polls, so the last `RECENT_CAPACITY` published signatures are
+//   remembered instead. A batch that fails to publish is retried on the next poll, so consumers
+//   may see a transaction more than once and should dedupe by signature.
+
+use crate::{
+    config::{KafkaSinkConfig, NatsSinkConfig, SinksConfig},
+    data_processing::TransactionData,
+};
+
+use async_trait::async_trait;
+use log::{error, info};
+
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::Mutex,
+};
+
+/// Published signatures remembered when transactions aren't stored.
+const RECENT_CAPACITY: usize = 10_000;
+
+#[async_trait]
+pub trait Sink: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    /// Publish each transaction as a JSON message, keyed by signature where the bus has keys.
+    async fn send(&self, txns: &[&TransactionData]) -> anyhow::Result<()>;
+}
+
+#[cfg(feature = "kafka")]
+pub struct KafkaSink {
+    producer: rdkafka::producer::FutureProducer,
+    topic: String,
+}
+
+#[cfg(feature = "kafka")]
+impl KafkaSink {
+    pub fn new(config: &KafkaSinkConfig) -> anyhow::Result<Self> {
+        let producer = rdkafka::ClientConfig::new()
+            .set("bootstrap.servers", &config.brokers)
+            .set("message.timeout.ms", "10000")
+            .create()?;
+
+        Ok(KafkaSink {
+            producer,
+            topic: config.topic.clone(),
+        })
+    }
+}
+
+#[cfg(feature = "kafka")]
+#[async_trait]
+impl Sink for KafkaSink {
+    fn name(&self) -> &'static str {
+        "Kafka"
+    }
+
+    async fn send(&self, txns: &[&TransactionData]) -> anyhow::Result<()> {
+        use rdkafka::{producer::FutureRecord, util::Timeout};
+
+        for txn in txns {
+            let payload = serde_json::to_vec(txn)?;
+            let record = FutureRecord::to(&self.topic)
+                .key(&txn.signature)
+                .payload(&payload);
+
+            self.producer
+                .send(record, Timeout::Never)
+                .await
+                .map_err(|(e, _)| e)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "nats")]
+pub struct NatsSink {
+    client: async_nats::Client,
+    subject: String,
+}
+
+#[cfg(feature = "nats")]
+impl NatsSink {
+    pub async fn connect(config: &NatsSinkConfig) -> anyhow::Result<Self> {
+        Ok(NatsSink {
+            client: async_nats::connect(&config.url).await?,
+            subject: config.subject.clone(),
+        })
+    }
+}
+
+#[cfg(feature = "nats")]
+#[async_trait]
+impl Sink for NatsSink {
+    fn name(&self) -> &'static str {
+        "NATS"
+    }
+
+    async fn send(&self, txns: &[&TransactionData]) -> anyhow::Result<()> {
+        for txn in txns {
+            self.client
+                .publish(self.subject.clone(), serde_json::to_vec(txn)?.into())
+                .await?;
+        }
+
+        // wait until the server has the batch, so failures are reported against it
+        self.client.flush().await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "kafka")]
+fn kafka_sink(config: &KafkaSinkConfig) -> anyhow::Result<Box<dyn Sink>> {
+    Ok(Box::new(KafkaSink::new(config)?))
+}
+
+#[cfg(not(feature = "kafka"))]
+fn kafka_sink(config: &KafkaSinkConfig) -> anyhow::Result<Box<dyn Sink>> {
+    anyhow::bail!(
+        "A Kafka sink to {} is configured, but this build lacks the `kafka` feature",
+        config.brokers
+    )
+}
+
+#[cfg(feature = "nats")]
+async fn nats_sink(config: &NatsSinkConfig) -> anyhow::Result<Box<dyn Sink>> {
+    Ok(Box::new(NatsSink::connect(config).await?))
+}
+
+#[cfg(not(feature = "nats"))]
+async fn nats_sink(config: &NatsSinkConfig) -> anyhow::Result<Box<dyn Sink>> {
+    anyhow::bail!(
+        "A NATS sink to {} is configured, but this build lacks the `nats` feature",
+        config.url
+    )
+}
+
+/// Signatures published most recently, oldest first.
+#[derive(Debug, Default)]
+struct RecentSignatures {
+    order: VecDeque<String>,
+    set: HashSet<String>,
+}
+
+impl RecentSignatures {
+    fn contains(&self, signature: &str) -> bool {
+        self.set.contains(signature)
+    }
+
+    fn insert(&mut self, signature: &str) {
+        if !self.set.insert(signature.to_string()) {
+            return;
+        }
+
+        self.order.push_back(signature.to_string());
+
+        if self.order.len() > RECENT_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.set.remove(&oldest);
+            }
+        }
+    }
+}
+
+pub struct Sinks {
+    sinks: Vec<Box<dyn Sink>>,
+    recent: Mutex<RecentSignatures>,
+}
+
+impl Sinks {
+    pub fn new(sinks: Vec<Box<dyn Sink>>) -> Self {
+        Sinks {
+            sinks,
+            recent: Mutex::new(RecentSignatures::default()),
+        }
+    }
+
+    /// Connect to the configured buses. `None` if there are none.
+    pub async fn from_config(config: &SinksConfig) -> anyhow::Result<Option<Self>> {
+        let mut sinks: Vec<Box<dyn Sink>> = Vec::new();
+
+        // exactly-once publishing goes through the outbox instead
+        if let Some(kafka) = config.kafka.as_ref().filter(|kafka| !kafka.exactly_once) {
+            sinks.push(kafka_sink(kafka)?);
+        }
+
+        if let Some(nats) = &config.nats {
+            sinks.push(nats_sink(nats).await?);
+        }
+
+        for sink in &sinks {
+            info!("Publishing transactions to {}", sink.name());
+        }
+
+        Ok((!sinks.is_empty()).then(|| Sinks::new(sinks)))
+    }
+
+    /// Publish newly stored transactions to every sink, logging the ones that fail.
+    pub async fn publish<'a>(&self, txns: impl IntoIterator<Item = &'a TransactionData>) {
+        let txns = txns.into_iter().collect::<Vec<_>>();
+
+        if txns.is_empty() {
+            return;
+        }
+
+        for sink in &self.sinks {
+            if let Err(e) = sink.send(&txns).await {
+                error!("Failed to publish transactions to {}: {e:?}", sink.name());
+            }
+        }
+    }
+
+    /// Publish the transactions that haven't been published yet, for when they aren't stored.
+    /// They are only remembered as published if every sink took them, so a failed batch is
+    /// published again on the next poll.
+    pub async fn publish_unseen(&self, txns: &[TransactionData]) -> anyhow::Result<()> {
+        let unseen = {
+            let recent = self.recent.lock().expect("recent signatures lock poisoned");
+
+            txns.iter()
+                .filter(|txn| !recent.contains(&txn.signature))
+                .collect::<Vec<_>>()
+        };
+
+        if unseen.is_empty() {
+            return Ok(());
+        }
+
+        let mut failed = 0;
+
+        for sink in &self.sinks {
+            if let Err(e) = sink.send(&unseen).await {
+                error!("Failed to publish transactions to {}: {e:?}", sink.name());
+                failed += 1;
+            }
+        }
+
+        if failed > 0 {
+            anyhow::bail!("{failed} of {} sinks failed", self.sinks.len());
+        }
+
+        let mut recent = self.recent.lock().expect("recent signatures lock poisoned");
+
+        for txn in unseen {
+            recent.insert(&txn.signature);
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    };
+
+    #[derive(Default)]
+    struct RecordingSink {
+        sent: Arc<Mutex<Vec<String>>>,
+        failing: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl Sink for RecordingSink {
+        fn name(&self) -> &'static str {
+            "recording"
+        }
+
+        async fn send(&self, txns: &[&TransactionData]) -> anyhow::Result<()> {
+            if self.failing.load(Ordering::SeqCst) {
+                anyhow::bail!("unavailable");
+            }
+
+            let mut sent = self.sent.lock().unwrap();
+            sent.extend(txns.iter().map(|txn| txn.signature.clone()));
+
+            Ok(())
+        }
+    }
+
+    fn txn(signature: &str) -> TransactionData {
+        TransactionData {
+            signature: signature.to_string(),
+            sender: "sender".to_string(),
+            receiver: "receiver".to_string(),
+            sol_amount: 1,
+            fee: 5000,
+            timestamp: 1_700_000_000,
+            ..TransactionData::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_publish_unseen_skips_published_and_retries_failed() {
+        let sink = RecordingSink::default();
+        let sent = Arc::clone(&sink.sent);
+        let failing = Arc::clone(&sink.failing);
+        let sinks = Sinks::new(vec![Box::new(sink)]);
+
+        sinks.publish_unseen(&[txn("a"), txn("b")]).await.unwrap();
+        sinks.publish_unseen(&[txn("b"), txn("c")]).await.unwrap();
+        assert_eq!(*sent.lock().unwrap(), ["a", "b", "c"]);
+
+        failing.store(true, Ordering::SeqCst);
+        assert!(sinks.publish_unseen(&[txn("d")]).await.is_err());
+
+        failing.store(false, Ordering::SeqCst);
+        sinks.publish_unseen(&[txn("c"), txn("d")]).await.unwrap();
+        assert_eq!(*sent.lock().unwrap(), ["a", "b", "c", "d"]);
+    }
+
+    #[test]
+    fn test_recent_signatures_are_bounded() {
+        let mut recent = RecentSignatures::default();
+
+        for i in 0..=RECENT_CAPACITY {
+            recent.insert(&i.to_string());
+        }
+
+        assert!(!recent.contains("0"));
+        assert!(recent.contains("1"));
+        assert_eq!(recent.set.len(), RECENT_CAPACITY);
+    }
+}
@@ -0,0 +1,283 @@
+// Tracks per-route API latency and error ratios against configured SLOs
+
+// Responsibilities:
+// * Record the latency and outcome of every API request, keyed by route pattern.
+// * Report latency percentiles and error ratios over a rolling window.
+// * Raise an operational alert when a route breaches its configured thresholds.
+
+// Implementation:
+// * Samples are kept per route in a bounded `VecDeque` and pruned to the window on access.
+// * Thresholds are evaluated periodically by a background task.
+
+use crate::{alerting::Alerter, config::SloConfig, schedules::Job};
+
+use serde::Serialize;
+use tokio::time::{Duration, Instant};
+use tokio_util::sync::CancellationToken;
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Mutex,
+};
+
+/// Upper bound on samples kept per route, so a traffic spike can't exhaust memory.
+const MAX_SAMPLES_PER_ROUTE: usize = 10_000;
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    at: Instant,
+    latency: Duration,
+    is_error: bool,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RouteReport {
+    pub route: String,
+    pub requests: usize,
+    pub error_ratio: f64,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub p99_ms: f64,
+    /// Threshold violations, empty if the route is within its SLO (or has none).
+    pub breaches: Vec<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SloReport {
+    pub window_secs: u64,
+    pub routes: Vec<RouteReport>,
+}
+
+#[derive(Debug)]
+pub struct SloTracker {
+    config: SloConfig,
+    samples: Mutex<HashMap<String, VecDeque<Sample>>>,
+    /// Routes currently in breach, so each breach is alerted once rather than on every check.
+    breached: Mutex<HashSet<String>>,
+}
+
+impl SloTracker {
+    pub fn new(config: SloConfig) -> Self {
+        SloTracker {
+            config,
+            samples: Mutex::new(HashMap::new()),
+            breached: Mutex::new(HashSet::new()),
+        }
+    }
+
+    fn window(&self) -> Duration {
+        Duration::from_secs(self.config.window_secs)
+    }
+
+    /// Record the outcome of a single request.
+    pub fn record(&self, route: &str, latency: Duration, is_error: bool) {
+        let mut samples = self.samples.lock().expect("SLO samples lock poisoned");
+        let route_samples = samples.entry(route.to_string()).or_default();
+
+        if route_samples.len() == MAX_SAMPLES_PER_ROUTE {
+            route_samples.pop_front();
+        }
+
+        route_samples.push_back(Sample {
+            at: Instant::now(),
+            latency,
+            is_error,
+        });
+    }
+
+    /// Summarize every route over the rolling window.
+    pub fn report(&self) -> SloReport {
+        let window = self.window();
+        let mut samples = self.samples.lock().expect("SLO samples lock poisoned");
+
+        let mut routes = samples
+            .iter_mut()
+            .filter_map(|(route, route_samples)| {
+                while route_samples
+                    .front()
+                    .is_some_and(|sample| sample.at.elapsed() > window)
+                {
+                    route_samples.pop_front();
+                }
+
+                if route_samples.is_empty() {
+                    return None;
+                }
+
+                Some(self.summarize(route, route_samples))
+            })
+            .collect::<Vec<_>>();
+
+        routes.sort_by(|a, b| a.route.cmp(&b.route));
+
+        SloReport {
+            window_secs: self.config.window_secs,
+            routes,
+        }
+    }
+
+    fn summarize(&self, route: &str, samples: &VecDeque<Sample>) -> RouteReport {
+        let mut latencies = samples
+            .iter()
+            .map(|sample| sample.latency.as_secs_f64() * 1000.0)
+            .collect::<Vec<_>>();
+        latencies.sort_by(|a, b| a.total_cmp(b));
+
+        let errors = samples.iter().filter(|sample| sample.is_error).count();
+        let error_ratio = errors as f64 / samples.len() as f64;
+
+        let mut report = RouteReport {
+            route: route.to_string(),
+            requests: samples.len(),
+            error_ratio,
+            p50_ms: percentile(&latencies, 50.0),
+            p95_ms: percentile(&latencies, 95.0),
+            p99_ms: percentile(&latencies, 99.0),
+            breaches: Vec::new(),
+        };
+
+        if let Some(threshold) = self.config.routes.iter().find(|t| t.route == route) {
+            if report.requests >= threshold.min_requests {
+                if let Some(p99_ms) = threshold.p99_ms {
+                    if report.p99_ms > p99_ms {
+                        report
+                            .breaches
+                            .push(format!("p99 {:.1}ms > {p99_ms}ms", report.p99_ms));
+                    }
+                }
+
+                if let Some(max_error_ratio) = threshold.max_error_ratio {
+                    if report.error_ratio > max_error_ratio {
+                        report.breaches.push(format!(
+                            "error ratio {:.4} > {max_error_ratio}",
+                            report.error_ratio
+                        ));
+                    }
+                }
+            }
+        }
+
+        report
+    }
+
+    /// Periodically evaluate the thresholds, alerting when a route enters breach.
+    pub async fn run_checks(&self, alerter: &Alerter, job: &Job, shutdown: &CancellationToken) {
+        while job.tick(shutdown).await {
+            let _ = job
+                .run(async {
+                    self.check(alerter).await;
+                    Ok(())
+                })
+                .await;
+        }
+    }
+
+    async fn check(&self, alerter: &Alerter) {
+        let report = self.report();
+
+        let newly_breached = {
+            let mut breached = self.breached.lock().expect("SLO breach lock poisoned");
+            let current = report
+                .routes
+                .iter()
+                .filter(|route| !route.breaches.is_empty())
+                .map(|route| route.route.clone())
+                .collect::<HashSet<_>>();
+
+            let newly_breached = report
+                .routes
+                .iter()
+                .filter(|route| current.contains(&route.route) && !breached.contains(&route.route))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            *breached = current;
+            newly_breached
+        };
+
+        for route in newly_breached {
+            alerter
+                .raise(
+                    &format!("SLO breached for {}", route.route),
+                    &route.breaches.join("; "),
+                )
+                .await;
+        }
+    }
+}
+
+/// Nearest-rank percentile of already sorted values.
+fn percentile(sorted: &[f64], pct: f64) -> f64 {
+    if sorted.is_empty() {
+        return 0.0;
+    }
+
+    let rank = ((pct / 100.0) * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::RouteSlo;
+
+    fn tracker() -> SloTracker {
+        SloTracker::new(SloConfig {
+            window_secs: 300,
+            check_interval_secs: 60,
+            routes: vec![RouteSlo {
+                route: "/transactions".to_string(),
+                p99_ms: Some(100.0),
+                max_error_ratio: Some(0.1),
+                min_requests: 10,
+            }],
+        })
+    }
+
+    #[test]
+    fn test_percentile() {
+        let values = (1..=100).map(f64::from).collect::<Vec<_>>();
+
+        assert_eq!(percentile(&values, 50.0), 50.0);
+        assert_eq!(percentile(&values, 99.0), 99.0);
+        assert_eq!(percentile(&[], 99.0), 0.0);
+    }
+
+    #[test]
+    fn test_report_within_slo() {
+        let tracker = tracker();
+
+        for _ in 0..20 {
+            tracker.record("/transactions", Duration::from_millis(10), false);
+        }
+
+        let report = tracker.report();
+
+        assert_eq!(report.routes.len(), 1);
+        assert_eq!(report.routes[0].requests, 20);
+        assert_eq!(report.routes[0].error_ratio, 0.0);
+        assert!(report.routes[0].breaches.is_empty());
+    }
+
+    #[test]
+    fn test_report_breaches() {
+        let tracker = tracker();
+
+        for i in 0..20 {
+            tracker.record("/transactions", Duration::from_millis(500), i % 2 == 0);
+        }
+
+        let report = tracker.report();
+
+        assert_eq!(report.routes[0].breaches.len(), 2);
+    }
+
+    #[test]
+    fn test_breach_requires_min_requests() {
+        let tracker = tracker();
+
+        tracker.record("/transactions", Duration::from_millis(500), true);
+
+        assert!(tracker.report().routes[0].breaches.is_empty());
+    }
+}
@@ -0,0 +1,124 @@
+// Serves aggregated transaction statistics with a short-lived cache
+
+// Responsibilities:
+// * Compute totals over stored transactions, overall and per account.
+// * Keep repeated dashboard refreshes from re-running the aggregation queries.
+
+// Implementation:
+// * Totals and counterparties are read from counters that every insert keeps up to date (see
+//   `data_storage::get_transaction_stats`), so a read costs the same however many transactions
+//   are stored.
+// * Results are cached per scope (overall, or one account) for `cache_ttl_secs`.
+// * Expired entries are dropped whenever a fresh result is stored, so the cache stays small.
+
+use crate::{
+    config::StatsConfig,
+    data_storage::{get_transaction_stats, TransactionStats},
+};
+
+use sqlx::PgPool;
+use tokio::time::{Duration, Instant};
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+#[derive(Debug)]
+pub struct StatsCache {
+    ttl: Duration,
+    top_counterparties: i64,
+    /// Keyed by account, `None` being the overall statistics.
+    entries: Mutex<HashMap<Option<String>, (Instant, TransactionStats)>>,
+}
+
+impl StatsCache {
+    pub fn new(config: &StatsConfig) -> Self {
+        StatsCache {
+            ttl: Duration::from_secs(config.cache_ttl_secs),
+            top_counterparties: config.top_counterparties,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Statistics over all stored transactions, or those of `pubkey` if given.
+    pub async fn get(
+        &self,
+        db: &Arc<PgPool>,
+        pubkey: Option<&str>,
+    ) -> anyhow::Result<TransactionStats> {
+        let key = pubkey.map(str::to_string);
+
+        if let Some(stats) = self.cached(&key) {
+            return Ok(stats);
+        }
+
+        let stats = get_transaction_stats(db, pubkey, self.top_counterparties).await?;
+        self.store(key, stats.clone());
+
+        Ok(stats)
+    }
+
+    fn cached(&self, key: &Option<String>) -> Option<TransactionStats> {
+        let entries = self.entries.lock().expect("stats cache lock poisoned");
+
+        entries
+            .get(key)
+            .filter(|(computed_at, _)| computed_at.elapsed() < self.ttl)
+            .map(|(_, stats)| stats.clone())
+    }
+
+    fn store(&self, key: Option<String>, stats: TransactionStats) {
+        let mut entries = self.entries.lock().expect("stats cache lock poisoned");
+
+        entries.retain(|_, (computed_at, _)| computed_at.elapsed() < self.ttl);
+        entries.insert(key, (Instant::now(), stats));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(transaction_count: i64) -> TransactionStats {
+        TransactionStats {
+            transaction_count,
+            total_sol_amount: 0,
+            total_fees: 0,
+            average_fee: 0.0,
+            total_usd_volume: 0.0,
+            first_activity: None,
+            last_activity: None,
+            top_counterparties: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_cache_is_scoped_and_expires() {
+        let cache = StatsCache::new(&StatsConfig {
+            cache_ttl_secs: 60,
+            top_counterparties: 10,
+        });
+
+        cache.store(None, stats(3));
+        cache.store(Some("account".to_string()), stats(1));
+
+        assert_eq!(cache.cached(&None).unwrap().transaction_count, 3);
+        assert_eq!(
+            cache
+                .cached(&Some("account".to_string()))
+                .unwrap()
+                .transaction_count,
+            1
+        );
+        assert!(cache.cached(&Some("other".to_string())).is_none());
+
+        let expired = StatsCache::new(&StatsConfig {
+            cache_ttl_secs: 0,
+            top_counterparties: 10,
+        });
+        expired.store(None, stats(3));
+
+        assert!(expired.cached(&None).is_none());
+    }
+}
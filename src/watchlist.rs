@@ -0,0 +1,199 @@
+// Keeps the set of monitored addresses and programs, discovering the addresses of owned wallets
+
+// Responsibilities:
+// * Hold every address the monitor polls and the balance tracker snapshots: the configured ones,
+//   those imported through the API, plus, in personal mode, the wallets of the configured owners
+//   and their token and stake accounts.
+// * Hold the program ids whose transactions the monitor polls as well. Programs have no balance
+//   worth tracking, so they're kept apart from the addresses.
+// * Keep the discovered accounts in sync as the owners open and close them.
+
+// Implementation:
+// * Owners are given by public key only; no keypair or seed is ever needed. Solana derives wallets
+//   with hardened-only ed25519 paths, so there is no xpub to derive further addresses from, and
+//   each owned wallet is listed instead.
+// * Discovery asks the RPC node for each owner's SPL Token and Token-2022 accounts, which include
+//   its associated token accounts, and for the stake accounts it is the staker or withdrawer of.
+//   It runs once at startup and then every `discovery_interval_secs`.
+// * Each run replaces the discovered set, so closed accounts stop being polled. A failed run
+//   leaves it as it was.
+// * Imported addresses are only ever added; they're persisted by the caller and loaded back at
+//   startup.
+// * Readers take a snapshot of the addresses, so discovery never waits on a poll in progress.
+
+use crate::{config::PersonalConfig, data_retrieval::SolanaClient, schedules::Job};
+
+use log::info;
+use solana_sdk::pubkey::Pubkey;
+use tokio_util::sync::CancellationToken;
+
+use std::{
+    collections::{BTreeSet, HashSet},
+    sync::RwLock,
+};
+
+#[derive(Debug)]
+pub struct Watchlist {
+    configured: Vec<Pubkey>,
+    programs: Vec<Pubkey>,
+    owners: Vec<Pubkey>,
+    stake_accounts: bool,
+    imported: RwLock<BTreeSet<Pubkey>>,
+    discovered: RwLock<BTreeSet<Pubkey>>,
+}
+
+impl Watchlist {
+    pub fn new(
+        configured: Vec<Pubkey>,
+        programs: Vec<Pubkey>,
+        personal: &PersonalConfig,
+    ) -> anyhow::Result<Self> {
+        Ok(Watchlist {
+            configured,
+            programs,
+            owners: personal.owners()?,
+            stake_accounts: personal.stake_accounts,
+            imported: RwLock::new(BTreeSet::new()),
+            discovered: RwLock::new(BTreeSet::new()),
+        })
+    }
+
+    /// Whether addresses are discovered for owners, i.e. personal mode is on.
+    pub fn discovers(&self) -> bool {
+        !self.owners.is_empty()
+    }
+
+    /// Every watched address: the configured ones, the imported ones, the owners, then the
+    /// discovered accounts.
+    pub fn addresses(&self) -> Vec<Pubkey> {
+        let imported = self.imported.read().expect("watchlist lock poisoned");
+        let discovered = self.discovered.read().expect("watchlist lock poisoned");
+        let mut seen = HashSet::new();
+
+        self.configured
+            .iter()
+            .chain(imported.iter())
+            .chain(&self.owners)
+            .chain(discovered.iter())
+            .filter(|address| seen.insert(**address))
+            .copied()
+            .collect()
+    }
+
+    /// Watch more addresses, returning those that weren't watched yet.
+    pub fn import(&self, addresses: &[Pubkey]) -> Vec<Pubkey> {
+        let watched = self.addresses().into_iter().collect::<HashSet<_>>();
+        let mut imported = self.imported.write().expect("watchlist lock poisoned");
+
+        addresses
+            .iter()
+            .filter(|address| !watched.contains(address) && imported.insert(**address))
+            .copied()
+            .collect()
+    }
+
+    /// Every watched program id.
+    pub fn programs(&self) -> &[Pubkey] {
+        &self.programs
+    }
+
+    /// Everything the monitor polls: the addresses, then the programs that aren't also addresses.
+    pub fn polled(&self) -> Vec<Pubkey> {
+        let mut polled = self.addresses();
+        let programs = self
+            .programs
+            .iter()
+            .filter(|program| !polled.contains(program))
+            .copied()
+            .collect::<Vec<_>>();
+
+        polled.extend(programs);
+        polled
+    }
+
+    /// Periodically rediscover the owners' accounts until `shutdown` is cancelled.
+    pub async fn run(&self, solana_client: &SolanaClient, job: &Job, shutdown: &CancellationToken) {
+        while job.tick(shutdown).await {
+            let _ = job.run(self.discover(solana_client)).await;
+        }
+    }
+
+    /// Look up the owners' token and stake accounts, replacing the discovered set.
+    pub async fn discover(&self, solana_client: &SolanaClient) -> anyhow::Result<()> {
+        let mut found = BTreeSet::new();
+
+        for owner in &self.owners {
+            found.extend(solana_client.fetch_token_accounts(owner).await?);
+
+            if self.stake_accounts {
+                found.extend(solana_client.fetch_stake_accounts(owner).await?);
+            }
+        }
+
+        let (added, removed) = self.replace_discovered(found);
+
+        if added > 0 || removed > 0 {
+            info!(
+                "Discovered {added} new and {removed} closed accounts of {} owners",
+                self.owners.len()
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Replace the discovered accounts, returning how many were added and removed.
+    fn replace_discovered(&self, found: BTreeSet<Pubkey>) -> (usize, usize) {
+        let mut discovered = self.discovered.write().expect("watchlist lock poisoned");
+
+        let added = found.difference(&discovered).count();
+        let removed = discovered.difference(&found).count();
+        *discovered = found;
+
+        (added, removed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_addresses_combine_without_duplicates() {
+        let configured = Pubkey::new_unique();
+        let owner = Pubkey::new_unique();
+        let token_account = Pubkey::new_unique();
+        let stake_account = Pubkey::new_unique();
+
+        let program = Pubkey::new_unique();
+
+        let watchlist = Watchlist {
+            configured: vec![configured, owner],
+            programs: vec![program, configured],
+            owners: vec![owner],
+            stake_accounts: true,
+            imported: RwLock::new(BTreeSet::new()),
+            discovered: RwLock::new(BTreeSet::new()),
+        };
+        assert_eq!(watchlist.addresses(), [configured, owner]);
+        assert_eq!(watchlist.polled(), [configured, owner, program]);
+
+        let found = BTreeSet::from([token_account, stake_account]);
+        assert_eq!(watchlist.replace_discovered(found), (2, 0));
+        assert_eq!(watchlist.addresses().len(), 4);
+
+        // the token account was closed
+        let found = BTreeSet::from([stake_account]);
+        assert_eq!(watchlist.replace_discovered(found), (0, 1));
+        assert!(!watchlist.addresses().contains(&token_account));
+
+        // only addresses that aren't watched yet are imported, once
+        let imported = Pubkey::new_unique();
+        assert_eq!(
+            watchlist.import(&[configured, imported, stake_account, imported]),
+            [imported]
+        );
+        assert!(watchlist.import(&[imported]).is_empty());
+        assert_eq!(watchlist.addresses()[1], imported);
+    }
+}